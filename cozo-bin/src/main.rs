@@ -14,10 +14,12 @@ use clap::{Parser, Subcommand};
 use env_logger::Env;
 
 use crate::repl::{repl_main, ReplArgs};
+use crate::run::{run_main, RunArgs};
 use crate::server::{server_main, ServerArgs};
 
 mod client;
 mod repl;
+mod run;
 mod server;
 
 #[derive(Parser)]
@@ -32,6 +34,7 @@ struct AppArgs {
 enum Commands {
     Server(ServerArgs),
     Repl(ReplArgs),
+    Run(RunArgs),
 }
 
 fn main() {
@@ -50,6 +53,12 @@ fn main() {
                 exit(-1);
             }
         }
+        Commands::Run(args) => {
+            if let Err(e) = run_main(args) {
+                eprintln!("{e:?}");
+                exit(1);
+            }
+        }
     };
 
     // if args.repl {
@@ -12,40 +12,179 @@ use std::collections::BTreeMap;
 use std::error::Error;
 use std::fs;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{IsTerminal, Read, Write};
+use std::process::{Command, Stdio};
+use std::time::Instant;
+use std::{env, io};
 
 use clap::Args;
 use miette::{bail, miette, IntoDiagnostic};
 use rustyline::history::DefaultHistory;
-use rustyline::Changeset;
 use serde_json::{json, Value};
 
-use cozo::{evaluate_expressions, DataValue, DbInstance, NamedRows, ScriptMutability};
+use cozo::{
+    evaluate_expressions, format_script, DataValue, DbInstance, NamedRows, ScriptMutability,
+};
 
-struct Indented;
+const DEFAULT_TERMINAL_HEIGHT: usize = 40;
 
-impl rustyline::hint::Hinter for Indented {
+const META_OPS: &[&str] = &[
+    "%backup", "%clear", "%eval", "%format", "%import", "%pager", "%params", "%restore", "%run",
+    "%save", "%set", "%timing", "%unset",
+];
+
+struct ReplHelper {
+    db: DbInstance,
+}
+
+impl ReplHelper {
+    fn relation_names(&self) -> Vec<String> {
+        self.column_of("::relations", "name")
+    }
+
+    fn column_names(&self, relation: &str) -> Vec<String> {
+        self.column_of(&format!("::columns {relation}"), "column")
+    }
+
+    fn column_of(&self, script: &str, header: &str) -> Vec<String> {
+        let Ok(res) = self
+            .db
+            .run_script(script, Default::default(), ScriptMutability::Immutable)
+        else {
+            return vec![];
+        };
+        let Some(idx) = res.headers.iter().position(|h| h == header) else {
+            return vec![];
+        };
+        res.rows
+            .into_iter()
+            .filter_map(|mut row| match row.swap_remove(idx) {
+                DataValue::Str(s) => Some(s.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == ':' || c == '.'
+}
+
+/// Returns the relation whose braces or brackets enclose the end of `prefix`, as in `*rel{a, b`.
+fn enclosing_relation(prefix: &str) -> Option<&str> {
+    let mut depth = 0;
+    for (i, c) in prefix.char_indices().rev() {
+        match c {
+            '}' | ']' | ')' => depth += 1,
+            '(' if depth == 0 => return None,
+            '{' | '[' | '(' if depth > 0 => depth -= 1,
+            '{' | '[' => {
+                let before = prefix[..i].trim_end();
+                let start = before
+                    .rfind(|c: char| !is_ident_char(c))
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                let name = &before[start..];
+                return if name.is_empty() { None } else { Some(name) };
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Checks whether all brackets in a script are closed, skipping strings and comments.
+fn brackets_balanced(input: &str) -> bool {
+    let mut depth = 0;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                let mut closed = false;
+                while let Some(n) = chars.next() {
+                    if n == '\\' {
+                        chars.next();
+                    } else if n == c {
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return false;
+                }
+            }
+            '#' => {
+                for n in chars.by_ref() {
+                    if n == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut closed = false;
+                while let Some(n) = chars.next() {
+                    if n == '*' && chars.peek() == Some(&'/') {
+                        chars.next();
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return false;
+                }
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth <= 0
+}
+
+impl rustyline::hint::Hinter for ReplHelper {
     type Hint = String;
 }
 
-impl rustyline::highlight::Highlighter for Indented {}
-impl rustyline::completion::Completer for Indented {
+impl rustyline::highlight::Highlighter for ReplHelper {}
+
+impl rustyline::completion::Completer for ReplHelper {
     type Candidate = String;
 
-    fn update(
+    fn complete(
         &self,
-        _line: &mut rustyline::line_buffer::LineBuffer,
-        _start: usize,
-        _elected: &str,
-        _cl: &mut Changeset,
-    ) {
-        unreachable!();
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix = &line[..pos];
+        let start = prefix
+            .rfind(|c: char| !is_ident_char(c))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let word = &prefix[start..];
+
+        if start == 1 && prefix.starts_with('%') {
+            let candidates = META_OPS
+                .iter()
+                .filter(|op| op[1..].starts_with(word))
+                .map(|op| op[1..].to_string())
+                .collect();
+            return Ok((start, candidates));
+        }
+
+        let mut candidates = match enclosing_relation(prefix) {
+            Some(rel) => self.column_names(rel),
+            None => self.relation_names(),
+        };
+        candidates.retain(|c| c.starts_with(word));
+        Ok((start, candidates))
     }
 }
 
-impl rustyline::Helper for Indented {}
+impl rustyline::Helper for ReplHelper {}
 
-impl rustyline::validate::Validator for Indented {
+impl rustyline::validate::Validator for ReplHelper {
     fn validate(
         &self,
         ctx: &mut rustyline::validate::ValidationContext<'_>,
@@ -56,12 +195,19 @@ impl rustyline::validate::Validator for Indented {
             } else {
                 rustyline::validate::ValidationResult::Incomplete
             }
+        } else if !brackets_balanced(ctx.input()) {
+            rustyline::validate::ValidationResult::Incomplete
         } else {
             rustyline::validate::ValidationResult::Valid(None)
         })
     }
 }
 
+struct ReplOptions {
+    timing: bool,
+    pager: bool,
+}
+
 #[derive(Args, Debug)]
 pub(crate) struct ReplArgs {
     /// Database engine, can be `mem`, `sqlite`, `rocksdb` and others.
@@ -101,12 +247,19 @@ pub(crate) fn repl_main(args: ReplArgs) -> Result<(), Box<dyn Error>> {
 
     println!("Welcome to the Cozo REPL.");
     println!("Type a space followed by newline to enter multiline mode.");
+    println!(
+        "Unclosed brackets continue on the next line, TAB completes relation and column names."
+    );
 
     let mut exit = false;
-    let mut rl = rustyline::Editor::<Indented, DefaultHistory>::new()?;
+    let mut rl = rustyline::Editor::<ReplHelper, DefaultHistory>::new()?;
     let mut params = BTreeMap::new();
     let mut save_next: Option<String> = None;
-    rl.set_helper(Some(Indented));
+    let mut opts = ReplOptions {
+        timing: false,
+        pager: true,
+    };
+    rl.set_helper(Some(ReplHelper { db: db.clone() }));
 
     let history_file = ".cozo_repl_history";
     if rl.load_history(history_file).is_ok() {
@@ -117,7 +270,7 @@ pub(crate) fn repl_main(args: ReplArgs) -> Result<(), Box<dyn Error>> {
         let readline = rl.readline("=> ");
         match readline {
             Ok(line) => {
-                if let Err(err) = process_line(&line, &db, &mut params, &mut save_next) {
+                if let Err(err) = process_line(&line, &db, &mut params, &mut save_next, &mut opts) {
                     eprintln!("{err:?}");
                 }
                 if let Err(err) = rl.add_history_entry(line) {
@@ -149,11 +302,14 @@ fn process_line(
     db: &DbInstance,
     params: &mut BTreeMap<String, DataValue>,
     save_next: &mut Option<String>,
+    opts: &mut ReplOptions,
 ) -> miette::Result<()> {
     let line = line.trim();
     if line.is_empty() {
         return Ok(());
     }
+    let started = Instant::now();
+    let pager = opts.pager;
 
     let mut process_out = |out: NamedRows| -> miette::Result<()> {
         if let Some(path) = save_next.as_ref() {
@@ -201,7 +357,10 @@ fn process_line(
                 table.add_row(prettytable::Row::new(row));
             }
             table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-            table.printstd();
+            let rendered = table.to_string();
+            if !(pager && page_output(&rendered)) {
+                print!("{rendered}");
+            }
         }
         Ok(())
     };
@@ -234,6 +393,14 @@ fn process_line(
             "clear" => {
                 params.clear();
             }
+            "timing" => {
+                opts.timing = parse_toggle(payload, opts.timing)?;
+                println!("Timing is {}", if opts.timing { "on" } else { "off" });
+            }
+            "pager" => {
+                opts.pager = parse_toggle(payload, opts.pager)?;
+                println!("Pager is {}", if opts.pager { "on" } else { "off" });
+            }
             "params" => {
                 let display = serde_json::to_string_pretty(&json!(&params)).into_diagnostic()?;
                 println!("{display}");
@@ -255,6 +422,14 @@ fn process_line(
                 let out = db.run_script(&content, params.clone(), ScriptMutability::Mutable)?;
                 process_out(out)?;
             }
+            "format" => {
+                let path = payload.trim();
+                if path.is_empty() {
+                    bail!("Format requires path to a script");
+                }
+                let content = fs::read_to_string(path).into_diagnostic()?;
+                print!("{}", format_script(&content)?);
+            }
             "restore" => {
                 let path = payload.trim();
                 if path.is_empty() {
@@ -297,5 +472,50 @@ fn process_line(
         let out = db.run_script(line, params.clone(), ScriptMutability::Mutable)?;
         process_out(out)?;
     }
+    if opts.timing {
+        println!("Took {:.3}s", started.elapsed().as_secs_f64());
+    }
     Ok(())
 }
+
+fn parse_toggle(payload: &str, current: bool) -> miette::Result<bool> {
+    match payload.trim() {
+        "" => Ok(!current),
+        "on" => Ok(true),
+        "off" => Ok(false),
+        v => bail!("Expected 'on' or 'off', got '{}'", v),
+    }
+}
+
+/// Sends output taller than the terminal through `$PAGER` (`less` by default).
+/// Returns `false` if the output should be printed directly instead.
+fn page_output(rendered: &str) -> bool {
+    if !io::stdout().is_terminal() {
+        return false;
+    }
+    let height = env::var("LINES")
+        .ok()
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_TERMINAL_HEIGHT);
+    if rendered.lines().count() < height {
+        return false;
+    }
+    let pager = env::var("PAGER").unwrap_or_else(|_| "less -FRX".to_string());
+    let mut parts = pager.split_whitespace();
+    let Some(program) = parts.next() else {
+        return false;
+    };
+    let Ok(mut child) = Command::new(program)
+        .args(parts)
+        .stdin(Stdio::piped())
+        .spawn()
+    else {
+        return false;
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user may quit the pager before reading everything
+        let _ = stdin.write_all(rendered.as_bytes());
+    }
+    let _ = child.wait();
+    true
+}
@@ -0,0 +1,108 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+
+use clap::{Args, ValueEnum};
+use miette::{miette, IntoDiagnostic, Result};
+use serde_json::Value;
+
+use cozo::{DataValue, DbInstance, NamedRows, ScriptMutability};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// One JSON object with `headers` and `rows` per script, each on its own line
+    Json,
+    /// A header line followed by the rows, with an empty line between scripts
+    Csv,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RunArgs {
+    /// Script files to run in order, `-` reads from the standard input
+    #[clap(short, long, required = true, num_args = 1..)]
+    file: Vec<String>,
+
+    /// Database engine, can be `mem`, `sqlite`, `rocksdb` and others.
+    #[clap(short, long, default_value_t = String::from("mem"))]
+    engine: String,
+
+    /// Path to the directory to store the database
+    #[clap(short, long, visible_alias = "db", default_value_t = String::from("cozo.db"))]
+    path: String,
+
+    /// Extra config in JSON format
+    #[clap(short, long, default_value_t = String::from("{}"))]
+    config: String,
+
+    /// Parameters for the scripts, as a JSON object
+    #[clap(long, default_value_t = String::from("{}"))]
+    params: String,
+
+    /// Output format of the results
+    #[clap(long, value_enum, default_value_t = OutputFormat::Json)]
+    format: OutputFormat,
+
+    /// Reject scripts that attempt to modify the database
+    #[clap(long)]
+    read_only: bool,
+}
+
+/// Runs every script, stopping at the first failure.
+pub(crate) fn run_main(args: RunArgs) -> Result<()> {
+    let params: BTreeMap<String, Value> = serde_json::from_str(&args.params)
+        .map_err(|e| miette!("params must be a JSON object: {}", e))?;
+    let params: BTreeMap<String, DataValue> = params
+        .into_iter()
+        .map(|(k, v)| (k, DataValue::from(v)))
+        .collect();
+    let db = DbInstance::new(&args.engine, &args.path, &args.config)?;
+    let mutability = if args.read_only {
+        ScriptMutability::Immutable
+    } else {
+        ScriptMutability::Mutable
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (i, file) in args.file.iter().enumerate() {
+        let script = if file == "-" {
+            let mut script = String::new();
+            io::stdin().read_to_string(&mut script).into_diagnostic()?;
+            script
+        } else {
+            fs::read_to_string(file).map_err(|e| miette!("cannot read '{}': {}", file, e))?
+        };
+        let res = db.run_script(&script, params.clone(), mutability)?;
+        match args.format {
+            OutputFormat::Json => writeln!(out, "{}", res.into_json()).into_diagnostic()?,
+            OutputFormat::Csv => {
+                if i > 0 {
+                    writeln!(out).into_diagnostic()?;
+                }
+                write_csv(&mut out, res)?;
+            }
+        }
+    }
+    out.flush().into_diagnostic()
+}
+
+fn write_csv(out: &mut impl Write, res: NamedRows) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(&res.headers).into_diagnostic()?;
+    for row in res.rows {
+        let record = row.into_iter().map(|v| match v {
+            DataValue::Str(s) => s.to_string(),
+            v => Value::from(v).to_string(),
+        });
+        wtr.write_record(record).into_diagnostic()?;
+    }
+    wtr.flush().into_diagnostic()
+}
@@ -12,9 +12,10 @@ use std::net::{Ipv6Addr, SocketAddr};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use axum::body::Body;
-use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::extract::{DefaultBodyLimit, FromRequest, Path, Query, State};
 use axum::http::{header, HeaderName, Method, Request, Response, StatusCode};
 use axum::response::sse::{Event, KeepAlive};
 use axum::response::{Html, Sse};
@@ -22,7 +23,7 @@ use axum::routing::{get, post, put};
 use axum::{Extension, Json, Router};
 use clap::Args;
 use futures::future::BoxFuture;
-use futures::stream::Stream;
+use futures::stream::{Stream, StreamExt};
 use itertools::Itertools;
 use log::{error, info, warn};
 use miette::miette;
@@ -228,6 +229,8 @@ pub(crate) async fn server_main(args: ServerArgs) {
         token_table: args.token_table.map(|t| Arc::new((t, db.clone()))),
     };
 
+    tokio::spawn(run_schedules(db.clone()));
+
     let state = DbState {
         db,
         rule_senders: Default::default(),
@@ -254,6 +257,7 @@ pub(crate) async fn server_main(args: ServerArgs) {
         ) // +keep alive
         .route("/transact", post(start_transact))
         .route("/transact/:id", post(transact_query).put(finish_query))
+        .route("/metrics", get(metrics))
         .with_state(state)
         .layer(AsyncRequireAuthorizationLayer::new(auth_obj))
         .fallback(not_found)
@@ -282,6 +286,31 @@ pub(crate) async fn server_main(args: ServerArgs) {
     axum::serve(listener, app.into_make_service()).await.unwrap();
 }
 
+fn seconds_since_the_epoch() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+/// Runs the scripts added with `::schedule add` when they are due, checking
+/// shortly after the start of every minute.
+async fn run_schedules(db: DbInstance) {
+    let mut last = seconds_since_the_epoch();
+    loop {
+        let wait = 60. - last % 60. + 0.1;
+        tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+        let now = seconds_since_the_epoch();
+        let db = db.clone();
+        match spawn_blocking(move || db.run_scheduled_scripts(last, now)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => error!("failed to run scheduled scripts: {err:?}"),
+            Err(err) => error!("failed to run scheduled scripts: {err}"),
+        }
+        last = now;
+    }
+}
+
 #[derive(serde_derive::Deserialize)]
 struct StartTransactPayload {
     write: bool,
@@ -425,8 +454,26 @@ async fn export_relations(
 
 async fn import_relations(
     State(st): State<DbState>,
-    Json(payload): Json<serde_json::Value>,
+    request: Request<Body>,
 ) -> (StatusCode, Json<serde_json::Value>) {
+    let is_ndjson = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(|ct| ct.starts_with("application/x-ndjson"))
+        .unwrap_or(false);
+    if is_ndjson {
+        return import_ndjson(st.db, request.into_body()).await;
+    }
+    let payload = match Json::<serde_json::Value>::from_request(request, &st).await {
+        Ok(Json(payload)) => payload,
+        Err(rejection) => {
+            return (
+                rejection.status(),
+                json!({"ok": false, "message": rejection.body_text()}).into(),
+            );
+        }
+    };
     let payload = match payload.as_object() {
         None => {
             return (
@@ -463,6 +510,147 @@ async fn import_relations(
     }
 }
 
+/// Number of rows buffered before they are written during a streaming import.
+const IMPORT_BATCH_SIZE: usize = 10000;
+
+enum ImportLine {
+    Blank,
+    Header(String, Vec<String>),
+    Row(Vec<DataValue>),
+}
+
+fn parse_import_line(line: &[u8]) -> Result<ImportLine, String> {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return Ok(ImportLine::Blank);
+    }
+    match serde_json::from_slice(line).map_err(|err| err.to_string())? {
+        serde_json::Value::Array(row) => Ok(ImportLine::Row(
+            row.into_iter().map(DataValue::from).collect(),
+        )),
+        serde_json::Value::Object(obj) => {
+            let relation = obj.get("relation").and_then(|r| r.as_str());
+            let headers = obj.get("headers").and_then(|h| h.as_array()).and_then(|h| {
+                h.iter()
+                    .map(|c| c.as_str().map(|c| c.to_string()))
+                    .collect::<Option<Vec<_>>>()
+            });
+            match (relation, headers) {
+                (Some(relation), Some(headers)) => {
+                    Ok(ImportLine::Header(relation.to_string(), headers))
+                }
+                _ => Err(
+                    "a header must have a 'relation' string and a 'headers' array of strings"
+                        .to_string(),
+                ),
+            }
+        }
+        _ => Err("expected a relation header or a row".to_string()),
+    }
+}
+
+/// State of an NDJSON import: each line is either a relation header such as
+/// `{"relation": "friends", "headers": ["fr", "to"]}`, or a row `["alice", "bob"]`
+/// belonging to the last header. Rows are written in batches, each in its own transaction.
+#[derive(Default)]
+struct StreamingImport {
+    current: Option<(String, Vec<String>)>,
+    rows: Vec<Vec<DataValue>>,
+    line_no: usize,
+    imported: usize,
+}
+
+impl StreamingImport {
+    async fn feed(&mut self, db: &DbInstance, line: &[u8]) -> Result<(), (StatusCode, String)> {
+        self.line_no += 1;
+        let parsed = parse_import_line(line).map_err(|msg| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("line {}: {}", self.line_no, msg),
+            )
+        })?;
+        match parsed {
+            ImportLine::Blank => {}
+            ImportLine::Header(relation, headers) => {
+                self.flush(db).await?;
+                self.current = Some((relation, headers));
+            }
+            ImportLine::Row(row) => {
+                if self.current.is_none() {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        format!(
+                            "line {}: row encountered before any relation header",
+                            self.line_no
+                        ),
+                    ));
+                }
+                self.rows.push(row);
+                if self.rows.len() >= IMPORT_BATCH_SIZE {
+                    self.flush(db).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self, db: &DbInstance) -> Result<(), (StatusCode, String)> {
+        let Some((relation, headers)) = &self.current else {
+            return Ok(());
+        };
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.rows);
+        let n = rows.len();
+        let data = BTreeMap::from([(relation.clone(), NamedRows::new(headers.clone(), rows))]);
+        let db = db.clone();
+        match spawn_blocking(move || db.import_relations(data)).await {
+            Ok(Ok(())) => {
+                self.imported += n;
+                Ok(())
+            }
+            Ok(Err(err)) => Err((
+                StatusCode::BAD_REQUEST,
+                format!("importing into '{}' failed: {}", relation, err),
+            )),
+            Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+        }
+    }
+}
+
+async fn import_ndjson(db: DbInstance, body: Body) -> (StatusCode, Json<serde_json::Value>) {
+    let mut state = StreamingImport::default();
+    let result = async {
+        let mut stream = body.into_data_stream();
+        let mut buf: Vec<u8> = vec![];
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+            buf.extend_from_slice(&chunk);
+            let mut start = 0;
+            while let Some(pos) = buf[start..].iter().position(|b| *b == b'\n') {
+                state.feed(&db, &buf[start..start + pos]).await?;
+                start += pos + 1;
+            }
+            buf.drain(..start);
+        }
+        if !buf.is_empty() {
+            state.feed(&db, &buf).await?;
+        }
+        state.flush(&db).await
+    }
+    .await;
+    match result {
+        Ok(()) => (
+            StatusCode::OK,
+            json!({"ok": true, "imported": state.imported}).into(),
+        ),
+        Err((code, message)) => (
+            code,
+            json!({"ok": false, "message": message, "imported": state.imported}).into(),
+        ),
+    }
+}
+
 #[derive(serde_derive::Deserialize)]
 struct BackupPayload {
     path: String,
@@ -661,6 +849,14 @@ async fn observe_changes(
     Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
+async fn metrics(State(st): State<DbState>) -> (StatusCode, [(HeaderName, &'static str); 1], String) {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        st.db.metrics_text(),
+    )
+}
+
 async fn root() -> Html<&'static str> {
     Html(include_str!("./index.html"))
 }
@@ -6,17 +6,22 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
 use std::fmt::{Debug, Formatter};
 
+use lazy_static::lazy_static;
 use miette::{bail, ensure, miette, Result};
 use rand::prelude::*;
 
+use crate::data::functions::{val2str, with_rng};
 use crate::data::value::DataValue;
 
 pub struct Aggregation {
     pub name: &'static str,
     pub is_meet: bool,
+    /// Whether the aggregation always gives the same result for the same values
+    pub is_deterministic: bool,
     pub meet_op: Option<Box<dyn MeetAggrObj>>,
     pub normal_op: Option<Box<dyn NormalAggrObj>>,
 }
@@ -26,6 +31,7 @@ impl Clone for Aggregation {
         Self {
             name: self.name,
             is_meet: self.is_meet,
+            is_deterministic: self.is_deterministic,
             meet_op: None,
             normal_op: None,
         }
@@ -56,9 +62,17 @@ impl Debug for Aggregation {
 
 macro_rules! define_aggr {
     ($name:ident, $is_meet:expr) => {
+        define_aggr!(@ $name, $is_meet, true);
+    };
+    // aggregations that may give different results for the same values
+    ($name:ident, $is_meet:expr, impure) => {
+        define_aggr!(@ $name, $is_meet, false);
+    };
+    (@ $name:ident, $is_meet:expr, $is_deterministic:expr) => {
         const $name: Aggregation = Aggregation {
             name: stringify!($name),
             is_meet: $is_meet,
+            is_deterministic: $is_deterministic,
             meet_op: None,
             normal_op: None,
         };
@@ -385,7 +399,7 @@ impl NormalAggrObj for AggrCollect {
     }
 }
 
-define_aggr!(AGGR_CHOICE_RAND, false);
+define_aggr!(AGGR_CHOICE_RAND, false, impure);
 
 pub(crate) struct AggrChoiceRand {
     count: usize,
@@ -405,7 +419,7 @@ impl NormalAggrObj for AggrChoiceRand {
     fn set(&mut self, value: &DataValue) -> Result<()> {
         self.count += 1;
         let prob = 1. / (self.count as f64);
-        let rd = thread_rng().gen::<f64>();
+        let rd = with_rng(|rng| rng.gen::<f64>());
         if rd < prob {
             self.value = value.clone();
         }
@@ -435,34 +449,45 @@ impl NormalAggrObj for AggrCount {
     }
 }
 
+/// Welford's streaming mean and sum of squared deviations from the mean,
+/// which unlike a sum of squares does not lose precision when the mean is large.
+#[derive(Default)]
+struct Welford {
+    count: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Welford {
+    fn add(&mut self, x: f64) {
+        self.count += 1.;
+        let delta = x - self.mean;
+        self.mean += delta / self.count;
+        self.m2 += delta * (x - self.mean);
+    }
+    fn sample_variance(&self) -> f64 {
+        self.m2 / (self.count - 1.)
+    }
+}
+
 define_aggr!(AGGR_VARIANCE, false);
 
 #[derive(Default)]
 pub(crate) struct AggrVariance {
-    count: i64,
-    sum: f64,
-    sum_sq: f64,
+    moments: Welford,
 }
 
 impl NormalAggrObj for AggrVariance {
     fn set(&mut self, value: &DataValue) -> Result<()> {
         match value {
-            DataValue::Num(n) => {
-                let f = n.get_float();
-                self.sum += f;
-                self.sum_sq += f * f;
-                self.count += 1;
-            }
+            DataValue::Num(n) => self.moments.add(n.get_float()),
             v => bail!("cannot compute 'variance': encountered value {:?}", v),
         }
         Ok(())
     }
 
     fn get(&self) -> Result<DataValue> {
-        let ct = self.count as f64;
-        Ok(DataValue::from(
-            (self.sum_sq - self.sum * self.sum / ct) / (ct - 1.),
-        ))
+        Ok(DataValue::from(self.moments.sample_variance()))
     }
 }
 
@@ -470,29 +495,140 @@ define_aggr!(AGGR_STD_DEV, false);
 
 #[derive(Default)]
 pub(crate) struct AggrStdDev {
-    count: i64,
-    sum: f64,
-    sum_sq: f64,
+    moments: Welford,
 }
 
 impl NormalAggrObj for AggrStdDev {
     fn set(&mut self, value: &DataValue) -> Result<()> {
         match value {
-            DataValue::Num(n) => {
-                let f = n.get_float();
-                self.sum += f;
-                self.sum_sq += f * f;
-                self.count += 1;
-            }
+            DataValue::Num(n) => self.moments.add(n.get_float()),
             v => bail!("cannot compute 'std_dev': encountered value {:?}", v),
         }
         Ok(())
     }
 
     fn get(&self) -> Result<DataValue> {
-        let ct = self.count as f64;
-        let var = (self.sum_sq - self.sum * self.sum / ct) / (ct - 1.);
-        Ok(DataValue::from(var.sqrt()))
+        Ok(DataValue::from(self.moments.sample_variance().sqrt()))
+    }
+}
+
+/// The streaming co-moment of pairs, extending [Welford] to two variables.
+#[derive(Default)]
+struct CoMoments {
+    count: f64,
+    mean_x: f64,
+    mean_y: f64,
+    m2_x: f64,
+    m2_y: f64,
+    c_xy: f64,
+}
+
+impl CoMoments {
+    fn add(&mut self, name: &str, value: &DataValue) -> Result<()> {
+        let (x, y) = match value {
+            DataValue::List(l) if l.len() == 2 => match (&l[0], &l[1]) {
+                (DataValue::Num(x), DataValue::Num(y)) => (x.get_float(), y.get_float()),
+                _ => bail!("cannot compute '{}': encountered value {:?}", name, value),
+            },
+            v => bail!(
+                "'{}' requires a list of exactly two numbers as argument, got {:?}",
+                name,
+                v
+            ),
+        };
+        self.count += 1.;
+        let dx = x - self.mean_x;
+        self.mean_x += dx / self.count;
+        let dy = y - self.mean_y;
+        self.mean_y += dy / self.count;
+        self.c_xy += dx * (y - self.mean_y);
+        self.m2_x += dx * (x - self.mean_x);
+        self.m2_y += dy * (y - self.mean_y);
+        Ok(())
+    }
+}
+
+define_aggr!(AGGR_COVARIANCE, false);
+
+/// Sample covariance of pairs `[x, y]`.
+#[derive(Default)]
+pub(crate) struct AggrCovariance {
+    moments: CoMoments,
+}
+
+impl NormalAggrObj for AggrCovariance {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.moments.add("covariance", value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(
+            self.moments.c_xy / (self.moments.count - 1.),
+        ))
+    }
+}
+
+define_aggr!(AGGR_CORRELATION, false);
+
+/// Pearson correlation of pairs `[x, y]`.
+#[derive(Default)]
+pub(crate) struct AggrCorrelation {
+    moments: CoMoments,
+}
+
+impl NormalAggrObj for AggrCorrelation {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.moments.add("correlation", value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        let m = &self.moments;
+        Ok(DataValue::from(m.c_xy / (m.m2_x * m.m2_y).sqrt()))
+    }
+}
+
+define_aggr!(AGGR_PERCENTILE, false);
+define_aggr!(AGGR_MEDIAN, false);
+
+/// The `p`-th percentile, interpolating linearly between the closest values.
+/// All values are kept, since exact percentiles cannot be computed in a single pass.
+pub(crate) struct AggrPercentile {
+    name: &'static str,
+    p: f64,
+    values: Vec<f64>,
+}
+
+impl AggrPercentile {
+    fn new(name: &'static str, p: f64) -> Self {
+        Self {
+            name,
+            p,
+            values: vec![],
+        }
+    }
+}
+
+impl NormalAggrObj for AggrPercentile {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        match value {
+            DataValue::Num(n) => self.values.push(n.get_float()),
+            v => bail!("cannot compute '{}': encountered value {:?}", self.name, v),
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        if self.values.is_empty() {
+            return Ok(DataValue::Null);
+        }
+        let mut values = self.values.clone();
+        values.sort_by(|a, b| a.total_cmp(b));
+        let rank = self.p / 100. * (values.len() - 1) as f64;
+        let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+        let frac = rank - lo as f64;
+        Ok(DataValue::from(
+            values[lo] + (values[hi] - values[lo]) * frac,
+        ))
     }
 }
 
@@ -756,6 +892,56 @@ impl NormalAggrObj for AggrLatestBy {
     }
 }
 
+define_aggr!(AGGR_LATEST, false);
+
+/// The value of the newest version from pairs `[value, validity]`, or null if the newest
+/// version is a retraction. Unlike `latest_by`, this takes into account that validities
+/// sort from the newest. Timestamps may also be integers, which are always assertions.
+#[derive(Default)]
+pub(crate) struct AggrLatest {
+    found: Option<(DataValue, i64, bool)>,
+}
+
+impl NormalAggrObj for AggrLatest {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        match value {
+            DataValue::List(l) => {
+                ensure!(
+                    l.len() == 2,
+                    "'latest' requires a list of exactly two items as argument"
+                );
+                let (ts, is_assert) = match &l[1] {
+                    DataValue::Validity(vld) => (vld.timestamp.0 .0, vld.is_assert.0),
+                    v => match v.get_int() {
+                        Some(ts) => (ts, true),
+                        None => bail!("'latest' requires a validity as second item, got {:?}", v),
+                    },
+                };
+                // at the same timestamp, an assertion wins over a retraction,
+                // as in time travel queries
+                let newer = match &self.found {
+                    None => true,
+                    Some((_, cur_ts, cur_assert)) => {
+                        ts > *cur_ts || (ts == *cur_ts && is_assert && !cur_assert)
+                    }
+                };
+                if newer {
+                    self.found = Some((l[0].clone(), ts, is_assert));
+                }
+                Ok(())
+            }
+            v => bail!("cannot compute 'latest' on {:?}", v),
+        }
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(match &self.found {
+            Some((v, _, true)) => v.clone(),
+            _ => DataValue::Null,
+        })
+    }
+}
+
 define_aggr!(AGGR_SMALLEST_BY, false);
 
 pub(crate) struct AggrSmallestBy {
@@ -796,6 +982,125 @@ impl NormalAggrObj for AggrSmallestBy {
     }
 }
 
+/// Split the `[value, by]` argument of `top_k` and `bottom_k`.
+fn ranked_pair(name: &str, value: &DataValue) -> Result<(DataValue, DataValue)> {
+    match value {
+        DataValue::List(l) => {
+            ensure!(
+                l.len() == 2,
+                "'{}' requires a list of exactly two items as argument",
+                name
+            );
+            Ok((l[1].clone(), l[0].clone()))
+        }
+        v => bail!("cannot compute '{}' on {:?}", name, v),
+    }
+}
+
+fn parse_k(name: &str, args: &[DataValue]) -> Result<usize> {
+    let k = args
+        .first()
+        .and_then(|a| a.get_int())
+        .ok_or_else(|| miette!("'{}' requires a positive integer as argument", name))?;
+    ensure!(k > 0, "argument to '{}' must be positive, got {}", name, k);
+    Ok(k as usize)
+}
+
+define_aggr!(AGGR_TOP_K, false);
+
+/// The `k` values with the largest `by`, largest first, from pairs `[value, by]`.
+pub(crate) struct AggrTopK {
+    k: usize,
+    /// The smallest of the kept pairs is on top, to be replaced by better ones.
+    heap: BinaryHeap<Reverse<(DataValue, DataValue)>>,
+}
+
+impl NormalAggrObj for AggrTopK {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let pair = ranked_pair("top_k", value)?;
+        if self.heap.len() < self.k {
+            self.heap.push(Reverse(pair));
+        } else if let Some(mut smallest) = self.heap.peek_mut() {
+            if pair > smallest.0 {
+                *smallest = Reverse(pair);
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::List(
+            self.heap
+                .clone()
+                .into_sorted_vec()
+                .into_iter()
+                .map(|Reverse((_, v))| v)
+                .collect(),
+        ))
+    }
+}
+
+define_aggr!(AGGR_BOTTOM_K, false);
+
+/// The `k` values with the smallest `by`, smallest first, from pairs `[value, by]`.
+pub(crate) struct AggrBottomK {
+    k: usize,
+    /// The largest of the kept pairs is on top, to be replaced by better ones.
+    heap: BinaryHeap<(DataValue, DataValue)>,
+}
+
+impl NormalAggrObj for AggrBottomK {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let pair = ranked_pair("bottom_k", value)?;
+        if self.heap.len() < self.k {
+            self.heap.push(pair);
+        } else if let Some(mut largest) = self.heap.peek_mut() {
+            if pair < *largest {
+                *largest = pair;
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::List(
+            self.heap
+                .clone()
+                .into_sorted_vec()
+                .into_iter()
+                .map(|(_, v)| v)
+                .collect(),
+        ))
+    }
+}
+
+define_aggr!(AGGR_GROUP_CONCAT, false);
+
+/// The values joined into a string, in order: either the values themselves,
+/// or pairs `[value, by]` ordered by `by`.
+pub(crate) struct AggrGroupConcat {
+    separator: String,
+    items: Vec<(DataValue, DataValue)>,
+}
+
+impl NormalAggrObj for AggrGroupConcat {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let item = match value {
+            DataValue::List(_) => ranked_pair("group_concat", value)?,
+            v => (v.clone(), v.clone()),
+        };
+        self.items.push(item);
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        let mut items: Vec<_> = self.items.iter().collect();
+        items.sort();
+        let strs: Vec<_> = items.into_iter().map(|(_, v)| val2str(v)).collect();
+        Ok(DataValue::from(strs.join(&self.separator)))
+    }
+}
+
 define_aggr!(AGGR_MIN_COST, true);
 
 pub(crate) struct AggrMinCost {
@@ -1155,35 +1460,51 @@ impl NormalAggrObj for AggrBitXor {
     }
 }
 
+lazy_static! {
+    static ref AGGRS_BY_NAME: BTreeMap<&'static str, &'static Aggregation> = BTreeMap::from([
+        ("and", &AGGR_AND),
+        ("or", &AGGR_OR),
+        ("unique", &AGGR_UNIQUE),
+        ("group_count", &AGGR_GROUP_COUNT),
+        ("union", &AGGR_UNION),
+        ("intersection", &AGGR_INTERSECTION),
+        ("count", &AGGR_COUNT),
+        ("count_unique", &AGGR_COUNT_UNIQUE),
+        ("variance", &AGGR_VARIANCE),
+        ("std_dev", &AGGR_STD_DEV),
+        ("covariance", &AGGR_COVARIANCE),
+        ("correlation", &AGGR_CORRELATION),
+        ("percentile", &AGGR_PERCENTILE),
+        ("median", &AGGR_MEDIAN),
+        ("sum", &AGGR_SUM),
+        ("product", &AGGR_PRODUCT),
+        ("min", &AGGR_MIN),
+        ("max", &AGGR_MAX),
+        ("mean", &AGGR_MEAN),
+        ("choice", &AGGR_CHOICE),
+        ("collect", &AGGR_COLLECT),
+        ("group_concat", &AGGR_GROUP_CONCAT),
+        ("shortest", &AGGR_SHORTEST),
+        ("min_cost", &AGGR_MIN_COST),
+        ("bit_and", &AGGR_BIT_AND),
+        ("bit_or", &AGGR_BIT_OR),
+        ("bit_xor", &AGGR_BIT_XOR),
+        ("latest_by", &AGGR_LATEST_BY),
+        ("smallest_by", &AGGR_SMALLEST_BY),
+        ("latest", &AGGR_LATEST),
+        ("top_k", &AGGR_TOP_K),
+        ("bottom_k", &AGGR_BOTTOM_K),
+        ("choice_rand", &AGGR_CHOICE_RAND),
+    ]);
+}
+
 pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
-    Some(match name {
-        "and" => &AGGR_AND,
-        "or" => &AGGR_OR,
-        "unique" => &AGGR_UNIQUE,
-        "group_count" => &AGGR_GROUP_COUNT,
-        "union" => &AGGR_UNION,
-        "intersection" => &AGGR_INTERSECTION,
-        "count" => &AGGR_COUNT,
-        "count_unique" => &AGGR_COUNT_UNIQUE,
-        "variance" => &AGGR_VARIANCE,
-        "std_dev" => &AGGR_STD_DEV,
-        "sum" => &AGGR_SUM,
-        "product" => &AGGR_PRODUCT,
-        "min" => &AGGR_MIN,
-        "max" => &AGGR_MAX,
-        "mean" => &AGGR_MEAN,
-        "choice" => &AGGR_CHOICE,
-        "collect" => &AGGR_COLLECT,
-        "shortest" => &AGGR_SHORTEST,
-        "min_cost" => &AGGR_MIN_COST,
-        "bit_and" => &AGGR_BIT_AND,
-        "bit_or" => &AGGR_BIT_OR,
-        "bit_xor" => &AGGR_BIT_XOR,
-        "latest_by" => &AGGR_LATEST_BY,
-        "smallest_by" => &AGGR_SMALLEST_BY,
-        "choice_rand" => &AGGR_CHOICE_RAND,
-        _ => return None,
-    })
+    AGGRS_BY_NAME.get(name).copied()
+}
+
+/// The names of all the aggregations that can be applied in rule heads.
+pub(crate) fn aggr_names() -> impl Iterator<Item = &'static str> {
+    AGGRS_BY_NAME.keys().copied()
 }
 
 impl Aggregation {
@@ -1219,6 +1540,20 @@ impl Aggregation {
             name if name == AGGR_MEAN.name => Box::new(AggrMean::default()),
             name if name == AGGR_VARIANCE.name => Box::new(AggrVariance::default()),
             name if name == AGGR_STD_DEV.name => Box::new(AggrStdDev::default()),
+            name if name == AGGR_COVARIANCE.name => Box::new(AggrCovariance::default()),
+            name if name == AGGR_CORRELATION.name => Box::new(AggrCorrelation::default()),
+            name if name == AGGR_MEDIAN.name => Box::new(AggrPercentile::new("median", 50.)),
+            name if name == AGGR_PERCENTILE.name => Box::new({
+                let p = args.first().and_then(|a| a.get_float()).ok_or_else(|| {
+                    miette!("'percentile' requires a number between 0 and 100 as argument")
+                })?;
+                ensure!(
+                    (0. ..=100.).contains(&p),
+                    "the argument to 'percentile' must be between 0 and 100, got {}",
+                    p
+                );
+                AggrPercentile::new("percentile", p)
+            }),
             name if name == AGGR_CHOICE.name => Box::new(AggrChoice::default()),
             name if name == AGGR_BIT_AND.name => Box::new(AggrBitAnd::default()),
             name if name == AGGR_BIT_OR.name => Box::new(AggrBitOr::default()),
@@ -1230,6 +1565,26 @@ impl Aggregation {
             name if name == AGGR_MIN_COST.name => Box::new(AggrMinCost::default()),
             name if name == AGGR_LATEST_BY.name => Box::new(AggrLatestBy::default()),
             name if name == AGGR_SMALLEST_BY.name => Box::new(AggrSmallestBy::default()),
+            name if name == AGGR_LATEST.name => Box::new(AggrLatest::default()),
+            name if name == AGGR_TOP_K.name => Box::new(AggrTopK {
+                k: parse_k("top_k", args)?,
+                heap: BinaryHeap::new(),
+            }),
+            name if name == AGGR_BOTTOM_K.name => Box::new(AggrBottomK {
+                k: parse_k("bottom_k", args)?,
+                heap: BinaryHeap::new(),
+            }),
+            name if name == AGGR_GROUP_CONCAT.name => Box::new(AggrGroupConcat {
+                separator: match args.first() {
+                    None => ",".to_string(),
+                    Some(DataValue::Str(s)) => s.to_string(),
+                    Some(v) => bail!(
+                        "the argument to 'group_concat' must be a string, got {:?}",
+                        v
+                    ),
+                },
+                items: vec![],
+            }),
             name if name == AGGR_CHOICE_RAND.name => Box::new(AggrChoiceRand::default()),
             name if name == AGGR_COLLECT.name => Box::new({
                 if args.is_empty() {
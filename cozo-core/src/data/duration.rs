@@ -0,0 +1,93 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Durations are counted in microseconds, like validity timestamps, and written as a sequence
+//! of amounts with units, e.g. `3h15m` or `-1d12h`.
+
+use miette::{bail, miette, Result};
+
+pub(crate) const MICROS_PER_SEC: i64 = 1_000_000;
+
+/// Units accepted when parsing, longer ones first where they share a prefix
+const UNITS: [(&str, i64); 8] = [
+    ("w", 7 * 86_400 * MICROS_PER_SEC),
+    ("d", 86_400 * MICROS_PER_SEC),
+    ("h", 3_600 * MICROS_PER_SEC),
+    ("ms", 1_000),
+    ("us", 1),
+    ("µs", 1),
+    ("m", 60 * MICROS_PER_SEC),
+    ("s", MICROS_PER_SEC),
+];
+
+/// Units used when formatting, from the largest
+const FORMAT_UNITS: [(&str, i64); 6] = [
+    ("d", 86_400 * MICROS_PER_SEC),
+    ("h", 3_600 * MICROS_PER_SEC),
+    ("m", 60 * MICROS_PER_SEC),
+    ("s", MICROS_PER_SEC),
+    ("ms", 1_000),
+    ("us", 1),
+];
+
+pub(crate) fn parse_duration(s: &str) -> Result<i64> {
+    let bad = || miette!("bad duration '{}', expected e.g. '3h15m'", s);
+    let trimmed = s.trim();
+    let (negative, mut rest) = match trimmed.strip_prefix('-') {
+        Some(r) => (true, r),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    if rest.is_empty() {
+        bail!(bad())
+    }
+    let mut total = 0i64;
+    while !rest.is_empty() {
+        rest = rest.trim_start();
+        let num_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+            .ok_or_else(bad)?;
+        if num_len == 0 {
+            bail!(bad())
+        }
+        let amount = rest[..num_len].replace('_', "");
+        rest = &rest[num_len..];
+        let (unit, micros) = UNITS
+            .iter()
+            .find(|(u, _)| rest.starts_with(u))
+            .ok_or_else(bad)?;
+        rest = &rest[unit.len()..];
+        let part = if let Ok(n) = amount.parse::<i64>() {
+            n.checked_mul(*micros).ok_or_else(bad)?
+        } else {
+            let f = amount.parse::<f64>().map_err(|_| bad())?;
+            (f * *micros as f64).round() as i64
+        };
+        total = total.checked_add(part).ok_or_else(bad)?;
+    }
+    Ok(if negative { -total } else { total })
+}
+
+pub(crate) fn format_duration(micros: i64) -> String {
+    if micros == 0 {
+        return "0s".to_string();
+    }
+    let mut ret = String::new();
+    if micros < 0 {
+        ret.push('-');
+    }
+    let mut remaining = micros.unsigned_abs();
+    for (unit, size) in FORMAT_UNITS {
+        let size = size as u64;
+        if remaining >= size {
+            ret.push_str(&(remaining / size).to_string());
+            ret.push_str(unit);
+            remaining %= size;
+        }
+    }
+    ret
+}
@@ -0,0 +1,359 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Reading and writing [EDN](https://github.com/edn-format/edn),
+//! the data format of Datomic and Datascript.
+
+use std::fmt::{Display, Formatter, Write};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Edn {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
+    /// Without the leading colon.
+    Keyword(String),
+    Symbol(String),
+    List(Vec<Edn>),
+    Vector(Vec<Edn>),
+    Map(Vec<(Edn, Edn)>),
+    Set(Vec<Edn>),
+    Tagged(String, Box<Edn>),
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid EDN at byte {0}: {1}")]
+#[diagnostic(code(edn::parse))]
+struct EdnParseError(usize, String);
+
+/// Parse all the top-level forms of `src`.
+pub(crate) fn parse_edn(src: &str) -> Result<Vec<Edn>> {
+    let mut reader = EdnReader {
+        src,
+        chars: src.char_indices().peekable(),
+    };
+    let mut ret = vec![];
+    while let Some(form) = reader.next_form()? {
+        ret.push(form);
+    }
+    Ok(ret)
+}
+
+struct EdnReader<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || ".*+!-_?$%&=<>/':#".contains(c)
+}
+
+impl<'a> EdnReader<'a> {
+    fn pos(&mut self) -> usize {
+        self.chars.peek().map(|(i, _)| *i).unwrap_or(self.src.len())
+    }
+    fn error<T>(&mut self, msg: impl Into<String>) -> Result<T> {
+        let pos = self.pos();
+        bail!(EdnParseError(pos, msg.into()))
+    }
+    fn skip_whitespace(&mut self) {
+        while let Some((_, c)) = self.chars.peek() {
+            match c {
+                c if c.is_whitespace() || *c == ',' => {
+                    self.chars.next();
+                }
+                ';' => {
+                    for (_, c) in self.chars.by_ref() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+    /// Skip whitespace and discarded forms, i.e. those following `#_`.
+    fn skip_ignored(&mut self) -> Result<()> {
+        loop {
+            self.skip_whitespace();
+            let pos = self.pos();
+            if !self.src[pos..].starts_with("#_") {
+                return Ok(());
+            }
+            self.chars.next();
+            self.chars.next();
+            if self.next_form()?.is_none() {
+                return self.error("nothing to discard");
+            }
+        }
+    }
+    fn token(&mut self) -> &'a str {
+        let start = self.pos();
+        while let Some((_, c)) = self.chars.peek() {
+            if !is_symbol_char(*c) {
+                break;
+            }
+            self.chars.next();
+        }
+        let end = self.pos();
+        &self.src[start..end]
+    }
+    /// The next form, or `None` at the end of the input.
+    fn next_form(&mut self) -> Result<Option<Edn>> {
+        self.skip_ignored()?;
+        let c = match self.chars.peek() {
+            None => return Ok(None),
+            Some((_, c)) => *c,
+        };
+        Ok(Some(match c {
+            '(' => {
+                self.chars.next();
+                Edn::List(self.seq(')')?)
+            }
+            '[' => {
+                self.chars.next();
+                Edn::Vector(self.seq(']')?)
+            }
+            '{' => {
+                self.chars.next();
+                let items = self.seq('}')?;
+                if items.len() % 2 != 0 {
+                    return self.error("a map must have an even number of forms");
+                }
+                Edn::Map(items.into_iter().tuples().collect())
+            }
+            ')' | ']' | '}' => return self.error(format!("unexpected '{c}'")),
+            '"' => {
+                self.chars.next();
+                Edn::Str(self.string()?)
+            }
+            '\\' => {
+                self.chars.next();
+                self.character()?
+            }
+            ':' => {
+                self.chars.next();
+                let name = self.token();
+                if name.is_empty() {
+                    return self.error("empty keyword");
+                }
+                Edn::Keyword(name.to_string())
+            }
+            '#' => {
+                self.chars.next();
+                match self.chars.peek().map(|(_, c)| *c) {
+                    Some('{') => {
+                        self.chars.next();
+                        Edn::Set(self.seq('}')?)
+                    }
+                    Some('#') => {
+                        self.chars.next();
+                        match self.token() {
+                            "Inf" => Edn::Float(f64::INFINITY),
+                            "-Inf" => Edn::Float(f64::NEG_INFINITY),
+                            "NaN" => Edn::Float(f64::NAN),
+                            t => return self.error(format!("unknown symbolic value '##{t}'")),
+                        }
+                    }
+                    _ => {
+                        let tag = self.token();
+                        if tag.is_empty() {
+                            return self.error("empty tag");
+                        }
+                        match self.next_form()? {
+                            Some(form) => Edn::Tagged(tag.to_string(), Box::new(form)),
+                            None => return self.error(format!("nothing follows the tag #{tag}")),
+                        }
+                    }
+                }
+            }
+            _ => {
+                let token = self.token();
+                if token.is_empty() {
+                    return self.error(format!("unexpected '{c}'"));
+                }
+                self.atom(token)?
+            }
+        }))
+    }
+    fn seq(&mut self, close: char) -> Result<Vec<Edn>> {
+        let mut ret = vec![];
+        loop {
+            self.skip_ignored()?;
+            match self.chars.peek() {
+                Some((_, c)) if *c == close => {
+                    self.chars.next();
+                    return Ok(ret);
+                }
+                _ => match self.next_form()? {
+                    Some(form) => ret.push(form),
+                    None => return self.error(format!("expected '{close}'")),
+                },
+            }
+        }
+    }
+    fn string(&mut self) -> Result<String> {
+        let mut ret = String::new();
+        loop {
+            match self.chars.next() {
+                None => return self.error("unterminated string"),
+                Some((_, '"')) => return Ok(ret),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, 'n')) => ret.push('\n'),
+                    Some((_, 't')) => ret.push('\t'),
+                    Some((_, 'r')) => ret.push('\r'),
+                    Some((_, '"')) => ret.push('"'),
+                    Some((_, '\\')) => ret.push('\\'),
+                    Some((_, 'u')) => ret.push(self.unicode_escape()?),
+                    _ => return self.error("invalid escape in string"),
+                },
+                Some((_, c)) => ret.push(c),
+            }
+        }
+    }
+    fn unicode_escape(&mut self) -> Result<char> {
+        let start = self.pos();
+        for _ in 0..4 {
+            self.chars.next();
+        }
+        let end = self.pos();
+        match u32::from_str_radix(&self.src[start..end], 16)
+            .ok()
+            .and_then(char::from_u32)
+        {
+            Some(c) => Ok(c),
+            None => self.error("invalid unicode escape"),
+        }
+    }
+    fn character(&mut self) -> Result<Edn> {
+        let first = match self.chars.next() {
+            None => return self.error("expected a character"),
+            Some((_, c)) => c,
+        };
+        if !first.is_alphanumeric() {
+            return Ok(Edn::Char(first));
+        }
+        let rest = self.token();
+        Ok(Edn::Char(match (first, rest) {
+            (c, "") => c,
+            ('n', "ewline") => '\n',
+            ('s', "pace") => ' ',
+            ('t', "ab") => '\t',
+            ('r', "eturn") => '\r',
+            ('u', hex) if hex.len() == 4 => {
+                match u32::from_str_radix(hex, 16).ok().and_then(char::from_u32) {
+                    Some(c) => c,
+                    None => return self.error("invalid unicode character"),
+                }
+            }
+            _ => return self.error(format!("unknown character \\{first}{rest}")),
+        }))
+    }
+    fn atom(&mut self, token: &str) -> Result<Edn> {
+        match token {
+            "nil" => return Ok(Edn::Nil),
+            "true" => return Ok(Edn::Bool(true)),
+            "false" => return Ok(Edn::Bool(false)),
+            _ => {}
+        }
+        let mut chars = token.chars();
+        let numeric = match chars.next() {
+            Some('+' | '-') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+            Some(c) => c.is_ascii_digit(),
+            None => false,
+        };
+        if !numeric {
+            return Ok(Edn::Symbol(token.to_string()));
+        }
+        if let Ok(int) = token.strip_suffix('N').unwrap_or(token).parse::<i64>() {
+            return Ok(Edn::Int(int));
+        }
+        match token.strip_suffix('M').unwrap_or(token).parse::<f64>() {
+            Ok(f) => Ok(Edn::Float(f)),
+            Err(_) => self.error(format!("invalid number '{token}'")),
+        }
+    }
+}
+
+fn write_seq(f: &mut Formatter<'_>, open: &str, items: &[Edn], close: char) -> std::fmt::Result {
+    f.write_str(open)?;
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_char(' ')?;
+        }
+        write!(f, "{item}")?;
+    }
+    f.write_char(close)
+}
+
+impl Display for Edn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Edn::Nil => f.write_str("nil"),
+            Edn::Bool(b) => write!(f, "{b}"),
+            Edn::Int(i) => write!(f, "{i}"),
+            Edn::Float(x) => {
+                if x.is_nan() {
+                    f.write_str("##NaN")
+                } else if x.is_infinite() {
+                    f.write_str(if *x > 0. { "##Inf" } else { "##-Inf" })
+                } else {
+                    // `{:?}` always writes a decimal point or an exponent
+                    write!(f, "{x:?}")
+                }
+            }
+            Edn::Str(s) => {
+                f.write_char('"')?;
+                for c in s.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        '\r' => f.write_str("\\r")?,
+                        c => f.write_char(c)?,
+                    }
+                }
+                f.write_char('"')
+            }
+            Edn::Char(c) => match c {
+                '\n' => f.write_str("\\newline"),
+                ' ' => f.write_str("\\space"),
+                '\t' => f.write_str("\\tab"),
+                '\r' => f.write_str("\\return"),
+                c => write!(f, "\\{c}"),
+            },
+            Edn::Keyword(k) => write!(f, ":{k}"),
+            Edn::Symbol(s) => f.write_str(s),
+            Edn::List(l) => write_seq(f, "(", l, ')'),
+            Edn::Vector(l) => write_seq(f, "[", l, ']'),
+            Edn::Set(l) => write_seq(f, "#{", l, '}'),
+            Edn::Map(m) => {
+                f.write_char('{')?;
+                for (i, (k, v)) in m.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{k} {v}")?;
+                }
+                f.write_char('}')
+            }
+            Edn::Tagged(tag, form) => write!(f, "#{tag} {form}"),
+        }
+    }
+}
@@ -12,6 +12,7 @@ use std::fmt::{Debug, Display, Formatter};
 use std::mem;
 
 use itertools::Itertools;
+use lazy_static::lazy_static;
 use miette::{bail, miette, Diagnostic, Result};
 use serde::de::{Error, Visitor};
 use serde::{Deserializer, Serializer};
@@ -21,7 +22,8 @@ use thiserror::Error;
 use crate::data::functions::*;
 use crate::data::relation::NullableColType;
 use crate::data::symb::Symbol;
-use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Num, LARGEST_UTF_CHAR};
 use crate::parse::expr::expr2bytecode;
 use crate::parse::SourceSpan;
 
@@ -154,6 +156,181 @@ pub fn eval_bytecode(
     Ok(stack.pop().unwrap())
 }
 
+/// Evaluate a predicate on a batch of tuples, returning whether each tuple satisfies it.
+///
+/// Bytecodes without jumps are run once for the whole batch, on columns instead of
+/// single values, with tight loops for comparisons and arithmetic on integers and for
+/// string prefixes. Other bytecodes, and batches raising errors, are evaluated row by
+/// row so that the errors are exactly those of [eval_bytecode_pred].
+pub(crate) fn eval_bytecode_pred_batch(
+    bytecodes: &[Bytecode],
+    rows: &[Tuple],
+    stack: &mut Vec<DataValue>,
+    span: SourceSpan,
+) -> Result<Vec<bool>> {
+    if !rows.is_empty() && is_batchable(bytecodes) {
+        if let Some(mask) = eval_columns(bytecodes, rows).and_then(|col| col.into_mask(rows.len()))
+        {
+            return Ok(mask);
+        }
+    }
+    rows.iter()
+        .map(|row| eval_bytecode_pred(bytecodes, row, stack, span))
+        .collect()
+}
+
+fn is_batchable(bytecodes: &[Bytecode]) -> bool {
+    bytecodes.iter().all(|code| match code {
+        Bytecode::Binding { tuple_pos, .. } => tuple_pos.is_some(),
+        Bytecode::Const { .. } => true,
+        // impure functions must be called in the same order as row by row
+        Bytecode::Apply { op, arity, .. } => *arity > 0 && op.deterministic,
+        Bytecode::JumpIfFalse { .. } | Bytecode::Goto { .. } => false,
+    })
+}
+
+/// The values of an expression for every tuple of a batch.
+enum Column {
+    /// The same value for every tuple
+    Scalar(DataValue),
+    /// A position in the tuples
+    Binding(usize),
+    Values(Vec<DataValue>),
+    Ints(Vec<i64>),
+    Bools(Vec<bool>),
+}
+
+impl Column {
+    fn get(&self, rows: &[Tuple], i: usize) -> Option<DataValue> {
+        Some(match self {
+            Column::Scalar(v) => v.clone(),
+            Column::Binding(pos) => rows[i].get(*pos)?.clone(),
+            Column::Values(vs) => vs[i].clone(),
+            Column::Ints(is) => DataValue::from(is[i]),
+            Column::Bools(bs) => DataValue::from(bs[i]),
+        })
+    }
+    /// The integers of the column, if it only contains integers.
+    fn ints(&self, rows: &[Tuple]) -> Option<Vec<i64>> {
+        let int_of = |v: &DataValue| match v {
+            DataValue::Num(Num::Int(i)) => Some(*i),
+            _ => None,
+        };
+        match self {
+            Column::Ints(is) => Some(is.clone()),
+            Column::Binding(pos) => rows.iter().map(|row| int_of(row.get(*pos)?)).collect(),
+            Column::Values(vs) => vs.iter().map(int_of).collect(),
+            _ => None,
+        }
+    }
+    fn into_mask(self, len: usize) -> Option<Vec<bool>> {
+        match self {
+            Column::Bools(bs) => Some(bs),
+            Column::Scalar(DataValue::Bool(b)) => Some(vec![b; len]),
+            Column::Values(vs) => vs.into_iter().map(|v| v.get_bool()).collect(),
+            _ => None,
+        }
+    }
+}
+
+/// Run jump-free bytecodes on columns. `None` means the batch must be evaluated
+/// row by row, in particular when evaluating it raised an error.
+fn eval_columns(bytecodes: &[Bytecode], rows: &[Tuple]) -> Option<Column> {
+    let mut stack: Vec<Column> = vec![];
+    for code in bytecodes {
+        match code {
+            Bytecode::Binding { tuple_pos, .. } => stack.push(Column::Binding((*tuple_pos)?)),
+            Bytecode::Const { val, .. } => stack.push(Column::Scalar(val.clone())),
+            Bytecode::Apply { op, arity, .. } => {
+                let args = stack.split_off(stack.len() - *arity);
+                stack.push(apply_columns(op, args, rows)?);
+            }
+            Bytecode::JumpIfFalse { .. } | Bytecode::Goto { .. } => return None,
+        }
+    }
+    stack.pop()
+}
+
+fn apply_columns(op: &Op, args: Vec<Column>, rows: &[Tuple]) -> Option<Column> {
+    if args.iter().all(|arg| matches!(arg, Column::Scalar(_))) {
+        let vals = args
+            .into_iter()
+            .map(|arg| match arg {
+                Column::Scalar(v) => v,
+                _ => unreachable!(),
+            })
+            .collect_vec();
+        return (op.inner)(&vals).ok().map(Column::Scalar);
+    }
+    if let [l, r] = &args[..] {
+        if let Some(col) = apply_int_columns(op.name, l, r, rows) {
+            return Some(col);
+        }
+        if op.name == "OP_STARTS_WITH" {
+            if let Column::Scalar(DataValue::Str(prefix)) = r {
+                let starts_with = |v: &DataValue| match v {
+                    DataValue::Str(s) => Some(s.starts_with(prefix as &str)),
+                    _ => None,
+                };
+                let mask: Option<Vec<bool>> = match l {
+                    Column::Binding(pos) => {
+                        rows.iter().map(|row| starts_with(row.get(*pos)?)).collect()
+                    }
+                    Column::Values(vs) => vs.iter().map(starts_with).collect(),
+                    _ => None,
+                };
+                if let Some(mask) = mask {
+                    return Some(Column::Bools(mask));
+                }
+            }
+        }
+    }
+    let mut frame = Vec::with_capacity(args.len());
+    let mut ret = Vec::with_capacity(rows.len());
+    for i in 0..rows.len() {
+        frame.clear();
+        for arg in &args {
+            frame.push(arg.get(rows, i)?);
+        }
+        ret.push((op.inner)(&frame).ok()?);
+    }
+    Some(Column::Values(ret))
+}
+
+/// Comparisons and arithmetic on integers, computed the same way as by the functions.
+fn apply_int_columns(op_name: &str, l: &Column, r: &Column, rows: &[Tuple]) -> Option<Column> {
+    let len = rows.len();
+    let ints = |c: &Column| match c {
+        Column::Scalar(DataValue::Num(Num::Int(i))) => Some(vec![*i; len]),
+        Column::Scalar(_) => None,
+        c => c.ints(rows),
+    };
+    let l = ints(l)?;
+    let r = ints(r)?;
+    let cmp = |f: fn(&i64, &i64) -> bool| {
+        Some(Column::Bools(
+            l.iter().zip(&r).map(|(a, b)| f(a, b)).collect(),
+        ))
+    };
+    let arith = |f: fn(i64, i64) -> i64| {
+        Some(Column::Ints(
+            l.iter().zip(&r).map(|(a, b)| f(*a, *b)).collect(),
+        ))
+    };
+    match op_name {
+        "OP_EQ" => cmp(i64::eq),
+        "OP_NEQ" => cmp(i64::ne),
+        "OP_GT" => cmp(i64::gt),
+        "OP_GE" => cmp(i64::ge),
+        "OP_LT" => cmp(i64::lt),
+        "OP_LE" => cmp(i64::le),
+        "OP_ADD" => arith(|a, b| a + b),
+        "OP_SUB" => arith(|a, b| a - b),
+        "OP_MUL" => arith(|a, b| a * b),
+        _ => None,
+    }
+}
+
 /// Expression can be evaluated to yield a DataValue
 #[derive(Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
 pub enum Expr {
@@ -729,6 +906,8 @@ pub struct Op {
     pub(crate) name: &'static str,
     pub(crate) min_arity: usize,
     pub(crate) vararg: bool,
+    /// Whether the function always returns the same value for the same arguments
+    pub(crate) deterministic: bool,
     pub(crate) inner: fn(&[DataValue]) -> Result<DataValue>,
 }
 
@@ -791,152 +970,196 @@ impl Debug for Op {
     }
 }
 
+lazy_static! {
+    static ref OPS_BY_NAME: BTreeMap<&'static str, &'static Op> = {
+        let ops = [
+            ("coalesce", &OP_COALESCE),
+            ("list", &OP_LIST),
+            ("json", &OP_JSON),
+            ("set_json_path", &OP_SET_JSON_PATH),
+            ("remove_json_path", &OP_REMOVE_JSON_PATH),
+            ("parse_json", &OP_PARSE_JSON),
+            ("dump_json", &OP_DUMP_JSON),
+            ("json_object", &OP_JSON_OBJECT),
+            ("json_set", &OP_JSON_SET),
+            ("json_remove", &OP_JSON_REMOVE),
+            ("json_merge_patch", &OP_JSON_MERGE_PATCH),
+            ("jsonpath", &OP_JSONPATH),
+            ("is_json", &OP_IS_JSON),
+            ("json_to_scalar", &OP_JSON_TO_SCALAR),
+            ("json_get", &OP_JSON_GET),
+            ("add", &OP_ADD),
+            ("sub", &OP_SUB),
+            ("mul", &OP_MUL),
+            ("div", &OP_DIV),
+            ("minus", &OP_MINUS),
+            ("abs", &OP_ABS),
+            ("signum", &OP_SIGNUM),
+            ("floor", &OP_FLOOR),
+            ("ceil", &OP_CEIL),
+            ("round", &OP_ROUND),
+            ("mod", &OP_MOD),
+            ("max", &OP_MAX),
+            ("min", &OP_MIN),
+            ("pow", &OP_POW),
+            ("sqrt", &OP_SQRT),
+            ("exp", &OP_EXP),
+            ("exp2", &OP_EXP2),
+            ("ln", &OP_LN),
+            ("log2", &OP_LOG2),
+            ("log10", &OP_LOG10),
+            ("sin", &OP_SIN),
+            ("cos", &OP_COS),
+            ("tan", &OP_TAN),
+            ("asin", &OP_ASIN),
+            ("acos", &OP_ACOS),
+            ("atan", &OP_ATAN),
+            ("atan2", &OP_ATAN2),
+            ("sinh", &OP_SINH),
+            ("cosh", &OP_COSH),
+            ("tanh", &OP_TANH),
+            ("asinh", &OP_ASINH),
+            ("acosh", &OP_ACOSH),
+            ("atanh", &OP_ATANH),
+            ("eq", &OP_EQ),
+            ("neq", &OP_NEQ),
+            ("gt", &OP_GT),
+            ("ge", &OP_GE),
+            ("lt", &OP_LT),
+            ("le", &OP_LE),
+            ("or", &OP_OR),
+            ("and", &OP_AND),
+            ("negate", &OP_NEGATE),
+            ("bit_and", &OP_BIT_AND),
+            ("bit_or", &OP_BIT_OR),
+            ("bit_not", &OP_BIT_NOT),
+            ("bit_xor", &OP_BIT_XOR),
+            ("pack_bits", &OP_PACK_BITS),
+            ("unpack_bits", &OP_UNPACK_BITS),
+            ("concat", &OP_CONCAT),
+            ("str_includes", &OP_STR_INCLUDES),
+            ("lowercase", &OP_LOWERCASE),
+            ("uppercase", &OP_UPPERCASE),
+            ("trim", &OP_TRIM),
+            ("trim_start", &OP_TRIM_START),
+            ("trim_end", &OP_TRIM_END),
+            ("starts_with", &OP_STARTS_WITH),
+            ("ends_with", &OP_ENDS_WITH),
+            ("is_null", &OP_IS_NULL),
+            ("is_int", &OP_IS_INT),
+            ("is_float", &OP_IS_FLOAT),
+            ("is_num", &OP_IS_NUM),
+            ("is_decimal", &OP_IS_DECIMAL),
+            ("is_duration", &OP_IS_DURATION),
+            ("is_string", &OP_IS_STRING),
+            ("is_list", &OP_IS_LIST),
+            ("is_bytes", &OP_IS_BYTES),
+            ("is_in", &OP_IS_IN),
+            ("is_finite", &OP_IS_FINITE),
+            ("is_infinite", &OP_IS_INFINITE),
+            ("is_nan", &OP_IS_NAN),
+            ("is_uuid", &OP_IS_UUID),
+            ("is_vec", &OP_IS_VEC),
+            ("length", &OP_LENGTH),
+            ("sorted", &OP_SORTED),
+            ("reverse", &OP_REVERSE),
+            ("append", &OP_APPEND),
+            ("prepend", &OP_PREPEND),
+            ("unicode_normalize", &OP_UNICODE_NORMALIZE),
+            ("haversine", &OP_HAVERSINE),
+            ("haversine_deg_input", &OP_HAVERSINE_DEG_INPUT),
+            ("haversine_km", &OP_HAVERSINE_KM),
+            ("geo_point", &OP_GEO_POINT),
+            ("geo_bbox", &OP_GEO_BBOX),
+            ("geo_contains", &OP_GEO_CONTAINS),
+            ("geohash_encode", &OP_GEOHASH_ENCODE),
+            ("geohash_decode", &OP_GEOHASH_DECODE),
+            ("deg_to_rad", &OP_DEG_TO_RAD),
+            ("rad_to_deg", &OP_RAD_TO_DEG),
+            ("get", &OP_GET),
+            ("maybe_get", &OP_MAYBE_GET),
+            ("chars", &OP_CHARS),
+            ("split_n", &OP_SPLIT_N),
+            ("levenshtein", &OP_LEVENSHTEIN),
+            ("jaro_winkler", &OP_JARO_WINKLER),
+            ("soundex", &OP_SOUNDEX),
+            ("slice_string", &OP_SLICE_STRING),
+            ("from_substrings", &OP_FROM_SUBSTRINGS),
+            ("slice", &OP_SLICE),
+            ("regex_matches", &OP_REGEX_MATCHES),
+            ("regex_replace", &OP_REGEX_REPLACE),
+            ("regex_replace_all", &OP_REGEX_REPLACE_ALL),
+            ("regex_extract", &OP_REGEX_EXTRACT),
+            ("regex_extract_first", &OP_REGEX_EXTRACT_FIRST),
+            ("t2s", &OP_T2S),
+            ("encode_base64", &OP_ENCODE_BASE64),
+            ("decode_base64", &OP_DECODE_BASE64),
+            ("first", &OP_FIRST),
+            ("last", &OP_LAST),
+            ("chunks", &OP_CHUNKS),
+            ("chunks_exact", &OP_CHUNKS_EXACT),
+            ("windows", &OP_WINDOWS),
+            ("zip", &OP_ZIP),
+            ("enumerate", &OP_ENUMERATE),
+            ("list_unique", &OP_LIST_UNIQUE),
+            ("to_int", &OP_TO_INT),
+            ("to_float", &OP_TO_FLOAT),
+            ("to_decimal", &OP_TO_DECIMAL),
+            ("to_bigint", &OP_TO_BIGINT),
+            ("to_duration", &OP_TO_DURATION),
+            ("to_string", &OP_TO_STRING),
+            ("l2_dist", &OP_L2_DIST),
+            ("l2_normalize", &OP_L2_NORMALIZE),
+            ("ip_dist", &OP_IP_DIST),
+            ("cos_dist", &OP_COS_DIST),
+            ("int_range", &OP_INT_RANGE),
+            ("rand_float", &OP_RAND_FLOAT),
+            ("rand_bernoulli", &OP_RAND_BERNOULLI),
+            ("rand_int", &OP_RAND_INT),
+            ("rand_choose", &OP_RAND_CHOOSE),
+            ("assert", &OP_ASSERT),
+            ("union", &OP_UNION),
+            ("intersection", &OP_INTERSECTION),
+            ("difference", &OP_DIFFERENCE),
+            ("to_uuid", &OP_TO_UUID),
+            ("to_bool", &OP_TO_BOOL),
+            ("to_unity", &OP_TO_UNITY),
+            ("rand_uuid_v1", &OP_RAND_UUID_V1),
+            ("rand_uuid_v4", &OP_RAND_UUID_V4),
+            ("gen_id", &OP_GEN_ID),
+            ("uuid_timestamp", &OP_UUID_TIMESTAMP),
+            ("validity", &OP_VALIDITY),
+            ("now", &OP_NOW),
+            ("format_timestamp", &OP_FORMAT_TIMESTAMP),
+            ("parse_timestamp", &OP_PARSE_TIMESTAMP),
+            ("vec", &OP_VEC),
+            ("rand_vec", &OP_RAND_VEC),
+        ];
+        #[cfg(feature = "fancy-regex")]
+        let ops = ops.into_iter().chain([
+            ("regex_matches_fancy", &OP_REGEX_MATCHES_FANCY),
+            ("regex_replace_fancy", &OP_REGEX_REPLACE_FANCY),
+            ("regex_replace_all_fancy", &OP_REGEX_REPLACE_ALL_FANCY),
+            ("regex_extract_fancy", &OP_REGEX_EXTRACT_FANCY),
+            ("regex_extract_first_fancy", &OP_REGEX_EXTRACT_FIRST_FANCY),
+        ]);
+        ops.into_iter().collect()
+    };
+}
+
 pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
-    Some(match name {
-        "coalesce" => &OP_COALESCE,
-        "list" => &OP_LIST,
-        "json" => &OP_JSON,
-        "set_json_path" => &OP_SET_JSON_PATH,
-        "remove_json_path" => &OP_REMOVE_JSON_PATH,
-        "parse_json" => &OP_PARSE_JSON,
-        "dump_json" => &OP_DUMP_JSON,
-        "json_object" => &OP_JSON_OBJECT,
-        "is_json" => &OP_IS_JSON,
-        "json_to_scalar" => &OP_JSON_TO_SCALAR,
-        "add" => &OP_ADD,
-        "sub" => &OP_SUB,
-        "mul" => &OP_MUL,
-        "div" => &OP_DIV,
-        "minus" => &OP_MINUS,
-        "abs" => &OP_ABS,
-        "signum" => &OP_SIGNUM,
-        "floor" => &OP_FLOOR,
-        "ceil" => &OP_CEIL,
-        "round" => &OP_ROUND,
-        "mod" => &OP_MOD,
-        "max" => &OP_MAX,
-        "min" => &OP_MIN,
-        "pow" => &OP_POW,
-        "sqrt" => &OP_SQRT,
-        "exp" => &OP_EXP,
-        "exp2" => &OP_EXP2,
-        "ln" => &OP_LN,
-        "log2" => &OP_LOG2,
-        "log10" => &OP_LOG10,
-        "sin" => &OP_SIN,
-        "cos" => &OP_COS,
-        "tan" => &OP_TAN,
-        "asin" => &OP_ASIN,
-        "acos" => &OP_ACOS,
-        "atan" => &OP_ATAN,
-        "atan2" => &OP_ATAN2,
-        "sinh" => &OP_SINH,
-        "cosh" => &OP_COSH,
-        "tanh" => &OP_TANH,
-        "asinh" => &OP_ASINH,
-        "acosh" => &OP_ACOSH,
-        "atanh" => &OP_ATANH,
-        "eq" => &OP_EQ,
-        "neq" => &OP_NEQ,
-        "gt" => &OP_GT,
-        "ge" => &OP_GE,
-        "lt" => &OP_LT,
-        "le" => &OP_LE,
-        "or" => &OP_OR,
-        "and" => &OP_AND,
-        "negate" => &OP_NEGATE,
-        "bit_and" => &OP_BIT_AND,
-        "bit_or" => &OP_BIT_OR,
-        "bit_not" => &OP_BIT_NOT,
-        "bit_xor" => &OP_BIT_XOR,
-        "pack_bits" => &OP_PACK_BITS,
-        "unpack_bits" => &OP_UNPACK_BITS,
-        "concat" => &OP_CONCAT,
-        "str_includes" => &OP_STR_INCLUDES,
-        "lowercase" => &OP_LOWERCASE,
-        "uppercase" => &OP_UPPERCASE,
-        "trim" => &OP_TRIM,
-        "trim_start" => &OP_TRIM_START,
-        "trim_end" => &OP_TRIM_END,
-        "starts_with" => &OP_STARTS_WITH,
-        "ends_with" => &OP_ENDS_WITH,
-        "is_null" => &OP_IS_NULL,
-        "is_int" => &OP_IS_INT,
-        "is_float" => &OP_IS_FLOAT,
-        "is_num" => &OP_IS_NUM,
-        "is_string" => &OP_IS_STRING,
-        "is_list" => &OP_IS_LIST,
-        "is_bytes" => &OP_IS_BYTES,
-        "is_in" => &OP_IS_IN,
-        "is_finite" => &OP_IS_FINITE,
-        "is_infinite" => &OP_IS_INFINITE,
-        "is_nan" => &OP_IS_NAN,
-        "is_uuid" => &OP_IS_UUID,
-        "is_vec" => &OP_IS_VEC,
-        "length" => &OP_LENGTH,
-        "sorted" => &OP_SORTED,
-        "reverse" => &OP_REVERSE,
-        "append" => &OP_APPEND,
-        "prepend" => &OP_PREPEND,
-        "unicode_normalize" => &OP_UNICODE_NORMALIZE,
-        "haversine" => &OP_HAVERSINE,
-        "haversine_deg_input" => &OP_HAVERSINE_DEG_INPUT,
-        "deg_to_rad" => &OP_DEG_TO_RAD,
-        "rad_to_deg" => &OP_RAD_TO_DEG,
-        "get" => &OP_GET,
-        "maybe_get" => &OP_MAYBE_GET,
-        "chars" => &OP_CHARS,
-        "slice_string" => &OP_SLICE_STRING,
-        "from_substrings" => &OP_FROM_SUBSTRINGS,
-        "slice" => &OP_SLICE,
-        "regex_matches" => &OP_REGEX_MATCHES,
-        "regex_replace" => &OP_REGEX_REPLACE,
-        "regex_replace_all" => &OP_REGEX_REPLACE_ALL,
-        "regex_extract" => &OP_REGEX_EXTRACT,
-        "regex_extract_first" => &OP_REGEX_EXTRACT_FIRST,
-        "t2s" => &OP_T2S,
-        "encode_base64" => &OP_ENCODE_BASE64,
-        "decode_base64" => &OP_DECODE_BASE64,
-        "first" => &OP_FIRST,
-        "last" => &OP_LAST,
-        "chunks" => &OP_CHUNKS,
-        "chunks_exact" => &OP_CHUNKS_EXACT,
-        "windows" => &OP_WINDOWS,
-        "to_int" => &OP_TO_INT,
-        "to_float" => &OP_TO_FLOAT,
-        "to_string" => &OP_TO_STRING,
-        "l2_dist" => &OP_L2_DIST,
-        "l2_normalize" => &OP_L2_NORMALIZE,
-        "ip_dist" => &OP_IP_DIST,
-        "cos_dist" => &OP_COS_DIST,
-        "int_range" => &OP_INT_RANGE,
-        "rand_float" => &OP_RAND_FLOAT,
-        "rand_bernoulli" => &OP_RAND_BERNOULLI,
-        "rand_int" => &OP_RAND_INT,
-        "rand_choose" => &OP_RAND_CHOOSE,
-        "assert" => &OP_ASSERT,
-        "union" => &OP_UNION,
-        "intersection" => &OP_INTERSECTION,
-        "difference" => &OP_DIFFERENCE,
-        "to_uuid" => &OP_TO_UUID,
-        "to_bool" => &OP_TO_BOOL,
-        "to_unity" => &OP_TO_UNITY,
-        "rand_uuid_v1" => &OP_RAND_UUID_V1,
-        "rand_uuid_v4" => &OP_RAND_UUID_V4,
-        "uuid_timestamp" => &OP_UUID_TIMESTAMP,
-        "validity" => &OP_VALIDITY,
-        "now" => &OP_NOW,
-        "format_timestamp" => &OP_FORMAT_TIMESTAMP,
-        "parse_timestamp" => &OP_PARSE_TIMESTAMP,
-        "vec" => &OP_VEC,
-        "rand_vec" => &OP_RAND_VEC,
-        _ => return None,
-    })
+    OPS_BY_NAME.get(name).copied()
+}
+
+/// The names of all the functions that can be applied in expressions.
+pub(crate) fn op_names() -> impl Iterator<Item = &'static str> {
+    OPS_BY_NAME.keys().copied()
 }
 
 impl Op {
     pub(crate) fn post_process_args(&self, args: &mut [Expr]) {
-        if self.name.starts_with("OP_REGEX_") {
+        // the patterns of the fancy variants are compiled by the functions themselves
+        if self.name.starts_with("OP_REGEX_") && !self.name.ends_with("_FANCY") {
             args[1] = Expr::Apply {
                 op: &OP_REGEX,
                 args: [args[1].clone()].into(),
@@ -6,6 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cell::RefCell;
 use std::cmp::Reverse;
 use std::collections::BTreeSet;
 use std::mem;
@@ -15,6 +16,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
+use bigdecimal::{BigDecimal, RoundingMode, Signed, ToPrimitive, Zero};
 use chrono::{DateTime, TimeZone, Utc};
 use itertools::Itertools;
 #[cfg(target_arch = "wasm32")]
@@ -27,19 +29,34 @@ use smartstring::SmartString;
 use unicode_normalization::UnicodeNormalization;
 use uuid::v1::Timestamp;
 
+use crate::data::duration::{format_duration, parse_duration, MICROS_PER_SEC};
 use crate::data::expr::Op;
+use crate::data::geo::{
+    bbox_contains, bounding_box, geohash_decode, geohash_encode, get_point, haversine_km,
+    polygon_contains, MAX_GEOHASH_PRECISION,
+};
 use crate::data::json::JsonValue;
+use crate::data::json_path::JsonPath;
 use crate::data::relation::VecElementType;
 use crate::data::value::{
     DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector,
 };
+use crate::runtime::idgen::next_id;
 
 macro_rules! define_op {
     ($name:ident, $min_arity:expr, $vararg:expr) => {
+        define_op!(@ $name, $min_arity, $vararg, true);
+    };
+    // functions that may return different values for the same arguments
+    ($name:ident, $min_arity:expr, $vararg:expr, impure) => {
+        define_op!(@ $name, $min_arity, $vararg, false);
+    };
+    (@ $name:ident, $min_arity:expr, $vararg:expr, $deterministic:expr) => {
         pub(crate) const $name: Op = Op {
             name: stringify!($name),
             min_arity: $min_arity,
             vararg: $vararg,
+            deterministic: $deterministic,
             inner: ::casey::lower!($name),
         };
     };
@@ -51,7 +68,8 @@ fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
         (a, b),
         (Null, Null)
             | (Bool(_), Bool(_))
-            | (Num(_), Num(_))
+            | (Num(_) | Decimal(_), Num(_) | Decimal(_))
+            | (Duration(_), Duration(_))
             | (Str(_), Str(_))
             | (Bytes(_), Bytes(_))
             | (Regex(_), Regex(_))
@@ -68,6 +86,41 @@ fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
     Ok(())
 }
 
+/// The value as an exact decimal, if it is an int or a decimal.
+fn get_decimal(v: &DataValue) -> Option<BigDecimal> {
+    match v {
+        DataValue::Num(Num::Int(i)) => Some(BigDecimal::from(*i)),
+        DataValue::Decimal(d) => Some(d.clone()),
+        _ => None,
+    }
+}
+
+/// Computes `exact` on the arguments as decimals if one of them is a decimal and the others
+/// are ints or decimals. Decimals mixed with other values are turned into floats for `approx`.
+fn with_decimals(
+    args: &[DataValue],
+    exact: impl FnOnce(Vec<BigDecimal>) -> Result<DataValue>,
+    approx: fn(&[DataValue]) -> Result<DataValue>,
+) -> Option<Result<DataValue>> {
+    if !args.iter().any(|v| matches!(v, DataValue::Decimal(_))) {
+        return None;
+    }
+    Some(
+        match args.iter().map(get_decimal).collect::<Option<Vec<_>>>() {
+            Some(ds) => exact(ds),
+            None => approx(
+                &args
+                    .iter()
+                    .map(|v| match v {
+                        DataValue::Decimal(_) => DataValue::from(v.get_float().unwrap()),
+                        v => v.clone(),
+                    })
+                    .collect_vec(),
+            ),
+        },
+    )
+}
+
 define_op!(OP_LIST, 0, true);
 pub(crate) fn op_list(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(args.to_vec()))
@@ -182,6 +235,105 @@ pub(crate) fn op_remove_json_path(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Json(JsonData(result)))
 }
 
+/// A path given either as a JSON path string or as a list of keys and indices.
+fn json_path_keys(path: &DataValue) -> Result<Vec<DataValue>> {
+    match path {
+        DataValue::Str(s) => JsonPath::parse(s)?.to_keys(),
+        DataValue::List(l) => Ok(l.clone()),
+        _ => bail!("json path must be a string or a list"),
+    }
+}
+
+define_op!(OP_JSON_SET, 3, false);
+pub(crate) fn op_json_set(args: &[DataValue]) -> Result<DataValue> {
+    let path = json_path_keys(&args[1])?;
+    let mut result = to_json(&args[0]);
+    let pointer = get_json_path(&mut result, &path)?;
+    *pointer = to_json(&args[2]);
+    Ok(DataValue::Json(JsonData(result)))
+}
+
+define_op!(OP_JSON_REMOVE, 2, false);
+pub(crate) fn op_json_remove(args: &[DataValue]) -> Result<DataValue> {
+    let path = json_path_keys(&args[1])?;
+    let mut result = to_json(&args[0]);
+    let (last, path) = path
+        .split_last()
+        .ok_or_else(|| miette!("json path must not be empty"))?;
+    // removing what does not exist leaves the document as it is
+    let mut pointer = Some(&mut result);
+    for key in path {
+        pointer = match pointer {
+            Some(JsonValue::Object(obj)) => obj.get_mut(&val2str(key) as &str),
+            Some(JsonValue::Array(arr)) => key
+                .get_int()
+                .and_then(|i| usize::try_from(i).ok())
+                .and_then(|i| arr.get_mut(i)),
+            _ => None,
+        };
+    }
+    match pointer {
+        Some(JsonValue::Object(obj)) => {
+            obj.remove(&val2str(last) as &str);
+        }
+        Some(JsonValue::Array(arr)) => {
+            if let Some(i) = last.get_int().and_then(|i| usize::try_from(i).ok()) {
+                if i < arr.len() {
+                    arr.remove(i);
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(DataValue::Json(JsonData(result)))
+}
+
+define_op!(OP_JSON_MERGE_PATCH, 2, false);
+/// Apply the second argument to the first as a JSON merge patch (RFC 7396):
+/// nulls in the patch remove keys, objects are merged recursively,
+/// and anything else replaces the target.
+pub(crate) fn op_json_merge_patch(args: &[DataValue]) -> Result<DataValue> {
+    fn merge_patch(target: JsonValue, patch: JsonValue) -> JsonValue {
+        match patch {
+            JsonValue::Object(patch) => {
+                let mut target = match target {
+                    JsonValue::Object(obj) => obj,
+                    _ => Default::default(),
+                };
+                for (key, value) in patch {
+                    if value.is_null() {
+                        target.remove(&key);
+                    } else {
+                        let old = target.remove(&key).unwrap_or(JsonValue::Null);
+                        target.insert(key, merge_patch(old, value));
+                    }
+                }
+                JsonValue::Object(target)
+            }
+            patch => patch,
+        }
+    }
+    Ok(DataValue::Json(JsonData(merge_patch(
+        to_json(&args[0]),
+        to_json(&args[1]),
+    ))))
+}
+
+define_op!(OP_JSONPATH, 2, false);
+pub(crate) fn op_jsonpath(args: &[DataValue]) -> Result<DataValue> {
+    let path = match &args[1] {
+        DataValue::Str(s) => JsonPath::parse(s)?,
+        _ => bail!("'jsonpath' requires a string as the path"),
+    };
+    let doc = to_json(&args[0]);
+    Ok(DataValue::List(
+        path.select(&doc)
+            .into_iter()
+            .map(|v| json2val(v.clone()))
+            .collect(),
+    ))
+}
+
 define_op!(OP_JSON_OBJECT, 0, true);
 pub(crate) fn op_json_object(args: &[DataValue]) -> Result<DataValue> {
     ensure!(
@@ -213,6 +365,12 @@ fn to_json(d: &DataValue) -> JsonValue {
                 json!(f)
             }
         },
+        DataValue::Decimal(d) => {
+            json!(d.to_string())
+        }
+        DataValue::Duration(d) => {
+            json!(format_duration(*d))
+        }
         DataValue::Str(s) => {
             json!(s)
         }
@@ -296,6 +454,9 @@ pub(crate) fn op_coalesce(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_EQ, 2, false);
 pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(res) = with_decimals(args, |ds| Ok(DataValue::from(ds[0] == ds[1])), op_eq) {
+        return res;
+    }
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
         | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 == *f,
@@ -321,6 +482,19 @@ pub(crate) fn op_json_to_scalar(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
+define_op!(OP_JSON_GET, 2, false);
+pub(crate) fn op_json_get(args: &[DataValue]) -> Result<DataValue> {
+    let json = to_json(&args[0]);
+    let path = match &args[1] {
+        DataValue::List(l) => l.as_slice(),
+        key => std::slice::from_ref(key),
+    };
+    Ok(match get_json_path_immutable(&json, path) {
+        Ok(v) => json2val(v.clone()),
+        Err(_) => DataValue::Null,
+    })
+}
+
 define_op!(OP_IS_IN, 2, false);
 pub(crate) fn op_is_in(args: &[DataValue]) -> Result<DataValue> {
     let left = &args[0];
@@ -332,6 +506,9 @@ pub(crate) fn op_is_in(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_NEQ, 2, false);
 pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(res) = with_decimals(args, |ds| Ok(DataValue::from(ds[0] != ds[1])), op_neq) {
+        return res;
+    }
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
         | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 != *f,
@@ -342,6 +519,9 @@ pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_GT, 2, false);
 pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
+    if let Some(res) = with_decimals(args, |ds| Ok(DataValue::from(ds[0] > ds[1])), op_gt) {
+        return res;
+    }
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l > *r as f64,
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 > *r,
@@ -352,6 +532,9 @@ pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_GE, 2, false);
 pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
+    if let Some(res) = with_decimals(args, |ds| Ok(DataValue::from(ds[0] >= ds[1])), op_ge) {
+        return res;
+    }
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l >= *r as f64,
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 >= *r,
@@ -362,6 +545,9 @@ pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_LT, 2, false);
 pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
+    if let Some(res) = with_decimals(args, |ds| Ok(DataValue::from(ds[0] < ds[1])), op_lt) {
+        return res;
+    }
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l < (*r as f64),
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) < *r,
@@ -372,6 +558,9 @@ pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_LE, 2, false);
 pub(crate) fn op_le(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
+    if let Some(res) = with_decimals(args, |ds| Ok(DataValue::from(ds[0] <= ds[1])), op_le) {
+        return res;
+    }
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l <= (*r as f64),
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) <= *r,
@@ -381,6 +570,16 @@ pub(crate) fn op_le(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_ADD, 0, true);
 pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
+    if args.iter().any(|v| matches!(v, DataValue::Duration(_))) {
+        return add_durations(args);
+    }
+    if let Some(res) = with_decimals(
+        args,
+        |ds| Ok(DataValue::Decimal(ds.into_iter().sum())),
+        op_add,
+    ) {
+        return res;
+    }
     let mut i_accum = 0i64;
     let mut f_accum = 0.0f64;
     for arg in args {
@@ -398,6 +597,54 @@ pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+fn secs_to_micros(secs: f64) -> Result<i64> {
+    ensure!(
+        secs.is_finite(),
+        "cannot use {} seconds as a duration",
+        secs
+    );
+    Ok((secs * MICROS_PER_SEC as f64).round() as i64)
+}
+
+fn duration_overflow() -> miette::Report {
+    miette!("duration overflow")
+}
+
+/// Sums durations, possibly onto one timestamp: either a validity or a number of seconds,
+/// as given by `now()` and `parse_timestamp`.
+fn add_durations(args: &[DataValue]) -> Result<DataValue> {
+    let mut total = 0i64;
+    let mut base = None;
+    for arg in args {
+        match arg {
+            DataValue::Duration(d) => {
+                total = total.checked_add(*d).ok_or_else(duration_overflow)?
+            }
+            DataValue::Validity(_) | DataValue::Num(_) if base.is_none() => base = Some(arg),
+            _ => bail!("durations can only be added to durations and to one timestamp"),
+        }
+    }
+    shift_timestamp(base, total)
+}
+
+fn shift_timestamp(base: Option<&DataValue>, micros: i64) -> Result<DataValue> {
+    Ok(match base {
+        None => DataValue::Duration(micros),
+        Some(DataValue::Validity(vld)) => DataValue::Validity(Validity {
+            timestamp: ValidityTs(Reverse(
+                vld.timestamp
+                    .0
+                     .0
+                    .checked_add(micros)
+                    .ok_or_else(duration_overflow)?,
+            )),
+            is_assert: vld.is_assert,
+        }),
+        Some(DataValue::Num(n)) => DataValue::from(n.get_float() + micros as f64 / 1e6),
+        Some(v) => bail!("cannot shift {:?} by a duration", v),
+    })
+}
+
 fn add_vecs(args: &[DataValue]) -> Result<DataValue> {
     if args.len() == 1 {
         return Ok(args[0].clone());
@@ -452,6 +699,13 @@ fn add_vecs(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_MAX, 1, true);
 pub(crate) fn op_max(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(res) = with_decimals(
+        args,
+        |ds| Ok(DataValue::Decimal(ds.into_iter().max().unwrap())),
+        op_max,
+    ) {
+        return res;
+    }
     let res = args
         .iter()
         .try_fold(None, |accum, nxt| match (accum, nxt) {
@@ -467,6 +721,13 @@ pub(crate) fn op_max(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_MIN, 1, true);
 pub(crate) fn op_min(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(res) = with_decimals(
+        args,
+        |ds| Ok(DataValue::Decimal(ds.into_iter().min().unwrap())),
+        op_min,
+    ) {
+        return res;
+    }
     let res = args
         .iter()
         .try_fold(None, |accum, nxt| match (accum, nxt) {
@@ -482,6 +743,29 @@ pub(crate) fn op_min(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_SUB, 2, false);
 pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
+    match (&args[0], &args[1]) {
+        (DataValue::Duration(a), DataValue::Duration(b)) => {
+            return Ok(DataValue::Duration(
+                a.checked_sub(*b).ok_or_else(duration_overflow)?,
+            ));
+        }
+        (a @ (DataValue::Validity(_) | DataValue::Num(_)), DataValue::Duration(d)) => {
+            return shift_timestamp(Some(a), d.checked_neg().ok_or_else(duration_overflow)?);
+        }
+        (DataValue::Validity(a), DataValue::Validity(b)) => {
+            return Ok(DataValue::Duration(
+                a.timestamp
+                    .0
+                     .0
+                    .checked_sub(b.timestamp.0 .0)
+                    .ok_or_else(duration_overflow)?,
+            ));
+        }
+        _ => {}
+    }
+    if let Some(res) = with_decimals(args, |ds| Ok(DataValue::Decimal(&ds[0] - &ds[1])), op_sub) {
+        return res;
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Int(*a - *b))
@@ -543,6 +827,20 @@ pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_MUL, 0, true);
 pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
+    if args.iter().any(|v| matches!(v, DataValue::Duration(_))) {
+        return mul_duration(args);
+    }
+    if let Some(res) = with_decimals(
+        args,
+        |ds| {
+            Ok(DataValue::Decimal(
+                ds.into_iter().fold(BigDecimal::from(1), |acc, d| acc * d),
+            ))
+        },
+        op_mul,
+    ) {
+        return res;
+    }
     let mut i_accum = 1i64;
     let mut f_accum = 1.0f64;
     for arg in args {
@@ -560,6 +858,23 @@ pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+fn mul_duration(args: &[DataValue]) -> Result<DataValue> {
+    let mut duration = None;
+    let mut factor = DataValue::from(1);
+    for arg in args {
+        match arg {
+            DataValue::Duration(d) if duration.is_none() => duration = Some(*d),
+            DataValue::Num(_) => factor = op_mul(&[factor, arg.clone()])?,
+            _ => bail!("durations can only be multiplied by numbers"),
+        }
+    }
+    let d = duration.unwrap();
+    Ok(DataValue::Duration(match factor {
+        DataValue::Num(Num::Int(i)) => d.checked_mul(i).ok_or_else(duration_overflow)?,
+        f => secs_to_micros(d as f64 * f.get_float().unwrap() / 1e6)?,
+    }))
+}
+
 fn mul_vecs(args: &[DataValue]) -> Result<DataValue> {
     if args.len() == 1 {
         return Ok(args[0].clone());
@@ -614,6 +929,30 @@ fn mul_vecs(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_DIV, 2, false);
 pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
+    match (&args[0], &args[1]) {
+        (DataValue::Duration(a), DataValue::Duration(b)) => {
+            return Ok(DataValue::from(*a as f64 / *b as f64));
+        }
+        (DataValue::Duration(d), DataValue::Num(n)) => {
+            return Ok(DataValue::Duration(match n {
+                Num::Int(i) => d
+                    .checked_div(*i)
+                    .ok_or_else(|| miette!("division of a duration by zero"))?,
+                Num::Float(f) => secs_to_micros(*d as f64 / f / 1e6)?,
+            }));
+        }
+        _ => {}
+    }
+    if let Some(res) = with_decimals(
+        args,
+        |ds| {
+            ensure!(!ds[1].is_zero(), "division of decimals by zero");
+            Ok(DataValue::Decimal(&ds[0] / &ds[1]))
+        },
+        op_div,
+    ) {
+        return res;
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float((*a as f64) / (*b as f64)))
@@ -672,6 +1011,10 @@ pub(crate) fn op_minus(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(-(*i))),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(-(*f))),
+        DataValue::Decimal(d) => DataValue::Decimal(-d),
+        DataValue::Duration(d) => {
+            DataValue::Duration(d.checked_neg().ok_or_else(duration_overflow)?)
+        }
         DataValue::Vec(Vector::F64(v)) => DataValue::Vec(Vector::F64(0. - v)),
         DataValue::Vec(Vector::F32(v)) => DataValue::Vec(Vector::F32(0. - v)),
         _ => bail!("minus can only be applied to numbers"),
@@ -683,6 +1026,10 @@ pub(crate) fn op_abs(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(i.abs())),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.abs())),
+        DataValue::Decimal(d) => DataValue::Decimal(d.abs()),
+        DataValue::Duration(d) => {
+            DataValue::Duration(d.checked_abs().ok_or_else(duration_overflow)?)
+        }
         DataValue::Vec(Vector::F64(v)) => DataValue::Vec(Vector::F64(v.mapv(|x| x.abs()))),
         DataValue::Vec(Vector::F32(v)) => DataValue::Vec(Vector::F32(v.mapv(|x| x.abs()))),
         _ => bail!("'abs' requires numbers"),
@@ -704,6 +1051,13 @@ pub(crate) fn op_signum(args: &[DataValue]) -> Result<DataValue> {
                 DataValue::from(f64::NAN)
             }
         }
+        DataValue::Decimal(d) => DataValue::from(if d.is_zero() {
+            0
+        } else if d.is_negative() {
+            -1
+        } else {
+            1
+        }),
         _ => bail!("'signum' requires numbers"),
     })
 }
@@ -713,6 +1067,7 @@ pub(crate) fn op_floor(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.floor())),
+        DataValue::Decimal(d) => DataValue::Decimal(d.with_scale_round(0, RoundingMode::Floor)),
         _ => bail!("'floor' requires numbers"),
     })
 }
@@ -722,6 +1077,7 @@ pub(crate) fn op_ceil(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.ceil())),
+        DataValue::Decimal(d) => DataValue::Decimal(d.with_scale_round(0, RoundingMode::Ceiling)),
         _ => bail!("'ceil' requires numbers"),
     })
 }
@@ -731,6 +1087,7 @@ pub(crate) fn op_round(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.round())),
+        DataValue::Decimal(d) => DataValue::Decimal(d.with_scale_round(0, RoundingMode::HalfUp)),
         _ => bail!("'round' requires numbers"),
     })
 }
@@ -1068,6 +1425,16 @@ pub(crate) fn op_pow(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_MOD, 2, false);
 pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(res) = with_decimals(
+        args,
+        |ds| {
+            ensure!(!ds[1].is_zero(), "'mod' requires non-zero divisor");
+            Ok(DataValue::Decimal(&ds[0] % &ds[1]))
+        },
+        op_mod,
+    ) {
+        return res;
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             if *b == 0 {
@@ -1414,7 +1781,7 @@ pub(crate) fn op_regex_replace_all(args: &[DataValue]) -> Result<DataValue> {
         (DataValue::Str(s), DataValue::Regex(r), DataValue::Str(rp)) => {
             Ok(DataValue::Str(r.0.replace_all(s, rp as &str).into()))
         }
-        _ => bail!("'regex_replace' requires strings"),
+        _ => bail!("'regex_replace_all' requires strings"),
     }
 }
 
@@ -1443,6 +1810,107 @@ pub(crate) fn op_regex_extract_first(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+#[cfg(feature = "fancy-regex")]
+pub(crate) use fancy::*;
+
+/// Variants of the regex functions taking patterns for `fancy-regex`,
+/// which supports lookaround and backreferences at the cost of backtracking.
+#[cfg(feature = "fancy-regex")]
+mod fancy {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const MAX_CACHED_PATTERNS: usize = 256;
+
+    thread_local! {
+        static FANCY_REGEXES: RefCell<HashMap<String, fancy_regex::Regex>> = RefCell::new(HashMap::new());
+    }
+
+    /// Patterns are compiled once per thread, as there is no value type for them.
+    fn with_fancy_regex<T>(
+        name: &str,
+        args: &[DataValue],
+        f: impl FnOnce(&str, &fancy_regex::Regex) -> Result<T>,
+    ) -> Result<T> {
+        let (s, pattern) = match (&args[0], &args[1]) {
+            (DataValue::Str(s), DataValue::Str(p)) => (s, p),
+            _ => bail!("'{}' requires strings", name),
+        };
+        let re = FANCY_REGEXES.with(|cache| -> Result<fancy_regex::Regex> {
+            let mut cache = cache.borrow_mut();
+            if let Some(re) = cache.get(pattern as &str) {
+                return Ok(re.clone());
+            }
+            let re = fancy_regex::Regex::new(pattern)
+                .map_err(|err| miette!("The string cannot be interpreted as regex: {}", err))?;
+            if cache.len() >= MAX_CACHED_PATTERNS {
+                cache.clear();
+            }
+            cache.insert(pattern.to_string(), re.clone());
+            Ok(re)
+        })?;
+        f(s, &re)
+    }
+
+    fn fancy_err(err: fancy_regex::Error) -> miette::Report {
+        miette!("regex matching failed: {}", err)
+    }
+
+    define_op!(OP_REGEX_MATCHES_FANCY, 2, false);
+    pub(crate) fn op_regex_matches_fancy(args: &[DataValue]) -> Result<DataValue> {
+        with_fancy_regex("regex_matches_fancy", args, |s, re| {
+            Ok(DataValue::from(re.is_match(s).map_err(fancy_err)?))
+        })
+    }
+
+    define_op!(OP_REGEX_REPLACE_FANCY, 3, false);
+    pub(crate) fn op_regex_replace_fancy(args: &[DataValue]) -> Result<DataValue> {
+        let rp = args[2]
+            .get_str()
+            .ok_or_else(|| miette!("'regex_replace_fancy' requires strings"))?;
+        with_fancy_regex("regex_replace_fancy", args, |s, re| {
+            Ok(DataValue::from(
+                re.try_replacen(s, 1, rp).map_err(fancy_err)?.as_ref(),
+            ))
+        })
+    }
+
+    define_op!(OP_REGEX_REPLACE_ALL_FANCY, 3, false);
+    pub(crate) fn op_regex_replace_all_fancy(args: &[DataValue]) -> Result<DataValue> {
+        let rp = args[2]
+            .get_str()
+            .ok_or_else(|| miette!("'regex_replace_all_fancy' requires strings"))?;
+        with_fancy_regex("regex_replace_all_fancy", args, |s, re| {
+            Ok(DataValue::from(
+                re.try_replacen(s, 0, rp).map_err(fancy_err)?.as_ref(),
+            ))
+        })
+    }
+
+    define_op!(OP_REGEX_EXTRACT_FANCY, 2, false);
+    pub(crate) fn op_regex_extract_fancy(args: &[DataValue]) -> Result<DataValue> {
+        with_fancy_regex("regex_extract_fancy", args, |s, re| {
+            let found: Vec<_> = re
+                .find_iter(s)
+                .map(|m| m.map(|m| DataValue::from(m.as_str())).map_err(fancy_err))
+                .try_collect()?;
+            Ok(DataValue::List(found))
+        })
+    }
+
+    define_op!(OP_REGEX_EXTRACT_FIRST_FANCY, 2, false);
+    pub(crate) fn op_regex_extract_first_fancy(args: &[DataValue]) -> Result<DataValue> {
+        with_fancy_regex("regex_extract_first_fancy", args, |s, re| {
+            let found = re.find(s).map_err(fancy_err)?;
+            Ok(found
+                .map(|m| DataValue::from(m.as_str()))
+                .unwrap_or(DataValue::Null))
+        })
+    }
+}
+
 define_op!(OP_T2S, 1, false);
 fn op_t2s(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
@@ -1476,10 +1944,20 @@ define_op!(OP_IS_NUM, 1, false);
 pub(crate) fn op_is_num(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(
         args[0],
-        DataValue::Num(Num::Int(_)) | DataValue::Num(Num::Float(_))
+        DataValue::Num(Num::Int(_)) | DataValue::Num(Num::Float(_)) | DataValue::Decimal(_)
     )))
 }
 
+define_op!(OP_IS_DURATION, 1, false);
+pub(crate) fn op_is_duration(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(matches!(args[0], DataValue::Duration(_))))
+}
+
+define_op!(OP_IS_DECIMAL, 1, false);
+pub(crate) fn op_is_decimal(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(matches!(args[0], DataValue::Decimal(_))))
+}
+
 define_op!(OP_IS_FINITE, 1, false);
 pub(crate) fn op_is_finite(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
@@ -1638,6 +2116,106 @@ pub(crate) fn op_haversine_deg_input(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(ret))
 }
 
+define_op!(OP_GEO_POINT, 2, false);
+pub(crate) fn op_geo_point(args: &[DataValue]) -> Result<DataValue> {
+    let point = DataValue::List(vec![
+        DataValue::from(
+            args[0]
+                .get_float()
+                .ok_or_else(|| miette!("'geo_point' requires numbers"))?,
+        ),
+        DataValue::from(
+            args[1]
+                .get_float()
+                .ok_or_else(|| miette!("'geo_point' requires numbers"))?,
+        ),
+    ]);
+    ensure!(
+        get_point(&point).is_some(),
+        "'geo_point' requires a latitude within [-90, 90] and a longitude within [-180, 180]"
+    );
+    Ok(point)
+}
+
+fn geo_point_arg(name: &str, arg: &DataValue) -> Result<(f64, f64)> {
+    get_point(arg).ok_or_else(|| miette!("'{}' requires points [lat, lon], got {:?}", name, arg))
+}
+
+define_op!(OP_HAVERSINE_KM, 2, false);
+pub(crate) fn op_haversine_km(args: &[DataValue]) -> Result<DataValue> {
+    let (lat1, lon1) = geo_point_arg("haversine_km", &args[0])?;
+    let (lat2, lon2) = geo_point_arg("haversine_km", &args[1])?;
+    Ok(DataValue::from(haversine_km(lat1, lon1, lat2, lon2)))
+}
+
+define_op!(OP_GEO_BBOX, 2, false);
+pub(crate) fn op_geo_bbox(args: &[DataValue]) -> Result<DataValue> {
+    let (lat, lon) = geo_point_arg("geo_bbox", &args[0])?;
+    let radius = args[1]
+        .get_float()
+        .filter(|r| *r >= 0.)
+        .ok_or_else(|| miette!("'geo_bbox' requires a non-negative radius"))?;
+    let ((lat_min, lat_max), (lon_min, lon_max)) = bounding_box(lat, lon, radius);
+    Ok(DataValue::List(vec![
+        DataValue::List(vec![DataValue::from(lat_min), DataValue::from(lon_min)]),
+        DataValue::List(vec![DataValue::from(lat_max), DataValue::from(lon_max)]),
+    ]))
+}
+
+define_op!(OP_GEO_CONTAINS, 2, false);
+pub(crate) fn op_geo_contains(args: &[DataValue]) -> Result<DataValue> {
+    let region = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("'geo_contains' requires a bounding box or a polygon"))?
+        .iter()
+        .map(|p| geo_point_arg("geo_contains", p))
+        .collect::<Result<Vec<_>>>()?;
+    let (lat, lon) = geo_point_arg("geo_contains", &args[1])?;
+    Ok(DataValue::from(match region.len() {
+        2 => bbox_contains(
+            ((region[0].0, region[1].0), (region[0].1, region[1].1)),
+            lat,
+            lon,
+        ),
+        n if n > 2 => polygon_contains(&region, lat, lon),
+        _ => bail!("'geo_contains' requires a bounding box or a polygon"),
+    }))
+}
+
+define_op!(OP_GEOHASH_ENCODE, 1, true);
+pub(crate) fn op_geohash_encode(args: &[DataValue]) -> Result<DataValue> {
+    let (lat, lon) = geo_point_arg("geohash_encode", &args[0])?;
+    let precision = match args.get(1) {
+        None => MAX_GEOHASH_PRECISION as i64,
+        Some(v) => v
+            .get_int()
+            .ok_or_else(|| miette!("'geohash_encode' requires an integer precision"))?,
+    };
+    ensure!(
+        (1..=MAX_GEOHASH_PRECISION as i64).contains(&precision),
+        "'geohash_encode' requires a precision between 1 and {}",
+        MAX_GEOHASH_PRECISION
+    );
+    Ok(DataValue::from(geohash_encode(
+        lat,
+        lon,
+        precision as usize,
+    )))
+}
+
+define_op!(OP_GEOHASH_DECODE, 1, false);
+pub(crate) fn op_geohash_decode(args: &[DataValue]) -> Result<DataValue> {
+    let hash = args[0]
+        .get_str()
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| miette!("'geohash_decode' requires a non-empty string"))?;
+    let ((lat_min, lat_max), (lon_min, lon_max)) = geohash_decode(hash)?;
+    Ok(DataValue::List(vec![
+        DataValue::from((lat_min + lat_max) / 2.),
+        DataValue::from((lon_min + lon_max) / 2.),
+    ]))
+}
+
 define_op!(OP_DEG_TO_RAD, 1, false);
 pub(crate) fn op_deg_to_rad(args: &[DataValue]) -> Result<DataValue> {
     let x = args[0]
@@ -1722,6 +2300,52 @@ pub(crate) fn op_windows(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(res))
 }
 
+define_op!(OP_ZIP, 2, true);
+pub(crate) fn op_zip(args: &[DataValue]) -> Result<DataValue> {
+    let lists: Vec<_> = args
+        .iter()
+        .map(|arg| {
+            arg.get_slice()
+                .ok_or_else(|| miette!("all arguments of 'zip' must be lists"))
+        })
+        .try_collect()?;
+    // as long as the shortest list
+    let len = lists.iter().map(|l| l.len()).min().unwrap_or(0);
+    let res = (0..len)
+        .map(|i| DataValue::List(lists.iter().map(|l| l[i].clone()).collect()))
+        .collect_vec();
+    Ok(DataValue::List(res))
+}
+
+define_op!(OP_ENUMERATE, 1, false);
+pub(crate) fn op_enumerate(args: &[DataValue]) -> Result<DataValue> {
+    let arg = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("'enumerate' requires a list"))?;
+    let res = arg
+        .iter()
+        .enumerate()
+        .map(|(i, el)| DataValue::List(vec![DataValue::from(i as i64), el.clone()]))
+        .collect_vec();
+    Ok(DataValue::List(res))
+}
+
+define_op!(OP_LIST_UNIQUE, 1, false);
+pub(crate) fn op_list_unique(args: &[DataValue]) -> Result<DataValue> {
+    let arg = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("'list_unique' requires a list"))?;
+    // the first occurrences are kept, in order
+    #[allow(clippy::mutable_key_type)]
+    let mut seen = BTreeSet::new();
+    let res = arg
+        .iter()
+        .filter(|el| seen.insert(*el))
+        .cloned()
+        .collect_vec();
+    Ok(DataValue::List(res))
+}
+
 fn get_index(mut i: i64, total: usize, is_upper: bool) -> Result<usize> {
     if i < 0 {
         i += total as i64;
@@ -1844,6 +2468,134 @@ pub(crate) fn op_chars(args: &[DataValue]) -> Result<DataValue> {
     ))
 }
 
+define_op!(OP_SPLIT_N, 3, false);
+pub(crate) fn op_split_n(args: &[DataValue]) -> Result<DataValue> {
+    let s = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("first argument of 'split_n' must be a string"))?;
+    let sep = args[1]
+        .get_str()
+        .ok_or_else(|| miette!("second argument of 'split_n' must be a string"))?;
+    let n = args[2]
+        .get_int()
+        .ok_or_else(|| miette!("third argument of 'split_n' must be an integer"))?;
+    ensure!(n > 0, "third argument to 'split_n' must be positive");
+    // the last part is the rest of the string
+    Ok(DataValue::List(
+        s.splitn(n as usize, sep).map(DataValue::from).collect_vec(),
+    ))
+}
+
+define_op!(OP_LEVENSHTEIN, 2, false);
+/// The number of single character insertions, deletions and substitutions
+/// turning one string into the other.
+pub(crate) fn op_levenshtein(args: &[DataValue]) -> Result<DataValue> {
+    let (a, b) = match (args[0].get_str(), args[1].get_str()) {
+        (Some(a), Some(b)) => (a.chars().collect_vec(), b.chars().collect_vec()),
+        _ => bail!("'levenshtein' requires strings"),
+    };
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    Ok(DataValue::from(prev[b.len()] as i64))
+}
+
+define_op!(OP_JARO_WINKLER, 2, false);
+/// Jaro-Winkler similarity, between 0 and 1, with the usual prefix scale of 0.1
+/// over at most four characters.
+pub(crate) fn op_jaro_winkler(args: &[DataValue]) -> Result<DataValue> {
+    let (a, b) = match (args[0].get_str(), args[1].get_str()) {
+        (Some(a), Some(b)) => (a.chars().collect_vec(), b.chars().collect_vec()),
+        _ => bail!("'jaro_winkler' requires strings"),
+    };
+    if a.is_empty() && b.is_empty() {
+        return Ok(DataValue::from(1.));
+    }
+    if a.is_empty() || b.is_empty() {
+        return Ok(DataValue::from(0.));
+    }
+    let window = (a.len().max(b.len()) / 2).saturating_sub(1);
+    let mut a_matched = vec![false; a.len()];
+    let mut b_matched = vec![false; b.len()];
+    let mut matches = 0;
+    for (i, ca) in a.iter().enumerate() {
+        let lo = i.saturating_sub(window);
+        let hi = (i + window + 1).min(b.len());
+        for j in lo..hi {
+            if !b_matched[j] && b[j] == *ca {
+                a_matched[i] = true;
+                b_matched[j] = true;
+                matches += 1;
+                break;
+            }
+        }
+    }
+    if matches == 0 {
+        return Ok(DataValue::from(0.));
+    }
+    let a_seq = a.iter().zip(&a_matched).filter(|(_, m)| **m);
+    let b_seq = b.iter().zip(&b_matched).filter(|(_, m)| **m);
+    let transpositions = a_seq.zip(b_seq).filter(|((x, _), (y, _))| x != y).count() / 2;
+    let m = matches as f64;
+    let jaro = (m / a.len() as f64 + m / b.len() as f64 + (m - transpositions as f64) / m) / 3.;
+    let prefix = a.iter().zip(&b).take(4).take_while(|(x, y)| x == y).count();
+    Ok(DataValue::from(jaro + prefix as f64 * 0.1 * (1. - jaro)))
+}
+
+define_op!(OP_SOUNDEX, 1, false);
+/// American Soundex: the first letter followed by three digits coding the consonants.
+/// Characters other than ASCII letters are ignored.
+pub(crate) fn op_soundex(args: &[DataValue]) -> Result<DataValue> {
+    fn code(c: char) -> Option<char> {
+        match c {
+            'B' | 'F' | 'P' | 'V' => Some('1'),
+            'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => Some('2'),
+            'D' | 'T' => Some('3'),
+            'L' => Some('4'),
+            'M' | 'N' => Some('5'),
+            'R' => Some('6'),
+            _ => None,
+        }
+    }
+    let s = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("'soundex' requires strings"))?;
+    let mut letters = s
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase());
+    let first = match letters.next() {
+        Some(c) => c,
+        None => return Ok(DataValue::from("")),
+    };
+    let mut ret = String::from(first);
+    let mut last = code(first);
+    for c in letters {
+        if ret.len() == 4 {
+            break;
+        }
+        let cur = code(c);
+        if let Some(digit) = cur.filter(|_| cur != last) {
+            ret.push(digit);
+        }
+        // H and W do not separate letters with the same code, vowels do
+        if c != 'H' && c != 'W' {
+            last = cur;
+        }
+    }
+    while ret.len() < 4 {
+        ret.push('0');
+    }
+    Ok(DataValue::from(ret))
+}
+
 define_op!(OP_SLICE_STRING, 3, false);
 pub(crate) fn op_slice_string(args: &[DataValue]) -> Result<DataValue> {
     let s = args[0]
@@ -1922,6 +2674,8 @@ pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
         DataValue::Null => false,
         DataValue::Bool(b) => *b,
         DataValue::Num(n) => n.get_int() != Some(0),
+        DataValue::Decimal(d) => !d.is_zero(),
+        DataValue::Duration(d) => *d != 0,
         DataValue::Str(s) => !s.is_empty(),
         DataValue::Bytes(b) => !b.is_empty(),
         DataValue::Uuid(u) => !u.0.is_nil(),
@@ -1948,6 +2702,8 @@ pub(crate) fn op_to_unity(args: &[DataValue]) -> Result<DataValue> {
         DataValue::Null => 0,
         DataValue::Bool(b) => *b as i64,
         DataValue::Num(n) => (n.get_float() != 0.) as i64,
+        DataValue::Decimal(d) => i64::from(!d.is_zero()),
+        DataValue::Duration(d) => i64::from(*d != 0),
         DataValue::Str(s) => i64::from(!s.is_empty()),
         DataValue::Bytes(b) => i64::from(!b.is_empty()),
         DataValue::Uuid(u) => i64::from(!u.0.is_nil()),
@@ -1978,6 +2734,11 @@ pub(crate) fn op_to_int(args: &[DataValue]) -> Result<DataValue> {
             }
             Some(i) => DataValue::Num(Num::Int(i)),
         },
+        DataValue::Decimal(d) => d
+            .with_scale_round(0, RoundingMode::Down)
+            .to_i64()
+            .ok_or_else(|| miette!("The decimal {} is too large for an int", d))?
+            .into(),
         DataValue::Null => DataValue::from(0),
         DataValue::Bool(b) => DataValue::from(if *b { 1 } else { 0 }),
         DataValue::Str(t) => {
@@ -1987,6 +2748,8 @@ pub(crate) fn op_to_int(args: &[DataValue]) -> Result<DataValue> {
                 .into()
         }
         DataValue::Validity(vld) => DataValue::Num(Num::Int(vld.timestamp.0 .0)),
+        // durations convert to and from numbers of seconds
+        DataValue::Duration(d) => DataValue::from(*d / MICROS_PER_SEC),
         v => bail!("'to_int' does not recognize {:?}", v),
     })
 }
@@ -1995,6 +2758,8 @@ define_op!(OP_TO_FLOAT, 1, false);
 pub(crate) fn op_to_float(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(n) => n.get_float().into(),
+        DataValue::Decimal(_) => args[0].get_float().unwrap().into(),
+        DataValue::Duration(d) => (*d as f64 / 1e6).into(),
         DataValue::Null => DataValue::from(0.0),
         DataValue::Bool(b) => DataValue::from(if *b { 1.0 } else { 0.0 }),
         DataValue::Str(t) => match t as &str {
@@ -2011,14 +2776,63 @@ pub(crate) fn op_to_float(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
+/// The value as a decimal: ints and floats are converted exactly as they are written,
+/// strings are parsed.
+pub(crate) fn to_decimal(v: &DataValue) -> Result<BigDecimal> {
+    Ok(match v {
+        DataValue::Num(Num::Int(i)) => BigDecimal::from(*i),
+        DataValue::Num(Num::Float(f)) => {
+            ensure!(f.is_finite(), "cannot convert {} to a decimal", f);
+            BigDecimal::from_str(&f.to_string()).into_diagnostic()?
+        }
+        DataValue::Decimal(d) => d.clone(),
+        DataValue::Str(s) => BigDecimal::from_str(s.trim())
+            .map_err(|_| miette!("The string cannot be interpreted as decimal"))?,
+        v => bail!("'to_decimal' does not recognize {:?}", v),
+    })
+}
+
+define_op!(OP_TO_DECIMAL, 1, true);
+pub(crate) fn op_to_decimal(args: &[DataValue]) -> Result<DataValue> {
+    let d = to_decimal(&args[0])?;
+    Ok(DataValue::Decimal(match args.get(1) {
+        None => d,
+        Some(scale) => {
+            let scale = scale
+                .get_int()
+                .ok_or_else(|| miette!("'to_decimal' requires an integer scale"))?;
+            d.with_scale_round(scale, RoundingMode::HalfUp)
+        }
+    }))
+}
+
+define_op!(OP_TO_BIGINT, 1, false);
+pub(crate) fn op_to_bigint(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Decimal(
+        to_decimal(&args[0])?.with_scale_round(0, RoundingMode::Down),
+    ))
+}
+
+define_op!(OP_TO_DURATION, 1, false);
+pub(crate) fn op_to_duration(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Duration(match &args[0] {
+        DataValue::Duration(d) => *d,
+        DataValue::Str(s) => parse_duration(s)?,
+        DataValue::Num(n) => secs_to_micros(n.get_float())?,
+        v => bail!("'to_duration' does not recognize {:?}", v),
+    }))
+}
+
 define_op!(OP_TO_STRING, 1, false);
 pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Str(val2str(&args[0]).into()))
 }
 
-fn val2str(arg: &DataValue) -> String {
+pub(crate) fn val2str(arg: &DataValue) -> String {
     match arg {
         DataValue::Str(s) => s.to_string(),
+        DataValue::Decimal(d) => d.to_string(),
+        DataValue::Duration(d) => format_duration(*d),
         DataValue::Json(JsonData(JsonValue::String(s))) => s.clone(),
         v => {
             let jv = to_json(v);
@@ -2131,7 +2945,7 @@ pub(crate) fn op_vec(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_RAND_VEC, 1, true);
+define_op!(OP_RAND_VEC, 1, true, impure);
 pub(crate) fn op_rand_vec(args: &[DataValue]) -> Result<DataValue> {
     let len = args[0]
         .get_int()
@@ -2146,8 +2960,7 @@ pub(crate) fn op_rand_vec(args: &[DataValue]) -> Result<DataValue> {
         _ => bail!("'vec' requires a string as second argument"),
     };
 
-    let mut rng = thread_rng();
-    match t {
+    with_rng(|rng| match t {
         VecElementType::F32 => {
             let mut res_arr = ndarray::Array1::zeros(len);
             for mut row in res_arr.axis_iter_mut(ndarray::Axis(0)) {
@@ -2162,7 +2975,7 @@ pub(crate) fn op_rand_vec(args: &[DataValue]) -> Result<DataValue> {
             }
             Ok(DataValue::Vec(Vector::F64(res_arr)))
         }
-    }
+    })
 }
 
 define_op!(OP_L2_NORMALIZE, 1, false);
@@ -2302,12 +3115,52 @@ pub(crate) fn op_int_range(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List((start..end).map(DataValue::from).collect()))
 }
 
-define_op!(OP_RAND_FLOAT, 0, false);
+thread_local! {
+    static SEEDED_RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Restores the previous random number generator of the thread when dropped.
+pub(crate) struct SeededRngGuard(Option<Option<StdRng>>);
+
+impl Drop for SeededRngGuard {
+    fn drop(&mut self) {
+        if let Some(prev) = self.0.take() {
+            SEEDED_RNG.with(|rng| *rng.borrow_mut() = prev);
+        }
+    }
+}
+
+/// Make the random functions evaluated on this thread deterministic until the guard is dropped.
+/// Does nothing if `seed` is `None`.
+pub(crate) fn seed_rng(seed: Option<u64>) -> SeededRngGuard {
+    let prev = seed
+        .map(|seed| SEEDED_RNG.with(|rng| rng.borrow_mut().replace(StdRng::seed_from_u64(seed))));
+    SeededRngGuard(prev)
+}
+
+/// Derive the seed of one part of a query from the seed of the whole query,
+/// so that the parts do not share a random stream.
+pub(crate) fn derive_seed(seed: u64, part: &str) -> u64 {
+    // FNV-1a, which unlike the hasher of the standard library is stable across releases
+    part.bytes().fold(seed ^ 0xcbf29ce484222325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Run `f` with the seeded generator of the thread if there is one, otherwise with `thread_rng()`.
+pub(crate) fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    SEEDED_RNG.with(|rng| match rng.borrow_mut().as_mut() {
+        Some(rng) => f(rng),
+        None => f(&mut thread_rng()),
+    })
+}
+
+define_op!(OP_RAND_FLOAT, 0, false, impure);
 pub(crate) fn op_rand_float(_args: &[DataValue]) -> Result<DataValue> {
-    Ok(thread_rng().gen::<f64>().into())
+    Ok(with_rng(|rng| rng.gen::<f64>()).into())
 }
 
-define_op!(OP_RAND_BERNOULLI, 1, false);
+define_op!(OP_RAND_BERNOULLI, 1, false, impure);
 pub(crate) fn op_rand_bernoulli(args: &[DataValue]) -> Result<DataValue> {
     let prob = match &args[0] {
         DataValue::Num(n) => {
@@ -2320,10 +3173,10 @@ pub(crate) fn op_rand_bernoulli(args: &[DataValue]) -> Result<DataValue> {
         }
         _ => bail!("'rand_bernoulli' requires number between 0. and 1."),
     };
-    Ok(DataValue::from(thread_rng().gen_bool(prob)))
+    Ok(DataValue::from(with_rng(|rng| rng.gen_bool(prob))))
 }
 
-define_op!(OP_RAND_INT, 2, false);
+define_op!(OP_RAND_INT, 2, false, impure);
 pub(crate) fn op_rand_int(args: &[DataValue]) -> Result<DataValue> {
     let lower = &args[0]
         .get_int()
@@ -2331,23 +3184,17 @@ pub(crate) fn op_rand_int(args: &[DataValue]) -> Result<DataValue> {
     let upper = &args[1]
         .get_int()
         .ok_or_else(|| miette!("'rand_int' requires integers"))?;
-    Ok(thread_rng().gen_range(*lower..=*upper).into())
+    Ok(with_rng(|rng| rng.gen_range(*lower..=*upper)).into())
 }
 
-define_op!(OP_RAND_CHOOSE, 1, false);
+define_op!(OP_RAND_CHOOSE, 1, false, impure);
 pub(crate) fn op_rand_choose(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
-        DataValue::List(l) => Ok(l
-            .choose(&mut thread_rng())
-            .cloned()
-            .unwrap_or(DataValue::Null)),
-        DataValue::Set(l) => Ok(l
-            .iter()
-            .collect_vec()
-            .choose(&mut thread_rng())
-            .cloned()
-            .cloned()
-            .unwrap_or(DataValue::Null)),
+        DataValue::List(l) => Ok(with_rng(|rng| l.choose(rng).cloned()).unwrap_or(DataValue::Null)),
+        DataValue::Set(l) => {
+            let l = l.iter().collect_vec();
+            Ok(with_rng(|rng| l.choose(rng).cloned().cloned()).unwrap_or(DataValue::Null))
+        }
         _ => bail!("'rand_choice' requires lists"),
     }
 }
@@ -2438,7 +3285,7 @@ pub(crate) fn op_to_uuid(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_NOW, 0, false);
+define_op!(OP_NOW, 0, false, impure);
 #[cfg(target_arch = "wasm32")]
 pub(crate) fn op_now(_args: &[DataValue]) -> Result<DataValue> {
     let d: f64 = Date::now() / 1000.;
@@ -2524,10 +3371,9 @@ pub(crate) fn str2vld(s: &str) -> Result<ValidityTs> {
     Ok(ValidityTs(Reverse(microseconds as i64)))
 }
 
-define_op!(OP_RAND_UUID_V1, 0, false);
+define_op!(OP_RAND_UUID_V1, 0, false, impure);
 pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
-    let mut rng = rand::thread_rng();
-    let uuid_ctx = uuid::v1::Context::new(rng.gen());
+    let uuid_ctx = uuid::v1::Context::new(with_rng(|rng| rng.gen()));
     #[cfg(target_arch = "wasm32")]
     let ts = {
         let since_epoch: f64 = Date::now();
@@ -2542,17 +3388,27 @@ pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
         Timestamp::from_unix(uuid_ctx, since_epoch.as_secs(), since_epoch.subsec_nanos())
     };
     let mut rand_vals = [0u8; 6];
-    rng.fill(&mut rand_vals);
+    with_rng(|rng| rng.fill(&mut rand_vals));
     let id = uuid::Uuid::new_v1(ts, &rand_vals);
     Ok(DataValue::uuid(id))
 }
 
-define_op!(OP_RAND_UUID_V4, 0, false);
+define_op!(OP_RAND_UUID_V4, 0, false, impure);
 pub(crate) fn op_rand_uuid_v4(_args: &[DataValue]) -> Result<DataValue> {
-    let id = uuid::Uuid::new_v4();
+    let mut bytes = [0u8; 16];
+    with_rng(|rng| rng.fill(&mut bytes));
+    let id = uuid::Builder::from_random_bytes(bytes).into_uuid();
     Ok(DataValue::uuid(id))
 }
 
+define_op!(OP_GEN_ID, 1, false, impure);
+pub(crate) fn op_gen_id(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Str(name) => next_id(name),
+        _ => bail!("'gen_id' requires the name of an ID generator"),
+    }
+}
+
 define_op!(OP_UUID_TIMESTAMP, 1, false);
 pub(crate) fn op_uuid_timestamp(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
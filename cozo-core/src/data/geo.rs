@@ -0,0 +1,215 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Geospatial helpers. Points are lists `[lat, lon]` in degrees.
+//! Geohashes interleave the bits of the longitude and the latitude, starting with the longitude,
+//! so that the cells of a geohash are the geohashes having it as prefix.
+
+use std::f64::consts::PI;
+
+use miette::{bail, Result};
+
+use crate::data::value::DataValue;
+
+/// Mean radius of the Earth
+pub(crate) const EARTH_RADIUS_KM: f64 = 6371.0088;
+pub(crate) const MAX_GEOHASH_PRECISION: usize = 12;
+/// Radius searches scan at most that many cells, using larger cells for larger radii.
+const MAX_COVER_CELLS: usize = 64;
+
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// The latitude and the longitude of a point, if the value is one.
+pub(crate) fn get_point(v: &DataValue) -> Option<(f64, f64)> {
+    match v.get_slice()? {
+        [lat, lon] => {
+            let lat = lat.get_float()?;
+            let lon = lon.get_float()?;
+            if (-90. ..=90.).contains(&lat) && (-180. ..=180.).contains(&lon) {
+                Some((lat, lon))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lon1, lat2, lon2) = (
+        lat1.to_radians(),
+        lon1.to_radians(),
+        lat2.to_radians(),
+        lon2.to_radians(),
+    );
+    let a = ((lat1 - lat2) / 2.).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon1 - lon2) / 2.).sin().powi(2);
+    2. * EARTH_RADIUS_KM * a.sqrt().min(1.).asin()
+}
+
+pub(crate) fn geohash_encode(lat: f64, lon: f64, precision: usize) -> String {
+    let mut lat_range = (-90., 90.);
+    let mut lon_range = (-180., 180.);
+    let mut hash = String::with_capacity(precision);
+    let mut is_lon = true;
+    let mut bits = 0;
+    let mut ch = 0;
+    while hash.len() < precision {
+        let (range, v) = if is_lon {
+            (&mut lon_range, lon)
+        } else {
+            (&mut lat_range, lat)
+        };
+        let mid = (range.0 + range.1) / 2.;
+        ch <<= 1;
+        if v >= mid {
+            ch |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        is_lon = !is_lon;
+        bits += 1;
+        if bits == 5 {
+            hash.push(BASE32[ch] as char);
+            bits = 0;
+            ch = 0;
+        }
+    }
+    hash
+}
+
+/// The cell of a geohash, as `((lat_min, lat_max), (lon_min, lon_max))`.
+pub(crate) fn geohash_decode(hash: &str) -> Result<((f64, f64), (f64, f64))> {
+    let mut lat_range = (-90., 90.);
+    let mut lon_range = (-180., 180.);
+    let mut is_lon = true;
+    for c in hash.bytes() {
+        let ch = match BASE32.iter().position(|b| *b == c.to_ascii_lowercase()) {
+            Some(i) => i,
+            None => bail!("invalid character '{}' in geohash '{}'", c as char, hash),
+        };
+        for shift in (0..5).rev() {
+            let range = if is_lon {
+                &mut lon_range
+            } else {
+                &mut lat_range
+            };
+            let mid = (range.0 + range.1) / 2.;
+            if (ch >> shift) & 1 == 1 {
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            is_lon = !is_lon;
+        }
+    }
+    Ok((lat_range, lon_range))
+}
+
+/// The bounding box `((lat_min, lat_max), (lon_min, lon_max))` of the points within
+/// `radius_km` of a point. `lon_min > lon_max` when it crosses the antimeridian.
+pub(crate) fn bounding_box(lat: f64, lon: f64, radius_km: f64) -> ((f64, f64), (f64, f64)) {
+    let angle = radius_km / EARTH_RADIUS_KM;
+    let lat_min = lat - angle.to_degrees();
+    let lat_max = lat + angle.to_degrees();
+    if lat_min <= -90. || lat_max >= 90. || angle >= PI / 2. {
+        // a pole is within the radius
+        return ((lat_min.max(-90.), lat_max.min(90.)), (-180., 180.));
+    }
+    let d_lon = (angle.sin() / lat.to_radians().cos()).asin().to_degrees();
+    if d_lon.is_nan() || d_lon >= 180. {
+        return ((lat_min, lat_max), (-180., 180.));
+    }
+    let wrap = |l: f64| {
+        if l < -180. {
+            l + 360.
+        } else if l > 180. {
+            l - 360.
+        } else {
+            l
+        }
+    };
+    ((lat_min, lat_max), (wrap(lon - d_lon), wrap(lon + d_lon)))
+}
+
+/// Whether a point is within the bounding box `((lat_min, lat_max), (lon_min, lon_max))`.
+pub(crate) fn bbox_contains(bbox: ((f64, f64), (f64, f64)), lat: f64, lon: f64) -> bool {
+    let ((lat_min, lat_max), (lon_min, lon_max)) = bbox;
+    let lon_ok = if lon_min <= lon_max {
+        lon_min <= lon && lon <= lon_max
+    } else {
+        lon >= lon_min || lon <= lon_max
+    };
+    lat_min <= lat && lat <= lat_max && lon_ok
+}
+
+/// Whether a point is within a polygon given by its vertices, treating latitudes and longitudes
+/// as planar coordinates, which is fine for polygons not spanning large distances.
+pub(crate) fn polygon_contains(vertices: &[(f64, f64)], lat: f64, lon: f64) -> bool {
+    let mut inside = false;
+    let mut j = vertices.len() - 1;
+    for (i, &(lat_i, lon_i)) in vertices.iter().enumerate() {
+        let (lat_j, lon_j) = vertices[j];
+        if (lat_i > lat) != (lat_j > lat)
+            && lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Geohashes of at most `max_precision` characters whose cells together cover all points
+/// within `radius_km` of a point: the finest cells for which not too many are needed.
+pub(crate) fn covering_cells(
+    lat: f64,
+    lon: f64,
+    radius_km: f64,
+    max_precision: usize,
+) -> Vec<String> {
+    let ((lat_min, lat_max), (lon_min, lon_max)) = bounding_box(lat, lon, radius_km);
+    let lon_ranges = if lon_min <= lon_max {
+        vec![(lon_min, lon_max)]
+    } else {
+        vec![(lon_min, 180.), (-180., lon_max)]
+    };
+    let mut precision = max_precision.max(1);
+    loop {
+        let lat_bits = precision * 5 / 2;
+        let lon_bits = precision * 5 - lat_bits;
+        let n_rows = 1usize << lat_bits;
+        let n_cols = 1usize << lon_bits;
+        let height = 180. / n_rows as f64;
+        let width = 360. / n_cols as f64;
+        let row_of = |l: f64| (((l + 90.) / height) as usize).min(n_rows - 1);
+        let col_of = |l: f64| (((l + 180.) / width) as usize).min(n_cols - 1);
+        let rows = row_of(lat_min)..=row_of(lat_max);
+        let cols = lon_ranges
+            .iter()
+            .flat_map(|(lo, hi)| col_of(*lo)..=col_of(*hi))
+            .collect::<Vec<_>>();
+        if precision == 1 || rows.clone().count() * cols.len() <= MAX_COVER_CELLS {
+            let mut cells = vec![];
+            for row in rows {
+                for col in cols.iter() {
+                    cells.push(geohash_encode(
+                        -90. + (row as f64 + 0.5) * height,
+                        -180. + (*col as f64 + 0.5) * width,
+                        precision,
+                    ));
+                }
+            }
+            cells.sort();
+            cells.dedup();
+            return cells;
+        }
+        precision -= 1;
+    }
+}
@@ -11,6 +11,7 @@ use base64::Engine;
 use serde_json::json;
 pub(crate) use serde_json::Value as JsonValue;
 
+use crate::data::duration::format_duration;
 use crate::data::value::{DataValue, Num, Vector};
 use crate::JsonData;
 
@@ -73,6 +74,8 @@ impl From<DataValue> for JsonValue {
                     unreachable!()
                 }
             }
+            DataValue::Decimal(d) => JsonValue::String(d.to_string()),
+            DataValue::Duration(d) => JsonValue::String(format_duration(d)),
             DataValue::Str(t) => JsonValue::String(t.into()),
             DataValue::Bytes(bytes) => JsonValue::String(STANDARD.encode(bytes)),
             DataValue::List(l) => {
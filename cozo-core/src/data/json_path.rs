@@ -0,0 +1,205 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A subset of JSONPath: the root `$`, then any of `.key`, `['key']`, `[index]`
+//! (negative indices count from the end), the wildcards `.*` and `[*]`,
+//! and `..` for the descendants of a node, e.g. `..key`.
+
+use miette::{bail, miette, Result};
+
+use crate::data::json::JsonValue;
+use crate::data::value::DataValue;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Key(String),
+    Index(i64),
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Child(Selector),
+    /// The selector applied to the node and all its descendants
+    Descendant(Selector),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct JsonPath(Vec<Segment>);
+
+impl JsonPath {
+    pub(crate) fn parse(path: &str) -> Result<Self> {
+        let bad = |msg: &str| miette!("invalid JSON path '{}': {}", path, msg);
+        let chars: Vec<char> = path.trim().chars().collect();
+        if chars.first() != Some(&'$') {
+            return Err(bad("it must start with '$'"));
+        }
+        let mut segments = vec![];
+        let mut pos = 1;
+        while pos < chars.len() {
+            let descendant = chars[pos..].starts_with(&['.', '.']);
+            let selector = if descendant || chars[pos] == '.' {
+                pos += if descendant { 2 } else { 1 };
+                match chars.get(pos) {
+                    Some('[') => {
+                        let (selector, next) = parse_bracket(&chars, pos).map_err(bad)?;
+                        pos = next;
+                        selector
+                    }
+                    Some('*') => {
+                        pos += 1;
+                        Selector::Wildcard
+                    }
+                    _ => {
+                        let start = pos;
+                        while pos < chars.len()
+                            && (chars[pos].is_alphanumeric()
+                                || chars[pos] == '_'
+                                || chars[pos] == '-')
+                        {
+                            pos += 1;
+                        }
+                        if start == pos {
+                            return Err(bad("a key is expected after '.'"));
+                        }
+                        Selector::Key(chars[start..pos].iter().collect())
+                    }
+                }
+            } else if chars[pos] == '[' {
+                let (selector, next) = parse_bracket(&chars, pos).map_err(bad)?;
+                pos = next;
+                selector
+            } else {
+                return Err(bad(&format!("unexpected '{}'", chars[pos])));
+            };
+            segments.push(if descendant {
+                Segment::Descendant(selector)
+            } else {
+                Segment::Child(selector)
+            });
+        }
+        Ok(Self(segments))
+    }
+
+    /// All the values selected from `root`, in document order.
+    pub(crate) fn select<'a>(&self, root: &'a JsonValue) -> Vec<&'a JsonValue> {
+        let mut current = vec![root];
+        for segment in &self.0 {
+            let mut next = vec![];
+            match segment {
+                Segment::Child(selector) => {
+                    for node in current {
+                        apply_selector(selector, node, &mut next);
+                    }
+                }
+                Segment::Descendant(selector) => {
+                    for node in current {
+                        let mut stack = vec![node];
+                        while let Some(node) = stack.pop() {
+                            apply_selector(selector, node, &mut next);
+                            match node {
+                                JsonValue::Object(obj) => stack.extend(obj.values().rev()),
+                                JsonValue::Array(arr) => stack.extend(arr.iter().rev()),
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            }
+            current = next;
+        }
+        current
+    }
+
+    /// The keys and indices of a path selecting a single location,
+    /// as taken by `json_set` and `json_remove`.
+    pub(crate) fn to_keys(&self) -> Result<Vec<DataValue>> {
+        self.0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Child(Selector::Key(k)) => Ok(DataValue::from(k as &str)),
+                Segment::Child(Selector::Index(i)) if *i >= 0 => Ok(DataValue::from(*i)),
+                _ => bail!(
+                    "the JSON path must select a single location: \
+                    wildcards, descendants and negative indices are not allowed"
+                ),
+            })
+            .collect()
+    }
+}
+
+/// Parse `[...]` starting at `pos`, returning the selector and the position after `]`.
+fn parse_bracket(
+    chars: &[char],
+    mut pos: usize,
+) -> std::result::Result<(Selector, usize), &'static str> {
+    pos += 1;
+    let selector = match chars.get(pos) {
+        Some('*') => {
+            pos += 1;
+            Selector::Wildcard
+        }
+        Some(quote @ ('\'' | '"')) => {
+            let quote = *quote;
+            pos += 1;
+            let mut key = String::new();
+            loop {
+                match chars.get(pos) {
+                    None => return Err("unterminated quoted key"),
+                    Some('\\') => {
+                        key.push(*chars.get(pos + 1).ok_or("unterminated quoted key")?);
+                        pos += 2;
+                    }
+                    Some(c) if *c == quote => {
+                        pos += 1;
+                        break;
+                    }
+                    Some(c) => {
+                        key.push(*c);
+                        pos += 1;
+                    }
+                }
+            }
+            Selector::Key(key)
+        }
+        _ => {
+            let start = pos;
+            if chars.get(pos) == Some(&'-') {
+                pos += 1;
+            }
+            while pos < chars.len() && chars[pos].is_ascii_digit() {
+                pos += 1;
+            }
+            let index: String = chars[start..pos].iter().collect();
+            Selector::Index(
+                index
+                    .parse()
+                    .map_err(|_| "an index, a quoted key or '*' is expected in brackets")?,
+            )
+        }
+    };
+    if chars.get(pos) != Some(&']') {
+        return Err("']' expected");
+    }
+    Ok((selector, pos + 1))
+}
+
+fn apply_selector<'a>(selector: &Selector, node: &'a JsonValue, out: &mut Vec<&'a JsonValue>) {
+    match (selector, node) {
+        (Selector::Key(k), JsonValue::Object(obj)) => out.extend(obj.get(k)),
+        (Selector::Index(i), JsonValue::Array(arr)) => {
+            let idx = if *i < 0 { arr.len() as i64 + i } else { *i };
+            if idx >= 0 {
+                out.extend(arr.get(idx as usize));
+            }
+        }
+        (Selector::Wildcard, JsonValue::Object(obj)) => out.extend(obj.values()),
+        (Selector::Wildcard, JsonValue::Array(arr)) => out.extend(arr.iter()),
+        _ => {}
+    }
+}
@@ -11,11 +11,14 @@ use std::collections::BTreeSet;
 use std::io::Write;
 use std::str::FromStr;
 
+use bigdecimal::num_bigint::{BigInt, Sign};
+use bigdecimal::BigDecimal;
 use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use regex::Regex;
 
 use crate::data::value::{
-    DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector,
+    decimal_approx_f64, DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs,
+    Vector,
 };
 
 const INIT_TAG: u8 = 0x00;
@@ -32,6 +35,7 @@ const LIST_TAG: u8 = 0x0A;
 const SET_TAG: u8 = 0x0B;
 const VLD_TAG: u8 = 0x0C;
 const JSON_TAG: u8 = 0x0D;
+const DURATION_TAG: u8 = 0x0E;
 const BOT_TAG: u8 = 0xFF;
 
 const VEC_F32: u8 = 0x01;
@@ -39,9 +43,14 @@ const VEC_F64: u8 = 0x02;
 
 const IS_FLOAT: u8 = 0b00010000;
 const IS_APPROX_INT: u8 = 0b00000100;
+const IS_DECIMAL: u8 = 0b00001000;
 const IS_EXACT_INT: u8 = 0b00000000;
 const EXACT_INT_BOUND: i64 = 0x20_0000_0000_0000;
 
+const DECIMAL_NEG: u8 = 0x01;
+const DECIMAL_ZERO: u8 = 0x02;
+const DECIMAL_POS: u8 = 0x03;
+
 pub(crate) trait MemCmpEncoder: Write {
     fn encode_datavalue(&mut self, v: &DataValue) {
         match v {
@@ -122,7 +131,50 @@ pub(crate) trait MemCmpEncoder: Write {
                 self.write_u8(!vld.is_assert.0 as u8).unwrap();
             }
             DataValue::Bot => self.write_u8(BOT_TAG).unwrap(),
+            DataValue::Decimal(d) => {
+                self.write_u8(NUM_TAG).unwrap();
+                self.encode_decimal(d);
+            }
+            DataValue::Duration(d) => {
+                self.write_u8(DURATION_TAG).unwrap();
+                self.write_u64::<BigEndian>(order_encode_i64(*d)).unwrap();
+            }
+        }
+    }
+    /// Decimals sort among the numbers by their approximate value, then by their exact value,
+    /// written as `0.d1d2...dn * 10^e` with the digits packed in pairs.
+    /// Negative decimals have all bytes after the sign flipped.
+    fn encode_decimal(&mut self, d: &BigDecimal) {
+        let u = order_encode_f64(decimal_approx_f64(d));
+        self.write_u64::<BigEndian>(u).unwrap();
+        self.write_u8(IS_DECIMAL).unwrap();
+        let (digits, scale) = d.normalized().into_bigint_and_exponent();
+        let sign = match digits.sign() {
+            Sign::NoSign => {
+                self.write_u8(DECIMAL_ZERO).unwrap();
+                return;
+            }
+            Sign::Minus => DECIMAL_NEG,
+            Sign::Plus => DECIMAL_POS,
+        };
+        let digits = digits.magnitude().to_str_radix(10);
+        let exponent = digits.len() as i64 - scale;
+        let mut body = Vec::with_capacity(digits.len() / 2 + 10);
+        body.write_u64::<BigEndian>(order_encode_i64(exponent))
+            .unwrap();
+        for pair in digits.as_bytes().chunks(2) {
+            let hi = pair[0] - b'0';
+            let lo = pair.get(1).map(|c| c - b'0').unwrap_or(0);
+            body.push(hi * 10 + lo + 1);
+        }
+        body.push(0);
+        if sign == DECIMAL_NEG {
+            for b in body.iter_mut() {
+                *b = !*b;
+            }
         }
+        self.write_u8(sign).unwrap();
+        self.write_all(&body).unwrap();
     }
     fn encode_num(&mut self, v: Num) {
         let f = v.get_float();
@@ -254,6 +306,39 @@ impl Num {
     }
 }
 
+fn decode_decimal(bs: &[u8]) -> (BigDecimal, &[u8]) {
+    let (sign, remaining) = bs.split_first().unwrap();
+    let flip = match *sign {
+        DECIMAL_ZERO => return (BigDecimal::default(), remaining),
+        DECIMAL_NEG => 0xFF,
+        _ => 0x00,
+    };
+    let (exp_bytes, mut remaining) = remaining.split_at(8);
+    let mut exp_bytes = BigEndian::read_u64(exp_bytes);
+    if flip != 0 {
+        exp_bytes = !exp_bytes;
+    }
+    let exponent = order_decode_i64(exp_bytes);
+    let mut digits = String::new();
+    loop {
+        let (b, rest) = remaining.split_first().unwrap();
+        remaining = rest;
+        let pair = *b ^ flip;
+        if pair == 0 {
+            break;
+        }
+        let pair = pair - 1;
+        digits.push((b'0' + pair / 10) as char);
+        digits.push((b'0' + pair % 10) as char);
+    }
+    let scale = digits.len() as i64 - exponent;
+    let mut digits = BigInt::parse_bytes(digits.as_bytes(), 10).unwrap();
+    if flip != 0 {
+        digits = -digits;
+    }
+    (BigDecimal::new(digits, scale).normalized(), remaining)
+}
+
 impl DataValue {
     pub(crate) fn decode_from_key(bs: &[u8]) -> (Self, &[u8]) {
         let (tag, remaining) = bs.split_first().unwrap();
@@ -262,6 +347,10 @@ impl DataValue {
             FALSE_TAG => (DataValue::from(false), remaining),
             TRUE_TAG => (DataValue::from(true), remaining),
             NUM_TAG => {
+                if remaining[8] == IS_DECIMAL {
+                    let (d, remaining) = decode_decimal(&remaining[9..]);
+                    return (DataValue::Decimal(d), remaining);
+                }
                 let (n, remaining) = Num::decode_from_key(remaining);
                 (DataValue::Num(n), remaining)
             }
@@ -335,6 +424,11 @@ impl DataValue {
                 )
             }
             BOT_TAG => (DataValue::Bot, remaining),
+            DURATION_TAG => {
+                let (d_bytes, rest) = remaining.split_at(8);
+                let d = order_decode_i64(BigEndian::read_u64(d_bytes));
+                (DataValue::Duration(d), rest)
+            }
             VEC_TAG => {
                 let (t_tag, remaining) = remaining.split_first().unwrap();
                 let (len_bytes, mut rest) = remaining.split_at(8);
@@ -366,6 +460,68 @@ impl DataValue {
             _ => unreachable!("{:?}", bs),
         }
     }
+    /// Skip over an encoded value without decoding it, returning the bytes after it.
+    pub(crate) fn skip_in_key(bs: &[u8]) -> &[u8] {
+        let (tag, remaining) = bs.split_first().unwrap();
+        match *tag {
+            NULL_TAG | FALSE_TAG | TRUE_TAG | BOT_TAG => remaining,
+            NUM_TAG => match remaining[8] {
+                IS_DECIMAL => skip_decimal(&remaining[9..]),
+                IS_APPROX_INT => &remaining[17..],
+                _ => &remaining[9..],
+            },
+            STR_TAG | JSON_TAG | BYTES_TAG | REGEX_TAG => skip_bytes(remaining),
+            UUID_TAG => &remaining[16..],
+            LIST_TAG | SET_TAG => {
+                let mut remaining = remaining;
+                while remaining[0] != INIT_TAG {
+                    remaining = DataValue::skip_in_key(remaining);
+                }
+                &remaining[1..]
+            }
+            VLD_TAG => &remaining[9..],
+            DURATION_TAG => &remaining[8..],
+            VEC_TAG => {
+                let (t_tag, remaining) = remaining.split_first().unwrap();
+                let (len_bytes, rest) = remaining.split_at(8);
+                let len = BigEndian::read_u64(len_bytes) as usize;
+                match *t_tag {
+                    VEC_F32 => &rest[len * 4..],
+                    VEC_F64 => &rest[len * 8..],
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!("{:?}", bs),
+        }
+    }
+}
+
+fn skip_bytes(data: &[u8]) -> &[u8] {
+    let mut remaining = data;
+    loop {
+        let (chunk, rest) = remaining.split_at(ENC_GROUP_SIZE + 1);
+        remaining = rest;
+        if chunk[ENC_GROUP_SIZE] != ENC_MARKER {
+            return remaining;
+        }
+    }
+}
+
+fn skip_decimal(bs: &[u8]) -> &[u8] {
+    let (sign, remaining) = bs.split_first().unwrap();
+    let flip = match *sign {
+        DECIMAL_ZERO => return remaining,
+        DECIMAL_NEG => 0xFF,
+        _ => 0x00,
+    };
+    let mut remaining = &remaining[8..];
+    loop {
+        let (b, rest) = remaining.split_first().unwrap();
+        remaining = rest;
+        if *b ^ flip == 0 {
+            return remaining;
+        }
+    }
 }
 
 impl<T: Write> MemCmpEncoder for T {}
@@ -7,9 +7,13 @@
  */
 
 pub(crate) mod aggr;
+pub(crate) mod duration;
+pub(crate) mod edn;
 pub(crate) mod expr;
 pub mod functions;
+pub(crate) mod geo;
 pub(crate) mod json;
+pub(crate) mod json_path;
 pub(crate) mod memcmp;
 pub mod program;
 pub(crate) mod relation;
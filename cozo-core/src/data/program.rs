@@ -11,28 +11,36 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::Arc;
 
+use itertools::Itertools;
 use miette::{bail, ensure, miette, Diagnostic, Result};
+use serde_json::Map;
 use smallvec::SmallVec;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::aggr::Aggregation;
 use crate::data::expr::Expr;
+use crate::data::json::JsonValue;
 use crate::data::relation::StoredRelationMetadata;
 use crate::data::symb::{Symbol, PROG_ENTRY};
-use crate::data::value::{DataValue, ValidityTs};
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, JsonData, ValidityTs};
 use crate::fixed_rule::{FixedRule, FixedRuleHandle};
 use crate::fts::FtsIndexManifest;
 use crate::parse::SourceSpan;
 use crate::query::compile::ContainedRuleMultiplicity;
 use crate::query::logical::{Disjunction, NamedFieldNotFound};
+use crate::query::window::QueryWindow;
+use crate::runtime::geo_index::{GeoIndexManifest, GeoSearch};
 use crate::runtime::hnsw::HnswIndexManifest;
 use crate::runtime::minhash_lsh::{LshSearch, MinHashLshIndexManifest};
+use crate::runtime::outbox::OUTBOX_LOCK;
 use crate::runtime::relation::{
     AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
 };
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub(crate) enum QueryAssertion {
@@ -44,6 +52,18 @@ pub(crate) enum QueryAssertion {
 pub(crate) enum ReturnMutation {
     NotReturning,
     Returning,
+    ReturningKeys,
+    ReturningCounts,
+}
+
+impl ReturnMutation {
+    /// Whether the mutated rows themselves are returned, as opposed to a status row.
+    pub(crate) fn returns_rows(&self) -> bool {
+        matches!(
+            self,
+            ReturnMutation::Returning | ReturnMutation::ReturningKeys
+        )
+    }
 }
 
 #[derive(Clone, PartialEq, Default)]
@@ -54,8 +74,23 @@ pub struct QueryOutOptions {
     pub timeout: Option<f64>,
     /// Sleep after performing the query for this number of seconds. Ignored in WASM.
     pub sleep: Option<f64>,
+    /// Seed of the random functions and fixed rules, making the query deterministic.
+    pub seed: Option<u64>,
     pub sorters: Vec<(Symbol, SortDir)>,
     pub store_relation: Option<(InputRelationHandle, RelationOp, ReturnMutation)>,
+    /// Only mutate the rows for which this condition holds, evaluated against the
+    /// existing rows bound as `old.<column>`.
+    pub guard: Option<Box<Expr>>,
+    /// Track the transaction time of the relation created, see `track_tx_time` in `:create`.
+    pub track_tx_time: bool,
+    /// Append the rows to the outbox as events of this topic.
+    pub outbox: Option<SmartString<LazyCompact>>,
+    /// Reshape the rows before returning them.
+    pub transform: Option<Box<QueryTransform>>,
+    /// Nest the rows into documents before returning them.
+    pub nest: Option<Box<QueryNest>>,
+    /// Add the values of window functions to the rows before returning them.
+    pub window: Option<Box<QueryWindow>>,
     pub assertion: Option<QueryAssertion>,
 }
 
@@ -76,6 +111,9 @@ impl Display for QueryOutOptions {
         if let Some(l) = self.timeout {
             writeln!(f, ":timeout {l};")?;
         }
+        if let Some(l) = self.seed {
+            writeln!(f, ":seed {l};")?;
+        }
         for (symb, dir) in &self.sorters {
             write!(f, ":order ")?;
             if *dir == SortDir::Dsc {
@@ -95,8 +133,11 @@ impl Display for QueryOutOptions {
             return_mutation,
         )) = &self.store_relation
         {
-            if *return_mutation == ReturnMutation::Returning {
-                writeln!(f, ":returning")?;
+            match return_mutation {
+                ReturnMutation::NotReturning => {}
+                ReturnMutation::Returning => writeln!(f, ":returning")?,
+                ReturnMutation::ReturningKeys => writeln!(f, ":returning keys")?,
+                ReturnMutation::ReturningCounts => writeln!(f, ":returning counts")?,
             }
             match op {
                 RelationOp::Create => {
@@ -114,6 +155,9 @@ impl Display for QueryOutOptions {
                 RelationOp::Update => {
                     write!(f, ":update ")?;
                 }
+                RelationOp::Upsert => {
+                    write!(f, ":upsert ")?;
+                }
                 RelationOp::Rm => {
                     write!(f, ":rm ")?;
                 }
@@ -157,7 +201,35 @@ impl Display for QueryOutOptions {
                     write!(f, " = {bind}")?;
                 }
             }
-            writeln!(f, "}};")?;
+            write!(f, "}}")?;
+            if self.track_tx_time {
+                write!(f, " {{track_tx_time: true}}")?;
+            }
+            if let Some(guard) = &self.guard {
+                write!(f, " if {guard}")?;
+            }
+            writeln!(f, ";")?;
+        }
+        if let Some(topic) = &self.outbox {
+            writeln!(f, ":outbox {};", DataValue::from(topic as &str))?;
+        }
+        if let Some(QueryTransform {
+            name,
+            expr,
+            group_by,
+        }) = self.transform.as_deref()
+        {
+            write!(f, ":transform {name} = {expr}")?;
+            if !group_by.is_empty() {
+                write!(f, " by {}", group_by.iter().map(|(k, _)| k).join(", "))?;
+            }
+            writeln!(f, ";")?;
+        }
+        if let Some(nest) = &self.nest {
+            writeln!(f, ":nest {nest};")?;
+        }
+        if let Some(window) = &self.window {
+            writeln!(f, ":window {window};")?;
         }
 
         if let Some(a) = &self.assertion {
@@ -175,6 +247,161 @@ impl Display for QueryOutOptions {
     }
 }
 
+/// The `:transform` option: each output row is replaced by the value of `expr`,
+/// and if `group_by` is not empty, the values of rows agreeing on the grouping columns
+/// are collected into a list, in the order of the rows.
+#[derive(Clone, PartialEq)]
+pub struct QueryTransform {
+    pub(crate) name: Symbol,
+    /// Bindings are resolved against the output head.
+    pub(crate) expr: Expr,
+    /// The grouping columns and their positions in the output head.
+    pub(crate) group_by: Vec<(Symbol, usize)>,
+}
+
+impl QueryTransform {
+    pub(crate) fn apply(&self, rows: Vec<Tuple>) -> Result<NamedRows> {
+        if self.group_by.is_empty() {
+            let rows = rows
+                .iter()
+                .map(|row| -> Result<Tuple> { Ok(vec![self.expr.eval(row)?]) })
+                .try_collect()?;
+            return Ok(NamedRows::new(vec![self.name.to_string()], rows));
+        }
+        let mut groups: Vec<(Tuple, Vec<DataValue>)> = vec![];
+        #[allow(clippy::mutable_key_type)]
+        let mut positions: BTreeMap<Tuple, usize> = BTreeMap::new();
+        for row in rows {
+            let val = self.expr.eval(&row)?;
+            let key = self
+                .group_by
+                .iter()
+                .map(|(_, i)| row[*i].clone())
+                .collect_vec();
+            match positions.entry(key) {
+                Entry::Occupied(ent) => groups[*ent.get()].1.push(val),
+                Entry::Vacant(ent) => {
+                    groups.push((ent.key().clone(), vec![val]));
+                    ent.insert(groups.len() - 1);
+                }
+            }
+        }
+        let mut headers = self
+            .group_by
+            .iter()
+            .map(|(k, _)| k.to_string())
+            .collect_vec();
+        headers.push(self.name.to_string());
+        let rows = groups
+            .into_iter()
+            .map(|(mut key, vals)| {
+                key.push(DataValue::List(vals));
+                key
+            })
+            .collect_vec();
+        Ok(NamedRows::new(headers, rows))
+    }
+}
+
+/// The `:nest` option: rows agreeing on the `parent` columns are merged into one,
+/// and each child collects the distinct documents built from the merged rows by its own spec,
+/// recursively. Rows where all the `parent` columns of a child are null,
+/// as produced by outer joins, do not contribute documents to the child.
+#[derive(Clone, PartialEq)]
+pub struct QueryNest {
+    /// The columns and their positions in the output head.
+    pub(crate) parent: Vec<(Symbol, usize)>,
+    pub(crate) children: Vec<(Symbol, QueryNest)>,
+}
+
+impl Display for QueryNest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parent = self.parent.iter().map(|(k, _)| k).join(", ");
+        if self.children.is_empty() {
+            return write!(f, "[{parent}]");
+        }
+        let children = self
+            .children
+            .iter()
+            .map(|(name, child)| format!("{name}: {child}"))
+            .join(", ");
+        write!(f, "{{parent: [{parent}], children: {{{children}}}}}")
+    }
+}
+
+impl QueryNest {
+    pub(crate) fn apply(&self, rows: Vec<Tuple>) -> NamedRows {
+        let headers = self
+            .parent
+            .iter()
+            .map(|(k, _)| k)
+            .chain(self.children.iter().map(|(k, _)| k))
+            .map(|k| k.to_string())
+            .collect_vec();
+        let rows = rows.iter().collect_vec();
+        let rows = self
+            .group(&rows, false)
+            .into_iter()
+            .map(|(mut key, members)| {
+                for (_, child) in &self.children {
+                    let docs = child
+                        .documents(&members)
+                        .into_iter()
+                        .map(|doc| DataValue::Json(JsonData(doc)))
+                        .collect_vec();
+                    key.push(DataValue::List(docs));
+                }
+                key
+            })
+            .collect_vec();
+        NamedRows::new(headers, rows)
+    }
+    /// Group the rows by the `parent` columns, in the order the groups are first seen.
+    fn group<'a>(&self, rows: &[&'a Tuple], skip_nulls: bool) -> Vec<(Tuple, Vec<&'a Tuple>)> {
+        let mut groups: Vec<(Tuple, Vec<&'a Tuple>)> = vec![];
+        #[allow(clippy::mutable_key_type)]
+        let mut positions: BTreeMap<Tuple, usize> = BTreeMap::new();
+        for row in rows {
+            let key = self
+                .parent
+                .iter()
+                .map(|(_, i)| row[*i].clone())
+                .collect_vec();
+            if skip_nulls && key.iter().all(|v| *v == DataValue::Null) {
+                continue;
+            }
+            match positions.entry(key) {
+                Entry::Occupied(ent) => groups[*ent.get()].1.push(row),
+                Entry::Vacant(ent) => {
+                    groups.push((ent.key().clone(), vec![row]));
+                    ent.insert(groups.len() - 1);
+                }
+            }
+        }
+        groups
+    }
+    fn documents(&self, rows: &[&Tuple]) -> Vec<JsonValue> {
+        self.group(rows, true)
+            .into_iter()
+            .map(|(key, members)| {
+                let mut doc: Map<String, JsonValue> = self
+                    .parent
+                    .iter()
+                    .map(|(k, _)| k.to_string())
+                    .zip(key.into_iter().map(JsonValue::from))
+                    .collect();
+                for (name, child) in &self.children {
+                    doc.insert(
+                        name.to_string(),
+                        JsonValue::Array(child.documents(&members)),
+                    );
+                }
+                JsonValue::Object(doc)
+            })
+            .collect_vec()
+    }
+}
+
 impl QueryOutOptions {
     pub(crate) fn num_to_take(&self) -> Option<usize> {
         match (self.limit, self.offset) {
@@ -198,6 +425,8 @@ pub enum RelationOp {
     Put,
     Insert,
     Update,
+    /// Like `Update`, but inserts the row with column defaults when the key does not exist
+    Upsert,
     Rm,
     Delete,
     Ensure,
@@ -471,10 +700,97 @@ pub struct InputProgram {
     pub prog: BTreeMap<Symbol, InputInlineRulesOrFixed>,
     pub out_opts: QueryOutOptions,
     pub disable_magic_rewrite: bool,
+    /// Features opted into with `:experimental`.
+    pub experimental: ExperimentalFeatures,
+    /// Directives to the planner given with `:hints`.
+    pub hints: QueryHints,
+    /// The transaction time given with `:as_of_tx`: relations tracking transaction time
+    /// are read as they were then. Inputs of fixed rules are read as they are now.
+    pub as_of_tx: Option<ValidityTs>,
+    /// The modules whose rules are brought into scope with `use`, see `::module save`.
+    /// Their rules are added to [Self::prog] when the program is compiled.
+    pub uses: Vec<Symbol>,
+}
+
+/// Planner and runtime behaviours that a query opts into with `:experimental`,
+/// before they become the defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExperimentalFeatures {
+    /// Order the relations of rule bodies so that each joins on as many
+    /// already bound variables as possible, instead of keeping the written order.
+    pub join_reorder: bool,
+    /// Join on hash tables instead of sorted materializations when no index can be used.
+    pub hash_join: bool,
+}
+
+/// Directives to the planner given with `:hints`, overriding its choices
+/// when they turn out to be bad for a query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryHints {
+    /// Rules exempted from the magic set rewrite, given with `no_magic_set rule`.
+    pub no_magic_set: BTreeSet<Symbol>,
+    /// Orders in which to join the rule and stored relation applications named,
+    /// given with `join_order(a, b, c)`.
+    pub join_orders: Vec<Vec<Symbol>>,
+    /// The index to scan instead of each stored relation, given with `use_index rel:idx`.
+    pub use_index: BTreeMap<SmartString<LazyCompact>, Symbol>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The rule or stored relation {0} given in hints is not found")]
+#[diagnostic(code(eval::hint_target_not_found))]
+struct HintTargetNotFound(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation {0} has no index {1}")]
+#[diagnostic(code(eval::hinted_index_not_found))]
+#[diagnostic(help("Only indices on the columns of the relation can be hinted"))]
+struct HintedIndexNotFound(String, String, #[label] SourceSpan);
+
+impl QueryHints {
+    /// Check that the rules, relations and indices named by the hints exist,
+    /// so that misspelt hints are not silently ignored.
+    fn check(
+        &self,
+        prog: &BTreeMap<Symbol, InputInlineRulesOrFixed>,
+        tx: &SessionTx<'_>,
+    ) -> Result<()> {
+        for rule in &self.no_magic_set {
+            ensure!(
+                prog.contains_key(rule),
+                HintTargetNotFound(rule.name.to_string(), rule.span)
+            );
+        }
+        for name in self.join_orders.iter().flatten() {
+            ensure!(
+                prog.contains_key(name) || tx.relation_exists(&name.name)?,
+                HintTargetNotFound(name.name.to_string(), name.span)
+            );
+        }
+        for (relation, index) in &self.use_index {
+            ensure!(
+                tx.relation_exists(relation)?,
+                HintTargetNotFound(relation.to_string(), index.span)
+            );
+            let handle = tx.get_relation(relation, false)?;
+            let on_columns = match handle.indices.get(&index.name) {
+                None => false,
+                Some((_, mapper)) => mapper.iter().all(|i| *i < handle.arity()),
+            };
+            ensure!(
+                on_columns,
+                HintedIndexNotFound(relation.to_string(), index.name.to_string(), index.span)
+            );
+        }
+        Ok(())
+    }
 }
 
 impl Display for InputProgram {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for module in &self.uses {
+            writeln!(f, "use {module};")?;
+        }
         for (name, rules) in &self.prog {
             match rules {
                 InputInlineRulesOrFixed::Rules { rules, .. } => {
@@ -562,7 +878,9 @@ pub(crate) struct NoEntryError;
 
 impl InputProgram {
     pub(crate) fn needs_write_lock(&self) -> Option<SmartString<LazyCompact>> {
-        if let Some((h, _, _)) = &self.out_opts.store_relation {
+        if self.out_opts.outbox.is_some() {
+            Some(SmartString::from(OUTBOX_LOCK))
+        } else if let Some((h, _, _)) = &self.out_opts.store_relation {
             if !h.name.name.starts_with('_') {
                 Some(h.name.name.clone())
             } else {
@@ -633,9 +951,12 @@ impl InputProgram {
         Err(NoEntryError.into())
     }
     pub(crate) fn into_normalized_program(
-        self,
+        mut self,
         tx: &SessionTx<'_>,
     ) -> Result<(NormalFormProgram, QueryOutOptions)> {
+        tx.import_modules(&mut self.prog, &self.uses)?;
+        self.expand_paths(tx)?;
+        self.hints.check(&self.prog, tx)?;
         let mut prog: BTreeMap<Symbol, _> = Default::default();
         for (k, rules_or_fixed) in self.prog {
             match rules_or_fixed {
@@ -682,11 +1003,17 @@ impl InputProgram {
                                     }))
                                 }
                             }
-                            let normalized_rule = NormalFormInlineRule {
+                            let mut normalized_rule = NormalFormInlineRule {
                                 head: new_head.clone(),
                                 aggr: rule.aggr.clone(),
                                 body,
                             };
+                            if self.experimental.join_reorder {
+                                normalized_rule.reorder_joins();
+                            }
+                            for order in &self.hints.join_orders {
+                                normalized_rule.apply_join_order(order);
+                            }
                             collected_rules.push(normalized_rule.convert_to_well_ordered_rule()?);
                         }
                     }
@@ -706,6 +1033,7 @@ impl InputProgram {
             NormalFormProgram {
                 prog,
                 disable_magic_rewrite: self.disable_magic_rewrite,
+                no_magic_set: self.hints.no_magic_set,
             },
             self.out_opts,
         ))
@@ -734,6 +1062,8 @@ impl NormalFormRulesOrFixed {
 pub(crate) struct NormalFormProgram {
     pub(crate) prog: BTreeMap<Symbol, NormalFormRulesOrFixed>,
     pub(crate) disable_magic_rewrite: bool,
+    /// Rules exempted from the magic set rewrite by hints.
+    pub(crate) no_magic_set: BTreeSet<Symbol>,
 }
 
 #[derive(Debug)]
@@ -961,6 +1291,11 @@ pub enum InputAtom {
     Search {
         inner: SearchInput,
     },
+    /// `a -[:knows+ / :works_at]-> b`, expanded into rules before normalization
+    Path {
+        /// The ends of the path and its pattern.
+        inner: InputPathApplyAtom,
+    },
 }
 
 #[derive(Clone)]
@@ -1150,6 +1485,23 @@ impl SearchInput {
 
         let filter = self.parameters.remove("filter");
 
+        let bind_similarity = match self.parameters.remove("bind_similarity") {
+            None => None,
+            Some(Expr::Binding { var, .. }) => Some(var),
+            Some(expr) => {
+                let span = expr.span();
+                let kw = gen.next(span);
+                let unif = NormalFormAtom::Unification(Unification {
+                    binding: kw.clone(),
+                    expr,
+                    one_many_unif: false,
+                    span,
+                });
+                conj.push(unif);
+                Some(kw)
+            }
+        };
+
         #[derive(Debug, Error, Diagnostic)]
         #[error("Extra parameters for LSH search: {0:?}")]
         #[diagnostic(code(parser::extra_parameters_for_lsh_search))]
@@ -1171,6 +1523,174 @@ impl SearchInput {
             query,
             span: self.span,
             filter,
+            bind_similarity,
+        }));
+
+        Ok(Disjunction::conj(conj))
+    }
+    fn normalize_geo(
+        mut self,
+        base_handle: RelationHandle,
+        idx_handle: RelationHandle,
+        manifest: GeoIndexManifest,
+        gen: &mut TempSymbGen,
+    ) -> Result<Disjunction> {
+        let mut conj = Vec::with_capacity(self.bindings.len() + 8);
+        let mut bindings = Vec::with_capacity(self.bindings.len());
+        let mut seen_variables = BTreeSet::new();
+
+        for col in base_handle
+            .metadata
+            .keys
+            .iter()
+            .chain(base_handle.metadata.non_keys.iter())
+        {
+            if let Some(arg) = self.bindings.remove(&col.name) {
+                match arg {
+                    Expr::Binding { var, .. } => {
+                        if var.is_ignored_symbol() {
+                            bindings.push(gen.next_ignored(var.span));
+                        } else if seen_variables.insert(var.clone()) {
+                            bindings.push(var);
+                        } else {
+                            let span = var.span;
+                            let dup = gen.next(span);
+                            let unif = NormalFormAtom::Unification(Unification {
+                                binding: dup.clone(),
+                                expr: Expr::Binding {
+                                    var,
+                                    tuple_pos: None,
+                                },
+                                one_many_unif: false,
+                                span,
+                            });
+                            conj.push(unif);
+                            bindings.push(dup);
+                        }
+                    }
+                    expr => {
+                        let span = expr.span();
+                        let kw = gen.next(span);
+                        bindings.push(kw.clone());
+                        let unif = NormalFormAtom::Unification(Unification {
+                            binding: kw,
+                            expr,
+                            one_many_unif: false,
+                            span,
+                        });
+                        conj.push(unif)
+                    }
+                }
+            } else {
+                bindings.push(gen.next_ignored(self.span));
+            }
+        }
+
+        if let Some((name, _)) = self.bindings.pop_first() {
+            bail!(NamedFieldNotFound(
+                self.relation.name.to_string(),
+                name.to_string(),
+                self.span
+            ));
+        }
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Field `{0}` is required for geo search")]
+        #[diagnostic(code(parser::geo_query_required))]
+        struct GeoRequiredMissing(String, #[label] SourceSpan);
+
+        let query = match self
+            .parameters
+            .remove("query")
+            .ok_or_else(|| miette!(GeoRequiredMissing("query".to_string(), self.span)))?
+        {
+            Expr::Binding { var, .. } => var,
+            expr => {
+                let span = expr.span();
+                let kw = gen.next(span);
+                let unif = NormalFormAtom::Unification(Unification {
+                    binding: kw.clone(),
+                    expr,
+                    one_many_unif: false,
+                    span,
+                });
+                conj.push(unif);
+                kw
+            }
+        };
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Expected non-negative number of kilometres for `radius`")]
+        #[diagnostic(code(parser::expected_float_for_geo_radius))]
+        struct ExpectedFloatForGeoRadius(#[label] SourceSpan);
+
+        let radius = self
+            .parameters
+            .remove("radius")
+            .ok_or_else(|| miette!(GeoRequiredMissing("radius".to_string(), self.span)))?
+            .eval_to_const()?
+            .get_float()
+            .filter(|r| *r >= 0.)
+            .ok_or(ExpectedFloatForGeoRadius(self.span))?;
+
+        let k = match self.parameters.remove("k") {
+            None => None,
+            Some(k_expr) => {
+                let k = k_expr.eval_to_const()?;
+                let k = k.get_int().ok_or(ExpectedPosIntForGeoK(self.span))?;
+
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("Expected positive integer for `k`")]
+                #[diagnostic(code(parser::expected_int_for_geo_k))]
+                struct ExpectedPosIntForGeoK(#[label] SourceSpan);
+
+                ensure!(k > 0, ExpectedPosIntForGeoK(self.span));
+                Some(k as usize)
+            }
+        };
+
+        let bind_distance = match self.parameters.remove("bind_distance") {
+            None => None,
+            Some(Expr::Binding { var, .. }) => Some(var),
+            Some(expr) => {
+                let span = expr.span();
+                let kw = gen.next(span);
+                let unif = NormalFormAtom::Unification(Unification {
+                    binding: kw.clone(),
+                    expr,
+                    one_many_unif: false,
+                    span,
+                });
+                conj.push(unif);
+                Some(kw)
+            }
+        };
+
+        let filter = self.parameters.remove("filter");
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Extra parameters for geo search: {0:?}")]
+        #[diagnostic(code(parser::extra_parameters_for_geo_search))]
+        struct ExtraParametersForGeoSearch(Vec<String>, #[label] SourceSpan);
+
+        if !self.parameters.is_empty() {
+            bail!(ExtraParametersForGeoSearch(
+                self.parameters.keys().map(|s| s.to_string()).collect(),
+                self.span
+            ));
+        }
+
+        conj.push(NormalFormAtom::GeoSearch(GeoSearch {
+            base_handle,
+            idx_handle,
+            manifest,
+            bindings,
+            k,
+            query,
+            radius,
+            bind_distance,
+            filter,
+            span: self.span,
         }));
 
         Ok(Disjunction::conj(conj))
@@ -1594,6 +2114,10 @@ impl SearchInput {
         {
             return self.normalize_lsh(base_handle, idx_handle, manifest, gen);
         }
+        if let Some((idx_handle, manifest)) = base_handle.geo_indices.get(&self.index.name).cloned()
+        {
+            return self.normalize_geo(base_handle, idx_handle, manifest, gen);
+        }
         #[derive(Debug, Error, Diagnostic)]
         #[error("Index {name} not found on relation {relation}")]
         #[diagnostic(code(eval::hnsw_index_not_found))]
@@ -1627,14 +2151,24 @@ impl Display for InputAtom {
                 f.debug_list().entries(args).finish()?;
             }
             InputAtom::NamedFieldRelation {
-                inner: InputNamedFieldRelationApplyAtom { name, args, .. },
+                inner:
+                    InputNamedFieldRelationApplyAtom {
+                        name,
+                        args,
+                        bind_rest,
+                        ..
+                    },
             } => {
                 f.write_str("*")?;
                 let mut sf = f.debug_struct(name);
                 for (k, v) in args {
                     sf.field(k, v);
                 }
-                sf.finish()?;
+                if *bind_rest {
+                    sf.finish_non_exhaustive()?;
+                } else {
+                    sf.finish()?;
+                }
             }
             InputAtom::Relation {
                 inner: InputRelationApplyAtom { name, args, .. },
@@ -1692,6 +2226,14 @@ impl Display for InputAtom {
                 }
                 write!(f, "{expr}")?;
             }
+            InputAtom::Path {
+                inner:
+                    InputPathApplyAtom {
+                        from, to, pattern, ..
+                    },
+            } => {
+                write!(f, "{from} -[{pattern}]-> {to}")?;
+            }
         }
         Ok(())
     }
@@ -1719,6 +2261,7 @@ impl InputAtom {
             InputAtom::Predicate { inner, .. } => inner.span(),
             InputAtom::Unification { inner, .. } => inner.span,
             InputAtom::Search { inner, .. } => inner.span,
+            InputAtom::Path { inner, .. } => inner.span,
         }
     }
 }
@@ -1734,6 +2277,7 @@ pub(crate) enum NormalFormAtom {
     HnswSearch(HnswSearch),
     FtsSearch(FtsSearch),
     LshSearch(LshSearch),
+    GeoSearch(GeoSearch),
 }
 
 #[derive(Debug, Clone)]
@@ -1747,6 +2291,7 @@ pub(crate) enum MagicAtom {
     HnswSearch(HnswSearch),
     FtsSearch(FtsSearch),
     LshSearch(LshSearch),
+    GeoSearch(GeoSearch),
 }
 
 #[derive(Clone, Debug)]
@@ -1760,6 +2305,9 @@ pub struct InputRuleApplyAtom {
 pub struct InputNamedFieldRelationApplyAtom {
     pub name: Symbol,
     pub args: BTreeMap<SmartString<LazyCompact>, Expr>,
+    /// Whether the columns not in `args` are bound to variables of the same names,
+    /// as with `*rel{a, ..}`.
+    pub bind_rest: bool,
     pub valid_at: Option<ValidityTs>,
     pub span: SourceSpan,
 }
@@ -1772,6 +2320,105 @@ pub struct InputRelationApplyAtom {
     pub span: SourceSpan,
 }
 
+/// A path between two nodes following edges as described by a pattern.
+#[derive(Clone, Debug)]
+pub struct InputPathApplyAtom {
+    /// The variable bound to the start of the path.
+    pub from: Symbol,
+    /// The variable bound to the end of the path.
+    pub to: Symbol,
+    /// The edges the path follows.
+    pub pattern: PathPattern,
+    /// The span of the whole atom.
+    pub span: SourceSpan,
+}
+
+/// A regular expression over the edges of paths.
+#[derive(Clone, Debug)]
+pub enum PathPattern {
+    /// An edge in a stored relation, `:name` or `^:name`.
+    Label {
+        /// The relation, from the first column of which edges go to the second.
+        relation: Symbol,
+        /// Whether edges are followed from the second column to the first.
+        inverse: bool,
+    },
+    /// The patterns one after the other, `p / q`.
+    Sequence(Vec<PathPattern>),
+    /// Any of the patterns, `p | q`.
+    Alternative(Vec<PathPattern>),
+    /// `p+`
+    OneOrMore(Box<PathPattern>),
+    /// `p*`
+    ZeroOrMore(Box<PathPattern>),
+    /// `p?`
+    Optional(Box<PathPattern>),
+}
+
+impl PathPattern {
+    /// The stored relations whose edges the pattern follows.
+    pub(crate) fn relations<'a>(&'a self, coll: &mut Vec<&'a Symbol>) {
+        match self {
+            PathPattern::Label { relation, .. } => coll.push(relation),
+            PathPattern::Sequence(inner) | PathPattern::Alternative(inner) => {
+                for p in inner {
+                    p.relations(coll);
+                }
+            }
+            PathPattern::OneOrMore(inner)
+            | PathPattern::ZeroOrMore(inner)
+            | PathPattern::Optional(inner) => inner.relations(coll),
+        }
+    }
+}
+
+impl Display for PathPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let grouped = |p: &PathPattern, f: &mut Formatter<'_>| match p {
+            PathPattern::Sequence(_) | PathPattern::Alternative(_) => write!(f, "({p})"),
+            _ => write!(f, "{p}"),
+        };
+        match self {
+            PathPattern::Label { relation, inverse } => {
+                if *inverse {
+                    f.write_str("^")?;
+                }
+                write!(f, ":{relation}")
+            }
+            PathPattern::Sequence(inner) => {
+                for (i, p) in inner.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" / ")?;
+                    }
+                    grouped(p, f)?;
+                }
+                Ok(())
+            }
+            PathPattern::Alternative(inner) => {
+                for (i, p) in inner.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" | ")?;
+                    }
+                    write!(f, "{p}")?;
+                }
+                Ok(())
+            }
+            PathPattern::OneOrMore(inner) => {
+                grouped(inner, f)?;
+                f.write_str("+")
+            }
+            PathPattern::ZeroOrMore(inner) => {
+                grouped(inner, f)?;
+                f.write_str("*")
+            }
+            PathPattern::Optional(inner) => {
+                grouped(inner, f)?;
+                f.write_str("?")
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct NormalFormRuleApplyAtom {
     pub(crate) name: Symbol,
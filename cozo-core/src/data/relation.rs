@@ -13,6 +13,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
+use bigdecimal::RoundingMode;
 use chrono::DateTime;
 use itertools::Itertools;
 use miette::{bail, ensure, Diagnostic, Result};
@@ -20,7 +21,9 @@ use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::duration::{format_duration, parse_duration};
 use crate::data::expr::Expr;
+use crate::data::functions::to_decimal;
 use crate::data::value::{DataValue, JsonData, UuidWrapper, Validity, ValidityTs, Vector};
 use crate::Num;
 
@@ -37,6 +40,13 @@ impl Display for NullableColType {
             ColType::Bool => f.write_str("Bool")?,
             ColType::Int => f.write_str("Int")?,
             ColType::Float => f.write_str("Float")?,
+            ColType::BigInt => f.write_str("BigInt")?,
+            ColType::Decimal { scale } => {
+                f.write_str("Decimal")?;
+                if let Some(s) = scale {
+                    write!(f, "({s})")?;
+                }
+            }
             ColType::String => f.write_str("String")?,
             ColType::Bytes => f.write_str("Bytes")?,
             ColType::Uuid => f.write_str("Uuid")?,
@@ -72,6 +82,9 @@ impl Display for NullableColType {
             ColType::Json => {
                 f.write_str("Json")?;
             }
+            ColType::Duration => {
+                f.write_str("Duration")?;
+            }
         }
         if self.nullable {
             f.write_str("?")?;
@@ -86,6 +99,12 @@ pub enum ColType {
     Bool,
     Int,
     Float,
+    /// Integers of arbitrary size, stored as decimals
+    BigInt,
+    /// Exact decimals, rounded to the given number of digits after the point if any
+    Decimal {
+        scale: Option<i64>,
+    },
     String,
     Bytes,
     Uuid,
@@ -100,6 +119,7 @@ pub enum ColType {
     Tuple(Vec<NullableColType>),
     Validity,
     Json,
+    Duration,
 }
 
 #[derive(
@@ -213,6 +233,18 @@ impl NullableColType {
             ColType::Bool => DataValue::from(data.get_bool().ok_or_else(make_err)?),
             ColType::Int => DataValue::from(data.get_int().ok_or_else(make_err)?),
             ColType::Float => DataValue::from(data.get_float().ok_or_else(make_err)?),
+            ColType::BigInt => {
+                let d = to_decimal(&data).map_err(|_| make_err())?;
+                ensure!(d.is_integer(), make_err());
+                DataValue::Decimal(d.with_scale(0))
+            }
+            ColType::Decimal { scale } => {
+                let d = to_decimal(&data).map_err(|_| make_err())?;
+                DataValue::Decimal(match scale {
+                    None => d,
+                    Some(s) => d.with_scale_round(*s, RoundingMode::HalfUp),
+                })
+            }
             ColType::String => {
                 if matches!(data, DataValue::Str(_)) {
                     data
@@ -235,6 +267,11 @@ impl NullableColType {
                 _ => bail!(make_err()),
             },
             ColType::Uuid => DataValue::Uuid(UuidWrapper(data.get_uuid().ok_or_else(make_err)?)),
+            ColType::Duration => match data {
+                d @ DataValue::Duration(_) => d,
+                DataValue::Str(s) => DataValue::Duration(parse_duration(&s)?),
+                _ => bail!(make_err()),
+            },
             ColType::List { eltype, len } => {
                 if let DataValue::List(l) = data {
                     if let Some(expected) = len {
@@ -402,6 +439,12 @@ impl NullableColType {
                         json!(f)
                     }
                 },
+                DataValue::Decimal(d) => {
+                    json!(d.to_string())
+                }
+                DataValue::Duration(d) => {
+                    json!(format_duration(d))
+                }
                 DataValue::Str(s) => {
                     json!(s)
                 }
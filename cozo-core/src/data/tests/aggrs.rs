@@ -271,6 +271,178 @@ fn test_std_dev() {
     assert!(v.abs_diff_eq(&(0.5_f64).sqrt(), 1e-10));
 }
 
+#[test]
+fn test_variance_large_offset() {
+    let mut aggr = parse_aggr("variance").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut variance_aggr = aggr.normal_op.unwrap();
+    for x in [4., 7., 13., 16.] {
+        variance_aggr.set(&DataValue::from(1e9 + x)).unwrap();
+    }
+    let v = variance_aggr.get().unwrap().get_float().unwrap();
+    assert!(v.abs_diff_eq(&30., 1e-6));
+}
+
+#[test]
+fn test_covariance() {
+    let mut aggr = parse_aggr("covariance").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut covariance_aggr = aggr.normal_op.unwrap();
+    for (x, y) in [(1, 2), (2, 4), (3, 6)] {
+        covariance_aggr
+            .set(&DataValue::List(vec![
+                DataValue::from(x),
+                DataValue::from(y),
+            ]))
+            .unwrap();
+    }
+    let v = covariance_aggr.get().unwrap().get_float().unwrap();
+    assert!(v.abs_diff_eq(&2., 1e-10));
+    assert!(covariance_aggr.set(&DataValue::from(1)).is_err());
+}
+
+#[test]
+fn test_correlation() {
+    let mut aggr = parse_aggr("correlation").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut correlation_aggr = aggr.normal_op.unwrap();
+    for (x, y) in [(1, 6), (2, 4), (3, 2)] {
+        correlation_aggr
+            .set(&DataValue::List(vec![
+                DataValue::from(x),
+                DataValue::from(y),
+            ]))
+            .unwrap();
+    }
+    let v = correlation_aggr.get().unwrap().get_float().unwrap();
+    assert!(v.abs_diff_eq(&-1., 1e-10));
+}
+
+#[test]
+fn test_percentile() {
+    let mut aggr = parse_aggr("percentile").unwrap().clone();
+    assert!(aggr.normal_init(&[]).is_err());
+    assert!(aggr.normal_init(&[DataValue::from(101)]).is_err());
+    aggr.normal_init(&[DataValue::from(25)]).unwrap();
+
+    let mut percentile_aggr = aggr.normal_op.unwrap();
+    assert_eq!(percentile_aggr.get().unwrap(), DataValue::Null);
+    for x in [4, 1, 3, 2, 5] {
+        percentile_aggr.set(&DataValue::from(x)).unwrap();
+    }
+    let v = percentile_aggr.get().unwrap().get_float().unwrap();
+    assert!(v.abs_diff_eq(&2., 1e-10));
+
+    let mut aggr = parse_aggr("median").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut median_aggr = aggr.normal_op.unwrap();
+    for x in [4, 1, 3, 2] {
+        median_aggr.set(&DataValue::from(x)).unwrap();
+    }
+    let v = median_aggr.get().unwrap().get_float().unwrap();
+    assert!(v.abs_diff_eq(&2.5, 1e-10));
+}
+
+#[test]
+fn test_top_k() {
+    let mut aggr = parse_aggr("top_k").unwrap().clone();
+    assert!(aggr.normal_init(&[]).is_err());
+    assert!(aggr.normal_init(&[DataValue::from(0)]).is_err());
+    aggr.normal_init(&[DataValue::from(2)]).unwrap();
+
+    let mut top_k_aggr = aggr.normal_op.unwrap();
+    for (v, by) in [("a", 3), ("b", 5), ("c", 1), ("d", 4)] {
+        top_k_aggr
+            .set(&DataValue::List(vec![
+                DataValue::from(v),
+                DataValue::from(by),
+            ]))
+            .unwrap();
+    }
+    assert_eq!(
+        top_k_aggr.get().unwrap(),
+        DataValue::List(vec![DataValue::from("b"), DataValue::from("d")])
+    );
+    assert!(top_k_aggr.set(&DataValue::from(1)).is_err());
+
+    let mut aggr = parse_aggr("bottom_k").unwrap().clone();
+    aggr.normal_init(&[DataValue::from(3)]).unwrap();
+
+    let mut bottom_k_aggr = aggr.normal_op.unwrap();
+    for (v, by) in [("a", 3), ("b", 5), ("c", 1), ("d", 4)] {
+        bottom_k_aggr
+            .set(&DataValue::List(vec![
+                DataValue::from(v),
+                DataValue::from(by),
+            ]))
+            .unwrap();
+    }
+    assert_eq!(
+        bottom_k_aggr.get().unwrap(),
+        DataValue::List(vec![
+            DataValue::from("c"),
+            DataValue::from("a"),
+            DataValue::from("d")
+        ])
+    );
+}
+
+#[test]
+fn test_latest() {
+    let mut aggr = parse_aggr("latest").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut latest_aggr = aggr.normal_op.unwrap();
+    assert_eq!(latest_aggr.get().unwrap(), DataValue::Null);
+    let version = |v: &str, ts: i64, is_assert: bool| {
+        DataValue::List(vec![
+            DataValue::from(v),
+            DataValue::Validity((ts, is_assert).into()),
+        ])
+    };
+    latest_aggr.set(&version("a", 20, true)).unwrap();
+    latest_aggr.set(&version("b", 10, true)).unwrap();
+    assert_eq!(latest_aggr.get().unwrap(), DataValue::from("a"));
+    latest_aggr.set(&version("a", 30, false)).unwrap();
+    assert_eq!(latest_aggr.get().unwrap(), DataValue::Null);
+    latest_aggr.set(&version("c", 30, true)).unwrap();
+    assert_eq!(latest_aggr.get().unwrap(), DataValue::from("c"));
+    assert!(latest_aggr.set(&DataValue::from(1)).is_err());
+}
+
+#[test]
+fn test_group_concat() {
+    let mut aggr = parse_aggr("group_concat").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut group_concat_aggr = aggr.normal_op.unwrap();
+    for v in ["b", "c", "a"] {
+        group_concat_aggr.set(&DataValue::from(v)).unwrap();
+    }
+    group_concat_aggr.set(&DataValue::from(1)).unwrap();
+    assert_eq!(group_concat_aggr.get().unwrap(), DataValue::from("1,a,b,c"));
+
+    let mut aggr = parse_aggr("group_concat").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from(1)]).is_err());
+    aggr.normal_init(&[DataValue::from(" -> ")]).unwrap();
+    let mut group_concat_aggr = aggr.normal_op.unwrap();
+    for (v, by) in [("x", 2), ("y", 0), ("z", 1)] {
+        group_concat_aggr
+            .set(&DataValue::List(vec![
+                DataValue::from(v),
+                DataValue::from(by),
+            ]))
+            .unwrap();
+    }
+    assert_eq!(
+        group_concat_aggr.get().unwrap(),
+        DataValue::from("y -> z -> x")
+    );
+}
+
 #[test]
 fn test_mean() {
     let mut aggr = parse_aggr("mean").unwrap().clone();
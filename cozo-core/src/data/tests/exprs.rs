@@ -6,6 +6,11 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
+
+use crate::data::expr::{eval_bytecode_pred, eval_bytecode_pred_batch};
+use crate::data::symb::Symbol;
+use crate::parse::parse_expressions;
 use crate::{DataValue, DbInstance};
 
 #[test]
@@ -30,3 +35,92 @@ fn expression_eval() {
         .unwrap();
     assert_eq!(res.rows[0][0].get_bool().unwrap(), true);
 }
+
+#[test]
+fn batched_predicates() {
+    let values = [
+        DataValue::from(1),
+        DataValue::from(-7),
+        DataValue::from(42),
+        DataValue::from(2.5),
+        DataValue::from(""),
+        DataValue::from("abc"),
+        DataValue::from("xyz"),
+        DataValue::Null,
+    ];
+    let rows = values
+        .iter()
+        .flat_map(|a| values.iter().map(|b| vec![a.clone(), b.clone()]))
+        .collect::<Vec<_>>();
+    let int_rows = (-50..50)
+        .map(|i| vec![DataValue::from(i), DataValue::from(i * 7 % 13)])
+        .collect::<Vec<_>>();
+    let binding_map = BTreeMap::from([
+        (Symbol::new("a", Default::default()), 0),
+        (Symbol::new("b", Default::default()), 1),
+    ]);
+    for src in [
+        "a > b",
+        "a <= 3",
+        "a == b",
+        "a != 1",
+        "a + b < 10",
+        "a * 2 - b >= a",
+        "starts_with(a, 'ab')",
+        "starts_with(a, b)",
+        "is_int(a) && a > 0",
+        "a > 0 || b > 0",
+        "if(a > 0, true, false)",
+        "a",
+    ] {
+        let mut expr = parse_expressions(src, &Default::default()).unwrap();
+        expr.fill_binding_indices(&binding_map).unwrap();
+        let bytecodes = expr.compile().unwrap();
+        let span = expr.span();
+        let mut stack = vec![];
+        for rows in [&rows, &int_rows] {
+            let expected = rows
+                .iter()
+                .map(|row| eval_bytecode_pred(&bytecodes, row, &mut stack, span).ok())
+                .collect::<Option<Vec<_>>>();
+            let batched = eval_bytecode_pred_batch(&bytecodes, rows, &mut stack, span).ok();
+            assert_eq!(batched, expected, "{src}");
+        }
+    }
+}
+
+#[test]
+fn batched_filters() {
+    let db = DbInstance::default();
+
+    let res = db
+        .run_default(
+            r#"
+    ?[count(x)] := x in int_range(5000), x % 3 == 0, x > 100, starts_with(to_string(x), '1')
+    "#,
+        )
+        .unwrap();
+    let expected = (0..5000)
+        .filter(|x| x % 3 == 0 && *x > 100 && x.to_string().starts_with('1'))
+        .count();
+    assert_eq!(res.rows[0][0], DataValue::from(expected as i64));
+
+    // rows after the limit are never reported, even if they would fail the filter
+    let res = db
+        .run_default(
+            r#"
+    ?[x] := x in concat(int_range(1500), ['a']), x >= 0
+    :limit 3
+    "#,
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 3);
+
+    assert!(db
+        .run_default(
+            r#"
+    ?[x] := x in concat(int_range(1500), ['a']), x >= 0
+    "#,
+        )
+        .is_err());
+}
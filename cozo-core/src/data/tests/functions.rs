@@ -1487,3 +1487,363 @@ fn test_range() {
         .into_json();
     assert_eq!(res["rows"][0][0], json!([15, 13, 11, 9, 7, 5]));
 }
+
+#[test]
+fn test_json_path_functions() {
+    let db = DbInstance::default();
+    let eval = |expr: &str| {
+        db.run_default(&format!("?[a] := a = {expr}"))
+            .unwrap()
+            .into_json()["rows"][0][0]
+            .clone()
+    };
+    let doc = r#"parse_json('{"a": [{"b": 1}, {"b": 2, "c": {"b": 3}}], "d": "x"}')"#;
+    assert_eq!(eval(&format!("jsonpath({doc}, '$.a[*].b')")), json!([1, 2]));
+    assert_eq!(eval(&format!("jsonpath({doc}, '$..b')")), json!([1, 2, 3]));
+    assert_eq!(
+        eval(&format!("jsonpath({doc}, '$.a[-1].c')")),
+        json!([{"b": 3}])
+    );
+    assert_eq!(eval(&format!("jsonpath({doc}, \"$['d']\")")), json!(["x"]));
+    assert_eq!(eval(&format!("jsonpath({doc}, '$.nothing')")), json!([]));
+    assert!(db
+        .run_default("?[a] := a = jsonpath(json({}), 'a.b')")
+        .is_err());
+
+    assert_eq!(
+        eval(&format!("json_set({doc}, '$.a[0].b', 10)")),
+        json!({"a": [{"b": 10}, {"b": 2, "c": {"b": 3}}], "d": "x"})
+    );
+    assert_eq!(
+        eval("json_set(json({}), '$.x.y', [1, 2])"),
+        json!({"x": {"y": [1, 2]}})
+    );
+    assert_eq!(
+        eval("json_set(json({}), ['x', 'y'], 1)"),
+        json!({"x": {"y": 1}})
+    );
+    assert!(db
+        .run_default("?[a] := a = json_set(json({}), '$.x[*]', 1)")
+        .is_err());
+
+    assert_eq!(
+        eval(&format!("json_remove({doc}, '$.a[1].c')")),
+        json!({"a": [{"b": 1}, {"b": 2}], "d": "x"})
+    );
+    assert_eq!(
+        eval(&format!("json_remove({doc}, '$.a[5]')")),
+        json!({"a": [{"b": 1}, {"b": 2, "c": {"b": 3}}], "d": "x"})
+    );
+
+    assert_eq!(
+        eval(
+            r#"json_merge_patch(parse_json('{"a": "b", "c": {"d": "e", "f": "g"}}'),
+                                parse_json('{"a": "z", "c": {"f": null}}'))"#
+        ),
+        json!({"a": "z", "c": {"d": "e"}})
+    );
+    assert_eq!(
+        eval(r#"json_merge_patch(parse_json('{"a": [1, 2]}'), parse_json('{"a": [3]}'))"#),
+        json!({"a": [3]})
+    );
+    assert_eq!(
+        eval(r#"json_merge_patch(parse_json('[1]'), parse_json('{"a": 1}'))"#),
+        json!({"a": 1})
+    );
+
+    assert_eq!(eval(&format!("json_get({doc}, 'd')")), json!("x"));
+    assert_eq!(
+        eval(&format!("json_get({doc}, ['a', 1, 'c', 'b'])")),
+        json!(3)
+    );
+    assert_eq!(eval(&format!("json_get({doc}, ['a', 5])")), json!(null));
+    assert_eq!(eval("json_get(null, 'a')"), json!(null));
+}
+
+#[test]
+fn test_list_reshaping() {
+    let l = |items: &[i64]| DataValue::List(items.iter().map(|i| DataValue::from(*i)).collect());
+    assert_eq!(
+        op_zip(&[l(&[1, 2, 3]), l(&[4, 5])]).unwrap(),
+        DataValue::List(vec![l(&[1, 4]), l(&[2, 5])])
+    );
+    assert_eq!(
+        op_zip(&[l(&[1, 2]), l(&[3, 4]), l(&[5, 6])]).unwrap(),
+        DataValue::List(vec![l(&[1, 3, 5]), l(&[2, 4, 6])])
+    );
+    assert!(op_zip(&[l(&[1]), DataValue::from(1)]).is_err());
+    assert_eq!(
+        op_enumerate(&[DataValue::List(vec![
+            DataValue::from("a"),
+            DataValue::from("b")
+        ])])
+        .unwrap(),
+        DataValue::List(vec![
+            DataValue::List(vec![DataValue::from(0), DataValue::from("a")]),
+            DataValue::List(vec![DataValue::from(1), DataValue::from("b")]),
+        ])
+    );
+    assert_eq!(op_enumerate(&[l(&[])]).unwrap(), l(&[]));
+    assert_eq!(
+        op_list_unique(&[l(&[3, 1, 3, 2, 1])]).unwrap(),
+        l(&[3, 1, 2])
+    );
+}
+
+#[test]
+fn test_fuzzy_matching() {
+    let s = |s: &str| DataValue::from(s);
+    assert_eq!(
+        op_levenshtein(&[s("kitten"), s("sitting")]).unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        op_levenshtein(&[s(""), s("abc")]).unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        op_levenshtein(&[s("été"), s("ete")]).unwrap(),
+        DataValue::from(2)
+    );
+    assert!(op_levenshtein(&[s("a"), DataValue::from(1)]).is_err());
+
+    let jw = |a: &str, b: &str| op_jaro_winkler(&[s(a), s(b)]).unwrap().get_float().unwrap();
+    assert!(jw("MARTHA", "MARHTA").abs_diff_eq(&0.9611, 1e-4));
+    assert!(jw("DWAYNE", "DUANE").abs_diff_eq(&0.84, 1e-4));
+    assert!(jw("DIXON", "DICKSONX").abs_diff_eq(&0.8133, 1e-4));
+    assert_eq!(jw("abc", "abc"), 1.);
+    assert_eq!(jw("abc", "xyz"), 0.);
+    assert_eq!(jw("", ""), 1.);
+
+    for (name, code) in [
+        ("Robert", "R163"),
+        ("Rupert", "R163"),
+        ("Rubin", "R150"),
+        ("Ashcraft", "A261"),
+        ("Tymczak", "T522"),
+        ("Pfister", "P236"),
+        ("Honeyman", "H555"),
+        ("lee", "L000"),
+        ("123", ""),
+    ] {
+        assert_eq!(op_soundex(&[s(name)]).unwrap(), s(code), "{name}");
+    }
+
+    assert_eq!(
+        op_split_n(&[s("a,b,c"), s(","), DataValue::from(2)]).unwrap(),
+        DataValue::List(vec![s("a"), s("b,c")])
+    );
+    assert!(op_split_n(&[s("a,b,c"), s(","), DataValue::from(0)]).is_err());
+
+    let db = DbInstance::default();
+    let res = db
+        .run_default("?[s] := s = regex_replace_all('2024-01-31, 2023-12-01', '([0-9]+)-([0-9]+)-([0-9]+)', '$3/$2/$1')")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"][0][0], json!("31/01/2024, 01/12/2023"));
+}
+
+#[cfg(feature = "fancy-regex")]
+#[test]
+fn test_fancy_regex() {
+    let db = DbInstance::default();
+    let eval = |expr: &str| {
+        db.run_default(&format!("?[a] := a = {expr}"))
+            .unwrap()
+            .into_json()["rows"][0][0]
+            .clone()
+    };
+    assert_eq!(
+        eval("regex_matches_fancy('foofoo', '^(foo)\\\\1$')"),
+        json!(true)
+    );
+    assert_eq!(
+        eval("regex_matches_fancy('foobar', '^(foo)\\\\1$')"),
+        json!(false)
+    );
+    assert_eq!(
+        eval("regex_extract_fancy('price: 10 USD, 20 EUR, 30 USD', '[0-9]+(?= USD)')"),
+        json!(["10", "30"])
+    );
+    assert_eq!(
+        eval("regex_extract_first_fancy('a1 b2', '(?<=b)[0-9]')"),
+        json!("2")
+    );
+    assert_eq!(
+        eval("regex_extract_first_fancy('a1', '(?<=b)[0-9]')"),
+        json!(null)
+    );
+    assert_eq!(
+        eval("regex_replace_all_fancy('Q:abc Q:def', '(?<=Q:)([a-z])([a-z]+)', '$2$1')"),
+        json!("Q:bca Q:efd")
+    );
+    assert_eq!(
+        eval("regex_replace_fancy('aa bb', '(\\\\w)\\\\1', '<$1>')"),
+        json!("<a> bb")
+    );
+    // the standard engine rejects lookaround
+    assert!(db
+        .run_default("?[a] := a = regex_matches('ab', 'a(?=b)')")
+        .is_err());
+    assert!(db
+        .run_default("?[a] := a = regex_matches_fancy('ab', 'a(?=b')")
+        .is_err());
+}
+
+#[test]
+fn test_geo_functions() {
+    assert_eq!(
+        op_geohash_encode(&[
+            DataValue::List(vec![DataValue::from(57.64911), DataValue::from(10.40744)]),
+            DataValue::from(11)
+        ])
+        .unwrap(),
+        DataValue::from("u4pruydqqvj")
+    );
+    let decoded = op_geohash_decode(&[DataValue::from("u4pruydqqvj")]).unwrap();
+    let decoded = decoded.get_slice().unwrap();
+    assert!((decoded[0].get_float().unwrap() - 57.64911).abs() < 1e-5);
+    assert!((decoded[1].get_float().unwrap() - 10.40744).abs() < 1e-5);
+    assert!(op_geohash_decode(&[DataValue::from("u4pa")]).is_err());
+
+    let db = DbInstance::default();
+    let eval = |expr: &str| {
+        db.run_default(&format!("?[a] := a = {expr}"))
+            .unwrap()
+            .into_json()["rows"][0][0]
+            .clone()
+    };
+    let d = eval("haversine_km(geo_point(48.8566, 2.3522), [51.5074, -0.1278])");
+    assert!((d.as_f64().unwrap() - 343.5).abs() < 1., "{}", d);
+    assert!(db.run_default("?[a] := a = geo_point(91, 0)").is_err());
+    assert_eq!(
+        eval("geo_contains([[48, 2], [49, 3]], [48.8566, 2.3522])"),
+        json!(true)
+    );
+    assert_eq!(
+        eval("geo_contains([[48, 2], [49, 3]], [51.5074, -0.1278])"),
+        json!(false)
+    );
+    // a bounding box crossing the antimeridian
+    assert_eq!(
+        eval("geo_contains([[-20, 179], [-10, -179]], [-17, -179.5])"),
+        json!(true)
+    );
+    assert_eq!(
+        eval("geo_contains([[-20, 179], [-10, -179]], [-17, 0])"),
+        json!(false)
+    );
+    let triangle = "[[0, 0], [0, 10], [10, 0]]";
+    assert_eq!(
+        eval(&format!("geo_contains({triangle}, [2, 2])")),
+        json!(true)
+    );
+    assert_eq!(
+        eval(&format!("geo_contains({triangle}, [6, 6])")),
+        json!(false)
+    );
+    assert_eq!(
+        eval("geo_contains(geo_bbox([48.8566, 2.3522], 20), [48.8049, 2.1204])"),
+        json!(true)
+    );
+    assert_eq!(
+        eval("geo_contains(geo_bbox([48.8566, 2.3522], 10), [48.8049, 2.1204])"),
+        json!(false)
+    );
+}
+
+#[test]
+fn test_decimals() {
+    let db = DbInstance::default();
+    let eval = |expr: &str| {
+        db.run_default(&format!("?[x] := x = {expr}"))
+            .unwrap()
+            .into_json()["rows"][0][0]
+            .clone()
+    };
+    assert_eq!(
+        eval("to_decimal('0.1') + to_decimal('0.2') == to_decimal('0.3')"),
+        json!(true)
+    );
+    assert_eq!(eval("0.1 + 0.2 == 0.3"), json!(false));
+    assert_eq!(
+        eval("to_string(to_bigint(9223372036854775807) * 10 + 1)"),
+        json!("92233720368547758071")
+    );
+    assert_eq!(eval("to_string(to_decimal('1.5') - 2)"), json!("-0.5"));
+    assert_eq!(eval("to_string(to_decimal(1) / 8)"), json!("0.125"));
+    assert_eq!(eval("to_string(to_decimal('2.345', 2))"), json!("2.35"));
+    assert_eq!(eval("to_string(to_decimal(0.1))"), json!("0.1"));
+    assert_eq!(eval("to_string(to_bigint('-12.7'))"), json!("-12"));
+    assert_eq!(eval("to_string(round(to_decimal('-2.5')))"), json!("-3"));
+    assert_eq!(eval("to_string(floor(to_decimal('-2.5')))"), json!("-3"));
+    assert_eq!(eval("to_string(ceil(to_decimal('-2.5')))"), json!("-2"));
+    assert_eq!(eval("to_string(to_decimal('7.5') % 2)"), json!("1.5"));
+    assert_eq!(
+        eval("to_string(max(1, to_decimal('1.5'), -3))"),
+        json!("1.5")
+    );
+    assert_eq!(eval("to_decimal('1.5') > 1"), json!(true));
+    assert_eq!(eval("to_decimal(1) == 1"), json!(true));
+    assert_eq!(eval("to_decimal('0.5') + 0.25"), json!(0.75));
+    assert_eq!(eval("to_int(to_decimal('-3.9'))"), json!(-3));
+    assert_eq!(eval("to_float(to_decimal('2.5'))"), json!(2.5));
+    assert_eq!(eval("is_decimal(to_bigint(1))"), json!(true));
+    assert_eq!(eval("is_decimal(1)"), json!(false));
+    assert_eq!(eval("to_decimal('1.5')"), json!("1.5"));
+    assert!(db.run_default("?[x] := x = to_decimal(1) / 0").is_err());
+    assert!(db.run_default("?[x] := x = to_decimal('abc')").is_err());
+}
+
+#[test]
+fn test_durations() {
+    let db = DbInstance::default();
+    let eval = |expr: &str| {
+        db.run_default(&format!("?[x] := x = {expr}"))
+            .unwrap()
+            .into_json()["rows"][0][0]
+            .clone()
+    };
+    assert_eq!(eval("to_duration('3h15m')"), json!("3h15m"));
+    assert_eq!(eval("to_duration(' -1d 12h ')"), json!("-1d12h"));
+    assert_eq!(eval("to_duration('1.5s')"), json!("1s500ms"));
+    assert_eq!(eval("to_duration('2w')"), json!("14d"));
+    assert_eq!(eval("to_duration('250us')"), json!("250us"));
+    assert_eq!(eval("to_duration(90)"), json!("1m30s"));
+    assert_eq!(eval("to_duration('0m')"), json!("0s"));
+    assert_eq!(
+        eval("to_duration('1h') + to_duration('30m') + to_duration('45s')"),
+        json!("1h30m45s")
+    );
+    assert_eq!(
+        eval("to_duration('1h') - to_duration('90m')"),
+        json!("-30m")
+    );
+    assert_eq!(eval("to_duration('1h30m') * 2"), json!("3h"));
+    assert_eq!(eval("0.5 * to_duration('1h')"), json!("30m"));
+    assert_eq!(eval("to_duration('1h') / 4"), json!("15m"));
+    assert_eq!(eval("to_duration('1h') / to_duration('15m')"), json!(4.0));
+    assert_eq!(eval("-to_duration('1h')"), json!("-1h"));
+    assert_eq!(eval("abs(to_duration('-1h'))"), json!("1h"));
+    assert_eq!(eval("to_duration('1h') > to_duration('59m')"), json!(true));
+    assert_eq!(eval("to_duration('60m') == to_duration('1h')"), json!(true));
+    assert_eq!(eval("to_float(to_duration('1m30s'))"), json!(90.0));
+    assert_eq!(eval("to_int(to_duration('1m30s500ms'))"), json!(90));
+    assert_eq!(eval("to_string(to_duration('36h'))"), json!("1d12h"));
+    assert_eq!(eval("is_duration(to_duration('1s'))"), json!(true));
+    assert_eq!(eval("is_duration(1)"), json!(false));
+    // timestamps given as seconds, such as those from `now()`
+    assert_eq!(eval("100 + to_duration('1m')"), json!(160.0));
+    assert_eq!(
+        eval("format_timestamp(parse_timestamp('2023-01-31T12:00:00Z') - to_duration('31d'))"),
+        json!("2022-12-31T12:00:00+00:00")
+    );
+    assert!(db.run_default("?[x] := x = to_duration('3x')").is_err());
+    assert!(db.run_default("?[x] := x = to_duration('3')").is_err());
+    assert!(db.run_default("?[x] := x = to_duration('h')").is_err());
+    assert!(db
+        .run_default("?[x] := x = to_duration('1h') + to_duration('1h') + 'a'")
+        .is_err());
+    assert!(db.run_default("?[x] := x = to_duration('1h') > 1").is_err());
+    assert!(db.run_default("?[x] := x = to_duration('1h') / 0").is_err());
+}
@@ -7,10 +7,16 @@
  *
  */
 
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use serde::Serialize;
 use uuid::Uuid;
 
 use crate::data::memcmp::{decode_bytes, MemCmpEncoder};
-use crate::data::value::{DataValue, Num, UuidWrapper};
+use crate::data::tuple::{TupleT, TupleView};
+use crate::data::value::{DataValue, Num, UuidWrapper, Vector};
+use crate::runtime::relation::RelationId;
 
 #[test]
 fn encode_decode_num() {
@@ -135,3 +141,134 @@ fn encode_decode_datavalues() {
     assert!(remaining.is_empty());
     assert_eq!(decoded, v);
 }
+
+#[test]
+fn encode_decode_decimals() {
+    use bigdecimal::BigDecimal;
+    use std::str::FromStr;
+
+    let dec = |s: &str| DataValue::Decimal(BigDecimal::from_str(s).unwrap());
+    let mut vals = vec![
+        dec("0"),
+        dec("0.00"),
+        dec("1"),
+        dec("1.0"),
+        dec("-1"),
+        dec("0.1"),
+        dec("0.101"),
+        dec("0.12"),
+        dec("-0.1"),
+        dec("-0.101"),
+        dec("-0.12"),
+        dec("123456789012345678901234567890"),
+        dec("123456789012345678901234567891"),
+        dec("-123456789012345678901234567890"),
+        dec("-123456789012345678901234567891"),
+        dec("9223372036854775807.5"),
+        dec("1e-400"),
+        dec("-1e-400"),
+        dec("1e400"),
+        DataValue::from(0),
+        DataValue::from(1),
+        DataValue::from(-1),
+        DataValue::from(0.1),
+        DataValue::from(1.0),
+        DataValue::from(i64::MAX),
+        DataValue::from(i64::MIN),
+        DataValue::from(f64::INFINITY),
+        DataValue::from(f64::NEG_INFINITY),
+        DataValue::from(""),
+    ];
+    let mut collected = vec![];
+    for v in vals.iter() {
+        let mut encoder = vec![];
+        encoder.encode_datavalue(v);
+        let (decoded, rest) = DataValue::decode_from_key(&encoder);
+        assert_eq!(&decoded, v);
+        assert!(rest.is_empty());
+        collected.push(encoder);
+    }
+    collected.sort();
+    vals.sort();
+    let decoded = collected
+        .iter()
+        .map(|c| DataValue::decode_from_key(c).0)
+        .collect::<Vec<_>>();
+    assert_eq!(decoded, vals);
+    assert!(dec("0.1") < dec("0.101"));
+    assert!(dec("-0.101") < dec("-0.1"));
+}
+
+#[test]
+fn encode_decode_durations() {
+    let mut vals = vec![
+        DataValue::Duration(0),
+        DataValue::Duration(1),
+        DataValue::Duration(-1),
+        DataValue::Duration(i64::MAX),
+        DataValue::Duration(i64::MIN),
+        DataValue::Duration(3_600_000_000),
+    ];
+    let mut collected = vec![];
+    for v in vals.iter() {
+        let mut encoder = vec![];
+        encoder.encode_datavalue(v);
+        let (decoded, rest) = DataValue::decode_from_key(&encoder);
+        assert_eq!(&decoded, v);
+        assert!(rest.is_empty());
+        collected.push(encoder);
+    }
+    collected.sort();
+    vals.sort();
+    let decoded = collected
+        .iter()
+        .map(|c| DataValue::decode_from_key(c).0)
+        .collect::<Vec<_>>();
+    assert_eq!(decoded, vals);
+}
+
+#[test]
+fn tuple_view_decodes_needed_columns() {
+    let vals = vec![
+        DataValue::Null,
+        DataValue::from(true),
+        DataValue::from(i64::MAX),
+        DataValue::from(-1.5),
+        DataValue::Decimal(BigDecimal::from_str("-123.456").unwrap()),
+        DataValue::Decimal(BigDecimal::from_str("0").unwrap()),
+        DataValue::from("a string longer than a group"),
+        DataValue::Bytes(vec![0, 255, 1, 2, 3, 4, 5, 6, 7]),
+        DataValue::Uuid(UuidWrapper(Uuid::from_u128(123456789))),
+        DataValue::List(vec![DataValue::from(1), DataValue::from("x")]),
+        DataValue::Vec(Vector::F32(ndarray::Array1::from(vec![1., 2., 3.]))),
+        DataValue::Duration(-42),
+        DataValue::from(7),
+    ];
+    let n_keys = 7;
+    let key = vals[..n_keys].to_vec().encode_as_key(RelationId(9));
+    let mut val = vec![0; 8];
+    vals[n_keys..]
+        .serialize(&mut rmp_serde::Serializer::new(&mut val))
+        .unwrap();
+    let view = TupleView::new(&key, &val);
+
+    assert_eq!(view.decode_columns(&[]), vals);
+    assert_eq!(view.decode_columns(&vec![true; vals.len()]), vals);
+    for mask in [
+        0b1010101010101u32,
+        0b0101010101010,
+        0b1000000000001,
+        0b0000000000111,
+        0,
+    ] {
+        let needed = (0..vals.len())
+            .map(|i| mask & (1 << i) != 0)
+            .collect::<Vec<_>>();
+        let expected = vals
+            .iter()
+            .zip(&needed)
+            .map(|(v, n)| if *n { v.clone() } else { DataValue::Null })
+            .collect::<Vec<_>>();
+        assert_eq!(view.decode_columns(&needed), expected);
+    }
+}
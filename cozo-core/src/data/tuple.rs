@@ -8,7 +8,9 @@
 
 use crate::data::functions::TERMINAL_VALIDITY;
 use miette::Result;
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, SeqAccess, Visitor};
 use std::cmp::Reverse;
+use std::fmt;
 
 use crate::data::memcmp::MemCmpEncoder;
 use crate::data::value::{DataValue, Validity, ValidityTs};
@@ -49,6 +51,88 @@ pub fn decode_tuple_from_key(key: &[u8], size_hint: usize) -> Tuple {
     ret
 }
 
+/// A stored tuple borrowing its key and value bytes, decoding columns only when asked.
+pub(crate) struct TupleView<'a> {
+    key: &'a [u8],
+    val: &'a [u8],
+}
+
+impl<'a> TupleView<'a> {
+    pub(crate) fn new(key: &'a [u8], val: &'a [u8]) -> Self {
+        Self { key, val }
+    }
+    /// Decode the columns marked in `needed`, putting `Null` in the place of the others.
+    /// Columns past the end of `needed` are always decoded.
+    pub(crate) fn decode_columns(&self, needed: &[bool]) -> Tuple {
+        let is_needed = |i: usize| needed.get(i).copied().unwrap_or(true);
+        let mut ret = Vec::with_capacity(needed.len());
+        let mut remaining = &self.key[ENCODED_KEY_MIN_LEN..];
+        while !remaining.is_empty() {
+            if is_needed(ret.len()) {
+                let (val, next) = DataValue::decode_from_key(remaining);
+                ret.push(val);
+                remaining = next;
+            } else {
+                ret.push(DataValue::Null);
+                remaining = DataValue::skip_in_key(remaining);
+            }
+        }
+        if self.val.is_empty() {
+            return ret;
+        }
+        if needed.len() > ret.len() && (ret.len()..needed.len()).all(|i| !is_needed(i)) {
+            ret.resize(needed.len(), DataValue::Null);
+            return ret;
+        }
+        let mut de = rmp_serde::Deserializer::from_read_ref(&self.val[ENCODED_KEY_MIN_LEN..]);
+        NeededColumns {
+            needed,
+            out: &mut ret,
+        }
+        .deserialize(&mut de)
+        .unwrap();
+        ret
+    }
+}
+
+/// Deserializes the stored non-key values, skipping those not needed.
+struct NeededColumns<'n> {
+    needed: &'n [bool],
+    out: &'n mut Tuple,
+}
+
+impl<'de, 'n> DeserializeSeed<'de> for NeededColumns<'n> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'n> Visitor<'de> for NeededColumns<'n> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a sequence of values")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        loop {
+            if self.needed.get(self.out.len()).copied().unwrap_or(true) {
+                match seq.next_element::<DataValue>()? {
+                    Some(v) => self.out.push(v),
+                    None => return Ok(()),
+                }
+            } else {
+                match seq.next_element::<IgnoredAny>()? {
+                    Some(_) => self.out.push(DataValue::Null),
+                    None => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
 const DEFAULT_SIZE_HINT: usize = 16;
 
 /// Check if the tuple key passed in should be a valid return for a validity query.
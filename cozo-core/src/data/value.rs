@@ -8,6 +8,7 @@
 
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
+use bigdecimal::{BigDecimal, ToPrimitive};
 use ndarray::Array1;
 use std::cmp::{Ordering, Reverse};
 use std::collections::BTreeSet;
@@ -15,6 +16,7 @@ use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 
+use crate::data::duration::format_duration;
 use crate::data::json::JsonValue;
 use crate::data::relation::VecElementType;
 use ordered_float::OrderedFloat;
@@ -140,9 +142,7 @@ impl From<(i64, bool)> for Validity {
 }
 
 /// A Value in the database
-#[derive(
-Clone, PartialEq, Eq, PartialOrd, Ord, serde_derive::Deserialize, serde_derive::Serialize, Hash,
-)]
+#[derive(Clone, PartialEq, Eq, serde_derive::Deserialize, serde_derive::Serialize, Hash)]
 pub enum DataValue {
     /// null
     Null,
@@ -171,6 +171,10 @@ pub enum DataValue {
     Validity(Validity),
     /// bottom type, used internally only
     Bot,
+    /// exact decimal number of arbitrary precision, also used for big integers
+    Decimal(BigDecimal),
+    /// duration in microseconds
+    Duration(i64),
 }
 
 /// Wrapper for JsonValue
@@ -597,6 +601,73 @@ impl Ord for Num {
     }
 }
 
+/// An `f64` close to the decimal, monotonic in it
+pub(crate) fn decimal_approx_f64(d: &BigDecimal) -> f64 {
+    d.to_string().parse().unwrap_or(f64::NAN)
+}
+
+impl DataValue {
+    /// Position of the variant in the total order of values, decimals ranking with numbers
+    fn type_rank(&self) -> u8 {
+        match self {
+            DataValue::Null => 0,
+            DataValue::Bool(_) => 1,
+            DataValue::Num(_) | DataValue::Decimal(_) => 2,
+            DataValue::Str(_) => 3,
+            DataValue::Bytes(_) => 4,
+            DataValue::Uuid(_) => 5,
+            DataValue::Regex(_) => 6,
+            DataValue::List(_) => 7,
+            DataValue::Set(_) => 8,
+            DataValue::Vec(_) => 9,
+            DataValue::Json(_) => 10,
+            DataValue::Validity(_) => 11,
+            DataValue::Duration(_) => 12,
+            DataValue::Bot => 13,
+        }
+    }
+}
+
+impl PartialOrd for DataValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DataValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (DataValue::Null, DataValue::Null) | (DataValue::Bot, DataValue::Bot) => {
+                Ordering::Equal
+            }
+            (DataValue::Bool(l), DataValue::Bool(r)) => l.cmp(r),
+            (DataValue::Num(l), DataValue::Num(r)) => l.cmp(r),
+            (DataValue::Decimal(l), DataValue::Decimal(r)) => l.cmp(r),
+            // same order as the memcmp encoding: by the approximate value first,
+            // then ints before decimals before floats
+            (DataValue::Num(n), DataValue::Decimal(d)) => n
+                .get_float()
+                .total_cmp(&decimal_approx_f64(d))
+                .then(match n {
+                    Num::Int(_) => Ordering::Less,
+                    Num::Float(_) => Ordering::Greater,
+                }),
+            (DataValue::Decimal(_), DataValue::Num(_)) => other.cmp(self).reverse(),
+            (DataValue::Str(l), DataValue::Str(r)) => l.cmp(r),
+            (DataValue::Bytes(l), DataValue::Bytes(r)) => l.cmp(r),
+            (DataValue::Uuid(l), DataValue::Uuid(r)) => l.cmp(r),
+            (DataValue::Regex(l), DataValue::Regex(r)) => l.cmp(r),
+            (DataValue::List(l), DataValue::List(r)) => l.cmp(r),
+            (DataValue::Set(l), DataValue::Set(r)) => l.cmp(r),
+            (DataValue::Vec(l), DataValue::Vec(r)) => l.cmp(r),
+            (DataValue::Json(l), DataValue::Json(r)) => l.cmp(r),
+            (DataValue::Validity(l), DataValue::Validity(r)) => l.cmp(r),
+            (DataValue::Duration(l), DataValue::Duration(r)) => l.cmp(r),
+            (l, r) => l.type_rank().cmp(&r.type_rank()),
+        }
+    }
+}
+
 impl Debug for DataValue {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{self}")
@@ -624,6 +695,8 @@ impl Display for DataValue {
             DataValue::List(ls) => f.debug_list().entries(ls).finish(),
             DataValue::Set(s) => f.debug_list().entries(s).finish(),
             DataValue::Bot => write!(f, "null"),
+            DataValue::Decimal(d) => write!(f, "to_decimal({:?})", d.to_string()),
+            DataValue::Duration(d) => write!(f, "to_duration({:?})", format_duration(*d)),
             DataValue::Validity(v) => f
                 .debug_struct("Validity")
                 .field("timestamp", &v.timestamp.0)
@@ -674,6 +747,7 @@ impl DataValue {
     pub fn get_int(&self) -> Option<i64> {
         match self {
             DataValue::Num(n) => n.get_int(),
+            DataValue::Decimal(d) if d.is_integer() => d.to_i64(),
             _ => None,
         }
     }
@@ -689,6 +763,7 @@ impl DataValue {
     pub fn get_float(&self) -> Option<f64> {
         match self {
             DataValue::Num(n) => Some(n.get_float()),
+            DataValue::Decimal(d) => Some(decimal_approx_f64(d)),
             _ => None,
         }
     }
@@ -25,6 +25,9 @@ use crate::runtime::temp_store::RegularTempStore;
 pub(crate) struct LabelPropagation;
 
 impl FixedRule for LabelPropagation {
+    fn is_deterministic(&self) -> bool {
+        false
+    }
     fn run(
         &self,
         payload: FixedRulePayload<'_, '_>,
@@ -35,7 +38,7 @@ impl FixedRule for LabelPropagation {
         let undirected = payload.bool_option("undirected", Some(false))?;
         let max_iter = payload.pos_integer_option("max_iter", Some(10))?;
         let (graph, indices, _inv_indices) = edges.as_directed_weighted_graph(undirected, true)?;
-        let labels = label_propagation(&graph, max_iter, poison)?;
+        let labels = label_propagation(&graph, max_iter, payload.rng(), poison)?;
         for (idx, label) in labels.into_iter().enumerate() {
             let node = indices[idx].clone();
             out.put(vec![DataValue::from(label as i64), node]);
@@ -56,11 +59,11 @@ impl FixedRule for LabelPropagation {
 fn label_propagation(
     graph: &DirectedCsrGraph<u32, (), f32>,
     max_iter: usize,
+    mut rng: impl Rng,
     poison: Poison,
 ) -> Result<Vec<u32>> {
     let n_nodes = graph.node_count();
     let mut labels = (0..n_nodes).collect_vec();
-    let mut rng = thread_rng();
     let mut iter_order = (0..n_nodes).collect_vec();
     for _ in 0..max_iter {
         iter_order.shuffle(&mut rng);
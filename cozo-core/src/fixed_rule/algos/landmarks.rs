@@ -0,0 +1,330 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Point-to-point shortest paths with A*, landmarks and the triangle inequality (ALT).
+//!
+//! `LandmarkDistances` precomputes the distances from and to a few landmark nodes,
+//! which are meant to be stored in a relation, and `ShortestPathALT` uses them
+//! as a lower bound of the remaining distance to the goal.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighborsWithValues, Graph};
+use itertools::Itertools;
+use miette::Result;
+use ordered_float::OrderedFloat;
+use priority_queue::PriorityQueue;
+use rayon::prelude::*;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{BadExprValueError, FixedRule, FixedRuleInputRelation, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+pub(crate) struct LandmarkDistances;
+
+impl FixedRule for LandmarkDistances {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let num_landmarks = payload.pos_integer_option("num_landmarks", Some(8))?;
+
+        let (graph, indices, inv_indices) = edges.as_directed_weighted_graph(undirected, false)?;
+
+        let landmarks = match payload.get_input(1) {
+            Ok(rel) => {
+                let mut landmarks = vec![];
+                for tuple in rel.iter()? {
+                    let tuple = tuple?;
+                    if let Some(idx) = inv_indices.get(&tuple[0]) {
+                        if !landmarks.contains(idx) {
+                            landmarks.push(*idx);
+                        }
+                    }
+                }
+                landmarks
+                    .into_par_iter()
+                    .map(|l| -> Result<(u32, Vec<f32>)> {
+                        Ok((l, distances(&graph, l, false, poison.clone())?))
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            }
+            Err(_) => farthest_landmarks(&graph, num_landmarks, poison.clone())?,
+        };
+
+        let with_backward = landmarks
+            .into_par_iter()
+            .map(|(l, from)| -> Result<(u32, Vec<f32>, Vec<f32>)> {
+                let to = distances(&graph, l, true, poison.clone())?;
+                Ok((l, from, to))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for (l, from, to) in with_backward {
+            for (node, (d_from, d_to)) in from.into_iter().zip(to).enumerate() {
+                // pairs that are not connected are left out
+                if !d_from.is_finite() && !d_to.is_finite() {
+                    continue;
+                }
+                let as_value = |d: f32| {
+                    if d.is_finite() {
+                        DataValue::from(d as f64)
+                    } else {
+                        DataValue::Null
+                    }
+                };
+                out.put(vec![
+                    indices[l as usize].clone(),
+                    indices[node].clone(),
+                    as_value(d_from),
+                    as_value(d_to),
+                ]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(4)
+    }
+}
+
+/// Distances from `start` to every node, or from every node to `start` if `backward`.
+fn distances(
+    edges: &DirectedCsrGraph<u32, (), f32>,
+    start: u32,
+    backward: bool,
+    poison: Poison,
+) -> Result<Vec<f32>> {
+    let mut distance = vec![f32::INFINITY; edges.node_count() as usize];
+    let mut pq = PriorityQueue::new();
+    distance[start as usize] = 0.;
+    pq.push(start, Reverse(OrderedFloat(0.)));
+    while let Some((node, Reverse(OrderedFloat(cost)))) = pq.pop() {
+        let neighbours = if backward {
+            edges.in_neighbors_with_values(node)
+        } else {
+            edges.out_neighbors_with_values(node)
+        };
+        for target in neighbours {
+            let nxt_cost = cost + target.value;
+            if nxt_cost < distance[target.target as usize] {
+                pq.push_increase(target.target, Reverse(OrderedFloat(nxt_cost)));
+                distance[target.target as usize] = nxt_cost;
+            }
+        }
+        poison.check()?;
+    }
+    Ok(distance)
+}
+
+/// Pick landmarks one by one, each time the node farthest from those already picked,
+/// starting with the node farthest from the first node of the graph.
+/// Nodes not reachable from any landmark yet count as the farthest.
+/// Returns the landmarks together with their forward distances.
+fn farthest_landmarks(
+    edges: &DirectedCsrGraph<u32, (), f32>,
+    n: usize,
+    poison: Poison,
+) -> Result<Vec<(u32, Vec<f32>)>> {
+    let graph_size = edges.node_count() as usize;
+    let mut ret: Vec<(u32, Vec<f32>)> = vec![];
+    if graph_size == 0 {
+        return Ok(ret);
+    }
+    let mut closeness = distances(edges, 0, false, poison.clone())?;
+    let mut picked = BTreeSet::new();
+    while ret.len() < n.min(graph_size) {
+        let next = (0..graph_size as u32)
+            .filter(|node| !picked.contains(node))
+            .max_by(|a, b| {
+                let (da, db) = (closeness[*a as usize], closeness[*b as usize]);
+                da.total_cmp(&db).then_with(|| b.cmp(a))
+            })
+            .unwrap();
+        let from = distances(edges, next, false, poison.clone())?;
+        if ret.is_empty() {
+            closeness = from.clone();
+        } else {
+            for (c, d) in closeness.iter_mut().zip(from.iter()) {
+                *c = c.min(*d);
+            }
+        }
+        picked.insert(next);
+        ret.push((next, from));
+    }
+    Ok(ret)
+}
+
+pub(crate) struct ShortestPathALT;
+
+impl FixedRule for ShortestPathALT {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let landmark_rel = payload.get_input(1)?.ensure_min_len(4)?;
+        let starting = payload.get_input(2)?;
+        let goals = payload.get_input(3)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+
+        let (graph, indices, inv_indices) = edges.as_directed_weighted_graph(undirected, false)?;
+        let graph_size = graph.node_count() as usize;
+
+        #[allow(clippy::mutable_key_type)]
+        let mut landmarks: BTreeMap<DataValue, (Vec<f32>, Vec<f32>)> = BTreeMap::new();
+        for tuple in landmark_rel.iter()? {
+            let tuple = tuple?;
+            let node = match inv_indices.get(&tuple[1]) {
+                Some(idx) => *idx as usize,
+                None => continue,
+            };
+            let (from, to) = landmarks.entry(tuple[0].clone()).or_insert_with(|| {
+                (
+                    vec![f32::INFINITY; graph_size],
+                    vec![f32::INFINITY; graph_size],
+                )
+            });
+            let as_distance = |v: &DataValue| -> Result<f32> {
+                match v {
+                    DataValue::Null => Ok(f32::INFINITY),
+                    v => match v.get_float() {
+                        Some(f) if f >= 0. => Ok(f as f32),
+                        _ => Err(BadExprValueError(
+                            v.clone(),
+                            landmark_rel.span(),
+                            "landmark distances must be non-negative numbers or null".to_string(),
+                        )
+                        .into()),
+                    },
+                }
+            };
+            from[node] = as_distance(&tuple[2])?;
+            to[node] = as_distance(&tuple[3])?;
+        }
+        let landmarks = landmarks.into_values().collect_vec();
+
+        // nodes that are not in the graph are ignored, as in `ShortestPathDijkstra`
+        let node_indices = |rel: FixedRuleInputRelation<'_, '_>| -> Result<BTreeSet<u32>> {
+            let mut ret = BTreeSet::new();
+            for tuple in rel.iter()? {
+                if let Some(idx) = inv_indices.get(&tuple?[0]) {
+                    ret.insert(*idx);
+                }
+            }
+            Ok(ret)
+        };
+        let starting = node_indices(starting)?;
+        let goals = node_indices(goals)?;
+        for start in &starting {
+            for goal in &goals {
+                let (cost, path) = alt_search(&graph, *start, *goal, &landmarks, poison.clone())?;
+                out.put(vec![
+                    indices[*start as usize].clone(),
+                    indices[*goal as usize].clone(),
+                    DataValue::from(cost as f64),
+                    DataValue::List(
+                        path.into_iter()
+                            .map(|u| indices[u as usize].clone())
+                            .collect_vec(),
+                    ),
+                ]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(4)
+    }
+}
+
+/// A* search where the heuristic is the best lower bound given by the triangle inequality
+/// through any landmark: `d(v, t) >= d(l, t) - d(l, v)` and `d(v, t) >= d(v, l) - d(t, l)`.
+/// Landmark distances computed before the weights decreased may overestimate,
+/// in which case the path returned is not necessarily the shortest.
+fn alt_search(
+    edges: &DirectedCsrGraph<u32, (), f32>,
+    start: u32,
+    goal: u32,
+    landmarks: &[(Vec<f32>, Vec<f32>)],
+    poison: Poison,
+) -> Result<(f32, Vec<u32>)> {
+    let g = goal as usize;
+    let heuristic = |node: u32| -> f32 {
+        let v = node as usize;
+        let mut bound: f32 = 0.;
+        for (from, to) in landmarks {
+            let forward = from[g] - from[v];
+            if forward.is_finite() {
+                bound = bound.max(forward);
+            }
+            let backward = to[v] - to[g];
+            if backward.is_finite() {
+                bound = bound.max(backward);
+            }
+        }
+        bound
+    };
+
+    let graph_size = edges.node_count() as usize;
+    let mut distance = vec![f32::INFINITY; graph_size];
+    let mut back_pointers = vec![u32::MAX; graph_size];
+    let mut pq = PriorityQueue::new();
+    distance[start as usize] = 0.;
+    pq.push(start, Reverse(OrderedFloat(heuristic(start))));
+    while let Some((node, _)) = pq.pop() {
+        if node == goal {
+            let mut path = vec![];
+            let mut current = goal;
+            while current != start {
+                path.push(current);
+                current = back_pointers[current as usize];
+            }
+            path.push(start);
+            path.reverse();
+            return Ok((distance[g], path));
+        }
+        let cost = distance[node as usize];
+        for target in edges.out_neighbors_with_values(node) {
+            let nxt_node = target.target;
+            let nxt_cost = cost + target.value;
+            if nxt_cost < distance[nxt_node as usize] {
+                let h = heuristic(nxt_node);
+                distance[nxt_node as usize] = nxt_cost;
+                back_pointers[nxt_node as usize] = node;
+                pq.push_increase(nxt_node, Reverse(OrderedFloat(nxt_cost + h)));
+            }
+        }
+        poison.check()?;
+    }
+    Ok((f32::INFINITY, vec![]))
+}
@@ -0,0 +1,337 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Reverse;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, VecDeque};
+
+use miette::{bail, ensure, Result};
+use ordered_float::OrderedFloat;
+use priority_queue::PriorityQueue;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{BadExprValueError, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Nodes of one side of a bipartite graph, numbered in the order they are first seen.
+#[derive(Default)]
+struct Side {
+    nodes: Vec<DataValue>,
+    #[allow(clippy::mutable_key_type)]
+    indices: BTreeMap<DataValue, usize>,
+}
+
+impl Side {
+    fn index(&mut self, node: DataValue) -> usize {
+        match self.indices.entry(node) {
+            Entry::Occupied(ent) => *ent.get(),
+            Entry::Vacant(ent) => {
+                self.nodes.push(ent.key().clone());
+                *ent.insert(self.nodes.len() - 1)
+            }
+        }
+    }
+}
+
+/// Maximum cardinality matching between the nodes in the first column of the edges
+/// and those in the second column, with the Hopcroft–Karp algorithm.
+/// The two columns are separate sets of nodes even if they share values.
+pub(crate) struct MaximumBipartiteMatching;
+
+impl FixedRule for MaximumBipartiteMatching {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(2)?;
+        let mut left = Side::default();
+        let mut right = Side::default();
+        let mut adj: Vec<Vec<usize>> = vec![];
+        for tuple in edges.iter()? {
+            let mut tuple = tuple?.into_iter();
+            let u = left.index(tuple.next().unwrap());
+            let v = right.index(tuple.next().unwrap());
+            if u == adj.len() {
+                adj.push(vec![]);
+            }
+            adj[u].push(v);
+        }
+        let pair_u = hopcroft_karp(&adj, right.nodes.len(), &poison)?;
+        for (u, v) in pair_u.into_iter().enumerate() {
+            if let Some(v) = v {
+                out.put(vec![left.nodes[u].clone(), right.nodes[v].clone()]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// Returns the node matched with each left node.
+fn hopcroft_karp(
+    adj: &[Vec<usize>],
+    n_right: usize,
+    poison: &Poison,
+) -> Result<Vec<Option<usize>>> {
+    let n_left = adj.len();
+    let mut pair_u: Vec<Option<usize>> = vec![None; n_left];
+    let mut pair_v: Vec<Option<usize>> = vec![None; n_right];
+    let mut dist = vec![usize::MAX; n_left];
+    loop {
+        // layers of alternating paths starting at free left nodes
+        let mut queue = VecDeque::new();
+        for u in 0..n_left {
+            if pair_u[u].is_none() {
+                dist[u] = 0;
+                queue.push_back(u);
+            } else {
+                dist[u] = usize::MAX;
+            }
+        }
+        let mut free_dist = usize::MAX;
+        while let Some(u) = queue.pop_front() {
+            if dist[u] >= free_dist {
+                continue;
+            }
+            for &v in &adj[u] {
+                match pair_v[v] {
+                    None => free_dist = free_dist.min(dist[u] + 1),
+                    Some(w) if dist[w] == usize::MAX => {
+                        dist[w] = dist[u] + 1;
+                        queue.push_back(w);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if free_dist == usize::MAX {
+            return Ok(pair_u);
+        }
+
+        // vertex-disjoint shortest augmenting paths, with an explicit stack
+        let mut next_edge = vec![0; n_left];
+        for start in 0..n_left {
+            if pair_u[start].is_some() {
+                continue;
+            }
+            let mut stack = vec![start];
+            while let Some(&u) = stack.last() {
+                if next_edge[u] == adj[u].len() {
+                    dist[u] = usize::MAX;
+                    stack.pop();
+                    if let Some(&parent) = stack.last() {
+                        next_edge[parent] += 1;
+                    }
+                    continue;
+                }
+                let v = adj[u][next_edge[u]];
+                match pair_v[v] {
+                    None if dist[u] + 1 == free_dist => {
+                        for &u in &stack {
+                            let v = adj[u][next_edge[u]];
+                            pair_u[u] = Some(v);
+                            pair_v[v] = Some(u);
+                        }
+                        break;
+                    }
+                    Some(w) if dist[w] == dist[u] + 1 => stack.push(w),
+                    _ => next_edge[u] += 1,
+                }
+            }
+            poison.check()?;
+        }
+    }
+}
+
+/// Minimum (or with `maximize: true` maximum) total weight matching between the nodes
+/// in the first column of the edges and those in the second column. As many nodes as
+/// possible are matched, using only the given edges.
+pub(crate) struct MinimumCostAssignment;
+
+impl FixedRule for MinimumCostAssignment {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(3)?;
+        let maximize = payload.bool_option("maximize", Some(false))?;
+
+        let mut left = Side::default();
+        let mut right = Side::default();
+        // parallel edges keep the best weight
+        let mut weights: BTreeMap<(usize, usize), f64> = BTreeMap::new();
+        for tuple in edges.iter()? {
+            let tuple = tuple?;
+            let weight = match tuple[2].get_float() {
+                Some(w) if w.is_finite() => w,
+                _ => bail!(BadExprValueError(
+                    tuple[2].clone(),
+                    edges.span(),
+                    "weights must be finite numbers".to_string()
+                )),
+            };
+            let mut tuple = tuple.into_iter();
+            let u = left.index(tuple.next().unwrap());
+            let v = right.index(tuple.next().unwrap());
+            let cost = if maximize { -weight } else { weight };
+            weights
+                .entry((u, v))
+                .and_modify(|c| *c = c.min(cost))
+                .or_insert(cost);
+        }
+        // path lengths are bounded by twice the total weight
+        ensure!(
+            (2. * weights.values().map(|w| w.abs()).sum::<f64>()).is_finite(),
+            BadExprValueError(
+                DataValue::Null,
+                edges.span(),
+                "the sum of the weights is too large".to_string()
+            )
+        );
+
+        // every matching of k edges is shifted by the same amount, so the optimal ones
+        // do not change, and non-negative costs are required by the algorithm
+        let min_cost = weights.values().copied().fold(0., f64::min);
+        let mut adj: Vec<Vec<(usize, f64)>> = vec![vec![]; left.nodes.len()];
+        for ((u, v), c) in &weights {
+            adj[*u].push((*v, c - min_cost));
+        }
+
+        let pair_u = min_cost_matching(&adj, right.nodes.len(), &poison)?;
+        for (u, v) in pair_u.into_iter().enumerate() {
+            if let Some(v) = v {
+                let cost = weights[&(u, v)];
+                let weight = if maximize { -cost } else { cost };
+                out.put(vec![
+                    left.nodes[u].clone(),
+                    right.nodes[v].clone(),
+                    DataValue::from(weight),
+                ]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+}
+
+/// Returns the node matched with each left node, in a matching with as many edges as possible
+/// and the least total cost among those. The costs of the edges in `adj` must be non-negative.
+///
+/// Successive shortest augmenting paths, found with Dijkstra's algorithm on costs reduced
+/// by node potentials, in `O(k E log V)` for a matching of `k` edges.
+fn min_cost_matching(
+    adj: &[Vec<(usize, f64)>],
+    n_right: usize,
+    poison: &Poison,
+) -> Result<Vec<Option<usize>>> {
+    let n_left = adj.len();
+    let mut pair_u: Vec<Option<usize>> = vec![None; n_left];
+    // the left node matched with each right node, and the cost of the edge
+    let mut pair_v: Vec<Option<(usize, f64)>> = vec![None; n_right];
+    // free left nodes keep a potential of zero
+    let mut pot_u = vec![0.; n_left];
+    let mut pot_v = vec![0.; n_right];
+    let mut pot_sink = 0.;
+    loop {
+        // right nodes are numbered after the left ones in the queue
+        let mut dist_u = vec![f64::INFINITY; n_left];
+        let mut dist_v = vec![f64::INFINITY; n_right];
+        let mut prev = vec![(usize::MAX, 0.); n_right];
+        let mut pq = PriorityQueue::new();
+        for u in 0..n_left {
+            if pair_u[u].is_none() {
+                dist_u[u] = 0.;
+                pq.push(u, Reverse(OrderedFloat(0.)));
+            }
+        }
+        // the length of the shortest augmenting path, and the free right node it ends at
+        let mut best: Option<(f64, usize)> = None;
+        while let Some((node, Reverse(OrderedFloat(d)))) = pq.pop() {
+            if best.is_some_and(|(b, _)| d >= b) {
+                break;
+            }
+            if node < n_left {
+                let u = node;
+                for &(v, c) in &adj[u] {
+                    if pair_u[u] == Some(v) {
+                        continue;
+                    }
+                    let nd = d + (c + pot_u[u] - pot_v[v]).max(0.);
+                    if nd < dist_v[v] {
+                        dist_v[v] = nd;
+                        prev[v] = (u, c);
+                        pq.push_increase(n_left + v, Reverse(OrderedFloat(nd)));
+                    }
+                }
+            } else {
+                let v = node - n_left;
+                match pair_v[v] {
+                    Some((w, c)) => {
+                        let nd = d + (pot_v[v] - c - pot_u[w]).max(0.);
+                        if nd < dist_u[w] {
+                            dist_u[w] = nd;
+                            pq.push_increase(w, Reverse(OrderedFloat(nd)));
+                        }
+                    }
+                    None => {
+                        let nd = d + (pot_v[v] - pot_sink).max(0.);
+                        if best.is_none_or(|(b, _)| nd < b) {
+                            best = Some((nd, v));
+                        }
+                    }
+                }
+            }
+            poison.check()?;
+        }
+        let Some((total, mut v)) = best else {
+            return Ok(pair_u);
+        };
+        loop {
+            let (u, c) = prev[v];
+            let old = pair_u[u].replace(v);
+            pair_v[v] = Some((u, c));
+            match old {
+                None => break,
+                Some(o) => v = o,
+            }
+        }
+        // truncating the distances at the length of the path keeps reduced costs non-negative
+        for (p, d) in pot_u.iter_mut().zip(dist_u) {
+            *p += d.min(total);
+        }
+        for (p, d) in pot_v.iter_mut().zip(dist_v) {
+            *p += d.min(total);
+        }
+        pot_sink += total;
+    }
+}
@@ -0,0 +1,329 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, VecDeque};
+
+use miette::{bail, ensure, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{BadExprValueError, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Maximum flow from `source` to `sink` with Dinic's algorithm. Depending on `output`,
+/// returns the flow through each edge, the value of the flow, or the edges of a minimum cut.
+pub(crate) struct MaxFlow;
+
+enum FlowOutput {
+    Flows,
+    Value,
+    Cut,
+}
+
+impl FixedRule for MaxFlow {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(3)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let source = payload.expr_option("source", None)?.eval_to_const()?;
+        let sink = payload.expr_option("sink", None)?.eval_to_const()?;
+        let output = match &payload.string_option("output", Some("flows"))? as &str {
+            "flows" => FlowOutput::Flows,
+            "value" => FlowOutput::Value,
+            "cut" => FlowOutput::Cut,
+            o => bail!(WrongFixedRuleOptionError {
+                name: "output".to_string(),
+                span: payload.option_span("output")?,
+                rule_name: payload.name().to_string(),
+                help: format!("'{o}' is not one of 'flows', 'value' or 'cut'")
+            }),
+        };
+        ensure!(
+            source != sink,
+            WrongFixedRuleOptionError {
+                name: "sink".to_string(),
+                span: payload.option_span("sink")?,
+                rule_name: payload.name().to_string(),
+                help: "the sink must be different from the source".to_string()
+            }
+        );
+
+        let mut network = FlowNetwork::default();
+        #[allow(clippy::mutable_key_type)]
+        let mut inv_indices: BTreeMap<DataValue, usize> = BTreeMap::new();
+        let mut indices: Vec<DataValue> = vec![];
+        let mut index_of =
+            |node: DataValue, network: &mut FlowNetwork| match inv_indices.entry(node) {
+                Entry::Occupied(ent) => *ent.get(),
+                Entry::Vacant(ent) => {
+                    indices.push(ent.key().clone());
+                    network.arcs.push(vec![]);
+                    *ent.insert(indices.len() - 1)
+                }
+            };
+        // parallel edges are merged into one whose capacity is the sum
+        let mut merged: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+        for tuple in edges.iter()? {
+            let tuple = tuple?;
+            let capacity = match tuple[2].get_float() {
+                Some(c) if c.is_finite() && c >= 0. => c,
+                _ => bail!(BadExprValueError(
+                    tuple[2].clone(),
+                    edges.span(),
+                    "capacities must be finite non-negative numbers".to_string()
+                )),
+            };
+            let mut tuple = tuple.into_iter();
+            let from = index_of(tuple.next().unwrap(), &mut network);
+            let to = index_of(tuple.next().unwrap(), &mut network);
+            if from == to {
+                continue;
+            }
+            let key = if undirected {
+                (from.min(to), from.max(to))
+            } else {
+                (from, to)
+            };
+            match merged.entry(key) {
+                Entry::Occupied(ent) => network.add_capacity(*ent.get(), capacity, undirected),
+                Entry::Vacant(ent) => {
+                    ent.insert(network.add_edge(key.0, key.1, capacity, undirected));
+                }
+            }
+        }
+
+        let (s, t) = match (inv_indices.get(&source), inv_indices.get(&sink)) {
+            (Some(s), Some(t)) => (*s, *t),
+            // a node that is not on any edge cannot carry any flow
+            _ => {
+                if let FlowOutput::Value = output {
+                    out.put(vec![source, sink, DataValue::from(0.)]);
+                }
+                return Ok(());
+            }
+        };
+        let value = network.max_flow(s, t, poison)?;
+
+        match output {
+            FlowOutput::Value => out.put(vec![source, sink, DataValue::from(value)]),
+            FlowOutput::Flows => {
+                for (from, to, flow) in network.edge_flows() {
+                    if flow > 0. {
+                        out.put(vec![
+                            indices[from].clone(),
+                            indices[to].clone(),
+                            DataValue::from(flow),
+                        ]);
+                    }
+                }
+            }
+            FlowOutput::Cut => {
+                let reachable = network.levels(s);
+                for edge in &network.edges {
+                    for (from, to, capacity) in network.original_arcs(edge) {
+                        if reachable[from].is_some() && reachable[to].is_none() {
+                            out.put(vec![
+                                indices[from].clone(),
+                                indices[to].clone(),
+                                DataValue::from(capacity),
+                            ]);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+}
+
+struct FlowArc {
+    to: usize,
+    /// Position of the reverse arc in the arcs of `to`.
+    rev: usize,
+    residual: f64,
+}
+
+/// An edge of the input, with the position of its forward arc.
+struct FlowEdge {
+    from: usize,
+    arc: usize,
+    capacity: f64,
+    undirected: bool,
+}
+
+#[derive(Default)]
+struct FlowNetwork {
+    arcs: Vec<Vec<FlowArc>>,
+    edges: Vec<FlowEdge>,
+}
+
+impl FlowNetwork {
+    /// For an undirected edge the reverse arc gets the capacity too,
+    /// so that the flow can go either way.
+    fn add_edge(&mut self, from: usize, to: usize, capacity: f64, undirected: bool) -> usize {
+        let arc = self.arcs[from].len();
+        let rev = self.arcs[to].len();
+        self.arcs[from].push(FlowArc {
+            to,
+            rev,
+            residual: capacity,
+        });
+        self.arcs[to].push(FlowArc {
+            to: from,
+            rev: arc,
+            residual: if undirected { capacity } else { 0. },
+        });
+        self.edges.push(FlowEdge {
+            from,
+            arc,
+            capacity,
+            undirected,
+        });
+        self.edges.len() - 1
+    }
+    fn add_capacity(&mut self, edge: usize, capacity: f64, undirected: bool) {
+        let edge = &mut self.edges[edge];
+        edge.capacity += capacity;
+        let arc = &mut self.arcs[edge.from][edge.arc];
+        arc.residual += capacity;
+        if undirected {
+            let (to, rev) = (arc.to, arc.rev);
+            self.arcs[to][rev].residual += capacity;
+        }
+    }
+    /// Distances from `s` in the residual network, `None` for unreachable nodes.
+    fn levels(&self, s: usize) -> Vec<Option<usize>> {
+        let mut levels = vec![None; self.arcs.len()];
+        levels[s] = Some(0);
+        let mut queue = VecDeque::from([s]);
+        while let Some(u) = queue.pop_front() {
+            let next = levels[u].map(|l| l + 1);
+            for arc in &self.arcs[u] {
+                if arc.residual > 0. && levels[arc.to].is_none() {
+                    levels[arc.to] = next;
+                    queue.push_back(arc.to);
+                }
+            }
+        }
+        levels
+    }
+    fn max_flow(&mut self, s: usize, t: usize, poison: Poison) -> Result<f64> {
+        let mut total = 0.;
+        loop {
+            let levels = self.levels(s);
+            if levels[t].is_none() {
+                return Ok(total);
+            }
+            total += self.blocking_flow(s, t, levels, &poison)?;
+        }
+    }
+    /// Saturate all shortest augmenting paths, with an explicit stack
+    /// since paths can be as long as the graph is large.
+    fn blocking_flow(
+        &mut self,
+        s: usize,
+        t: usize,
+        mut levels: Vec<Option<usize>>,
+        poison: &Poison,
+    ) -> Result<f64> {
+        let mut total = 0.;
+        let mut next_arc = vec![0; self.arcs.len()];
+        let mut path: Vec<(usize, usize)> = vec![];
+        let mut u = s;
+        loop {
+            if u == t {
+                let pushed = path
+                    .iter()
+                    .map(|(v, i)| self.arcs[*v][*i].residual)
+                    .fold(f64::INFINITY, f64::min);
+                for (v, i) in path.drain(..) {
+                    let arc = &mut self.arcs[v][i];
+                    arc.residual -= pushed;
+                    let (to, rev) = (arc.to, arc.rev);
+                    self.arcs[to][rev].residual += pushed;
+                }
+                total += pushed;
+                u = s;
+                poison.check()?;
+                continue;
+            }
+            let arcs = &self.arcs[u];
+            while next_arc[u] < arcs.len() {
+                let arc = &arcs[next_arc[u]];
+                let advances = match (levels[u], levels[arc.to]) {
+                    (Some(a), Some(b)) => b == a + 1,
+                    _ => false,
+                };
+                if arc.residual > 0. && advances {
+                    break;
+                }
+                next_arc[u] += 1;
+            }
+            if next_arc[u] < arcs.len() {
+                path.push((u, next_arc[u]));
+                u = arcs[next_arc[u]].to;
+            } else {
+                // dead end, never visit it again in this phase
+                if u == s {
+                    return Ok(total);
+                }
+                levels[u] = None;
+                let (prev, _) = path.pop().unwrap();
+                next_arc[prev] += 1;
+                u = prev;
+            }
+        }
+    }
+    /// The arcs of an input edge that go from the `from` node to the `to` node,
+    /// with their capacities: both directions for undirected edges.
+    fn original_arcs(&self, edge: &FlowEdge) -> Vec<(usize, usize, f64)> {
+        let to = self.arcs[edge.from][edge.arc].to;
+        if edge.undirected {
+            vec![
+                (edge.from, to, edge.capacity),
+                (to, edge.from, edge.capacity),
+            ]
+        } else {
+            vec![(edge.from, to, edge.capacity)]
+        }
+    }
+    /// The net flow through each input edge, in the direction of the flow.
+    fn edge_flows(&self) -> Vec<(usize, usize, f64)> {
+        self.edges
+            .iter()
+            .map(|edge| {
+                let arc = &self.arcs[edge.from][edge.arc];
+                let flow = edge.capacity - arc.residual;
+                if flow < 0. {
+                    (arc.to, edge.from, -flow)
+                } else {
+                    (edge.from, arc.to, flow)
+                }
+            })
+            .collect()
+    }
+}
@@ -13,15 +13,22 @@ pub(crate) mod degree_centrality;
 pub(crate) mod dfs;
 pub(crate) mod kruskal;
 pub(crate) mod label_propagation;
+pub(crate) mod landmarks;
 pub(crate) mod louvain;
+pub(crate) mod matching;
+pub(crate) mod max_flow;
+pub(crate) mod node2vec;
 pub(crate) mod pagerank;
 pub(crate) mod prim;
 pub(crate) mod random_walk;
 pub(crate) mod shortest_path_bfs;
 pub(crate) mod shortest_path_dijkstra;
+pub(crate) mod shortest_path_temporal;
 pub(crate) mod strongly_connected_components;
 pub(crate) mod top_sort;
+pub(crate) mod transitive_closure;
 pub(crate) mod triangles;
+pub(crate) mod union_find;
 pub(crate) mod yen;
 
 pub(crate) use all_pairs_shortest_path::{BetweennessCentrality, ClosenessCentrality};
@@ -31,13 +38,20 @@ pub(crate) use degree_centrality::DegreeCentrality;
 pub(crate) use dfs::Dfs;
 pub(crate) use kruskal::MinimumSpanningForestKruskal;
 pub(crate) use label_propagation::LabelPropagation;
+pub(crate) use landmarks::{LandmarkDistances, ShortestPathALT};
 pub(crate) use louvain::CommunityDetectionLouvain;
+pub(crate) use matching::{MaximumBipartiteMatching, MinimumCostAssignment};
+pub(crate) use max_flow::MaxFlow;
+pub(crate) use node2vec::Node2Vec;
 pub(crate) use pagerank::PageRank;
 pub(crate) use prim::MinimumSpanningTreePrim;
 pub(crate) use random_walk::RandomWalk;
 pub(crate) use shortest_path_bfs::ShortestPathBFS;
 pub(crate) use shortest_path_dijkstra::ShortestPathDijkstra;
+pub(crate) use shortest_path_temporal::ShortestPathTemporal;
 pub(crate) use strongly_connected_components::StronglyConnectedComponent;
 pub(crate) use top_sort::TopSort;
+pub(crate) use transitive_closure::TransitiveClosure;
 pub(crate) use triangles::ClusteringCoefficients;
+pub(crate) use union_find::WccUnionFind;
 pub(crate) use yen::KShortestPathYen;
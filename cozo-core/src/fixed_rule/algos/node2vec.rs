@@ -0,0 +1,242 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighborsWithValues, Graph};
+use miette::{ensure, Result};
+use ndarray::Array1;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::{DataValue, Vector};
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Node embeddings learned by skip-gram with negative sampling over biased random walks.
+/// The return parameter `p` and the in-out parameter `q` bias the walks towards
+/// going back or going further; with both equal to 1, this is DeepWalk.
+/// Returns `[node, embedding]` with `<F32; dimensions>` embeddings.
+pub(crate) struct Node2Vec;
+
+struct WalkParams {
+    walk_length: usize,
+    inv_p: f64,
+    inv_q: f64,
+}
+
+struct TrainParams {
+    dimensions: usize,
+    window: usize,
+    negative: usize,
+    learning_rate: f32,
+    epochs: usize,
+}
+
+impl FixedRule for Node2Vec {
+    fn is_deterministic(&self) -> bool {
+        false
+    }
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let dimensions = payload.pos_integer_option("dimensions", Some(64))?;
+        let walk_length = payload.pos_integer_option("walk_length", Some(40))?;
+        let walks_per_node = payload.pos_integer_option("walks_per_node", Some(10))?;
+        let window = payload.pos_integer_option("window", Some(5))?;
+        let negative = payload.non_neg_integer_option("negative", Some(5))?;
+        let epochs = payload.pos_integer_option("epochs", Some(1))?;
+        let learning_rate = payload.float_option("learning_rate", Some(0.025))?;
+        let p = payload.float_option("p", Some(1.))?;
+        let q = payload.float_option("q", Some(1.))?;
+        for (name, val) in [("learning_rate", learning_rate), ("p", p), ("q", q)] {
+            ensure!(
+                val.is_finite() && val > 0.,
+                WrongFixedRuleOptionError {
+                    name: name.to_string(),
+                    span: payload.option_span(name)?,
+                    rule_name: payload.name().to_string(),
+                    help: "a positive number is required".to_string()
+                }
+            );
+        }
+
+        let (graph, indices, _) = edges.as_directed_weighted_graph(undirected, false)?;
+        let mut rng = payload.rng();
+        let walk_params = WalkParams {
+            walk_length,
+            inv_p: 1. / p,
+            inv_q: 1. / q,
+        };
+        let mut walks = vec![];
+        let mut order = (0..graph.node_count()).collect::<Vec<_>>();
+        for _ in 0..walks_per_node {
+            order.shuffle(&mut rng);
+            for start in &order {
+                walks.push(biased_walk(&graph, *start, &walk_params, &mut rng));
+            }
+            poison.check()?;
+        }
+
+        let train_params = TrainParams {
+            dimensions,
+            window,
+            negative,
+            learning_rate: learning_rate as f32,
+            epochs,
+        };
+        let embeddings = skip_gram(
+            &walks,
+            graph.node_count() as usize,
+            &train_params,
+            &mut rng,
+            &poison,
+        )?;
+        for (node, embedding) in embeddings.chunks(dimensions).enumerate() {
+            out.put(vec![
+                indices[node].clone(),
+                DataValue::Vec(Vector::F32(Array1::from(embedding.to_vec()))),
+            ]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// A second-order random walk: the weight of going from `cur` to `next` is multiplied by
+/// `1/p` if `next` is the previous node, by 1 if it is a neighbour of the previous node,
+/// and by `1/q` otherwise.
+fn biased_walk(
+    graph: &DirectedCsrGraph<u32, (), f32>,
+    start: u32,
+    params: &WalkParams,
+    rng: &mut impl Rng,
+) -> Vec<u32> {
+    let mut walk = vec![start];
+    while walk.len() < params.walk_length {
+        let cur = *walk.last().unwrap();
+        let prev = walk.len().checked_sub(2).map(|i| walk[i]);
+        let candidates = graph.out_neighbors_with_values(cur).as_slice();
+        let weights = candidates.iter().map(|target| {
+            let bias = match prev {
+                None => 1.,
+                Some(prev) if prev == target.target => params.inv_p,
+                Some(prev) => {
+                    // neighbours are sorted
+                    let back = graph.out_neighbors_with_values(prev).as_slice();
+                    if back
+                        .binary_search_by_key(&target.target, |t| t.target)
+                        .is_ok()
+                    {
+                        1.
+                    } else {
+                        params.inv_q
+                    }
+                }
+            };
+            target.value as f64 * bias
+        });
+        match WeightedIndex::new(weights) {
+            Ok(dist) => walk.push(candidates[dist.sample(rng)].target),
+            // a dead end, or only edges of zero weight
+            Err(_) => break,
+        }
+    }
+    walk
+}
+
+/// Returns the embeddings of all nodes, one after the other.
+fn skip_gram(
+    walks: &[Vec<u32>],
+    n_nodes: usize,
+    params: &TrainParams,
+    rng: &mut impl Rng,
+    poison: &Poison,
+) -> Result<Vec<f32>> {
+    let dim = params.dimensions;
+    let mut input: Vec<f32> = (0..n_nodes * dim)
+        .map(|_| (rng.gen::<f32>() - 0.5) / dim as f32)
+        .collect();
+    let mut output = vec![0f32; n_nodes * dim];
+    if n_nodes == 0 {
+        return Ok(input);
+    }
+
+    // negative samples are drawn proportionally to the frequency to the power 3/4
+    let mut frequencies = vec![0usize; n_nodes];
+    for node in walks.iter().flatten() {
+        frequencies[*node as usize] += 1;
+    }
+    let noise = WeightedIndex::new(frequencies.iter().map(|f| (*f as f64).powf(0.75))).ok();
+
+    let total_steps = (params.epochs * walks.len()).max(1) as f32;
+    let mut step = 0;
+    let mut grad = vec![0f32; dim];
+    for _ in 0..params.epochs {
+        for walk in walks {
+            // the learning rate decreases linearly, down to a small fraction of the initial one
+            let lr = params.learning_rate * (1. - step as f32 / total_steps).max(1e-4);
+            step += 1;
+            for (i, center) in walk.iter().enumerate() {
+                let lo = i.saturating_sub(params.window);
+                let hi = (i + params.window + 1).min(walk.len());
+                let center = *center as usize * dim;
+                for (j, context) in walk.iter().enumerate().take(hi).skip(lo) {
+                    if i == j {
+                        continue;
+                    }
+                    grad.iter_mut().for_each(|g| *g = 0.);
+                    let negatives = (0..params.negative).filter_map(|_| {
+                        let sample = noise.as_ref()?.sample(rng) as u32;
+                        (sample != *context).then_some((sample, 0.))
+                    });
+                    let targets = std::iter::once((*context, 1.)).chain(negatives);
+                    for (target, label) in targets.collect::<Vec<_>>() {
+                        let target = target as usize * dim;
+                        let dot: f32 = (0..dim)
+                            .map(|k| input[center + k] * output[target + k])
+                            .sum();
+                        let g = (label - sigmoid(dot)) * lr;
+                        for k in 0..dim {
+                            grad[k] += g * output[target + k];
+                            output[target + k] += g * input[center + k];
+                        }
+                    }
+                    for k in 0..dim {
+                        input[center + k] += grad[k];
+                    }
+                }
+            }
+            poison.check()?;
+        }
+    }
+    Ok(input)
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1. / (1. + (-x.clamp(-30., 30.)).exp())
+}
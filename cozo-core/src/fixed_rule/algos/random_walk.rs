@@ -25,6 +25,9 @@ use crate::runtime::temp_store::RegularTempStore;
 pub(crate) struct RandomWalk;
 
 impl FixedRule for RandomWalk {
+    fn is_deterministic(&self) -> bool {
+        false
+    }
     fn run(
         &self,
         payload: FixedRulePayload<'_, '_>,
@@ -51,7 +54,7 @@ impl FixedRule for RandomWalk {
         let mut stack = vec![];
 
         let mut counter = 0i64;
-        let mut rng = thread_rng();
+        let mut rng = payload.rng();
         for start_node in starting.iter()? {
             let start_node = start_node?;
             let start_node_key = &start_node[0];
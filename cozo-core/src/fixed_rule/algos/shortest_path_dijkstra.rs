@@ -12,12 +12,13 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::iter;
 
 use itertools::Itertools;
-use miette::Result;
+use miette::{bail, Diagnostic, Result};
 use ordered_float::OrderedFloat;
 use priority_queue::PriorityQueue;
 use rayon::prelude::*;
 use smallvec::{smallvec, SmallVec};
 use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
 
 use crate::data::expr::Expr;
 use crate::data::symb::Symbol;
@@ -41,6 +42,7 @@ impl FixedRule for ShortestPathDijkstra {
         let termination = payload.get_input(2);
         let undirected = payload.bool_option("undirected", Some(false))?;
         let keep_ties = payload.bool_option("keep_ties", Some(false))?;
+        let bidirectional = payload.bool_option("bidirectional", Some(false))?;
 
         let (graph, indices, inv_indices) = edges.as_directed_weighted_graph(undirected, false)?;
 
@@ -67,7 +69,34 @@ impl FixedRule for ShortestPathDijkstra {
             }
         };
 
-        if starting_nodes.len() <= 1 {
+        if bidirectional {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Bidirectional search requires a relation of termination nodes and no ties")]
+            #[diagnostic(code(algo::bidirectional_without_goals))]
+            #[diagnostic(help("Pass the goals as the third relation and remove 'keep_ties'"))]
+            struct BidirectionalWithoutGoalsError(#[label] SourceSpan);
+
+            let goals = match &termination_nodes {
+                Some(tn) if !keep_ties => tn,
+                _ => bail!(BidirectionalWithoutGoalsError(payload.span())),
+            };
+            for start in starting_nodes {
+                for goal in goals {
+                    let (cost, path) =
+                        bidirectional_dijkstra(&graph, start, *goal, poison.clone())?;
+                    out.put(vec![
+                        indices[start as usize].clone(),
+                        indices[*goal as usize].clone(),
+                        DataValue::from(cost as f64),
+                        DataValue::List(
+                            path.into_iter()
+                                .map(|u| indices[u as usize].clone())
+                                .collect_vec(),
+                        ),
+                    ])
+                }
+            }
+        } else if starting_nodes.len() <= 1 {
             for start in starting_nodes {
                 let res = if let Some(tn) = &termination_nodes {
                     if tn.len() == 1 {
@@ -430,3 +459,78 @@ pub(crate) fn dijkstra_keep_ties<FE: ForbiddenEdge, FN: ForbiddenNode, G: Goal +
 
     Ok(ret)
 }
+
+/// Search from both ends at once, expanding the side whose frontier is closer,
+/// and stop when the two frontiers together cannot improve on the best meeting point.
+/// Explores roughly two balls of half the radius instead of one ball of the full radius.
+pub(crate) fn bidirectional_dijkstra(
+    edges: &DirectedCsrGraph<u32, (), f32>,
+    start: u32,
+    goal: u32,
+    poison: Poison,
+) -> Result<(f32, Vec<u32>)> {
+    let graph_size = edges.node_count() as usize;
+    let mut distance = [
+        vec![f32::INFINITY; graph_size],
+        vec![f32::INFINITY; graph_size],
+    ];
+    let mut back_pointers = [vec![u32::MAX; graph_size], vec![u32::MAX; graph_size]];
+    let mut pqs = [PriorityQueue::new(), PriorityQueue::new()];
+    distance[0][start as usize] = 0.;
+    distance[1][goal as usize] = 0.;
+    pqs[0].push(start, Reverse(OrderedFloat(0.)));
+    pqs[1].push(goal, Reverse(OrderedFloat(0.)));
+    let mut best = if start == goal { 0. } else { f32::INFINITY };
+    let mut meeting = start;
+
+    while let (
+        Some((_, Reverse(OrderedFloat(forward_top)))),
+        Some((_, Reverse(OrderedFloat(backward_top)))),
+    ) = (pqs[0].peek(), pqs[1].peek())
+    {
+        let (forward_top, backward_top) = (*forward_top, *backward_top);
+        if forward_top + backward_top >= best {
+            break;
+        }
+        let side = if forward_top <= backward_top { 0 } else { 1 };
+        let (node, Reverse(OrderedFloat(cost))) = pqs[side].pop().unwrap();
+        let neighbours = if side == 0 {
+            edges.out_neighbors_with_values(node)
+        } else {
+            edges.in_neighbors_with_values(node)
+        };
+        for target in neighbours {
+            let nxt_node = target.target;
+            let nxt_cost = cost + target.value;
+            if nxt_cost < distance[side][nxt_node as usize] {
+                pqs[side].push_increase(nxt_node, Reverse(OrderedFloat(nxt_cost)));
+                distance[side][nxt_node as usize] = nxt_cost;
+                back_pointers[side][nxt_node as usize] = node;
+                let through = nxt_cost + distance[1 - side][nxt_node as usize];
+                if through < best {
+                    best = through;
+                    meeting = nxt_node;
+                }
+            }
+        }
+        poison.check()?;
+    }
+
+    if !best.is_finite() {
+        return Ok((best, vec![]));
+    }
+    let mut path = vec![];
+    let mut current = meeting;
+    while current != start {
+        path.push(current);
+        current = back_pointers[0][current as usize];
+    }
+    path.push(start);
+    path.reverse();
+    let mut current = meeting;
+    while current != goal {
+        current = back_pointers[1][current as usize];
+        path.push(current);
+    }
+    Ok((best, path))
+}
@@ -0,0 +1,223 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Reverse;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+
+use graph::prelude::{CsrLayout, DirectedCsrGraph, GraphBuilder};
+use itertools::Itertools;
+use miette::{bail, Result};
+use rayon::prelude::*;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::functions::{current_validity, str2vld, MAX_VALIDITY_TS};
+use crate::data::symb::Symbol;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::fixed_rule::algos::shortest_path_dijkstra::dijkstra;
+use crate::fixed_rule::{BadExprValueError, FixedRule, FixedRulePayload};
+use crate::parse::query::expr2vld_spec;
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Dijkstra's shortest paths in the graph as it was at the time given by `at`.
+///
+/// The edges are `[from, to, validity, weight]`, the weight being optional.
+/// The validity is either a `Validity`, as in a stored relation with time travel,
+/// in which case the latest assertion or retraction of `from -> to` not after `at` decides,
+/// or a list `[start, end]` of timestamps, in which case the edge exists from `start`
+/// included to `end` excluded. A null `start` or `end` leaves that side open.
+pub(crate) struct ShortestPathTemporal;
+
+impl FixedRule for ShortestPathTemporal {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(3)?;
+        let starting = payload.get_input(1)?;
+        let termination = payload.get_input(2);
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let cur_vld = current_validity();
+        let at = match payload.expr_option("at", None) {
+            Ok(expr) => expr2vld_spec(expr, cur_vld)?,
+            Err(_) => cur_vld,
+        };
+        let at = at.0 .0;
+
+        // for versioned edges, the latest version not after `at`
+        #[allow(clippy::mutable_key_type)]
+        let mut versions: BTreeMap<(DataValue, DataValue), (i64, bool, f64)> = BTreeMap::new();
+        let mut active = vec![];
+        for tuple in edges.iter()? {
+            let tuple = tuple?;
+            let weight = match tuple.get(3) {
+                None => 1.,
+                Some(w) => match w.get_float() {
+                    Some(f) if f.is_finite() && f >= 0. => f,
+                    _ => bail!(BadExprValueError(
+                        w.clone(),
+                        edges.span(),
+                        "edge weights must be finite non-negative numbers".to_string()
+                    )),
+                },
+            };
+            match &tuple[2] {
+                DataValue::Validity(vld) => {
+                    let ts = vld.timestamp.0 .0;
+                    if ts > at {
+                        continue;
+                    }
+                    let version = (ts, vld.is_assert.0, weight);
+                    match versions.entry((tuple[0].clone(), tuple[1].clone())) {
+                        Entry::Vacant(ent) => {
+                            ent.insert(version);
+                        }
+                        Entry::Occupied(mut ent) => {
+                            if ent.get().0 < ts {
+                                ent.insert(version);
+                            }
+                        }
+                    }
+                }
+                DataValue::List(interval) if interval.len() == 2 => {
+                    let start = interval_bound(&interval[0], cur_vld, edges.span())?;
+                    let end = interval_bound(&interval[1], cur_vld, edges.span())?;
+                    let started = !matches!(start, Some(s) if s > at);
+                    let ended = matches!(end, Some(e) if e <= at);
+                    if started && !ended {
+                        active.push((tuple[0].clone(), tuple[1].clone(), weight));
+                    }
+                }
+                v => bail!(BadExprValueError(
+                    v.clone(),
+                    edges.span(),
+                    "the third column must be a validity or a list [start, end]".to_string()
+                )),
+            }
+        }
+        for ((from, to), (_, is_assert, weight)) in versions {
+            if is_assert {
+                active.push((from, to, weight));
+            }
+        }
+
+        let mut indices: Vec<DataValue> = vec![];
+        #[allow(clippy::mutable_key_type)]
+        let mut inv_indices: BTreeMap<DataValue, u32> = BTreeMap::new();
+        let mut index_of = |node: DataValue| match inv_indices.entry(node) {
+            Entry::Occupied(ent) => *ent.get(),
+            Entry::Vacant(ent) => {
+                indices.push(ent.key().clone());
+                *ent.insert(indices.len() as u32 - 1)
+            }
+        };
+        let mut graph_edges = vec![];
+        for (from, to, weight) in active {
+            let (from, to) = (index_of(from), index_of(to));
+            graph_edges.push((from, to, weight as f32));
+            if undirected {
+                graph_edges.push((to, from, weight as f32));
+            }
+        }
+        let graph: DirectedCsrGraph<u32, (), f32> = GraphBuilder::new()
+            .csr_layout(CsrLayout::Sorted)
+            .edges_with_values(graph_edges)
+            .build();
+
+        let mut starting_nodes = BTreeSet::new();
+        for tuple in starting.iter()? {
+            if let Some(idx) = inv_indices.get(&tuple?[0]) {
+                starting_nodes.insert(*idx);
+            }
+        }
+        let termination_nodes = match termination {
+            Err(_) => None,
+            Ok(t) => {
+                let mut tn = BTreeSet::new();
+                for tuple in t.iter()? {
+                    if let Some(idx) = inv_indices.get(&tuple?[0]) {
+                        tn.insert(*idx);
+                    }
+                }
+                Some(tn)
+            }
+        };
+
+        let all_res: Vec<_> = starting_nodes
+            .into_par_iter()
+            .map(|start| -> Result<(u32, Vec<(u32, f32, Vec<u32>)>)> {
+                poison.check()?;
+                Ok((
+                    start,
+                    match &termination_nodes {
+                        Some(tn) => dijkstra(&graph, start, tn, &(), &()),
+                        None => dijkstra(&graph, start, &(), &(), &()),
+                    },
+                ))
+            })
+            .collect::<Result<_>>()?;
+        for (start, res) in all_res {
+            for (target, cost, path) in res {
+                out.put(vec![
+                    indices[start as usize].clone(),
+                    indices[target as usize].clone(),
+                    DataValue::from(cost as f64),
+                    DataValue::List(
+                        path.into_iter()
+                            .map(|u| indices[u as usize].clone())
+                            .collect_vec(),
+                    ),
+                ])
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(4)
+    }
+}
+
+/// A bound of a validity interval in microseconds, `None` if open.
+fn interval_bound(v: &DataValue, cur_vld: ValidityTs, span: SourceSpan) -> Result<Option<i64>> {
+    let ts = match v {
+        DataValue::Null => return Ok(None),
+        DataValue::Validity(vld) => vld.timestamp,
+        DataValue::Str(s) => match s as &str {
+            "NOW" => cur_vld,
+            "END" => MAX_VALIDITY_TS,
+            s => match str2vld(s) {
+                Ok(ts) => ts,
+                Err(_) => bail!(BadExprValueError(
+                    v.clone(),
+                    span,
+                    "not a valid timestamp".to_string()
+                )),
+            },
+        },
+        v => match v.get_int() {
+            Some(i) => ValidityTs(Reverse(i)),
+            None => bail!(BadExprValueError(
+                v.clone(),
+                span,
+                "interval bounds must be timestamps or null".to_string()
+            )),
+        },
+    };
+    Ok(Some(ts.0 .0))
+}
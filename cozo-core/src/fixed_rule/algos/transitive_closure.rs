@@ -0,0 +1,171 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// The nodes reachable from the starting nodes, with the routes reaching them.
+///
+/// This is the transitive closure that rules accumulating the path as a list compute,
+/// without materializing a list for every step, and without following cycles.
+/// By default each reachable node is returned once per starting node, with one of the
+/// shortest routes to it. With `all_paths: true`, every route without cycles is returned.
+/// Routes are not extended beyond `max_depth` edges, if given.
+///
+/// The starting nodes are all the nodes with outgoing edges if not given.
+/// The rows returned are `[start, node, hops, route]`.
+pub(crate) struct TransitiveClosure;
+
+type Adjacency = BTreeMap<DataValue, Vec<DataValue>>;
+
+impl FixedRule for TransitiveClosure {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(2)?;
+        let all_paths = payload.bool_option("all_paths", Some(false))?;
+        let max_depth = match payload.option_span("max_depth") {
+            Ok(_) => Some(payload.non_neg_integer_option("max_depth", None)?),
+            Err(_) => None,
+        };
+
+        #[allow(clippy::mutable_key_type)]
+        let mut graph: Adjacency = BTreeMap::new();
+        for tuple in edges.iter()? {
+            let mut tuple = tuple?.into_iter();
+            let from = tuple.next().unwrap();
+            let to = tuple.next().unwrap();
+            graph.entry(from).or_default().push(to);
+        }
+        let starting_nodes: Vec<DataValue> = if payload.inputs_count() > 1 {
+            payload
+                .get_input(1)?
+                .ensure_min_len(1)?
+                .iter()?
+                .map_ok(|n| n.into_iter().next().unwrap())
+                .try_collect()?
+        } else {
+            graph.keys().cloned().collect()
+        };
+
+        for start in starting_nodes.iter() {
+            if all_paths {
+                simple_paths(&graph, start, max_depth, out, &poison)?;
+            } else {
+                shortest_routes(&graph, start, max_depth, out);
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(4)
+    }
+}
+
+fn put_route(route: &[&DataValue], out: &mut RegularTempStore) {
+    out.put(vec![
+        route[0].clone(),
+        route[route.len() - 1].clone(),
+        DataValue::from((route.len() - 1) as i64),
+        DataValue::List(route.iter().map(|n| (*n).clone()).collect_vec()),
+    ])
+}
+
+/// Breadth first search from `start`, with one of the shortest routes to each node reached.
+#[allow(clippy::mutable_key_type)]
+fn shortest_routes(
+    graph: &Adjacency,
+    start: &DataValue,
+    max_depth: Option<usize>,
+    out: &mut RegularTempStore,
+) {
+    let mut backtrace: BTreeMap<&DataValue, &DataValue> = BTreeMap::new();
+    let mut frontier = vec![start];
+    let mut depth = 0;
+    while !frontier.is_empty() && max_depth.is_none_or(|max| depth < max) {
+        depth += 1;
+        let mut next = vec![];
+        for node in frontier {
+            for to in graph.get(node).into_iter().flatten() {
+                if to == start || backtrace.contains_key(to) {
+                    continue;
+                }
+                backtrace.insert(to, node);
+                next.push(to);
+
+                let mut route = vec![to];
+                let mut current = to;
+                while current != start {
+                    current = backtrace[current];
+                    route.push(current);
+                }
+                route.reverse();
+                put_route(&route, out);
+            }
+        }
+        frontier = next;
+    }
+}
+
+/// Depth first search from `start`, with every route not visiting a node twice.
+#[allow(clippy::mutable_key_type)]
+fn simple_paths(
+    graph: &Adjacency,
+    start: &DataValue,
+    max_depth: Option<usize>,
+    out: &mut RegularTempStore,
+    poison: &Poison,
+) -> Result<()> {
+    let mut route = vec![start];
+    let mut on_route = BTreeSet::from([start]);
+    // for each node of the route, the position of the next edge to follow
+    let mut next_edges = vec![0];
+    while let Some(next_edge) = next_edges.last_mut() {
+        let node = *route.last().unwrap();
+        let tos = graph.get(node).map(|tos| tos.as_slice()).unwrap_or(&[]);
+        let depth = route.len() - 1;
+        if *next_edge >= tos.len() || max_depth.is_some_and(|max| depth >= max) {
+            on_route.remove(node);
+            route.pop();
+            next_edges.pop();
+            continue;
+        }
+        let to = &tos[*next_edge];
+        *next_edge += 1;
+        if on_route.contains(to) {
+            continue;
+        }
+        route.push(to);
+        on_route.insert(to);
+        next_edges.push(0);
+        put_route(&route, out);
+        poison.check()?;
+    }
+    Ok(())
+}
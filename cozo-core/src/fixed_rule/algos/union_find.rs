@@ -0,0 +1,120 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Weakly connected components computed in a single pass over the edges,
+/// without building the adjacency lists of the graph as `ConnectedComponents` does.
+pub(crate) struct WccUnionFind;
+
+#[derive(Default)]
+struct DisjointSets {
+    parents: Vec<u32>,
+    sizes: Vec<u32>,
+}
+
+impl DisjointSets {
+    fn add(&mut self) -> u32 {
+        let idx = self.parents.len() as u32;
+        self.parents.push(idx);
+        self.sizes.push(1);
+        idx
+    }
+    fn find(&mut self, mut idx: u32) -> u32 {
+        let mut root = idx;
+        while self.parents[root as usize] != root {
+            root = self.parents[root as usize];
+        }
+        // path compression
+        while self.parents[idx as usize] != root {
+            let next = self.parents[idx as usize];
+            self.parents[idx as usize] = root;
+            idx = next;
+        }
+        root
+    }
+    fn union(&mut self, a: u32, b: u32) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        // union by size
+        let (small, large) = if self.sizes[a as usize] < self.sizes[b as usize] {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        self.parents[small as usize] = large;
+        self.sizes[large as usize] += self.sizes[small as usize];
+    }
+}
+
+impl FixedRule for WccUnionFind {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(2)?;
+
+        #[allow(clippy::mutable_key_type)]
+        let mut indices: BTreeMap<DataValue, u32> = BTreeMap::new();
+        let mut sets = DisjointSets::default();
+        let mut index_of = |node: DataValue, sets: &mut DisjointSets| match indices.entry(node) {
+            Entry::Occupied(ent) => *ent.get(),
+            Entry::Vacant(ent) => *ent.insert(sets.add()),
+        };
+        for tuple in edges.iter()? {
+            let mut tuple = tuple?.into_iter();
+            let from = index_of(tuple.next().unwrap(), &mut sets);
+            let to = index_of(tuple.next().unwrap(), &mut sets);
+            sets.union(from, to);
+            poison.check()?;
+        }
+        if let Ok(nodes) = payload.get_input(1) {
+            for tuple in nodes.iter()? {
+                let node = tuple?.into_iter().next().unwrap();
+                index_of(node, &mut sets);
+            }
+        }
+
+        // components are numbered in the order their first nodes were seen
+        let mut component_ids: BTreeMap<u32, i64> = BTreeMap::new();
+        let mut nodes = indices.into_iter().collect::<Vec<_>>();
+        nodes.sort_by_key(|(_, idx)| *idx);
+        for (node, idx) in nodes {
+            let root = sets.find(idx);
+            let next_id = component_ids.len() as i64;
+            let id = *component_ids.entry(root).or_insert(next_id);
+            out.put(vec![node, DataValue::from(id)]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
@@ -19,6 +19,8 @@ use lazy_static::lazy_static;
 use miette::IntoDiagnostic;
 #[allow(unused_imports)]
 use miette::{bail, ensure, Diagnostic, Report, Result};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
@@ -48,6 +50,7 @@ pub struct FixedRulePayload<'a, 'b> {
     pub(crate) manifest: &'a MagicFixedRuleApply,
     pub(crate) stores: &'a BTreeMap<MagicSymbol, EpochStore>,
     pub(crate) tx: &'a SessionTx<'b>,
+    pub(crate) rng_seed: Option<u64>,
 }
 
 /// Represents an input relation during the execution of a fixed rule
@@ -329,6 +332,27 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
 }
 
 impl<'a, 'b> FixedRulePayload<'a, 'b> {
+    /// Set the seed of the random number generator returned by [`rng`](Self::rng).
+    /// The engine sets it from the `:seed` option of the query.
+    pub fn with_rng_seed(mut self, seed: Option<u64>) -> Self {
+        self.rng_seed = seed;
+        self
+    }
+    /// A random number generator for the rule, deterministic if a seed is set.
+    pub fn rng(&self) -> StdRng {
+        match self.rng_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+    /// Fails if the sandbox of the database forbids accessing `url`.
+    /// Rules reading files or reaching the network must call this before doing so.
+    pub fn ensure_io_allowed(&self, url: &str) -> Result<()> {
+        match &self.tx.sandbox {
+            None => Ok(()),
+            Some(sandbox) => sandbox.check_url(url),
+        }
+    }
     /// Get the total number of input relations.
     pub fn inputs_count(&self) -> usize {
         self.manifest.relations_count()
@@ -564,6 +588,13 @@ pub trait FixedRule: Send + Sync {
         out: &'_ mut RegularTempStore,
         poison: Poison,
     ) -> Result<()>;
+    /// Whether the rule returns the same rows whenever its inputs and options are the same.
+    /// Rules reading external data or using randomness must return `false`,
+    /// so that their results are not served from the query cache.
+    /// The default implementation returns `true`.
+    fn is_deterministic(&self) -> bool {
+        true
+    }
 }
 
 /// Simple wrapper for custom fixed rule. You have less control than implementing [FixedRule] directly,
@@ -643,44 +674,20 @@ impl FixedRule for SimpleFixedRule {
         out: &'_ mut RegularTempStore,
         _poison: Poison,
     ) -> Result<()> {
-        let options: BTreeMap<_, _> = payload
-            .manifest
-            .options
-            .iter()
-            .map(|(k, v)| -> Result<_> {
-                let val = v.clone().eval_to_const()?;
-                Ok((k.to_string(), val))
-            })
-            .try_collect()?;
+        let options = simple_rule_options(&payload)?;
         let input_arity = payload.manifest.rule_args.len();
         let inputs: Vec<_> = (0..input_arity)
             .map(|i| -> Result<_> {
                 let input = payload.get_input(i).unwrap();
                 let rows: Vec<_> = input.iter()?.try_collect()?;
-                let mut headers = input
-                    .arg_manifest
-                    .bindings()
-                    .iter()
-                    .map(|s| s.name.to_string())
-                    .collect_vec();
-                let l = headers.len();
-                let m = input.arg_manifest.arity(payload.tx, payload.stores)?;
-                for i in l..m {
-                    headers.push(format!("_{i}"));
-                }
-                Ok(NamedRows::new(headers, rows))
+                Ok(NamedRows::new(simple_rule_headers(&payload, input)?, rows))
             })
             .try_collect()?;
         let results: NamedRows = (self.rule)(inputs, options)?;
         for row in results.rows {
-            #[derive(Debug, Error, Diagnostic)]
-            #[error("arity mismatch: expect {1}, got {2}")]
-            #[diagnostic(code(parser::simple_fixed_rule_arity_mismatch))]
-            struct ArityMismatch(#[label] SourceSpan, usize, usize);
-
             ensure!(
                 row.len() == self.return_arity,
-                ArityMismatch(payload.span(), self.return_arity, row.len())
+                SimpleFixedRuleArityMismatch(payload.span(), self.return_arity, row.len())
             );
             out.put(row);
         }
@@ -688,6 +695,177 @@ impl FixedRule for SimpleFixedRule {
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("arity mismatch: expect {1}, got {2}")]
+#[diagnostic(code(parser::simple_fixed_rule_arity_mismatch))]
+struct SimpleFixedRuleArityMismatch(#[label] SourceSpan, usize, usize);
+
+/// The options of a simple fixed rule, evaluated to constants.
+fn simple_rule_options(payload: &FixedRulePayload<'_, '_>) -> Result<BTreeMap<String, DataValue>> {
+    payload
+        .manifest
+        .options
+        .iter()
+        .map(|(k, v)| -> Result<_> {
+            let val = v.clone().eval_to_const()?;
+            Ok((k.to_string(), val))
+        })
+        .try_collect()
+}
+
+/// The headers of an input of a simple fixed rule: the bindings, then `_i` for the
+/// columns left unbound.
+fn simple_rule_headers(
+    payload: &FixedRulePayload<'_, '_>,
+    input: FixedRuleInputRelation<'_, '_>,
+) -> Result<Vec<String>> {
+    let mut headers = input
+        .arg_manifest
+        .bindings()
+        .iter()
+        .map(|s| s.name.to_string())
+        .collect_vec();
+    let l = headers.len();
+    let m = input.arg_manifest.arity(payload.tx, payload.stores)?;
+    for i in l..m {
+        headers.push(format!("_{i}"));
+    }
+    Ok(headers)
+}
+
+/// An input relation of a [StreamingFixedRule], read in batches of rows.
+/// Rows are only read from the database as the batches are requested.
+pub struct RowBatches<'a> {
+    headers: Vec<String>,
+    rows: TupleIter<'a>,
+    batch_size: usize,
+    poison: Poison,
+}
+
+impl RowBatches<'_> {
+    /// The names of the columns of the rows.
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+}
+
+impl Iterator for RowBatches<'_> {
+    type Item = Result<Vec<Vec<DataValue>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.poison.check() {
+            return Some(Err(err));
+        }
+        let batch: Result<Vec<_>> = self.rows.by_ref().take(self.batch_size).collect();
+        match batch {
+            Ok(batch) if batch.is_empty() => None,
+            res => Some(res),
+        }
+    }
+}
+
+/// Receives the rows emitted by a [StreamingFixedRule], which are put into the
+/// result of the rule as they arrive.
+pub struct RowSink<'a> {
+    out: &'a mut RegularTempStore,
+    return_arity: usize,
+    span: SourceSpan,
+    poison: Poison,
+}
+
+impl RowSink<'_> {
+    /// Emit rows of the result. Every row must have length equal to the return arity of the rule.
+    /// Fails if the query has been killed, in which case the rule should stop.
+    pub fn emit(&mut self, rows: impl IntoIterator<Item = Vec<DataValue>>) -> Result<()> {
+        self.poison.check()?;
+        for row in rows {
+            ensure!(
+                row.len() == self.return_arity,
+                SimpleFixedRuleArityMismatch(self.span, self.return_arity, row.len())
+            );
+            self.out.put(row);
+        }
+        Ok(())
+    }
+}
+
+type StreamingRuleFn = dyn Fn(Vec<RowBatches<'_>>, BTreeMap<String, DataValue>, &mut RowSink<'_>) -> Result<()>
+    + Send
+    + Sync
+    + 'static;
+
+/// Wrapper for custom fixed rules working on inputs too large to be realized in memory.
+/// Unlike [SimpleFixedRule], the rule pulls the rows of its inputs in batches and emits its
+/// results as they are computed: the inputs are read no faster than the rule consumes them.
+pub struct StreamingFixedRule {
+    return_arity: usize,
+    batch_size: usize,
+    rule: Box<StreamingRuleFn>,
+}
+
+impl StreamingFixedRule {
+    /// Construct a StreamingFixedRule.
+    ///
+    /// * `return_arity`: The return arity of this rule.
+    /// * `batch_size`: The maximal number of rows in each batch of the inputs.
+    /// * `rule`: The rule implementation as a closure.
+    //    The first argument is a vector of input relations, as iterators over batches of rows,
+    //    the second argument is a JSON object of passed in options,
+    //    and the third argument receives the rows of the return relation.
+    pub fn new<R>(return_arity: usize, batch_size: usize, rule: R) -> Self
+    where
+        R: Fn(Vec<RowBatches<'_>>, BTreeMap<String, DataValue>, &mut RowSink<'_>) -> Result<()>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            return_arity,
+            batch_size: batch_size.max(1),
+            rule: Box::new(rule),
+        }
+    }
+}
+
+impl FixedRule for StreamingFixedRule {
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(self.return_arity)
+    }
+
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &'_ mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let options = simple_rule_options(&payload)?;
+        let input_arity = payload.manifest.rule_args.len();
+        let inputs: Vec<_> = (0..input_arity)
+            .map(|i| -> Result<_> {
+                let input = payload.get_input(i).unwrap();
+                Ok(RowBatches {
+                    headers: simple_rule_headers(&payload, input)?,
+                    rows: input.iter()?,
+                    batch_size: self.batch_size,
+                    poison: poison.clone(),
+                })
+            })
+            .try_collect()?;
+        let mut sink = RowSink {
+            out,
+            return_arity: self.return_arity,
+            span: payload.span(),
+            poison,
+        };
+        (self.rule)(inputs, options, &mut sink)
+    }
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Cannot determine arity for algo {0} since {1}")]
 #[diagnostic(code(parser::no_algo_arity))]
@@ -761,11 +939,51 @@ lazy_static! {
                 Arc::<Box<dyn FixedRule>>::new(Box::new(ShortestPathAStar)),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "ShortestPathALT".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(ShortestPathALT)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "ShortestPathTemporal".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(ShortestPathTemporal)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "Node2Vec".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Node2Vec)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "LandmarkDistances".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(LandmarkDistances)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "TransitiveClosure".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(TransitiveClosure)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "KShortestPathYen".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(KShortestPathYen)),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "MaxFlow".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(MaxFlow)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "MaximumBipartiteMatching".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(MaximumBipartiteMatching)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "MinimumCostAssignment".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(MinimumCostAssignment)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "MinimumSpanningTreePrim".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(MinimumSpanningTreePrim)),
@@ -786,6 +1004,11 @@ lazy_static! {
                 Arc::<Box<dyn FixedRule>>::new(Box::new(StronglyConnectedComponent::new(false))),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "WCCUnionFind".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(WccUnionFind)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "StronglyConnectedComponents".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(StronglyConnectedComponent::new(true))),
@@ -819,6 +1042,42 @@ lazy_static! {
                 "ReorderSort".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(ReorderSort)),
             ),
+            (
+                "BandJoin".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(BandJoin)),
+            ),
+            (
+                "Resample".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Resample)),
+            ),
+            (
+                "Rollup".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Rollup)),
+            ),
+            (
+                "Sample".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Sample)),
+            ),
+            (
+                "FillGaps".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(FillGaps)),
+            ),
+            (
+                "Sessionize".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Sessionize)),
+            ),
+            (
+                "Funnel".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Funnel)),
+            ),
+            (
+                "KMeans".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(KMeans)),
+            ),
+            (
+                "DBSCAN".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Dbscan)),
+            ),
             (
                 "JsonReader".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(JsonReader)),
@@ -827,6 +1086,11 @@ lazy_static! {
                 "CsvReader".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(CsvReader)),
             ),
+            #[cfg(feature = "sql-reader")]
+            (
+                "SqlReader".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(SqlReader)),
+            ),
             (
                 "Constant".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(Constant)),
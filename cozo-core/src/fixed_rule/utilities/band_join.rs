@@ -0,0 +1,144 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::{eval_bytecode, Expr};
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{
+    CannotDetermineArity, FixedRule, FixedRuleInputRelation, FixedRulePayload,
+};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Joins two relations on `abs(left_key - right_key) < window` by sweeping
+/// both sides in key order, instead of filtering their cross product.
+pub(crate) struct BandJoin;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The key of the band join must evaluate to a number, got {0:?}")]
+#[diagnostic(code(algo::band_join_key_not_number))]
+struct BandJoinKeyNotNumber(DataValue, #[label] SourceSpan);
+
+fn sorted_by_key(
+    rel: &FixedRuleInputRelation<'_, '_>,
+    mut key: Expr,
+    poison: &Poison,
+) -> Result<Vec<(f64, Tuple)>> {
+    key.fill_binding_indices(&rel.get_binding_map(0))?;
+    let span = key.span();
+    let bytecode = key.compile()?;
+    let mut stack = vec![];
+    let mut ret = vec![];
+    for tuple in rel.iter()? {
+        let tuple = tuple?;
+        let k = match eval_bytecode(&bytecode, &tuple, &mut stack)? {
+            DataValue::Num(n) => n.get_float(),
+            v => bail!(BandJoinKeyNotNumber(v, span)),
+        };
+        ensure!(!k.is_nan(), BandJoinKeyNotNumber(DataValue::from(k), span));
+        ret.push((k, tuple));
+        poison.check()?;
+    }
+    ret.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    Ok(ret)
+}
+
+impl FixedRule for BandJoin {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let left = payload.get_input(0)?;
+        let right = payload.get_input(1)?;
+        let window = payload.float_option("window", None)?;
+        if window.is_nan() || window < 0. {
+            bail!(WrongFixedRuleOptionError {
+                name: "window".to_string(),
+                span: payload.span(),
+                rule_name: payload.name().to_string(),
+                help: "a non-negative number is required".to_string()
+            })
+        }
+        let inclusive = payload.bool_option("inclusive", Some(false))?;
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("The rule head of the band join must have {0} columns, got {1}")]
+        #[diagnostic(code(algo::band_join_arity_mismatch))]
+        #[diagnostic(help("The output consists of the columns of the left relation followed by those of the right relation"))]
+        struct BandJoinArityMismatch(usize, usize, #[label] SourceSpan);
+
+        let expected = left.arity()? + right.arity()?;
+        ensure!(
+            expected == payload.manifest.arity,
+            BandJoinArityMismatch(expected, payload.manifest.arity, payload.span())
+        );
+
+        let left = sorted_by_key(&left, payload.expr_option("left_key", None)?, &poison)?;
+        let right = sorted_by_key(&right, payload.expr_option("right_key", None)?, &poison)?;
+
+        let below = |r: f64, l: f64| {
+            if inclusive {
+                r < l - window
+            } else {
+                r <= l - window
+            }
+        };
+        let within = |r: f64, l: f64| {
+            if inclusive {
+                r <= l + window
+            } else {
+                r < l + window
+            }
+        };
+
+        // the start of the window in `right` only moves forward as `left` is swept
+        let mut start = 0;
+        for (l_key, l_tuple) in &left {
+            while start < right.len() && below(right[start].0, *l_key) {
+                start += 1;
+            }
+            for (r_key, r_tuple) in &right[start..] {
+                if !within(*r_key, *l_key) {
+                    break;
+                }
+                let mut tuple = l_tuple.clone();
+                tuple.extend_from_slice(r_tuple);
+                out.put(tuple);
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        match rule_head.len() {
+            0 => bail!(CannotDetermineArity(
+                "BandJoin".to_string(),
+                "the rule head must be given explicitly".to_string(),
+                span
+            )),
+            i => Ok(i),
+        }
+    }
+}
@@ -0,0 +1,275 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, VecDeque};
+
+use miette::{bail, ensure, Result};
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::{DataValue, Vector};
+use crate::fixed_rule::{BadExprValueError, FixedRule, FixedRuleInputRelation, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Read the `[id, vector]` rows of `rel`. Vectors may also be given as lists of numbers,
+/// and must all have the same length.
+fn read_points(rel: &FixedRuleInputRelation<'_, '_>) -> Result<(Vec<DataValue>, Vec<Vec<f64>>)> {
+    let mut ids = vec![];
+    let mut points: Vec<Vec<f64>> = vec![];
+    for tuple in rel.iter()? {
+        let mut tuple = tuple?.into_iter();
+        let id = tuple.next().unwrap();
+        let v = tuple.next().unwrap();
+        let point = match &v {
+            DataValue::Vec(Vector::F32(a)) => a.iter().map(|x| *x as f64).collect(),
+            DataValue::Vec(Vector::F64(a)) => a.to_vec(),
+            DataValue::List(l) => match l.iter().map(|x| x.get_float()).collect::<Option<_>>() {
+                Some(p) => p,
+                None => bail!(BadExprValueError(
+                    v,
+                    rel.span(),
+                    "a list of numbers is required".to_string()
+                )),
+            },
+            _ => bail!(BadExprValueError(
+                v,
+                rel.span(),
+                "a vector is required".to_string()
+            )),
+        };
+        if let Some(first) = points.first() {
+            if first.len() != point.len() {
+                bail!(BadExprValueError(
+                    v,
+                    rel.span(),
+                    format!("all vectors must have {} elements", first.len())
+                ))
+            }
+        }
+        ids.push(id);
+        points.push(point);
+    }
+    Ok((ids, points))
+}
+
+fn squared_l2(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+fn cosine_distance(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms =
+        a.iter().map(|x| x * x).sum::<f64>().sqrt() * b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norms == 0. {
+        1.
+    } else {
+        1. - dot / norms
+    }
+}
+
+/// K-means clustering of the vectors in the second column, with k-means++ initialization.
+/// Clusters are numbered from 0 in the order of the first row assigned to them.
+pub(crate) struct KMeans;
+
+impl FixedRule for KMeans {
+    fn is_deterministic(&self) -> bool {
+        false
+    }
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let rel = payload.get_input(0)?.ensure_min_len(2)?;
+        let k = payload.pos_integer_option("k", None)?;
+        let max_iterations = payload.pos_integer_option("max_iterations", Some(100))?;
+        let (ids, points) = read_points(&rel)?;
+        if points.is_empty() {
+            return Ok(());
+        }
+        let k = k.min(points.len());
+        let mut rng = payload.rng();
+
+        // each new centroid is drawn with a probability proportional to
+        // the squared distance to the closest centroid already chosen
+        let mut centroids = vec![points[rng.gen_range(0..points.len())].clone()];
+        let mut closest: Vec<f64> = points
+            .iter()
+            .map(|p| squared_l2(p, &centroids[0]))
+            .collect();
+        while centroids.len() < k {
+            let next = match WeightedIndex::new(&closest) {
+                Ok(dist) => dist.sample(&mut rng),
+                // all remaining points coincide with a centroid
+                Err(_) => break,
+            };
+            centroids.push(points[next].clone());
+            for (c, p) in closest.iter_mut().zip(&points) {
+                *c = c.min(squared_l2(p, &points[next]));
+            }
+            poison.check()?;
+        }
+
+        let nearest = |p: &[f64], centroids: &[Vec<f64>]| {
+            (0..centroids.len())
+                .min_by(|a, b| {
+                    squared_l2(p, &centroids[*a]).total_cmp(&squared_l2(p, &centroids[*b]))
+                })
+                .unwrap()
+        };
+        let mut assignment: Vec<usize> = points.iter().map(|p| nearest(p, &centroids)).collect();
+        for _ in 0..max_iterations {
+            let dim = points[0].len();
+            let mut sums = vec![vec![0.; dim]; centroids.len()];
+            let mut counts = vec![0usize; centroids.len()];
+            for (p, c) in points.iter().zip(&assignment) {
+                counts[*c] += 1;
+                for (s, x) in sums[*c].iter_mut().zip(p) {
+                    *s += x;
+                }
+            }
+            for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+                // an empty cluster keeps its centroid
+                if count > 0 {
+                    *centroid = sum.into_iter().map(|s| s / count as f64).collect();
+                }
+            }
+            let next: Vec<usize> = points.iter().map(|p| nearest(p, &centroids)).collect();
+            poison.check()?;
+            if next == assignment {
+                break;
+            }
+            assignment = next;
+        }
+
+        let mut labels: BTreeMap<usize, i64> = BTreeMap::new();
+        for (id, c) in ids.into_iter().zip(assignment) {
+            let n_labels = labels.len() as i64;
+            let label = *labels.entry(c).or_insert(n_labels);
+            out.put(vec![id, DataValue::from(label)]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// Density-based clustering of the vectors in the second column.
+/// Points with fewer than `min_points` neighbours within `eps` (themselves included)
+/// and not within `eps` of such a point are noise, with a null cluster.
+/// Neighbours are found by comparing all pairs of points.
+pub(crate) struct Dbscan;
+
+impl FixedRule for Dbscan {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let rel = payload.get_input(0)?.ensure_min_len(2)?;
+        let eps = payload.float_option("eps", None)?;
+        let min_points = payload.pos_integer_option("min_points", Some(5))?;
+        let distance: fn(&[f64], &[f64]) -> f64 =
+            match &payload.string_option("distance", Some("L2"))? as &str {
+                "L2" => |a, b| squared_l2(a, b).sqrt(),
+                "Cosine" => cosine_distance,
+                d => bail!(WrongFixedRuleOptionError {
+                    name: "distance".to_string(),
+                    span: payload.option_span("distance")?,
+                    rule_name: payload.name().to_string(),
+                    help: format!("'{d}' is not one of 'L2' or 'Cosine'")
+                }),
+            };
+        ensure!(
+            eps.is_finite() && eps >= 0.,
+            WrongFixedRuleOptionError {
+                name: "eps".to_string(),
+                span: payload.option_span("eps")?,
+                rule_name: payload.name().to_string(),
+                help: "a non-negative number is required".to_string()
+            }
+        );
+        let (ids, points) = read_points(&rel)?;
+
+        let neighbours = |i: usize| -> Vec<usize> {
+            (0..points.len())
+                .filter(|j| distance(&points[i], &points[*j]) <= eps)
+                .collect()
+        };
+        let mut clusters: Vec<Option<i64>> = vec![None; points.len()];
+        let mut visited = vec![false; points.len()];
+        let mut n_clusters = 0;
+        for i in 0..points.len() {
+            if visited[i] {
+                continue;
+            }
+            visited[i] = true;
+            let around = neighbours(i);
+            if around.len() < min_points {
+                continue;
+            }
+            clusters[i] = Some(n_clusters);
+            let mut queue = VecDeque::from([i]);
+            let mut core_around = Some(around);
+            while let Some(j) = queue.pop_front() {
+                let around = match core_around.take() {
+                    Some(around) => around,
+                    None => neighbours(j),
+                };
+                // only core points extend the cluster
+                if around.len() < min_points {
+                    continue;
+                }
+                for k in around {
+                    if clusters[k].is_none() {
+                        clusters[k] = Some(n_clusters);
+                    }
+                    // points are marked when queued, so each is queued at most once
+                    if !visited[k] {
+                        visited[k] = true;
+                        queue.push_back(k);
+                    }
+                }
+                poison.check()?;
+            }
+            n_clusters += 1;
+        }
+
+        for (id, cluster) in ids.into_iter().zip(clusters) {
+            out.put(vec![
+                id,
+                cluster.map(DataValue::from).unwrap_or(DataValue::Null),
+            ]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
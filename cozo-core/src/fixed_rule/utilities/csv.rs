@@ -28,6 +28,9 @@ use crate::runtime::temp_store::RegularTempStore;
 pub(crate) struct CsvReader;
 
 impl FixedRule for CsvReader {
+    fn is_deterministic(&self) -> bool {
+        false
+    }
     fn run(
         &self,
         payload: FixedRulePayload<'_, '_>,
@@ -138,6 +141,18 @@ impl FixedRule for CsvReader {
                                     Some(i) => out_tuple.push(DataValue::from(i)),
                                 };
                             }
+                            ColType::BigInt | ColType::Decimal { .. } | ColType::Duration => {
+                                out_tuple.push(match typ.coerce(dv, TERMINAL_VALIDITY.timestamp) {
+                                    Ok(data) => data,
+                                    Err(err) => {
+                                        if typ.nullable {
+                                            DataValue::Null
+                                        } else {
+                                            bail!(err)
+                                        }
+                                    }
+                                })
+                            }
                             _ => bail!("cannot convert {} to type {}", s, typ),
                         }
                     }
@@ -148,6 +163,7 @@ impl FixedRule for CsvReader {
         };
 
         let url = payload.string_option("url", None)?;
+        payload.ensure_io_allowed(&url)?;
         match url.strip_prefix("file://") {
             Some(file_path) => {
                 let mut rdr = rdr_builder.from_path(file_path).into_diagnostic()?;
@@ -0,0 +1,156 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::utilities::resample::{series_arity, SeriesExtractor, TimeGrid};
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Fills in the missing buckets of a sparse time series.
+pub(crate) struct FillGaps;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum FillMethod {
+    Null,
+    Forward,
+    Linear,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Linear interpolation requires numeric values, got {0:?}")]
+#[diagnostic(code(algo::interpolate_non_number))]
+struct InterpolateNonNumber(DataValue, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Filling the gaps would take {0} steps, more than the maximum of {1}")]
+#[diagnostic(code(algo::too_many_fill_steps))]
+#[diagnostic(help("Narrow the range with 'start' and 'end', or raise 'max_steps'"))]
+struct TooManyFillSteps(u128, usize, #[label] SourceSpan);
+
+fn interpolate(
+    (k0, v0): (i64, &DataValue),
+    (k1, v1): (i64, &DataValue),
+    k: i64,
+    span: SourceSpan,
+) -> Result<DataValue> {
+    let v0 = match v0 {
+        DataValue::Num(n) => n.get_float(),
+        v => bail!(InterpolateNonNumber(v.clone(), span)),
+    };
+    let v1 = match v1 {
+        DataValue::Num(n) => n.get_float(),
+        v => bail!(InterpolateNonNumber(v.clone(), span)),
+    };
+    Ok(DataValue::from(
+        v0 + (v1 - v0) * (k - k0) as f64 / (k1 - k0) as f64,
+    ))
+}
+
+impl FixedRule for FillGaps {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?;
+        let grid = TimeGrid::from_payload(&payload)?;
+        let mut extractor = SeriesExtractor::from_payload(&payload, &in_rel.get_binding_map(0))?;
+        let method = match &payload.string_option("method", Some("forward"))? as &str {
+            "null" => FillMethod::Null,
+            "forward" => FillMethod::Forward,
+            "linear" => FillMethod::Linear,
+            m => bail!(WrongFixedRuleOptionError {
+                name: "method".to_string(),
+                span: payload.option_span("method")?,
+                rule_name: payload.name().to_string(),
+                help: format!("'{m}' is not one of 'null', 'forward' or 'linear'")
+            }),
+        };
+        let value_span = payload
+            .option_span("value")
+            .unwrap_or_else(|_| payload.span());
+        let bound = |name: &str| -> Result<Option<i64>> {
+            Ok(match payload.manifest.options.get(name) {
+                None => None,
+                Some(ex) => Some(grid.index(&ex.clone().eval_to_const()?, true, ex.span())?),
+            })
+        };
+        let start = bound("start")?;
+        let end = bound("end")?;
+        let max_steps = payload.pos_integer_option("max_steps", Some(1_000_000))?;
+
+        // points on the grid, the last one wins if several fall into the same bucket
+        #[allow(clippy::mutable_key_type)]
+        let mut groups: BTreeMap<Vec<DataValue>, BTreeMap<i64, DataValue>> = BTreeMap::new();
+        for tuple in in_rel.iter()? {
+            let tuple = tuple?;
+            let (group, t, v) = extractor.extract(&tuple)?;
+            let k = grid.index(&t, true, extractor.time_span())?;
+            groups.entry(group).or_default().insert(k, v);
+            poison.check()?;
+        }
+
+        for (group, points) in groups {
+            let (first, last) = match (points.keys().next(), points.keys().next_back()) {
+                (Some(f), Some(l)) => (*f, *l),
+                _ => continue,
+            };
+            let start = start.unwrap_or(first);
+            let end = end.unwrap_or(last);
+            let steps = (end as i128 - start as i128 + 1).max(0) as u128;
+            if steps > max_steps as u128 {
+                bail!(TooManyFillSteps(steps, max_steps, payload.span()))
+            }
+            let mut prev: Option<(i64, &DataValue)> =
+                points.range(..start).next_back().map(|(k, v)| (*k, v));
+            for k in start..=end {
+                let val = match points.get(&k) {
+                    Some(v) => {
+                        prev = Some((k, v));
+                        v.clone()
+                    }
+                    None => match (method, prev) {
+                        (FillMethod::Null, _) | (_, None) => DataValue::Null,
+                        (FillMethod::Forward, Some((_, v))) => v.clone(),
+                        (FillMethod::Linear, Some(p)) => match points.range(k..).next() {
+                            None => DataValue::Null,
+                            Some((nk, nv)) => interpolate(p, (*nk, nv), k, value_span)?,
+                        },
+                    },
+                };
+                let mut tuple = group.clone();
+                tuple.push(grid.bucket(k, extractor.time_span())?);
+                tuple.push(val);
+                out.put(tuple);
+                poison.check()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        series_arity("FillGaps", options, span)
+    }
+}
@@ -0,0 +1,119 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::utilities::resample::timestamp_secs;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Finds, for each user, the furthest step of an ordered sequence of events
+/// reached within a time window.
+pub(crate) struct Funnel;
+
+impl FixedRule for Funnel {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?.ensure_min_len(3)?;
+        let steps = match payload.expr_option("steps", None)?.eval_to_const()? {
+            DataValue::List(l) if !l.is_empty() => l,
+            _ => bail!(WrongFixedRuleOptionError {
+                name: "steps".to_string(),
+                span: payload.option_span("steps")?,
+                rule_name: payload.name().to_string(),
+                help: "a non-empty list of events is required".to_string()
+            }),
+        };
+        let window = payload.float_option("window", Some(f64::INFINITY))?;
+        if window.is_nan() || window < 0. {
+            bail!(WrongFixedRuleOptionError {
+                name: "window".to_string(),
+                span: payload.option_span("window")?,
+                rule_name: payload.name().to_string(),
+                help: "a non-negative number is required".to_string()
+            })
+        }
+
+        #[allow(clippy::mutable_key_type)]
+        let mut users: BTreeMap<DataValue, Vec<(f64, DataValue, DataValue)>> = BTreeMap::new();
+        for tuple in in_rel.iter()? {
+            let mut tuple = tuple?;
+            tuple.truncate(3);
+            let event = tuple.pop().unwrap();
+            let t = tuple.pop().unwrap();
+            let user = tuple.pop().unwrap();
+            if !steps.contains(&event) {
+                continue;
+            }
+            users
+                .entry(user)
+                .or_default()
+                .push((timestamp_secs(&t, in_rel.span())?, t, event));
+            poison.check()?;
+        }
+
+        for (user, mut events) in users {
+            // stable, so that events with equal timestamps keep their relative order
+            events.sort_by(|(a, _, _), (b, _, _)| a.total_cmp(b));
+            // for each step, the start of the latest chain that has reached it
+            let mut chains: Vec<Option<(f64, &DataValue)>> = vec![None; steps.len()];
+            let mut best: Option<(usize, &DataValue, &DataValue)> = None;
+            for (t, t_val, event) in &events {
+                // in reverse, so that one event cannot advance a chain by several steps
+                for k in (0..steps.len()).rev() {
+                    if steps[k] != *event {
+                        continue;
+                    }
+                    let start = if k == 0 {
+                        Some((*t, t_val))
+                    } else {
+                        chains[k - 1].filter(|(s, _)| *t - *s <= window)
+                    };
+                    if let Some(start) = start {
+                        chains[k] = Some(start);
+                        if best.is_none_or(|(reached, _, _)| k + 1 > reached) {
+                            best = Some((k + 1, start.1, t_val));
+                        }
+                    }
+                }
+            }
+            if let Some((reached, start, end)) = best {
+                out.put(vec![
+                    user,
+                    DataValue::from(reached as i64),
+                    start.clone(),
+                    end.clone(),
+                ]);
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(4)
+    }
+}
@@ -32,6 +32,9 @@ use crate::runtime::temp_store::RegularTempStore;
 pub(crate) struct JsonReader;
 
 impl FixedRule for JsonReader {
+    fn is_deterministic(&self) -> bool {
+        false
+    }
     fn run(
         &self,
         payload: FixedRulePayload<'_, '_>,
@@ -39,6 +42,7 @@ impl FixedRule for JsonReader {
         _poison: Poison,
     ) -> Result<()> {
         let url = payload.string_option("url", None)?;
+        payload.ensure_io_allowed(&url)?;
         let json_lines = payload.bool_option("json_lines", Some(true))?;
         let null_if_absent = payload.bool_option("null_if_absent", Some(false))?;
         let prepend_index = payload.bool_option("prepend_index", Some(false))?;
@@ -6,12 +6,32 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub(crate) mod band_join;
+pub(crate) mod clustering;
 pub(crate) mod constant;
 pub(crate) mod csv;
+pub(crate) mod fill_gaps;
+pub(crate) mod funnel;
 pub(crate) mod jlines;
 pub(crate) mod reorder_sort;
+pub(crate) mod resample;
+pub(crate) mod rollup;
+pub(crate) mod sample;
+pub(crate) mod sessionize;
+#[cfg(feature = "sql-reader")]
+pub(crate) mod sql;
 
 pub(crate) use self::csv::CsvReader;
+pub(crate) use band_join::BandJoin;
+pub(crate) use clustering::{Dbscan, KMeans};
 pub(crate) use constant::Constant;
+pub(crate) use fill_gaps::FillGaps;
+pub(crate) use funnel::Funnel;
 pub(crate) use jlines::JsonReader;
 pub(crate) use reorder_sort::ReorderSort;
+pub(crate) use resample::Resample;
+pub(crate) use rollup::Rollup;
+pub(crate) use sample::Sample;
+pub(crate) use sessionize::Sessionize;
+#[cfg(feature = "sql-reader")]
+pub(crate) use sql::SqlReader;
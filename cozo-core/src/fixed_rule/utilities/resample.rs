@@ -0,0 +1,303 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::aggr::{parse_aggr, NormalAggrObj};
+use crate::data::expr::{eval_bytecode, Bytecode, Expr};
+use crate::data::functions::OP_LIST;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Num};
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Buckets timestamps into fixed intervals and aggregates the values in each bucket.
+pub(crate) struct Resample;
+
+/// The grid of buckets `origin + k * interval` shared by the time series utilities.
+/// Buckets are identified by `k`.
+pub(crate) struct TimeGrid {
+    origin: Num,
+    interval: Num,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The timestamp must evaluate to a number, got {0:?}")]
+#[diagnostic(code(algo::timestamp_not_number))]
+struct TimestampNotNumber(DataValue, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The timestamp {0:?} is too far from the origin of the time grid")]
+#[diagnostic(code(algo::timestamp_out_of_range))]
+struct TimestampOutOfRange(DataValue, #[label] SourceSpan);
+
+/// The timestamp `t` as a float, for the utilities comparing durations.
+pub(crate) fn timestamp_secs(t: &DataValue, span: SourceSpan) -> Result<f64> {
+    match t {
+        DataValue::Num(n) if !n.get_float().is_nan() => Ok(n.get_float()),
+        v => bail!(TimestampNotNumber(v.clone(), span)),
+    }
+}
+
+impl TimeGrid {
+    pub(crate) fn from_payload(payload: &FixedRulePayload<'_, '_>) -> Result<Self> {
+        let num_option = |name: &str, default: Option<Num>| -> Result<Num> {
+            let ex = payload.expr_option(
+                name,
+                default.map(|n| Expr::Const {
+                    val: DataValue::Num(n),
+                    span: SourceSpan(0, 0),
+                }),
+            )?;
+            match ex.clone().eval_to_const()? {
+                DataValue::Num(n) if n.get_float().is_finite() => Ok(n),
+                _ => bail!(WrongFixedRuleOptionError {
+                    name: name.to_string(),
+                    span: ex.span(),
+                    rule_name: payload.name().to_string(),
+                    help: "a number is required".to_string()
+                }),
+            }
+        };
+        let interval = num_option("interval", None)?;
+        if interval.get_float() <= 0. {
+            bail!(WrongFixedRuleOptionError {
+                name: "interval".to_string(),
+                span: payload.option_span("interval")?,
+                rule_name: payload.name().to_string(),
+                help: "a positive number is required".to_string()
+            })
+        }
+        let origin = num_option("origin", Some(Num::Int(0)))?;
+        Ok(Self { origin, interval })
+    }
+    /// The bucket containing `t`.
+    /// If `nearest` is true, the bucket whose start is nearest to `t` is returned instead.
+    pub(crate) fn index(&self, t: &DataValue, nearest: bool, span: SourceSpan) -> Result<i64> {
+        let t = match t {
+            DataValue::Num(n) => *n,
+            v => bail!(TimestampNotNumber(v.clone(), span)),
+        };
+        let k = match (t, self.origin, self.interval) {
+            (Num::Int(t), Num::Int(o), Num::Int(i)) => t.checked_sub(o).and_then(|d| {
+                if nearest {
+                    d.checked_add(i / 2)?.checked_div_euclid(i)
+                } else {
+                    d.checked_div_euclid(i)
+                }
+            }),
+            _ => {
+                let k = (t.get_float() - self.origin.get_float()) / self.interval.get_float();
+                if k.is_nan() {
+                    bail!(TimestampNotNumber(DataValue::Num(t), span))
+                }
+                let k = if nearest { k.round() } else { k.floor() };
+                // the bounds are powers of two, so they are exact as floats
+                if (i64::MIN as f64..i64::MAX as f64).contains(&k) {
+                    Some(k as i64)
+                } else {
+                    None
+                }
+            }
+        };
+        match k {
+            Some(k) => Ok(k),
+            None => bail!(TimestampOutOfRange(DataValue::Num(t), span)),
+        }
+    }
+    /// The start of the `k`-th bucket.
+    pub(crate) fn bucket(&self, k: i64, span: SourceSpan) -> Result<DataValue> {
+        Ok(match (self.origin, self.interval) {
+            (Num::Int(o), Num::Int(i)) => match k.checked_mul(i).and_then(|d| d.checked_add(o)) {
+                Some(t) => DataValue::from(t),
+                None => bail!(TimestampOutOfRange(DataValue::from(k), span)),
+            },
+            (o, i) => DataValue::from(o.get_float() + k as f64 * i.get_float()),
+        })
+    }
+}
+
+pub(crate) fn by_list(expr: Expr) -> Option<Vec<Expr>> {
+    match expr {
+        Expr::Const {
+            val: DataValue::List(l),
+            span,
+        } => Some(
+            l.into_iter()
+                .map(|val| Expr::Const { val, span })
+                .collect_vec(),
+        ),
+        Expr::Apply { op, args, .. } if *op == OP_LIST => Some(args.to_vec()),
+        _ => None,
+    }
+}
+
+/// Compiled `time`, `value` and `by` options, evaluated against the tuples of the input relation.
+pub(crate) struct SeriesExtractor {
+    time: Vec<Bytecode>,
+    time_span: SourceSpan,
+    value: Vec<Bytecode>,
+    by: Vec<Vec<Bytecode>>,
+    stack: Vec<DataValue>,
+}
+
+impl SeriesExtractor {
+    pub(crate) fn from_payload(
+        payload: &FixedRulePayload<'_, '_>,
+        binding_map: &BTreeMap<Symbol, usize>,
+    ) -> Result<Self> {
+        let mut time = payload.expr_option("time", None)?;
+        let mut value = payload.expr_option(
+            "value",
+            Some(Expr::Const {
+                val: DataValue::Null,
+                span: SourceSpan(0, 0),
+            }),
+        )?;
+        let by = payload.expr_option(
+            "by",
+            Some(Expr::Const {
+                val: DataValue::List(vec![]),
+                span: SourceSpan(0, 0),
+            }),
+        )?;
+        let mut by = match by_list(by) {
+            Some(l) => l,
+            None => bail!(WrongFixedRuleOptionError {
+                name: "by".to_string(),
+                span: payload.option_span("by")?,
+                rule_name: payload.name().to_string(),
+                help: "This option must evaluate to a list".to_string()
+            }),
+        };
+        time.fill_binding_indices(binding_map)?;
+        value.fill_binding_indices(binding_map)?;
+        for ex in by.iter_mut() {
+            ex.fill_binding_indices(binding_map)?;
+        }
+        Ok(Self {
+            time_span: time.span(),
+            time: time.compile()?,
+            value: value.compile()?,
+            by: by.iter().map(|ex| ex.compile()).try_collect()?,
+            stack: vec![],
+        })
+    }
+    pub(crate) fn time_span(&self) -> SourceSpan {
+        self.time_span
+    }
+    /// The number of `by` columns.
+    pub(crate) fn group_len(&self) -> usize {
+        self.by.len()
+    }
+    /// Returns the group, the timestamp and the value of the tuple.
+    pub(crate) fn extract(
+        &mut self,
+        tuple: &Tuple,
+    ) -> Result<(Vec<DataValue>, DataValue, DataValue)> {
+        let group = self
+            .by
+            .iter()
+            .map(|bc| eval_bytecode(bc, tuple, &mut self.stack))
+            .try_collect()?;
+        let t = eval_bytecode(&self.time, tuple, &mut self.stack)?;
+        let v = eval_bytecode(&self.value, tuple, &mut self.stack)?;
+        Ok((group, t, v))
+    }
+}
+
+/// The arity of the time series utilities: the `by` columns, the bucket and the value.
+pub(crate) fn series_arity(
+    rule_name: &str,
+    options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    span: SourceSpan,
+) -> Result<usize> {
+    Ok(match options.get("by") {
+        None => 2,
+        Some(by) => match by_list(by.clone()) {
+            Some(l) => l.len() + 2,
+            None => bail!(CannotDetermineArity(
+                rule_name.to_string(),
+                "invalid option 'by' given, expect a list".to_string(),
+                span
+            )),
+        },
+    })
+}
+
+impl FixedRule for Resample {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?;
+        let grid = TimeGrid::from_payload(&payload)?;
+        let mut extractor = SeriesExtractor::from_payload(&payload, &in_rel.get_binding_map(0))?;
+        let aggr_name = payload.string_option("aggregation", Some("mean"))?;
+        let aggr = match parse_aggr(&aggr_name) {
+            Some(aggr) => aggr,
+            None => bail!(WrongFixedRuleOptionError {
+                name: "aggregation".to_string(),
+                span: payload.option_span("aggregation")?,
+                rule_name: payload.name().to_string(),
+                help: format!("'{aggr_name}' is not an aggregation")
+            }),
+        };
+
+        #[allow(clippy::mutable_key_type)]
+        let mut groups: BTreeMap<Vec<DataValue>, BTreeMap<i64, Box<dyn NormalAggrObj>>> =
+            BTreeMap::new();
+        for tuple in in_rel.iter()? {
+            let tuple = tuple?;
+            let (group, t, v) = extractor.extract(&tuple)?;
+            let k = grid.index(&t, false, extractor.time_span())?;
+            let buckets = groups.entry(group).or_default();
+            let op = match buckets.entry(k) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let mut aggr = aggr.clone();
+                    aggr.normal_init(&[])?;
+                    e.insert(aggr.normal_op.unwrap())
+                }
+            };
+            op.set(&v)?;
+            poison.check()?;
+        }
+        for (group, buckets) in groups {
+            for (k, op) in buckets {
+                let mut tuple = group.clone();
+                tuple.push(grid.bucket(k, extractor.time_span())?);
+                tuple.push(op.get()?);
+                out.put(tuple);
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        series_arity("Resample", options, span)
+    }
+}
@@ -0,0 +1,176 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::aggr::{parse_aggr, Aggregation, NormalAggrObj};
+use crate::data::expr::{eval_bytecode, Bytecode, Expr};
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::utilities::resample::{by_list, SeriesExtractor, TimeGrid};
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Buckets timestamps into fixed intervals and computes several aggregations in each bucket,
+/// for downsampling into a relation of rollups.
+///
+/// If a second input relation is given, it holds the rollups written so far, starting with
+/// the `by` columns and the bucket. Rows of a group before its latest rolled up bucket are
+/// then skipped, so that running the rule again, e.g. on a schedule, only computes the
+/// buckets that may have changed since.
+pub(crate) struct Rollup;
+
+fn aggregations_list(
+    payload: &FixedRulePayload<'_, '_>,
+) -> Result<Vec<(&'static Aggregation, Expr)>> {
+    let bad = |help: String| -> Result<_> {
+        bail!(WrongFixedRuleOptionError {
+            name: "aggregations".to_string(),
+            span: payload.option_span("aggregations")?,
+            rule_name: payload.name().to_string(),
+            help
+        })
+    };
+    let pairs = match by_list(payload.expr_option("aggregations", None)?) {
+        Some(l) if !l.is_empty() => l,
+        _ => return bad("a non-empty list of [aggregation, value] pairs is required".to_string()),
+    };
+    let mut ret = vec![];
+    for pair in pairs {
+        let Some([name, value]) = by_list(pair).and_then(|p| <[Expr; 2]>::try_from(p).ok()) else {
+            return bad("each aggregation must be given as [aggregation, value]".to_string());
+        };
+        let name = match name.eval_to_const()? {
+            DataValue::Str(s) => s,
+            v => return bad(format!("{v:?} is not the name of an aggregation")),
+        };
+        match parse_aggr(&name) {
+            Some(aggr) => ret.push((aggr, value)),
+            None => return bad(format!("'{name}' is not an aggregation")),
+        }
+    }
+    Ok(ret)
+}
+
+impl FixedRule for Rollup {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?;
+        let grid = TimeGrid::from_payload(&payload)?;
+        let binding_map = in_rel.get_binding_map(0);
+        let mut extractor = SeriesExtractor::from_payload(&payload, &binding_map)?;
+        let aggrs = aggregations_list(&payload)?;
+        let values: Vec<Vec<Bytecode>> = aggrs
+            .iter()
+            .map(|(_, value)| {
+                let mut value = value.clone();
+                value.fill_binding_indices(&binding_map)?;
+                value.compile()
+            })
+            .try_collect()?;
+        let mut stack = vec![];
+
+        #[allow(clippy::mutable_key_type)]
+        let mut latest: BTreeMap<Vec<DataValue>, i64> = BTreeMap::new();
+        if payload.inputs_count() > 1 {
+            let n_by = extractor.group_len();
+            let done = payload.get_input(1)?.ensure_min_len(n_by + 1)?;
+            for tuple in done.iter()? {
+                let mut tuple = tuple?;
+                tuple.truncate(n_by + 1);
+                let bucket = tuple.pop().unwrap();
+                let k = grid.index(&bucket, false, done.span())?;
+                let entry = latest.entry(tuple).or_insert(k);
+                *entry = (*entry).max(k);
+                poison.check()?;
+            }
+        }
+
+        #[allow(clippy::mutable_key_type)]
+        let mut groups: BTreeMap<
+            Vec<DataValue>,
+            BTreeMap<i64, Vec<Box<dyn NormalAggrObj>>>,
+        > = BTreeMap::new();
+        for tuple in in_rel.iter()? {
+            let tuple = tuple?;
+            let (group, t, _) = extractor.extract(&tuple)?;
+            let k = grid.index(&t, false, extractor.time_span())?;
+            if latest.get(&group).is_some_and(|l| k < *l) {
+                continue;
+            }
+            let buckets = groups.entry(group).or_default();
+            let ops = match buckets.entry(k) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let mut ops = Vec::with_capacity(aggrs.len());
+                    for (aggr, _) in &aggrs {
+                        let mut aggr = (*aggr).clone();
+                        aggr.normal_init(&[])?;
+                        ops.push(aggr.normal_op.unwrap());
+                    }
+                    e.insert(ops)
+                }
+            };
+            for (op, value) in ops.iter_mut().zip(values.iter()) {
+                op.set(&eval_bytecode(value, &tuple, &mut stack)?)?;
+            }
+            poison.check()?;
+        }
+        for (group, buckets) in groups {
+            for (k, ops) in buckets {
+                let mut tuple = group.clone();
+                tuple.push(grid.bucket(k, extractor.time_span())?);
+                for op in ops {
+                    tuple.push(op.get()?);
+                }
+                out.put(tuple);
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        let list_len = |name: &str| -> Result<usize> {
+            match options.get(name) {
+                None if name == "by" => Ok(0),
+                None => bail!(CannotDetermineArity(
+                    "Rollup".to_string(),
+                    format!("option '{name}' not provided"),
+                    span
+                )),
+                Some(ex) => match by_list(ex.clone()) {
+                    Some(l) => Ok(l.len()),
+                    None => bail!(CannotDetermineArity(
+                        "Rollup".to_string(),
+                        format!("invalid option '{name}' given, expect a list"),
+                        span
+                    )),
+                },
+            }
+        };
+        Ok(list_len("by")? + 1 + list_len("aggregations")?)
+    }
+}
@@ -0,0 +1,109 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, Diagnostic, Result};
+use rand::Rng;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Samples the rows of a relation in a single pass, keeping at most the sample in memory:
+/// `n` rows uniformly by reservoir sampling, or each row with probability `p`.
+pub(crate) struct Sample;
+
+impl FixedRule for Sample {
+    fn is_deterministic(&self) -> bool {
+        false
+    }
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?;
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("The rule head of the sample must have {0} columns, got {1}")]
+        #[diagnostic(code(algo::sample_arity_mismatch))]
+        #[diagnostic(help("The sampled rows have the columns of the input relation"))]
+        struct SampleArityMismatch(usize, usize, #[label] SourceSpan);
+
+        let expected = in_rel.arity()?;
+        ensure!(
+            expected == payload.manifest.arity,
+            SampleArityMismatch(expected, payload.manifest.arity, payload.span())
+        );
+
+        let options = &payload.manifest.options;
+        let mut rng = payload.rng();
+        match (options.contains_key("n"), options.contains_key("p")) {
+            (true, false) => {
+                let n = payload.non_neg_integer_option("n", None)?;
+                let mut reservoir = Vec::with_capacity(n);
+                for (i, tuple) in in_rel.iter()?.enumerate() {
+                    let tuple = tuple?;
+                    if i < n {
+                        reservoir.push(tuple);
+                    } else {
+                        let j = rng.gen_range(0..=i);
+                        if j < n {
+                            reservoir[j] = tuple;
+                        }
+                    }
+                    poison.check()?;
+                }
+                for tuple in reservoir {
+                    out.put(tuple);
+                }
+            }
+            (false, true) => {
+                let p = payload.unit_interval_option("p", None)?;
+                for tuple in in_rel.iter()? {
+                    let tuple = tuple?;
+                    if rng.gen::<f64>() < p {
+                        out.put(tuple);
+                    }
+                    poison.check()?;
+                }
+            }
+            _ => bail!(WrongFixedRuleOptionError {
+                name: "n".to_string(),
+                span: payload.span(),
+                rule_name: payload.name().to_string(),
+                help: "exactly one of the options 'n' and 'p' is required".to_string()
+            }),
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        match rule_head.len() {
+            0 => bail!(CannotDetermineArity(
+                "Sample".to_string(),
+                "the rule head must be given explicitly".to_string(),
+                span
+            )),
+            i => Ok(i),
+        }
+    }
+}
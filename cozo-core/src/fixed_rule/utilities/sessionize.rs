@@ -0,0 +1,91 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::utilities::resample::timestamp_secs;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Splits the events of each user into sessions separated by periods of inactivity.
+pub(crate) struct Sessionize;
+
+impl FixedRule for Sessionize {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?.ensure_min_len(2)?;
+        let gap = payload.float_option("gap", None)?;
+        if gap.is_nan() || gap < 0. {
+            bail!(WrongFixedRuleOptionError {
+                name: "gap".to_string(),
+                span: payload.option_span("gap")?,
+                rule_name: payload.name().to_string(),
+                help: "a non-negative number is required".to_string()
+            })
+        }
+
+        #[allow(clippy::mutable_key_type)]
+        let mut users: BTreeMap<DataValue, Vec<(f64, DataValue)>> = BTreeMap::new();
+        for tuple in in_rel.iter()? {
+            let mut tuple = tuple?;
+            tuple.truncate(2);
+            let t = tuple.pop().unwrap();
+            let user = tuple.pop().unwrap();
+            users
+                .entry(user)
+                .or_default()
+                .push((timestamp_secs(&t, in_rel.span())?, t));
+            poison.check()?;
+        }
+
+        for (user, mut events) in users {
+            events.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+            let mut session = 0;
+            let mut start = 0;
+            for i in 1..=events.len() {
+                // a session ends at the last event, or before a gap longer than `gap`
+                if i < events.len() && events[i].0 - events[i - 1].0 <= gap {
+                    continue;
+                }
+                out.put(vec![
+                    user.clone(),
+                    DataValue::from(session),
+                    events[start].1.clone(),
+                    events[i - 1].1.clone(),
+                    DataValue::from((i - start) as i64),
+                ]);
+                session += 1;
+                start = i;
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(5)
+    }
+}
@@ -0,0 +1,259 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, miette, IntoDiagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::program::{FixedRuleOptionNotFoundError, WrongFixedRuleOptionError};
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+#[cfg(feature = "sql-reader-postgres")]
+use crate::data::value::JsonData;
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Runs a SQL query against an external database and yields the rows of the result.
+/// The database is chosen by the scheme of the `connection` option:
+/// `sqlite://<path>`, `postgres://...` or `mysql://...`. The `query` option is the SQL to run,
+/// and `columns` lists the names of the returned columns, which determines the arity.
+/// SQLite databases are opened read-only, and connections to servers do not use TLS.
+pub(crate) struct SqlReader;
+
+impl FixedRule for SqlReader {
+    fn is_deterministic(&self) -> bool {
+        false
+    }
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let connection = payload.string_option("connection", None)?;
+        payload.ensure_io_allowed(&connection)?;
+        let query = payload.string_option("query", None)?;
+        let arity = column_names(&payload.expr_option("columns", None)?, payload.span())?.len();
+
+        let mut emit = |row: Vec<DataValue>| -> Result<()> {
+            ensure!(
+                row.len() == arity,
+                WrongFixedRuleOptionError {
+                    name: "columns".to_string(),
+                    span: payload.span(),
+                    rule_name: "SqlReader".to_string(),
+                    help: format!(
+                        "the query returns {} columns, but {} are declared",
+                        row.len(),
+                        arity
+                    )
+                }
+            );
+            poison.check()?;
+            out.put(row);
+            Ok(())
+        };
+
+        if let Some(path) = connection.strip_prefix("sqlite://") {
+            read_sqlite(path, &query, &mut emit)
+        } else if connection.starts_with("postgres://") || connection.starts_with("postgresql://") {
+            #[cfg(feature = "sql-reader-postgres")]
+            return read_postgres(&connection, &query, &mut emit);
+            #[cfg(not(feature = "sql-reader-postgres"))]
+            bail!("the feature `sql-reader-postgres` is not enabled for the build")
+        } else if connection.starts_with("mysql://") {
+            #[cfg(feature = "sql-reader-mysql")]
+            return read_mysql(&connection, &query, &mut emit);
+            #[cfg(not(feature = "sql-reader-mysql"))]
+            bail!("the feature `sql-reader-mysql` is not enabled for the build")
+        } else {
+            bail!(WrongFixedRuleOptionError {
+                name: "connection".to_string(),
+                span: payload.span(),
+                rule_name: "SqlReader".to_string(),
+                help:
+                    "the connection string must start with 'sqlite://', 'postgres://' or 'mysql://'"
+                        .to_string()
+            })
+        }
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        let columns = options
+            .get("columns")
+            .ok_or_else(|| FixedRuleOptionNotFoundError {
+                name: "columns".to_string(),
+                span,
+                rule_name: "SqlReader".to_string(),
+            })?;
+        Ok(column_names(columns, span)?.len())
+    }
+}
+
+fn column_names(columns: &Expr, span: SourceSpan) -> Result<Vec<DataValue>> {
+    match columns.clone().eval_to_const()? {
+        DataValue::List(l) if !l.is_empty() && l.iter().all(|c| c.get_str().is_some()) => Ok(l),
+        _ => bail!(CannotDetermineArity(
+            "SqlReader".to_string(),
+            "invalid option 'columns' given, expect a non-empty list of column names".to_string(),
+            span
+        )),
+    }
+}
+
+fn read_sqlite(
+    path: &str,
+    query: &str,
+    emit: &mut impl FnMut(Vec<DataValue>) -> Result<()>,
+) -> Result<()> {
+    let flags = sqlite::OpenFlags::new().with_read_only();
+    let conn = sqlite::Connection::open_with_flags(path, flags)
+        .map_err(|e| miette!("cannot open SQLite database '{}': {}", path, e))?;
+    let mut statement = conn.prepare(query).into_diagnostic()?;
+    let n_cols = statement.column_count();
+    while statement.next().into_diagnostic()? != sqlite::State::Done {
+        let row = (0..n_cols)
+            .map(|i| {
+                Ok(
+                    match statement.read::<sqlite::Value, _>(i).into_diagnostic()? {
+                        sqlite::Value::Null => DataValue::Null,
+                        sqlite::Value::Integer(i) => DataValue::from(i),
+                        sqlite::Value::Float(f) => DataValue::from(f),
+                        sqlite::Value::String(s) => DataValue::from(s),
+                        sqlite::Value::Binary(b) => DataValue::from(b),
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        emit(row)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sql-reader-postgres")]
+fn read_postgres(
+    connection: &str,
+    query: &str,
+    emit: &mut impl FnMut(Vec<DataValue>) -> Result<()>,
+) -> Result<()> {
+    use postgres::fallible_iterator::FallibleIterator;
+    use postgres::types::{ToSql, Type};
+
+    fn convert(row: &postgres::Row, i: usize) -> Result<DataValue, postgres::Error> {
+        let ty = row.columns()[i].type_();
+        Ok(match *ty {
+            Type::BOOL => row.try_get::<_, Option<bool>>(i)?.map(DataValue::from),
+            Type::INT2 => row
+                .try_get::<_, Option<i16>>(i)?
+                .map(|v| DataValue::from(v as i64)),
+            Type::INT4 => row.try_get::<_, Option<i32>>(i)?.map(DataValue::from),
+            Type::INT8 => row.try_get::<_, Option<i64>>(i)?.map(DataValue::from),
+            Type::OID => row
+                .try_get::<_, Option<u32>>(i)?
+                .map(|v| DataValue::from(v as i64)),
+            Type::FLOAT4 => row
+                .try_get::<_, Option<f32>>(i)?
+                .map(|v| DataValue::from(v as f64)),
+            Type::FLOAT8 => row.try_get::<_, Option<f64>>(i)?.map(DataValue::from),
+            Type::BYTEA => row.try_get::<_, Option<Vec<u8>>>(i)?.map(DataValue::from),
+            Type::UUID => row
+                .try_get::<_, Option<uuid::Uuid>>(i)?
+                .map(DataValue::uuid),
+            Type::JSON | Type::JSONB => row
+                .try_get::<_, Option<serde_json::Value>>(i)?
+                .map(|v| DataValue::Json(JsonData(v))),
+            // text-like types, everything else fails with an error naming the type
+            _ => row.try_get::<_, Option<String>>(i)?.map(DataValue::from),
+        }
+        .unwrap_or(DataValue::Null))
+    }
+
+    let mut client = postgres::Client::connect(connection, postgres::NoTls)
+        .map_err(|e| miette!("cannot connect to PostgreSQL: {}", e))?;
+    let mut rows = client
+        .query_raw(query, std::iter::empty::<&dyn ToSql>())
+        .into_diagnostic()?;
+    while let Some(row) = rows.next().into_diagnostic()? {
+        let converted = (0..row.len())
+            .map(|i| convert(&row, i))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
+                miette!(
+                    "cannot convert PostgreSQL value: {}, cast the column to text in the query",
+                    e
+                )
+            })?;
+        emit(converted)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sql-reader-mysql")]
+fn read_mysql(
+    connection: &str,
+    query: &str,
+    emit: &mut impl FnMut(Vec<DataValue>) -> Result<()>,
+) -> Result<()> {
+    use mysql::prelude::Queryable;
+    use mysql::Value;
+
+    /// Character set number of binary strings
+    const BINARY_CHARSET: u16 = 63;
+
+    let opts = mysql::Opts::from_url(connection).into_diagnostic()?;
+    let mut conn = mysql::Conn::new(opts).map_err(|e| miette!("cannot connect to MySQL: {}", e))?;
+    // executed as a prepared statement, so that values come back typed
+    let result = conn.exec_iter(query, ()).into_diagnostic()?;
+    let binary = result
+        .columns()
+        .as_ref()
+        .iter()
+        .map(|c| c.character_set() == BINARY_CHARSET)
+        .collect::<Vec<_>>();
+    for row in result {
+        let row = row.into_diagnostic()?.unwrap();
+        let converted = row
+            .into_iter()
+            .zip(binary.iter())
+            .map(|(v, is_binary)| match v {
+                Value::NULL => DataValue::Null,
+                Value::Int(i) => DataValue::from(i),
+                Value::UInt(u) => match i64::try_from(u) {
+                    Ok(i) => DataValue::from(i),
+                    Err(_) => DataValue::from(u as f64),
+                },
+                Value::Float(f) => DataValue::from(f as f64),
+                Value::Double(f) => DataValue::from(f),
+                Value::Bytes(b) if *is_binary => DataValue::from(b),
+                Value::Bytes(b) => match String::from_utf8(b) {
+                    Ok(s) => DataValue::from(s),
+                    Err(e) => DataValue::from(e.into_bytes()),
+                },
+                Value::Date(y, mo, d, h, mi, s, us) => DataValue::from(format!(
+                    "{y:04}-{mo:02}-{d:02}T{h:02}:{mi:02}:{s:02}.{us:06}"
+                )),
+                Value::Time(neg, d, h, mi, s, us) => DataValue::from(format!(
+                    "{}{}:{mi:02}:{s:02}.{us:06}",
+                    if neg { "-" } else { "" },
+                    d * 24 + h as u32
+                )),
+            })
+            .collect();
+        emit(converted)?;
+    }
+    Ok(())
+}
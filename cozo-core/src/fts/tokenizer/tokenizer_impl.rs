@@ -121,6 +121,24 @@ impl TextAnalyzer {
             ret
         }
     }
+    /// Character n-grams of the text, with the tokens separated by single spaces
+    pub(crate) fn unique_char_ngrams(&self, text: &str, n: usize) -> FxHashSet<SmartString<LazyCompact>> {
+        let mut token_steam = self.token_stream(text);
+        let mut tokens: Vec<String> = vec![];
+        while let Some(token) = token_steam.next() {
+            tokens.push(token.text.clone());
+        }
+        let chars: Vec<char> = tokens.join(" ").chars().collect();
+
+        if n >= chars.len() {
+            iter::once(SmartString::from_iter(chars)).collect()
+        } else {
+            chars
+                .windows(n)
+                .map(|w| w.iter().copied().collect())
+                .collect()
+        }
+    }
 }
 
 impl Clone for TextAnalyzer {
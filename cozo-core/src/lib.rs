@@ -38,16 +38,18 @@ use std::sync::Arc;
 use std::time::Instant;
 
 use crossbeam::channel::{bounded, Receiver, Sender};
-use data::functions::current_validity;
 use lazy_static::lazy_static;
 pub use miette::Error;
+#[cfg(feature = "arrow")]
+pub use {arrow_array, arrow_schema};
+#[cfg(feature = "tracing")]
+pub use tracing;
 use miette::Report;
 #[allow(unused_imports)]
 use miette::{
     bail, miette, GraphicalReportHandler, GraphicalTheme, IntoDiagnostic, JSONReportHandler,
     Result, ThemeCharacters, ThemeStyles,
 };
-use parse::parse_script;
 use parse::CozoScript;
 use serde_json::json;
 
@@ -55,9 +57,11 @@ pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, Valid
 pub use fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
 pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
-pub use runtime::relation::decode_tuple_from_kv;
+pub use runtime::relation::{decode_tuple_columns_from_kv, decode_tuple_from_kv, MutationKind};
 pub use runtime::temp_store::RegularTempStore;
 pub use storage::mem::{new_cozo_mem, MemStorage};
+#[cfg(feature = "storage-opfs")]
+pub use storage::opfs::{new_cozo_opfs, OpfsStorage};
 #[cfg(feature = "storage-rocksdb")]
 pub use storage::rocks::{new_cozo_rocksdb, RocksDbStorage};
 #[cfg(feature = "storage-new-rocksdb")]
@@ -68,21 +72,37 @@ pub use storage::sled::{new_cozo_sled, SledStorage};
 pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
 #[cfg(feature = "storage-tikv")]
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
-pub use storage::{Storage, StoreTx};
+pub use storage::{Storage, StoreTx, TransactionConflict};
 
 pub use crate::data::expr::Expr;
 use crate::data::json::JsonValue;
 pub use crate::data::symb::Symbol;
 pub use crate::data::value::{JsonData, Vector};
-pub use crate::fixed_rule::SimpleFixedRule;
+pub use crate::fixed_rule::{RowBatches, RowSink, SimpleFixedRule, StreamingFixedRule};
+pub use crate::parse::format::format_script;
 pub use crate::parse::SourceSpan;
 pub use crate::runtime::callback::CallbackOp;
 pub use crate::runtime::db::evaluate_expressions;
+pub use crate::runtime::db::evaluate_expressions_batch;
 pub use crate::runtime::db::get_variables;
+pub use crate::runtime::db::InlineTransaction;
 pub use crate::runtime::db::Payload;
 pub use crate::runtime::db::Poison;
+pub use crate::runtime::db::QueryEvent;
+pub use crate::runtime::db::RestoreOptions;
 pub use crate::runtime::db::ScriptMutability;
+pub use crate::runtime::db::Snapshot;
 pub use crate::runtime::db::TransactionPayload;
+pub use crate::runtime::lsp::{
+    tokenize, Completion, CompletionKind, DiagnosticLabel, ScriptDiagnostic, Token, TokenKind,
+};
+pub use crate::runtime::migration::Migration;
+pub use crate::runtime::plan::{PlanNode, PlanOp, PlanRule, PlanRuleBody, PlanStratum, QueryPlan};
+pub use crate::runtime::pool::DbPool;
+pub use crate::runtime::sandbox::Sandbox;
+pub use crate::runtime::signature::{ParamSignature, ScriptSignature};
+pub use crate::runtime::virtual_relation::{VirtualRelation, VirtualRows};
+use crate::runtime::spans::Span;
 
 pub mod data;
 pub(crate) mod fixed_rule;
@@ -121,6 +141,10 @@ pub enum DbInstance {
     #[cfg(feature = "storage-tikv")]
     /// TiKV storage (experimental)
     TiKv(Db<TiKvStorage>),
+    #[cfg(feature = "storage-opfs")]
+    /// Origin Private File System storage, for browsers (experimental).
+    /// Constructed with [new_cozo_opfs] since it needs a file handle.
+    Opfs(Db<OpfsStorage>),
 }
 
 impl Default for DbInstance {
@@ -197,6 +221,8 @@ impl DbInstance {
             DbInstance::Sled(db) => db.get_fixed_rules(),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.get_fixed_rules(),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.get_fixed_rules(),
         }
     }
     /// Dispatcher method. See [crate::Db::run_script].
@@ -206,12 +232,128 @@ impl DbInstance {
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
     ) -> Result<NamedRows> {
-        let cur_vld = current_validity();
-        self.run_script_ast(
-            parse_script(payload, &params, &self.get_fixed_rules(), cur_vld)?,
-            cur_vld,
-            mutability,
-        )
+        self.run_script_with_handle(payload, params, mutability, &Poison::default())
+    }
+    /// Dispatcher method. See [crate::Db::run_script_arrow].
+    #[cfg(feature = "arrow")]
+    pub fn run_script_arrow(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<arrow_array::RecordBatch> {
+        self.run_script(payload, params, mutability)?.to_arrow()
+    }
+    /// Dispatcher method. See [crate::Db::run_script_in_span].
+    #[cfg(feature = "tracing")]
+    pub fn run_script_in_span(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        parent: &tracing::Span,
+    ) -> Result<NamedRows> {
+        parent.in_scope(|| self.run_script(payload, params, mutability))
+    }
+    /// Dispatcher method. See [crate::Db::run_script_with_handle].
+    pub fn run_script_with_handle(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        handle: &Poison,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.run_script_with_handle(payload, params, mutability, handle),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_with_handle(payload, params, mutability, handle),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_with_handle(payload, params, mutability, handle),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.run_script_with_handle(payload, params, mutability, handle),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_with_handle(payload, params, mutability, handle),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_with_handle(payload, params, mutability, handle),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.run_script_with_handle(payload, params, mutability, handle),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::explain].
+    pub fn explain(&self, payload: &str, params: BTreeMap<String, DataValue>) -> Result<QueryPlan> {
+        match self {
+            DbInstance::Mem(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.explain(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::diagnostics].
+    pub fn diagnostics(
+        &self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+    ) -> Vec<ScriptDiagnostic> {
+        match self {
+            DbInstance::Mem(db) => db.diagnostics(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.diagnostics(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.diagnostics(payload, params),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.diagnostics(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.diagnostics(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.diagnostics(payload, params),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.diagnostics(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::completions].
+    pub fn completions(&self, payload: &str, offset: usize) -> Result<Vec<Completion>> {
+        match self {
+            DbInstance::Mem(db) => db.completions(payload, offset),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.completions(payload, offset),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.completions(payload, offset),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.completions(payload, offset),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.completions(payload, offset),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.completions(payload, offset),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.completions(payload, offset),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::script_signature].
+    pub fn script_signature(&self, payload: &str) -> Result<ScriptSignature> {
+        match self {
+            DbInstance::Mem(db) => db.script_signature(payload),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.script_signature(payload),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.script_signature(payload),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.script_signature(payload),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.script_signature(payload),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.script_signature(payload),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.script_signature(payload),
+        }
     }
     /// `run_script` with mutable script and no parameters
     pub fn run_default(&self, payload: &str) -> Result<NamedRows> {
@@ -236,6 +378,280 @@ impl DbInstance {
             DbInstance::Sled(db) => db.run_script_ast(payload, cur_vld, mutability),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.run_script_ast(payload, cur_vld, mutability),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.run_script_ast(payload, cur_vld, mutability),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_script_ast_with_handle].
+    pub fn run_script_ast_with_handle(
+        &self,
+        payload: CozoScript,
+        cur_vld: ValidityTs,
+        mutability: ScriptMutability,
+        handle: &Poison,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => {
+                db.run_script_ast_with_handle(payload, cur_vld, mutability, handle)
+            }
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.run_script_ast_with_handle(payload, cur_vld, mutability, handle)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_script_ast_with_handle(payload, cur_vld, mutability, handle)
+            }
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => {
+                db.run_script_ast_with_handle(payload, cur_vld, mutability, handle)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.run_script_ast_with_handle(payload, cur_vld, mutability, handle)
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.run_script_ast_with_handle(payload, cur_vld, mutability, handle)
+            }
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => {
+                db.run_script_ast_with_handle(payload, cur_vld, mutability, handle)
+            }
+        }
+    }
+    /// Dispatcher method. See [crate::Db::metrics_text].
+    pub fn metrics_text(&self) -> String {
+        match self {
+            DbInstance::Mem(db) => db.metrics_text(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.metrics_text(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.metrics_text(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.metrics_text(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.metrics_text(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.metrics_text(),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.metrics_text(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::poll_outbox].
+    pub fn poll_outbox(&self, consumer_id: &str, n: usize) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.poll_outbox(consumer_id, n),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.poll_outbox(consumer_id, n),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.poll_outbox(consumer_id, n),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.poll_outbox(consumer_id, n),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.poll_outbox(consumer_id, n),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.poll_outbox(consumer_id, n),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.poll_outbox(consumer_id, n),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_scheduled_scripts].
+    pub fn run_scheduled_scripts(&self, since: f64, until: f64) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.run_scheduled_scripts(since, until),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_scheduled_scripts(since, until),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_scheduled_scripts(since, until),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.run_scheduled_scripts(since, until),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_scheduled_scripts(since, until),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_scheduled_scripts(since, until),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.run_scheduled_scripts(since, until),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::ack_outbox].
+    pub fn ack_outbox(&self, consumer_id: &str, seq: i64) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.ack_outbox(consumer_id, seq),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.ack_outbox(consumer_id, seq),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.ack_outbox(consumer_id, seq),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.ack_outbox(consumer_id, seq),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.ack_outbox(consumer_id, seq),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.ack_outbox(consumer_id, seq),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.ack_outbox(consumer_id, seq),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::migrate].
+    pub fn migrate(&self, migrations: &[Migration]) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.migrate(migrations),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.migrate(migrations),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.migrate(migrations),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.migrate(migrations),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.migrate(migrations),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.migrate(migrations),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.migrate(migrations),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::migrate_dry_run].
+    pub fn migrate_dry_run(&self, migrations: &[Migration]) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.migrate_dry_run(migrations),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.migrate_dry_run(migrations),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.migrate_dry_run(migrations),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.migrate_dry_run(migrations),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.migrate_dry_run(migrations),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.migrate_dry_run(migrations),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.migrate_dry_run(migrations),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_edn_transactions].
+    pub fn import_edn_transactions(&self, relation: &str, edn: &str) -> Result<usize> {
+        match self {
+            DbInstance::Mem(db) => db.import_edn_transactions(relation, edn),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_edn_transactions(relation, edn),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_edn_transactions(relation, edn),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.import_edn_transactions(relation, edn),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_edn_transactions(relation, edn),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_edn_transactions(relation, edn),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.import_edn_transactions(relation, edn),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_edn_transactions].
+    pub fn export_edn_transactions(&self, relation: &str) -> Result<String> {
+        match self {
+            DbInstance::Mem(db) => db.export_edn_transactions(relation),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_edn_transactions(relation),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_edn_transactions(relation),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.export_edn_transactions(relation),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_edn_transactions(relation),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_edn_transactions(relation),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.export_edn_transactions(relation),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_max_concurrency].
+    pub fn set_max_concurrency(&self, read: Option<usize>, write: Option<usize>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.set_max_concurrency(read, write),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_max_concurrency(read, write),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_max_concurrency(read, write),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.set_max_concurrency(read, write),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_max_concurrency(read, write),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_max_concurrency(read, write),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.set_max_concurrency(read, write),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_sandbox].
+    pub fn set_sandbox(&self, sandbox: Option<Sandbox>) {
+        match self {
+            DbInstance::Mem(db) => db.set_sandbox(sandbox),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_sandbox(sandbox),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_sandbox(sandbox),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.set_sandbox(sandbox),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_sandbox(sandbox),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_sandbox(sandbox),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.set_sandbox(sandbox),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_memory_budget].
+    pub fn set_memory_budget(&self, bytes: Option<usize>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.set_memory_budget(bytes),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_memory_budget(bytes),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_memory_budget(bytes),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.set_memory_budget(bytes),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_memory_budget(bytes),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_memory_budget(bytes),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.set_memory_budget(bytes),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_default_timeout].
+    pub fn set_default_timeout(&self, secs: Option<f64>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.set_default_timeout(secs),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_default_timeout(secs),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_default_timeout(secs),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.set_default_timeout(secs),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_default_timeout(secs),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_default_timeout(secs),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.set_default_timeout(secs),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_query_cache_capacity].
+    pub fn set_query_cache_capacity(&self, entries: usize) {
+        match self {
+            DbInstance::Mem(db) => db.set_query_cache_capacity(entries),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_query_cache_capacity(entries),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_query_cache_capacity(entries),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.set_query_cache_capacity(entries),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_query_cache_capacity(entries),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_query_cache_capacity(entries),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.set_query_cache_capacity(entries),
         }
     }
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
@@ -246,11 +662,21 @@ impl DbInstance {
         payload: &str,
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
+    ) -> JsonValue {
+        self.run_script_fold_err_with_handle(payload, params, mutability, &Poison::default())
+    }
+    /// Same as [DbInstance::run_script_fold_err], with a handle that can be used to cancel the script.
+    pub fn run_script_fold_err_with_handle(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        handle: &Poison,
     ) -> JsonValue {
         #[cfg(not(target_arch = "wasm32"))]
         let start = Instant::now();
 
-        match self.run_script(payload, params, mutability) {
+        match self.run_script_with_handle(payload, params, mutability, handle) {
             Ok(named_rows) => {
                 let mut j_val = named_rows.into_json();
                 #[cfg(not(target_arch = "wasm32"))]
@@ -268,6 +694,16 @@ impl DbInstance {
     /// Run the CozoScript passed in. The `params` argument is a map of parameters formatted as JSON.
     /// See [crate::Db::run_script].
     pub fn run_script_str(&self, payload: &str, params: &str, immutable: bool) -> String {
+        self.run_script_str_with_handle(payload, params, immutable, &Poison::default())
+    }
+    /// Same as [DbInstance::run_script_str], with a handle that can be used to cancel the script.
+    pub fn run_script_str_with_handle(
+        &self,
+        payload: &str,
+        params: &str,
+        immutable: bool,
+        handle: &Poison,
+    ) -> String {
         let params_json = if params.is_empty() {
             BTreeMap::default()
         } else {
@@ -282,7 +718,7 @@ impl DbInstance {
                 }
             }
         };
-        self.run_script_fold_err(
+        self.run_script_fold_err_with_handle(
             payload,
             params_json,
             if immutable {
@@ -290,6 +726,7 @@ impl DbInstance {
             } else {
                 ScriptMutability::Mutable
             },
+            handle,
         )
         .to_string()
     }
@@ -311,6 +748,31 @@ impl DbInstance {
             DbInstance::Sled(db) => db.export_relations(relations),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.export_relations(relations),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.export_relations(relations),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_query].
+    pub fn export_query(
+        &self,
+        relation: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<BTreeMap<String, NamedRows>> {
+        match self {
+            DbInstance::Mem(db) => db.export_query(relation, payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_query(relation, payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_query(relation, payload, params),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.export_query(relation, payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_query(relation, payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_query(relation, payload, params),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.export_query(relation, payload, params),
         }
     }
     /// Export relations to JSON-encoded string.
@@ -353,6 +815,8 @@ impl DbInstance {
             DbInstance::Sled(db) => db.import_relations(data),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.import_relations(data),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.import_relations(data),
         }
     }
     /// Import a relation, the data is given as a JSON string, and the returned result is converted into a string.
@@ -396,9 +860,11 @@ impl DbInstance {
             DbInstance::Sled(db) => db.backup_db(out_file),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.backup_db(out_file),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.backup_db(out_file),
         }
     }
-    /// Backup the running database into an Sqlite file, with JSON string return value.
+    /// Backup the running database into a file, with JSON string return value.
     /// See [crate::Db::backup_db].
     pub fn backup_db_str(&self, out_file: impl AsRef<Path>) -> String {
         match self.backup_db(out_file) {
@@ -420,9 +886,11 @@ impl DbInstance {
             DbInstance::Sled(db) => db.restore_backup(in_file),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.restore_backup(in_file),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.restore_backup(in_file),
         }
     }
-    /// Restore from an Sqlite backup, with JSON string return value.
+    /// Restore from a backup, with JSON string return value.
     /// See [crate::Db::restore_backup].
     pub fn restore_backup_str(&self, in_file: impl AsRef<Path>) -> String {
         match self.restore_backup(in_file) {
@@ -430,6 +898,64 @@ impl DbInstance {
             Err(err) => json!({"ok": false, "message": err.to_string()}).to_string(),
         }
     }
+    /// Dispatcher method. See [crate::Db::restore_backup_with_options].
+    pub fn restore_backup_with_options(
+        &self,
+        in_file: impl AsRef<Path>,
+        options: &RestoreOptions,
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.restore_backup_with_options(in_file, options),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.restore_backup_with_options(in_file, options),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.restore_backup_with_options(in_file, options),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.restore_backup_with_options(in_file, options),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.restore_backup_with_options(in_file, options),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.restore_backup_with_options(in_file, options),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.restore_backup_with_options(in_file, options),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::backup_db_to_bytes].
+    pub fn backup_db_to_bytes(&self) -> Result<Vec<u8>> {
+        match self {
+            DbInstance::Mem(db) => db.backup_db_to_bytes(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.backup_db_to_bytes(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.backup_db_to_bytes(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.backup_db_to_bytes(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.backup_db_to_bytes(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.backup_db_to_bytes(),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.backup_db_to_bytes(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::restore_backup_from_bytes].
+    pub fn restore_backup_from_bytes(&self, data: &[u8]) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.restore_backup_from_bytes(data),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.restore_backup_from_bytes(data),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.restore_backup_from_bytes(data),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.restore_backup_from_bytes(data),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.restore_backup_from_bytes(data),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.restore_backup_from_bytes(data),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.restore_backup_from_bytes(data),
+        }
+    }
     /// Dispatcher method. See [crate::Db::import_from_backup].
     pub fn import_from_backup(
         &self,
@@ -448,6 +974,8 @@ impl DbInstance {
             DbInstance::Sled(db) => db.import_from_backup(in_file, relations),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.import_from_backup(in_file, relations),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.import_from_backup(in_file, relations),
         }
     }
     /// Import relations from an Sqlite backup, with JSON string return value.
@@ -470,7 +998,6 @@ impl DbInstance {
     }
 
     /// Dispatcher method. See [crate::Db::register_callback].
-    #[cfg(not(target_arch = "wasm32"))]
     pub fn register_callback(
         &self,
         relation: &str,
@@ -488,11 +1015,12 @@ impl DbInstance {
             DbInstance::Sled(db) => db.register_callback(relation, capacity),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.register_callback(relation, capacity),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.register_callback(relation, capacity),
         }
     }
 
     /// Dispatcher method. See [crate::Db::unregister_callback].
-    #[cfg(not(target_arch = "wasm32"))]
     pub fn unregister_callback(&self, id: u32) -> bool {
         match self {
             DbInstance::Mem(db) => db.unregister_callback(id),
@@ -506,6 +1034,83 @@ impl DbInstance {
             DbInstance::Sled(db) => db.unregister_callback(id),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.unregister_callback(id),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.unregister_callback(id),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_mutation_guard].
+    pub fn set_mutation_guard(
+        &self,
+        guard: impl Fn(&str, MutationKind) -> Result<()> + Send + Sync + 'static,
+    ) {
+        match self {
+            DbInstance::Mem(db) => db.set_mutation_guard(guard),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_mutation_guard(guard),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_mutation_guard(guard),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.set_mutation_guard(guard),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_mutation_guard(guard),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_mutation_guard(guard),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.set_mutation_guard(guard),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::clear_mutation_guard].
+    pub fn clear_mutation_guard(&self) {
+        match self {
+            DbInstance::Mem(db) => db.clear_mutation_guard(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.clear_mutation_guard(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.clear_mutation_guard(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.clear_mutation_guard(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.clear_mutation_guard(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.clear_mutation_guard(),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.clear_mutation_guard(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_query_observer].
+    pub fn set_query_observer(&self, observer: impl Fn(QueryEvent) + Send + Sync + 'static) {
+        match self {
+            DbInstance::Mem(db) => db.set_query_observer(observer),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_query_observer(observer),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_query_observer(observer),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.set_query_observer(observer),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_query_observer(observer),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_query_observer(observer),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.set_query_observer(observer),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::clear_query_observer].
+    pub fn clear_query_observer(&self) {
+        match self {
+            DbInstance::Mem(db) => db.clear_query_observer(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.clear_query_observer(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.clear_query_observer(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.clear_query_observer(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.clear_query_observer(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.clear_query_observer(),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.clear_query_observer(),
         }
     }
     /// Dispatcher method. See [crate::Db::register_fixed_rule].
@@ -525,6 +1130,8 @@ impl DbInstance {
             DbInstance::Sled(db) => db.register_fixed_rule(name, rule_impl),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.register_fixed_rule(name, rule_impl),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.register_fixed_rule(name, rule_impl),
         }
     }
     /// Dispatcher method. See [crate::Db::unregister_fixed_rule]
@@ -541,6 +1148,48 @@ impl DbInstance {
             DbInstance::Sled(db) => db.unregister_fixed_rule(name),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.unregister_fixed_rule(name),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.unregister_fixed_rule(name),
+        }
+    }
+
+    /// Dispatcher method. See [crate::Db::register_virtual_relation].
+    pub fn register_virtual_relation<R>(&self, name: String, relation: R) -> Result<()>
+    where
+        R: VirtualRelation + 'static,
+    {
+        match self {
+            DbInstance::Mem(db) => db.register_virtual_relation(name, relation),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_virtual_relation(name, relation),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_virtual_relation(name, relation),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.register_virtual_relation(name, relation),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_virtual_relation(name, relation),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_virtual_relation(name, relation),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.register_virtual_relation(name, relation),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::unregister_virtual_relation].
+    pub fn unregister_virtual_relation(&self, name: &str) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.unregister_virtual_relation(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_virtual_relation(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_virtual_relation(name),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.unregister_virtual_relation(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_virtual_relation(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_virtual_relation(name),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.unregister_virtual_relation(name),
         }
     }
 
@@ -563,6 +1212,30 @@ impl DbInstance {
             DbInstance::Sled(db) => db.run_multi_transaction(write, payloads, results),
             #[cfg(feature = "storage-tikv")]
             DbInstance::TiKv(db) => db.run_multi_transaction(write, payloads, results),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.run_multi_transaction(write, payloads, results),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_snapshot]
+    pub fn run_snapshot(
+        &self,
+        payloads: Receiver<TransactionPayload>,
+        results: Sender<Result<NamedRows>>,
+    ) {
+        match self {
+            DbInstance::Mem(db) => db.run_snapshot(payloads, results),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_snapshot(payloads, results),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_snapshot(payloads, results),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.run_snapshot(payloads, results),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_snapshot(payloads, results),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_snapshot(payloads, results),
+            #[cfg(feature = "storage-opfs")]
+            DbInstance::Opfs(db) => db.run_snapshot(payloads, results),
         }
     }
     /// A higher-level, blocking wrapper for [crate::Db::run_multi_transaction]. Runs the transaction on a dedicated thread.
@@ -571,17 +1244,84 @@ impl DbInstance {
         let (app2db_send, app2db_recv) = bounded(1);
         let (db2app_send, db2app_recv) = bounded(1);
         let db = self.clone();
+        // the spans of the transaction belong to the caller's
+        let span = Span::current();
         #[cfg(target_arch = "wasm32")]
-        std::thread::spawn(move || db.run_multi_transaction(write, app2db_recv, db2app_send));
+        std::thread::spawn(move || {
+            span.in_scope(|| db.run_multi_transaction(write, app2db_recv, db2app_send))
+        });
         #[cfg(not(target_arch = "wasm32"))]
-        rayon::spawn(move || db.run_multi_transaction(write, app2db_recv, db2app_send));
+        rayon::spawn(move || {
+            span.in_scope(|| db.run_multi_transaction(write, app2db_recv, db2app_send))
+        });
         MultiTransaction {
             sender: app2db_send,
             receiver: db2app_recv,
         }
     }
+    /// A read-only snapshot of the database, see [crate::Db::snapshot]. Scripts run on the
+    /// returned handle all see the data as of this call. Committing or aborting the handle
+    /// releases the snapshot.
+    pub fn snapshot(&self) -> MultiTransaction {
+        let (app2db_send, app2db_recv) = bounded(1);
+        let (db2app_send, db2app_recv) = bounded(1);
+        let db = self.clone();
+        // the spans of the transaction belong to the caller's
+        let span = Span::current();
+        #[cfg(target_arch = "wasm32")]
+        std::thread::spawn(move || span.in_scope(|| db.run_snapshot(app2db_recv, db2app_send)));
+        #[cfg(not(target_arch = "wasm32"))]
+        rayon::spawn(move || span.in_scope(|| db.run_snapshot(app2db_recv, db2app_send)));
+        MultiTransaction {
+            sender: app2db_send,
+            receiver: db2app_recv,
+        }
+    }
+    /// Run `f` in a multi-transaction and commit it, running it again in a new transaction
+    /// when it fails because of a conflict with a concurrent write (see [TransactionConflict]),
+    /// at most `max_retries` times, with exponential backoff between attempts.
+    ///
+    /// `f` may run several times, so it should have no effects outside the transaction.
+    /// Other errors abort the transaction and are returned immediately.
+    pub fn transact_with_retries<T>(
+        &self,
+        write: bool,
+        max_retries: usize,
+        mut f: impl FnMut(&MultiTransaction) -> Result<T>,
+    ) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            let tx = self.multi_transaction(write);
+            let res = match f(&tx) {
+                Ok(ret) => tx.commit().map(|_| ret),
+                Err(err) => {
+                    let _ = tx.abort();
+                    Err(err)
+                }
+            };
+            match res {
+                Err(err) if attempt < max_retries && TransactionConflict::is_conflict(&err) => {
+                    attempt += 1;
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        use rand::Rng;
+                        let backoff_ms = 1u64 << attempt.min(MAX_RETRY_BACKOFF_EXPONENT);
+                        let jitter_ms = rand::thread_rng().gen_range(0..=backoff_ms);
+                        std::thread::sleep(std::time::Duration::from_millis(
+                            backoff_ms + jitter_ms,
+                        ));
+                    }
+                }
+                res => return res,
+            }
+        }
+    }
 }
 
+/// Backoff between retries of conflicting transactions is capped at 2^7 = 128 ms, plus jitter.
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_RETRY_BACKOFF_EXPONENT: usize = 7;
+
 /// A multi-transaction handle.
 /// You should use either the fields directly, or the associated functions.
 pub struct MultiTransaction {
@@ -615,7 +1355,7 @@ impl MultiTransaction {
             bail!(err);
         }
         match self.receiver.recv() {
-            Ok(_) => Ok(()),
+            Ok(r) => r.map(|_| ()),
             Err(err) => bail!(err),
         }
     }
@@ -0,0 +1,445 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Canonical formatting of scripts, see [format_script].
+
+use miette::Result;
+use pest::error::InputLocation;
+use pest::Parser;
+
+use crate::parse::{CozoScriptParser, Pair, ParseError, Rule, SourceSpan};
+use crate::runtime::lsp::{tokenize, Token, TokenKind};
+
+const INDENT: &str = "    ";
+
+/// Rules whose bodies are longer than this are laid out with one atom per line.
+const MAX_WIDTH: usize = 100;
+
+/// Format the script passed in canonically, so that equivalent scripts are formatted the same.
+///
+/// Modules used come first. Rules are kept in order, one per line, with long bodies laid out
+/// with one atom per line. Query options follow the rules, in a fixed order. Whitespace inside atoms and expressions
+/// is normalized, optional semicolons are dropped and comments are kept.
+///
+/// The script must be syntactically valid, but it is not checked further: parameters
+/// need not be given and relations need not exist.
+pub fn format_script(src: &str) -> Result<String> {
+    let parsed = CozoScriptParser::parse(Rule::script, src)
+        .map_err(|err| {
+            let span = match err.location {
+                InputLocation::Pos(p) => SourceSpan(p, 0),
+                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+            };
+            ParseError { span }
+        })?
+        .next()
+        .unwrap();
+    let formatter = ScriptFormatter { src };
+    let mut out = formatter.layout(parsed, 0);
+    out.push('\n');
+    Ok(out)
+}
+
+struct ScriptFormatter<'a> {
+    src: &'a str,
+}
+
+/// An item of a list laid out one per line, with the comments preceding it.
+struct Item {
+    comments: Vec<String>,
+    text: String,
+}
+
+fn token_is_operand(src: &str, token: &Token) -> bool {
+    match token.kind {
+        TokenKind::Variable | TokenKind::Number | TokenKind::String | TokenKind::Parameter => true,
+        TokenKind::Punctuation => matches!(
+            &src[token.span.0..token.span.0 + token.span.1],
+            ")" | "]" | "}"
+        ),
+        _ => false,
+    }
+}
+
+fn option_rank(opt: Rule) -> usize {
+    match opt {
+        Rule::transform_option => 0,
+        Rule::window_option => 1,
+        Rule::nest_option => 2,
+        Rule::sort_option => 3,
+        Rule::offset_option => 4,
+        Rule::limit_option => 5,
+        Rule::assert_none_option | Rule::assert_some_option => 6,
+        Rule::relation_option => 7,
+        Rule::returning_option => 8,
+        Rule::outbox_option => 9,
+        Rule::timeout_option => 10,
+        Rule::sleep_option => 11,
+        Rule::seed_option => 12,
+        Rule::as_of_tx_option => 13,
+        Rule::experimental_option => 14,
+        Rule::hints_option => 15,
+        Rule::disable_magic_rewrite_option => 16,
+        _ => 17,
+    }
+}
+
+impl<'a> ScriptFormatter<'a> {
+    fn indent(level: usize) -> String {
+        INDENT.repeat(level)
+    }
+
+    /// The comments between the positions `from` and `to`.
+    fn comments(&self, from: usize, to: usize) -> Vec<String> {
+        let text = &self.src[from..to];
+        tokenize(text)
+            .into_iter()
+            .filter(|t| t.kind == TokenKind::Comment)
+            .map(|t| text[t.span.0..t.span.0 + t.span.1].to_string())
+            .collect()
+    }
+
+    /// The text between `from` and `to` on a single line, with whitespace normalized.
+    /// Line comments are followed by a new line at `level`.
+    fn flat(&self, from: usize, to: usize, level: usize) -> String {
+        self.flat_with(from, to, level, false)
+    }
+
+    /// As [Self::flat], but if `sys_op` is set names like `relation:index` are kept together.
+    fn flat_with(&self, from: usize, to: usize, level: usize, sys_op: bool) -> String {
+        let text = &self.src[from..to];
+        let tokens = tokenize(text);
+        let token_text = |t: &Token| &text[t.span.0..t.span.0 + t.span.1];
+        let mut out = String::new();
+        let mut prev: Option<&Token> = None;
+        let mut after_unary = false;
+        // within the pattern of a path like `a -[:knows+ / :works_at]-> b`
+        let mut in_path = false;
+        let mut path_closed = false;
+        for token in &tokens {
+            let cur = token_text(token);
+            if let Some(p) = prev {
+                let pt = token_text(p);
+                let glued = p.span.0 + p.span.1 == token.span.0;
+                let space = if p.kind == TokenKind::Comment && pt.starts_with('#') {
+                    out.push('\n');
+                    out.push_str(&Self::indent(level));
+                    false
+                } else if in_path {
+                    in_path = cur != "]";
+                    path_closed = !in_path;
+                    matches!(cur, "/" | "|") || matches!(pt, "/" | "|")
+                } else if (pt == "-" && cur == "[" && glued) || (path_closed && cur == "->") {
+                    in_path = cur == "[";
+                    false
+                } else if after_unary
+                    || (p.kind == TokenKind::Punctuation && matches!(pt, "(" | "[" | "{"))
+                    || (token.kind == TokenKind::Punctuation
+                        && matches!(cur, ")" | "]" | "}" | "," | ";"))
+                {
+                    false
+                } else if token.kind == TokenKind::Punctuation && matches!(cur, "(" | "[") {
+                    !matches!(
+                        p.kind,
+                        TokenKind::Function | TokenKind::Rule | TokenKind::Relation
+                    )
+                } else if token.kind == TokenKind::Punctuation && cur == "{" {
+                    p.kind != TokenKind::Relation
+                } else if token.kind == TokenKind::Punctuation && matches!(cur, ":" | ".") {
+                    // the `..` binding the other columns of relations
+                    cur == "." && pt == ","
+                } else if p.kind == TokenKind::Punctuation && pt == ":" {
+                    !(sys_op && glued)
+                } else {
+                    true
+                };
+                if space {
+                    out.push(' ');
+                }
+            }
+            path_closed = path_closed && cur == "]";
+            after_unary = token.kind == TokenKind::Operator
+                && matches!(cur, "-" | "+" | "!")
+                && !prev.is_some_and(|p| token_is_operand(text, p));
+            out.push_str(cur);
+            prev = Some(token);
+        }
+        out
+    }
+
+    /// Lay out `items` one per line at `level`, followed by the comments in `trailing`.
+    fn lines(items: Vec<Item>, trailing: Vec<String>, level: usize) -> String {
+        let indent = Self::indent(level);
+        items
+            .into_iter()
+            .flat_map(|item| item.comments.into_iter().chain(std::iter::once(item.text)))
+            .chain(trailing)
+            .map(|line| format!("{indent}{line}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The end of the pair, before the comments it may end with.
+    fn end_of(&self, pair: &Pair<'_>) -> usize {
+        let span = pair.as_span();
+        match tokenize(span.as_str())
+            .iter()
+            .rev()
+            .find(|t| t.kind != TokenKind::Comment)
+        {
+            Some(t) => span.start() + t.span.0 + t.span.1,
+            None => span.start(),
+        }
+    }
+
+    fn start_of(pair: &Pair<'_>) -> usize {
+        pair.as_span().start()
+    }
+
+    /// The rules and options of a query, from `from` to `to`, laid out at `level`.
+    fn query(&self, children: Vec<Pair<'a>>, from: usize, to: usize, level: usize) -> String {
+        let mut uses = vec![];
+        let mut rules = vec![];
+        let mut options = vec![];
+        let mut pos = from;
+        for child in children {
+            if child.as_rule() == Rule::EOI {
+                continue;
+            }
+            let comments = self.comments(pos, Self::start_of(&child));
+            pos = self.end_of(&child);
+            match child.as_rule() {
+                Rule::use_module => uses.push(Item {
+                    comments,
+                    text: format!("use {}", child.into_inner().nth(1).unwrap().as_str()),
+                }),
+                Rule::rule | Rule::const_rule | Rule::fixed_rule => rules.push(Item {
+                    comments,
+                    text: self.rule(child, level),
+                }),
+                r => options.push((
+                    option_rank(r),
+                    Item {
+                        comments,
+                        text: self.flat(Self::start_of(&child), self.end_of(&child), level),
+                    },
+                )),
+            }
+        }
+        options.sort_by_key(|(rank, _)| *rank);
+        uses.extend(rules);
+        uses.extend(options.into_iter().map(|(_, item)| item));
+        Self::lines(uses, self.comments(pos, to), level)
+    }
+
+    fn rule(&self, pair: Pair<'a>, level: usize) -> String {
+        let kind = pair.as_rule();
+        let mut children = pair.into_inner();
+        let head = children.next().unwrap();
+        let head_text = self.flat(Self::start_of(&head), self.end_of(&head), level);
+        match kind {
+            Rule::const_rule => {
+                let expr = children.next().unwrap();
+                format!(
+                    "{head_text} <- {}",
+                    self.flat(Self::start_of(&expr), self.end_of(&expr), level + 1)
+                )
+            }
+            Rule::fixed_rule => {
+                let name = children.next().unwrap();
+                let args = children.next().unwrap();
+                format!(
+                    "{head_text} <~ {}",
+                    self.flat(Self::start_of(&name), self.end_of(&args), level + 1)
+                )
+            }
+            _ => {
+                let body = children.next().unwrap();
+                let body_end = self.end_of(&body);
+                let mut atoms = vec![];
+                let mut pos = Self::start_of(&body);
+                for atom in body.into_inner() {
+                    atoms.push(Item {
+                        comments: self.comments(pos, Self::start_of(&atom)),
+                        text: self.flat(Self::start_of(&atom), self.end_of(&atom), level + 1),
+                    });
+                    pos = self.end_of(&atom);
+                }
+                let trailing = self.comments(pos, body_end);
+                let one_line = format!(
+                    "{head_text} := {}",
+                    atoms
+                        .iter()
+                        .map(|a| a.text.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                let has_comments =
+                    !trailing.is_empty() || atoms.iter().any(|a| !a.comments.is_empty());
+                if !has_comments
+                    && !one_line.contains('\n')
+                    && level * INDENT.len() + one_line.len() <= MAX_WIDTH
+                {
+                    return one_line;
+                }
+                let n_atoms = atoms.len();
+                for (i, atom) in atoms.iter_mut().enumerate() {
+                    if i + 1 < n_atoms {
+                        atom.text.push(',');
+                    }
+                }
+                format!(
+                    "{head_text} :=\n{}",
+                    Self::lines(atoms, trailing, level + 1)
+                )
+            }
+        }
+    }
+
+    /// Statements of imperative scripts, laid out at `level`.
+    fn statements(&self, pair: Pair<'a>, level: usize) -> String {
+        let to = self.end_of(&pair);
+        let mut pos = Self::start_of(&pair);
+        let mut items = vec![];
+        for stmt in pair.into_inner() {
+            if stmt.as_rule() == Rule::EOI {
+                continue;
+            }
+            let comments = self.comments(pos, Self::start_of(&stmt));
+            pos = self.end_of(&stmt);
+            items.push(Item {
+                comments,
+                text: self.layout(stmt, level),
+            });
+        }
+        Self::lines(items, self.comments(pos, to), level)
+    }
+
+    /// `text` followed by ` as name` if `name` is given.
+    fn store_as(text: String, name: Option<Pair<'_>>) -> String {
+        match name {
+            None => text,
+            Some(name) => format!("{text} as {}", name.as_str()),
+        }
+    }
+
+    /// The pair laid out starting at `level`, without indenting its first line,
+    /// except for queries without brackets whose lines are all indented.
+    fn layout(&self, pair: Pair<'a>, level: usize) -> String {
+        let indent = Self::indent(level);
+        match pair.as_rule() {
+            Rule::query_script | Rule::query_script_inner_no_bracket | Rule::module_body => {
+                let (from, to) = (Self::start_of(&pair), self.end_of(&pair));
+                self.query(pair.into_inner().collect(), from, to, level)
+            }
+            Rule::query_script_inner => {
+                let (from, to) = (Self::start_of(&pair) + 1, self.end_of(&pair) - 1);
+                format!(
+                    "{{\n{}\n{indent}}}",
+                    self.query(pair.into_inner().collect(), from, to, level + 1)
+                )
+            }
+            Rule::imperative_script | Rule::imperative_block => self.statements(pair, level),
+            Rule::imperative_clause | Rule::imperative_sysop => {
+                let mut children = pair.into_inner();
+                let inner = children.next().unwrap();
+                Self::store_as(self.layout(inner, level), children.next())
+            }
+            Rule::ignore_error_script => format!(
+                "%ignore_error {}",
+                self.layout(pair.into_inner().next().unwrap(), level)
+            ),
+            Rule::if_chain | Rule::if_not_chain => {
+                let keyword = if pair.as_rule() == Rule::if_chain {
+                    "%if"
+                } else {
+                    "%if_not"
+                };
+                let mut children = pair.into_inner();
+                let cond = self.layout(children.next().unwrap(), level);
+                let then_branch = self.statements(children.next().unwrap(), level + 1);
+                let mut out = format!("{keyword} {cond} %then\n{then_branch}");
+                if let Some(else_branch) = children.next() {
+                    out.push_str(&format!(
+                        "\n{indent}%else\n{}",
+                        self.statements(else_branch, level + 1)
+                    ));
+                }
+                out.push_str(&format!("\n{indent}%end"));
+                out
+            }
+            Rule::loop_block => {
+                let mut out = String::new();
+                let mut children = pair.into_inner().peekable();
+                if children.peek().unwrap().as_rule() == Rule::ident {
+                    out.push_str(&format!(
+                        "%mark {}\n{indent}",
+                        children.next().unwrap().as_str()
+                    ));
+                }
+                let body = self.statements(children.next().unwrap(), level + 1);
+                out.push_str(&format!("%loop\n{body}\n{indent}%end"));
+                out
+            }
+            Rule::return_stmt => {
+                let returned = pair
+                    .into_inner()
+                    .map(|p| self.layout(p, level))
+                    .collect::<Vec<_>>();
+                if returned.is_empty() {
+                    "%return".to_string()
+                } else {
+                    format!("%return {}", returned.join(", "))
+                }
+            }
+            Rule::sys_script | Rule::sys_script_inner => {
+                let inner = pair
+                    .clone()
+                    .into_inner()
+                    .find(|p| p.as_rule() != Rule::EOI)
+                    .unwrap();
+                let op = self.with_queries(inner, level);
+                if pair.as_rule() == Rule::sys_script {
+                    format!("::{op}")
+                } else {
+                    format!("{{::{op}}}")
+                }
+            }
+            _ => self.flat(Self::start_of(&pair), self.end_of(&pair), level),
+        }
+    }
+
+    /// The pair on a single line, except for the queries it contains, laid out as blocks.
+    fn with_queries(&self, pair: Pair<'a>, level: usize) -> String {
+        let mut queries = vec![];
+        let mut pending = vec![pair.clone()];
+        while let Some(p) = pending.pop() {
+            if matches!(
+                p.as_rule(),
+                Rule::query_script_inner_no_bracket | Rule::module_body | Rule::imperative_block
+            ) {
+                queries.push(p);
+            } else {
+                pending.extend(p.into_inner());
+            }
+        }
+        queries.sort_by_key(|q| Self::start_of(q));
+        let mut out = String::new();
+        let mut pos = Self::start_of(&pair);
+        for query in queries {
+            out.push_str(&self.flat_with(pos, Self::start_of(&query), level, true));
+            out.push('\n');
+            pos = self.end_of(&query);
+            out.push_str(&self.layout(query, level + 1));
+            out.push('\n');
+            out.push_str(&Self::indent(level));
+        }
+        out.push_str(&self.flat_with(pos, self.end_of(&pair), level, true));
+        out
+    }
+}
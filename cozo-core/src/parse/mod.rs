@@ -15,7 +15,7 @@ use std::fmt::{Display, Formatter};
 use std::sync::Arc;
 
 use either::{Either, Left};
-use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use miette::{bail, Diagnostic, IntoDiagnostic, Report, Result};
 use pest::error::InputLocation;
 use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
@@ -29,9 +29,11 @@ use crate::parse::imperative::parse_imperative_block;
 use crate::parse::query::parse_query;
 use crate::parse::schema::parse_nullable_type;
 use crate::parse::sys::{parse_sys, SysOp};
+use crate::runtime::lsp::{tokenize, TokenKind};
 use crate::{Expr, FixedRule};
 
 pub(crate) mod expr;
+pub(crate) mod format;
 pub(crate) mod fts;
 pub(crate) mod imperative;
 pub(crate) mod query;
@@ -47,6 +49,7 @@ pub(crate) type Pairs<'a> = pest::iterators::Pairs<'a, Rule>;
 
 /// This represents a full Cozo script, as you'd pass to `run_script`.
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum CozoScript {
     #[allow(missing_docs)]
     Single(InputProgram),
@@ -173,7 +176,7 @@ impl ImperativeStmt {
                         collector.insert(new.name.clone());
                     }
                 }
-                SysOp::CreateIndex(symb, subs, _) => {
+                SysOp::CreateIndex(symb, subs, ..) => {
                     collector.insert(symb.name.clone());
                     collector.insert(SmartString::from(format!("{}:{}", symb.name, subs.name)));
                 }
@@ -201,6 +204,11 @@ impl ImperativeStmt {
                 SysOp::RemoveIndex(rel, idx) => {
                     collector.insert(SmartString::from(format!("{}:{}", rel.name, idx.name)));
                 }
+                SysOp::AddGeneratedColumn(rel, name, ..)
+                | SysOp::RemoveGeneratedColumn(rel, name) => {
+                    collector.insert(rel.name.clone());
+                    collector.insert(SmartString::from(format!("{}:{}", rel.name, name.name)));
+                }
                 _ => {}
             },
         }
@@ -308,6 +316,86 @@ pub fn parse_script(
     param_pool: &BTreeMap<String, DataValue>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
+) -> Result<CozoScript> {
+    parse_whole_script(src, param_pool, fixed_rules, cur_vld).map_err(|err| {
+        let errs = recover_block_errors(src, param_pool, fixed_rules, cur_vld);
+        if errs.len() > 1 {
+            MultipleErrors(errs).into()
+        } else {
+            err
+        }
+    })
+}
+
+/// Several errors found in independent parts of a script.
+#[derive(Debug, Error, Diagnostic)]
+#[error("{} errors found in the script", .0.len())]
+#[diagnostic(code(parser::multiple_errors))]
+pub(crate) struct MultipleErrors(#[related] pub(crate) Vec<Report>);
+
+/// Parse each top level block `{...}` of a script on its own, so that an error in one block
+/// does not hide errors in the others. The errors found are returned, in order.
+///
+/// The other parts of the script are blanked out instead of cut out,
+/// so that the spans of the errors are positions in the whole script.
+fn recover_block_errors(
+    src: &str,
+    param_pool: &BTreeMap<String, DataValue>,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
+) -> Vec<Report> {
+    let mut blocks = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for token in tokenize(src) {
+        if token.kind != TokenKind::Punctuation {
+            continue;
+        }
+        match &src[token.span.0..token.span.0 + token.span.1] {
+            "{" => {
+                if depth == 0 {
+                    start = token.span.0;
+                }
+                depth += 1;
+            }
+            "}" => match depth.checked_sub(1) {
+                None => return vec![],
+                Some(d) => {
+                    depth = d;
+                    if depth == 0 {
+                        blocks.push((start, token.span.0 + 1));
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    if depth != 0 || blocks.len() < 2 {
+        return vec![];
+    }
+    blocks
+        .into_iter()
+        .filter_map(|(start, end)| {
+            let masked: String = src
+                .char_indices()
+                .map(|(i, c)| {
+                    if (start..end).contains(&i) || c == '\n' {
+                        c.to_string()
+                    } else {
+                        " ".repeat(c.len_utf8())
+                    }
+                })
+                .collect();
+            parse_whole_script(&masked, param_pool, fixed_rules, cur_vld).err()
+        })
+        .collect()
+}
+
+fn parse_whole_script(
+    src: &str,
+    param_pool: &BTreeMap<String, DataValue>,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
 ) -> Result<CozoScript> {
     let parsed = CozoScriptParser::parse(Rule::script, src)
         .map_err(|err| {
@@ -22,11 +22,12 @@ use thiserror::Error;
 
 use crate::data::aggr::{parse_aggr, Aggregation};
 use crate::data::expr::Expr;
-use crate::data::functions::{str2vld, MAX_VALIDITY_TS};
+use crate::data::functions::{derive_seed, seed_rng, str2vld, MAX_VALIDITY_TS};
 use crate::data::program::{
-    FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
-    InputNamedFieldRelationApplyAtom, InputProgram, InputRelationApplyAtom, InputRuleApplyAtom,
-    QueryAssertion, QueryOutOptions, RelationOp, ReturnMutation, SearchInput, SortDir, Unification,
+    ExperimentalFeatures, FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule,
+    InputInlineRulesOrFixed, InputNamedFieldRelationApplyAtom, InputPathApplyAtom, InputProgram,
+    InputRelationApplyAtom, InputRuleApplyAtom, PathPattern, QueryAssertion, QueryHints, QueryNest,
+    QueryOutOptions, QueryTransform, RelationOp, ReturnMutation, SearchInput, SortDir, Unification,
 };
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::{Symbol, PROG_ENTRY};
@@ -36,6 +37,7 @@ use crate::fixed_rule::{FixedRuleHandle, FixedRuleNotFoundError};
 use crate::parse::expr::build_expr;
 use crate::parse::schema::parse_schema;
 use crate::parse::{CozoScriptParser, ExtractSpan, Pair, Pairs, Rule, SourceSpan};
+use crate::query::window::{QueryWindow, WindowFunction, WindowFunctionKind};
 use crate::runtime::relation::InputRelationHandle;
 use crate::FixedRule;
 
@@ -59,6 +61,11 @@ struct OptionNotPosIntError(&'static str, #[label] SourceSpan);
 #[diagnostic(code(parser::option_not_bool))]
 struct OptionNotBoolError(&'static str, #[label] SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Query option {0} requires a string")]
+#[diagnostic(code(parser::option_not_string))]
+struct OptionNotStringError(&'static str, #[label] SourceSpan);
+
 #[derive(Debug)]
 struct MultipleRuleDefinitionError(String, Vec<SourceSpan>);
 
@@ -112,9 +119,32 @@ pub(crate) fn parse_query(
     let mut progs: BTreeMap<Symbol, InputInlineRulesOrFixed> = Default::default();
     let mut out_opts: QueryOutOptions = Default::default();
     let mut disable_magic_rewrite = false;
+    let mut experimental = ExperimentalFeatures::default();
+    let mut hints = QueryHints::default();
+    let mut as_of_tx = None;
 
     let mut stored_relation = None;
     let mut returning_mutation = ReturnMutation::NotReturning;
+    let mut outbox_span = None;
+    let mut transform = None;
+    let mut nest = None;
+    let mut window = None;
+    let mut uses: Vec<Symbol> = vec![];
+
+    // the seed also applies to constants evaluated during parsing, so it is read first
+    for pair in src.clone() {
+        if pair.as_rule() == Rule::seed_option {
+            let pair = pair.into_inner().next().unwrap();
+            let span = pair.extract_span();
+            let seed = build_expr(pair, param_pool)?
+                .eval_to_const()
+                .map_err(|err| OptionNotConstantError("seed", span, [err]))?
+                .get_non_neg_int()
+                .ok_or(OptionNotNonNegIntError("seed", span))?;
+            out_opts.seed = Some(seed);
+        }
+    }
+    let _rng = seed_rng(out_opts.seed.map(|seed| derive_seed(seed, "parse")));
 
     for pair in src {
         match pair.as_rule() {
@@ -271,6 +301,36 @@ pub(crate) fn parse_query(
                     out_opts.timeout = None;
                 }
             }
+            Rule::seed_option => {}
+            Rule::transform_option => {
+                let span = pair.extract_span();
+                let mut args = pair.into_inner();
+                let name = args.next().unwrap();
+                let name = Symbol::new(name.as_str(), name.extract_span());
+                let expr = build_expr(args.next().unwrap(), param_pool)?;
+                let group_by = args
+                    .map(|p| Symbol::new(p.as_str(), p.extract_span()))
+                    .collect_vec();
+                transform = Some((name, expr, group_by, span));
+            }
+            Rule::nest_option => {
+                nest = Some(pair);
+            }
+            Rule::window_option => {
+                window = Some(pair);
+            }
+            Rule::outbox_option => {
+                outbox_span = Some(pair.extract_span());
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let topic = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("outbox", span, [err]))?;
+                let topic = topic
+                    .get_str()
+                    .ok_or(OptionNotStringError("outbox", span))?;
+                out_opts.outbox = Some(SmartString::from(topic));
+            }
             Rule::sleep_option => {
                 #[cfg(target_arch = "wasm32")]
                 bail!(":sleep is not supported under WASM");
@@ -310,25 +370,15 @@ pub(crate) fn parse_query(
             }
             Rule::sort_option => {
                 for part in pair.into_inner() {
-                    let mut var = "";
-                    let mut dir = SortDir::Asc;
-                    let mut span = part.extract_span();
-                    for a in part.into_inner() {
-                        match a.as_rule() {
-                            Rule::out_arg => {
-                                var = a.as_str();
-                                span = a.extract_span();
-                            }
-                            Rule::sort_asc => dir = SortDir::Asc,
-                            Rule::sort_desc => dir = SortDir::Dsc,
-                            _ => unreachable!(),
-                        }
-                    }
-                    out_opts.sorters.push((Symbol::new(var, span), dir));
+                    out_opts.sorters.push(parse_sort_arg(part));
                 }
             }
             Rule::returning_option => {
-                returning_mutation = ReturnMutation::Returning;
+                returning_mutation = match pair.into_inner().next().map(|p| p.as_rule()) {
+                    Some(Rule::returning_keys) => ReturnMutation::ReturningKeys,
+                    Some(Rule::returning_counts) => ReturnMutation::ReturningCounts,
+                    _ => ReturnMutation::Returning,
+                };
             }
             Rule::relation_option => {
                 let span = pair.extract_span();
@@ -339,6 +389,7 @@ pub(crate) fn parse_query(
                     Rule::relation_put => RelationOp::Put,
                     Rule::relation_insert => RelationOp::Insert,
                     Rule::relation_update => RelationOp::Update,
+                    Rule::relation_upsert => RelationOp::Upsert,
                     Rule::relation_rm => RelationOp::Rm,
                     Rule::relation_delete => RelationOp::Delete,
                     Rule::relation_ensure => RelationOp::Ensure,
@@ -348,7 +399,75 @@ pub(crate) fn parse_query(
 
                 let name_p = args.next().unwrap();
                 let name = Symbol::new(name_p.as_str(), name_p.extract_span());
-                match args.next() {
+                let mut schema_p = None;
+                let mut settings_p = None;
+                let mut guard_p = None;
+                for p in args {
+                    match p.as_rule() {
+                        Rule::relation_guard => guard_p = Some(p),
+                        Rule::relation_settings => settings_p = Some(p),
+                        _ => schema_p = Some(p),
+                    }
+                }
+                if let Some(settings_p) = settings_p {
+                    #[derive(Debug, Error, Diagnostic)]
+                    #[error("Relation settings are only allowed for :create")]
+                    #[diagnostic(code(parser::relation_settings_not_allowed))]
+                    struct RelationSettingsNotAllowed(#[label] SourceSpan);
+
+                    #[derive(Debug, Error, Diagnostic)]
+                    #[error("Unknown relation setting {0}")]
+                    #[diagnostic(code(parser::unknown_relation_setting))]
+                    #[diagnostic(help("The only setting is `track_tx_time`"))]
+                    struct UnknownRelationSetting(String, #[label] SourceSpan);
+
+                    ensure!(
+                        op == RelationOp::Create,
+                        RelationSettingsNotAllowed(settings_p.extract_span())
+                    );
+                    for setting in settings_p.into_inner() {
+                        let mut parts = setting.into_inner();
+                        let name_p = parts.next().unwrap();
+                        let val_p = parts.next().unwrap();
+                        let span = val_p.extract_span();
+                        match name_p.as_str() {
+                            "track_tx_time" => {
+                                out_opts.track_tx_time = build_expr(val_p, param_pool)?
+                                    .eval_to_const()
+                                    .map_err(|err| {
+                                        OptionNotConstantError("track_tx_time", span, [err])
+                                    })?
+                                    .get_bool()
+                                    .ok_or(OptionNotBoolError("track_tx_time", span))?;
+                            }
+                            name => bail!(UnknownRelationSetting(
+                                name.to_string(),
+                                name_p.extract_span()
+                            )),
+                        }
+                    }
+                }
+                if let Some(guard_p) = guard_p {
+                    #[derive(Debug, Error, Diagnostic)]
+                    #[error("Guard conditions are only allowed for :put, :update, :upsert, :rm and :delete")]
+                    #[diagnostic(code(parser::guard_not_allowed))]
+                    struct GuardNotAllowed(#[label] SourceSpan);
+
+                    ensure!(
+                        matches!(
+                            op,
+                            RelationOp::Put
+                                | RelationOp::Update
+                                | RelationOp::Upsert
+                                | RelationOp::Rm
+                                | RelationOp::Delete
+                        ),
+                        GuardNotAllowed(guard_p.extract_span())
+                    );
+                    let guard = build_expr(guard_p.into_inner().next().unwrap(), param_pool)?;
+                    out_opts.guard = Some(Box::new(guard));
+                }
+                match schema_p {
                     None => stored_relation = Some(Left((name, span, op))),
                     Some(schema_p) => {
                         let (mut metadata, mut key_bindings, mut dep_bindings) =
@@ -396,6 +515,63 @@ pub(crate) fn parse_query(
                     .ok_or(OptionNotBoolError("disable_magic_rewrite", span))?;
                 disable_magic_rewrite = val;
             }
+            Rule::experimental_option => {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("Unknown experimental feature {0}")]
+                #[diagnostic(code(parser::unknown_experimental_feature))]
+                #[diagnostic(help("The experimental features are 'join_reorder' and 'hash_join'"))]
+                struct UnknownExperimentalFeature(String, #[label] SourceSpan);
+
+                for feature in pair.into_inner() {
+                    match feature.as_str() {
+                        "join_reorder" => experimental.join_reorder = true,
+                        "hash_join" => experimental.hash_join = true,
+                        s => bail!(UnknownExperimentalFeature(
+                            s.to_string(),
+                            feature.extract_span()
+                        )),
+                    }
+                }
+            }
+            Rule::hints_option => {
+                for hint in pair.into_inner() {
+                    match hint.as_rule() {
+                        Rule::no_magic_set_hint => {
+                            let name_p = hint.into_inner().next().unwrap();
+                            hints
+                                .no_magic_set
+                                .insert(Symbol::new(name_p.as_str(), name_p.extract_span()));
+                        }
+                        Rule::join_order_hint => hints.join_orders.push(
+                            hint.into_inner()
+                                .map(|p| Symbol::new(p.as_str(), p.extract_span()))
+                                .collect_vec(),
+                        ),
+                        Rule::use_index_hint => {
+                            let mut src = hint.into_inner();
+                            let relation = SmartString::from(src.next().unwrap().as_str());
+                            let index_p = src.next().unwrap();
+                            hints.use_index.insert(
+                                relation,
+                                Symbol::new(index_p.as_str(), index_p.extract_span()),
+                            );
+                        }
+                        r => unreachable!("{:?}", r),
+                    }
+                }
+            }
+            Rule::as_of_tx_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let expr = build_expr(pair, param_pool)?;
+                as_of_tx = Some(expr2vld_spec(expr, cur_vld)?);
+            }
+            Rule::use_module => {
+                let name_p = pair.into_inner().nth(1).unwrap();
+                let module = Symbol::new(name_p.as_str(), name_p.extract_span());
+                if !uses.contains(&module) {
+                    uses.push(module);
+                }
+            }
             Rule::EOI => break,
             r => unreachable!("{:?}", r),
         }
@@ -405,6 +581,10 @@ pub(crate) fn parse_query(
         prog: progs,
         out_opts,
         disable_magic_rewrite,
+        experimental,
+        hints,
+        as_of_tx,
+        uses,
     };
 
     if prog.prog.is_empty() {
@@ -461,6 +641,102 @@ pub(crate) fn parse_query(
         Some(Right((h, o))) => prog.out_opts.store_relation = Some((h, o, returning_mutation)),
     }
 
+    if let Some(span) = outbox_span {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Query option :outbox cannot be combined with a mutation")]
+        #[diagnostic(code(parser::outbox_with_mutation))]
+        #[diagnostic(help("Append to the outbox in a separate query of the same script"))]
+        struct OutboxWithMutationError(#[label] SourceSpan);
+
+        ensure!(
+            prog.out_opts.store_relation.is_none(),
+            OutboxWithMutationError(span)
+        );
+    }
+
+    if let Some((name, mut expr, group_by, span)) = transform {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Query option :transform cannot be combined with a mutation or :outbox")]
+        #[diagnostic(code(parser::transform_with_mutation))]
+        struct TransformWithMutationError(#[label] SourceSpan);
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Grouping key '{0}' not found")]
+        #[diagnostic(code(parser::transform_key_not_found))]
+        #[diagnostic(help("Grouping keys must be variables in the head of the entry rule"))]
+        struct TransformKeyNotFound(String, #[label] SourceSpan);
+
+        ensure!(
+            prog.out_opts.store_relation.is_none() && prog.out_opts.outbox.is_none(),
+            TransformWithMutationError(span)
+        );
+        let head = prog.get_entry_out_head_or_default()?;
+        let binding_map: BTreeMap<Symbol, usize> = head
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.clone(), i))
+            .collect();
+        expr.fill_binding_indices(&binding_map)?;
+        let group_by = group_by
+            .into_iter()
+            .map(|key| match binding_map.get(&key) {
+                Some(i) => Ok((key, *i)),
+                None => Err(TransformKeyNotFound(key.to_string(), key.span)),
+            })
+            .try_collect()?;
+        prog.out_opts.transform = Some(Box::new(QueryTransform {
+            name,
+            expr,
+            group_by,
+        }));
+    }
+
+    if let Some(pair) = nest {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Query option :nest cannot be combined with a mutation, :outbox or :transform")]
+        #[diagnostic(code(parser::nest_with_mutation))]
+        struct NestWithMutationError(#[label] SourceSpan);
+
+        ensure!(
+            prog.out_opts.store_relation.is_none()
+                && prog.out_opts.outbox.is_none()
+                && prog.out_opts.transform.is_none(),
+            NestWithMutationError(pair.extract_span())
+        );
+        let head = prog.get_entry_out_head_or_default()?;
+        let binding_map: BTreeMap<Symbol, usize> = head
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.clone(), i))
+            .collect();
+        let spec = pair.into_inner().next().unwrap();
+        prog.out_opts.nest = Some(Box::new(parse_nest_spec(spec, &binding_map)?));
+    }
+
+    if let Some(pair) = window {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error(
+            "Query option :window cannot be combined with a mutation, :outbox, :transform or :nest"
+        )]
+        #[diagnostic(code(parser::window_with_mutation))]
+        struct WindowWithMutationError(#[label] SourceSpan);
+
+        ensure!(
+            prog.out_opts.store_relation.is_none()
+                && prog.out_opts.outbox.is_none()
+                && prog.out_opts.transform.is_none()
+                && prog.out_opts.nest.is_none(),
+            WindowWithMutationError(pair.extract_span())
+        );
+        let head = prog.get_entry_out_head_or_default()?;
+        let binding_map: BTreeMap<Symbol, usize> = head
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.clone(), i))
+            .collect();
+        prog.out_opts.window = Some(Box::new(parse_window(pair, param_pool, &binding_map)?));
+    }
+
     if prog.prog.is_empty() {
         if let Some((handle, RelationOp::Create, _)) = &prog.out_opts.store_relation {
             let mut bindings = handle.dep_bindings.clone();
@@ -531,6 +807,154 @@ pub(crate) fn parse_query(
     Ok(prog)
 }
 
+fn parse_sort_arg(pair: Pair<'_>) -> (Symbol, SortDir) {
+    let mut var = "";
+    let mut dir = SortDir::Asc;
+    let mut span = pair.extract_span();
+    for a in pair.into_inner() {
+        match a.as_rule() {
+            Rule::out_arg => {
+                var = a.as_str();
+                span = a.extract_span();
+            }
+            Rule::sort_asc => dir = SortDir::Asc,
+            Rule::sort_desc => dir = SortDir::Dsc,
+            _ => unreachable!(),
+        }
+    }
+    (Symbol::new(var, span), dir)
+}
+
+fn parse_window(
+    pair: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    binding_map: &BTreeMap<Symbol, usize>,
+) -> Result<QueryWindow> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Column '{0}' not found")]
+    #[diagnostic(code(parser::window_column_not_found))]
+    #[diagnostic(help("Window columns must be variables in the head of the entry rule"))]
+    struct WindowColumnNotFound(String, #[label] SourceSpan);
+
+    let column = |key: Symbol| match binding_map.get(&key) {
+        Some(i) => Ok((key, *i)),
+        None => Err(WindowColumnNotFound(key.to_string(), key.span)),
+    };
+    let mut functions = vec![];
+    let mut partition_by = vec![];
+    let mut order_by = vec![];
+    for part in pair.into_inner() {
+        match part.as_rule() {
+            Rule::window_fn => {
+                let mut inner = part.into_inner();
+                let mut call = inner.next().unwrap();
+                let mut alias = None;
+                if call.as_rule() == Rule::var {
+                    alias = Some(Symbol::new(call.as_str(), call.extract_span()));
+                    call = inner.next().unwrap();
+                }
+                let source = call.as_str().to_string();
+                let span = call.extract_span();
+                let mut args = call.into_inner();
+                let name = args.next().unwrap().as_str();
+                let args = args.map(|arg| build_expr(arg, param_pool)).try_collect()?;
+                let mut kind = WindowFunctionKind::new(name, args, span)?;
+                if let Some(expr) = kind.expr_mut() {
+                    expr.fill_binding_indices(binding_map)?;
+                }
+                functions.push(WindowFunction {
+                    alias,
+                    source,
+                    kind,
+                });
+            }
+            Rule::window_over => {
+                for clause in part.into_inner() {
+                    match clause.as_rule() {
+                        Rule::window_partition => {
+                            for key in clause.into_inner() {
+                                partition_by
+                                    .push(column(Symbol::new(key.as_str(), key.extract_span()))?);
+                            }
+                        }
+                        Rule::window_order => {
+                            for arg in clause.into_inner() {
+                                let (key, dir) = parse_sort_arg(arg);
+                                let (key, i) = column(key)?;
+                                order_by.push((key, i, dir));
+                            }
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    Ok(QueryWindow {
+        functions,
+        partition_by,
+        order_by,
+    })
+}
+
+fn parse_nest_spec(pair: Pair<'_>, binding_map: &BTreeMap<Symbol, usize>) -> Result<QueryNest> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Column '{0}' not found")]
+    #[diagnostic(code(parser::nest_column_not_found))]
+    #[diagnostic(help("Columns to nest must be variables in the head of the entry rule"))]
+    struct NestColumnNotFound(String, #[label] SourceSpan);
+
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Field '{0}' appears more than once in the same document")]
+    #[diagnostic(code(parser::nest_duplicate_field))]
+    struct NestDuplicateField(String, #[label] SourceSpan);
+
+    let (cols, children) = match pair.as_rule() {
+        Rule::nest_cols => (pair, None),
+        Rule::nest_spec => {
+            let mut inner = pair.into_inner();
+            (inner.next().unwrap(), inner.next())
+        }
+        _ => unreachable!(),
+    };
+    let parent: Vec<(Symbol, usize)> = cols
+        .into_inner()
+        .map(|p| {
+            let key = Symbol::new(p.as_str(), p.extract_span());
+            match binding_map.get(&key) {
+                Some(i) => Ok((key, *i)),
+                None => Err(NestColumnNotFound(key.to_string(), key.span)),
+            }
+        })
+        .try_collect()?;
+    let mut children_specs: Vec<(Symbol, QueryNest)> = vec![];
+    if let Some(children) = children {
+        for child in children.into_inner() {
+            let mut inner = child.into_inner();
+            let name = inner.next().unwrap();
+            let name = Symbol::new(name.as_str(), name.extract_span());
+            let spec = parse_nest_spec(inner.next().unwrap(), binding_map)?;
+            children_specs.push((name, spec));
+        }
+    }
+    let mut seen = BTreeSet::new();
+    for name in parent
+        .iter()
+        .map(|(k, _)| k)
+        .chain(children_specs.iter().map(|(k, _)| k))
+    {
+        ensure!(
+            seen.insert(&name.name),
+            NestDuplicateField(name.to_string(), name.span)
+        );
+    }
+    Ok(QueryNest {
+        parent,
+        children: children_specs,
+    })
+}
+
 fn parse_rule(
     src: Pair<'_>,
     param_pool: &BTreeMap<String, DataValue>,
@@ -756,6 +1180,10 @@ fn parse_atom(
                 .into_inner()
                 .map(|arg| extract_named_apply_arg(arg, param_pool))
                 .try_collect()?;
+            let mut src = src.peekable();
+            let bind_rest = src
+                .next_if(|p| p.as_rule() == Rule::named_apply_rest)
+                .is_some();
             let valid_at = match src.next() {
                 None => None,
                 Some(vld_clause) => {
@@ -767,15 +1195,99 @@ fn parse_atom(
                 inner: InputNamedFieldRelationApplyAtom {
                     name,
                     args,
+                    bind_rest,
                     span,
                     valid_at,
                 },
             }
         }
+        Rule::relation_column_apply => {
+            let span = src.extract_span();
+            let mut src = src.into_inner();
+            let name_p = src.next().unwrap();
+            let name_span = name_p.extract_span();
+            // the column is after the last dot, as names of relations can contain dots
+            let (name, column) = name_p.as_str()[1..].rsplit_once('.').unwrap();
+            let arg = build_expr(src.next().unwrap(), param_pool)?;
+            let valid_at = match src.next() {
+                None => None,
+                Some(vld_clause) => {
+                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
+                    Some(expr2vld_spec(vld_expr, cur_vld)?)
+                }
+            };
+            InputAtom::NamedFieldRelation {
+                inner: InputNamedFieldRelationApplyAtom {
+                    name: Symbol::new(name, name_span),
+                    args: BTreeMap::from([(SmartString::from(column), arg)]),
+                    bind_rest: false,
+                    span,
+                    valid_at,
+                },
+            }
+        }
+        Rule::path_apply => {
+            let span = src.extract_span();
+            let mut src = src.into_inner();
+            let from = src.next().unwrap();
+            let pattern = parse_path_pattern(src.next().unwrap());
+            let to = src.next().unwrap();
+            InputAtom::Path {
+                inner: InputPathApplyAtom {
+                    from: Symbol::new(from.as_str(), from.extract_span()),
+                    to: Symbol::new(to.as_str(), to.extract_span()),
+                    pattern,
+                    span,
+                },
+            }
+        }
         r => unreachable!("{:?}", r),
     })
 }
 
+fn parse_path_pattern(src: Pair<'_>) -> PathPattern {
+    let is_seq = src.as_rule() == Rule::path_seq;
+    let mut collected = src
+        .into_inner()
+        .map(|p| match p.as_rule() {
+            Rule::path_seq => parse_path_pattern(p),
+            Rule::path_factor => {
+                let mut inner = p.into_inner();
+                let primary = inner.next().unwrap();
+                let primary = match primary.as_rule() {
+                    Rule::path_label => {
+                        let mut label = primary.into_inner();
+                        let mut relation = label.next().unwrap();
+                        let inverse = relation.as_rule() == Rule::path_inverse;
+                        if inverse {
+                            relation = label.next().unwrap();
+                        }
+                        PathPattern::Label {
+                            relation: Symbol::new(relation.as_str(), relation.extract_span()),
+                            inverse,
+                        }
+                    }
+                    _ => parse_path_pattern(primary),
+                };
+                match inner.next().map(|r| r.as_str()) {
+                    None => primary,
+                    Some("+") => PathPattern::OneOrMore(primary.into()),
+                    Some("*") => PathPattern::ZeroOrMore(primary.into()),
+                    Some(_) => PathPattern::Optional(primary.into()),
+                }
+            }
+            r => unreachable!("{:?}", r),
+        })
+        .collect_vec();
+    if collected.len() == 1 {
+        collected.pop().unwrap()
+    } else if is_seq {
+        PathPattern::Sequence(collected)
+    } else {
+        PathPattern::Alternative(collected)
+    }
+}
+
 fn extract_named_apply_arg(
     pair: Pair<'_>,
     param_pool: &BTreeMap<String, DataValue>,
@@ -1083,7 +1595,7 @@ fn make_empty_const_rule(prog: &mut InputProgram, bindings: &[Symbol]) {
     );
 }
 
-fn expr2vld_spec(expr: Expr, cur_vld: ValidityTs) -> Result<ValidityTs> {
+pub(crate) fn expr2vld_spec(expr: Expr, cur_vld: ValidityTs) -> Result<ValidityTs> {
     let vld_span = expr.span();
     match expr.eval_to_const()? {
         DataValue::Num(n) => {
@@ -108,10 +108,22 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
         Rule::bool_type => ColType::Bool,
         Rule::int_type => ColType::Int,
         Rule::float_type => ColType::Float,
+        Rule::bigint_type => ColType::BigInt,
+        Rule::decimal_type => {
+            let scale = match pair.into_inner().next() {
+                None => None,
+                Some(p) => {
+                    let scale = p.as_str().replace('_', "");
+                    Some(scale.parse::<i64>().into_diagnostic()?)
+                }
+            };
+            ColType::Decimal { scale }
+        }
         Rule::string_type => ColType::String,
         Rule::bytes_type => ColType::Bytes,
         Rule::uuid_type => ColType::Uuid,
         Rule::json_type => ColType::Json,
+        Rule::duration_type => ColType::Duration,
         Rule::validity_type => ColType::Validity,
         Rule::list_type => {
             let mut inner = pair.into_inner();
@@ -6,6 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cmp::Reverse;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 
@@ -15,15 +16,26 @@ use ordered_float::OrderedFloat;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::functions::str2vld;
+use crate::data::geo::MAX_GEOHASH_PRECISION;
 use crate::data::program::InputProgram;
 use crate::data::relation::VecElementType;
 use crate::data::symb::Symbol;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fts::TokenizerConfig;
 use crate::parse::expr::{build_expr, parse_string};
+use crate::parse::format::format_script;
 use crate::parse::query::parse_query;
 use crate::parse::{ExtractSpan, Pairs, Rule, SourceSpan};
+use crate::runtime::cdc::{BadCdcTarget, CdcFormat, CdcSinkSpec, CdcTarget};
+use crate::runtime::cron::{BadCronExpr, CronExpr, ScheduledScript};
+use crate::runtime::idgen::{
+    IdGenKind, IdGenSpec, MAX_SNOWFLAKE_NODE_ID, NANOID_ALPHABET, NANOID_SIZE, SNOWFLAKE_EPOCH,
+};
+use crate::runtime::module::{parse_module_body, StoredModule};
+use crate::runtime::procedure::{check_procedure_body, DuplicateProcedureParam, StoredProcedure};
 use crate::runtime::relation::AccessLevel;
+use crate::runtime::retention::RetentionPolicy;
 use crate::{Expr, FixedRule};
 
 #[derive(Debug)]
@@ -33,19 +45,54 @@ pub enum SysOp {
     ListIndices(Symbol),
     ListRelations,
     ListRunning,
+    ShowScheduler,
     ListFixedRules,
     KillRunning(u64),
     Explain(Box<InputProgram>),
+    Check(Box<InputProgram>),
+    Format(String),
     RemoveRelation(Vec<Symbol>),
     RenameRelation(Vec<(Symbol, Symbol)>),
     ShowTrigger(Symbol),
     SetTriggers(Symbol, Vec<String>, Vec<String>, Vec<String>),
     SetAccessLevel(Vec<Symbol>, AccessLevel),
-    CreateIndex(Symbol, Symbol, Vec<Symbol>),
+    SetRetention(Symbol, Option<RetentionPolicy>),
+    ShowRetention,
+    RunRetention,
+    PurgeHistory(Symbol, ValidityTs),
+    SetHistoryRetention(Symbol, Option<f64>),
+    SetPartitions(Symbol, Vec<DataValue>),
+    ListPartitions(Symbol),
+    Truncate(Symbol),
+    DeleteWhere(Symbol, Expr),
+    TrackTxTime(Symbol),
+    UntrackTxTime(Symbol),
+    CreateIdGen(IdGenSpec, SourceSpan),
+    RemoveIdGen(Symbol),
+    ListIdGens,
+    AddSchedule(ScheduledScript),
+    RemoveSchedule(Symbol),
+    ListSchedules,
+    SaveModule(StoredModule),
+    RemoveModule(Symbol),
+    ListModules,
+    SaveProcedure(StoredProcedure),
+    RemoveProcedure(Symbol),
+    ListProcedures,
+    Call(Symbol, Vec<DataValue>),
+    AddCdcSink(CdcSinkSpec, SourceSpan),
+    RemoveCdcSink(Symbol),
+    ListCdcSinks,
+    VerifyRelation(Symbol),
+    CreateIndex(Symbol, Symbol, Vec<Expr>, Vec<Symbol>, bool),
     CreateVectorIndex(HnswIndexConfig),
     CreateFtsIndex(FtsIndexConfig),
     CreateMinHashLshIndex(MinHashLshConfig),
+    CreateGeoIndex(GeoIndexConfig),
     RemoveIndex(Symbol, Symbol),
+    AddGeneratedColumn(Symbol, Symbol, Expr, bool),
+    RemoveGeneratedColumn(Symbol, Symbol),
+    IndexAdvisor(Box<InputProgram>),
     DescribeRelation(Symbol, SmartString<LazyCompact>),
 }
 
@@ -67,12 +114,22 @@ pub struct MinHashLshConfig {
     pub tokenizer: TokenizerConfig,
     pub filters: Vec<TokenizerConfig>,
     pub n_gram: usize,
+    pub shingling: LshShingling,
     pub n_perm: usize,
     pub false_positive_weight: OrderedFloat<f64>,
     pub false_negative_weight: OrderedFloat<f64>,
     pub target_threshold: OrderedFloat<f64>,
 }
 
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GeoIndexConfig {
+    pub base_relation: SmartString<LazyCompact>,
+    pub index_name: SmartString<LazyCompact>,
+    pub extractor: String,
+    pub precision: usize,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct HnswIndexConfig {
     pub base_relation: SmartString<LazyCompact>,
@@ -86,6 +143,19 @@ pub struct HnswIndexConfig {
     pub index_filter: Option<String>,
     pub extend_candidates: bool,
     pub keep_pruned_connections: bool,
+    pub quantization: Option<HnswQuantization>,
+}
+
+/// Compression of the vectors used for traversing HNSW indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HnswQuantization {
+    /// One byte per component
+    Scalar,
+    /// One byte per subspace, with centroids trained on the existing vectors
+    Product {
+        /// The number of subspaces, which must divide the dimension
+        subspaces: usize,
+    },
 }
 
 #[derive(
@@ -97,6 +167,26 @@ pub enum HnswDistance {
     Cosine,
 }
 
+/// How strings are cut into the shingles hashed by LSH indices
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    serde_derive::Serialize,
+    serde_derive::Deserialize,
+)]
+pub enum LshShingling {
+    /// N-grams of the tokens
+    #[default]
+    Word,
+    /// N-grams of the characters, with the tokens separated by single spaces
+    Char,
+}
+
 #[derive(Debug, Diagnostic, Error)]
 #[error("Cannot interpret {0} as process ID")]
 #[diagnostic(code(parser::not_proc_id))]
@@ -112,6 +202,7 @@ pub(crate) fn parse_sys(
     Ok(match inner.as_rule() {
         Rule::compact_op => SysOp::Compact,
         Rule::running_op => SysOp::ListRunning,
+        Rule::scheduler_op => SysOp::ShowScheduler,
         Rule::kill_op => {
             let i_expr = inner.into_inner().next().unwrap();
             let i_val = build_expr(i_expr, param_pool)?;
@@ -130,6 +221,19 @@ pub(crate) fn parse_sys(
             )?;
             SysOp::Explain(Box::new(prog))
         }
+        Rule::check_op => {
+            let prog = parse_query(
+                inner.into_inner().next().unwrap().into_inner(),
+                param_pool,
+                algorithms,
+                cur_vld,
+            )?;
+            SysOp::Check(Box::new(prog))
+        }
+        Rule::format_op => {
+            let prog = inner.into_inner().next().unwrap();
+            SysOp::Format(format_script(prog.as_str())?)
+        }
         Rule::describe_relation_op => {
             let mut inner = inner.into_inner();
             let rels_p = inner.next().unwrap();
@@ -149,6 +253,10 @@ pub(crate) fn parse_sys(
 
             SysOp::RemoveRelation(rel)
         }
+        Rule::verify_op => {
+            let rel_p = inner.into_inner().next().unwrap();
+            SysOp::VerifyRelation(Symbol::new(rel_p.as_str(), rel_p.extract_span()))
+        }
         Rule::list_columns_op => {
             let rels_p = inner.into_inner().next().unwrap();
             let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
@@ -189,6 +297,251 @@ pub(crate) fn parse_sys(
             }
             SysOp::SetAccessLevel(rels, access_level)
         }
+        Rule::retention_op => {
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::retention_set => {
+                    let mut ps = op.into_inner();
+                    let rel_p = ps.next().unwrap();
+                    let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+                    let keep = parse_duration(ps.next().unwrap().as_str());
+                    let column = SmartString::from(ps.next().unwrap().as_str());
+                    SysOp::SetRetention(rel, Some(RetentionPolicy { column, keep }))
+                }
+                Rule::retention_remove => {
+                    let rel_p = op.into_inner().next().unwrap();
+                    let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+                    SysOp::SetRetention(rel, None)
+                }
+                Rule::retention_status => SysOp::ShowRetention,
+                Rule::retention_run => SysOp::RunRetention,
+                _ => unreachable!(),
+            }
+        }
+        Rule::history_op => {
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::history_purge => {
+                    let mut ps = op.into_inner();
+                    let rel_p = ps.next().unwrap();
+                    let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+                    let ts_p = ps.next().unwrap();
+                    let before = match ts_p.as_rule() {
+                        Rule::pos_int => {
+                            let micros =
+                                ts_p.as_str().replace('_', "").parse::<i64>().map_err(|_| {
+                                    miette!("timestamp {} is out of range", ts_p.as_str())
+                                })?;
+                            ValidityTs(Reverse(micros))
+                        }
+                        _ => str2vld(&parse_string(ts_p)?)?,
+                    };
+                    SysOp::PurgeHistory(rel, before)
+                }
+                Rule::history_set => {
+                    let mut ps = op.into_inner();
+                    let rel_p = ps.next().unwrap();
+                    let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+                    let keep = parse_duration(ps.next().unwrap().as_str());
+                    SysOp::SetHistoryRetention(rel, Some(keep))
+                }
+                Rule::history_remove => {
+                    let rel_p = op.into_inner().next().unwrap();
+                    let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+                    SysOp::SetHistoryRetention(rel, None)
+                }
+                _ => unreachable!(),
+            }
+        }
+        Rule::truncate_op => {
+            let rel_p = inner.into_inner().next().unwrap();
+            SysOp::Truncate(Symbol::new(rel_p.as_str(), rel_p.extract_span()))
+        }
+        Rule::delete_where_op => {
+            let mut ps = inner.into_inner();
+            let rel_p = ps.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let pred = build_expr(ps.next().unwrap(), param_pool)?;
+            SysOp::DeleteWhere(rel, pred)
+        }
+        Rule::partition_op => {
+            let op = inner.into_inner().next().unwrap();
+            let op_rule = op.as_rule();
+            let mut ps = op.into_inner();
+            let rel_p = ps.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            match op_rule {
+                Rule::partition_set => {
+                    let bounds_p = ps.next().unwrap();
+                    let span = bounds_p.extract_span();
+                    let bounds = match build_expr(bounds_p, param_pool)?.eval_to_const()? {
+                        DataValue::List(l) if !l.is_empty() => l,
+                        _ => bail!(BadPartitionBounds(span)),
+                    };
+                    SysOp::SetPartitions(rel, bounds)
+                }
+                Rule::partition_remove => SysOp::SetPartitions(rel, vec![]),
+                Rule::partition_list => SysOp::ListPartitions(rel),
+                _ => unreachable!(),
+            }
+        }
+        Rule::tx_time_op => {
+            let op = inner.into_inner().next().unwrap();
+            let is_track = op.as_rule() == Rule::tx_time_track;
+            let rel_p = op.into_inner().next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            if is_track {
+                SysOp::TrackTxTime(rel)
+            } else {
+                SysOp::UntrackTxTime(rel)
+            }
+        }
+        Rule::idgen_op => {
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::idgen_create => {
+                    let span = op.extract_span();
+                    let mut ps = op.into_inner();
+                    let name = SmartString::from(ps.next().unwrap().as_str());
+                    let mut opts = BTreeMap::new();
+                    for opt_pair in ps {
+                        let mut opt_inner = opt_pair.into_inner();
+                        let opt_name = opt_inner.next().unwrap().as_str();
+                        let mut expr = build_expr(opt_inner.next().unwrap(), param_pool)?;
+                        expr.partial_eval()?;
+                        opts.insert(opt_name, expr.eval_to_const()?);
+                    }
+                    let kind = parse_id_gen_kind(opts)?;
+                    SysOp::CreateIdGen(IdGenSpec { name, kind }, span)
+                }
+                Rule::idgen_drop => {
+                    let name_p = op.into_inner().next().unwrap();
+                    SysOp::RemoveIdGen(Symbol::new(name_p.as_str(), name_p.extract_span()))
+                }
+                Rule::idgen_list => SysOp::ListIdGens,
+                _ => unreachable!(),
+            }
+        }
+        Rule::schedule_op => {
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::schedule_add => {
+                    let mut ps = op.into_inner();
+                    let name = SmartString::from(ps.next().unwrap().as_str());
+                    let cron_p = ps.next().unwrap();
+                    let cron_span = cron_p.extract_span();
+                    let cron = parse_string(cron_p)?.to_string();
+                    if let Err(msg) = CronExpr::parse(&cron) {
+                        bail!(BadCronExpr(cron, msg, cron_span))
+                    }
+                    let script = parse_string(ps.next().unwrap())?.to_string();
+                    SysOp::AddSchedule(ScheduledScript { name, cron, script })
+                }
+                Rule::schedule_remove => {
+                    let name_p = op.into_inner().next().unwrap();
+                    SysOp::RemoveSchedule(Symbol::new(name_p.as_str(), name_p.extract_span()))
+                }
+                Rule::schedule_list => SysOp::ListSchedules,
+                _ => unreachable!(),
+            }
+        }
+        Rule::module_op => {
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::module_save => {
+                    let mut ps = op.into_inner();
+                    let name = SmartString::from(ps.next().unwrap().as_str());
+                    let body = ps.next().unwrap();
+                    let rules = body.as_str().to_string();
+                    parse_module_body(body, algorithms)?;
+                    SysOp::SaveModule(StoredModule { name, rules })
+                }
+                Rule::module_remove => {
+                    let name_p = op.into_inner().next().unwrap();
+                    SysOp::RemoveModule(Symbol::new(name_p.as_str(), name_p.extract_span()))
+                }
+                Rule::module_list => SysOp::ListModules,
+                _ => unreachable!(),
+            }
+        }
+        Rule::procedure_op => {
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::procedure_save => {
+                    let mut ps = op.into_inner();
+                    let name = SmartString::from(ps.next().unwrap().as_str());
+                    let mut params: Vec<String> = vec![];
+                    let mut body = None;
+                    for p in ps {
+                        match p.as_rule() {
+                            Rule::param => {
+                                let param = p.as_str().strip_prefix('$').unwrap().to_string();
+                                if params.contains(&param) {
+                                    bail!(DuplicateProcedureParam(param, p.extract_span()))
+                                }
+                                params.push(param)
+                            }
+                            _ => body = Some(p),
+                        }
+                    }
+                    let body = body.unwrap();
+                    check_procedure_body(body.clone(), &params)?;
+                    let body = body.as_str().trim().to_string();
+                    SysOp::SaveProcedure(StoredProcedure { name, params, body })
+                }
+                Rule::procedure_remove => {
+                    let name_p = op.into_inner().next().unwrap();
+                    SysOp::RemoveProcedure(Symbol::new(name_p.as_str(), name_p.extract_span()))
+                }
+                Rule::procedure_list => SysOp::ListProcedures,
+                _ => unreachable!(),
+            }
+        }
+        Rule::call_op => {
+            let mut ps = inner.into_inner();
+            let name_p = ps.next().unwrap();
+            let name = Symbol::new(name_p.as_str(), name_p.extract_span());
+            let args = ps
+                .map(|arg| build_expr(arg, param_pool)?.eval_to_const())
+                .try_collect()?;
+            SysOp::Call(name, args)
+        }
+        Rule::cdc_op => {
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::cdc_add => {
+                    let span = op.extract_span();
+                    let mut ps = op.into_inner();
+                    let name = SmartString::from(ps.next().unwrap().as_str());
+                    let relation = SmartString::from(ps.next().unwrap().as_str());
+                    let target_p = ps.next().unwrap();
+                    let target_span = target_p.extract_span();
+                    let target = parse_string(target_p)?.to_string();
+                    if let Err(msg) = CdcTarget::parse(&target) {
+                        bail!(BadCdcTarget(target, msg, target_span))
+                    }
+                    let format = match ps.next().map(|p| p.as_str()) {
+                        Some("avro") => CdcFormat::Avro,
+                        _ => CdcFormat::Json,
+                    };
+                    SysOp::AddCdcSink(
+                        CdcSinkSpec {
+                            name,
+                            relation,
+                            target,
+                            format,
+                        },
+                        span,
+                    )
+                }
+                Rule::cdc_remove => {
+                    let name_p = op.into_inner().next().unwrap();
+                    SysOp::RemoveCdcSink(Symbol::new(name_p.as_str(), name_p.extract_span()))
+                }
+                Rule::cdc_list => SysOp::ListCdcSinks,
+                _ => unreachable!(),
+            }
+        }
         Rule::trigger_relation_show_op => {
             let rels_p = inner.into_inner().next().unwrap();
             let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
@@ -236,6 +589,7 @@ pub(crate) fn parse_sys(
                     let mut extractor = "".to_string();
                     let mut extract_filter = "".to_string();
                     let mut n_gram = 1;
+                    let mut shingling = LshShingling::Word;
                     let mut n_perm = 200;
                     let mut target_threshold = 0.9;
                     let mut false_positive_weight = 1.0;
@@ -270,6 +624,18 @@ pub(crate) fn parse_sys(
                                     .ok_or_else(|| miette!("n_gram must be an integer"))?
                                     as usize;
                             }
+                            "shingling" => {
+                                shingling = match opt_val.as_str().trim() {
+                                    "Word" => LshShingling::Word,
+                                    "Char" => LshShingling::Char,
+                                    _ => {
+                                        return Err(miette!(
+                                            "Invalid shingling: {}",
+                                            opt_val.as_str()
+                                        ))
+                                    }
+                                }
+                            }
                             "n_perm" => {
                                 let mut expr = build_expr(opt_val, param_pool)?;
                                 expr.partial_eval()?;
@@ -383,6 +749,7 @@ pub(crate) fn parse_sys(
                         tokenizer,
                         filters,
                         n_gram,
+                        shingling,
                         n_perm,
                         false_positive_weight: false_positive_weight.into(),
                         false_negative_weight: false_negative_weight.into(),
@@ -402,6 +769,72 @@ pub(crate) fn parse_sys(
                 r => unreachable!("{:?}", r),
             }
         }
+        Rule::geo_idx_op => {
+            let inner = inner.into_inner().next().unwrap();
+            match inner.as_rule() {
+                Rule::index_create_adv => {
+                    let mut inner = inner.into_inner();
+                    let rel = inner.next().unwrap();
+                    let name = inner.next().unwrap();
+                    let mut extractor = "".to_string();
+                    let mut extract_filter = "".to_string();
+                    let mut precision = 7;
+                    for opt_pair in inner {
+                        let mut opt_inner = opt_pair.into_inner();
+                        let opt_name = opt_inner.next().unwrap();
+                        let opt_val = opt_inner.next().unwrap();
+                        match opt_name.as_str() {
+                            "precision" => {
+                                let mut expr = build_expr(opt_val, param_pool)?;
+                                expr.partial_eval()?;
+                                let v = expr.eval_to_const()?;
+                                precision = v
+                                    .get_int()
+                                    .ok_or_else(|| miette!("precision must be an integer"))?;
+                            }
+                            "extractor" => {
+                                let mut ex = build_expr(opt_val, param_pool)?;
+                                ex.partial_eval()?;
+                                extractor = ex.to_string();
+                            }
+                            "extract_filter" => {
+                                let mut ex = build_expr(opt_val, param_pool)?;
+                                ex.partial_eval()?;
+                                extract_filter = ex.to_string();
+                            }
+                            _ => bail!("Unknown option {} for geo index", opt_name.as_str()),
+                        }
+                    }
+                    ensure!(!extractor.is_empty(), "extractor is required for geo index");
+                    ensure!(
+                        (1..=MAX_GEOHASH_PRECISION as i64).contains(&precision),
+                        "precision must be between 1 and {}",
+                        MAX_GEOHASH_PRECISION
+                    );
+
+                    if !extract_filter.is_empty() {
+                        extractor = format!("if({}, {})", extract_filter, extractor);
+                    }
+
+                    SysOp::CreateGeoIndex(GeoIndexConfig {
+                        base_relation: SmartString::from(rel.as_str()),
+                        index_name: SmartString::from(name.as_str()),
+                        extractor,
+                        precision: precision as usize,
+                    })
+                }
+                Rule::index_drop => {
+                    let mut inner = inner.into_inner();
+                    let rel = inner.next().unwrap();
+                    let name = inner.next().unwrap();
+                    SysOp::RemoveIndex(
+                        Symbol::new(rel.as_str(), rel.extract_span()),
+                        Symbol::new(name.as_str(), name.extract_span()),
+                    )
+                }
+                r => unreachable!("{:?}", r),
+            }
+        }
         Rule::fts_idx_op => {
             let inner = inner.into_inner().next().unwrap();
             match inner.as_rule() {
@@ -529,6 +962,8 @@ pub(crate) fn parse_sys(
                     let mut index_filter = None;
                     let mut extend_candidates = false;
                     let mut keep_pruned_connections = false;
+                    let mut quantization = None;
+                    let mut pq_subspaces = None;
 
                     for opt_pair in inner {
                         let mut opt_inner = opt_pair.into_inner();
@@ -599,6 +1034,28 @@ pub(crate) fn parse_sys(
                             "keep_pruned_connections" => {
                                 keep_pruned_connections = opt_val.as_str().trim() == "true";
                             }
+                            "quantization" => {
+                                quantization = match opt_val.as_str().trim() {
+                                    "SQ" => Some(HnswQuantization::Scalar),
+                                    "PQ" => Some(HnswQuantization::Product { subspaces: 0 }),
+                                    _ => {
+                                        return Err(miette!(
+                                            "Invalid quantization: {}",
+                                            opt_val.as_str()
+                                        ))
+                                    }
+                                }
+                            }
+                            "pq_subspaces" => {
+                                let v = build_expr(opt_val, param_pool)?
+                                    .eval_to_const()?
+                                    .get_int()
+                                    .ok_or_else(|| {
+                                        miette!("Invalid pq_subspaces: {}", opt_val_str)
+                                    })?;
+                                ensure!(v > 0, "Invalid pq_subspaces: {}", v);
+                                pq_subspaces = Some(v as usize);
+                            }
                             _ => return Err(miette!("Invalid option: {}", opt_name.as_str())),
                         }
                     }
@@ -608,6 +1065,16 @@ pub(crate) fn parse_sys(
                     if m_neighbours == 0 {
                         bail!("m_neighbours must be set");
                     }
+                    if let Some(HnswQuantization::Product { subspaces }) = &mut quantization {
+                        // by default, subvectors of four components
+                        *subspaces = match pq_subspaces {
+                            Some(n) => n,
+                            None if vec_dim % 4 == 0 => vec_dim / 4,
+                            None => vec_dim,
+                        };
+                    } else if pq_subspaces.is_some() {
+                        bail!("pq_subspaces requires product quantization");
+                    }
                     SysOp::CreateVectorIndex(HnswIndexConfig {
                         base_relation: SmartString::from(rel.as_str()),
                         index_name: SmartString::from(name.as_str()),
@@ -620,6 +1087,7 @@ pub(crate) fn parse_sys(
                         index_filter,
                         extend_candidates,
                         keep_pruned_connections,
+                        quantization,
                     })
                 }
                 Rule::index_drop => {
@@ -642,9 +1110,21 @@ pub(crate) fn parse_sys(
                     let mut inner = inner.into_inner();
                     let rel = inner.next().unwrap();
                     let name = inner.next().unwrap();
-                    let cols = inner
-                        .map(|p| Symbol::new(p.as_str(), p.extract_span()))
-                        .collect_vec();
+                    let mut cols = vec![];
+                    let mut payload = vec![];
+                    let mut unique = false;
+                    for p in inner {
+                        match p.as_rule() {
+                            Rule::index_payload => {
+                                payload = p
+                                    .into_inner()
+                                    .map(|p| Symbol::new(p.as_str(), p.extract_span()))
+                                    .collect_vec();
+                            }
+                            Rule::index_unique => unique = true,
+                            _ => cols.push(build_expr(p, param_pool)?),
+                        }
+                    }
 
                     #[derive(Debug, Diagnostic, Error)]
                     #[error("index must have at least one column specified")]
@@ -656,6 +1136,8 @@ pub(crate) fn parse_sys(
                         Symbol::new(rel.as_str(), rel.extract_span()),
                         Symbol::new(name.as_str(), name.extract_span()),
                         cols,
+                        payload,
+                        unique,
                     )
                 }
                 Rule::index_drop => {
@@ -667,6 +1149,43 @@ pub(crate) fn parse_sys(
                         Symbol::new(name.as_str(), name.extract_span()),
                     )
                 }
+                Rule::index_advisor => {
+                    let prog = parse_query(
+                        inner.into_inner().next().unwrap().into_inner(),
+                        param_pool,
+                        algorithms,
+                        cur_vld,
+                    )?;
+                    SysOp::IndexAdvisor(Box::new(prog))
+                }
+                _ => unreachable!(),
+            }
+        }
+        Rule::alter_op => {
+            let mut inner = inner.into_inner();
+            let rel = inner.next().unwrap();
+            let rel = Symbol::new(rel.as_str(), rel.extract_span());
+            let op = inner.next().unwrap();
+            match op.as_rule() {
+                Rule::alter_add_generated => {
+                    let mut inner = op.into_inner();
+                    let name = inner.next().unwrap();
+                    let expr = build_expr(inner.next().unwrap(), param_pool)?;
+                    let index = inner.next().is_some();
+                    SysOp::AddGeneratedColumn(
+                        rel,
+                        Symbol::new(name.as_str(), name.extract_span()),
+                        expr,
+                        index,
+                    )
+                }
+                Rule::alter_drop_generated => {
+                    let name = op.into_inner().next().unwrap();
+                    SysOp::RemoveGeneratedColumn(
+                        rel,
+                        Symbol::new(name.as_str(), name.extract_span()),
+                    )
+                }
                 _ => unreachable!(),
             }
         }
@@ -674,3 +1193,70 @@ pub(crate) fn parse_sys(
         r => unreachable!("{:?}", r),
     })
 }
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Partition bounds must be a non-empty list of values")]
+#[diagnostic(code(parser::bad_partition_bounds))]
+struct BadPartitionBounds(#[label] SourceSpan);
+
+/// Seconds in a duration such as `90d`, as matched by `retention_duration`.
+fn parse_duration(s: &str) -> f64 {
+    let (num, unit) = s.split_at(s.len() - 1);
+    let multiplier = match unit {
+        "s" => 1.,
+        "m" => 60.,
+        "h" => 3600.,
+        "d" => 86400.,
+        "w" => 604800.,
+        _ => unreachable!(),
+    };
+    num.parse::<f64>().unwrap() * multiplier
+}
+
+fn parse_id_gen_kind(mut opts: BTreeMap<&str, DataValue>) -> Result<IdGenKind> {
+    let kind_name = match opts.remove("kind") {
+        Some(DataValue::Str(s)) => s,
+        _ => bail!("ID generators require a string option 'kind'"),
+    };
+    let kind = match &kind_name as &str {
+        "uuid_v7" => IdGenKind::UuidV7,
+        "snowflake" => {
+            let node_id = match opts.remove("node_id").map(|v| v.get_int()) {
+                Some(Some(i)) if (0..=MAX_SNOWFLAKE_NODE_ID as i64).contains(&i) => i as u16,
+                _ => bail!(
+                    "Snowflake ID generators require an integer option 'node_id' between 0 and {}",
+                    MAX_SNOWFLAKE_NODE_ID
+                ),
+            };
+            let epoch = match opts.remove("epoch") {
+                None => SNOWFLAKE_EPOCH,
+                Some(v) => v
+                    .get_int()
+                    .ok_or_else(|| miette!("epoch must be an integer of milliseconds"))?,
+            };
+            IdGenKind::Snowflake { node_id, epoch }
+        }
+        "nanoid" => {
+            let alphabet = match opts.remove("alphabet") {
+                None => NANOID_ALPHABET.to_string(),
+                Some(DataValue::Str(s)) if !s.is_empty() => s.to_string(),
+                Some(_) => bail!("alphabet must be a non-empty string"),
+            };
+            let size = match opts.remove("size") {
+                None => NANOID_SIZE,
+                Some(v) => match v.get_int() {
+                    Some(i) if i > 0 => i as usize,
+                    _ => bail!("size must be a positive integer"),
+                },
+            };
+            IdGenKind::NanoId { alphabet, size }
+        }
+        k => bail!(
+            "Unknown ID generator kind '{k}', expected one of 'uuid_v7', 'snowflake' or 'nanoid'"
+        ),
+    };
+    if let Some(opt) = opts.keys().next() {
+        bail!("Option '{opt}' does not apply to ID generators of kind '{kind_name}'")
+    }
+    Ok(kind)
+}
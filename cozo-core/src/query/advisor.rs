@@ -0,0 +1,282 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Suggestions for indices that would eliminate full scans of stored relations.
+//! Used by the `::index advisor` system op.
+
+use std::collections::BTreeSet;
+
+use itertools::Itertools;
+
+use crate::data::expr::Expr;
+use crate::data::functions::{OP_COS_DIST, OP_IP_DIST, OP_L2_DIST, OP_STR_INCLUDES};
+use crate::data::relation::ColType;
+use crate::data::symb::Symbol;
+use crate::query::compile::{CompiledProgram, CompiledRuleSet};
+use crate::query::ra::{join_is_prefix, RelAlgebra};
+use crate::runtime::relation::RelationHandle;
+
+/// An index that the planner would pick up for a scan that currently reads
+/// the whole relation.
+#[derive(Debug)]
+pub(crate) struct IndexAdvice {
+    pub(crate) rule: String,
+    pub(crate) relation: String,
+    pub(crate) kind: &'static str,
+    pub(crate) columns: Vec<String>,
+    pub(crate) reason: String,
+    pub(crate) suggestion: String,
+}
+
+/// Walks the compiled plan and collects index suggestions, at most one for
+/// each relation, kind and set of columns.
+pub(crate) fn advise_indices(strata: &[CompiledProgram]) -> Vec<IndexAdvice> {
+    let mut ret = vec![];
+    for p in strata {
+        for (rule_name, rule_set) in p {
+            if let CompiledRuleSet::Rules(rules) = rule_set {
+                for rule in rules {
+                    advise_relation(&rule.relation, &rule_name.to_string(), &mut ret);
+                }
+            }
+        }
+    }
+    let mut seen = BTreeSet::new();
+    ret.retain(|a| seen.insert((a.relation.clone(), a.kind, a.columns.clone())));
+    ret
+}
+
+fn advise_relation(rel: &RelAlgebra, rule: &str, coll: &mut Vec<IndexAdvice>) {
+    match rel {
+        RelAlgebra::Fixed(_) | RelAlgebra::TempStore(_) | RelAlgebra::Virtual(_) => {}
+        RelAlgebra::Stored(s) => advise_filters(&s.storage, &s.bindings, &s.filters, rule, coll),
+        RelAlgebra::StoredWithValidity(s) => {
+            advise_filters(&s.storage, &s.bindings, &s.filters, rule, coll)
+        }
+        RelAlgebra::Join(inner) => {
+            advise_relation(&inner.left, rule, coll);
+            advise_relation(&inner.right, rule, coll);
+            advise_join(&inner.right, &inner.joiner.right_keys, false, rule, coll);
+        }
+        RelAlgebra::NegJoin(inner) => {
+            advise_relation(&inner.left, rule, coll);
+            advise_relation(&inner.right, rule, coll);
+            advise_join(&inner.right, &inner.joiner.right_keys, true, rule, coll);
+        }
+        RelAlgebra::Reorder(r) => advise_relation(&r.relation, rule, coll),
+        RelAlgebra::Filter(r) => advise_relation(&r.parent, rule, coll),
+        RelAlgebra::Unification(r) => advise_relation(&r.parent, rule, coll),
+        RelAlgebra::HnswSearch(r) => advise_relation(&r.parent, rule, coll),
+        RelAlgebra::FtsSearch(r) => advise_relation(&r.parent, rule, coll),
+        RelAlgebra::LshSearch(r) => advise_relation(&r.parent, rule, coll),
+        RelAlgebra::GeoSearch(r) => advise_relation(&r.parent, rule, coll),
+    }
+}
+
+fn column_names(storage: &RelationHandle) -> Vec<String> {
+    storage
+        .metadata
+        .keys
+        .iter()
+        .chain(storage.metadata.non_keys.iter())
+        .map(|col| col.name.to_string())
+        .collect()
+}
+
+fn fresh_index_name(storage: &RelationHandle, base: String) -> String {
+    let mut name = base.clone();
+    let mut counter = 1;
+    while storage.has_index(&name) {
+        counter += 1;
+        name = format!("{base}_{counter}");
+    }
+    name
+}
+
+/// A join against a stored relation whose bound columns do not form a prefix of
+/// its keys materializes the whole relation. An index whose leading columns are
+/// exactly the bound ones turns this into prefix scans.
+fn advise_join(
+    right: &RelAlgebra,
+    right_keys: &[Symbol],
+    is_negation: bool,
+    rule: &str,
+    coll: &mut Vec<IndexAdvice>,
+) {
+    let (storage, bindings, has_validity) = match right {
+        RelAlgebra::Stored(s) => (&s.storage, &s.bindings, false),
+        RelAlgebra::StoredWithValidity(s) => (&s.storage, &s.bindings, true),
+        _ => return,
+    };
+    // indices and other derived relations cannot be indexed themselves
+    if storage.name.contains(':') || right_keys.is_empty() {
+        return;
+    }
+    let mut positions = right_keys
+        .iter()
+        .filter_map(|k| bindings.iter().position(|b| b == k))
+        .collect_vec();
+    if positions.len() != right_keys.len() || join_is_prefix(&positions) {
+        return;
+    }
+    // the planner never uses an index when the first key is bound
+    if positions.contains(&0) {
+        return;
+    }
+    let key_len = storage.metadata.keys.len();
+    if has_validity {
+        // the validity is always the last key of the index
+        positions.retain(|i| *i != key_len - 1);
+    }
+    positions.sort();
+    positions.dedup();
+    if positions.is_empty() {
+        return;
+    }
+    // negations only use an index that contains every column they look at
+    if is_negation
+        && bindings.iter().enumerate().any(|(i, b)| {
+            i >= key_len && !b.is_generated_ignored_symbol() && !positions.contains(&i)
+        })
+    {
+        return;
+    }
+    let names = column_names(storage);
+    let columns = positions.iter().map(|i| names[*i].clone()).collect_vec();
+    let idx_name = fresh_index_name(storage, format!("idx_{}", columns.join("_")));
+    coll.push(IndexAdvice {
+        rule: rule.to_string(),
+        relation: storage.name.to_string(),
+        kind: "index",
+        reason: format!(
+            "{} scans all of :{} since {} is not a prefix of its keys",
+            if is_negation { "negation" } else { "join" },
+            storage.name,
+            columns.join(", ")
+        ),
+        suggestion: format!(
+            "::index create {}:{} {{{}}}",
+            storage.name,
+            idx_name,
+            columns.join(", ")
+        ),
+        columns,
+    });
+}
+
+fn collect_applications<'a>(expr: &'a Expr, coll: &mut Vec<(&'static str, &'a [Expr])>) {
+    match expr {
+        Expr::Apply { op, args, .. } => {
+            coll.push((op.name, args));
+            for arg in args.iter() {
+                collect_applications(arg, coll);
+            }
+        }
+        Expr::Cond { clauses, .. } => {
+            for (cond, val) in clauses {
+                collect_applications(cond, coll);
+                collect_applications(val, coll);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Substring and vector distance filters read every row of the relation, whereas
+/// full-text and vector indices can answer them directly once the query uses them.
+fn advise_filters(
+    storage: &RelationHandle,
+    bindings: &[Symbol],
+    filters: &[Expr],
+    rule: &str,
+    coll: &mut Vec<IndexAdvice>,
+) {
+    if storage.name.contains(':') {
+        return;
+    }
+    let mut applications = vec![];
+    for filter in filters {
+        collect_applications(filter, &mut applications);
+    }
+    let columns = storage
+        .metadata
+        .keys
+        .iter()
+        .chain(storage.metadata.non_keys.iter())
+        .collect_vec();
+    for (op_name, args) in applications {
+        let col_idx = match args.iter().find_map(|arg| match arg {
+            Expr::Binding { var, .. } => bindings.iter().position(|b| b == var),
+            _ => None,
+        }) {
+            Some(i) => i,
+            None => continue,
+        };
+        let col_name = columns[col_idx].name.to_string();
+        if op_name == OP_STR_INCLUDES.name {
+            if storage
+                .fts_indices
+                .values()
+                .any(|(_, manifest)| manifest.extractor == col_name)
+            {
+                continue;
+            }
+            let idx_name = fresh_index_name(storage, format!("fts_{col_name}"));
+            coll.push(IndexAdvice {
+                rule: rule.to_string(),
+                relation: storage.name.to_string(),
+                kind: "fts",
+                columns: vec![col_name.clone()],
+                reason: format!(
+                    "substring filter scans all of :{}, query ~{}:{} instead",
+                    storage.name, storage.name, idx_name
+                ),
+                suggestion: format!(
+                    "::fts create {}:{} {{extractor: {}, tokenizer: Simple, filters: [Lowercase]}}",
+                    storage.name, idx_name, col_name
+                ),
+            });
+        } else {
+            let distance = if op_name == OP_L2_DIST.name {
+                "L2"
+            } else if op_name == OP_COS_DIST.name {
+                "Cosine"
+            } else if op_name == OP_IP_DIST.name {
+                "IP"
+            } else {
+                continue;
+            };
+            let (dtype, dim) = match &columns[col_idx].typing.coltype {
+                ColType::Vec { eltype, len } => (eltype, len),
+                _ => continue,
+            };
+            if storage
+                .hnsw_indices
+                .values()
+                .any(|(_, manifest)| manifest.vec_fields.contains(&col_idx))
+            {
+                continue;
+            }
+            let idx_name = fresh_index_name(storage, format!("hnsw_{col_name}"));
+            coll.push(IndexAdvice {
+                rule: rule.to_string(),
+                relation: storage.name.to_string(),
+                kind: "hnsw",
+                columns: vec![col_name.clone()],
+                reason: format!(
+                    "vector distance filter scans all of :{}, query ~{}:{} instead",
+                    storage.name, storage.name, idx_name
+                ),
+                suggestion: format!(
+                    "::hnsw create {}:{} {{dim: {}, dtype: {:?}, fields: [{}], distance: {}, m: 50, ef_construction: 20}}",
+                    storage.name, idx_name, dim, dtype, col_name, distance
+                ),
+            });
+        }
+    }
+}
@@ -7,6 +7,7 @@
  */
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
 
 use itertools::Itertools;
 use miette::{bail, ensure, Context, Diagnostic, Result};
@@ -15,15 +16,16 @@ use thiserror::Error;
 use crate::data::aggr::Aggregation;
 use crate::data::expr::Expr;
 use crate::data::program::{
-    MagicAtom, MagicFixedRuleApply, MagicInlineRule, MagicRulesOrFixed, MagicSymbol,
-    StratifiedMagicProgram,
+    ExperimentalFeatures, MagicAtom, MagicFixedRuleApply, MagicFixedRuleRuleArg, MagicInlineRule,
+    MagicRelationApplyAtom, MagicRulesOrFixed, MagicSymbol, QueryHints, StratifiedMagicProgram,
 };
 use crate::data::symb::Symbol;
-use crate::data::value::DataValue;
+use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::SourceSpan;
 use crate::query::ra::RelAlgebra;
 use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
 use crate::runtime::transact::SessionTx;
+use crate::runtime::virtual_relation::VirtualRelation;
 
 pub(crate) type CompiledProgram = BTreeMap<MagicSymbol, CompiledRuleSet>;
 
@@ -90,6 +92,96 @@ pub(crate) struct CompiledRule {
     pub(crate) contained_rules: BTreeMap<MagicSymbol, ContainedRuleMultiplicity>,
 }
 
+impl CompiledRuleSet {
+    /// The rules whose stores are read by this rule set.
+    fn dependencies(&self) -> BTreeSet<&MagicSymbol> {
+        match self {
+            CompiledRuleSet::Rules(rules) => rules
+                .iter()
+                .flat_map(|rule| rule.contained_rules.keys())
+                .collect(),
+            CompiledRuleSet::Fixed(fixed) => fixed
+                .rule_args
+                .iter()
+                .filter_map(|arg| match arg {
+                    MagicFixedRuleRuleArg::InMem { name, .. } => Some(name),
+                    MagicFixedRuleRuleArg::Stored { .. } => None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Move the rules of each stratum to the earliest stratum in which everything they
+/// read is already complete, so that independent strata are evaluated together, and
+/// their rules concurrently. Rules of a stratum that read each other move together,
+/// and the entry rule stays in the last stratum. Emptied strata are kept, as the store
+/// lifetimes refer to the strata by their indices.
+fn hoist_independent_rules(mut strata: Vec<CompiledProgram>) -> Vec<CompiledProgram> {
+    let mut placed: BTreeMap<MagicSymbol, usize> = BTreeMap::new();
+    for idx in 0..strata.len() {
+        let mut prog = std::mem::take(&mut strata[idx]);
+        let deps: BTreeMap<MagicSymbol, BTreeSet<MagicSymbol>> = prog
+            .iter()
+            .map(|(name, ruleset)| {
+                (
+                    name.clone(),
+                    ruleset.dependencies().into_iter().cloned().collect(),
+                )
+            })
+            .collect();
+        let mut neighbours: BTreeMap<&MagicSymbol, BTreeSet<&MagicSymbol>> = BTreeMap::new();
+        for (name, name_deps) in &deps {
+            neighbours.entry(name).or_default();
+            for dep in name_deps {
+                if deps.contains_key(dep) {
+                    neighbours.entry(name).or_default().insert(dep);
+                    neighbours.entry(dep).or_default().insert(name);
+                }
+            }
+        }
+        let mut visited: BTreeSet<&MagicSymbol> = BTreeSet::new();
+        for start in deps.keys() {
+            if !visited.insert(start) {
+                continue;
+            }
+            let mut component = vec![start];
+            let mut pending = vec![start];
+            while let Some(cur) = pending.pop() {
+                for next in &neighbours[cur] {
+                    if visited.insert(next) {
+                        component.push(next);
+                        pending.push(next);
+                    }
+                }
+            }
+            let target = if component.iter().any(|name| name.is_prog_entry()) {
+                idx
+            } else {
+                component
+                    .iter()
+                    .flat_map(|name| deps[*name].iter())
+                    .filter(|dep| !deps.contains_key(*dep))
+                    .map(|dep| placed.get(dep).map_or(idx, |at| at + 1))
+                    .max()
+                    .unwrap_or(0)
+                    .min(idx)
+            };
+            for name in component {
+                let ruleset = prog.remove(name).unwrap();
+                placed.insert(name.clone(), target);
+                if target == idx {
+                    prog.insert(name.clone(), ruleset);
+                } else {
+                    strata[target].insert(name.clone(), ruleset);
+                }
+            }
+        }
+        strata[idx].append(&mut prog);
+    }
+    strata
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Requested rule {0} not found")]
 #[diagnostic(code(eval::rule_not_found))]
@@ -101,6 +193,49 @@ struct RuleNotFound(String, #[label] SourceSpan);
 #[diagnostic(help("Required arity: {1}, number of arguments given: {2}"))]
 struct ArityMismatch(String, usize, usize, #[label] SourceSpan);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Virtual relation {0} does not support time travel")]
+#[diagnostic(code(eval::virtual_relation_time_travel))]
+struct VirtualRelationTimeTravel(String, #[label] SourceSpan);
+
+/// The variables for joining a virtual relation with the atoms before it in a rule body:
+/// the variables already bound, the fresh ones bound to them, and those of the relation.
+fn virtual_relation_vars(
+    relation: &Arc<dyn VirtualRelation>,
+    rel_app: &MagicRelationApplyAtom,
+    seen_variables: &mut BTreeSet<Symbol>,
+    gen_symb: &mut impl FnMut(SourceSpan) -> Symbol,
+) -> Result<(Vec<Symbol>, Vec<Symbol>, Vec<Symbol>)> {
+    ensure!(
+        rel_app.valid_at.is_none(),
+        VirtualRelationTimeTravel(rel_app.name.to_string(), rel_app.span)
+    );
+    ensure!(
+        relation.arity() == rel_app.args.len(),
+        ArityMismatch(
+            rel_app.name.to_string(),
+            relation.arity(),
+            rel_app.args.len(),
+            rel_app.span
+        )
+    );
+    let mut prev_joiner_vars = vec![];
+    let mut right_joiner_vars = vec![];
+    let mut right_vars = vec![];
+    for var in &rel_app.args {
+        if seen_variables.contains(var) {
+            prev_joiner_vars.push(var.clone());
+            let rk = gen_symb(var.span);
+            right_vars.push(rk.clone());
+            right_joiner_vars.push(rk);
+        } else {
+            seen_variables.insert(var.clone());
+            right_vars.push(var.clone());
+        }
+    }
+    Ok((prev_joiner_vars, right_joiner_vars, right_vars))
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) enum IndexPositionUse {
     Join,
@@ -112,6 +247,9 @@ impl<'a> SessionTx<'a> {
     pub(crate) fn stratified_magic_compile(
         &mut self,
         prog: StratifiedMagicProgram,
+        experimental: ExperimentalFeatures,
+        hints: &QueryHints,
+        as_of_tx: Option<ValidityTs>,
     ) -> Result<Vec<CompiledProgram>> {
         let mut store_arities: BTreeMap<MagicSymbol, usize> = Default::default();
 
@@ -136,12 +274,15 @@ impl<'a> SessionTx<'a> {
                                 for rule in body.iter() {
                                     let header = &rule.head;
                                     let mut relation =
-                                        self.compile_magic_rule_body(rule, &k, &store_arities, header)?;
+                                        self.compile_magic_rule_body(rule, &k, &store_arities, header, hints, as_of_tx)?;
                                     relation.fill_binding_indices_and_compile().with_context(|| {
                                         format!(
                                             "error encountered when filling binding indices for {relation:#?}"
                                         )
                                     })?;
+                                    if experimental.hash_join {
+                                        relation.use_hash_joins();
+                                    }
                                     collected.push(CompiledRule {
                                         aggr: rule.aggr.clone(),
                                         relation,
@@ -159,7 +300,7 @@ impl<'a> SessionTx<'a> {
                     .try_collect()
             })
             .try_collect()?;
-        Ok(compiled)
+        Ok(hoist_independent_rules(compiled))
     }
     pub(crate) fn compile_magic_rule_body(
         &mut self,
@@ -167,6 +308,8 @@ impl<'a> SessionTx<'a> {
         rule_name: &MagicSymbol,
         store_arities: &BTreeMap<MagicSymbol, usize>,
         ret_vars: &[Symbol],
+        hints: &QueryHints,
+        as_of_tx: Option<ValidityTs>,
     ) -> Result<RelAlgebra> {
         let mut ret = RelAlgebra::unit(rule_name.symbol().span);
         let mut seen_variables = BTreeSet::new();
@@ -217,6 +360,23 @@ impl<'a> SessionTx<'a> {
                     ret = ret.join(right, prev_joiner_vars, right_joiner_vars, rule_app.span);
                 }
                 MagicAtom::Relation(rel_app) => {
+                    if let Some(relation) = self.get_virtual_relation(&rel_app.name) {
+                        let (prev_joiner_vars, right_joiner_vars, right_vars) =
+                            virtual_relation_vars(
+                                &relation,
+                                rel_app,
+                                &mut seen_variables,
+                                &mut gen_symb,
+                            )?;
+                        let right = RelAlgebra::virtual_relation(
+                            right_vars,
+                            rel_app.name.clone(),
+                            relation,
+                            rel_app.span,
+                        );
+                        ret = ret.join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
+                        continue;
+                    }
                     let store = self.get_relation(&rel_app.name, false)?;
                     if store.access_level < AccessLevel::ReadOnly {
                         bail!(InsufficientAccessLevel(
@@ -267,8 +427,32 @@ impl<'a> SessionTx<'a> {
                         }
                     }
 
-                    let chosen_index =
-                        store.choose_index(&join_indices, rel_app.valid_at.is_some());
+                    if let Some(as_of_tx) = as_of_tx {
+                        if let Some(history) = self.tx_history(&store)? {
+                            // the transaction time of the history is not bound to anything
+                            right_vars.insert(store.metadata.keys.len(), gen_symb(rel_app.span));
+                            let right = RelAlgebra::tx_history(
+                                right_vars,
+                                history,
+                                rel_app.span,
+                                as_of_tx,
+                                rel_app.valid_at,
+                            )?;
+                            debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
+                            ret =
+                                ret.join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
+                            continue;
+                        }
+                    }
+
+                    let chosen_index = match hints.use_index.get(&store.name) {
+                        Some(index) => Some(store.hinted_index(
+                            index,
+                            &join_indices,
+                            rel_app.valid_at.is_some(),
+                        )?),
+                        None => store.choose_index(&join_indices, rel_app.valid_at.is_some()),
+                    };
 
                     match chosen_index {
                         None => {
@@ -412,7 +596,31 @@ impl<'a> SessionTx<'a> {
                     ret = ret.neg_join(right, prev_joiner_vars, right_joiner_vars, rule_app.span);
                 }
                 MagicAtom::NegatedRelation(rel_app) => {
+                    if let Some(relation) = self.get_virtual_relation(&rel_app.name) {
+                        let (prev_joiner_vars, right_joiner_vars, right_vars) =
+                            virtual_relation_vars(
+                                &relation,
+                                rel_app,
+                                &mut seen_variables,
+                                &mut gen_symb,
+                            )?;
+                        let right = RelAlgebra::virtual_relation(
+                            right_vars,
+                            rel_app.name.clone(),
+                            relation,
+                            rel_app.span,
+                        );
+                        ret =
+                            ret.neg_join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
+                        continue;
+                    }
                     let store = self.get_relation(&rel_app.name, false)?;
+                    if as_of_tx.is_some() && store.tx_history.is_some() {
+                        bail!(
+                            "Negation of relation {} tracking transaction time is not supported with `:as_of_tx`",
+                            store.name
+                        );
+                    }
                     ensure!(
                         store.arity() == rel_app.args.len(),
                         ArityMismatch(
@@ -453,8 +661,14 @@ impl<'a> SessionTx<'a> {
                         }
                     }
 
-                    let chosen_index =
-                        store.choose_index(&join_indices, rel_app.valid_at.is_some());
+                    let chosen_index = match hints.use_index.get(&store.name) {
+                        Some(index) => Some(store.hinted_index(
+                            index,
+                            &join_indices,
+                            rel_app.valid_at.is_some(),
+                        )?),
+                        None => store.choose_index(&join_indices, rel_app.valid_at.is_some()),
+                    };
 
                     match chosen_index {
                         None | Some((_, _, true)) => {
@@ -599,6 +813,40 @@ impl<'a> SessionTx<'a> {
                         ret = ret.filter(Expr::build_and(post_filters, s.span))?;
                     }
                 }
+                MagicAtom::GeoSearch(s) => {
+                    debug_assert!(
+                        seen_variables.contains(&s.query),
+                        "Geo search query must be bound"
+                    );
+                    let mut own_bindings = vec![];
+                    let mut post_filters = vec![];
+                    for var in s.all_bindings() {
+                        if seen_variables.contains(var) {
+                            let rk = gen_symb(var.span);
+                            post_filters.push(Expr::build_equate(
+                                vec![
+                                    Expr::Binding {
+                                        var: var.clone(),
+                                        tuple_pos: None,
+                                    },
+                                    Expr::Binding {
+                                        var: rk.clone(),
+                                        tuple_pos: None,
+                                    },
+                                ],
+                                var.span,
+                            ));
+                            own_bindings.push(rk);
+                        } else {
+                            seen_variables.insert(var.clone());
+                            own_bindings.push(var.clone());
+                        }
+                    }
+                    ret = ret.geo_search(s.clone(), own_bindings)?;
+                    if !post_filters.is_empty() {
+                        ret = ret.filter(Expr::build_and(post_filters, s.span))?;
+                    }
+                }
                 MagicAtom::Unification(u) => {
                     if seen_variables.contains(&u.binding) {
                         let expr = if u.one_many_unif {
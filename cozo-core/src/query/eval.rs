@@ -17,6 +17,7 @@ use miette::Result;
 use rayon::prelude::*;
 
 use crate::data::aggr::Aggregation;
+use crate::data::functions::{derive_seed, seed_rng};
 use crate::data::program::{MagicSymbol, NoEntryError};
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::tuple::Tuple;
@@ -27,6 +28,7 @@ use crate::query::compile::{
     AggrKind, CompiledProgram, CompiledRule, CompiledRuleSet, ContainedRuleMultiplicity,
 };
 use crate::runtime::db::Poison;
+use crate::runtime::spans::{span, Span};
 use crate::runtime::temp_store::{EpochStore, MeetAggrStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
 
@@ -68,6 +70,7 @@ impl<'a> SessionTx<'a> {
         store_lifetimes: BTreeMap<MagicSymbol, usize>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
+        seed: Option<u64>,
         poison: Poison,
     ) -> Result<(EpochStore, bool)> {
         let mut stores: BTreeMap<MagicSymbol, EpochStore> = BTreeMap::new();
@@ -95,11 +98,13 @@ impl<'a> SessionTx<'a> {
                 stores.insert(rule_name.clone(), store);
             }
             debug!("stratum {}", stratum);
+            let _span = span!("cozo.stratum", stratum, rules = cur_prog.len()).entered();
             early_return = self.semi_naive_magic_evaluate(
                 cur_prog,
                 &mut stores,
                 total_num_to_take,
                 num_to_skip,
+                seed,
                 poison.clone(),
             )?;
         }
@@ -116,6 +121,7 @@ impl<'a> SessionTx<'a> {
         stores: &mut BTreeMap<MagicSymbol, EpochStore>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
+        seed: Option<u64>,
         poison: Poison,
     ) -> Result<bool> {
         let limiter = QueryLimiter {
@@ -125,6 +131,8 @@ impl<'a> SessionTx<'a> {
         };
 
         let used_limiter: AtomicBool = false.into();
+        // rules may run on other threads, where the stratum is not the current span
+        let stratum_span = Span::current();
 
         for epoch in 0u32.. {
             debug!("epoch {}", epoch);
@@ -133,6 +141,10 @@ impl<'a> SessionTx<'a> {
             if epoch == 0 {
                 #[allow(clippy::needless_borrow)]
                 let execution = |(k, compiled_ruleset): (_, &CompiledRuleSet)| -> Result<_> {
+                    // rules may run on any thread, so each gets a stream of its own
+                    let _rng =
+                        seed_rng(seed.map(|seed| derive_seed(seed, &format!("{k:?}/{epoch}"))));
+                    let _id_gens = self.id_generators.install();
                     let new_store = match compiled_ruleset {
                         CompiledRuleSet::Rules(ruleset) => match compiled_ruleset.aggr_kind() {
                             AggrKind::None => {
@@ -174,8 +186,14 @@ impl<'a> SessionTx<'a> {
                                 manifest: &fixed,
                                 stores: borrowed_stores,
                                 tx: self,
+                                rng_seed: seed.map(|seed| derive_seed(seed, &format!("{k:?}"))),
                             };
-                            fixed_impl.run(payload, &mut out, poison.clone())?;
+                            stratum_span.in_scope(|| {
+                                let _span =
+                                    span!("cozo.fixed_rule", rule = %fixed.fixed_handle.name)
+                                        .entered();
+                                fixed_impl.run(payload, &mut out, poison.clone())
+                            })?;
                             out.wrap()
                         }
                     };
@@ -217,6 +235,10 @@ impl<'a> SessionTx<'a> {
                 // Follow up epoch > 0
                 #[allow(clippy::needless_borrow)]
                 let execution = |(k, compiled_ruleset): (_, &CompiledRuleSet)| -> Result<_> {
+                    // rules may run on any thread, so each gets a stream of its own
+                    let _rng =
+                        seed_rng(seed.map(|seed| derive_seed(seed, &format!("{k:?}/{epoch}"))));
+                    let _id_gens = self.id_generators.install();
                     let new_store = match compiled_ruleset {
                         CompiledRuleSet::Rules(ruleset) => {
                             match compiled_ruleset.aggr_kind() {
@@ -290,6 +312,7 @@ impl<'a> SessionTx<'a> {
             }
             let mut changed = false;
             for (k, new_store) in to_merge {
+                poison.track_memory(|| new_store.approx_bytes())?;
                 let old_store = stores.get_mut(k).unwrap();
                 old_store.merge_in(new_store)?;
                 trace!("delta for {}: {}", k, old_store.has_delta());
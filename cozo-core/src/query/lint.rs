@@ -0,0 +1,349 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Diagnostics for programs that compile but are probably not what the user meant.
+//! Used by the `::check` system op.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+
+use crate::data::expr::Expr;
+use crate::data::functions::OP_GEN_ID;
+use crate::data::program::{FixedRuleArg, InputAtom, InputInlineRulesOrFixed, InputProgram};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::query::graph::{reachable_components, strongly_connected_components, Graph};
+
+/// A problem that does not prevent the program from running.
+#[derive(Debug)]
+pub(crate) struct LintWarning {
+    pub(crate) rule: Symbol,
+    pub(crate) code: &'static str,
+    pub(crate) message: String,
+    pub(crate) span: SourceSpan,
+}
+
+impl InputAtom {
+    /// Variables that are bound whenever the atom matches.
+    fn positive_bindings(&self, coll: &mut BTreeSet<Symbol>) {
+        let mut bind = |ex: &Expr| {
+            if let Expr::Binding { var, .. } = ex {
+                coll.insert(var.clone());
+            }
+        };
+        match self {
+            InputAtom::Rule { inner } => inner.args.iter().for_each(bind),
+            InputAtom::Relation { inner } => inner.args.iter().for_each(bind),
+            InputAtom::NamedFieldRelation { inner } => inner.args.values().for_each(bind),
+            InputAtom::Search { inner } => {
+                inner.bindings.values().for_each(&mut bind);
+                inner
+                    .parameters
+                    .iter()
+                    .filter(|(k, _)| k.starts_with("bind_"))
+                    .for_each(|(_, v)| bind(v));
+            }
+            InputAtom::Unification { inner } => {
+                coll.insert(inner.binding.clone());
+            }
+            InputAtom::Path { inner } => {
+                coll.insert(inner.from.clone());
+                coll.insert(inner.to.clone());
+            }
+            InputAtom::Conjunction { inner, .. } => {
+                for atom in inner {
+                    atom.positive_bindings(coll);
+                }
+            }
+            InputAtom::Disjunction { inner, .. } => {
+                // only those bound in every branch
+                let mut branches = inner.iter().map(|atom| {
+                    let mut branch = BTreeSet::new();
+                    atom.positive_bindings(&mut branch);
+                    branch
+                });
+                if let Some(first) = branches.next() {
+                    let common = branches.fold(first, |acc, b| &acc & &b);
+                    coll.extend(common);
+                }
+            }
+            InputAtom::Predicate { .. } | InputAtom::Negation { .. } => {}
+        }
+    }
+    fn collect_rule_refs<'a>(&'a self, coll: &mut Vec<&'a Symbol>) {
+        match self {
+            InputAtom::Rule { inner } => coll.push(&inner.name),
+            InputAtom::Negation { inner, .. } => inner.collect_rule_refs(coll),
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                for atom in inner {
+                    atom.collect_rule_refs(coll);
+                }
+            }
+            _ => {}
+        }
+    }
+    /// The number of times the atom refers to rules in `group` along a single branch.
+    fn count_refs_to(&self, group: &BTreeSet<&Symbol>) -> usize {
+        match self {
+            InputAtom::Rule { inner } => usize::from(group.contains(&inner.name)),
+            InputAtom::Negation { inner, .. } => inner.count_refs_to(group),
+            InputAtom::Conjunction { inner, .. } => {
+                inner.iter().map(|atom| atom.count_refs_to(group)).sum()
+            }
+            InputAtom::Disjunction { inner, .. } => inner
+                .iter()
+                .map(|atom| atom.count_refs_to(group))
+                .max()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+    /// If the atom can never match, the span and the reason why.
+    fn always_empty(&self, empty_rules: &BTreeSet<&Symbol>) -> Option<(SourceSpan, String)> {
+        match self {
+            InputAtom::Rule { inner } if empty_rules.contains(&inner.name) => Some((
+                inner.span,
+                format!("rule '{}' never contains any rows", inner.name),
+            )),
+            InputAtom::Predicate { inner } => {
+                if has_random_op(inner) || !inner.bindings().is_ok_and(|b| b.is_empty()) {
+                    return None;
+                }
+                match inner.clone().eval_to_const() {
+                    Ok(DataValue::Bool(false) | DataValue::Null) => {
+                        Some((inner.span(), "the condition is always false".to_string()))
+                    }
+                    _ => None,
+                }
+            }
+            InputAtom::Conjunction { inner, .. } => {
+                let mut consts: BTreeMap<&Symbol, &Expr> = BTreeMap::new();
+                for atom in inner {
+                    if let Some(found) = atom.always_empty(empty_rules) {
+                        return Some(found);
+                    }
+                    if let InputAtom::Unification { inner: u } = atom {
+                        if u.one_many_unif || !u.is_const() {
+                            continue;
+                        }
+                        if let Some(Expr::Const { val, .. }) = consts.get(&u.binding) {
+                            if let Expr::Const { val: new_val, .. } = &u.expr {
+                                if val != new_val {
+                                    return Some((
+                                        u.span,
+                                        format!(
+                                            "'{}' is unified with both {:?} and {:?}",
+                                            u.binding, val, new_val
+                                        ),
+                                    ));
+                                }
+                            }
+                        }
+                        consts.insert(&u.binding, &u.expr);
+                    }
+                }
+                None
+            }
+            InputAtom::Disjunction { inner, .. } => {
+                let mut found = None;
+                for atom in inner {
+                    found = Some(atom.always_empty(empty_rules)?);
+                }
+                found
+            }
+            _ => None,
+        }
+    }
+}
+
+fn has_random_op(expr: &Expr) -> bool {
+    match expr {
+        Expr::Apply { op, args, .. } => {
+            op.name.starts_with("OP_RAND")
+                || op.name == OP_GEN_ID.name
+                || args.iter().any(has_random_op)
+        }
+        Expr::UnboundApply { args, .. } => args.iter().any(has_random_op),
+        Expr::Cond { clauses, .. } => clauses
+            .iter()
+            .any(|(cond, val)| has_random_op(cond) || has_random_op(val)),
+        Expr::Binding { .. } | Expr::Const { .. } => false,
+    }
+}
+
+impl InputInlineRulesOrFixed {
+    fn rule_refs(&self) -> Vec<&Symbol> {
+        let mut coll = vec![];
+        match self {
+            InputInlineRulesOrFixed::Rules { rules } => {
+                for rule in rules {
+                    for atom in &rule.body {
+                        atom.collect_rule_refs(&mut coll);
+                    }
+                }
+            }
+            InputInlineRulesOrFixed::Fixed { fixed } => {
+                for arg in &fixed.rule_args {
+                    if let FixedRuleArg::InMem { name, .. } = arg {
+                        coll.push(name);
+                    }
+                }
+            }
+        }
+        coll
+    }
+    fn is_empty_constant(&self) -> bool {
+        match self {
+            InputInlineRulesOrFixed::Fixed { fixed } => {
+                fixed.fixed_handle.name.name == "Constant"
+                    && matches!(
+                        fixed.options.get("data"),
+                        Some(Expr::Const { val: DataValue::List(l), .. }) if l.is_empty()
+                    )
+            }
+            InputInlineRulesOrFixed::Rules { .. } => false,
+        }
+    }
+}
+
+impl InputProgram {
+    /// Look for unused rules, unbound head variables, bodies that can never match
+    /// and non-linear recursion. The program is not evaluated.
+    pub(crate) fn lint(&self) -> Vec<LintWarning> {
+        let mut ret = vec![];
+        let graph: Graph<&Symbol> = self.prog.iter().map(|(k, v)| (k, v.rule_refs())).collect();
+
+        // rules that cannot be reached from the entry are never evaluated
+        let entry = Symbol::new(PROG_ENTRY, SourceSpan(0, 0));
+        if let Some((entry, _)) = self.prog.get_key_value(&entry) {
+            let reachable = reachable_components(&graph, &entry);
+            for (name, rules) in &self.prog {
+                if !reachable.contains(&name) {
+                    ret.push(LintWarning {
+                        rule: name.clone(),
+                        code: "unused_rule",
+                        message: format!("rule '{name}' is not used by the entry rule"),
+                        span: rules.first_span(),
+                    });
+                }
+            }
+        }
+
+        for (name, rules) in &self.prog {
+            if let InputInlineRulesOrFixed::Rules { rules } = rules {
+                for rule in rules {
+                    let mut bound = BTreeSet::new();
+                    for atom in &rule.body {
+                        atom.positive_bindings(&mut bound);
+                    }
+                    for var in rule.head.iter().unique() {
+                        if !bound.contains(var) {
+                            ret.push(LintWarning {
+                                rule: name.clone(),
+                                code: "unbound_head_variable",
+                                message: format!(
+                                    "head variable '{var}' is not bound by the body of the rule"
+                                ),
+                                span: var.span,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        // rules that never produce rows, propagated until nothing changes
+        let mut empty_rules: BTreeSet<&Symbol> = self
+            .prog
+            .iter()
+            .filter(|(_, v)| v.is_empty_constant())
+            .map(|(k, _)| k)
+            .collect();
+        loop {
+            let newly_empty = self
+                .prog
+                .iter()
+                .filter(|(k, _)| !empty_rules.contains(k))
+                .filter(|(_, v)| match v {
+                    InputInlineRulesOrFixed::Rules { rules } => rules.iter().all(|rule| {
+                        InputAtom::Conjunction {
+                            inner: rule.body.clone(),
+                            span: rule.span,
+                        }
+                        .always_empty(&empty_rules)
+                        .is_some()
+                    }),
+                    InputInlineRulesOrFixed::Fixed { .. } => false,
+                })
+                .map(|(k, _)| k)
+                .collect_vec();
+            if newly_empty.is_empty() {
+                break;
+            }
+            empty_rules.extend(newly_empty);
+        }
+        for (name, rules) in &self.prog {
+            if let InputInlineRulesOrFixed::Rules { rules } = rules {
+                for rule in rules {
+                    let body = InputAtom::Conjunction {
+                        inner: rule.body.clone(),
+                        span: rule.span,
+                    };
+                    if let Some((span, reason)) = body.always_empty(&empty_rules) {
+                        ret.push(LintWarning {
+                            rule: name.clone(),
+                            code: "always_empty",
+                            message: format!("the body of the rule can never match: {reason}"),
+                            span,
+                        });
+                    }
+                }
+            }
+        }
+
+        // recursion through more than one atom joins every delta against whole relations
+        if let Ok(sccs) = strongly_connected_components(&graph) {
+            for scc in sccs {
+                let group: BTreeSet<&Symbol> = scc.into_iter().copied().collect();
+                let is_recursive = group.len() > 1
+                    || group
+                        .iter()
+                        .any(|k| graph.get(k).is_some_and(|refs| refs.contains(k)));
+                if !is_recursive {
+                    continue;
+                }
+                for name in &group {
+                    if let Some(InputInlineRulesOrFixed::Rules { rules }) = self.prog.get(*name) {
+                        for rule in rules {
+                            let n = rule
+                                .body
+                                .iter()
+                                .map(|atom| atom.count_refs_to(&group))
+                                .sum::<usize>();
+                            if n > 1 {
+                                ret.push(LintWarning {
+                                    rule: (*name).clone(),
+                                    code: "non_tail_recursion",
+                                    message: format!(
+                                        "the rule refers to its recursive group {n} times, \
+                                        consider rewriting it with a single recursive atom"
+                                    ),
+                                    span: rule.span,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+}
@@ -6,17 +6,20 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use itertools::Itertools;
 use miette::{bail, ensure, Diagnostic, Result, miette};
+use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::expr::Expr;
+use crate::data::functions::OP_EQ;
 use crate::data::program::{
     InputAtom, InputNamedFieldRelationApplyAtom, InputRelationApplyAtom, InputRuleApplyAtom,
     NormalFormAtom, NormalFormRelationApplyAtom, NormalFormRuleApplyAtom, TempSymbGen, Unification,
 };
+use crate::data::symb::Symbol;
 use crate::parse::SourceSpan;
 use crate::query::reorder::UnsafeNegation;
 use crate::runtime::transact::SessionTx;
@@ -63,7 +66,8 @@ impl InputAtom {
             a @ (InputAtom::Rule { .. }
             | InputAtom::NamedFieldRelation { .. }
             | InputAtom::Predicate { .. }
-            | InputAtom::Relation { .. }) => a,
+            | InputAtom::Relation { .. }
+            | InputAtom::Path { .. }) => a,
             InputAtom::Conjunction { inner: args, span } => InputAtom::Conjunction {
                 inner: args
                     .into_iter()
@@ -82,7 +86,8 @@ impl InputAtom {
             InputAtom::Negation { inner: arg, span } => match *arg {
                 a @ (InputAtom::Rule { .. }
                 | InputAtom::NamedFieldRelation { .. }
-                | InputAtom::Relation { .. }) => InputAtom::Negation {
+                | InputAtom::Relation { .. }
+                | InputAtom::Path { .. }) => InputAtom::Negation {
                     inner: Box::new(a),
                     span,
                 },
@@ -135,49 +140,116 @@ impl InputAtom {
         neg_form.do_disjunctive_normal_form(&mut gen, tx)
     }
 
+    /// The relation atom binding the named fields, and the atoms computing the generated
+    /// columns among them from the other columns.
     fn convert_named_field_relation(
         InputNamedFieldRelationApplyAtom {
             name,
             mut args,
+            bind_rest,
             valid_at,
             span,
         }: InputNamedFieldRelationApplyAtom,
         gen: &mut TempSymbGen,
         tx: &SessionTx<'_>,
-    ) -> Result<InputRelationApplyAtom> {
-        let stored = tx.get_relation(&name, false)?;
-        let fields: BTreeSet<_> = stored
-            .metadata
-            .keys
-            .iter()
-            .chain(stored.metadata.non_keys.iter())
-            .map(|col| &col.name)
-            .collect();
+    ) -> Result<(InputRelationApplyAtom, Vec<NormalFormAtom>)> {
+        let mut generated = BTreeMap::new();
+        let columns: Vec<SmartString<LazyCompact>> = match tx.get_virtual_relation(&name) {
+            Some(relation) => relation
+                .schema()
+                .into_iter()
+                .map(SmartString::from)
+                .collect(),
+            None => {
+                let stored = tx.get_relation(&name, false)?;
+                for k in args.keys() {
+                    if let Some(expr) = stored.generated_column(k)? {
+                        generated.insert(k.clone(), expr);
+                    }
+                }
+                stored
+                    .metadata
+                    .keys
+                    .iter()
+                    .chain(stored.metadata.non_keys.iter())
+                    .map(|col| col.name.clone())
+                    .collect()
+            }
+        };
         for k in args.keys() {
             ensure!(
-                fields.contains(k),
-                NamedFieldNotFound(name.to_string(), k.to_string(), span)
+                columns.contains(k) || generated.contains_key(k),
+                ColumnNotInRelation(
+                    name.to_string(),
+                    k.to_string(),
+                    columns.iter().map(|c| format!("'{c}'")).join(", "),
+                    span
+                )
             );
         }
         let mut new_args = vec![];
-        for col_def in stored
-            .metadata
-            .keys
-            .iter()
-            .chain(stored.metadata.non_keys.iter())
-        {
-            let arg = args.remove(&col_def.name).unwrap_or_else(|| Expr::Binding {
-                var: gen.next_ignored(span),
+        for col in &columns {
+            let arg = args.remove(col).unwrap_or_else(|| Expr::Binding {
+                var: if bind_rest {
+                    Symbol::new(col.clone(), span)
+                } else {
+                    gen.next_ignored(span)
+                },
                 tuple_pos: None,
             });
             new_args.push(arg)
         }
-        Ok(InputRelationApplyAtom {
-            name,
-            args: new_args,
-            span,
-            valid_at,
-        })
+        let mut extra = vec![];
+        for (col, mut expr) in generated {
+            let arg = args.remove(&col).unwrap();
+            let mut values = BTreeMap::new();
+            for var in expr.bindings()? {
+                let i = columns.iter().position(|c| *c == var.name).unwrap();
+                let value = match &new_args[i] {
+                    Expr::Binding { var, .. }
+                        if var.is_ignored_symbol() || var.is_generated_ignored_symbol() =>
+                    {
+                        let fresh = Expr::Binding {
+                            var: gen.next(span),
+                            tuple_pos: None,
+                        };
+                        new_args[i] = fresh.clone();
+                        fresh
+                    }
+                    value => value.clone(),
+                };
+                values.insert(var.name, value);
+            }
+            substitute_columns(&mut expr, &values);
+            match arg {
+                Expr::Binding { var, .. } if var.is_ignored_symbol() => {}
+                Expr::Binding { var, .. } => extra.push(NormalFormAtom::Unification(Unification {
+                    binding: var,
+                    expr,
+                    one_many_unif: false,
+                    span,
+                })),
+                arg => {
+                    // kept as a comparison, so that an index on the column can serve it
+                    let mut pred = Expr::Apply {
+                        op: &OP_EQ,
+                        args: [expr, arg].into(),
+                        span,
+                    };
+                    pred.partial_eval()?;
+                    extra.push(NormalFormAtom::Predicate(pred))
+                }
+            }
+        }
+        Ok((
+            InputRelationApplyAtom {
+                name,
+                args: new_args,
+                span,
+                valid_at,
+            },
+            extra,
+        ))
     }
 
     fn do_disjunctive_normal_form(
@@ -211,8 +283,12 @@ impl InputAtom {
             }
             InputAtom::Rule { inner: r } => r.normalize(false, gen),
             InputAtom::NamedFieldRelation { inner } => {
-                let r = Self::convert_named_field_relation(inner, gen, tx)?;
-                r.normalize(false, gen)
+                let (r, extra) = Self::convert_named_field_relation(inner, gen, tx)?;
+                let mut ret = r.normalize(false, gen);
+                for conj in ret.inner.iter_mut() {
+                    conj.0.extend(extra.iter().cloned());
+                }
+                ret
             }
             InputAtom::Relation { inner: v } => v.normalize(false, gen),
             InputAtom::Predicate { inner: mut p } => {
@@ -223,7 +299,12 @@ impl InputAtom {
                 InputAtom::Rule { inner: r } => r.normalize(true, gen),
                 InputAtom::Relation { inner: v } => v.normalize(true, gen),
                 InputAtom::NamedFieldRelation { inner } => {
-                    let r = Self::convert_named_field_relation(inner, gen, tx)?;
+                    let span = inner.span;
+                    let (r, extra) = Self::convert_named_field_relation(inner, gen, tx)?;
+                    ensure!(
+                        extra.is_empty(),
+                        NegatedGeneratedColumn(r.name.to_string(), span)
+                    );
                     r.normalize(true, gen)
                 }
                 _ => unreachable!(),
@@ -232,6 +313,7 @@ impl InputAtom {
                 Disjunction::singlet(NormalFormAtom::Unification(u))
             }
             InputAtom::Search { inner } => inner.normalize(gen, tx)?,
+            InputAtom::Path { .. } => unreachable!("path atoms are expanded into rules"),
         })
     }
 }
@@ -358,6 +440,41 @@ impl InputRelationApplyAtom {
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("stored relation '{0}' does not have field '{1}'")]
+#[diagnostic(code(eval::named_field_not_found))]
+#[diagnostic(help("The columns of '{0}' are {2}"))]
+struct ColumnNotInRelation(String, String, String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("generated columns of stored relation '{0}' cannot be read under negation")]
+#[diagnostic(code(eval::negated_generated_column))]
+#[diagnostic(help("Bind the generated column in a positive atom and negate a comparison instead"))]
+struct NegatedGeneratedColumn(String, #[label] SourceSpan);
+
+/// Replace the columns in the expression of a generated column with their values in the atom.
+fn substitute_columns(expr: &mut Expr, values: &BTreeMap<SmartString<LazyCompact>, Expr>) {
+    match expr {
+        Expr::Binding { var, .. } => {
+            if let Some(value) = values.get(&var.name) {
+                *expr = value.clone();
+            }
+        }
+        Expr::Const { .. } | Expr::UnboundApply { .. } => {}
+        Expr::Apply { args, .. } => {
+            for arg in args.iter_mut() {
+                substitute_columns(arg, values);
+            }
+        }
+        Expr::Cond { clauses, .. } => {
+            for (cond, val) in clauses.iter_mut() {
+                substitute_columns(cond, values);
+                substitute_columns(val, values);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("stored relation '{0}' does not have field '{1}'")]
 #[diagnostic(code(eval::named_field_not_found))]
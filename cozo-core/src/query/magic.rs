@@ -30,7 +30,7 @@ use crate::runtime::transact::SessionTx;
 impl NormalFormProgram {
     pub(crate) fn exempt_aggr_rules_for_magic_sets(&self, exempt_rules: &mut BTreeSet<Symbol>) {
         for (name, rule_set) in self.prog.iter() {
-            if self.disable_magic_rewrite {
+            if self.disable_magic_rewrite || self.no_magic_set.contains(name) {
                 exempt_rules.insert(name.clone());
                 continue;
             }
@@ -188,6 +188,10 @@ fn magic_rewrite_ruleset(
                     seen_bindings.extend(s.all_bindings().cloned());
                     collected_atoms.push(MagicAtom::LshSearch(s));
                 }
+                MagicAtom::GeoSearch(s) => {
+                    seen_bindings.extend(s.all_bindings().cloned());
+                    collected_atoms.push(MagicAtom::GeoSearch(s));
+                }
                 MagicAtom::Rule(r_app) => {
                     if r_app.name.has_bound_adornment() {
                         // we are guaranteed to have a magic rule application
@@ -555,6 +559,14 @@ impl NormalFormAtom {
                 }
                 MagicAtom::LshSearch(s.clone())
             }
+            NormalFormAtom::GeoSearch(s) => {
+                for arg in s.all_bindings() {
+                    if !seen_bindings.contains(arg) {
+                        seen_bindings.insert(arg.clone());
+                    }
+                }
+                MagicAtom::GeoSearch(s.clone())
+            }
 
             NormalFormAtom::Predicate(p) => {
                 // predicate cannot introduce new bindings
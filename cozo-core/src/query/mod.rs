@@ -6,13 +6,17 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub(crate) mod advisor;
 pub(crate) mod compile;
 pub(crate) mod eval;
 pub(crate) mod graph;
+pub(crate) mod lint;
 pub(crate) mod logical;
 pub(crate) mod magic;
+pub(crate) mod path;
 pub(crate) mod ra;
 pub(crate) mod reorder;
 pub(crate) mod sort;
 pub(crate) mod stored;
 pub(crate) mod stratify;
+pub(crate) mod window;
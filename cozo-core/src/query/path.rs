@@ -0,0 +1,308 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Regular path queries: atoms like `a -[:knows+ / :works_at]-> b` are expanded into rules
+//! evaluating the product of the edge relations with the automaton of the pattern.
+//!
+//! The automaton is built with the Glushkov construction, which has one state for each edge
+//! label in the pattern and no empty transitions. Each state becomes a rule holding the pairs
+//! of nodes joined by a path ending in that state, so that semi-naive evaluation and the
+//! magic set rewrite apply to path queries as to any other recursive rules.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::{ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::{
+    InputAtom, InputInlineRule, InputInlineRulesOrFixed, InputPathApplyAtom, InputProgram,
+    InputRelationApplyAtom, InputRuleApplyAtom, PathPattern,
+};
+use crate::data::symb::Symbol;
+use crate::parse::SourceSpan;
+use crate::runtime::transact::SessionTx;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Path pattern {0} matches paths without any edges")]
+#[diagnostic(code(eval::nullable_path_pattern))]
+#[diagnostic(help("Require at least one edge, e.g. with `+` instead of `*`"))]
+struct NullablePathPattern(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation {0} has {1} column(s), but edges of path patterns require two")]
+#[diagnostic(code(eval::path_label_arity))]
+#[diagnostic(help("The first two columns of the relation are the source and the target of edges"))]
+struct PathLabelArity(String, usize, #[label] SourceSpan);
+
+/// The automaton of a pattern: the states are the labels of the pattern, in order.
+#[derive(Default)]
+struct Glushkov<'a> {
+    labels: Vec<(&'a Symbol, bool)>,
+    /// The states that can follow each state.
+    follow: Vec<BTreeSet<usize>>,
+}
+
+/// Whether a pattern matches the empty path, the states it can start in and end in.
+struct Fragment {
+    nullable: bool,
+    first: BTreeSet<usize>,
+    last: BTreeSet<usize>,
+}
+
+impl<'a> Glushkov<'a> {
+    fn build(&mut self, pattern: &'a PathPattern) -> Fragment {
+        match pattern {
+            PathPattern::Label { relation, inverse } => {
+                let state = self.labels.len();
+                self.labels.push((relation, *inverse));
+                self.follow.push(BTreeSet::new());
+                Fragment {
+                    nullable: false,
+                    first: BTreeSet::from([state]),
+                    last: BTreeSet::from([state]),
+                }
+            }
+            PathPattern::Sequence(inner) => {
+                let mut ret = Fragment {
+                    nullable: true,
+                    first: BTreeSet::new(),
+                    last: BTreeSet::new(),
+                };
+                for p in inner {
+                    let next = self.build(p);
+                    for s in &ret.last {
+                        self.follow[*s].extend(next.first.iter().copied());
+                    }
+                    if ret.nullable {
+                        ret.first.extend(next.first.iter().copied());
+                    }
+                    if !next.nullable {
+                        ret.last.clear();
+                    }
+                    ret.last.extend(next.last);
+                    ret.nullable = ret.nullable && next.nullable;
+                }
+                ret
+            }
+            PathPattern::Alternative(inner) => {
+                let mut ret = Fragment {
+                    nullable: false,
+                    first: BTreeSet::new(),
+                    last: BTreeSet::new(),
+                };
+                for p in inner {
+                    let next = self.build(p);
+                    ret.nullable = ret.nullable || next.nullable;
+                    ret.first.extend(next.first);
+                    ret.last.extend(next.last);
+                }
+                ret
+            }
+            PathPattern::OneOrMore(inner) | PathPattern::ZeroOrMore(inner) => {
+                let mut ret = self.build(inner);
+                for s in &ret.last {
+                    self.follow[*s].extend(ret.first.iter().copied());
+                }
+                if matches!(pattern, PathPattern::ZeroOrMore(_)) {
+                    ret.nullable = true;
+                }
+                ret
+            }
+            PathPattern::Optional(inner) => {
+                let mut ret = self.build(inner);
+                ret.nullable = true;
+                ret
+            }
+        }
+    }
+}
+
+fn binding(name: &str, span: SourceSpan) -> Expr {
+    Expr::Binding {
+        var: Symbol::new(name, span),
+        tuple_pos: None,
+    }
+}
+
+fn rule_apply(name: &Symbol, from: &str, to: &str, span: SourceSpan) -> InputAtom {
+    InputAtom::Rule {
+        inner: InputRuleApplyAtom {
+            name: name.clone(),
+            args: vec![binding(from, span), binding(to, span)],
+            span,
+        },
+    }
+}
+
+struct PathExpander<'a, 't> {
+    tx: &'a SessionTx<'t>,
+    arities: BTreeMap<Symbol, usize>,
+    rules: Vec<(Symbol, InputInlineRule)>,
+    n_paths: usize,
+}
+
+impl PathExpander<'_, '_> {
+    /// The edge from `from` to `to` in the relation, with the other columns ignored.
+    fn edge(
+        &mut self,
+        relation: &Symbol,
+        inverse: bool,
+        from: &str,
+        to: &str,
+        span: SourceSpan,
+    ) -> Result<InputAtom> {
+        let arity = match self.arities.get(relation) {
+            Some(arity) => *arity,
+            None => {
+                let handle = self.tx.get_relation(relation, false)?;
+                let arity = handle.metadata.keys.len() + handle.metadata.non_keys.len();
+                self.arities.insert(relation.clone(), arity);
+                arity
+            }
+        };
+        ensure!(
+            arity >= 2,
+            PathLabelArity(relation.to_string(), arity, relation.span)
+        );
+        let (src, dst) = if inverse { (to, from) } else { (from, to) };
+        let mut args = vec![binding(src, span), binding(dst, span)];
+        args.extend((2..arity).map(|_| binding("_", span)));
+        Ok(InputAtom::Relation {
+            inner: InputRelationApplyAtom {
+                name: relation.clone(),
+                args,
+                valid_at: None,
+                span,
+            },
+        })
+    }
+
+    /// Generate the rules of the automaton of the path, returning the rule
+    /// holding the pairs of nodes joined by the path.
+    fn expand(&mut self, path: &InputPathApplyAtom) -> Result<Symbol> {
+        let span = path.span;
+        let mut automaton = Glushkov::default();
+        let fragment = automaton.build(&path.pattern);
+        ensure!(
+            !fragment.nullable,
+            NullablePathPattern(path.pattern.to_string(), span)
+        );
+        // generated names cannot clash with those of the program, which cannot start with `~`
+        let prefix = format!("~path{}", self.n_paths);
+        self.n_paths += 1;
+        let states = (0..automaton.labels.len())
+            .map(|i| Symbol::new(format!("{prefix}_{i}"), span))
+            .collect_vec();
+        let head = vec![Symbol::new("src", span), Symbol::new("dst", span)];
+        let mut rules = vec![];
+        for (i, (relation, inverse)) in automaton.labels.iter().enumerate() {
+            if fragment.first.contains(&i) {
+                rules.push((
+                    states[i].clone(),
+                    vec![self.edge(relation, *inverse, "src", "dst", span)?],
+                ));
+            }
+            for (j, follow) in automaton.follow.iter().enumerate() {
+                if follow.contains(&i) {
+                    rules.push((
+                        states[i].clone(),
+                        vec![
+                            rule_apply(&states[j], "src", "mid", span),
+                            self.edge(relation, *inverse, "mid", "dst", span)?,
+                        ],
+                    ));
+                }
+            }
+        }
+        let accept = Symbol::new(prefix, span);
+        for i in &fragment.last {
+            rules.push((
+                accept.clone(),
+                vec![rule_apply(&states[*i], "src", "dst", span)],
+            ));
+        }
+        for (name, body) in rules {
+            self.rules.push((
+                name,
+                InputInlineRule {
+                    head: head.clone(),
+                    aggr: vec![None, None],
+                    body,
+                    span,
+                },
+            ));
+        }
+        Ok(accept)
+    }
+
+    fn expand_atom(&mut self, atom: &mut InputAtom) -> Result<()> {
+        match atom {
+            InputAtom::Path { inner } => {
+                let accept = self.expand(inner)?;
+                let replacement = InputAtom::Rule {
+                    inner: InputRuleApplyAtom {
+                        name: accept,
+                        args: vec![
+                            Expr::Binding {
+                                var: inner.from.clone(),
+                                tuple_pos: None,
+                            },
+                            Expr::Binding {
+                                var: inner.to.clone(),
+                                tuple_pos: None,
+                            },
+                        ],
+                        span: inner.span,
+                    },
+                };
+                *atom = replacement;
+            }
+            InputAtom::Negation { inner, .. } => self.expand_atom(inner)?,
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                for a in inner {
+                    self.expand_atom(a)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl InputProgram {
+    /// Replace the path atoms in the rules with applications of generated rules.
+    pub(crate) fn expand_paths(&mut self, tx: &SessionTx<'_>) -> Result<()> {
+        let mut expander = PathExpander {
+            tx,
+            arities: BTreeMap::new(),
+            rules: vec![],
+            n_paths: 0,
+        };
+        for rules_or_fixed in self.prog.values_mut() {
+            if let InputInlineRulesOrFixed::Rules { rules } = rules_or_fixed {
+                for rule in rules {
+                    for atom in rule.body.iter_mut() {
+                        expander.expand_atom(atom)?;
+                    }
+                }
+            }
+        }
+        for (name, rule) in expander.rules {
+            match self.prog.get_mut(&name) {
+                Some(InputInlineRulesOrFixed::Rules { rules }) => rules.push(rule),
+                _ => {
+                    self.prog
+                        .insert(name, InputInlineRulesOrFixed::Rules { rules: vec![rule] });
+                }
+            }
+        }
+        Ok(())
+    }
+}
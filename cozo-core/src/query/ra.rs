@@ -6,29 +6,41 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::fmt::{Debug, Formatter, Write};
+use std::hash::{Hash, Hasher};
 use std::iter;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
 use either::{Left, Right};
 use itertools::Itertools;
 use log::{debug, error};
 use miette::{bail, Diagnostic, Result};
-use smartstring::SmartString;
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::expr::{compute_bounds, eval_bytecode, eval_bytecode_pred, Bytecode, Expr};
+use crate::data::expr::{
+    compute_bounds, eval_bytecode, eval_bytecode_pred, eval_bytecode_pred_batch, Bytecode, Expr,
+};
+use crate::data::functions::OP_EQ;
 use crate::data::program::{FtsSearch, HnswSearch, MagicSymbol};
 use crate::data::relation::{ColType, NullableColType};
 use crate::data::symb::Symbol;
 use crate::data::tuple::{Tuple, TupleIter};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::SourceSpan;
+use crate::runtime::geo_index::GeoSearch;
 use crate::runtime::minhash_lsh::LshSearch;
 use crate::runtime::relation::RelationHandle;
-use crate::runtime::temp_store::EpochStore;
+use crate::runtime::temp_store::{approx_tuple_bytes, EpochStore};
 use crate::runtime::transact::SessionTx;
-use crate::utils::swap_option_result;
+use crate::runtime::tx_time::valid_versions;
+use crate::runtime::virtual_relation::{VirtualRelation, VirtualRows};
+use crate::utils::{swap_option_result, TempCollector};
 
 pub(crate) enum RelAlgebra {
     Fixed(InlineFixedRA),
@@ -43,6 +55,8 @@ pub(crate) enum RelAlgebra {
     HnswSearch(HnswSearchRA),
     FtsSearch(FtsSearchRA),
     LshSearch(LshSearchRA),
+    GeoSearch(GeoSearchRA),
+    Virtual(VirtualRA),
 }
 
 impl RelAlgebra {
@@ -60,6 +74,8 @@ impl RelAlgebra {
             RelAlgebra::HnswSearch(i) => i.hnsw_search.span,
             RelAlgebra::FtsSearch(i) => i.fts_search.span,
             RelAlgebra::LshSearch(i) => i.lsh_search.span,
+            RelAlgebra::GeoSearch(i) => i.geo_search.span,
+            RelAlgebra::Virtual(i) => i.span,
         }
     }
 }
@@ -299,6 +315,17 @@ impl Debug for RelAlgebra {
                 .field(&bindings)
                 .field(&s.lsh_search.idx_handle.name)
                 .finish(),
+            RelAlgebra::GeoSearch(s) => f
+                .debug_tuple("GeoSearch")
+                .field(&bindings)
+                .field(&s.geo_search.idx_handle.name)
+                .finish(),
+            RelAlgebra::Virtual(r) => f
+                .debug_tuple("Virtual")
+                .field(&bindings)
+                .field(&r.name)
+                .field(&r.filters)
+                .finish(),
             RelAlgebra::StoredWithValidity(r) => f
                 .debug_tuple("StoredWithValidity")
                 .field(&bindings)
@@ -374,9 +401,15 @@ impl RelAlgebra {
             RelAlgebra::LshSearch(s) => {
                 s.fill_binding_indices_and_compile()?;
             }
+            RelAlgebra::GeoSearch(s) => {
+                s.fill_binding_indices_and_compile()?;
+            }
             RelAlgebra::StoredWithValidity(v) => {
                 v.fill_binding_indices_and_compile()?;
             }
+            RelAlgebra::Virtual(v) => {
+                v.fill_binding_indices_and_compile()?;
+            }
             RelAlgebra::Reorder(r) => {
                 r.relation.fill_binding_indices_and_compile()?;
             }
@@ -398,6 +431,29 @@ impl RelAlgebra {
         }
         Ok(())
     }
+    /// Make the joins that would materialize their right side use hash tables instead.
+    pub(crate) fn use_hash_joins(&mut self) {
+        match self {
+            RelAlgebra::Fixed(_)
+            | RelAlgebra::TempStore(_)
+            | RelAlgebra::Stored(_)
+            | RelAlgebra::StoredWithValidity(_)
+            | RelAlgebra::Virtual(_) => {}
+            RelAlgebra::HnswSearch(s) => s.parent.use_hash_joins(),
+            RelAlgebra::FtsSearch(s) => s.parent.use_hash_joins(),
+            RelAlgebra::LshSearch(s) => s.parent.use_hash_joins(),
+            RelAlgebra::GeoSearch(s) => s.parent.use_hash_joins(),
+            RelAlgebra::Reorder(r) => r.relation.use_hash_joins(),
+            RelAlgebra::Filter(f) => f.parent.use_hash_joins(),
+            RelAlgebra::Unification(u) => u.parent.use_hash_joins(),
+            RelAlgebra::NegJoin(r) => r.left.use_hash_joins(),
+            RelAlgebra::Join(r) => {
+                r.hash_join = true;
+                r.left.use_hash_joins();
+                r.right.use_hash_joins();
+            }
+        }
+    }
     pub(crate) fn unit(span: SourceSpan) -> Self {
         Self::Fixed(InlineFixedRA::unit(span))
     }
@@ -432,10 +488,12 @@ impl RelAlgebra {
     ) -> Result<Self> {
         match validity {
             None => Ok(Self::Stored(StoredRA {
+                needed: vec![true; bindings.len()],
                 bindings,
                 storage,
                 filters: vec![],
                 filters_bytecodes: vec![],
+                expr_index: None,
                 span,
             })),
             Some(vld) => {
@@ -453,11 +511,59 @@ impl RelAlgebra {
                     filters: vec![],
                     filters_bytecodes: vec![],
                     valid_at: vld,
+                    history_valid_at: None,
                     span,
                 }))
             }
         }
     }
+    /// Scan the transaction time history of a relation as of `as_of_tx`,
+    /// at `validity` if given.
+    pub(crate) fn tx_history(
+        bindings: Vec<Symbol>,
+        history: RelationHandle,
+        span: SourceSpan,
+        as_of_tx: ValidityTs,
+        validity: Option<ValidityTs>,
+    ) -> Result<Self> {
+        if validity.is_some() {
+            let keys = &history.metadata.keys;
+            if keys.len() < 2
+                || keys[keys.len() - 2].typing
+                    != (NullableColType {
+                        coltype: ColType::Validity,
+                        nullable: false,
+                    })
+            {
+                let name = history.name.split(':').next().unwrap_or_default();
+                bail!(InvalidTimeTravelScanning(name.to_string(), span));
+            }
+        }
+        Ok(Self::StoredWithValidity(StoredWithValidityRA {
+            bindings,
+            storage: history,
+            filters: vec![],
+            filters_bytecodes: vec![],
+            valid_at: as_of_tx,
+            history_valid_at: validity,
+            span,
+        }))
+    }
+    pub(crate) fn virtual_relation(
+        bindings: Vec<Symbol>,
+        name: Symbol,
+        relation: Arc<dyn VirtualRelation>,
+        span: SourceSpan,
+    ) -> Self {
+        Self::Virtual(VirtualRA {
+            bindings,
+            name,
+            relation,
+            filters: vec![],
+            filters_bytecodes: vec![],
+            span,
+        })
+    }
     pub(crate) fn reorder(self, new_order: Vec<Symbol>) -> Self {
         Self::Reorder(ReorderRA {
             relation: Box::new(self),
@@ -472,7 +578,8 @@ impl RelAlgebra {
             | RelAlgebra::Unification(_)
             | RelAlgebra::HnswSearch(_)
             | RelAlgebra::FtsSearch(_)
-            | RelAlgebra::LshSearch(_)) => {
+            | RelAlgebra::LshSearch(_)
+            | RelAlgebra::GeoSearch(_)) => {
                 let span = filter.span();
                 RelAlgebra::Filter(FilteredRA {
                     parent: Box::new(s),
@@ -519,6 +626,8 @@ impl RelAlgebra {
                 storage,
                 mut filters,
                 filters_bytecodes,
+                needed,
+                expr_index,
                 span,
             }) => {
                 filters.push(filter);
@@ -527,6 +636,8 @@ impl RelAlgebra {
                     storage,
                     filters,
                     filters_bytecodes,
+                    needed,
+                    expr_index,
                     span,
                 })
             }
@@ -537,6 +648,7 @@ impl RelAlgebra {
                 filters_bytecodes: filter_bytecodes,
                 span,
                 valid_at,
+                history_valid_at,
             }) => {
                 filters.push(filter);
                 RelAlgebra::StoredWithValidity(StoredWithValidityRA {
@@ -545,9 +657,14 @@ impl RelAlgebra {
                     filters,
                     span,
                     valid_at,
+                    history_valid_at,
                     filters_bytecodes: filter_bytecodes,
                 })
             }
+            RelAlgebra::Virtual(mut r) => {
+                r.filters.push(filter);
+                RelAlgebra::Virtual(r)
+            }
             RelAlgebra::Join(inner) => {
                 let filters = filter.to_conjunction();
                 let left_bindings: BTreeSet<Symbol> =
@@ -564,7 +681,7 @@ impl RelAlgebra {
                     joiner,
                     to_eliminate,
                     span,
-                    ..
+                    hash_join,
                 } = *inner;
                 for filter in filters {
                     let f_bindings = filter.bindings()?;
@@ -582,6 +699,7 @@ impl RelAlgebra {
                     joiner,
                     to_eliminate,
                     span,
+                    hash_join,
                 }));
                 if !remaining.is_empty() {
                     joined = RelAlgebra::Filter(FilteredRA {
@@ -649,6 +767,18 @@ impl RelAlgebra {
             own_bindings,
         }))
     }
+    pub(crate) fn geo_search(
+        self,
+        geo_search: GeoSearch,
+        own_bindings: Vec<Symbol>,
+    ) -> Result<Self> {
+        Ok(Self::GeoSearch(GeoSearchRA {
+            parent: Box::new(self),
+            geo_search,
+            filter_bytecode: None,
+            own_bindings,
+        }))
+    }
     pub(crate) fn join(
         self,
         right: RelAlgebra,
@@ -665,6 +795,7 @@ impl RelAlgebra {
             },
             to_eliminate: Default::default(),
             span,
+            hash_join: false,
         }))
     }
     pub(crate) fn neg_join(
@@ -848,25 +979,86 @@ fn invert_option_err<T>(v: Result<Option<T>>) -> Option<Result<T>> {
     }
 }
 
+/// Number of tuples filtered together by [filter_iter].
+const FILTER_BATCH_SIZE: usize = 1024;
+
 fn filter_iter(
     filters_bytecodes: Vec<(Vec<Bytecode>, SourceSpan)>,
-    it: impl Iterator<Item = Result<Tuple>>,
+    mut it: impl Iterator<Item = Result<Tuple>>,
 ) -> impl Iterator<Item = Result<Tuple>> {
     let mut stack = vec![];
-    it.filter_map_ok(move |t| -> Option<Result<Tuple>> {
-        for (p, span) in filters_bytecodes.iter() {
-            match eval_bytecode_pred(p, &t, &mut stack, *span) {
-                Ok(false) => return None,
+    let mut out: VecDeque<Result<Tuple>> = VecDeque::new();
+    iter::from_fn(move || loop {
+        if let Some(item) = out.pop_front() {
+            return Some(item);
+        }
+        let mut batch = Vec::with_capacity(FILTER_BATCH_SIZE);
+        let mut upstream_err = None;
+        for item in it.by_ref() {
+            match item {
+                Ok(t) => {
+                    batch.push(t);
+                    if batch.len() == FILTER_BATCH_SIZE {
+                        break;
+                    }
+                }
                 Err(e) => {
-                    debug!("{:?}", t);
-                    return Some(Err(e));
+                    upstream_err = Some(e);
+                    break;
                 }
-                Ok(true) => {}
             }
         }
-        Some(Ok(t))
+        if batch.is_empty() && upstream_err.is_none() {
+            return None;
+        }
+        match filter_batch(&filters_bytecodes, &mut batch, &mut stack) {
+            Ok(()) => out.extend(batch.into_iter().map(Ok)),
+            // rows dropped so far could not have raised an error before the failing
+            // filter, so evaluating the rest row by row gives the row by row result
+            Err(_) => {
+                for t in batch {
+                    match filter_tuple(&filters_bytecodes, &t, &mut stack) {
+                        Ok(true) => out.push_back(Ok(t)),
+                        Ok(false) => {}
+                        Err(e) => {
+                            debug!("{:?}", t);
+                            out.push_back(Err(e))
+                        }
+                    }
+                }
+            }
+        }
+        out.extend(upstream_err.map(Err));
     })
-    .map(flatten_err)
+}
+
+fn filter_batch(
+    filters_bytecodes: &[(Vec<Bytecode>, SourceSpan)],
+    batch: &mut Vec<Tuple>,
+    stack: &mut Vec<DataValue>,
+) -> Result<()> {
+    for (p, span) in filters_bytecodes {
+        if batch.is_empty() {
+            break;
+        }
+        let mask = eval_bytecode_pred_batch(p, batch, stack, *span)?;
+        let mut keep = mask.into_iter();
+        batch.retain(|_| keep.next().unwrap_or(false));
+    }
+    Ok(())
+}
+
+fn filter_tuple(
+    filters_bytecodes: &[(Vec<Bytecode>, SourceSpan)],
+    t: &Tuple,
+    stack: &mut Vec<DataValue>,
+) -> Result<bool> {
+    for (p, span) in filters_bytecodes {
+        if !eval_bytecode_pred(p, t, stack, *span)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
 }
 
 fn get_eliminate_indices(bindings: &[Symbol], eliminate: &BTreeSet<Symbol>) -> BTreeSet<usize> {
@@ -889,9 +1081,22 @@ pub(crate) struct StoredRA {
     pub(crate) storage: RelationHandle,
     pub(crate) filters: Vec<Expr>,
     pub(crate) filters_bytecodes: Vec<(Vec<Bytecode>, SourceSpan)>,
+    /// The columns that need to be decoded when scanning, the others are read as `Null`
+    pub(crate) needed: Vec<bool>,
+    /// An expression index to scan instead of the relation, found by matching the filters
+    pub(crate) expr_index: Option<ExprIndexScan>,
     pub(crate) span: SourceSpan,
 }
 
+/// Scanning an expression index for the rows whose indexed expression equals a value.
+#[derive(Debug)]
+pub(crate) struct ExprIndexScan {
+    pub(crate) index: RelationHandle,
+    /// The positions of the keys of the relation in the tuples of the index
+    pub(crate) key_positions: Vec<usize>,
+    pub(crate) value: DataValue,
+}
+
 #[derive(Debug)]
 pub(crate) struct HnswSearchRA {
     pub(crate) parent: Box<RelAlgebra>,
@@ -973,6 +1178,62 @@ impl LshSearchRA {
     }
 }
 
+#[derive(Debug)]
+pub(crate) struct GeoSearchRA {
+    pub(crate) parent: Box<RelAlgebra>,
+    pub(crate) geo_search: GeoSearch,
+    pub(crate) filter_bytecode: Option<(Vec<Bytecode>, SourceSpan)>,
+    pub(crate) own_bindings: Vec<Symbol>,
+}
+
+impl GeoSearchRA {
+    fn fill_binding_indices_and_compile(&mut self) -> Result<()> {
+        self.parent.fill_binding_indices_and_compile()?;
+        if let Some(filter) = self.geo_search.filter.as_mut() {
+            let bindings: BTreeMap<_, _> = self
+                .own_bindings
+                .iter()
+                .cloned()
+                .enumerate()
+                .map(|(a, b)| (b, a))
+                .collect();
+            filter.fill_binding_indices(&bindings)?;
+            self.filter_bytecode = Some((filter.compile()?, filter.span()));
+        }
+        Ok(())
+    }
+    fn iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+    ) -> Result<TupleIter<'a>> {
+        let bindings = self.parent.bindings_after_eliminate();
+        let bind_idx = bindings
+            .iter()
+            .position(|b| *b == self.geo_search.query)
+            .unwrap_or(usize::MAX);
+        let config = self.geo_search.clone();
+        let filter_code = self.filter_bytecode.clone();
+        let mut stack = vec![];
+
+        let it = self
+            .parent
+            .iter(tx, delta_rule, stores)?
+            .map_ok(move |tuple| -> Result<_> {
+                let res = tx.geo_search(&tuple[bind_idx], &config, &mut stack, &filter_code)?;
+                Ok(res.into_iter().map(move |t| {
+                    let mut r = tuple.clone();
+                    r.extend(t);
+                    r
+                }))
+            })
+            .map(flatten_err)
+            .flatten_ok();
+        Ok(Box::new(it))
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct FtsSearchRA {
     pub(crate) parent: Box<RelAlgebra>,
@@ -1128,6 +1389,9 @@ pub(crate) struct StoredWithValidityRA {
     pub(crate) filters: Vec<Expr>,
     pub(crate) filters_bytecodes: Vec<(Vec<Bytecode>, SourceSpan)>,
     pub(crate) valid_at: ValidityTs,
+    /// For the history of a relation with transaction time, scanned at `valid_at` as the
+    /// transaction time: the valid time to read the relation at, if any.
+    pub(crate) history_valid_at: Option<ValidityTs>,
     pub(crate) span: SourceSpan,
 }
 
@@ -1147,7 +1411,26 @@ impl StoredWithValidityRA {
         Ok(())
     }
     fn iter<'a>(&'a self, tx: &'a SessionTx<'_>) -> Result<TupleIter<'a>> {
-        let it = self.storage.skip_scan_all(tx, self.valid_at);
+        let key_bindings = &self.bindings[..self.storage.metadata.keys.len()];
+        // bounds on the keys could leave out the versions valid at the time
+        let bounds = match self.history_valid_at {
+            None => key_bounds(&self.filters, key_bindings),
+            Some(_) => None,
+        };
+        let it: TupleIter<'a> = match bounds {
+            None => Box::new(self.storage.skip_scan_all(tx, self.valid_at)),
+            Some((l_bound, u_bound)) => Box::new(self.storage.skip_scan_bounded_prefix(
+                tx,
+                &vec![],
+                &l_bound,
+                &u_bound,
+                self.valid_at,
+            )),
+        };
+        let it: TupleIter<'a> = match self.history_valid_at {
+            None => Box::new(it),
+            Some(vld) => Box::new(valid_versions(it, self.storage.metadata.keys.len(), vld)),
+        };
         Ok(if self.filters.is_empty() {
             Box::new(it)
         } else {
@@ -1168,7 +1451,8 @@ impl StoredWithValidityRA {
             .map(|(a, _)| left_join_indices[a])
             .collect_vec();
 
-        let mut skip_range_check = false;
+        // bounds on the keys could leave out the versions valid at the time
+        let mut skip_range_check = self.history_valid_at.is_some();
 
         let it = left_iter
             .map_ok(move |tuple| {
@@ -1213,9 +1497,17 @@ impl StoredWithValidityRA {
                 }
                 skip_range_check = true;
                 let mut stack = vec![];
+                let found_it = self.storage.skip_scan_prefix(tx, &prefix, self.valid_at);
+                let found_it: TupleIter<'a> = match self.history_valid_at {
+                    None => Box::new(found_it),
+                    Some(vld) => Box::new(valid_versions(
+                        found_it,
+                        self.storage.metadata.keys.len(),
+                        vld,
+                    )),
+                };
                 Right(
-                    self.storage
-                        .skip_scan_prefix(tx, &prefix, self.valid_at)
+                    found_it
                         .map(move |res_found| -> Result<Option<Tuple>> {
                             let found = res_found?;
                             for (p, span) in self.filters_bytecodes.iter() {
@@ -1241,6 +1533,18 @@ impl StoredWithValidityRA {
 }
 
 impl StoredRA {
+    pub(crate) fn do_eliminate_temp_vars(&mut self, used: &BTreeSet<Symbol>) -> Result<()> {
+        // keys are always decoded, since they keep the tuples distinct for later deduplication
+        let key_len = self.storage.metadata.keys.len();
+        self.needed = self
+            .bindings
+            .iter()
+            .enumerate()
+            .map(|(i, b)| i < key_len || used.contains(b))
+            .collect();
+        Ok(())
+    }
+
     fn fill_binding_indices_and_compile(&mut self) -> Result<()> {
         let bindings: BTreeMap<_, _> = self
             .bindings
@@ -1253,9 +1557,71 @@ impl StoredRA {
             e.fill_binding_indices(&bindings)?;
             self.filters_bytecodes.push((e.compile()?, e.span()));
         }
+        for (p, _) in &self.filters_bytecodes {
+            for code in p {
+                if let Bytecode::Binding {
+                    tuple_pos: Some(i), ..
+                } = code
+                {
+                    self.needed[*i] = true;
+                }
+            }
+        }
+        self.expr_index = self.find_expr_index()?;
         Ok(())
     }
 
+    /// Looks for a filter equating an indexed expression with a constant. The expressions
+    /// are matched in their canonical form, with the variables replaced by column names.
+    fn find_expr_index(&self) -> Result<Option<ExprIndexScan>> {
+        if self.storage.index_exprs.is_empty() {
+            return Ok(None);
+        }
+        let columns = self
+            .storage
+            .metadata
+            .keys
+            .iter()
+            .chain(self.storage.metadata.non_keys.iter())
+            .map(|col| col.name.clone())
+            .collect_vec();
+        let arity = self.storage.arity();
+        for filter in &self.filters {
+            let args = match filter {
+                Expr::Apply { op, args, .. } if op.name == OP_EQ.name && args.len() == 2 => args,
+                _ => continue,
+            };
+            for (indexed, other) in [(&args[0], &args[1]), (&args[1], &args[0])] {
+                if !other.bindings()?.is_empty() {
+                    continue;
+                }
+                let mut renamed = indexed.clone();
+                if !rename_bindings_to_columns(&mut renamed, &self.bindings, &columns) {
+                    continue;
+                }
+                let text = renamed.to_string();
+                let pos = match self.storage.index_exprs.iter().position(|e| *e == text) {
+                    Some(pos) => pos,
+                    None => continue,
+                };
+                for (index, mapper) in self.storage.indices.values() {
+                    if mapper[0] != arity + pos {
+                        continue;
+                    }
+                    let key_positions = (0..self.storage.metadata.keys.len())
+                        .map(|k| mapper.iter().position(|i| *i == k).unwrap())
+                        .collect_vec();
+                    return Ok(Some(ExprIndexScan {
+                        index: index.clone(),
+                        key_positions,
+                        value: other.clone().eval_to_const()?,
+                    }));
+                }
+            }
+        }
+        Ok(None)
+    }
+
     fn point_lookup_join<'a>(
         &'a self,
         tx: &'a SessionTx<'_>,
@@ -1263,8 +1629,7 @@ impl StoredRA {
         key_len: usize,
         left_to_prefix_indices: Vec<usize>,
         eliminate_indices: BTreeSet<usize>,
-        left_join_indices: Vec<usize>,
-        right_join_indices: Vec<usize>,
+        (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
     ) -> Result<TupleIter<'a>> {
         let mut stack = vec![];
 
@@ -1275,7 +1640,7 @@ impl StoredRA {
                     .map(|i| tuple[*i].clone())
                     .collect_vec();
                 let key = &prefix[0..key_len];
-                match self.storage.get(tx, key)? {
+                match self.storage.get_columns(tx, key, &self.needed)? {
                     None => Ok(None),
                     Some(found) => {
                         for (lk, rk) in left_join_indices.iter().zip(right_join_indices.iter()) {
@@ -1325,8 +1690,7 @@ impl StoredRA {
                 key_len,
                 left_to_prefix_indices,
                 eliminate_indices,
-                left_join_indices,
-                right_join_indices,
+                (left_join_indices, right_join_indices),
             );
         }
 
@@ -1351,7 +1715,13 @@ impl StoredRA {
                     {
                         return Left(
                             self.storage
-                                .scan_bounded_prefix(tx, &prefix, &l_bound, &u_bound)
+                                .scan_bounded_prefix_columns(
+                                    tx,
+                                    &prefix,
+                                    &l_bound,
+                                    &u_bound,
+                                    &self.needed,
+                                )
                                 .map(move |res_found| -> Result<Option<Tuple>> {
                                     let found = res_found?;
                                     for (p, span) in self.filters_bytecodes.iter() {
@@ -1370,7 +1740,7 @@ impl StoredRA {
                 skip_range_check = true;
                 Right(
                     self.storage
-                        .scan_prefix(tx, &prefix)
+                        .scan_prefix_columns(tx, &prefix, &self.needed)
                         .map(move |res_found| -> Result<Option<Tuple>> {
                             let found = res_found?;
                             for (p, span) in self.filters_bytecodes.iter() {
@@ -1497,7 +1867,44 @@ impl StoredRA {
     }
 
     fn iter<'a>(&'a self, tx: &'a SessionTx<'_>) -> Result<TupleIter<'a>> {
-        let it = self.storage.scan_all(tx);
+        let key_bindings = &self.bindings[..self.storage.metadata.keys.len()];
+        let bounds = key_bounds(&self.filters, key_bindings);
+        #[cfg(not(target_arch = "wasm32"))]
+        if !self.storage.partition_bounds.is_empty() && self.expr_index.is_none() {
+            let ranges = self.storage.partition_ranges(bounds.as_ref());
+            if ranges.len() > 1 {
+                return Ok(self.scan_partitions(tx, ranges));
+            }
+        }
+        let needed = &self.needed;
+        let it: TupleIter<'a> = match (bounds, needed.iter().all(|n| *n)) {
+            (None, _) if self.expr_index.is_some() => {
+                let scan = self.expr_index.as_ref().unwrap();
+                Box::new(
+                    scan.index
+                        .scan_prefix(tx, &vec![scan.value.clone()])
+                        .map(move |found| -> Result<Option<Tuple>> {
+                            let found = found?;
+                            let key = scan
+                                .key_positions
+                                .iter()
+                                .map(|p| found[*p].clone())
+                                .collect_vec();
+                            self.storage.get_columns(tx, &key, needed)
+                        })
+                        .filter_map(invert_option_err),
+                )
+            }
+            (None, true) => Box::new(self.storage.scan_all(tx)),
+            (None, false) => Box::new(self.storage.scan_all_columns(tx, needed)),
+            (Some((l, u)), true) => Box::new(self.storage.scan_bounded_prefix(tx, &[], &l, &u)),
+            (Some((l, u)), false) => {
+                Box::new(
+                    self.storage
+                        .scan_bounded_prefix_columns(tx, &[], &l, &u, needed),
+                )
+            }
+        };
         Ok(if self.filters.is_empty() {
             Box::new(it)
         } else {
@@ -1506,7 +1913,90 @@ impl StoredRA {
     }
 }
 
-fn join_is_prefix(right_join_indices: &[usize]) -> bool {
+impl StoredRA {
+    /// Scan the given partitions of the relation and apply the filters, in waves of as many
+    /// partitions as there are threads, each wave in parallel. The rows come out in key order,
+    /// and the partitions of the later waves are not scanned if the consumer stops early.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn scan_partitions<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        ranges: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> TupleIter<'a> {
+        let wave = rayon::current_num_threads().max(1);
+        let waves = ranges
+            .into_iter()
+            .chunks(wave)
+            .into_iter()
+            .map(|chunk| chunk.collect_vec())
+            .collect_vec();
+        Box::new(waves.into_iter().flat_map(move |wave| {
+            let scanned: Vec<Result<Vec<Tuple>>> = wave
+                .par_iter()
+                .map(|(lower, upper)| {
+                    let it = self
+                        .storage
+                        .scan_key_range_columns(tx, lower, upper, &self.needed);
+                    if self.filters.is_empty() {
+                        it.collect()
+                    } else {
+                        filter_iter(self.filters_bytecodes.clone(), it).collect()
+                    }
+                })
+                .collect();
+            scanned.into_iter().flat_map(|part| match part {
+                Ok(rows) => Left(rows.into_iter().map(Ok)),
+                Err(err) => Right(iter::once(Err(err))),
+            })
+        }))
+    }
+}
+
+/// The bounds on the key columns implied by comparisons in the filters, if the first key
+/// column is bounded. Scanning only within them still requires applying the filters.
+pub(crate) fn key_bounds(
+    filters: &[Expr],
+    key_bindings: &[Symbol],
+) -> Option<(Vec<DataValue>, Vec<DataValue>)> {
+    if filters.is_empty() || key_bindings.is_empty() {
+        return None;
+    }
+    let (l_bound, u_bound) = compute_bounds(filters, key_bindings).ok()?;
+    if l_bound[0] == DataValue::Null && u_bound[0] == DataValue::Bot {
+        None
+    } else {
+        Some((l_bound, u_bound))
+    }
+}
+
+/// Replaces the variables of an expression by the columns they are bound to. Fails if
+/// some variable is not bound to a column.
+fn rename_bindings_to_columns(
+    expr: &mut Expr,
+    bindings: &[Symbol],
+    columns: &[SmartString<LazyCompact>],
+) -> bool {
+    match expr {
+        Expr::Binding { var, .. } => match bindings.iter().position(|b| b == var) {
+            Some(i) => {
+                *var = Symbol::new(columns[i].clone(), var.span);
+                true
+            }
+            None => false,
+        },
+        Expr::Const { .. } => true,
+        Expr::Apply { args, .. } => args
+            .iter_mut()
+            .all(|arg| rename_bindings_to_columns(arg, bindings, columns)),
+        Expr::Cond { clauses, .. } => clauses.iter_mut().all(|(cond, val)| {
+            rename_bindings_to_columns(cond, bindings, columns)
+                && rename_bindings_to_columns(val, bindings, columns)
+        }),
+        Expr::UnboundApply { .. } => false,
+    }
+}
+
+pub(crate) fn join_is_prefix(right_join_indices: &[usize]) -> bool {
     // We do not consider partial index match to be "prefix", e.g. [a, u => c]
     // with a, c bound and u unbound is not "prefix", as it is not clear that
     // using prefix scanning in this case will really save us computation.
@@ -1769,6 +2259,167 @@ impl TempStoreRA {
     }
 }
 
+/// Scanning a virtual relation registered on the database.
+pub(crate) struct VirtualRA {
+    pub(crate) bindings: Vec<Symbol>,
+    pub(crate) name: Symbol,
+    pub(crate) relation: Arc<dyn VirtualRelation>,
+    pub(crate) filters: Vec<Expr>,
+    pub(crate) filters_bytecodes: Vec<(Vec<Bytecode>, SourceSpan)>,
+    pub(crate) span: SourceSpan,
+}
+
+impl VirtualRA {
+    fn fill_binding_indices_and_compile(&mut self) -> Result<()> {
+        let bindings: BTreeMap<_, _> = self
+            .bindings
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(a, b)| (b, a))
+            .collect();
+        for e in self.filters.iter_mut() {
+            e.fill_binding_indices(&bindings)?;
+            self.filters_bytecodes.push((e.compile()?, e.span()))
+        }
+        Ok(())
+    }
+    /// Fails on the rows returned by the implementation that do not have one value per column.
+    fn checked<'a>(&'a self, rows: VirtualRows<'a>) -> TupleIter<'a> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Virtual relation {0} returned a row with {2} values, but it has {1} columns")]
+        #[diagnostic(code(eval::virtual_row_arity_mismatch))]
+        struct VirtualRowArityMismatch(String, usize, usize, #[label] SourceSpan);
+
+        Box::new(rows.map(move |row| {
+            let row = row?;
+            if row.len() != self.bindings.len() {
+                bail!(VirtualRowArityMismatch(
+                    self.name.to_string(),
+                    self.bindings.len(),
+                    row.len(),
+                    self.span
+                ));
+            }
+            Ok(row)
+        }))
+    }
+    fn iter(&self) -> Result<TupleIter<'_>> {
+        let it = self.checked(self.relation.scan()?);
+        Ok(if self.filters.is_empty() {
+            it
+        } else {
+            Box::new(filter_iter(self.filters_bytecodes.clone(), it))
+        })
+    }
+    fn prefix_join<'a>(
+        &'a self,
+        left_iter: TupleIter<'a>,
+        (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
+        eliminate_indices: BTreeSet<usize>,
+    ) -> Result<TupleIter<'a>> {
+        let mut right_invert_indices = right_join_indices.iter().enumerate().collect_vec();
+        right_invert_indices.sort_by_key(|(_, b)| **b);
+        let left_to_prefix_indices = right_invert_indices
+            .into_iter()
+            .map(|(a, _)| left_join_indices[a])
+            .collect_vec();
+
+        let it = left_iter
+            .map_ok(move |tuple| -> Result<_> {
+                let prefix = left_to_prefix_indices
+                    .iter()
+                    .map(|i| tuple[*i].clone())
+                    .collect_vec();
+                let mut stack = vec![];
+                let found_it = if prefix.is_empty() {
+                    self.checked(self.relation.scan()?)
+                } else {
+                    self.checked(self.relation.prefix_scan(&prefix)?)
+                };
+                Ok(found_it
+                    .map(move |res_found| -> Result<Option<Tuple>> {
+                        let found = res_found?;
+                        for (p, span) in self.filters_bytecodes.iter() {
+                            if !eval_bytecode_pred(p, &found, &mut stack, *span)? {
+                                return Ok(None);
+                            }
+                        }
+                        let mut ret = tuple.clone();
+                        ret.extend(found);
+                        Ok(Some(ret))
+                    })
+                    .filter_map(swap_option_result))
+            })
+            .map(flatten_err)
+            .flatten_ok()
+            .map(flatten_err);
+        Ok(if eliminate_indices.is_empty() {
+            Box::new(it)
+        } else {
+            Box::new(it.map_ok(move |t| eliminate_from_tuple(t, &eliminate_indices)))
+        })
+    }
+    fn neg_join<'a>(
+        &'a self,
+        left_iter: TupleIter<'a>,
+        (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
+        eliminate_indices: BTreeSet<usize>,
+    ) -> Result<TupleIter<'a>> {
+        if join_is_prefix(&right_join_indices) {
+            let mut right_invert_indices = right_join_indices.iter().enumerate().collect_vec();
+            right_invert_indices.sort_by_key(|(_, b)| **b);
+            let left_to_prefix_indices = right_invert_indices
+                .into_iter()
+                .map(|(a, _)| left_join_indices[a])
+                .collect_vec();
+            Ok(Box::new(
+                left_iter
+                    .map_ok(move |tuple| -> Result<Option<Tuple>> {
+                        let prefix = left_to_prefix_indices
+                            .iter()
+                            .map(|i| tuple[*i].clone())
+                            .collect_vec();
+                        if let Some(found) =
+                            self.checked(self.relation.prefix_scan(&prefix)?).next()
+                        {
+                            found?;
+                            return Ok(None);
+                        }
+                        Ok(Some(eliminate_from_tuple(tuple, &eliminate_indices)))
+                    })
+                    .map(flatten_err)
+                    .filter_map(invert_option_err),
+            ))
+        } else {
+            let mut right_join_vals = vec![];
+            for tuple in self.checked(self.relation.scan()?) {
+                let tuple = tuple?;
+                let to_join = right_join_indices
+                    .iter()
+                    .map(|i| tuple[*i].clone())
+                    .collect_vec();
+                right_join_vals.push(to_join);
+            }
+            right_join_vals.sort();
+            Ok(Box::new(
+                left_iter
+                    .map_ok(move |tuple| -> Option<Tuple> {
+                        let left_join_vals = left_join_indices
+                            .iter()
+                            .map(|i| tuple[*i].clone())
+                            .collect_vec();
+                        if right_join_vals.binary_search(&left_join_vals).is_ok() {
+                            return None;
+                        }
+                        Some(eliminate_from_tuple(tuple, &eliminate_indices))
+                    })
+                    .filter_map(swap_option_result),
+            ))
+        }
+    }
+}
+
 pub(crate) struct Joiner {
     // invariant: these are of the same lengths
     pub(crate) left_keys: Vec<Symbol>,
@@ -1823,7 +2474,7 @@ impl RelAlgebra {
         match self {
             RelAlgebra::Fixed(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::TempStore(_r) => Ok(()),
-            RelAlgebra::Stored(_v) => Ok(()),
+            RelAlgebra::Stored(v) => v.do_eliminate_temp_vars(used),
             RelAlgebra::StoredWithValidity(_v) => Ok(()),
             RelAlgebra::Join(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::Reorder(r) => r.relation.eliminate_temp_vars(used),
@@ -1833,6 +2484,8 @@ impl RelAlgebra {
             RelAlgebra::HnswSearch(_) => Ok(()),
             RelAlgebra::FtsSearch(_) => Ok(()),
             RelAlgebra::LshSearch(_) => Ok(()),
+            RelAlgebra::GeoSearch(_) => Ok(()),
+            RelAlgebra::Virtual(_) => Ok(()),
         }
     }
 
@@ -1850,6 +2503,8 @@ impl RelAlgebra {
             RelAlgebra::HnswSearch(_) => None,
             RelAlgebra::FtsSearch(_) => None,
             RelAlgebra::LshSearch(_) => None,
+            RelAlgebra::GeoSearch(_) => None,
+            RelAlgebra::Virtual(_) => None,
         }
     }
 
@@ -1870,6 +2525,7 @@ impl RelAlgebra {
             RelAlgebra::TempStore(d) => d.bindings.clone(),
             RelAlgebra::Stored(v) => v.bindings.clone(),
             RelAlgebra::StoredWithValidity(v) => v.bindings.clone(),
+            RelAlgebra::Virtual(v) => v.bindings.clone(),
             RelAlgebra::Join(j) => j.bindings(),
             RelAlgebra::Reorder(r) => r.bindings(),
             RelAlgebra::Filter(r) => r.parent.bindings_after_eliminate(),
@@ -1894,6 +2550,11 @@ impl RelAlgebra {
                 bindings.extend_from_slice(&s.own_bindings);
                 bindings
             }
+            RelAlgebra::GeoSearch(s) => {
+                let mut bindings = s.parent.bindings_after_eliminate();
+                bindings.extend_from_slice(&s.own_bindings);
+                bindings
+            }
         }
     }
     pub(crate) fn iter<'a>(
@@ -1907,6 +2568,7 @@ impl RelAlgebra {
             RelAlgebra::TempStore(r) => r.iter(delta_rule, stores),
             RelAlgebra::Stored(v) => v.iter(tx),
             RelAlgebra::StoredWithValidity(v) => v.iter(tx),
+            RelAlgebra::Virtual(v) => v.iter(),
             RelAlgebra::Join(j) => j.iter(tx, delta_rule, stores),
             RelAlgebra::Reorder(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::Filter(r) => r.iter(tx, delta_rule, stores),
@@ -1915,6 +2577,7 @@ impl RelAlgebra {
             RelAlgebra::HnswSearch(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::FtsSearch(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::LshSearch(r) => r.iter(tx, delta_rule, stores),
+            RelAlgebra::GeoSearch(r) => r.iter(tx, delta_rule, stores),
         }
     }
 }
@@ -1972,6 +2635,20 @@ impl NegJoin {
                     "stored_neg_mat_join"
                 }
             }
+            RelAlgebra::Virtual(_) => {
+                let join_indices = self
+                    .joiner
+                    .join_indices(
+                        &self.left.bindings_after_eliminate(),
+                        &self.right.bindings_after_eliminate(),
+                    )
+                    .unwrap();
+                if join_is_prefix(&join_indices.1) {
+                    "virtual_neg_prefix_join"
+                } else {
+                    "virtual_neg_mat_join"
+                }
+            }
             _ => {
                 unreachable!()
             }
@@ -2017,6 +2694,20 @@ impl NegJoin {
                     eliminate_indices,
                 )
             }
+            RelAlgebra::Virtual(v) => {
+                let join_indices = self
+                    .joiner
+                    .join_indices(
+                        &self.left.bindings_after_eliminate(),
+                        &self.right.bindings_after_eliminate(),
+                    )
+                    .unwrap();
+                v.neg_join(
+                    self.left.iter(tx, delta_rule, stores)?,
+                    join_indices,
+                    eliminate_indices,
+                )
+            }
             _ => {
                 unreachable!()
             }
@@ -2031,6 +2722,8 @@ pub(crate) struct InnerJoin {
     pub(crate) joiner: Joiner,
     pub(crate) to_eliminate: BTreeSet<Symbol>,
     pub(crate) span: SourceSpan,
+    /// Use a hash table instead of a sorted materialization of the right side.
+    pub(crate) hash_join: bool,
 }
 
 impl InnerJoin {
@@ -2064,6 +2757,14 @@ impl InnerJoin {
         ret
     }
     pub(crate) fn join_type(&self) -> &str {
+        let join_type = self.default_join_type();
+        if self.hash_join && join_type.ends_with("_mat_join") {
+            "hash_join"
+        } else {
+            join_type
+        }
+    }
+    fn default_join_type(&self) -> &str {
         match &self.right {
             RelAlgebra::Fixed(f) => f.join_type(),
             RelAlgebra::TempStore(_) => {
@@ -2094,9 +2795,24 @@ impl InnerJoin {
                     "stored_mat_join"
                 }
             }
+            RelAlgebra::Virtual(_) => {
+                let join_indices = self
+                    .joiner
+                    .join_indices(
+                        &self.left.bindings_after_eliminate(),
+                        &self.right.bindings_after_eliminate(),
+                    )
+                    .unwrap();
+                if join_is_prefix(&join_indices.1) {
+                    "virtual_prefix_join"
+                } else {
+                    "virtual_mat_join"
+                }
+            }
             RelAlgebra::HnswSearch(_) => "hnsw_search_join",
             RelAlgebra::FtsSearch(_) => "fts_search_join",
             RelAlgebra::LshSearch(_) => "lsh_search_join",
+            RelAlgebra::GeoSearch(_) => "geo_search_join",
             RelAlgebra::StoredWithValidity(_) => {
                 let join_indices = self
                     .joiner
@@ -2203,12 +2919,31 @@ impl InnerJoin {
                     self.materialized_join(tx, eliminate_indices, delta_rule, stores)
                 }
             }
+            RelAlgebra::Virtual(r) => {
+                let join_indices = self
+                    .joiner
+                    .join_indices(
+                        &self.left.bindings_after_eliminate(),
+                        &self.right.bindings_after_eliminate(),
+                    )
+                    .unwrap();
+                if join_is_prefix(&join_indices.1) {
+                    r.prefix_join(
+                        self.left.iter(tx, delta_rule, stores)?,
+                        join_indices,
+                        eliminate_indices,
+                    )
+                } else {
+                    self.materialized_join(tx, eliminate_indices, delta_rule, stores)
+                }
+            }
             RelAlgebra::Join(_)
             | RelAlgebra::Filter(_)
             | RelAlgebra::Unification(_)
             | RelAlgebra::HnswSearch(_)
             | RelAlgebra::FtsSearch(_)
-            | RelAlgebra::LshSearch(_) => {
+            | RelAlgebra::LshSearch(_)
+            | RelAlgebra::GeoSearch(_) => {
                 self.materialized_join(tx, eliminate_indices, delta_rule, stores)
             }
             RelAlgebra::Reorder(_) => {
@@ -2226,7 +2961,6 @@ impl InnerJoin {
         delta_rule: Option<&MagicSymbol>,
         stores: &'a BTreeMap<MagicSymbol, EpochStore>,
     ) -> Result<TupleIter<'a>> {
-        debug!("using materialized join");
         let right_bindings = self.right.bindings_after_eliminate();
         let (left_join_indices, right_join_indices) = self
             .joiner
@@ -2240,6 +2974,23 @@ impl InnerJoin {
             Some(Ok(data)) => data,
         };
 
+        let mut right_iter = self.right.iter(tx, delta_rule, stores)?;
+        if self.hash_join {
+            debug!("using hash join");
+            return self.hash_table_join(tx, left_cache, left_iter, right_iter, eliminate_indices);
+        }
+        // sorting is only worth it for small right sides
+        let mut right_rows = vec![];
+        for item in right_iter.by_ref().take(HASH_JOIN_MIN_ROWS + 1) {
+            right_rows.push(item?);
+        }
+        if right_rows.len() > HASH_JOIN_MIN_ROWS {
+            debug!("switching to hash join");
+            let right_iter = Box::new(right_rows.into_iter().map(Ok).chain(right_iter));
+            return self.hash_table_join(tx, left_cache, left_iter, right_iter, eliminate_indices);
+        }
+        debug!("using materialized join");
+
         let right_join_indices_set = BTreeSet::from_iter(right_join_indices.iter().cloned());
         let mut right_store_indices = right_join_indices;
         for i in 0..right_bindings.len() {
@@ -2254,22 +3005,17 @@ impl InnerJoin {
             .sorted_by_key(|(_, b)| **b)
             .map(|(a, _)| a)
             .collect_vec();
-        let cached_data = {
-            let mut cache = BTreeSet::new();
-            for item in self.right.iter(tx, delta_rule, stores)? {
-                match item {
-                    Ok(tuple) => {
-                        let stored_tuple = right_store_indices
-                            .iter()
-                            .map(|i| tuple[*i].clone())
-                            .collect_vec();
-                        cache.insert(stored_tuple);
-                    }
-                    Err(e) => return Err(e),
-                }
-            }
-            cache.into_iter().collect_vec()
-        };
+        let cached_data = right_rows
+            .into_iter()
+            .map(|tuple| {
+                right_store_indices
+                    .iter()
+                    .map(|i| tuple[*i].clone())
+                    .collect_vec()
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect_vec();
 
         let (prefix, right_idx) =
             build_mat_range_iter(&cached_data, &left_join_indices, &left_cache);
@@ -2286,6 +3032,144 @@ impl InnerJoin {
         };
         Ok(Box::new(it))
     }
+    #[allow(clippy::mutable_key_type)]
+    fn hash_table_join<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        left_first: Tuple,
+        left_rest: TupleIter<'a>,
+        mut right_iter: TupleIter<'a>,
+        eliminate_indices: BTreeSet<usize>,
+    ) -> Result<TupleIter<'a>> {
+        let (left_join_indices, right_join_indices) = self
+            .joiner
+            .join_indices(
+                &self.left.bindings_after_eliminate(),
+                &self.right.bindings_after_eliminate(),
+            )
+            .unwrap();
+        let left_iter = iter::once(Ok(left_first)).chain(left_rest);
+
+        let mut table: HashMap<Tuple, Vec<Tuple>> = HashMap::new();
+        let mut table_bytes = 0;
+        let mut over_budget = false;
+        for item in right_iter.by_ref() {
+            let tuple = item?;
+            table_bytes += approx_tuple_bytes(&tuple);
+            table
+                .entry(join_key(&tuple, &right_join_indices))
+                .or_default()
+                .push(tuple);
+            if matches!(tx.memory_budget, Some(budget) if table_bytes > budget) {
+                over_budget = true;
+                break;
+            }
+        }
+        if !over_budget {
+            dedup_hash_table(&mut table);
+            return Ok(Box::new(probe_hash_table(
+                left_iter,
+                table,
+                left_join_indices,
+                eliminate_indices,
+            )));
+        }
+
+        // Grace hash join: both sides are split into partitions by the hash of
+        // their join keys and written to disk, then joined partition by partition.
+        debug!("hash join spilling to disk");
+        tx.metrics.hash_join_spills.fetch_add(1, Ordering::Relaxed);
+        let new_partitions = || {
+            (0..HASH_JOIN_PARTITIONS)
+                .map(|_| TempCollector::with_swap_after(SPILL_BATCH_SIZE))
+                .collect_vec()
+        };
+        let mut right_partitions = new_partitions();
+        for (key, tuples) in table {
+            let partition = &mut right_partitions[hash_partition(&key)];
+            for tuple in tuples {
+                partition.push(tuple);
+            }
+        }
+        for item in right_iter {
+            let tuple = item?;
+            right_partitions[hash_partition(&join_key(&tuple, &right_join_indices))].push(tuple);
+        }
+        let mut left_partitions = new_partitions();
+        for item in left_iter {
+            let tuple = item?;
+            left_partitions[hash_partition(&join_key(&tuple, &left_join_indices))].push(tuple);
+        }
+        let it =
+            right_partitions
+                .into_iter()
+                .zip(left_partitions)
+                .flat_map(move |(right, left)| {
+                    let mut table: HashMap<Tuple, Vec<Tuple>> = HashMap::new();
+                    for tuple in right.into_iter() {
+                        table
+                            .entry(join_key(&tuple, &right_join_indices))
+                            .or_default()
+                            .push(tuple);
+                    }
+                    dedup_hash_table(&mut table);
+                    probe_hash_table(
+                        left.into_iter().map(Ok),
+                        table,
+                        left_join_indices.clone(),
+                        eliminate_indices.clone(),
+                    )
+                });
+        Ok(Box::new(it))
+    }
+}
+
+/// Materialized joins with more rows than this on their right side use hash tables.
+const HASH_JOIN_MIN_ROWS: usize = 10000;
+/// Number of partitions of a hash join that spilled to disk.
+const HASH_JOIN_PARTITIONS: usize = 64;
+/// Tuples kept in memory by each partition of a spilled hash join before writing to disk.
+const SPILL_BATCH_SIZE: usize = 1024;
+
+fn join_key(tuple: &Tuple, indices: &[usize]) -> Tuple {
+    indices.iter().map(|i| tuple[*i].clone()).collect_vec()
+}
+
+fn hash_partition(key: &Tuple) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % HASH_JOIN_PARTITIONS as u64) as usize
+}
+
+#[allow(clippy::mutable_key_type)]
+fn dedup_hash_table(table: &mut HashMap<Tuple, Vec<Tuple>>) {
+    // the same rows in the same order as with a sorted materialization
+    for tuples in table.values_mut() {
+        tuples.sort();
+        tuples.dedup();
+    }
+}
+
+#[allow(clippy::mutable_key_type)]
+fn probe_hash_table<'a>(
+    left: impl Iterator<Item = Result<Tuple>> + 'a,
+    table: HashMap<Tuple, Vec<Tuple>>,
+    left_join_indices: Vec<usize>,
+    eliminate_indices: BTreeSet<usize>,
+) -> impl Iterator<Item = Result<Tuple>> + 'a {
+    left.map_ok(move |left_tuple| {
+        table
+            .get(&join_key(&left_tuple, &left_join_indices))
+            .into_iter()
+            .flatten()
+            .map(|right_tuple| {
+                let mut ret = left_tuple.clone();
+                ret.extend(right_tuple.iter().cloned());
+                eliminate_from_tuple(ret, &eliminate_indices)
+            })
+            .collect_vec()
+    })
+    .flatten_ok()
 }
 
 struct CachedMaterializedIterator<'a> {
@@ -2395,4 +3279,23 @@ mod tests {
             vec![vec![DataValue::from(1)], vec![DataValue::from(2)]]
         )
     }
+
+    #[test]
+    fn test_hash_join() {
+        let db = DbInstance::default();
+        let res = db
+            .run_default(
+                r#"
+        data[a, b] <- [[1, 2], [1, 3], [2, 3]]
+        ?[x] := a = 3, data[x, a]
+        :experimental hash_join
+        "#,
+            )
+            .unwrap()
+            .rows;
+        assert_eq!(
+            res,
+            vec![vec![DataValue::from(1)], vec![DataValue::from(2)]]
+        )
+    }
 }
@@ -6,6 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cmp::Reverse;
 use std::collections::BTreeSet;
 use std::mem;
 
@@ -13,6 +14,7 @@ use miette::{bail, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::program::{NormalFormAtom, NormalFormInlineRule};
+use crate::data::symb::Symbol;
 use crate::parse::SourceSpan;
 
 #[derive(Diagnostic, Debug, Error)]
@@ -96,6 +98,14 @@ impl NormalFormInlineRule {
                         pending.push(NormalFormAtom::LshSearch(s));
                     }
                 }
+                NormalFormAtom::GeoSearch(s) => {
+                    if seen_variables.contains(&s.query) {
+                        seen_variables.extend(s.all_bindings().cloned());
+                        round_1_collected.push(NormalFormAtom::GeoSearch(s));
+                    } else {
+                        pending.push(NormalFormAtom::GeoSearch(s));
+                    }
+                }
             }
         }
 
@@ -136,6 +146,10 @@ impl NormalFormInlineRule {
                     seen_variables.extend(s.all_bindings().cloned());
                     collected.push(NormalFormAtom::LshSearch(s));
                 }
+                NormalFormAtom::GeoSearch(s) => {
+                    seen_variables.extend(s.all_bindings().cloned());
+                    collected.push(NormalFormAtom::GeoSearch(s));
+                }
             }
             for atom in last_pending.iter() {
                 match atom {
@@ -178,6 +192,14 @@ impl NormalFormInlineRule {
                             pending.push(NormalFormAtom::LshSearch(s.clone()));
                         }
                     }
+                    NormalFormAtom::GeoSearch(s) => {
+                        if seen_variables.contains(&s.query) {
+                            seen_variables.extend(s.all_bindings().cloned());
+                            collected.push(NormalFormAtom::GeoSearch(s.clone()));
+                        } else {
+                            pending.push(NormalFormAtom::GeoSearch(s.clone()));
+                        }
+                    }
                     NormalFormAtom::Predicate(p) => {
                         if p.bindings()?.is_subset(&seen_variables) {
                             collected.push(NormalFormAtom::Predicate(p.clone()));
@@ -229,6 +251,9 @@ impl NormalFormInlineRule {
                     NormalFormAtom::LshSearch(s) => {
                         bail!(UnboundVariable(s.span))
                     }
+                    NormalFormAtom::GeoSearch(s) => {
+                        bail!(UnboundVariable(s.span))
+                    }
                 }
             }
         }
@@ -239,4 +264,83 @@ impl NormalFormInlineRule {
             body: collected,
         })
     }
+    /// Reorder each run of consecutive rule and relation applications greedily, so that
+    /// the next one joined is the one with the longest prefix of arguments already bound,
+    /// then the most arguments already bound, ties going to the written order.
+    /// Longer bound prefixes allow joining on indices, and any bound argument avoids
+    /// a cartesian product.
+    pub(crate) fn reorder_joins(&mut self) {
+        fn join_args(atom: &NormalFormAtom) -> Option<&[Symbol]> {
+            match atom {
+                NormalFormAtom::Rule(r) => Some(&r.args),
+                NormalFormAtom::Relation(v) => Some(&v.args),
+                _ => None,
+            }
+        }
+
+        let mut bound: BTreeSet<Symbol> = BTreeSet::new();
+        let mut start = 0;
+        while start < self.body.len() {
+            let end = start
+                + self.body[start..]
+                    .iter()
+                    .take_while(|atom| join_args(atom).is_some())
+                    .count();
+            if end == start {
+                match &self.body[start] {
+                    NormalFormAtom::Unification(u) => {
+                        bound.insert(u.binding.clone());
+                    }
+                    NormalFormAtom::HnswSearch(s) => bound.extend(s.all_bindings().cloned()),
+                    NormalFormAtom::FtsSearch(s) => bound.extend(s.all_bindings().cloned()),
+                    NormalFormAtom::LshSearch(s) => bound.extend(s.all_bindings().cloned()),
+                    NormalFormAtom::GeoSearch(s) => bound.extend(s.all_bindings().cloned()),
+                    _ => {}
+                }
+                start += 1;
+                continue;
+            }
+            let mut run = self.body.drain(start..end).collect::<Vec<_>>();
+            let mut ordered = Vec::with_capacity(run.len());
+            while !run.is_empty() {
+                let next = (0..run.len())
+                    .max_by_key(|i| {
+                        let args = join_args(&run[*i]).unwrap();
+                        let prefix = args.iter().take_while(|a| bound.contains(*a)).count();
+                        let total = args.iter().filter(|a| bound.contains(*a)).count();
+                        (prefix, total, Reverse(*i))
+                    })
+                    .unwrap();
+                let atom = run.remove(next);
+                bound.extend(join_args(&atom).unwrap().iter().cloned());
+                ordered.push(atom);
+            }
+            self.body.splice(start..start, ordered);
+            start = end;
+        }
+    }
+    /// Put the rule and relation applications named in `order` in that order, in the positions
+    /// they already occupy. Other atoms are placed after the applications binding their
+    /// variables when the rule is well-ordered, so they need not move.
+    pub(crate) fn apply_join_order(&mut self, order: &[Symbol]) {
+        let rank = |atom: &NormalFormAtom| {
+            let name = match atom {
+                NormalFormAtom::Rule(r) => &r.name,
+                NormalFormAtom::Relation(v) => &v.name,
+                _ => return None,
+            };
+            order.iter().position(|o| o.name == name.name)
+        };
+        let positions = (0..self.body.len())
+            .filter(|i| rank(&self.body[*i]).is_some())
+            .collect::<Vec<_>>();
+        let mut hinted = positions
+            .iter()
+            .map(|i| self.body[*i].clone())
+            .collect::<Vec<_>>();
+        hinted.sort_by_key(rank);
+        for (i, atom) in positions.into_iter().zip(hinted) {
+            self.body[i] = atom;
+        }
+    }
 }
@@ -15,11 +15,11 @@ use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::expr::{Bytecode, Expr};
+use crate::data::expr::{eval_bytecode_pred, Bytecode, Expr};
 use crate::data::program::{FixedRuleApply, InputInlineRulesOrFixed, InputProgram, RelationOp};
 use crate::data::relation::{ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
-use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN};
+use crate::data::tuple::{Tuple, TupleT, ENCODED_KEY_MIN_LEN};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::FixedRuleHandle;
@@ -29,17 +29,26 @@ use crate::parse::{parse_script, CozoScriptParser, Rule};
 use crate::runtime::callback::{CallbackCollector, CallbackOp};
 use crate::runtime::minhash_lsh::HashPermutations;
 use crate::runtime::relation::{
-    extend_tuple_from_v, AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
+    decode_tuple_from_kv, extend_tuple_from_v, AccessLevel, InputRelationHandle,
+    InsufficientAccessLevel, MutationKind, RelationHandle,
 };
 use crate::runtime::transact::SessionTx;
 use crate::storage::Storage;
-use crate::{Db, NamedRows, SourceSpan, StoreTx};
+use crate::{Db, NamedRows, Poison, SourceSpan, StoreTx};
 
 #[derive(Debug, Error, Diagnostic)]
 #[error("attempting to write into relation {0} of arity {1} with data of arity {2}")]
 #[diagnostic(code(eval::relation_arity_mismatch))]
 struct RelationArityMismatch(String, usize, usize);
 
+/// The numbers of rows affected by a mutation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MutationCounts {
+    pub(crate) inserted: usize,
+    pub(crate) updated: usize,
+    pub(crate) deleted: usize,
+}
+
 impl<'a> SessionTx<'a> {
     pub(crate) fn execute_relation<'s, S: Storage<'s>>(
         &mut self,
@@ -51,9 +60,24 @@ impl<'a> SessionTx<'a> {
         cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
+        poison: &Poison,
         propagate_triggers: bool,
         force_collect: &str,
+        counts: &mut MutationCounts,
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mutation = match op {
+            RelationOp::Create => Some(MutationKind::Create),
+            RelationOp::Replace => Some(MutationKind::Replace),
+            RelationOp::Put => Some(MutationKind::Put),
+            RelationOp::Insert => Some(MutationKind::Insert),
+            RelationOp::Update | RelationOp::Upsert => Some(MutationKind::Update),
+            RelationOp::Rm => Some(MutationKind::Rm),
+            RelationOp::Delete => Some(MutationKind::Delete),
+            RelationOp::Ensure | RelationOp::EnsureNot => None,
+        };
+        if let Some(kind) = mutation {
+            db.check_mutation(&meta.name, kind)?;
+        }
         let mut to_clear = vec![];
         let mut replaced_old_triggers = None;
         if op == RelationOp::Replace {
@@ -72,6 +96,14 @@ impl<'a> SessionTx<'a> {
                     struct ReplaceRelationWithIndices(String);
                     bail!(ReplaceRelationWithIndices(old_handle.name.to_string()))
                 }
+                if old_handle.tx_history.is_some() {
+                    #[derive(Debug, Error, Diagnostic)]
+                    #[error("cannot replace relation {0} since it tracks transaction time")]
+                    #[diagnostic(code(eval::replace_rel_with_tx_time))]
+                    #[diagnostic(help("Untrack it first with `::tx_time untrack`"))]
+                    struct ReplaceRelationWithTxTime(String);
+                    bail!(ReplaceRelationWithTxTime(old_handle.name.to_string()))
+                }
                 if old_handle.access_level < AccessLevel::Normal {
                     bail!(InsufficientAccessLevel(
                         old_handle.name.to_string(),
@@ -98,6 +130,7 @@ impl<'a> SessionTx<'a> {
                             cur_vld,
                             callback_targets,
                             callback_collector,
+                            poison,
                             false,
                         )
                         .map_err(|err| {
@@ -140,6 +173,7 @@ impl<'a> SessionTx<'a> {
                 cur_vld,
                 callback_targets,
                 callback_collector,
+                poison,
                 propagate_triggers,
                 &mut to_clear,
                 &relation_store,
@@ -147,6 +181,7 @@ impl<'a> SessionTx<'a> {
                 key_bindings,
                 op == RelationOp::Delete,
                 force_collect,
+                counts,
                 *span,
             )?,
             RelationOp::Ensure => self.ensure_in_relation(
@@ -167,19 +202,22 @@ impl<'a> SessionTx<'a> {
                 key_bindings,
                 *span,
             )?,
-            RelationOp::Update => self.update_in_relation(
+            RelationOp::Update | RelationOp::Upsert => self.update_in_relation(
                 db,
                 res_iter,
                 headers,
                 cur_vld,
                 callback_targets,
                 callback_collector,
+                poison,
                 propagate_triggers,
                 &mut to_clear,
                 &relation_store,
                 metadata,
                 key_bindings,
+                op == RelationOp::Upsert,
                 force_collect,
+                counts,
                 *span,
             )?,
             RelationOp::Create | RelationOp::Replace | RelationOp::Put | RelationOp::Insert => self
@@ -190,6 +228,7 @@ impl<'a> SessionTx<'a> {
                     cur_vld,
                     callback_targets,
                     callback_collector,
+                    poison,
                     propagate_triggers,
                     &mut to_clear,
                     &relation_store,
@@ -198,6 +237,7 @@ impl<'a> SessionTx<'a> {
                     dep_bindings,
                     op == RelationOp::Insert,
                     force_collect,
+                    counts,
                     *span,
                 )?,
         };
@@ -205,6 +245,75 @@ impl<'a> SessionTx<'a> {
         Ok(to_clear)
     }
 
+    /// Keep only the rows of a mutation whose guard condition holds. The guard is evaluated
+    /// with the bindings of the rows and the existing values of the stored row bound as
+    /// `old.<column>`, which are null if the row does not exist yet.
+    pub(crate) fn filter_by_guard(
+        &self,
+        res_iter: impl Iterator<Item = Tuple>,
+        meta: &InputRelationHandle,
+        headers: &[Symbol],
+        guard: &Expr,
+        cur_vld: ValidityTs,
+    ) -> Result<Vec<Tuple>> {
+        let relation_store = self.get_relation(&meta.name, false)?;
+        let key_extractors = make_extractors(
+            &relation_store.metadata.keys,
+            &meta.metadata.keys,
+            &meta.key_bindings,
+            headers,
+        )?;
+        let mut binding_indices: BTreeMap<_, _> = headers
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(i, b)| (b, i))
+            .collect();
+        for (i, col) in relation_store
+            .metadata
+            .keys
+            .iter()
+            .chain(relation_store.metadata.non_keys.iter())
+            .enumerate()
+        {
+            binding_indices.insert(
+                Symbol::new(format!("old.{}", col.name), guard.span()),
+                headers.len() + i,
+            );
+        }
+        let mut guard = guard.clone();
+        guard.fill_binding_indices(&binding_indices)?;
+        let span = guard.span();
+        let guard = guard.compile()?;
+
+        let mut stack = vec![];
+        let mut kept = vec![];
+        for tuple in res_iter {
+            let extracted: Vec<DataValue> = key_extractors
+                .iter()
+                .map(|ex| ex.extract_data(&tuple, cur_vld))
+                .try_collect()?;
+            let key = relation_store.encode_key_for_store(&extracted, span)?;
+            let existing = if relation_store.is_temp {
+                self.temp_store_tx.get(&key, true)?
+            } else {
+                self.store_tx.get(&key, true)?
+            };
+            let mut bound = tuple.clone();
+            match existing {
+                Some(v) => {
+                    bound.extend(extracted);
+                    extend_tuple_from_v(&mut bound, &v);
+                }
+                None => bound.resize(tuple.len() + relation_store.arity(), DataValue::Null),
+            }
+            if eval_bytecode_pred(&guard, &bound, &mut stack, span)? {
+                kept.push(tuple);
+            }
+        }
+        Ok(kept)
+    }
+
     fn put_into_relation<'s, S: Storage<'s>>(
         &mut self,
         db: &Db<S>,
@@ -213,6 +322,7 @@ impl<'a> SessionTx<'a> {
         cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
+        poison: &Poison,
         propagate_triggers: bool,
         to_clear: &mut Vec<(Vec<u8>, Vec<u8>)>,
         relation_store: &RelationHandle,
@@ -221,6 +331,7 @@ impl<'a> SessionTx<'a> {
         dep_bindings: &[Symbol],
         is_insert: bool,
         force_collect: &str,
+        counts: &mut MutationCounts,
         span: SourceSpan,
     ) -> Result<()> {
         let is_callback_target = callback_targets.contains(&relation_store.name)
@@ -249,6 +360,7 @@ impl<'a> SessionTx<'a> {
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
         let has_fts_indices = !relation_store.fts_indices.is_empty();
         let has_lsh_indices = !relation_store.lsh_indices.is_empty();
+        let has_geo_indices = !relation_store.geo_indices.is_empty();
         let mut new_tuples: Vec<DataValue> = vec![];
         let mut old_tuples: Vec<DataValue> = vec![];
 
@@ -272,6 +384,9 @@ impl<'a> SessionTx<'a> {
         let hnsw_filters = Self::make_hnsw_filters(relation_store)?;
         let fts_lsh_processors = self.make_fts_lsh_processors(relation_store)?;
         let lsh_perms = self.make_lsh_hash_perms(relation_store);
+        let geo_extractors = Self::make_geo_extractors(relation_store)?;
+        let index_exprs = relation_store.compile_index_exprs()?;
+        let tx_history = self.tx_history(relation_store)?;
 
         for tuple in res_iter {
             let extracted: Vec<DataValue> = key_extractors
@@ -281,51 +396,79 @@ impl<'a> SessionTx<'a> {
 
             let key = relation_store.encode_key_for_store(&extracted, span)?;
 
-            if is_insert {
-                let already_exists = if relation_store.is_temp {
-                    self.temp_store_tx.exists(&key, true)?
-                } else {
-                    self.store_tx.exists(&key, true)?
-                };
-
-                if already_exists {
+            let existing = if relation_store.is_temp {
+                self.temp_store_tx.get(&key, is_insert)?
+            } else {
+                self.store_tx.get(&key, is_insert)?
+            };
+            if existing.is_some() {
+                if is_insert {
                     bail!(TransactAssertionFailure {
                         relation: relation_store.name.to_string(),
                         key: extracted,
                         notice: "key exists in database".to_string()
                     });
                 }
+                counts.updated += 1;
+            } else {
+                counts.inserted += 1;
             }
 
             let val = relation_store.encode_val_for_store(&extracted, span)?;
+            if let Some(history) = &tx_history {
+                self.record_tx_time(history, &extracted, true, cur_vld)?;
+            }
 
             if need_to_collect
                 || has_indices
                 || has_hnsw_indices
                 || has_fts_indices
                 || has_lsh_indices
+                || has_geo_indices
             {
-                if let Some(existing) = self.store_tx.get(&key, false)? {
+                if let Some(existing) = existing {
                     let mut tup = extracted[0..relation_store.metadata.keys.len()].to_vec();
                     extend_tuple_from_v(&mut tup, &existing);
                     if has_indices && extracted != tup {
-                        self.update_in_index(relation_store, &extracted, &tup)?;
+                        self.ensure_unique_in_indices(
+                            relation_store,
+                            &extracted,
+                            &index_exprs,
+                            &mut stack,
+                        )?;
+                        self.update_in_index(
+                            relation_store,
+                            &extracted,
+                            &tup,
+                            &index_exprs,
+                            &mut stack,
+                        )?;
                         self.del_in_fts(relation_store, &mut stack, &fts_lsh_processors, &tup)?;
                         self.del_in_lsh(relation_store, &tup)?;
                     }
+                    if has_geo_indices && extracted != tup {
+                        self.del_in_geo(relation_store, &mut stack, &geo_extractors, &tup)?;
+                    }
 
                     if need_to_collect {
                         old_tuples.push(DataValue::List(tup));
                     }
                 } else if has_indices {
+                    self.ensure_unique_in_indices(
+                        relation_store,
+                        &extracted,
+                        &index_exprs,
+                        &mut stack,
+                    )?;
                     for (idx_rel, extractor) in relation_store.indices.values() {
-                        let idx_tup_new = extractor
-                            .iter()
-                            .map(|i| extracted[*i].clone())
-                            .collect_vec();
-                        let encoded_new =
-                            idx_rel.encode_key_for_store(&idx_tup_new, Default::default())?;
-                        self.store_tx.put(&encoded_new, &[])?;
+                        let idx_tup_new = relation_store.index_tuple(
+                            extractor,
+                            &extracted,
+                            &index_exprs,
+                            &mut stack,
+                        )?;
+                        let (key, val) = idx_rel.encode_index_entry(&idx_tup_new)?;
+                        self.store_tx.put(&key, &val)?;
                     }
                 }
 
@@ -338,6 +481,7 @@ impl<'a> SessionTx<'a> {
                     &extracted,
                     &lsh_perms,
                 )?;
+                self.put_in_geo(relation_store, &mut stack, &geo_extractors, &extracted)?;
 
                 if need_to_collect {
                     new_tuples.push(DataValue::List(extracted));
@@ -357,6 +501,7 @@ impl<'a> SessionTx<'a> {
                 cur_vld,
                 callback_targets,
                 callback_collector,
+                poison,
                 propagate_triggers,
                 to_clear,
                 relation_store,
@@ -428,6 +573,51 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
+    fn put_in_geo(
+        &mut self,
+        rel_handle: &RelationHandle,
+        stack: &mut Vec<DataValue>,
+        extractors: &BTreeMap<SmartString<LazyCompact>, Vec<Bytecode>>,
+        new_kv: &[DataValue],
+    ) -> Result<()> {
+        for (k, (idx_handle, manifest)) in rel_handle.geo_indices.iter() {
+            let extractor = extractors.get(k).unwrap();
+            self.put_geo_index_item(new_kv, extractor, stack, rel_handle, idx_handle, manifest)?;
+        }
+        Ok(())
+    }
+
+    fn del_in_geo(
+        &mut self,
+        rel_handle: &RelationHandle,
+        stack: &mut Vec<DataValue>,
+        extractors: &BTreeMap<SmartString<LazyCompact>, Vec<Bytecode>>,
+        old_kv: &[DataValue],
+    ) -> Result<()> {
+        for (k, (idx_handle, manifest)) in rel_handle.geo_indices.iter() {
+            let extractor = extractors.get(k).unwrap();
+            self.del_geo_index_item(old_kv, extractor, stack, rel_handle, idx_handle, manifest)?;
+        }
+        Ok(())
+    }
+
+    fn make_geo_extractors(
+        relation_store: &RelationHandle,
+    ) -> Result<BTreeMap<SmartString<LazyCompact>, Vec<Bytecode>>> {
+        let mut extractors = BTreeMap::new();
+        for (name, (_, manifest)) in relation_store.geo_indices.iter() {
+            let parsed = CozoScriptParser::parse(Rule::expr, &manifest.extractor)
+                .into_diagnostic()?
+                .next()
+                .unwrap();
+            let mut code_expr = build_expr(parsed, &Default::default())?;
+            let binding_map = relation_store.raw_binding_map();
+            code_expr.fill_binding_indices(&binding_map)?;
+            extractors.insert(name.clone(), code_expr.compile()?);
+        }
+        Ok(extractors)
+    }
+
     fn update_in_hnsw(
         &mut self,
         relation_store: &RelationHandle,
@@ -525,12 +715,15 @@ impl<'a> SessionTx<'a> {
         cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
+        poison: &Poison,
         propagate_triggers: bool,
         to_clear: &mut Vec<(Vec<u8>, Vec<u8>)>,
         relation_store: &RelationHandle,
         metadata: &StoredRelationMetadata,
         key_bindings: &[Symbol],
+        insert_missing: bool,
         force_collect: &str,
+        counts: &mut MutationCounts,
         span: SourceSpan,
     ) -> Result<()> {
         let is_callback_target = callback_targets.contains(&relation_store.name)
@@ -559,6 +752,7 @@ impl<'a> SessionTx<'a> {
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
         let has_fts_indices = !relation_store.fts_indices.is_empty();
         let has_lsh_indices = !relation_store.lsh_indices.is_empty();
+        let has_geo_indices = !relation_store.geo_indices.is_empty();
         let mut new_tuples: Vec<DataValue> = vec![];
         let mut old_tuples: Vec<DataValue> = vec![];
 
@@ -573,6 +767,9 @@ impl<'a> SessionTx<'a> {
         let hnsw_filters = Self::make_hnsw_filters(relation_store)?;
         let fts_lsh_processors = self.make_fts_lsh_processors(relation_store)?;
         let lsh_perms = self.make_lsh_hash_perms(relation_store);
+        let geo_extractors = Self::make_geo_extractors(relation_store)?;
+        let index_exprs = relation_store.compile_index_exprs()?;
+        let tx_history = self.tx_history(relation_store)?;
 
         for tuple in res_iter {
             let mut new_kv: Vec<DataValue> = key_extractors
@@ -586,45 +783,97 @@ impl<'a> SessionTx<'a> {
             } else {
                 self.store_tx.get(&key, true)?
             };
-            let original_val: Tuple = match original_val_bytes {
-                None => {
+            let original_val: Option<Tuple> = match original_val_bytes {
+                None if !insert_missing => {
                     bail!(TransactAssertionFailure {
                         relation: relation_store.name.to_string(),
                         key: new_kv,
                         notice: "key to update does not exist".to_string()
                     })
                 }
-                Some(v) => rmp_serde::from_slice(&v[ENCODED_KEY_MIN_LEN..]).unwrap(),
+                None => {
+                    counts.inserted += 1;
+                    None
+                }
+                Some(v) => {
+                    counts.updated += 1;
+                    Some(rmp_serde::from_slice(&v[ENCODED_KEY_MIN_LEN..]).unwrap())
+                }
             };
-            let mut old_kv = Vec::with_capacity(relation_store.arity());
-            old_kv.extend_from_slice(&new_kv);
-            old_kv.extend_from_slice(&original_val);
+            let old_kv = original_val.as_ref().map(|original_val| {
+                let mut old_kv = Vec::with_capacity(relation_store.arity());
+                old_kv.extend_from_slice(&new_kv);
+                old_kv.extend_from_slice(original_val);
+                old_kv
+            });
             new_kv.reserve_exact(relation_store.arity());
             for (i, extractor) in val_extractors.iter().enumerate() {
-                match extractor {
-                    None => {
+                match (extractor, &original_val) {
+                    (Some(ex), _) => {
+                        let val = ex.extract_data(&tuple, cur_vld)?;
+                        new_kv.push(val);
+                    }
+                    (None, Some(original_val)) => {
                         new_kv.push(original_val[i].clone());
                     }
-                    Some(ex) => {
-                        let val = ex.extract_data(&tuple, cur_vld)?;
+                    (None, None) => {
+                        let col = &relation_store.metadata.non_keys[i];
+                        let Some(default_gen) = &col.default_gen else {
+                            bail!(TransactAssertionFailure {
+                                relation: relation_store.name.to_string(),
+                                key: new_kv,
+                                notice: format!(
+                                    "key to upsert does not exist and column '{}' has no default",
+                                    col.name
+                                )
+                            })
+                        };
+                        let val = col
+                            .typing
+                            .coerce(default_gen.clone().eval_to_const()?, cur_vld)?;
                         new_kv.push(val);
                     }
                 }
             }
             let new_val = relation_store.encode_val_for_store(&new_kv, span)?;
+            if let Some(history) = &tx_history {
+                self.record_tx_time(history, &new_kv, true, cur_vld)?;
+            }
 
             if need_to_collect
                 || has_indices
                 || has_hnsw_indices
                 || has_fts_indices
                 || has_lsh_indices
+                || has_geo_indices
             {
-                self.del_in_fts(relation_store, &mut stack, &fts_lsh_processors, &old_kv)?;
-                self.del_in_lsh(relation_store, &old_kv)?;
-                self.update_in_index(relation_store, &new_kv, &old_kv)?;
+                self.ensure_unique_in_indices(relation_store, &new_kv, &index_exprs, &mut stack)?;
+                if let Some(old_kv) = old_kv {
+                    self.del_in_fts(relation_store, &mut stack, &fts_lsh_processors, &old_kv)?;
+                    self.del_in_lsh(relation_store, &old_kv)?;
+                    self.del_in_geo(relation_store, &mut stack, &geo_extractors, &old_kv)?;
+                    self.update_in_index(
+                        relation_store,
+                        &new_kv,
+                        &old_kv,
+                        &index_exprs,
+                        &mut stack,
+                    )?;
 
-                if need_to_collect {
-                    old_tuples.push(DataValue::List(old_kv));
+                    if need_to_collect {
+                        old_tuples.push(DataValue::List(old_kv));
+                    }
+                } else {
+                    for (idx_rel, extractor) in relation_store.indices.values() {
+                        let idx_tup_new = relation_store.index_tuple(
+                            extractor,
+                            &new_kv,
+                            &index_exprs,
+                            &mut stack,
+                        )?;
+                        let (key, val) = idx_rel.encode_index_entry(&idx_tup_new)?;
+                        self.store_tx.put(&key, &val)?;
+                    }
                 }
 
                 self.update_in_hnsw(relation_store, &mut stack, &hnsw_filters, &new_kv)?;
@@ -636,6 +885,7 @@ impl<'a> SessionTx<'a> {
                     &new_kv,
                     &lsh_perms,
                 )?;
+                self.put_in_geo(relation_store, &mut stack, &geo_extractors, &new_kv)?;
 
                 if need_to_collect {
                     new_tuples.push(DataValue::List(new_kv));
@@ -655,6 +905,7 @@ impl<'a> SessionTx<'a> {
                 cur_vld,
                 callback_targets,
                 callback_collector,
+                poison,
                 propagate_triggers,
                 to_clear,
                 relation_store,
@@ -672,6 +923,7 @@ impl<'a> SessionTx<'a> {
         cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
+        poison: &Poison,
         propagate_triggers: bool,
         to_clear: &mut Vec<(Vec<u8>, Vec<u8>)>,
         relation_store: &RelationHandle,
@@ -723,6 +975,7 @@ impl<'a> SessionTx<'a> {
                         cur_vld,
                         callback_targets,
                         callback_collector,
+                        poison,
                         false,
                     )
                     .map_err(|err| {
@@ -776,21 +1029,19 @@ impl<'a> SessionTx<'a> {
         relation_store: &RelationHandle,
         new_kv: &[DataValue],
         old_kv: &[DataValue],
+        index_exprs: &[Vec<Bytecode>],
+        stack: &mut Vec<DataValue>,
     ) -> Result<()> {
         for (idx_rel, idx_extractor) in relation_store.indices.values() {
-            let idx_tup_old = idx_extractor
-                .iter()
-                .map(|i| old_kv[*i].clone())
-                .collect_vec();
+            let idx_tup_old =
+                relation_store.index_tuple(idx_extractor, old_kv, index_exprs, stack)?;
             let encoded_old = idx_rel.encode_key_for_store(&idx_tup_old, Default::default())?;
             self.store_tx.del(&encoded_old)?;
 
-            let idx_tup_new = idx_extractor
-                .iter()
-                .map(|i| new_kv[*i].clone())
-                .collect_vec();
-            let encoded_new = idx_rel.encode_key_for_store(&idx_tup_new, Default::default())?;
-            self.store_tx.put(&encoded_new, &[])?;
+            let idx_tup_new =
+                relation_store.index_tuple(idx_extractor, new_kv, index_exprs, stack)?;
+            let (key, val) = idx_rel.encode_index_entry(&idx_tup_new)?;
+            self.store_tx.put(&key, &val)?;
         }
         Ok(())
     }
@@ -919,6 +1170,7 @@ impl<'a> SessionTx<'a> {
         cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
+        poison: &Poison,
         propagate_triggers: bool,
         to_clear: &mut Vec<(Vec<u8>, Vec<u8>)>,
         relation_store: &RelationHandle,
@@ -926,6 +1178,7 @@ impl<'a> SessionTx<'a> {
         key_bindings: &[Symbol],
         check_exists: bool,
         force_collect: &str,
+        counts: &mut MutationCounts,
         span: SourceSpan,
     ) -> Result<()> {
         let is_callback_target =
@@ -953,7 +1206,11 @@ impl<'a> SessionTx<'a> {
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
         let has_fts_indices = !relation_store.fts_indices.is_empty();
         let has_lsh_indices = !relation_store.lsh_indices.is_empty();
+        let has_geo_indices = !relation_store.geo_indices.is_empty();
         let fts_processors = self.make_fts_lsh_processors(relation_store)?;
+        let geo_extractors = Self::make_geo_extractors(relation_store)?;
+        let index_exprs = relation_store.compile_index_exprs()?;
+        let tx_history = self.tx_history(relation_store)?;
         let mut new_tuples: Vec<DataValue> = vec![];
         let mut old_tuples: Vec<DataValue> = vec![];
         let mut stack = vec![];
@@ -964,37 +1221,51 @@ impl<'a> SessionTx<'a> {
                 .map(|ex| ex.extract_data(&tuple, cur_vld))
                 .try_collect()?;
             let key = relation_store.encode_key_for_store(&extracted, span)?;
-            if check_exists {
-                let exists = if relation_store.is_temp {
-                    self.temp_store_tx.exists(&key, false)?
-                } else {
-                    self.store_tx.exists(&key, false)?
-                };
-                if !exists {
+            let existing = if relation_store.is_temp {
+                self.temp_store_tx.get(&key, false)?
+            } else {
+                self.store_tx.get(&key, false)?
+            };
+            match &existing {
+                None if check_exists => {
                     bail!(TransactAssertionFailure {
                         relation: relation_store.name.to_string(),
                         key: extracted,
                         notice: "key does not exists in database".to_string()
                     });
                 }
+                None => {}
+                Some(_) => counts.deleted += 1,
             }
-            if need_to_collect || has_indices || has_hnsw_indices || has_fts_indices || has_lsh_indices {
-                if let Some(existing) = self.store_tx.get(&key, false)? {
+            if need_to_collect
+                || has_indices
+                || has_hnsw_indices
+                || has_fts_indices
+                || has_lsh_indices
+                || has_geo_indices
+            {
+                if let Some(existing) = &existing {
                     let mut tup = extracted.clone();
-                    extend_tuple_from_v(&mut tup, &existing);
+                    extend_tuple_from_v(&mut tup, existing);
                     self.del_in_fts(relation_store, &mut stack, &fts_processors, &tup)?;
                     self.del_in_lsh(relation_store, &tup)?;
+                    self.del_in_geo(relation_store, &mut stack, &geo_extractors, &tup)?;
                     if has_indices {
                         for (idx_rel, extractor) in relation_store.indices.values() {
-                            let idx_tup = extractor.iter().map(|i| tup[*i].clone()).collect_vec();
+                            let idx_tup = relation_store.index_tuple(
+                                extractor,
+                                &tup,
+                                &index_exprs,
+                                &mut stack,
+                            )?;
                             let encoded =
                                 idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
                             self.store_tx.del(&encoded)?;
                         }
                     }
                     if has_hnsw_indices {
-                        for (idx_handle, _) in relation_store.hnsw_indices.values() {
-                            self.hnsw_remove(relation_store, idx_handle, &extracted)?;
+                        for (idx_handle, manifest) in relation_store.hnsw_indices.values() {
+                            self.hnsw_remove(manifest, relation_store, idx_handle, &extracted)?;
                         }
                     }
                     if need_to_collect {
@@ -1008,6 +1279,10 @@ impl<'a> SessionTx<'a> {
             if relation_store.is_temp {
                 self.temp_store_tx.del(&key)?;
             } else {
+                // only rows that were there are recorded as removed
+                if let (Some(history), Some(_)) = (&tx_history, &existing) {
+                    self.record_tx_time(history, &extracted, false, cur_vld)?;
+                }
                 self.store_tx.del(&key)?;
             }
         }
@@ -1056,6 +1331,7 @@ impl<'a> SessionTx<'a> {
                             cur_vld,
                             callback_targets,
                             callback_collector,
+                            poison,
                             false,
                         )
                         .map_err(|err| {
@@ -1227,3 +1503,171 @@ fn make_const_rule(
         },
     );
 }
+
+/// Number of rows deleted together by [SessionTx::delete_where] and
+/// [SessionTx::truncate_relation].
+const BULK_DELETE_BATCH: usize = 1024;
+
+impl<'a> SessionTx<'a> {
+    /// Delete the rows of a stored relation for which `pred` is true, or all rows if there
+    /// is no predicate. The variables of `pred` are the columns of the relation.
+    ///
+    /// The relation is scanned in key order and the matching rows are deleted in batches
+    /// as they are found, so they are never all held in memory. Indices, triggers, callbacks
+    /// and transaction time are maintained as for `:rm`. Returns the number of rows deleted.
+    pub(crate) fn delete_where<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        rel: &Symbol,
+        pred: Option<&Expr>,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        poison: &Poison,
+    ) -> Result<usize> {
+        let handle = self.get_relation(rel, false)?;
+        if handle.is_temp {
+            bail!("Bulk deletion is only possible on stored relations, {rel} is not one")
+        }
+        let column = |col: &ColumnDef| Symbol::new(col.name.clone(), rel.span);
+        let meta = InputRelationHandle {
+            name: rel.clone(),
+            metadata: handle.metadata.clone(),
+            key_bindings: handle.metadata.keys.iter().map(column).collect(),
+            dep_bindings: handle.metadata.non_keys.iter().map(column).collect(),
+            span: rel.span,
+        };
+        let headers = meta
+            .key_bindings
+            .iter()
+            .chain(meta.dep_bindings.iter())
+            .cloned()
+            .collect_vec();
+        let pred = match pred {
+            None => None,
+            Some(pred) => {
+                let binding_indices = headers
+                    .iter()
+                    .cloned()
+                    .enumerate()
+                    .map(|(i, b)| (b, i))
+                    .collect();
+                let mut pred = pred.clone();
+                pred.fill_binding_indices(&binding_indices)?;
+                Some((pred.compile()?, pred.span()))
+            }
+        };
+
+        let mut lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let mut stack = vec![];
+        let mut counts = MutationCounts::default();
+        let mut to_clear = vec![];
+        loop {
+            poison.check()?;
+            let mut batch = vec![];
+            let mut last_key = None;
+            for kv in self.store_tx.range_scan(&lower, &upper) {
+                let (key, val) = kv?;
+                let tuple = decode_tuple_from_kv(&key, &val, Some(headers.len()));
+                last_key = Some(key);
+                let matches = match &pred {
+                    None => true,
+                    Some((code, span)) => eval_bytecode_pred(code, &tuple, &mut stack, *span)?,
+                };
+                if matches {
+                    batch.push(tuple);
+                    if batch.len() == BULK_DELETE_BATCH {
+                        break;
+                    }
+                }
+            }
+            let Some(mut last_key) = last_key else {
+                break;
+            };
+            let is_full = batch.len() == BULK_DELETE_BATCH;
+            to_clear.extend(self.execute_relation(
+                db,
+                batch.into_iter(),
+                RelationOp::Rm,
+                &meta,
+                &headers,
+                cur_vld,
+                callback_targets,
+                callback_collector,
+                poison,
+                true,
+                "",
+                &mut counts,
+            )?);
+            if !is_full {
+                break;
+            }
+            // the smallest key after the last one scanned
+            last_key.push(0);
+            lower = last_key;
+        }
+        for (lower, upper) in to_clear {
+            self.store_tx.del_range_from_persisted(&lower, &upper)?;
+        }
+        Ok(counts.deleted)
+    }
+    /// Delete all rows of a stored relation. Relations without indices, `rm` triggers,
+    /// callbacks or tracked transaction time have their keys deleted directly,
+    /// without decoding the rows. Returns the number of rows deleted.
+    pub(crate) fn truncate_relation<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        rel: &Symbol,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        poison: &Poison,
+    ) -> Result<usize> {
+        let handle = self.get_relation(rel, false)?;
+        if !handle.has_no_index()
+            || !handle.rm_triggers.is_empty()
+            || handle.tx_history.is_some()
+            || callback_targets.contains(&handle.name)
+            || handle.is_temp
+        {
+            return self.delete_where(
+                db,
+                rel,
+                None,
+                cur_vld,
+                callback_targets,
+                callback_collector,
+                poison,
+            );
+        }
+        db.check_mutation(&handle.name, MutationKind::Rm)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "row removal".to_string(),
+                handle.access_level
+            ));
+        }
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let mut deleted = 0;
+        loop {
+            poison.check()?;
+            let keys: Vec<_> = self
+                .store_tx
+                .range_scan(&lower, &upper)
+                .take(BULK_DELETE_BATCH)
+                .map_ok(|(key, _)| key)
+                .try_collect()?;
+            for key in &keys {
+                self.store_tx.del(key)?;
+            }
+            deleted += keys.len();
+            if keys.len() < BULK_DELETE_BATCH {
+                break;
+            }
+        }
+        Ok(deleted)
+    }
+}
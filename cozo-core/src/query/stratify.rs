@@ -7,11 +7,12 @@
  */
 
 use std::collections::btree_map::Entry;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
 
 use itertools::Itertools;
-use miette::{ensure, Diagnostic, Result};
-use thiserror::Error;
+use miette::{bail, Diagnostic, LabeledSpan, Result};
 
 use crate::data::program::{
     FixedRuleArg, MagicSymbol, NormalFormAtom, NormalFormProgram, NormalFormRulesOrFixed,
@@ -32,7 +33,8 @@ impl NormalFormAtom {
             | NormalFormAtom::Unification(_)
             | NormalFormAtom::HnswSearch(_)
             | NormalFormAtom::FtsSearch(_)
-            | NormalFormAtom::LshSearch(_) => Default::default(),
+            | NormalFormAtom::LshSearch(_)
+            | NormalFormAtom::GeoSearch(_) => Default::default(),
             NormalFormAtom::Rule(r) => BTreeMap::from([(&r.name, false)]),
             NormalFormAtom::NegatedRule(r) => BTreeMap::from([(&r.name, true)]),
         }
@@ -156,28 +158,221 @@ fn reduce_to_graph<'a>(g: &StratifiedGraph<&'a Symbol>) -> Graph<&'a Symbol> {
         .collect()
 }
 
-fn verify_no_cycle(g: &StratifiedGraph<&'_ Symbol>, sccs: &[BTreeSet<&Symbol>]) -> Result<()> {
+/// Why a rule depends on another in a way that forbids them from being in the same stratum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DependencyKind {
+    Plain,
+    Negation,
+    Aggregation,
+    FixedRuleInput,
+    FixedRuleOutput,
+}
+
+/// A dependency of a rule on another in a cycle, with the span of the application causing it.
+#[derive(Debug)]
+struct CycleEdge {
+    from: String,
+    to: String,
+    kind: DependencyKind,
+    span: SourceSpan,
+}
+
+impl CycleEdge {
+    fn label(&self) -> String {
+        let CycleEdge { from, to, .. } = self;
+        match self.kind {
+            DependencyKind::Plain => format!("'{from}' depends on '{to}' here"),
+            DependencyKind::Negation => format!("'{from}' negates '{to}' here"),
+            DependencyKind::Aggregation => format!("'{from}' aggregates over '{to}' here"),
+            DependencyKind::FixedRuleInput => {
+                format!("'{to}' is passed to the fixed rule '{from}' here")
+            }
+            DependencyKind::FixedRuleOutput => {
+                format!("'{from}' uses the output of the fixed rule '{to}' here")
+            }
+        }
+    }
+    fn suggestion(&self) -> String {
+        let CycleEdge { from, to, .. } = self;
+        match self.kind {
+            DependencyKind::Plain | DependencyKind::Negation => format!(
+                "Split '{from}' into a rule without the negation, for '{to}' to depend on, \
+                and a rule negating '{to}' on top of it; or make '{to}' independent of '{from}'."
+            ),
+            DependencyKind::Aggregation => format!(
+                "Only meet aggregations such as min, max, union and intersection can be recursive. \
+                Compute what '{to}' needs in a rule without aggregation, and aggregate over it \
+                in a separate rule that nothing in the cycle depends on."
+            ),
+            DependencyKind::FixedRuleInput | DependencyKind::FixedRuleOutput => format!(
+                "Fixed rules cannot be recursive. Compute the input of the fixed rule \
+                in rules that do not depend on its output, splitting '{from}' or '{to}' if needed."
+            ),
+        }
+    }
+}
+
+/// A cycle of dependencies that contains a forbidden one, see [DependencyKind].
+#[derive(Debug)]
+struct UnStratifiableProgram(Vec<CycleEdge>);
+
+impl Error for UnStratifiableProgram {}
+
+impl Display for UnStratifiableProgram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Query is unstratifiable: rule '{}' depends on itself through {}",
+            self.0[0].from,
+            match self.0[0].kind {
+                DependencyKind::Negation => "negation",
+                DependencyKind::Aggregation => "aggregation",
+                _ => "a fixed rule",
+            }
+        )
+    }
+}
+
+impl Diagnostic for UnStratifiableProgram {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new("eval::unstratifiable"))
+    }
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let cycle = self
+            .0
+            .iter()
+            .map(|e| format!("'{}'", e.from))
+            .chain([format!("'{}'", self.0[0].from)])
+            .join(" -> ");
+        Some(Box::new(format!(
+            "The dependency cycle is {cycle}. {}",
+            self.0[0].suggestion()
+        )))
+    }
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        Some(Box::new(self.0.iter().map(|e| {
+            LabeledSpan::new_with_span(Some(e.label()), e.span)
+        })))
+    }
+}
+
+/// How and where `from` depends on `to` in the program.
+fn dependency(
+    prog: &NormalFormProgram,
+    from: &Symbol,
+    to: &Symbol,
+) -> (DependencyKind, SourceSpan) {
+    let is_fixed = |name: &Symbol| {
+        matches!(
+            prog.prog.get(name),
+            Some(NormalFormRulesOrFixed::Fixed { .. })
+        )
+    };
+    let has_aggr = |name: &Symbol| match prog.prog.get(name) {
+        Some(NormalFormRulesOrFixed::Rules { rules }) => rules
+            .iter()
+            .any(|rule| rule.aggr.iter().any(|a| a.is_some())),
+        _ => false,
+    };
+    match prog.prog.get(from) {
+        Some(NormalFormRulesOrFixed::Fixed { fixed }) => {
+            let span = fixed
+                .rule_args
+                .iter()
+                .find_map(|arg| match arg {
+                    FixedRuleArg::InMem { name, span, .. } if name == to => Some(*span),
+                    _ => None,
+                })
+                .unwrap_or(fixed.span);
+            (DependencyKind::FixedRuleInput, span)
+        }
+        Some(NormalFormRulesOrFixed::Rules { rules }) => {
+            let mut applied = None;
+            for atom in rules.iter().flat_map(|rule| rule.body.iter()) {
+                match atom {
+                    NormalFormAtom::NegatedRule(r) if &r.name == to => {
+                        return (DependencyKind::Negation, r.span)
+                    }
+                    NormalFormAtom::Rule(r) if &r.name == to && applied.is_none() => {
+                        applied = Some(r.span)
+                    }
+                    _ => {}
+                }
+            }
+            let span = applied.unwrap_or(from.span);
+            let kind = if is_fixed(to) {
+                DependencyKind::FixedRuleOutput
+            } else if has_aggr(from) || (from != to && has_aggr(to)) {
+                DependencyKind::Aggregation
+            } else {
+                DependencyKind::Plain
+            };
+            (kind, span)
+        }
+        None => (DependencyKind::Plain, from.span),
+    }
+}
+
+/// The shortest path from `from` to `to` within the SCC, as the list of nodes after `from`.
+fn path_in_scc<'a>(
+    g: &StratifiedGraph<&'a Symbol>,
+    scc: &BTreeSet<&'a Symbol>,
+    from: &'a Symbol,
+    to: &'a Symbol,
+) -> Vec<&'a Symbol> {
+    let mut previous: BTreeMap<&Symbol, &Symbol> = BTreeMap::new();
+    let mut queue = VecDeque::from([from]);
+    while let Some(cur) = queue.pop_front() {
+        if cur == to {
+            break;
+        }
+        for next in g.get(cur).into_iter().flat_map(|vs| vs.keys()) {
+            if scc.contains(next) && *next != from && !previous.contains_key(next) {
+                previous.insert(next, cur);
+                queue.push_back(next);
+            }
+        }
+    }
+    let mut path = vec![];
+    let mut cur = to;
+    while cur != from {
+        path.push(cur);
+        cur = previous[cur];
+    }
+    path.reverse();
+    path
+}
+
+fn verify_no_cycle(
+    prog: &NormalFormProgram,
+    g: &StratifiedGraph<&'_ Symbol>,
+    sccs: &[BTreeSet<&Symbol>],
+) -> Result<()> {
     for (k, vs) in g {
         for scc in sccs {
             if scc.contains(k) {
                 for (v, negated) in vs {
-                    #[derive(Debug, Error, Diagnostic)]
-                    #[error("Query is unstratifiable")]
-                    #[diagnostic(code(eval::unstratifiable))]
-                    #[diagnostic(help(
-                        "The rule '{0}' is in the strongly connected component {1:?},\n\
-                    and is involved in at least one forbidden dependency \n\
-                    (negation, non-meet aggregation, or algorithm-application)."
-                    ))]
-                    struct UnStratifiableProgram(String, Vec<String>);
-
-                    ensure!(
-                        !negated || !scc.contains(v),
-                        UnStratifiableProgram(
-                            v.to_string(),
-                            scc.iter().map(|v| v.to_string()).collect_vec()
-                        )
-                    );
+                    if *negated && scc.contains(v) {
+                        // the forbidden dependency first, then back to where it starts
+                        let mut nodes = vec![*k, *v];
+                        if v != k {
+                            nodes.extend(path_in_scc(g, scc, v, k));
+                        }
+                        let cycle = nodes
+                            .iter()
+                            .tuple_windows()
+                            .map(|(from, to)| {
+                                let (kind, span) = dependency(prog, from, to);
+                                CycleEdge {
+                                    from: from.to_string(),
+                                    to: to.to_string(),
+                                    kind,
+                                    span,
+                                }
+                            })
+                            .collect_vec();
+                        bail!(UnStratifiableProgram(cycle))
+                    }
                 }
             }
         }
@@ -251,7 +446,7 @@ impl NormalFormProgram {
             .map(|scc| scc.into_iter().cloned().collect())
             .collect_vec();
         // 4. for each SCC, verify that no neg/agg edges are present so that it is really stratifiable
-        verify_no_cycle(&stratified_graph, &sccs)?;
+        verify_no_cycle(&self, &stratified_graph, &sccs)?;
         // 5. build a reduced graph for the SCC's
         let (invert_indices, reduced_graph) = make_scc_reduced_graph(&sccs, &stratified_graph);
         // 6. topological sort the reduced graph to get a stratification
@@ -267,6 +462,7 @@ impl NormalFormProgram {
             .map(|_| NormalFormProgram {
                 prog: BTreeMap::new(),
                 disable_magic_rewrite: self.disable_magic_rewrite,
+                no_magic_set: self.no_magic_set.clone(),
             })
             .collect_vec();
 
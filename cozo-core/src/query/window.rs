@@ -0,0 +1,383 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The `:window` query option: window functions computed over the output rows,
+//! after sorting and before `:offset` and `:limit`.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::functions::{op_add, op_sub};
+use crate::data::program::SortDir;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::NamedRows;
+
+/// Window functions, each adding a column to the output rows.
+/// Rows are partitioned by the `partition_by` columns, and within each partition
+/// ordered by the `order_by` columns, ties keeping the order of the output.
+#[derive(Clone, PartialEq)]
+pub struct QueryWindow {
+    pub(crate) functions: Vec<WindowFunction>,
+    /// The partitioning columns and their positions in the output head.
+    pub(crate) partition_by: Vec<(Symbol, usize)>,
+    /// The ordering columns and their positions in the output head.
+    pub(crate) order_by: Vec<(Symbol, usize, SortDir)>,
+}
+
+#[derive(Clone, PartialEq)]
+pub(crate) struct WindowFunction {
+    pub(crate) alias: Option<Symbol>,
+    /// The source of the call, naming the column unless there is an alias.
+    pub(crate) source: String,
+    pub(crate) kind: WindowFunctionKind,
+}
+
+impl WindowFunction {
+    fn header(&self) -> String {
+        match &self.alias {
+            Some(alias) => alias.to_string(),
+            None => self.source.clone(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub(crate) enum WindowFunctionKind {
+    /// Position in the partition, from 1.
+    RowNumber,
+    /// Position of the first row with the same ordering values, from 1.
+    Rank,
+    /// Number of distinct ordering values up to the row.
+    DenseRank,
+    /// The value `offset` rows before, or `default`.
+    Lag {
+        expr: Expr,
+        offset: usize,
+        default: DataValue,
+    },
+    /// The value `offset` rows after, or `default`.
+    Lead {
+        expr: Expr,
+        offset: usize,
+        default: DataValue,
+    },
+    /// The value minus the value `offset` rows before, or null.
+    Diff {
+        expr: Expr,
+        offset: usize,
+    },
+    FirstValue(Expr),
+    LastValue(Expr),
+    /// Running sum, row by row.
+    CumSum(Expr),
+    /// Running minimum, row by row.
+    CumMin(Expr),
+    /// Running maximum, row by row.
+    CumMax(Expr),
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unknown window function '{0}'")]
+#[diagnostic(code(parser::unknown_window_function))]
+#[diagnostic(help(
+    "The window functions are row_number, rank, dense_rank, lag, lead, diff, \
+    first_value, last_value, cumsum, cummin and cummax"
+))]
+struct UnknownWindowFunction(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Wrong arguments to window function '{0}'")]
+#[diagnostic(code(parser::bad_window_function_args))]
+#[diagnostic(help("{2}"))]
+struct BadWindowFunctionArgs(String, #[label] SourceSpan, &'static str);
+
+impl WindowFunctionKind {
+    /// Arguments after the first must be constants.
+    pub(crate) fn new(name: &str, args: Vec<Expr>, span: SourceSpan) -> Result<Self> {
+        let bad_args = |help| BadWindowFunctionArgs(name.to_string(), span, help);
+        let mut args = args.into_iter();
+        let mut consts = vec![];
+        let first = args.next();
+        for arg in args {
+            match arg.eval_to_const() {
+                Ok(val) => consts.push(val),
+                Err(_) => bail!(bad_args("only the first argument may refer to columns")),
+            }
+        }
+        let offset = |val: Option<&DataValue>| match val {
+            None => Ok(1),
+            Some(val) => match val.get_non_neg_int() {
+                Some(i) => Ok(i as usize),
+                None => Err(bad_args("the offset must be a non-negative integer")),
+            },
+        };
+        Ok(match (name, first) {
+            ("row_number", None) => Self::RowNumber,
+            ("rank", None) => Self::Rank,
+            ("dense_rank", None) => Self::DenseRank,
+            ("row_number" | "rank" | "dense_rank", Some(_)) => {
+                bail!(bad_args("this function takes no arguments"))
+            }
+            ("lag" | "lead", Some(expr)) if consts.len() <= 2 => {
+                let offset = offset(consts.first())?;
+                let default = consts.get(1).cloned().unwrap_or(DataValue::Null);
+                if name == "lag" {
+                    Self::Lag {
+                        expr,
+                        offset,
+                        default,
+                    }
+                } else {
+                    Self::Lead {
+                        expr,
+                        offset,
+                        default,
+                    }
+                }
+            }
+            ("lag" | "lead", _) => bail!(bad_args(
+                "the arguments are a value, an optional offset and an optional default"
+            )),
+            ("diff", Some(expr)) if consts.len() <= 1 => Self::Diff {
+                offset: offset(consts.first())?,
+                expr,
+            },
+            ("diff", _) => bail!(bad_args("the arguments are a value and an optional offset")),
+            ("first_value" | "last_value" | "cumsum" | "cummin" | "cummax", Some(expr))
+                if consts.is_empty() =>
+            {
+                match name {
+                    "first_value" => Self::FirstValue(expr),
+                    "last_value" => Self::LastValue(expr),
+                    "cumsum" => Self::CumSum(expr),
+                    "cummin" => Self::CumMin(expr),
+                    _ => Self::CumMax(expr),
+                }
+            }
+            ("first_value" | "last_value" | "cumsum" | "cummin" | "cummax", _) => {
+                bail!(bad_args("this function takes exactly one argument"))
+            }
+            _ => bail!(UnknownWindowFunction(name.to_string(), span)),
+        })
+    }
+    pub(crate) fn expr_mut(&mut self) -> Option<&mut Expr> {
+        match self {
+            Self::RowNumber | Self::Rank | Self::DenseRank => None,
+            Self::Lag { expr, .. } | Self::Lead { expr, .. } | Self::Diff { expr, .. } => {
+                Some(expr)
+            }
+            Self::FirstValue(expr)
+            | Self::LastValue(expr)
+            | Self::CumSum(expr)
+            | Self::CumMin(expr)
+            | Self::CumMax(expr) => Some(expr),
+        }
+    }
+    /// The values for the rows of a partition, in order.
+    /// `peers[i]` tells if row `i` has the same ordering values as the row before.
+    fn compute(&self, rows: &[&Tuple], peers: &[bool]) -> Result<Vec<DataValue>> {
+        let eval = |expr: &Expr| -> Result<Vec<DataValue>> {
+            rows.iter().map(|row| expr.eval(row)).try_collect()
+        };
+        Ok(match self {
+            Self::RowNumber => (1..=rows.len() as i64).map(DataValue::from).collect(),
+            Self::Rank => {
+                let mut rank = 0;
+                (0..rows.len())
+                    .map(|i| {
+                        if !peers[i] {
+                            rank = i as i64 + 1;
+                        }
+                        DataValue::from(rank)
+                    })
+                    .collect()
+            }
+            Self::DenseRank => {
+                let mut rank = 0;
+                peers
+                    .iter()
+                    .map(|peer| {
+                        if !peer {
+                            rank += 1;
+                        }
+                        DataValue::from(rank)
+                    })
+                    .collect()
+            }
+            Self::Lag {
+                expr,
+                offset,
+                default,
+            } => {
+                let vals = eval(expr)?;
+                (0..vals.len())
+                    .map(|i| match i.checked_sub(*offset) {
+                        Some(j) => vals[j].clone(),
+                        None => default.clone(),
+                    })
+                    .collect()
+            }
+            Self::Lead {
+                expr,
+                offset,
+                default,
+            } => {
+                let vals = eval(expr)?;
+                (0..vals.len())
+                    .map(|i| vals.get(i + offset).unwrap_or(default).clone())
+                    .collect()
+            }
+            Self::Diff { expr, offset } => {
+                let vals = eval(expr)?;
+                (0..vals.len())
+                    .map(|i| match i.checked_sub(*offset) {
+                        Some(j) => op_sub(&[vals[i].clone(), vals[j].clone()]),
+                        None => Ok(DataValue::Null),
+                    })
+                    .try_collect()?
+            }
+            Self::FirstValue(expr) => match rows.first() {
+                None => vec![],
+                Some(row) => vec![expr.eval(row)?; rows.len()],
+            },
+            Self::LastValue(expr) => match rows.last() {
+                None => vec![],
+                Some(row) => vec![expr.eval(row)?; rows.len()],
+            },
+            Self::CumSum(expr) => {
+                let mut sum = DataValue::from(0);
+                eval(expr)?
+                    .into_iter()
+                    .map(|val| -> Result<DataValue> {
+                        sum = op_add(&[sum.clone(), val])?;
+                        Ok(sum.clone())
+                    })
+                    .try_collect()?
+            }
+            Self::CumMin(expr) | Self::CumMax(expr) => {
+                let is_min = matches!(self, Self::CumMin(_));
+                let mut best: Option<DataValue> = None;
+                eval(expr)?
+                    .into_iter()
+                    .map(|val| {
+                        let better = match &best {
+                            None => true,
+                            Some(b) if is_min => val < *b,
+                            Some(b) => val > *b,
+                        };
+                        if better {
+                            best = Some(val);
+                        }
+                        best.clone().unwrap()
+                    })
+                    .collect()
+            }
+        })
+    }
+}
+
+impl Display for QueryWindow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let functions = self
+            .functions
+            .iter()
+            .map(|func| match &func.alias {
+                Some(alias) => format!("{alias} = {}", func.source),
+                None => func.source.clone(),
+            })
+            .join(", ");
+        write!(f, "{functions}")?;
+        if self.partition_by.is_empty() && self.order_by.is_empty() {
+            return Ok(());
+        }
+        write!(f, " over (")?;
+        if !self.partition_by.is_empty() {
+            let keys = self.partition_by.iter().map(|(k, _)| k).join(", ");
+            write!(f, "partition by {keys}")?;
+            if !self.order_by.is_empty() {
+                write!(f, " ")?;
+            }
+        }
+        if !self.order_by.is_empty() {
+            let keys = self
+                .order_by
+                .iter()
+                .map(|(k, _, dir)| match dir {
+                    SortDir::Asc => format!("{k}"),
+                    SortDir::Dsc => format!("-{k}"),
+                })
+                .join(", ");
+            write!(f, "order by {keys}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl QueryWindow {
+    /// Append the values of the window functions to the rows, which keep their order.
+    pub(crate) fn apply(&self, head: &[Symbol], mut rows: Vec<Tuple>) -> Result<NamedRows> {
+        #[allow(clippy::mutable_key_type)]
+        let mut partitions: BTreeMap<Tuple, Vec<usize>> = BTreeMap::new();
+        for (i, row) in rows.iter().enumerate() {
+            let key = self
+                .partition_by
+                .iter()
+                .map(|(_, j)| row[*j].clone())
+                .collect_vec();
+            partitions.entry(key).or_default().push(i);
+        }
+        let cmp_order = |a: &Tuple, b: &Tuple| {
+            for (_, idx, dir) in &self.order_by {
+                match a[*idx].cmp(&b[*idx]) {
+                    Ordering::Equal => {}
+                    o => {
+                        return match dir {
+                            SortDir::Asc => o,
+                            SortDir::Dsc => o.reverse(),
+                        }
+                    }
+                }
+            }
+            Ordering::Equal
+        };
+
+        let mut extra: Vec<Vec<DataValue>> = vec![vec![]; rows.len()];
+        for (_, mut indices) in partitions {
+            // a stable sort, so that ties keep the order of the output
+            indices.sort_by(|a, b| cmp_order(&rows[*a], &rows[*b]));
+            let part = indices.iter().map(|i| &rows[*i]).collect_vec();
+            let peers = (0..part.len())
+                .map(|i| i > 0 && cmp_order(part[i - 1], part[i]) == Ordering::Equal)
+                .collect_vec();
+            for func in &self.functions {
+                let vals = func.kind.compute(&part, &peers)?;
+                for (i, val) in indices.iter().zip(vals) {
+                    extra[*i].push(val);
+                }
+            }
+        }
+        for (row, vals) in rows.iter_mut().zip(extra) {
+            row.extend(vals);
+        }
+        let headers = head
+            .iter()
+            .map(|s| s.to_string())
+            .chain(self.functions.iter().map(|func| func.header()))
+            .collect_vec();
+        Ok(NamedRows::new(headers, rows))
+    }
+}
@@ -0,0 +1,272 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Conversion of [NamedRows] to and from Arrow record batches.
+//!
+//! Each column becomes the narrowest Arrow type holding all its values: booleans, 64-bit ints,
+//! doubles (ints and floats mixed), UTF-8 strings, binaries, microsecond durations, or lists of
+//! floats for vectors. Other columns, and columns mixing types, become strings,
+//! with values that are not strings written as JSON.
+
+use std::sync::Arc;
+
+use arrow_array::builder::{Float32Builder, Float64Builder, ListBuilder};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    DurationMicrosecondType, DurationMillisecondType, DurationNanosecondType, DurationSecondType,
+    Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, TimestampMicrosecondType,
+    TimestampMillisecondType, TimestampNanosecondType, TimestampSecondType, UInt16Type, UInt32Type,
+    UInt64Type, UInt8Type,
+};
+use arrow_array::{
+    Array, ArrayRef, BinaryArray, BooleanArray, DurationMicrosecondArray, Float64Array, Int64Array,
+    NullArray, RecordBatch, RecordBatchOptions, StringArray,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::BigDecimal;
+use miette::{bail, IntoDiagnostic, Result};
+
+use crate::data::json::JsonValue;
+use crate::data::value::{DataValue, Num, Vector};
+use crate::runtime::db::NamedRows;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum ColumnKind {
+    Null,
+    Bool,
+    Int,
+    Float,
+    Str,
+    Bytes,
+    Duration,
+    F32Vec,
+    F64Vec,
+    Text,
+}
+
+impl ColumnKind {
+    fn of(v: &DataValue) -> Self {
+        match v {
+            DataValue::Null => ColumnKind::Null,
+            DataValue::Bool(_) => ColumnKind::Bool,
+            DataValue::Num(Num::Int(_)) => ColumnKind::Int,
+            DataValue::Num(Num::Float(_)) => ColumnKind::Float,
+            DataValue::Str(_) => ColumnKind::Str,
+            DataValue::Bytes(_) => ColumnKind::Bytes,
+            DataValue::Duration(_) => ColumnKind::Duration,
+            DataValue::Vec(Vector::F32(_)) => ColumnKind::F32Vec,
+            DataValue::Vec(Vector::F64(_)) => ColumnKind::F64Vec,
+            _ => ColumnKind::Text,
+        }
+    }
+    fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnKind::Null, k) | (k, ColumnKind::Null) => k,
+            (ColumnKind::Int, ColumnKind::Float) | (ColumnKind::Float, ColumnKind::Int) => {
+                ColumnKind::Float
+            }
+            _ => ColumnKind::Text,
+        }
+    }
+    fn data_type(self) -> DataType {
+        match self {
+            ColumnKind::Null => DataType::Null,
+            ColumnKind::Bool => DataType::Boolean,
+            ColumnKind::Int => DataType::Int64,
+            ColumnKind::Float => DataType::Float64,
+            ColumnKind::Str | ColumnKind::Text => DataType::Utf8,
+            ColumnKind::Bytes => DataType::Binary,
+            ColumnKind::Duration => DataType::Duration(TimeUnit::Microsecond),
+            ColumnKind::F32Vec => {
+                DataType::List(Arc::new(Field::new("item", DataType::Float32, true)))
+            }
+            ColumnKind::F64Vec => {
+                DataType::List(Arc::new(Field::new("item", DataType::Float64, true)))
+            }
+        }
+    }
+}
+
+fn to_text(v: &DataValue) -> String {
+    match JsonValue::from(v.clone()) {
+        JsonValue::String(s) => s,
+        j => j.to_string(),
+    }
+}
+
+fn build_column(rows: &[Vec<DataValue>], idx: usize, kind: ColumnKind) -> ArrayRef {
+    let values = rows.iter().map(|row| &row[idx]);
+    match kind {
+        ColumnKind::Null => Arc::new(NullArray::new(rows.len())),
+        ColumnKind::Bool => Arc::new(values.map(|v| v.get_bool()).collect::<BooleanArray>()),
+        ColumnKind::Int => Arc::new(values.map(|v| v.get_int()).collect::<Int64Array>()),
+        ColumnKind::Float => Arc::new(values.map(|v| v.get_float()).collect::<Float64Array>()),
+        ColumnKind::Str => Arc::new(values.map(|v| v.get_str()).collect::<StringArray>()),
+        ColumnKind::Text => Arc::new(
+            values
+                .map(|v| match v {
+                    DataValue::Null => None,
+                    v => Some(to_text(v)),
+                })
+                .collect::<StringArray>(),
+        ),
+        ColumnKind::Bytes => Arc::new(values.map(|v| v.get_bytes()).collect::<BinaryArray>()),
+        ColumnKind::Duration => Arc::new(
+            values
+                .map(|v| match v {
+                    DataValue::Duration(d) => Some(*d),
+                    _ => None,
+                })
+                .collect::<DurationMicrosecondArray>(),
+        ),
+        ColumnKind::F32Vec => {
+            let mut builder = ListBuilder::new(Float32Builder::new());
+            for v in values {
+                match v {
+                    DataValue::Vec(Vector::F32(a)) => {
+                        builder.values().append_slice(&a.to_vec());
+                        builder.append(true);
+                    }
+                    _ => builder.append(false),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        ColumnKind::F64Vec => {
+            let mut builder = ListBuilder::new(Float64Builder::new());
+            for v in values {
+                match v {
+                    DataValue::Vec(Vector::F64(a)) => {
+                        builder.values().append_slice(&a.to_vec());
+                        builder.append(true);
+                    }
+                    _ => builder.append(false),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+    }
+}
+
+/// The value at position `i` of an Arrow array.
+fn arrow_value(array: &dyn Array, i: usize) -> Result<DataValue> {
+    if array.is_null(i) {
+        return Ok(DataValue::Null);
+    }
+    let micros = |v: i64, unit: &TimeUnit| match unit {
+        TimeUnit::Second => v.saturating_mul(1_000_000),
+        TimeUnit::Millisecond => v.saturating_mul(1_000),
+        TimeUnit::Microsecond => v,
+        TimeUnit::Nanosecond => v / 1_000,
+    };
+    Ok(match array.data_type() {
+        DataType::Null => DataValue::Null,
+        DataType::Boolean => DataValue::from(array.as_boolean().value(i)),
+        DataType::Int8 => DataValue::from(array.as_primitive::<Int8Type>().value(i) as i64),
+        DataType::Int16 => DataValue::from(array.as_primitive::<Int16Type>().value(i) as i64),
+        DataType::Int32 => DataValue::from(array.as_primitive::<Int32Type>().value(i) as i64),
+        DataType::Int64 => DataValue::from(array.as_primitive::<Int64Type>().value(i)),
+        DataType::UInt8 => DataValue::from(array.as_primitive::<UInt8Type>().value(i) as i64),
+        DataType::UInt16 => DataValue::from(array.as_primitive::<UInt16Type>().value(i) as i64),
+        DataType::UInt32 => DataValue::from(array.as_primitive::<UInt32Type>().value(i) as i64),
+        DataType::UInt64 => {
+            let v = array.as_primitive::<UInt64Type>().value(i);
+            match i64::try_from(v) {
+                Ok(v) => DataValue::from(v),
+                Err(_) => DataValue::Decimal(BigDecimal::from(v)),
+            }
+        }
+        DataType::Float32 => DataValue::from(array.as_primitive::<Float32Type>().value(i) as f64),
+        DataType::Float64 => DataValue::from(array.as_primitive::<Float64Type>().value(i)),
+        DataType::Decimal128(_, scale) => DataValue::Decimal(BigDecimal::new(
+            BigInt::from(
+                array
+                    .as_primitive::<arrow_array::types::Decimal128Type>()
+                    .value(i),
+            ),
+            *scale as i64,
+        )),
+        DataType::Utf8 => DataValue::from(array.as_string::<i32>().value(i)),
+        DataType::LargeUtf8 => DataValue::from(array.as_string::<i64>().value(i)),
+        DataType::Binary => DataValue::Bytes(array.as_binary::<i32>().value(i).to_vec()),
+        DataType::LargeBinary => DataValue::Bytes(array.as_binary::<i64>().value(i).to_vec()),
+        DataType::Duration(unit) => {
+            let v = match unit {
+                TimeUnit::Second => array.as_primitive::<DurationSecondType>().value(i),
+                TimeUnit::Millisecond => array.as_primitive::<DurationMillisecondType>().value(i),
+                TimeUnit::Microsecond => array.as_primitive::<DurationMicrosecondType>().value(i),
+                TimeUnit::Nanosecond => array.as_primitive::<DurationNanosecondType>().value(i),
+            };
+            DataValue::Duration(micros(v, unit))
+        }
+        // timestamps become seconds since the epoch, like the result of `now()`
+        DataType::Timestamp(unit, _) => {
+            let v = match unit {
+                TimeUnit::Second => array.as_primitive::<TimestampSecondType>().value(i),
+                TimeUnit::Millisecond => array.as_primitive::<TimestampMillisecondType>().value(i),
+                TimeUnit::Microsecond => array.as_primitive::<TimestampMicrosecondType>().value(i),
+                TimeUnit::Nanosecond => array.as_primitive::<TimestampNanosecondType>().value(i),
+            };
+            DataValue::from(micros(v, unit) as f64 / 1e6)
+        }
+        DataType::List(_) => list_values(array.as_list::<i32>().value(i).as_ref())?,
+        DataType::LargeList(_) => list_values(array.as_list::<i64>().value(i).as_ref())?,
+        DataType::FixedSizeList(_, _) => list_values(array.as_fixed_size_list().value(i).as_ref())?,
+        t => bail!("Arrow type {} is not supported", t),
+    })
+}
+
+fn list_values(array: &dyn Array) -> Result<DataValue> {
+    Ok(DataValue::List(
+        (0..array.len())
+            .map(|i| arrow_value(array, i))
+            .collect::<Result<_>>()?,
+    ))
+}
+
+impl NamedRows {
+    /// Convert to an Arrow record batch, ignoring the named rows following this one.
+    pub fn to_arrow(&self) -> Result<RecordBatch> {
+        let mut fields = Vec::with_capacity(self.headers.len());
+        let mut columns = Vec::with_capacity(self.headers.len());
+        for (idx, header) in self.headers.iter().enumerate() {
+            let kind = self.rows.iter().fold(ColumnKind::Null, |k, row| {
+                k.merge(ColumnKind::of(&row[idx]))
+            });
+            fields.push(Field::new(header, kind.data_type(), true));
+            columns.push(build_column(&self.rows, idx, kind));
+        }
+        RecordBatch::try_new_with_options(
+            Arc::new(Schema::new(fields)),
+            columns,
+            &RecordBatchOptions::new().with_row_count(Some(self.rows.len())),
+        )
+        .into_diagnostic()
+    }
+    /// Make named rows from an Arrow record batch
+    pub fn from_arrow(batch: &RecordBatch) -> Result<Self> {
+        let headers = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().to_string())
+            .collect();
+        let rows = (0..batch.num_rows())
+            .map(|i| {
+                batch
+                    .columns()
+                    .iter()
+                    .map(|col| arrow_value(col.as_ref(), i))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(NamedRows::new(headers, rows))
+    }
+}
@@ -0,0 +1,377 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Backup files in the v2 format. The file starts with a magic string, followed by
+//! zstd-compressed segments: one for the catalog and the other system data, and one for each
+//! stored relation, index and history relation. A JSON manifest locating the segments is
+//! written after them, and the file ends with the offset of the manifest and the magic string.
+//! Segments contain entries made of the lengths of the key and the value as little-endian `u32`,
+//! followed by the key and the value, as stored.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use miette::{bail, ensure, miette, IntoDiagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+
+use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::db::{seconds_since_the_epoch, Db, RestoreOptions};
+use crate::runtime::relation::{RelationHandle, RelationId};
+use crate::Storage;
+
+pub(crate) const BACKUP_V2_MAGIC: &[u8; 8] = b"COZOBAK2";
+const BACKUP_V2_VERSION: u32 = 2;
+const COMPRESSION_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SegmentKind {
+    System,
+    Relation,
+    Index,
+    History,
+}
+
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct BackupSegment {
+    pub(crate) name: String,
+    pub(crate) kind: SegmentKind,
+    /// The stored relation the segment belongs to, empty for the system segment
+    pub(crate) relation: String,
+    pub(crate) offset: u64,
+    pub(crate) length: u64,
+    pub(crate) entries: u64,
+}
+
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct BackupManifest {
+    pub(crate) version: u32,
+    pub(crate) created: f64,
+    pub(crate) segments: Vec<BackupSegment>,
+}
+
+/// Whether the file is a backup in the v2 format, as opposed to a v1 (Sqlite) backup.
+pub(crate) fn is_backup_v2(path: impl AsRef<Path>) -> Result<bool> {
+    let mut file = File::open(path).into_diagnostic()?;
+    let mut magic = [0u8; 8];
+    Ok(file.read_exact(&mut magic).is_ok() && magic == *BACKUP_V2_MAGIC)
+}
+
+/// The name of the relation if the system entry is its catalog entry.
+fn catalog_entry_name(key: &[u8]) -> Option<String> {
+    match decode_tuple_from_key(key, 1).as_slice() {
+        [DataValue::Str(name)] => Some(name.to_string()),
+        _ => None,
+    }
+}
+
+fn relation_range(id: RelationId) -> (Vec<u8>, Vec<u8>) {
+    (
+        Tuple::default().encode_as_key(id),
+        Tuple::default().encode_as_key(id.next()),
+    )
+}
+
+fn write_entry(out: &mut impl Write, k: &[u8], v: &[u8]) -> Result<()> {
+    out.write_all(&(k.len() as u32).to_le_bytes())
+        .into_diagnostic()?;
+    out.write_all(&(v.len() as u32).to_le_bytes())
+        .into_diagnostic()?;
+    out.write_all(k).into_diagnostic()?;
+    out.write_all(v).into_diagnostic()
+}
+
+fn read_entry(input: &mut impl Read) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut lens = [0u8; 8];
+    input
+        .read_exact(&mut lens)
+        .map_err(|e| miette!("Cannot read backup: {e}"))?;
+    let k_len = u32::from_le_bytes(lens[..4].try_into().unwrap()) as usize;
+    let v_len = u32::from_le_bytes(lens[4..].try_into().unwrap()) as usize;
+    let mut k = vec![0u8; k_len];
+    let mut v = vec![0u8; v_len];
+    input
+        .read_exact(&mut k)
+        .and_then(|_| input.read_exact(&mut v))
+        .map_err(|e| miette!("Cannot read backup: {e}"))?;
+    Ok((k, v))
+}
+
+struct BackupWriter {
+    out: BufWriter<File>,
+    segments: Vec<BackupSegment>,
+}
+
+impl BackupWriter {
+    fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| miette!("Cannot create backup: {e}"))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(BACKUP_V2_MAGIC).into_diagnostic()?;
+        Ok(Self {
+            out,
+            segments: vec![],
+        })
+    }
+    fn write_segment(
+        &mut self,
+        name: &str,
+        kind: SegmentKind,
+        relation: &str,
+        data: impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+    ) -> Result<()> {
+        let offset = self.out.stream_position().into_diagnostic()?;
+        let mut encoder = zstd::Encoder::new(&mut self.out, COMPRESSION_LEVEL).into_diagnostic()?;
+        let mut entries = 0;
+        for kv in data {
+            let (k, v) = kv?;
+            write_entry(&mut encoder, &k, &v)?;
+            entries += 1;
+        }
+        encoder.finish().into_diagnostic()?;
+        let length = self.out.stream_position().into_diagnostic()? - offset;
+        self.segments.push(BackupSegment {
+            name: name.to_string(),
+            kind,
+            relation: relation.to_string(),
+            offset,
+            length,
+            entries,
+        });
+        Ok(())
+    }
+    fn finish(mut self) -> Result<()> {
+        let manifest = BackupManifest {
+            version: BACKUP_V2_VERSION,
+            created: seconds_since_the_epoch()?,
+            segments: self.segments,
+        };
+        let offset = self.out.stream_position().into_diagnostic()?;
+        serde_json::to_writer(&mut self.out, &manifest).into_diagnostic()?;
+        self.out
+            .write_all(&offset.to_le_bytes())
+            .into_diagnostic()?;
+        self.out.write_all(BACKUP_V2_MAGIC).into_diagnostic()?;
+        self.out.flush().into_diagnostic()
+    }
+}
+
+pub(crate) struct BackupReader {
+    path: PathBuf,
+    pub(crate) manifest: BackupManifest,
+}
+
+impl BackupReader {
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = File::open(&path).map_err(|e| miette!("Cannot open backup: {e}"))?;
+        let len = file.metadata().into_diagnostic()?.len();
+        let mut head = [0u8; 8];
+        let mut tail = [0u8; 16];
+        ensure!(
+            len >= 24
+                && file.read_exact(&mut head).is_ok()
+                && head == *BACKUP_V2_MAGIC
+                && file.seek(SeekFrom::End(-16)).is_ok()
+                && file.read_exact(&mut tail).is_ok()
+                && tail[8..] == *BACKUP_V2_MAGIC,
+            "Cannot read backup: the file is not a Cozo backup, or it is truncated"
+        );
+        let offset = u64::from_le_bytes(tail[..8].try_into().unwrap());
+        ensure!(
+            (8..=len - 16).contains(&offset),
+            "Cannot read backup: bad manifest offset"
+        );
+        file.seek(SeekFrom::Start(offset)).into_diagnostic()?;
+        let mut manifest = vec![0u8; (len - 16 - offset) as usize];
+        file.read_exact(&mut manifest).into_diagnostic()?;
+        let manifest: BackupManifest = serde_json::from_slice(&manifest)
+            .map_err(|e| miette!("Cannot read backup manifest: {e}"))?;
+        if manifest.version != BACKUP_V2_VERSION {
+            bail!(
+                "Cannot read backup: unsupported version {}",
+                manifest.version
+            )
+        }
+        Ok(Self { path, manifest })
+    }
+    fn segment_named(&self, name: &str) -> Option<&BackupSegment> {
+        self.manifest.segments.iter().find(|s| s.name == name)
+    }
+    /// The entries of a segment, decompressed as they are read. Each segment is read
+    /// through its own file handle, so that several of them can be read at once.
+    pub(crate) fn read_segment(
+        &self,
+        segment: &BackupSegment,
+    ) -> Result<impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>> {
+        let mut file = File::open(&self.path).into_diagnostic()?;
+        file.seek(SeekFrom::Start(segment.offset))
+            .into_diagnostic()?;
+        let mut decoder =
+            zstd::Decoder::new(BufReader::new(file).take(segment.length)).into_diagnostic()?;
+        Ok((0..segment.entries).map(move |_| read_entry(&mut decoder)))
+    }
+    /// The catalog entry of a relation in the backup.
+    fn relation_handle(&self, name: &str) -> Result<RelationHandle> {
+        let system = self
+            .manifest
+            .segments
+            .iter()
+            .find(|s| s.kind == SegmentKind::System)
+            .ok_or_else(|| miette!("Cannot read backup: system segment not found"))?;
+        for kv in self.read_segment(system)? {
+            let (k, v) = kv?;
+            if catalog_entry_name(&k).as_deref() == Some(name) {
+                return RelationHandle::decode(&v);
+            }
+        }
+        bail!("Relation {name} not found in the backup")
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    pub(crate) fn backup_db_v2(&'s self, out_file: impl AsRef<Path>) -> Result<()> {
+        let mut writer = BackupWriter::create(out_file)?;
+        let mut tx = self.transact()?;
+        let (lower, upper) = relation_range(RelationId::SYSTEM);
+        let system: Vec<_> = tx.store_tx.range_scan(&lower, &upper).try_collect()?;
+        let mut handles = BTreeMap::new();
+        for (k, v) in &system {
+            if let Some(name) = catalog_entry_name(k) {
+                handles.insert(name, RelationHandle::decode(v)?);
+            }
+        }
+        writer.write_segment("", SegmentKind::System, "", system.into_iter().map(Ok))?;
+        for (name, handle) in &handles {
+            let (kind, relation) = match name.split_once(':') {
+                None => (SegmentKind::Relation, name.as_str()),
+                Some((base, _)) => {
+                    let is_history = handles
+                        .get(base)
+                        .and_then(|h| h.tx_history.as_ref())
+                        .is_some_and(|h| h == name);
+                    if is_history {
+                        (SegmentKind::History, base)
+                    } else {
+                        (SegmentKind::Index, base)
+                    }
+                }
+            };
+            let (lower, upper) = relation_range(handle.id);
+            writer.write_segment(name, kind, relation, tx.store_tx.range_scan(&lower, &upper))?;
+        }
+        tx.commit_tx()?;
+        writer.finish()
+    }
+
+    pub(crate) fn restore_backup_v2(
+        &'s self,
+        in_file: impl AsRef<Path>,
+        options: &RestoreOptions,
+    ) -> Result<()> {
+        let reader = BackupReader::open(in_file)?;
+        self.ensure_empty_for_restore()?;
+        let in_backup: BTreeSet<&str> = reader
+            .manifest
+            .segments
+            .iter()
+            .filter(|s| s.kind == SegmentKind::Relation)
+            .map(|s| s.name.as_str())
+            .collect();
+        let selected: BTreeSet<&str> = match &options.relations {
+            None => in_backup.clone(),
+            Some(relations) => {
+                for rel in relations {
+                    ensure!(
+                        in_backup.contains(rel.as_str()),
+                        "Cannot restore backup: relation {} not found in the backup",
+                        rel
+                    );
+                }
+                relations.iter().map(|r| r.as_str()).collect()
+            }
+        };
+        let wanted = |segment: &BackupSegment| {
+            segment.kind == SegmentKind::System
+                || (selected.contains(segment.relation.as_str())
+                    && !(options.skip_indices && segment.kind == SegmentKind::Index))
+        };
+
+        let mut iters: Vec<Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>>> = vec![];
+        for segment in &reader.manifest.segments {
+            if !wanted(segment) {
+                continue;
+            }
+            if segment.kind != SegmentKind::System {
+                iters.push(Box::new(reader.read_segment(segment)?));
+                continue;
+            }
+            // catalog entries are only restored for the relations that are restored
+            let mut system = vec![];
+            for kv in reader.read_segment(segment)? {
+                let (k, mut v) = kv?;
+                if let Some(name) = catalog_entry_name(&k) {
+                    match reader.segment_named(&name) {
+                        Some(seg) if !wanted(seg) => continue,
+                        Some(seg) if seg.kind == SegmentKind::Relation && options.skip_indices => {
+                            let mut handle = RelationHandle::decode(&v)?;
+                            handle.strip_indices();
+                            v.clear();
+                            handle
+                                .serialize(&mut Serializer::new(&mut v).with_struct_map())
+                                .unwrap();
+                        }
+                        _ => {}
+                    }
+                }
+                system.push(Ok((k, v)));
+            }
+            iters.push(Box::new(system.into_iter()));
+        }
+        self.db.batch_put(Box::new(iters.into_iter().flatten()))?;
+        self.initialize()
+    }
+
+    pub(crate) fn import_from_backup_v2(
+        &'s self,
+        in_file: impl AsRef<Path>,
+        relations: &[String],
+    ) -> Result<()> {
+        let reader = BackupReader::open(in_file)?;
+        let mut dst_tx = self.transact_write()?;
+        for relation in relations {
+            let dst_handle = dst_tx.get_relation(relation, false)?;
+            dst_handle.ensure_bare_import_allowed()?;
+            let segment = reader
+                .segment_named(relation)
+                .filter(|s| s.kind == SegmentKind::Relation)
+                .ok_or_else(|| miette!("Relation {relation} not found in the backup"))?;
+            let src_handle = reader.relation_handle(relation)?;
+            ensure!(
+                src_handle.metadata == dst_handle.metadata,
+                "Cannot import data into relation {}: the schema differs from that in the backup",
+                relation
+            );
+            for kv in reader.read_segment(segment)? {
+                let (mut k, mut v) = kv?;
+                dst_handle.amend_key_prefix(&mut k);
+                dst_handle.amend_key_prefix(&mut v);
+                dst_tx.store_tx.put(&k, &v)?;
+            }
+        }
+        dst_tx.commit_tx()
+    }
+}
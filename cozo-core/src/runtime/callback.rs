@@ -51,7 +51,6 @@ pub struct CallbackDeclaration {
 pub(crate) type CallbackCollector =
     BTreeMap<SmartString<LazyCompact>, Vec<(CallbackOp, NamedRows, NamedRows)>>;
 
-#[allow(dead_code)]
 pub(crate) type EventCallbackRegistry = (
     BTreeMap<u32, CallbackDeclaration>,
     BTreeMap<SmartString<LazyCompact>, BTreeSet<u32>>,
@@ -59,23 +58,14 @@ pub(crate) type EventCallbackRegistry = (
 
 impl<'s, S: Storage<'s>> Db<S> {
     pub(crate) fn current_callback_targets(&self) -> BTreeSet<SmartString<LazyCompact>> {
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            self.event_callbacks
-                .read()
-                .unwrap()
-                .1
-                .keys()
-                .cloned()
-                .collect()
-        }
-
-        #[cfg(target_arch = "wasm32")]
-        {
-            Default::default()
-        }
+        self.event_callbacks
+            .read()
+            .unwrap()
+            .1
+            .keys()
+            .cloned()
+            .collect()
     }
-    #[cfg(not(target_arch = "wasm32"))]
     pub(crate) fn send_callbacks(&'s self, collector: CallbackCollector) {
         let mut to_remove = vec![];
 
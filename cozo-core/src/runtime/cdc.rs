@@ -0,0 +1,553 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Change-data-capture sinks, configured with `::cdc`: each sink listens to the committed
+//! changes of one stored relation through a callback, and publishes one event per changed row
+//! to a Kafka topic or a NATS subject, as JSON or Avro.
+//!
+//! Events are published by a background thread after the commit, so delivery is at most once:
+//! events are dropped if the broker cannot be reached. Use the outbox if every change
+//! must be delivered.
+
+#[cfg(feature = "cdc")]
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use itertools::Itertools;
+use miette::{bail, miette, Diagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+#[cfg(feature = "cdc")]
+use serde_json::json;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+#[cfg(feature = "cdc")]
+use crate::data::json::JsonValue;
+use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::parse::SourceSpan;
+#[cfg(feature = "cdc")]
+use crate::runtime::callback::CallbackOp;
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+/// Encoding of the published events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum CdcFormat {
+    Json,
+    Avro,
+}
+
+impl Display for CdcFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CdcFormat::Json => f.write_str("json"),
+            CdcFormat::Avro => f.write_str("avro"),
+        }
+    }
+}
+
+/// Where the events are published.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CdcTarget {
+    /// `kafka://host1:9092,host2:9092/topic`
+    Kafka { hosts: Vec<String>, topic: String },
+    /// `nats://host:4222/subject`
+    Nats { url: String, subject: String },
+}
+
+impl CdcTarget {
+    pub(crate) fn parse(target: &str) -> Result<Self, String> {
+        let (scheme, rest) = target
+            .split_once("://")
+            .ok_or_else(|| "missing scheme".to_string())?;
+        let (hosts, name) = rest
+            .split_once('/')
+            .filter(|(hosts, name)| !hosts.is_empty() && !name.is_empty())
+            .ok_or_else(|| "expected hosts and a topic or subject separated by '/'".to_string())?;
+        match scheme {
+            "kafka" => Ok(CdcTarget::Kafka {
+                hosts: hosts.split(',').map(|h| h.to_string()).collect(),
+                topic: name.to_string(),
+            }),
+            "nats" => Ok(CdcTarget::Nats {
+                url: format!("nats://{hosts}"),
+                subject: name.to_string(),
+            }),
+            s => Err(format!("unknown scheme '{s}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct CdcSinkSpec {
+    pub(crate) name: SmartString<LazyCompact>,
+    pub(crate) relation: SmartString<LazyCompact>,
+    /// Validated with [CdcTarget::parse] when the sink is added
+    pub(crate) target: String,
+    pub(crate) format: CdcFormat,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bad change-data-capture target '{0}': {1}")]
+#[diagnostic(code(parser::bad_cdc_target))]
+#[diagnostic(help("Expected 'kafka://host1:9092,host2:9092/topic' or 'nats://host:4222/subject'"))]
+pub(crate) struct BadCdcTarget(
+    pub(crate) String,
+    pub(crate) String,
+    #[label] pub(crate) SourceSpan,
+);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Change-data-capture sink {0} not found")]
+#[diagnostic(code(tx::cdc_sink_not_found))]
+struct CdcSinkNotFound(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Change-data-capture sink {0} already exists")]
+#[diagnostic(code(tx::cdc_sink_exists))]
+#[diagnostic(help("Remove it with `::cdc remove {0}` first"))]
+struct CdcSinkExists(String, #[label] SourceSpan);
+
+fn cdc_key(name: &str) -> Vec<u8> {
+    let tuple = vec![
+        DataValue::Null,
+        DataValue::from("CDC"),
+        DataValue::from(name),
+    ];
+    tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+impl<'a> SessionTx<'a> {
+    pub(crate) fn add_cdc_sink(&mut self, spec: &CdcSinkSpec, span: SourceSpan) -> Result<()> {
+        let key = cdc_key(&spec.name);
+        if self.store_tx.exists(&key, false)? {
+            bail!(CdcSinkExists(spec.name.to_string(), span))
+        }
+        let handle = self.get_relation(&spec.relation, false)?;
+        if handle.is_temp {
+            bail!("Only changes to stored relations can be published")
+        }
+        let mut val = vec![];
+        spec.serialize(&mut Serializer::new(&mut val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&key, &val)?;
+        Ok(())
+    }
+    pub(crate) fn remove_cdc_sink(&mut self, name: &Symbol) -> Result<()> {
+        let key = cdc_key(&name.name);
+        if !self.store_tx.exists(&key, false)? {
+            bail!(CdcSinkNotFound(name.name.to_string(), name.span))
+        }
+        self.store_tx.del(&key)?;
+        Ok(())
+    }
+    pub(crate) fn cdc_sinks(&self) -> Result<Vec<CdcSinkSpec>> {
+        let lower = cdc_key("");
+        let upper = cdc_key(&String::from(LARGEST_UTF_CHAR));
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            ret.push(
+                rmp_serde::from_slice(&v_slice)
+                    .map_err(|e| miette!("Cannot decode change-data-capture sink: {e}"))?,
+            );
+        }
+        Ok(ret)
+    }
+}
+
+/// The change of a single row of a relation.
+#[cfg(feature = "cdc")]
+#[derive(Debug, PartialEq)]
+pub(crate) struct ChangeEvent {
+    pub(crate) op: CallbackOp,
+    pub(crate) keys: Vec<DataValue>,
+    pub(crate) new: Option<Vec<DataValue>>,
+    pub(crate) old: Option<Vec<DataValue>>,
+}
+
+/// Splits the rows passed to callbacks into events. For puts, `new` contains the rows put
+/// and `old` the rows they replaced. For removals, `new` contains the keys removed
+/// and `old` the rows that were removed.
+#[cfg(feature = "cdc")]
+#[allow(clippy::mutable_key_type)]
+pub(crate) fn change_events(
+    op: CallbackOp,
+    key_len: usize,
+    new: NamedRows,
+    old: NamedRows,
+) -> Vec<ChangeEvent> {
+    let mut old_rows: BTreeMap<Vec<DataValue>, Vec<DataValue>> = old
+        .rows
+        .into_iter()
+        .map(|row| (row[..key_len].to_vec(), row))
+        .collect();
+    new.rows
+        .into_iter()
+        .map(|row| {
+            let keys = row[..key_len].to_vec();
+            let old = old_rows.remove(&keys);
+            match op {
+                CallbackOp::Put => ChangeEvent {
+                    op,
+                    keys,
+                    new: Some(row),
+                    old,
+                },
+                CallbackOp::Rm => ChangeEvent {
+                    op,
+                    keys,
+                    new: None,
+                    old,
+                },
+            }
+        })
+        .collect_vec()
+}
+
+#[cfg(feature = "cdc")]
+impl ChangeEvent {
+    fn columns(headers: &[String], row: &Option<Vec<DataValue>>) -> JsonValue {
+        match row {
+            None => JsonValue::Null,
+            Some(row) => headers
+                .iter()
+                .zip(row.iter())
+                .map(|(h, v)| (h.clone(), JsonValue::from(v.clone())))
+                .collect(),
+        }
+    }
+    /// The key of the message, the same in all formats.
+    pub(crate) fn message_key(&self) -> Vec<u8> {
+        let keys = self.keys.iter().cloned().map(JsonValue::from).collect_vec();
+        JsonValue::Array(keys).to_string().into_bytes()
+    }
+    pub(crate) fn to_json(&self, relation: &str, headers: &[String]) -> JsonValue {
+        let key_headers = &headers[..self.keys.len()];
+        json!({
+            "relation": relation,
+            "op": self.op.as_str().to_lowercase(),
+            "keys": Self::columns(key_headers, &Some(self.keys.clone())),
+            "new": Self::columns(headers, &self.new),
+            "old": Self::columns(headers, &self.old),
+        })
+    }
+}
+
+/// Schema of the events in the Avro format. Values of columns that are not
+/// null, booleans, integers, floats, strings or bytes are sent as JSON strings.
+#[cfg(feature = "cdc")]
+pub(crate) const CDC_AVRO_SCHEMA: &str = r#"{
+  "type": "record",
+  "name": "Change",
+  "namespace": "cozo",
+  "fields": [
+    {"name": "relation", "type": "string"},
+    {"name": "op", "type": "string"},
+    {"name": "keys", "type": {"type": "map", "values": ["null", "boolean", "long", "double", "string", "bytes"]}},
+    {"name": "new", "type": ["null", {"type": "map", "values": ["null", "boolean", "long", "double", "string", "bytes"]}]},
+    {"name": "old", "type": ["null", {"type": "map", "values": ["null", "boolean", "long", "double", "string", "bytes"]}]}
+  ]
+}"#;
+
+#[cfg(feature = "cdc")]
+mod avro {
+    use std::collections::HashMap;
+
+    use apache_avro::types::Value;
+    use apache_avro::Schema;
+    use lazy_static::lazy_static;
+    use miette::{miette, Result};
+
+    use super::{ChangeEvent, CDC_AVRO_SCHEMA};
+    use crate::data::json::JsonValue;
+    use crate::data::value::{DataValue, Num};
+
+    lazy_static! {
+        static ref SCHEMA: Schema = Schema::parse_str(CDC_AVRO_SCHEMA).unwrap();
+    }
+
+    fn value(v: &DataValue) -> Value {
+        let (idx, v) = match v {
+            DataValue::Null => (0, Value::Null),
+            DataValue::Bool(b) => (1, Value::Boolean(*b)),
+            DataValue::Num(Num::Int(i)) => (2, Value::Long(*i)),
+            DataValue::Num(Num::Float(f)) => (3, Value::Double(*f)),
+            DataValue::Str(s) => (4, Value::String(s.to_string())),
+            DataValue::Bytes(b) => (5, Value::Bytes(b.clone())),
+            v => (4, Value::String(JsonValue::from(v.clone()).to_string())),
+        };
+        Value::Union(idx, Box::new(v))
+    }
+
+    fn columns(headers: &[String], row: &Option<Vec<DataValue>>) -> Value {
+        match row {
+            None => Value::Union(0, Box::new(Value::Null)),
+            Some(row) => {
+                let map: HashMap<String, Value> = headers
+                    .iter()
+                    .zip(row.iter())
+                    .map(|(h, v)| (h.clone(), value(v)))
+                    .collect();
+                Value::Union(1, Box::new(Value::Map(map)))
+            }
+        }
+    }
+
+    pub(super) fn encode(
+        event: &ChangeEvent,
+        relation: &str,
+        headers: &[String],
+    ) -> Result<Vec<u8>> {
+        let keys = headers
+            .iter()
+            .zip(event.keys.iter())
+            .map(|(h, v)| (h.clone(), value(v)))
+            .collect();
+        let record = Value::Record(vec![
+            ("relation".to_string(), Value::String(relation.to_string())),
+            (
+                "op".to_string(),
+                Value::String(event.op.as_str().to_lowercase()),
+            ),
+            ("keys".to_string(), Value::Map(keys)),
+            ("new".to_string(), columns(headers, &event.new)),
+            ("old".to_string(), columns(headers, &event.old)),
+        ]);
+        apache_avro::to_avro_datum(&SCHEMA, record)
+            .map_err(|e| miette!("Avro encoding failed: {e}"))
+    }
+}
+
+#[cfg(feature = "cdc")]
+pub(crate) fn encode_event(
+    event: &ChangeEvent,
+    format: CdcFormat,
+    relation: &str,
+    headers: &[String],
+) -> Result<Vec<u8>> {
+    match format {
+        CdcFormat::Json => Ok(event.to_json(relation, headers).to_string().into_bytes()),
+        CdcFormat::Avro => avro::encode(event, relation, headers),
+    }
+}
+
+/// A connection to the broker of a sink.
+#[cfg(feature = "cdc")]
+trait Publisher {
+    fn publish(&mut self, key: &[u8], payload: &[u8]) -> Result<()>;
+}
+
+#[cfg(feature = "cdc-kafka")]
+struct KafkaPublisher {
+    producer: kafka::producer::Producer,
+    topic: String,
+}
+
+#[cfg(feature = "cdc-kafka")]
+impl Publisher for KafkaPublisher {
+    fn publish(&mut self, key: &[u8], payload: &[u8]) -> Result<()> {
+        let record = kafka::producer::Record::from_key_value(&self.topic, key, payload);
+        self.producer
+            .send(&record)
+            .map_err(|e| miette!("cannot publish to Kafka: {e}"))
+    }
+}
+
+#[cfg(feature = "cdc-nats")]
+struct NatsPublisher {
+    runtime: tokio::runtime::Runtime,
+    client: async_nats::Client,
+    subject: String,
+}
+
+#[cfg(feature = "cdc-nats")]
+impl Publisher for NatsPublisher {
+    fn publish(&mut self, _key: &[u8], payload: &[u8]) -> Result<()> {
+        let payload = payload.to_vec().into();
+        self.runtime
+            .block_on(async {
+                self.client.publish(self.subject.clone(), payload).await?;
+                self.client.flush().await?;
+                Ok::<(), async_nats::Error>(())
+            })
+            .map_err(|e| miette!("cannot publish to NATS: {e}"))
+    }
+}
+
+#[cfg(feature = "cdc")]
+fn connect(target: &CdcTarget) -> Result<Box<dyn Publisher>> {
+    match target {
+        #[cfg(feature = "cdc-kafka")]
+        CdcTarget::Kafka { hosts, topic } => {
+            let producer = kafka::producer::Producer::from_hosts(hosts.clone())
+                .with_ack_timeout(std::time::Duration::from_secs(5))
+                .with_required_acks(kafka::producer::RequiredAcks::One)
+                .create()
+                .map_err(|e| miette!("cannot connect to Kafka: {e}"))?;
+            Ok(Box::new(KafkaPublisher {
+                producer,
+                topic: topic.clone(),
+            }))
+        }
+        #[cfg(feature = "cdc-nats")]
+        CdcTarget::Nats { url, subject } => {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| miette!("cannot start NATS client: {e}"))?;
+            let client = runtime
+                .block_on(async_nats::connect(url.as_str()))
+                .map_err(|e| miette!("cannot connect to NATS: {e}"))?;
+            Ok(Box::new(NatsPublisher {
+                runtime,
+                client,
+                subject: subject.clone(),
+            }))
+        }
+        #[allow(unreachable_patterns)]
+        _ => bail!(
+            "the feature for publishing to {:?} is not enabled for the build",
+            target
+        ),
+    }
+}
+
+/// Publishes the changes received by the callback until it is unregistered.
+#[cfg(feature = "cdc")]
+fn run_sink(
+    spec: CdcSinkSpec,
+    target: CdcTarget,
+    key_len: usize,
+    receiver: crossbeam::channel::Receiver<(CallbackOp, NamedRows, NamedRows)>,
+) {
+    let mut publisher: Option<Box<dyn Publisher>> = None;
+    for (op, new, old) in receiver {
+        let headers = old.headers.clone();
+        for event in change_events(op, key_len, new, old) {
+            let payload = match encode_event(&event, spec.format, &spec.relation, &headers) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    log::error!("CDC sink {}: {:?}", spec.name, err);
+                    continue;
+                }
+            };
+            let key = event.message_key();
+            // a failed publish is retried once with a new connection
+            for _ in 0..2 {
+                if publisher.is_none() {
+                    match connect(&target) {
+                        Ok(p) => publisher = Some(p),
+                        Err(err) => {
+                            log::error!("CDC sink {}: {:?}", spec.name, err);
+                            break;
+                        }
+                    }
+                }
+                match publisher.as_mut().unwrap().publish(&key, &payload) {
+                    Ok(()) => break,
+                    Err(err) => {
+                        log::error!("CDC sink {}: {:?}", spec.name, err);
+                        publisher = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Starts publishing the changes of the relation of the sink, if it is not running yet.
+    #[allow(unused_variables)]
+    pub(crate) fn start_cdc_sink(&'s self, spec: &CdcSinkSpec, key_len: usize) -> Result<()> {
+        #[cfg(feature = "cdc")]
+        {
+            let target = CdcTarget::parse(&spec.target).map_err(|e| miette!(e))?;
+            let mut sinks = self.cdc_sinks.lock().unwrap();
+            if sinks.contains_key(&spec.name) {
+                return Ok(());
+            }
+            let (id, receiver) = self.register_callback(&spec.relation, None);
+            let thread_spec = spec.clone();
+            std::thread::Builder::new()
+                .name(format!("cdc-{}", spec.name))
+                .spawn(move || run_sink(thread_spec, target, key_len, receiver))
+                .map_err(|e| miette!("cannot start CDC sink: {e}"))?;
+            sinks.insert(spec.name.clone(), id);
+            Ok(())
+        }
+        #[cfg(not(feature = "cdc"))]
+        bail!("publishing changes requires the `cdc-kafka` or `cdc-nats` feature to be enabled")
+    }
+    /// Stops the sink: its thread exits after publishing the changes already received.
+    pub(crate) fn stop_cdc_sink(&'s self, name: &str) {
+        if let Some(id) = self.cdc_sinks.lock().unwrap().remove(name) {
+            self.unregister_callback(id);
+        }
+    }
+    /// Starts the sinks stored in the database.
+    pub(crate) fn load_cdc_sinks(&'s self) -> Result<()> {
+        let tx = self.transact()?;
+        for spec in tx.cdc_sinks()? {
+            let key_len = match tx.get_relation(&spec.relation, false) {
+                Ok(handle) => handle.metadata.keys.len(),
+                Err(_) => {
+                    log::warn!(
+                        "CDC sink {}: relation {} does not exist",
+                        spec.name,
+                        spec.relation
+                    );
+                    continue;
+                }
+            };
+            if let Err(err) = self.start_cdc_sink(&spec, key_len) {
+                log::warn!("CDC sink {} not started: {:?}", spec.name, err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The sinks stored in the database, and whether each of them is running.
+pub(crate) fn list_cdc_sinks<'s, S: Storage<'s>>(
+    db: &Db<S>,
+    tx: &SessionTx<'_>,
+) -> Result<NamedRows> {
+    let running = db.cdc_sinks.lock().unwrap();
+    let rows = tx
+        .cdc_sinks()?
+        .into_iter()
+        .map(|spec| {
+            let is_running = running.contains_key(&spec.name);
+            vec![
+                DataValue::Str(spec.name),
+                DataValue::Str(spec.relation),
+                DataValue::from(spec.target),
+                DataValue::from(spec.format.to_string()),
+                DataValue::from(is_running),
+            ]
+        })
+        .collect_vec();
+    Ok(NamedRows::new(
+        vec![
+            "name".to_string(),
+            "relation".to_string(),
+            "target".to_string(),
+            "format".to_string(),
+            "running".to_string(),
+        ],
+        rows,
+    ))
+}
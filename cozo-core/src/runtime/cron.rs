@@ -0,0 +1,374 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Scheduled scripts: named scripts with cron expressions are kept in the catalog with
+//! `::schedule add`, and the host (e.g. the standalone server) periodically calls
+//! [Db::run_scheduled_scripts], which runs the due ones and logs each run in a stored relation.
+
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+use itertools::Itertools;
+use log::{error, info};
+use miette::{bail, ensure, miette, Diagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, JsonData, LARGEST_UTF_CHAR};
+use crate::parse::SourceSpan;
+use crate::runtime::db::{seconds_since_the_epoch, Db};
+use crate::runtime::relation::{AccessLevel, InputRelationHandle, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{NamedRows, ScriptMutability, Storage};
+
+/// Name of the stored relation holding the log of scheduled runs.
+pub(crate) const SCHEDULE_LOG_RELATION: &str = "schedule_log";
+
+/// A parsed cron expression with the five fields `minute hour day-of-month month day-of-week`,
+/// evaluated in UTC. Each field is a bitmask of the values it matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// As in Vixie cron, when both days of month and days of week are restricted,
+    /// a day matching either of them matches.
+    day_or_weekday: bool,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid cron expression '{0}': {1}")]
+#[diagnostic(code(parser::bad_cron_expr))]
+#[diagnostic(help(
+    "Expected five fields `minute hour day-of-month month day-of-week`, e.g. '30 2 * * 1-5', \
+     or one of @hourly, @daily, @weekly, @monthly and @yearly"
+))]
+pub(crate) struct BadCronExpr(
+    pub(crate) String,
+    pub(crate) String,
+    #[label] pub(crate) SourceSpan,
+);
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            None => (part, 1),
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("bad step in '{part}'")),
+            },
+        };
+        let parse_num = |s: &str| -> Result<u32, String> {
+            match s.parse::<u32>() {
+                Ok(n) if (min..=max).contains(&n) => Ok(n),
+                _ => Err(format!("'{s}' is not a number between {min} and {max}")),
+            }
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((lo, hi)) = range.split_once('-') {
+            (parse_num(lo)?, parse_num(hi)?)
+        } else {
+            let n = parse_num(range)?;
+            // `5/15` means starting from 5, every 15
+            (n, if step > 1 { max } else { n })
+        };
+        if lo > hi {
+            return Err(format!("empty range '{range}'"));
+        }
+        for n in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << n;
+        }
+    }
+    Ok(mask)
+}
+
+impl CronExpr {
+    pub(crate) fn parse(expr: &str) -> Result<Self, String> {
+        let expanded = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            s => s,
+        };
+        let fields = expanded.split_whitespace().collect_vec();
+        if fields.len() != 5 {
+            return Err(format!("expected 5 fields, got {}", fields.len()));
+        }
+        let mut weekdays = parse_cron_field(fields[4], 0, 7)?;
+        // both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)?,
+            days: parse_cron_field(fields[2], 1, 31)?,
+            months: parse_cron_field(fields[3], 1, 12)?,
+            weekdays,
+            day_or_weekday: !fields[2].starts_with('*') && !fields[4].starts_with('*'),
+        })
+    }
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.day_or_weekday {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+    /// The first time matching the expression strictly after `after`, in seconds since the epoch.
+    /// Returns `None` if nothing matches in the next few years, e.g. for February 30th.
+    pub(crate) fn next_after(&self, after: f64) -> Option<f64> {
+        let start = DateTime::from_timestamp(after.floor() as i64, 0)?.naive_utc();
+        let mut t: NaiveDateTime = start.with_second(0)? + Duration::minutes(1);
+        let last_year = start.year() + 5;
+        while t.year() <= last_year {
+            if self.months & (1 << t.month()) == 0 {
+                let (y, m) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(y, m, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(t.date()) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0)? + Duration::hours(1);
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+            } else {
+                return Some(t.and_utc().timestamp() as f64);
+            }
+        }
+        None
+    }
+}
+
+/// A script run whenever the cron expression matches.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct ScheduledScript {
+    pub(crate) name: SmartString<LazyCompact>,
+    pub(crate) cron: String,
+    pub(crate) script: String,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Scheduled script {0} not found")]
+#[diagnostic(code(tx::schedule_not_found))]
+struct ScheduleNotFound(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' exists but is not a schedule log")]
+#[diagnostic(code(tx::not_a_schedule_log))]
+#[diagnostic(help("The relation '{0}' is reserved for the schedule log, rename the existing one"))]
+struct NotAScheduleLogError(&'static str);
+
+fn schedule_key(name: &str) -> Vec<u8> {
+    let tuple = vec![
+        DataValue::Null,
+        DataValue::from("SCHEDULE"),
+        DataValue::from(name),
+    ];
+    tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+fn schedule_log_metadata() -> StoredRelationMetadata {
+    let col = |name: &str, coltype| ColumnDef {
+        name: name.into(),
+        typing: NullableColType {
+            coltype,
+            nullable: false,
+        },
+        default_gen: None,
+    };
+    StoredRelationMetadata {
+        keys: vec![col("name", ColType::String), col("at", ColType::Float)],
+        non_keys: vec![
+            col("ok", ColType::Bool),
+            col("took", ColType::Float),
+            col("result", ColType::Json),
+        ],
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    pub(crate) fn add_schedule(&mut self, schedule: &ScheduledScript) -> Result<()> {
+        let mut val = vec![];
+        schedule
+            .serialize(&mut Serializer::new(&mut val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&schedule_key(&schedule.name), &val)?;
+        Ok(())
+    }
+    pub(crate) fn remove_schedule(&mut self, name: &Symbol) -> Result<()> {
+        let key = schedule_key(&name.name);
+        if !self.store_tx.exists(&key, false)? {
+            bail!(ScheduleNotFound(name.name.to_string(), name.span))
+        }
+        self.store_tx.del(&key)?;
+        Ok(())
+    }
+    pub(crate) fn schedules(&self) -> Result<Vec<ScheduledScript>> {
+        let lower = schedule_key("");
+        let upper = schedule_key(&String::from(LARGEST_UTF_CHAR));
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            ret.push(
+                rmp_serde::from_slice(&v_slice)
+                    .map_err(|e| miette!("Cannot decode scheduled script: {e}"))?,
+            );
+        }
+        Ok(ret)
+    }
+    fn schedule_log_relation(&mut self) -> Result<RelationHandle> {
+        if self.relation_exists(SCHEDULE_LOG_RELATION)? {
+            let handle = self.get_relation(SCHEDULE_LOG_RELATION, false)?;
+            ensure!(
+                handle.metadata == schedule_log_metadata(),
+                NotAScheduleLogError(SCHEDULE_LOG_RELATION)
+            );
+            return Ok(handle);
+        }
+        let span = SourceSpan(0, 0);
+        let name = Symbol::new(SCHEDULE_LOG_RELATION, span);
+        let handle = self.create_relation(InputRelationHandle {
+            name: name.clone(),
+            metadata: schedule_log_metadata(),
+            key_bindings: vec![],
+            dep_bindings: vec![],
+            span,
+        })?;
+        // the log is only written by the scheduler
+        self.set_access_level(&name, AccessLevel::ReadOnly)?;
+        Ok(handle)
+    }
+    fn append_schedule_log(
+        &mut self,
+        name: &str,
+        at: f64,
+        took: f64,
+        result: &Result<NamedRows>,
+    ) -> Result<()> {
+        let span = SourceSpan(0, 0);
+        let handle = self.schedule_log_relation()?;
+        let (ok, result) = match result {
+            Ok(rows) => (true, rows.clone().into_json()),
+            Err(err) => (false, JsonValue::from(format!("{err:?}"))),
+        };
+        let tuple = vec![
+            DataValue::from(name),
+            DataValue::from(at),
+            DataValue::from(ok),
+            DataValue::from(took),
+            DataValue::Json(JsonData(result)),
+        ];
+        let key = handle.encode_key_for_store(&tuple, span)?;
+        let val = handle.encode_val_for_store(&tuple, span)?;
+        self.store_tx.put(&key, &val)?;
+        Ok(())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Run the scripts added with `::schedule add` whose cron expression matches a minute
+    /// in the interval `(since, until]`, both in seconds since the epoch.
+    ///
+    /// A script runs at most once per call even if several minutes match. Each run is recorded
+    /// in the `schedule_log` stored relation, with the result or the error of the script.
+    /// Returns the name of each script that was run and whether it succeeded.
+    ///
+    /// The retention policies are also enforced here when they are due.
+    pub fn run_scheduled_scripts(&'s self, since: f64, until: f64) -> Result<NamedRows> {
+        self.maybe_enforce_retention();
+        let schedules = self.transact()?.schedules()?;
+        let mut rows = vec![];
+        for schedule in schedules {
+            let due = match CronExpr::parse(&schedule.cron) {
+                Ok(expr) => expr.next_after(since).is_some_and(|t| t <= until),
+                Err(err) => {
+                    error!(
+                        "bad cron expression of scheduled script {}: {err}",
+                        schedule.name
+                    );
+                    false
+                }
+            };
+            if !due {
+                continue;
+            }
+            let at = seconds_since_the_epoch()?;
+            let start = Instant::now();
+            let result =
+                self.run_script(&schedule.script, BTreeMap::new(), ScriptMutability::Mutable);
+            let took = start.elapsed().as_secs_f64();
+            match &result {
+                Ok(_) => info!("scheduled script {} finished in {took:.3}s", schedule.name),
+                Err(err) => error!("scheduled script {} failed: {err:?}", schedule.name),
+            }
+            let mut tx = self.transact_write()?;
+            tx.append_schedule_log(&schedule.name, at, took, &result)?;
+            tx.commit_tx()?;
+            rows.push(vec![
+                DataValue::Str(schedule.name),
+                DataValue::from(result.is_ok()),
+            ]);
+        }
+        Ok(NamedRows::new(
+            vec!["name".to_string(), "ok".to_string()],
+            rows,
+        ))
+    }
+}
+
+/// The scheduled scripts, with the next time each of them is due.
+pub(crate) fn list_schedules(tx: &SessionTx<'_>) -> Result<NamedRows> {
+    let now = seconds_since_the_epoch()?;
+    let rows = tx
+        .schedules()?
+        .into_iter()
+        .map(|schedule| {
+            let next = CronExpr::parse(&schedule.cron)
+                .ok()
+                .and_then(|expr| expr.next_after(now))
+                .map_or(DataValue::Null, DataValue::from);
+            vec![
+                DataValue::Str(schedule.name),
+                DataValue::from(schedule.cron),
+                DataValue::from(schedule.script),
+                next,
+            ]
+        })
+        .collect_vec();
+    Ok(NamedRows::new(
+        vec![
+            "name".to_string(),
+            "cron".to_string(),
+            "script".to_string(),
+            "next_run".to_string(),
+        ],
+        rows,
+    ))
+}
@@ -0,0 +1,542 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Import and export of Datomic/Datascript transactions in EDN, stored as triples
+//! with validity `{entity: Int, attribute: String, value: Any, at: Validity}`.
+//!
+//! Attributes are stored without the leading colon, keyword values with it, so a string
+//! value starting with a colon is exported as a keyword. `#inst` values are stored
+//! as seconds since the epoch, as returned by `now()`.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::DateTime;
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::edn::{parse_edn, Edn};
+use crate::data::functions::current_validity;
+use crate::data::json::JsonValue;
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, JsonData, Num, UuidWrapper, Validity, ValidityTs};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Db;
+use crate::runtime::relation::InputRelationHandle;
+use crate::{NamedRows, Storage};
+
+const TX_INSTANT: &str = "db/txInstant";
+
+fn datom_metadata() -> StoredRelationMetadata {
+    let col = |name: &str, coltype| ColumnDef {
+        name: name.into(),
+        typing: NullableColType {
+            coltype,
+            nullable: false,
+        },
+        default_gen: None,
+    };
+    StoredRelationMetadata {
+        keys: vec![
+            col("entity", ColType::Int),
+            col("attribute", ColType::String),
+            col("value", ColType::Any),
+            col("at", ColType::Validity),
+        ],
+        non_keys: vec![],
+    }
+}
+
+fn datom_headers() -> Vec<String> {
+    ["entity", "attribute", "value", "at"]
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' cannot hold datoms")]
+#[diagnostic(code(datomic::not_a_datom_relation))]
+#[diagnostic(help(
+    "The relation must have the schema {{entity: Int, attribute: String, value: Any, at: Validity}}"
+))]
+struct NotADatomRelationError(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot import EDN transaction: {0}")]
+#[diagnostic(code(datomic::bad_transaction))]
+struct BadTransactionError(String);
+
+fn bad_tx<T>(msg: String) -> Result<T> {
+    bail!(BadTransactionError(msg))
+}
+
+fn inst_to_seconds(edn: &Edn) -> Result<f64> {
+    match edn {
+        Edn::Str(s) => match DateTime::parse_from_rfc3339(s) {
+            Ok(dt) => {
+                let st: SystemTime = dt.into();
+                Ok(match st.duration_since(UNIX_EPOCH) {
+                    Ok(d) => d.as_secs_f64(),
+                    Err(e) => -e.duration().as_secs_f64(),
+                })
+            }
+            Err(_) => bad_tx(format!("'{s}' is not an RFC 3339 instant")),
+        },
+        e => bad_tx(format!("#inst requires a string, got {e}")),
+    }
+}
+
+/// Convert a value that is not an entity reference.
+fn edn_to_value(edn: &Edn) -> Result<DataValue> {
+    Ok(match edn {
+        Edn::Nil => bad_tx("nil is not a valid value".to_string())?,
+        Edn::Bool(b) => DataValue::from(*b),
+        Edn::Int(i) => DataValue::from(*i),
+        Edn::Float(f) => DataValue::from(*f),
+        Edn::Str(s) => DataValue::from(s as &str),
+        Edn::Char(c) => DataValue::from(c.to_string()),
+        Edn::Keyword(k) => DataValue::from(format!(":{k}")),
+        Edn::Symbol(s) => DataValue::from(s as &str),
+        Edn::List(l) | Edn::Vector(l) | Edn::Set(l) => {
+            DataValue::List(l.iter().map(edn_to_value).try_collect()?)
+        }
+        Edn::Map(m) => {
+            let obj = m
+                .iter()
+                .map(|(k, v)| -> Result<(String, JsonValue)> {
+                    let k = match k {
+                        Edn::Str(s) => s.clone(),
+                        Edn::Keyword(k) => k.clone(),
+                        k => k.to_string(),
+                    };
+                    Ok((k, JsonValue::from(edn_to_value(v)?)))
+                })
+                .try_collect()?;
+            DataValue::Json(JsonData(JsonValue::Object(obj)))
+        }
+        Edn::Tagged(tag, form) => match (tag as &str, &**form) {
+            ("inst", form) => DataValue::from(inst_to_seconds(form)?),
+            ("uuid", Edn::Str(s)) => match uuid::Uuid::parse_str(s) {
+                Ok(u) => DataValue::Uuid(UuidWrapper(u)),
+                Err(_) => bad_tx(format!("'{s}' is not a UUID"))?,
+            },
+            _ => bad_tx(format!("unsupported tagged value #{tag} {form}"))?,
+        },
+    })
+}
+
+fn value_to_edn(v: &DataValue) -> Result<Edn> {
+    Ok(match v {
+        DataValue::Null => Edn::Nil,
+        DataValue::Bool(b) => Edn::Bool(*b),
+        DataValue::Num(Num::Int(i)) => Edn::Int(*i),
+        DataValue::Num(Num::Float(f)) => Edn::Float(*f),
+        DataValue::Str(s) => match s.strip_prefix(':') {
+            Some(k) if !k.is_empty() => Edn::Keyword(k.to_string()),
+            _ => Edn::Str(s.to_string()),
+        },
+        DataValue::Uuid(UuidWrapper(u)) => {
+            Edn::Tagged("uuid".to_string(), Box::new(Edn::Str(u.to_string())))
+        }
+        DataValue::List(l) => Edn::Vector(l.iter().map(value_to_edn).try_collect()?),
+        DataValue::Json(JsonData(j)) => json_to_edn(j),
+        v => bail!("Value {v} cannot be exported to EDN"),
+    })
+}
+
+fn json_to_edn(j: &JsonValue) -> Edn {
+    match j {
+        JsonValue::Null => Edn::Nil,
+        JsonValue::Bool(b) => Edn::Bool(*b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => Edn::Int(i),
+            None => Edn::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        JsonValue::String(s) => Edn::Str(s.clone()),
+        JsonValue::Array(a) => Edn::Vector(a.iter().map(json_to_edn).collect()),
+        JsonValue::Object(o) => Edn::Map(
+            o.iter()
+                .map(|(k, v)| (Edn::Keyword(k.clone()), json_to_edn(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// A datom before the time of its transaction is known.
+struct PendingDatom {
+    entity: i64,
+    attribute: String,
+    value: DataValue,
+    added: bool,
+}
+
+/// The state needed to resolve entities across transactions.
+#[derive(Default)]
+struct DatomImporter {
+    /// Current values of each attribute of each entity.
+    current: BTreeMap<(i64, String), BTreeSet<DataValue>>,
+    /// The entity having each attribute value, for lookup refs.
+    owners: BTreeMap<(String, DataValue), i64>,
+    next_id: i64,
+    last_ts: i64,
+    rows: Vec<Tuple>,
+}
+
+/// Temporary ids of one transaction.
+#[derive(Default)]
+struct TempIds(BTreeMap<String, i64>);
+
+impl DatomImporter {
+    fn apply(&mut self, entity: i64, attribute: &str, value: DataValue, added: bool, ts: i64) {
+        #[allow(clippy::mutable_key_type)]
+        let values = self
+            .current
+            .entry((entity, attribute.to_string()))
+            .or_default();
+        let owner = (attribute.to_string(), value.clone());
+        if added {
+            values.insert(value.clone());
+            self.owners.insert(owner, entity);
+        } else {
+            values.remove(&value);
+            if self.owners.get(&owner) == Some(&entity) {
+                self.owners.remove(&owner);
+            }
+        }
+        self.next_id = self.next_id.max(entity + 1);
+        self.rows.push(vec![
+            DataValue::from(entity),
+            DataValue::from(attribute),
+            value,
+            DataValue::Validity(Validity {
+                timestamp: ValidityTs(Reverse(ts)),
+                is_assert: Reverse(added),
+            }),
+        ]);
+    }
+    /// Restore the state from a stored datom. Only the `newest` datom of each triple
+    /// decides whether the triple currently holds.
+    fn restore(&mut self, row: &Tuple, newest: bool) {
+        if let (Some(entity), Some(attribute), DataValue::Validity(vld)) =
+            (row[0].get_int(), row[1].get_str(), &row[3])
+        {
+            if newest && vld.is_assert.0 {
+                self.current
+                    .entry((entity, attribute.to_string()))
+                    .or_default()
+                    .insert(row[2].clone());
+                self.owners
+                    .insert((attribute.to_string(), row[2].clone()), entity);
+            }
+            self.next_id = self.next_id.max(entity + 1);
+            self.last_ts = self.last_ts.max(vld.timestamp.0 .0);
+        }
+    }
+    fn fresh_id(&mut self) -> i64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+    fn temp_id(&mut self, key: String, tempids: &mut TempIds) -> i64 {
+        if let Some(id) = tempids.0.get(&key) {
+            return *id;
+        }
+        let id = self.fresh_id();
+        tempids.0.insert(key, id);
+        id
+    }
+    fn entity(&mut self, edn: &Edn, tempids: &mut TempIds) -> Result<i64> {
+        match edn {
+            Edn::Int(i) if *i >= 0 => {
+                self.next_id = self.next_id.max(*i + 1);
+                Ok(*i)
+            }
+            Edn::Int(_) | Edn::Str(_) => Ok(self.temp_id(edn.to_string(), tempids)),
+            Edn::Tagged(tag, form) if tag == "db/id" => match &**form {
+                Edn::Vector(v) if v.len() == 2 => Ok(self.temp_id(v[1].to_string(), tempids)),
+                Edn::Vector(v) if v.len() == 1 => Ok(self.fresh_id()),
+                f => bad_tx(format!("invalid #db/id {f}")),
+            },
+            Edn::Vector(v) if v.len() == 2 => {
+                let key = (attribute_name(&v[0])?, edn_to_value(&v[1])?);
+                match self.owners.get(&key) {
+                    Some(e) => Ok(*e),
+                    None => bad_tx(format!("lookup ref {edn} does not match any entity")),
+                }
+            }
+            e => bad_tx(format!("{e} is not an entity id")),
+        }
+    }
+    fn value(&mut self, edn: &Edn, tempids: &mut TempIds) -> Result<DataValue> {
+        match edn {
+            Edn::Tagged(tag, _) if tag == "db/id" => {
+                Ok(DataValue::from(self.entity(edn, tempids)?))
+            }
+            e => edn_to_value(e),
+        }
+    }
+    /// The datoms of an entity map, whose collection values hold several values
+    /// and whose map values are nested entities. Returns the id of the entity.
+    fn entity_map(
+        &mut self,
+        map: &[(Edn, Edn)],
+        tempids: &mut TempIds,
+        datoms: &mut Vec<PendingDatom>,
+    ) -> Result<i64> {
+        let entity = match map.iter().find(|(k, _)| *k == Edn::Keyword("db/id".into())) {
+            Some((_, e)) => self.entity(e, tempids)?,
+            None => self.fresh_id(),
+        };
+        for (k, v) in map {
+            let attribute = attribute_name(k)?;
+            if attribute == "db/id" {
+                continue;
+            }
+            let values = match v {
+                Edn::Vector(l) | Edn::List(l) | Edn::Set(l) => l.iter().collect_vec(),
+                v => vec![v],
+            };
+            for v in values {
+                let value = match v {
+                    Edn::Map(m) => DataValue::from(self.entity_map(m, tempids, datoms)?),
+                    v => self.value(v, tempids)?,
+                };
+                datoms.push(PendingDatom {
+                    entity,
+                    attribute: attribute.clone(),
+                    value,
+                    added: true,
+                });
+            }
+        }
+        Ok(entity)
+    }
+    /// Import one transaction, given as a vector of operations or as a log entry
+    /// `{:t t :data [...]}`.
+    fn transaction(&mut self, tx: &Edn) -> Result<()> {
+        let (ops, mut t) = match tx {
+            Edn::Vector(ops) | Edn::List(ops) => (ops, None),
+            Edn::Map(m) => {
+                let get = |name: &str| {
+                    m.iter()
+                        .find(|(k, _)| *k == Edn::Keyword(name.into()))
+                        .map(|(_, v)| v)
+                };
+                let t = match get("t") {
+                    None => None,
+                    Some(Edn::Int(t)) => Some(*t),
+                    Some(t) => bad_tx(format!(":t must be an integer, got {t}"))?,
+                };
+                match get("data") {
+                    Some(Edn::Vector(ops) | Edn::List(ops)) => (ops, t),
+                    _ => bad_tx("a log entry requires a vector of operations in :data".into())?,
+                }
+            }
+            tx => bad_tx(format!("{tx} is not a transaction"))?,
+        };
+        let mut tempids = TempIds::default();
+        let mut datoms = vec![];
+        for op in ops {
+            match op {
+                Edn::Vector(args) if !args.is_empty() => {
+                    let op_name = match &args[0] {
+                        Edn::Keyword(k) => k.as_str(),
+                        o => bad_tx(format!("unknown operation {o}"))?,
+                    };
+                    match (op_name, args.len()) {
+                        ("db/add" | "db/retract", 4) => {
+                            let entity = self.entity(&args[1], &mut tempids)?;
+                            let attribute = attribute_name(&args[2])?;
+                            let value = self.value(&args[3], &mut tempids)?;
+                            datoms.push(PendingDatom {
+                                entity,
+                                attribute,
+                                value,
+                                added: op_name == "db/add",
+                            });
+                        }
+                        ("db/retractEntity" | "db.fn/retractEntity", 2) => {
+                            let entity = self.entity(&args[1], &mut tempids)?;
+                            for ((_, attribute), values) in self
+                                .current
+                                .range((entity, String::new())..(entity + 1, String::new()))
+                            {
+                                for value in values {
+                                    datoms.push(PendingDatom {
+                                        entity,
+                                        attribute: attribute.clone(),
+                                        value: value.clone(),
+                                        added: false,
+                                    });
+                                }
+                            }
+                        }
+                        _ => bad_tx(format!("unsupported operation {op}"))?,
+                    }
+                }
+                Edn::Map(m) => {
+                    self.entity_map(m, &mut tempids, &mut datoms)?;
+                }
+                Edn::Tagged(tag, datom) if tag == "datom" || tag == "datascript/Datom" => {
+                    let datom = match &**datom {
+                        Edn::Vector(d) if (3..=5).contains(&d.len()) => d,
+                        d => bad_tx(format!("invalid datom {d}"))?,
+                    };
+                    if let (None, Some(Edn::Int(tx))) = (t, datom.get(3)) {
+                        t = Some(*tx);
+                    }
+                    let added = match datom.get(4) {
+                        None => true,
+                        Some(Edn::Bool(b)) => *b,
+                        Some(a) => bad_tx(format!(
+                            "the operation of a datom must be a boolean, got {a}"
+                        ))?,
+                    };
+                    datoms.push(PendingDatom {
+                        entity: self.entity(&datom[0], &mut tempids)?,
+                        attribute: attribute_name(&datom[1])?,
+                        value: self.value(&datom[2], &mut tempids)?,
+                        added,
+                    });
+                }
+                op => bad_tx(format!("unsupported operation {op}"))?,
+            }
+        }
+
+        // the time of the transaction is its instant if it has one
+        let instant = datoms
+            .iter()
+            .find(|d| d.attribute == TX_INSTANT && d.added)
+            .and_then(|d| d.value.get_float());
+        let ts = match (instant, t) {
+            (Some(secs), _) => (secs * 1_000_000.).round() as i64,
+            (None, Some(t)) => t,
+            (None, None) => self.last_ts + 1,
+        };
+        for datom in datoms {
+            self.apply(datom.entity, &datom.attribute, datom.value, datom.added, ts);
+        }
+        self.last_ts = self.last_ts.max(ts);
+        Ok(())
+    }
+}
+
+fn attribute_name(edn: &Edn) -> Result<String> {
+    match edn {
+        Edn::Keyword(k) => Ok(k.clone()),
+        a => bad_tx(format!("attribute {a} must be a keyword")),
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Import Datomic or Datascript transactions written in EDN into the stored relation
+    /// `relation`, which is created if it does not exist. Returns the number of datoms written.
+    ///
+    /// Each top-level form of `edn` is a transaction, either a vector of operations
+    /// (`[:db/add e a v]`, `[:db/retract e a v]`, `[:db/retractEntity e]` or entity maps),
+    /// or a log entry `{:t t :data [#datom [e a v tx added] ...]}`.
+    /// Entity ids can be integers, temporary ids (strings, negative integers or `#db/id`)
+    /// or lookup refs `[attribute value]`, and a value is an entity reference only if written
+    /// with `#db/id`. The validity of the datoms is the `:db/txInstant` of their transaction
+    /// if present, otherwise `:t`, otherwise one after the previous transaction.
+    pub fn import_edn_transactions(&'s self, relation: &str, edn: &str) -> Result<usize> {
+        let forms = parse_edn(edn)?;
+        let name = SmartString::from(relation);
+        let locks = self.obtain_relation_locks(iter::once(&name));
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+        let mut tx = self.transact_write()?;
+        let mut importer = DatomImporter::default();
+        if tx.relation_exists(relation)? {
+            let handle = tx.get_relation(relation, false)?;
+            ensure!(
+                handle.metadata == datom_metadata(),
+                NotADatomRelationError(relation.to_string())
+            );
+            // for each triple, the newest datom comes first
+            let mut prev: Option<Tuple> = None;
+            for row in handle.scan_all(&tx) {
+                let row = row?;
+                let newest = prev.as_ref().is_none_or(|p| p[..3] != row[..3]);
+                importer.restore(&row, newest);
+                prev = Some(row);
+            }
+        } else {
+            let span = SourceSpan(0, 0);
+            tx.create_relation(InputRelationHandle {
+                name: Symbol::new(relation, span),
+                metadata: datom_metadata(),
+                key_bindings: vec![],
+                dep_bindings: vec![],
+                span,
+            })?;
+        }
+
+        for form in &forms {
+            importer.transaction(form)?;
+        }
+        let n = importer.rows.len();
+        let rows = NamedRows::new(datom_headers(), importer.rows);
+        self.import_relation(&mut tx, relation, rows, current_validity())?;
+        tx.commit_tx()?;
+        Ok(n)
+    }
+    /// Export the datoms of `relation` as Datomic log entries, one transaction per line
+    /// in order of time: `{:t t, :data [#datom [e a v t added] ...]}`,
+    /// where `t` is the timestamp of the validity. The result can be imported again
+    /// with [Db::import_edn_transactions].
+    pub fn export_edn_transactions(&'s self, relation: &str) -> Result<String> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(relation, false)?;
+        ensure!(
+            handle.metadata == datom_metadata(),
+            NotADatomRelationError(relation.to_string())
+        );
+        drop(tx);
+        let rows = self
+            .export_relations([relation].into_iter())?
+            .remove(relation)
+            .unwrap()
+            .rows;
+        let mut by_time: BTreeMap<i64, Vec<Edn>> = BTreeMap::new();
+        for row in rows {
+            let vld = match &row[3] {
+                DataValue::Validity(vld) => vld,
+                _ => unreachable!(),
+            };
+            let ts = vld.timestamp.0 .0;
+            let datom = Edn::Vector(vec![
+                Edn::Int(row[0].get_int().unwrap()),
+                Edn::Keyword(row[1].get_str().unwrap().to_string()),
+                value_to_edn(&row[2])?,
+                Edn::Int(ts),
+                Edn::Bool(vld.is_assert.0),
+            ]);
+            by_time
+                .entry(ts)
+                .or_default()
+                .push(Edn::Tagged("datom".to_string(), Box::new(datom)));
+        }
+        let mut ret = String::new();
+        for (ts, datoms) in by_time {
+            let entry = Edn::Map(vec![
+                (Edn::Keyword("t".to_string()), Edn::Int(ts)),
+                (Edn::Keyword("data".to_string()), Edn::Vector(datoms)),
+            ]);
+            ret.push_str(&entry.to_string());
+            ret.push('\n');
+        }
+        Ok(ret)
+    }
+}
@@ -10,6 +10,7 @@ use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
 use std::default::Default;
 use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
 use std::iter;
 use std::path::Path;
 #[allow(unused_imports)]
@@ -17,6 +18,8 @@ use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 #[allow(unused_imports)]
 use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 #[allow(unused_imports)]
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
@@ -31,10 +34,11 @@ use miette::{bail, ensure, miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
 use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
+use twox_hash::XxHash64;
 
-use crate::data::functions::current_validity;
+use crate::data::functions::{current_validity, derive_seed, seed_rng};
 use crate::data::json::JsonValue;
-use crate::data::program::{InputProgram, QueryAssertion, RelationOp, ReturnMutation};
+use crate::data::program::{InputProgram, QueryAssertion, RelationOp};
 use crate::data::relation::ColumnDef;
 use crate::data::tuple::{Tuple, TupleT};
 use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
@@ -42,21 +46,39 @@ use crate::fixed_rule::DEFAULT_FIXED_RULES;
 use crate::fts::TokenizerCache;
 use crate::parse::sys::SysOp;
 use crate::parse::{parse_expressions, parse_script, CozoScript, SourceSpan};
+use crate::query::advisor::advise_indices;
 use crate::query::compile::{CompiledProgram, CompiledRule, CompiledRuleSet};
 use crate::query::ra::{
-    FilteredRA, FtsSearchRA, HnswSearchRA, InnerJoin, LshSearchRA, NegJoin, RelAlgebra, ReorderRA,
-    StoredRA, StoredWithValidityRA, TempStoreRA, UnificationRA,
+    FilteredRA, FtsSearchRA, GeoSearchRA, HnswSearchRA, InnerJoin, LshSearchRA, NegJoin,
+    RelAlgebra, ReorderRA, StoredRA, StoredWithValidityRA, TempStoreRA, UnificationRA, VirtualRA,
 };
+use crate::query::stored::MutationCounts;
 #[allow(unused_imports)]
+#[cfg(feature = "compressed-backup")]
+use crate::runtime::backup::is_backup_v2;
 use crate::runtime::callback::{
     CallbackCollector, CallbackDeclaration, CallbackOp, EventCallbackRegistry,
 };
+use crate::runtime::cdc::list_cdc_sinks;
+use crate::runtime::cron::list_schedules;
+use crate::runtime::idgen::{list_id_gens, IdGenerators};
+use crate::runtime::metrics::Metrics;
+use crate::runtime::module::list_modules;
+use crate::runtime::plan::{query_plan, QueryPlan};
+use crate::runtime::procedure::{list_procedures, NestedProcedureCall};
+use crate::runtime::quantization::VectorQuantizer;
+use crate::runtime::query_cache::{CacheKey, QueryCache, ReadSet, TrackedTx};
 use crate::runtime::relation::{
-    extend_tuple_from_v, AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId,
+    extend_tuple_from_v, AccessLevel, InsufficientAccessLevel, MutationKind, RelationHandle,
+    RelationId,
 };
+use crate::runtime::sandbox::Sandbox;
+use crate::runtime::scheduler::{MemoryGauge, QueryScheduler, QueueKind};
+use crate::runtime::spans::span;
 use crate::runtime::transact::SessionTx;
+use crate::runtime::virtual_relation::{VirtualRelation, VirtualRelationRegistry};
 use crate::storage::temp::TempStorage;
-use crate::storage::Storage;
+use crate::storage::{Storage, StoreTx};
 use crate::{decode_tuple_from_kv, FixedRule, Symbol};
 
 pub(crate) struct RunningQueryHandle {
@@ -73,7 +95,7 @@ impl Drop for RunningQueryCleanup {
     fn drop(&mut self) {
         let mut map = self.running_queries.lock().unwrap();
         if let Some(handle) = map.remove(&self.id) {
-            handle.poison.0.store(true, Ordering::Relaxed);
+            handle.poison.cancel();
         }
     }
 }
@@ -92,6 +114,27 @@ pub enum ScriptMutability {
     Immutable,
 }
 
+/// A script that has been run, as reported to the query observer, see [Db::set_query_observer].
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryEvent {
+    /// The text of the script.
+    pub script: String,
+    /// A hash of the parameters, stable across runs, so that scripts run with the same
+    /// parameters can be told apart without logging their values.
+    pub params_hash: u64,
+    /// Whether the script was allowed to mutate the database.
+    pub mutability: ScriptMutability,
+    /// Wall-clock time taken in seconds, always 0 on WASM.
+    pub took: f64,
+    /// The number of rows returned, if the script succeeded.
+    pub rows: Option<usize>,
+    /// The error message, if the script failed.
+    pub error: Option<String>,
+}
+
+/// Hook receiving each script that is run, see [Db::set_query_observer].
+type QueryObserver = dyn Fn(QueryEvent) + Send + Sync;
+
 /// The database object of Cozo.
 #[derive(Clone)]
 pub struct Db<S> {
@@ -102,13 +145,25 @@ pub struct Db<S> {
     pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
     pub(crate) fixed_rules: Arc<ShardedLock<BTreeMap<String, Arc<Box<dyn FixedRule>>>>>,
     pub(crate) tokenizers: Arc<TokenizerCache>,
-    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) id_generators: Arc<IdGenerators>,
     callback_count: Arc<AtomicU32>,
-    #[cfg(not(target_arch = "wasm32"))]
     pub(crate) event_callbacks: Arc<ShardedLock<EventCallbackRegistry>>,
     relation_locks: Arc<ShardedLock<BTreeMap<SmartString<LazyCompact>, Arc<ShardedLock<()>>>>>,
+    default_timeout: Arc<ShardedLock<Option<f64>>>,
+    pub(crate) scheduler: Arc<QueryScheduler>,
+    pub(crate) metrics: Arc<Metrics>,
+    /// IDs of the callbacks of the running change-data-capture sinks
+    pub(crate) cdc_sinks: Arc<Mutex<BTreeMap<SmartString<LazyCompact>, u32>>>,
+    pub(crate) query_cache: Arc<QueryCache>,
+    mutation_guard: Arc<ShardedLock<Option<Arc<MutationGuard>>>>,
+    sandbox: Arc<ShardedLock<Option<Arc<Sandbox>>>>,
+    query_observer: Arc<ShardedLock<Option<Arc<QueryObserver>>>>,
+    pub(crate) virtual_relations: VirtualRelationRegistry,
 }
 
+/// Hook deciding whether a change to a stored relation is allowed, see [Db::set_mutation_guard].
+type MutationGuard = dyn Fn(&str, MutationKind) -> Result<()> + Send + Sync;
+
 impl<S> Debug for Db<S> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Db")
@@ -120,6 +175,17 @@ impl<S> Debug for Db<S> {
 #[diagnostic(code(db::init))]
 pub(crate) struct BadDbInit(#[help] pub(crate) String);
 
+/// Options for [Db::restore_backup_with_options].
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// Only restore these stored relations, together with their indices.
+    /// All relations are restored if `None`.
+    pub relations: Option<Vec<String>>,
+    /// Do not restore the indices of the relations: their definitions are dropped,
+    /// and they can be created again after the restore.
+    pub skip_indices: bool,
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Cannot import data into relation {0} as it is an index")]
 #[diagnostic(code(tx::import_into_index))]
@@ -245,6 +311,22 @@ impl NamedRows {
 const STATUS_STR: &str = "status";
 const OK_STR: &str = "OK";
 
+const BYTES_BACKUP_MAGIC: &[u8] = b"COZOBAK1";
+
+fn take_backup_entry(data: &mut &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut take = |n: usize| -> Result<&[u8]> {
+        ensure!(data.len() >= n, "Cannot restore backup: data is truncated");
+        let (head, rest) = data.split_at(n);
+        *data = rest;
+        Ok(head)
+    };
+    let k_len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+    let v_len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+    let k = take(k_len)?.to_vec();
+    let v = take(v_len)?.to_vec();
+    Ok((k, v))
+}
+
 /// The query and parameters.
 pub type Payload = (String, BTreeMap<String, DataValue>);
 
@@ -259,6 +341,133 @@ pub enum TransactionPayload {
     Query(Payload),
 }
 
+/// A multi-transaction driven on the calling thread. See [Db::inline_transaction].
+pub struct InlineTransaction<'s, S: Storage<'s>> {
+    db: &'s Db<S>,
+    tx: SessionTx<'s>,
+    cleanups: Vec<(Vec<u8>, Vec<u8>)>,
+    ts: ValidityTs,
+    callback_targets: BTreeSet<SmartString<LazyCompact>>,
+    callback_collector: CallbackCollector,
+    write_locks: BTreeMap<SmartString<LazyCompact>, Arc<ShardedLock<()>>>,
+    is_write: bool,
+}
+
+impl<'s, S: Storage<'s>> InlineTransaction<'s, S> {
+    /// Runs a single script in the transaction.
+    pub fn run_script(
+        &mut self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let mutability = if self.is_write {
+            ScriptMutability::Mutable
+        } else {
+            ScriptMutability::Immutable
+        };
+        let db = self.db;
+        db.observed(payload, &params, mutability, || {
+            self.run_script_unobserved(payload, &params)
+        })
+    }
+    fn run_script_unobserved(
+        &mut self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let p = parse_script(
+            payload,
+            params,
+            &self.db.fixed_rules.read().unwrap(),
+            self.ts,
+        )?;
+        let p = p.get_single_program()?;
+        if let Some(write_lock_name) = p.needs_write_lock() {
+            if let Entry::Vacant(e) = self.write_locks.entry(write_lock_name) {
+                let lock = self
+                    .db
+                    .obtain_relation_locks(iter::once(e.key()))
+                    .pop()
+                    .unwrap();
+                e.insert(lock);
+            }
+        }
+
+        self.db.execute_single_program(
+            p,
+            &mut self.tx,
+            &mut self.cleanups,
+            self.ts,
+            &self.callback_targets,
+            &mut self.callback_collector,
+            &Poison::default(),
+        )
+    }
+    /// Commits the transaction.
+    pub fn commit(mut self) -> Result<()> {
+        for (lower, upper) in self.cleanups {
+            if let Err(err) = self.tx.store_tx.del_range_from_persisted(&lower, &upper) {
+                eprintln!("{err:?}")
+            }
+        }
+
+        let res = self.tx.commit_tx();
+        if !self.callback_collector.is_empty() {
+            self.db.send_callbacks(self.callback_collector)
+        }
+        res
+    }
+    /// Aborts the transaction.
+    pub fn abort(self) {}
+}
+
+/// A read-only view of the database pinned at the time it was taken: all queries run on it see
+/// the same data, whatever is written in the meantime. See [Db::snapshot].
+pub struct Snapshot<'s, S: Storage<'s>> {
+    db: &'s Db<S>,
+    tx: SessionTx<'s>,
+    ts: ValidityTs,
+}
+
+impl<'s, S: Storage<'s>> Snapshot<'s, S> {
+    /// Runs a single read-only script on the snapshot.
+    pub fn run_script(
+        &mut self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let db = self.db;
+        db.observed(payload, &params, ScriptMutability::Immutable, || {
+            self.run_script_unobserved(payload, &params)
+        })
+    }
+    fn run_script_unobserved(
+        &mut self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let p = parse_script(
+            payload,
+            params,
+            &self.db.fixed_rules.read().unwrap(),
+            self.ts,
+        )?;
+        let p = p.get_single_program()?;
+        if p.needs_write_lock().is_some() {
+            bail!("Cannot write to a snapshot");
+        }
+        self.db.execute_single_program(
+            p,
+            &mut self.tx,
+            &mut vec![],
+            self.ts,
+            &Default::default(),
+            &mut BTreeMap::new(),
+            &Poison::default(),
+        )
+    }
+}
+
 impl<'s, S: Storage<'s>> Db<S> {
     /// Create a new database object with the given storage.
     /// You must call [`initialize`](Self::initialize) immediately after creation.
@@ -272,12 +481,20 @@ impl<'s, S: Storage<'s>> Db<S> {
             running_queries: Default::default(),
             fixed_rules: Arc::new(ShardedLock::new(DEFAULT_FIXED_RULES.clone())),
             tokenizers: Arc::new(Default::default()),
-            #[cfg(not(target_arch = "wasm32"))]
+            id_generators: Default::default(),
             callback_count: Default::default(),
             // callback_receiver: Arc::new(receiver),
-            #[cfg(not(target_arch = "wasm32"))]
             event_callbacks: Default::default(),
             relation_locks: Default::default(),
+            default_timeout: Default::default(),
+            scheduler: Default::default(),
+            metrics: Default::default(),
+            cdc_sinks: Default::default(),
+            query_cache: Default::default(),
+            mutation_guard: Default::default(),
+            sandbox: Default::default(),
+            query_observer: Default::default(),
+            virtual_relations: Default::default(),
         };
         Ok(ret)
     }
@@ -285,6 +502,9 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// Must be called after creation of the database to initialize the runtime state.
     pub fn initialize(&'s self) -> Result<()> {
         self.load_last_ids()?;
+        self.id_generators.load(self.transact()?.id_gens()?);
+        self.load_cdc_sinks()?;
+        self.query_cache.clear();
         Ok(())
     }
 
@@ -301,13 +521,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         payloads: Receiver<TransactionPayload>,
         results: Sender<Result<NamedRows>>,
     ) {
-        let tx = if is_write {
-            self.transact_write()
-        } else {
-            self.transact()
-        };
-        let mut cleanups: Vec<(Vec<u8>, Vec<u8>)> = vec![];
-        let mut tx = match tx {
+        let mut tx = match self.inline_transaction(is_write) {
             Ok(tx) => tx,
             Err(err) => {
                 let _ = results.send(Err(err));
@@ -315,26 +529,10 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         };
 
-        let ts = current_validity();
-        let callback_targets = self.current_callback_targets();
-        let mut callback_collector = BTreeMap::new();
-        let mut write_locks = BTreeMap::new();
-
         for payload in payloads {
             match payload {
                 TransactionPayload::Commit => {
-                    for (lower, upper) in cleanups {
-                        if let Err(err) = tx.store_tx.del_range_from_persisted(&lower, &upper) {
-                            eprintln!("{err:?}")
-                        }
-                    }
-
-                    let _ = results.send(tx.commit_tx().map(|_| NamedRows::default()));
-                    #[cfg(not(target_arch = "wasm32"))]
-                    if !callback_collector.is_empty() {
-                        self.send_callbacks(callback_collector)
-                    }
-
+                    let _ = results.send(tx.commit().map(|_| NamedRows::default()));
                     break;
                 }
                 TransactionPayload::Abort => {
@@ -342,51 +540,88 @@ impl<'s, S: Storage<'s>> Db<S> {
                     break;
                 }
                 TransactionPayload::Query((script, params)) => {
-                    let p =
-                        match parse_script(&script, &params, &self.fixed_rules.read().unwrap(), ts)
-                        {
-                            Ok(p) => p,
-                            Err(err) => {
-                                if results.send(Err(err)).is_err() {
-                                    break;
-                                } else {
-                                    continue;
-                                }
-                            }
-                        };
-
-                    let p = match p.get_single_program() {
-                        Ok(p) => p,
-                        Err(err) => {
-                            if results.send(Err(err)).is_err() {
-                                break;
-                            } else {
-                                continue;
-                            }
-                        }
-                    };
-                    if let Some(write_lock_name) = p.needs_write_lock() {
-                        match write_locks.entry(write_lock_name) {
-                            Entry::Vacant(e) => {
-                                let lock = self
-                                    .obtain_relation_locks(iter::once(e.key()))
-                                    .pop()
-                                    .unwrap();
-                                e.insert(lock);
-                            }
-                            Entry::Occupied(_) => {}
-                        }
+                    if results.send(tx.run_script(&script, params)).is_err() {
+                        break;
                     }
+                }
+            }
+        }
+    }
+
+    /// Start a multi-transaction that is driven directly on the calling thread,
+    /// instead of through channels as in [Db::run_multi_transaction].
+    /// Useful where spawning threads is not possible, e.g. on WASM.
+    ///
+    /// Dropping the returned object without committing aborts the transaction.
+    pub fn inline_transaction(&'s self, is_write: bool) -> Result<InlineTransaction<'s, S>> {
+        let tx = if is_write {
+            self.transact_write()?
+        } else {
+            self.transact()?
+        };
+        Ok(InlineTransaction {
+            db: self,
+            tx,
+            cleanups: vec![],
+            ts: current_validity(),
+            callback_targets: self.current_callback_targets(),
+            callback_collector: BTreeMap::new(),
+            write_locks: BTreeMap::new(),
+            is_write,
+        })
+    }
+
+    /// Take a snapshot of the database, on which read-only scripts can be run
+    /// while other transactions keep writing. Validity in time-travel queries defaults to
+    /// the time the snapshot is taken.
+    ///
+    /// With the memory engine the data is copied, and with SQLite writes wait until
+    /// the snapshot is dropped.
+    pub fn snapshot(&'s self) -> Result<Snapshot<'s, S>> {
+        let tx = SessionTx {
+            store_tx: Box::new(self.db.snapshot()?),
+            temp_store_tx: self.temp_db.transact(true)?,
+            relation_store_id: self.relation_store_id.clone(),
+            temp_store_id: Default::default(),
+            tokenizers: self.tokenizers.clone(),
+            id_generators: self.id_generators.clone(),
+            id_gen_changes: vec![],
+            metrics: self.metrics.clone(),
+            memory_budget: self.scheduler.memory_budget(),
+            sandbox: self.sandbox.read().unwrap().clone(),
+            virtual_relations: self.virtual_relations.clone(),
+            fixed_rules: self.fixed_rules.clone(),
+        };
+        Ok(Snapshot {
+            db: self,
+            tx,
+            ts: current_validity(),
+        })
+    }
+
+    /// Drive a snapshot with channels, as [Db::run_multi_transaction] does for transactions.
+    /// The snapshot is released on receiving `Commit` or `Abort`, or when `payloads` is closed.
+    pub fn run_snapshot(
+        &'s self,
+        payloads: Receiver<TransactionPayload>,
+        results: Sender<Result<NamedRows>>,
+    ) {
+        let mut snapshot = match self.snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                let _ = results.send(Err(err));
+                return;
+            }
+        };
 
-                    let res = self.execute_single_program(
-                        p,
-                        &mut tx,
-                        &mut cleanups,
-                        ts,
-                        &callback_targets,
-                        &mut callback_collector,
-                    );
-                    if results.send(res).is_err() {
+        for payload in payloads {
+            match payload {
+                TransactionPayload::Commit | TransactionPayload::Abort => {
+                    let _ = results.send(Ok(NamedRows::default()));
+                    break;
+                }
+                TransactionPayload::Query((script, params)) => {
+                    if results.send(snapshot.run_script(&script, params)).is_err() {
                         break;
                     }
                 }
@@ -406,16 +641,18 @@ impl<'s, S: Storage<'s>> Db<S> {
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
     ) -> Result<NamedRows> {
-        self.run_script_ast(
-            parse_script(
-                payload,
-                &params,
-                &self.get_fixed_rules(),
-                current_validity(),
-            )?,
-            current_validity(),
-            mutability,
-        )
+        self.run_script_with_handle(payload, params, mutability, &Poison::default())
+    }
+
+    /// Run the CozoScript passed in, returning the result as an Arrow record batch.
+    #[cfg(feature = "arrow")]
+    pub fn run_script_arrow(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<arrow_array::RecordBatch> {
+        self.run_script(payload, params, mutability)?.to_arrow()
     }
 
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
@@ -427,24 +664,300 @@ impl<'s, S: Storage<'s>> Db<S> {
         self.run_script(payload, params, ScriptMutability::Immutable)
     }
 
+    /// Run the CozoScript passed in, with a handle that can be used to cancel it.
+    ///
+    /// Calling [Poison::cancel] on `handle` from another thread terminates the script
+    /// with an error. The same handle can be shared among several scripts.
+    pub fn run_script_with_handle(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        handle: &Poison,
+    ) -> Result<NamedRows> {
+        let _span = span!("cozo.script", ?mutability).entered();
+        self.observed(payload, &params, mutability, || {
+            let cur_vld = current_validity();
+            let script = span!("cozo.parse")
+                .in_scope(|| parse_script(payload, &params, &self.get_fixed_rules(), cur_vld))?;
+            match self.query_cache.key_for(
+                payload,
+                &params,
+                &script,
+                &self.virtual_relations,
+                cur_vld,
+            ) {
+                Some(key) => self.run_script_cached(key, script, cur_vld, mutability, handle),
+                None => self.run_script_ast_with_handle(script, cur_vld, mutability, handle),
+            }
+        })
+    }
+
+    /// Compile the query passed in without running it, and return its plan as data.
+    ///
+    /// This is the plan displayed by `::explain`, as a tree that tools can inspect,
+    /// for example to reject queries scanning whole relations with [QueryPlan::full_scans].
+    pub fn explain(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<QueryPlan> {
+        let script = parse_script(
+            payload,
+            &params,
+            &self.get_fixed_rules(),
+            current_validity(),
+        )?;
+        let prog = script.get_single_program()?;
+        let mut tx = self.transact()?;
+        self.generate_system_relations(&mut tx, &prog)?;
+        let (normalized_program, _) = prog.clone().into_normalized_program(&tx)?;
+        let (stratified_program, _) = normalized_program.into_stratified_program()?;
+        let program = stratified_program.magic_sets_rewrite(&tx)?;
+        let compiled =
+            tx.stratified_magic_compile(program, prog.experimental, &prog.hints, prog.as_of_tx)?;
+        Ok(query_plan(&compiled))
+    }
+
+    /// Install a hook that receives an event for each script run with [Db::run_script]
+    /// and its variants, in transactions and on snapshots, after it has finished.
+    /// Useful for audit trails and tracing. Replaces the previous observer, if any.
+    ///
+    /// The observer runs on the thread that ran the script, so it should return quickly.
+    pub fn set_query_observer(&self, observer: impl Fn(QueryEvent) + Send + Sync + 'static) {
+        *self.query_observer.write().unwrap() = Some(Arc::new(observer));
+    }
+
+    /// Remove the hook installed by [Db::set_query_observer].
+    pub fn clear_query_observer(&self) {
+        *self.query_observer.write().unwrap() = None;
+    }
+
+    /// Run the CozoScript passed in, inside the given span: the spans of the execution
+    /// are created as its children, wherever the execution takes place.
+    /// Useful when the script is run on behalf of a request traced by the caller.
+    #[cfg(feature = "tracing")]
+    pub fn run_script_in_span(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        parent: &tracing::Span,
+    ) -> Result<NamedRows> {
+        parent.in_scope(|| self.run_script(payload, params, mutability))
+    }
+
+    /// Run `f` and report the script to the query observer, if any.
+    fn observed(
+        &self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        f: impl FnOnce() -> Result<NamedRows>,
+    ) -> Result<NamedRows> {
+        let observer = self.query_observer.read().unwrap().clone();
+        let Some(observer) = observer else {
+            return f();
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let start = Instant::now();
+        let res = f();
+        #[cfg(not(target_arch = "wasm32"))]
+        let took = start.elapsed().as_secs_f64();
+        #[cfg(target_arch = "wasm32")]
+        let took = 0.;
+        let mut hasher = XxHash64::with_seed(0);
+        params.hash(&mut hasher);
+        observer(QueryEvent {
+            script: payload.to_string(),
+            params_hash: hasher.finish(),
+            mutability,
+            took,
+            rows: res.as_ref().ok().map(|r| r.rows.len()),
+            error: res.as_ref().err().map(|err| err.to_string()),
+        });
+        res
+    }
+
+    /// Enable caching of the results of read-only queries, keeping at most
+    /// `entries` results. A cached result is returned for a script run again
+    /// with the same parameters, as long as none of the relations it read has
+    /// been written to since. Scripts calling functions such as `now()` or
+    /// `rand_float()`, or using fixed rules that read external data, are never
+    /// cached. `0` disables the cache, which is the default.
+    pub fn set_query_cache_capacity(&self, entries: usize) {
+        self.query_cache.set_capacity(entries);
+    }
+
+    fn run_script_cached(
+        &'s self,
+        key: CacheKey,
+        script: CozoScript,
+        cur_vld: ValidityTs,
+        mutability: ScriptMutability,
+        handle: &Poison,
+    ) -> Result<NamedRows> {
+        if let Some(rows) = self.query_cache.get(&key) {
+            self.metrics.record_query(0., Some(rows.rows.len()));
+            return Ok(rows);
+        }
+        let seq = self.query_cache.current_seq();
+        let reads = Arc::new(Mutex::new(ReadSet::default()));
+        let res =
+            self.run_script_ast_impl(script, cur_vld, mutability, handle, Some(reads.clone()))?;
+        let reads = std::mem::take(&mut *reads.lock().unwrap());
+        self.query_cache.insert(key, seq, reads, res.clone());
+        Ok(res)
+    }
+
     /// Run the AST CozoScript passed in.
     pub fn run_script_ast(
         &'s self,
         payload: CozoScript,
         cur_vld: ValidityTs,
         mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        self.run_script_ast_with_handle(payload, cur_vld, mutability, &Poison::default())
+    }
+
+    /// Run the AST CozoScript passed in, with a handle that can be used to cancel it.
+    pub fn run_script_ast_with_handle(
+        &'s self,
+        payload: CozoScript,
+        cur_vld: ValidityTs,
+        mutability: ScriptMutability,
+        handle: &Poison,
+    ) -> Result<NamedRows> {
+        self.run_script_ast_impl(payload, cur_vld, mutability, handle, None)
+    }
+
+    fn run_script_ast_impl(
+        &'s self,
+        payload: CozoScript,
+        cur_vld: ValidityTs,
+        mutability: ScriptMutability,
+        handle: &Poison,
+        reads: Option<Arc<Mutex<ReadSet>>>,
     ) -> Result<NamedRows> {
         let read_only = mutability == ScriptMutability::Immutable;
-        match payload {
-            CozoScript::Single(p) => self.execute_single(cur_vld, p, read_only),
-            CozoScript::Imperative(ps) => self.execute_imperative(cur_vld, &ps, read_only),
-            CozoScript::Sys(op) => self.run_sys_op(op, read_only),
+        #[cfg(not(target_arch = "wasm32"))]
+        let start = Instant::now();
+        let res = match payload {
+            CozoScript::Single(p) => self.execute_single(cur_vld, p, read_only, handle, reads),
+            CozoScript::Imperative(ps) => self.execute_imperative(cur_vld, &ps, read_only, handle),
+            CozoScript::Sys(SysOp::Call(name, args)) => {
+                self.call_procedure(&name, args, cur_vld, mutability, handle)
+            }
+            CozoScript::Sys(op) => self.run_sys_op(op, read_only, handle),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let took = start.elapsed().as_secs_f64();
+        #[cfg(target_arch = "wasm32")]
+        let took = 0.;
+        self.metrics
+            .record_query(took, res.as_ref().ok().map(|r| r.rows.len()));
+        res
+    }
+
+    /// Metrics collected by this instance, in the Prometheus text exposition format.
+    pub fn metrics_text(&self) -> String {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        self.metrics.render(&[
+            (
+                "cozo_tokenizer_cache_hits_total",
+                "Number of full-text tokenizer lookups served from the cache.",
+                get(&self.tokenizers.hits),
+            ),
+            (
+                "cozo_tokenizer_cache_misses_total",
+                "Number of full-text tokenizers built.",
+                get(&self.tokenizers.misses),
+            ),
+            (
+                "cozo_query_cache_hits_total",
+                "Number of queries answered from the query cache.",
+                get(&self.query_cache.hits),
+            ),
+            (
+                "cozo_query_cache_misses_total",
+                "Number of cacheable queries that had to be run.",
+                get(&self.query_cache.misses),
+            ),
+        ])
+    }
+
+    /// Limit the number of scripts running concurrently on this instance.
+    /// Read-only and writing scripts are queued separately, so that one kind cannot
+    /// starve the other. `None` means unlimited, which is the default.
+    /// System ops are never queued.
+    pub fn set_max_concurrency(&self, read: Option<usize>, write: Option<usize>) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Maximum concurrency must be positive")]
+        #[diagnostic(code(db::bad_max_concurrency))]
+        struct BadMaxConcurrency;
+
+        ensure!(read != Some(0) && write != Some(0), BadMaxConcurrency);
+        #[cfg(target_arch = "wasm32")]
+        if read.is_some() || write.is_some() {
+            bail!("Cannot limit concurrency in WASM")
         }
+        self.scheduler.set_limits(read, write);
+        Ok(())
+    }
+
+    /// Set the approximate number of bytes that the temporary data of running scripts
+    /// may use together. When the budget is nearly used up, new scripts wait for running
+    /// ones to finish, and are rejected if they wait for too long. A script that
+    /// exceeds the budget fails. `None` means unlimited, which is the default.
+    pub fn set_memory_budget(&self, bytes: Option<usize>) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Memory budget must be positive")]
+        #[diagnostic(code(db::bad_memory_budget))]
+        struct BadMemoryBudget;
+
+        ensure!(bytes != Some(0), BadMemoryBudget);
+        self.scheduler.set_memory_budget(bytes);
+        Ok(())
+    }
+
+    /// Restrict the files and URLs that scripts may access through fixed rules such as
+    /// `CsvReader` and through CDC sinks. `None`, the default, leaves IO unrestricted.
+    /// Running transactions keep the sandbox they started with.
+    pub fn set_sandbox(&self, sandbox: Option<Sandbox>) {
+        *self.sandbox.write().unwrap() = sandbox.map(Arc::new);
+    }
+
+    /// Fails if the sandbox forbids accessing `url`.
+    pub(crate) fn ensure_io_allowed(&self, url: &str) -> Result<()> {
+        match &*self.sandbox.read().unwrap() {
+            None => Ok(()),
+            Some(sandbox) => sandbox.check_url(url),
+        }
+    }
+
+    /// Set the timeout in seconds applied to every query that does not specify
+    /// `:timeout` itself. Pass `None` to remove the default.
+    pub fn set_default_timeout(&self, secs: Option<f64>) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Default timeout must be a positive number, got {0}")]
+        #[diagnostic(code(db::bad_default_timeout))]
+        struct BadDefaultTimeout(f64);
+
+        if let Some(secs) = secs {
+            ensure!(secs.is_finite() && secs > 0., BadDefaultTimeout(secs));
+            #[cfg(target_arch = "wasm32")]
+            bail!("Cannot set timeout in WASM")
+        }
+        *self.default_timeout.write().unwrap() = secs;
+        Ok(())
     }
 
     /// Export relations to JSON data.
     ///
     /// `relations` contains names of the stored relations to export.
+    /// All relations are read in the same transaction, so the export is a consistent
+    /// snapshot of the database even if other transactions write to it concurrently.
+    /// The only exception is the Sled engine, which does not support snapshots.
     pub fn export_relations<I, T>(&'s self, relations: I) -> Result<BTreeMap<String, NamedRows>>
     where
         T: AsRef<str>,
@@ -493,6 +1006,22 @@ impl<'s, S: Storage<'s>> Db<S> {
         }
         Ok(ret)
     }
+    /// Export the result of a read-only query in the shape returned by
+    /// [Self::export_relations], under the name `relation`.
+    /// The data can then be imported with [Self::import_relations] into a stored relation
+    /// whose columns are named like the headers of the query.
+    pub fn export_query(
+        &'s self,
+        relation: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<BTreeMap<String, NamedRows>> {
+        let res = self.run_script(payload, params, ScriptMutability::Immutable)?;
+        Ok(BTreeMap::from([(
+            relation.to_string(),
+            NamedRows::new(res.headers, res.rows),
+        )]))
+    }
     /// Import relations. The argument `data` accepts data in the shape of
     /// what was returned by [Self::export_relations].
     /// The target stored relations must already exist in the database.
@@ -515,41 +1044,82 @@ impl<'s, S: Storage<'s>> Db<S> {
         let mut tx = self.transact_write()?;
 
         for (relation_op, in_data) in data {
-            let is_delete;
-            let relation: &str = match relation_op.strip_prefix('-') {
-                None => {
-                    is_delete = false;
-                    &relation_op
-                }
-                Some(s) => {
-                    is_delete = true;
-                    s
-                }
-            };
-            if relation.contains(':') {
-                bail!(ImportIntoIndex(relation.to_string()))
+            self.import_relation(&mut tx, &relation_op, in_data, cur_vld)?;
+        }
+        tx.commit_tx()?;
+        Ok(())
+    }
+    /// Import the rows of a single relation within `tx`, as [Db::import_relations] does.
+    pub(crate) fn import_relation(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        relation_op: &str,
+        in_data: NamedRows,
+        cur_vld: ValidityTs,
+    ) -> Result<()> {
+        let is_delete;
+        let relation: &str = match relation_op.strip_prefix('-') {
+            None => {
+                is_delete = false;
+                relation_op
             }
-            let handle = tx.get_relation(relation, false)?;
-            let has_indices = !handle.indices.is_empty();
-
-            if handle.access_level < AccessLevel::Protected {
-                bail!(InsufficientAccessLevel(
-                    handle.name.to_string(),
-                    "data import".to_string(),
-                    handle.access_level
-                ));
+            Some(s) => {
+                is_delete = true;
+                s
             }
+        };
+        if relation.contains(':') {
+            bail!(ImportIntoIndex(relation.to_string()))
+        }
+        let kind = if is_delete {
+            MutationKind::Rm
+        } else {
+            MutationKind::Put
+        };
+        self.check_mutation(relation, kind)?;
+        let handle = tx.get_relation(relation, false)?;
+        let has_indices = !handle.indices.is_empty();
+        let index_exprs = handle.compile_index_exprs()?;
+        let tx_history = tx.tx_history(&handle)?;
+        let mut stack = vec![];
+
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "data import".to_string(),
+                handle.access_level
+            ));
+        }
 
-            let header2idx: BTreeMap<_, _> = in_data
-                .headers
-                .iter()
-                .enumerate()
-                .map(|(i, k)| -> Result<(&str, usize)> { Ok((k as &str, i)) })
-                .try_collect()?;
+        let header2idx: BTreeMap<_, _> = in_data
+            .headers
+            .iter()
+            .enumerate()
+            .map(|(i, k)| -> Result<(&str, usize)> { Ok((k as &str, i)) })
+            .try_collect()?;
 
-            let key_indices: Vec<_> = handle
+        let key_indices: Vec<_> = handle
+            .metadata
+            .keys
+            .iter()
+            .map(|col| -> Result<(usize, &ColumnDef)> {
+                let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
+                    miette!(
+                        "required header {} not found for relation {}",
+                        col.name,
+                        relation
+                    )
+                })?;
+                Ok((*idx, col))
+            })
+            .try_collect()?;
+
+        let val_indices: Vec<_> = if is_delete {
+            vec![]
+        } else {
+            handle
                 .metadata
-                .keys
+                .non_keys
                 .iter()
                 .map(|col| -> Result<(usize, &ColumnDef)> {
                     let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
@@ -561,30 +1131,48 @@ impl<'s, S: Storage<'s>> Db<S> {
                     })?;
                     Ok((*idx, col))
                 })
-                .try_collect()?;
+                .try_collect()?
+        };
 
-            let val_indices: Vec<_> = if is_delete {
-                vec![]
+        for row in in_data.rows {
+            let keys: Vec<_> = key_indices
+                .iter()
+                .map(|(i, col)| -> Result<DataValue> {
+                    let v = row
+                        .get(*i)
+                        .ok_or_else(|| miette!("row too short: {:?}", row))?;
+                    col.typing.coerce(v.clone(), cur_vld)
+                })
+                .try_collect()?;
+            let k_store = handle.encode_key_for_store(&keys, Default::default())?;
+            let existing = if has_indices || (is_delete && tx_history.is_some()) {
+                tx.store_tx.get(&k_store, false)?
             } else {
-                handle
-                    .metadata
-                    .non_keys
-                    .iter()
-                    .map(|col| -> Result<(usize, &ColumnDef)> {
-                        let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
-                            miette!(
-                                "required header {} not found for relation {}",
-                                col.name,
-                                relation
-                            )
-                        })?;
-                        Ok((*idx, col))
-                    })
-                    .try_collect()?
+                None
             };
-
-            for row in in_data.rows {
-                let keys: Vec<_> = key_indices
+            if has_indices {
+                if let Some(existing) = &existing {
+                    let mut old = keys.clone();
+                    extend_tuple_from_v(&mut old, existing);
+                    if is_delete || old != row {
+                        for (idx_rel, extractor) in handle.indices.values() {
+                            let idx_tup =
+                                handle.index_tuple(extractor, &old, &index_exprs, &mut stack)?;
+                            let encoded =
+                                idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                            tx.store_tx.del(&encoded)?;
+                        }
+                    }
+                }
+            }
+            if is_delete {
+                // only rows that were there are recorded as removed
+                if let (Some(history), Some(_)) = (&tx_history, &existing) {
+                    tx.record_tx_time(history, &keys, false, cur_vld)?;
+                }
+                tx.store_tx.del(&k_store)?;
+            } else {
+                let vals: Vec<_> = val_indices
                     .iter()
                     .map(|(i, col)| -> Result<DataValue> {
                         let v = row
@@ -593,98 +1181,139 @@ impl<'s, S: Storage<'s>> Db<S> {
                         col.typing.coerce(v.clone(), cur_vld)
                     })
                     .try_collect()?;
-                let k_store = handle.encode_key_for_store(&keys, Default::default())?;
-                if has_indices {
-                    if let Some(existing) = tx.store_tx.get(&k_store, false)? {
-                        let mut old = keys.clone();
-                        extend_tuple_from_v(&mut old, &existing);
-                        if is_delete || old != row {
-                            for (idx_rel, extractor) in handle.indices.values() {
-                                let idx_tup =
-                                    extractor.iter().map(|i| old[*i].clone()).collect_vec();
-                                let encoded =
-                                    idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
-                                tx.store_tx.del(&encoded)?;
-                            }
-                        }
-                    }
+                let v_store = handle.encode_val_only_for_store(&vals, Default::default())?;
+                tx.store_tx.put(&k_store, &v_store)?;
+                let mut kv = keys;
+                kv.extend(vals);
+                if let Some(history) = &tx_history {
+                    tx.record_tx_time(history, &kv, true, cur_vld)?;
                 }
-                if is_delete {
-                    tx.store_tx.del(&k_store)?;
-                } else {
-                    let vals: Vec<_> = val_indices
-                        .iter()
-                        .map(|(i, col)| -> Result<DataValue> {
-                            let v = row
-                                .get(*i)
-                                .ok_or_else(|| miette!("row too short: {:?}", row))?;
-                            col.typing.coerce(v.clone(), cur_vld)
-                        })
-                        .try_collect()?;
-                    let v_store = handle.encode_val_only_for_store(&vals, Default::default())?;
-                    tx.store_tx.put(&k_store, &v_store)?;
-                    if has_indices {
-                        let mut kv = keys;
-                        kv.extend(vals);
-                        for (idx_rel, extractor) in handle.indices.values() {
-                            let idx_tup = extractor.iter().map(|i| kv[*i].clone()).collect_vec();
-                            let encoded =
-                                idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
-                            tx.store_tx.put(&encoded, &[])?;
-                        }
+                if has_indices {
+                    tx.ensure_unique_in_indices(&handle, &kv, &index_exprs, &mut stack)?;
+                    for (idx_rel, extractor) in handle.indices.values() {
+                        let idx_tup =
+                            handle.index_tuple(extractor, &kv, &index_exprs, &mut stack)?;
+                        let (key, val) = idx_rel.encode_index_entry(&idx_tup)?;
+                        tx.store_tx.put(&key, &val)?;
                     }
                 }
             }
         }
-        tx.commit_tx()?;
         Ok(())
     }
-    /// Backup the running database into an Sqlite file
+    /// Backup the running database into a file.
+    ///
+    /// With the `compressed-backup` feature the backup is written in the v2 format, with
+    /// the data of each relation compressed separately. Otherwise it is an Sqlite file (v1).
     #[allow(unused_variables)]
     pub fn backup_db(&'s self, out_file: impl AsRef<Path>) -> Result<()> {
-        #[cfg(feature = "storage-sqlite")]
-        {
-            let sqlite_db = crate::new_cozo_sqlite(out_file)?;
-            if sqlite_db.relation_store_id.load(Ordering::SeqCst) != 0 {
-                bail!("Cannot create backup: data exists in the target database.");
-            }
-            let mut tx = self.transact()?;
-            let iter = tx.store_tx.range_scan(&[], &[0xFF]);
-            sqlite_db.db.batch_put(iter)?;
-            tx.commit_tx()?;
-            Ok(())
+        #[cfg(feature = "compressed-backup")]
+        return self.backup_db_v2(out_file);
+        #[cfg(all(not(feature = "compressed-backup"), feature = "storage-sqlite"))]
+        return self.backup_db_v1(out_file);
+        #[cfg(not(any(feature = "compressed-backup", feature = "storage-sqlite")))]
+        bail!(
+            "backup requires the 'compressed-backup' or the 'storage-sqlite' feature to be enabled"
+        )
+    }
+    /// Backup the running database into an Sqlite file, the v1 backup format
+    #[cfg(all(
+        feature = "storage-sqlite",
+        any(test, not(feature = "compressed-backup"))
+    ))]
+    pub(crate) fn backup_db_v1(&'s self, out_file: impl AsRef<Path>) -> Result<()> {
+        let sqlite_db = crate::new_cozo_sqlite(out_file)?;
+        if sqlite_db.relation_store_id.load(Ordering::SeqCst) != 0 {
+            bail!("Cannot create backup: data exists in the target database.");
         }
-        #[cfg(not(feature = "storage-sqlite"))]
-        bail!("backup requires the 'storage-sqlite' feature to be enabled")
+        let mut tx = self.transact()?;
+        let iter = tx.store_tx.range_scan(&[], &[0xFF]);
+        sqlite_db.db.batch_put(iter)?;
+        tx.commit_tx()?;
+        Ok(())
     }
-    /// Restore from an Sqlite backup
-    #[allow(unused_variables)]
+    /// Restore from a backup created by [Db::backup_db], in either format.
+    /// The current database must be empty.
     pub fn restore_backup(&'s self, in_file: impl AsRef<Path>) -> Result<()> {
+        self.restore_backup_with_options(in_file, &RestoreOptions::default())
+    }
+    /// Restore from a backup, optionally only some of the relations, or without their indices.
+    /// Backups in the v1 (Sqlite) format can only be restored as a whole.
+    /// The current database must be empty.
+    #[allow(unused_variables)]
+    pub fn restore_backup_with_options(
+        &'s self,
+        in_file: impl AsRef<Path>,
+        options: &RestoreOptions,
+    ) -> Result<()> {
+        #[cfg(feature = "compressed-backup")]
+        if is_backup_v2(&in_file)? {
+            return self.restore_backup_v2(in_file, options);
+        }
         #[cfg(feature = "storage-sqlite")]
         {
+            ensure!(
+                options.relations.is_none() && !options.skip_indices,
+                "Selective restore requires a backup in the v2 format"
+            );
             let sqlite_db = crate::new_cozo_sqlite(in_file)?;
             let mut s_tx = sqlite_db.transact()?;
-            {
-                let mut tx = self.transact()?;
-                let store_id = tx.relation_store_id.load(Ordering::SeqCst);
-                if store_id != 0 {
-                    bail!(
-                        "Cannot restore backup: data exists in the current database. \
-                You can only restore into a new database (store id: {}).",
-                        store_id
-                    );
-                }
-                tx.commit_tx()?;
-            }
+            self.ensure_empty_for_restore()?;
             let iter = s_tx.store_tx.total_scan();
             self.db.batch_put(iter)?;
             s_tx.commit_tx()?;
+            self.query_cache.clear();
             Ok(())
         }
         #[cfg(not(feature = "storage-sqlite"))]
-        bail!("backup requires the 'storage-sqlite' feature to be enabled")
+        bail!("restoring this backup requires the 'storage-sqlite' feature to be enabled")
+    }
+    pub(crate) fn ensure_empty_for_restore(&'s self) -> Result<()> {
+        let mut tx = self.transact()?;
+        let store_id = tx.relation_store_id.load(Ordering::SeqCst);
+        if store_id != 0 {
+            bail!(
+                "Cannot restore backup: data exists in the current database. \
+                You can only restore into a new database (store id: {}).",
+                store_id
+            );
+        }
+        tx.commit_tx()
+    }
+    /// Backup the running database into a byte buffer, to be restored with
+    /// [Db::restore_backup_from_bytes]. Unlike [Db::backup_db], this works for every build,
+    /// including those without the `storage-sqlite` feature.
+    pub fn backup_db_to_bytes(&'s self) -> Result<Vec<u8>> {
+        let mut ret = BYTES_BACKUP_MAGIC.to_vec();
+        let mut tx = self.transact()?;
+        for kv in tx.store_tx.range_scan(&[], &[0xFF]) {
+            let (k, v) = kv?;
+            ret.extend_from_slice(&(k.len() as u32).to_le_bytes());
+            ret.extend_from_slice(&(v.len() as u32).to_le_bytes());
+            ret.extend_from_slice(&k);
+            ret.extend_from_slice(&v);
+        }
+        tx.commit_tx()?;
+        Ok(ret)
+    }
+    /// Restore from a backup created by [Db::backup_db_to_bytes].
+    /// The current database must be empty.
+    pub fn restore_backup_from_bytes(&'s self, data: &[u8]) -> Result<()> {
+        let mut data = data
+            .strip_prefix(BYTES_BACKUP_MAGIC)
+            .ok_or_else(|| miette!("Cannot restore backup: data is not a Cozo backup"))?;
+        self.ensure_empty_for_restore()?;
+        let iter = iter::from_fn(move || {
+            if data.is_empty() {
+                None
+            } else {
+                Some(take_backup_entry(&mut data))
+            }
+        });
+        self.db.batch_put(Box::new(iter))?;
+        self.initialize()
     }
-    /// Import data from relations in a backup file.
+    /// Import data from relations in a backup file, in either format.
     /// The target stored relations must already exist in the database, and it must not
     /// have any associated indices. If you want to import into relations with indices,
     /// use [Db::import_relations].
@@ -697,43 +1326,31 @@ impl<'s, S: Storage<'s>> Db<S> {
         in_file: impl AsRef<Path>,
         relations: &[String],
     ) -> Result<()> {
+        for relation in relations {
+            self.check_mutation(relation, MutationKind::Put)?;
+        }
+        let rel_names = relations.iter().map(SmartString::from).collect_vec();
+        let locks = self.obtain_relation_locks(rel_names.iter());
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+
+        #[cfg(feature = "compressed-backup")]
+        if is_backup_v2(&in_file)? {
+            return self.import_from_backup_v2(in_file, relations);
+        }
+
         #[cfg(not(feature = "storage-sqlite"))]
-        bail!("backup requires the 'storage-sqlite' feature to be enabled");
+        bail!("importing from this backup requires the 'storage-sqlite' feature to be enabled");
 
         #[cfg(feature = "storage-sqlite")]
         {
-            let rel_names = relations.iter().map(SmartString::from).collect_vec();
-            let locks = self.obtain_relation_locks(rel_names.iter());
-            let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
-
             let source_db = crate::new_cozo_sqlite(in_file)?;
             let mut src_tx = source_db.transact()?;
             let mut dst_tx = self.transact_write()?;
 
             for relation in relations {
-                if relation.contains(':') {
-                    bail!(ImportIntoIndex(relation.to_string()))
-                }
                 let src_handle = src_tx.get_relation(relation, false)?;
                 let dst_handle = dst_tx.get_relation(relation, false)?;
-
-                if !dst_handle.indices.is_empty() {
-                    #[derive(Debug, Error, Diagnostic)]
-                    #[error("Cannot import data into relation {0} from backup as the relation has indices")]
-                    #[diagnostic(code(tx::bare_import_with_indices))]
-                    #[diagnostic(help("Use `import_relations()` instead"))]
-                    pub(crate) struct RestoreIntoRelWithIndices(pub(crate) String);
-
-                    bail!(RestoreIntoRelWithIndices(dst_handle.name.to_string()))
-                }
-
-                if dst_handle.access_level < AccessLevel::Protected {
-                    bail!(InsufficientAccessLevel(
-                        dst_handle.name.to_string(),
-                        "data import".to_string(),
-                        dst_handle.access_level
-                    ));
-                }
+                dst_handle.ensure_bare_import_allowed()?;
 
                 let src_lower = Tuple::default().encode_as_key(src_handle.id);
                 let src_upper = Tuple::default().encode_as_key(src_handle.id.next());
@@ -783,9 +1400,43 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(self.fixed_rules.write().unwrap().remove(name).is_some())
     }
 
+    /// Register a virtual relation, readable in queries as `*name[...]` or `*name{...}`.
+    /// The name must not start with an underscore, nor be that of a stored relation.
+    pub fn register_virtual_relation<R>(&'s self, name: String, relation: R) -> Result<()>
+    where
+        R: VirtualRelation + 'static,
+    {
+        if name.starts_with('_') {
+            bail!("The name of a virtual relation cannot start with an underscore: {name}");
+        }
+        if self.transact()?.relation_exists(&name)? {
+            bail!("A stored relation with the name {name} already exists");
+        }
+        match self.virtual_relations.write().unwrap().entry(name) {
+            Entry::Vacant(ent) => {
+                ent.insert(Arc::new(relation));
+                Ok(())
+            }
+            Entry::Occupied(ent) => {
+                bail!(
+                    "A virtual relation with the name {} is already registered",
+                    ent.key()
+                )
+            }
+        }
+    }
+
+    /// Unregister a virtual relation, returns whether it was registered.
+    pub fn unregister_virtual_relation(&self, name: &str) -> bool {
+        self.virtual_relations
+            .write()
+            .unwrap()
+            .remove(name)
+            .is_some()
+    }
+
     /// Register callback channel to receive changes when the requested relation are successfully committed.
     /// The returned ID can be used to unregister the callback channel.
-    #[cfg(not(target_arch = "wasm32"))]
     pub fn register_callback(
         &self,
         relation: &str,
@@ -814,7 +1465,6 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
 
     /// Unregister callbacks/channels to run when changes to relations are committed.
-    #[cfg(not(target_arch = "wasm32"))]
     pub fn unregister_callback(&self, id: u32) -> bool {
         let mut guard = self.event_callbacks.write().unwrap();
         let ret = guard.0.remove(&id);
@@ -828,6 +1478,124 @@ impl<'s, S: Storage<'s>> Db<S> {
         ret.is_some()
     }
 
+    /// Install a hook that is called before each change to a stored relation, with the name
+    /// of the relation and the kind of change. Returning an error aborts the script, so that
+    /// applications can enforce their own rules on scripts from semi-trusted sources.
+    /// Replaces the previous guard, if any. Temporary relations are not guarded.
+    pub fn set_mutation_guard(
+        &self,
+        guard: impl Fn(&str, MutationKind) -> Result<()> + Send + Sync + 'static,
+    ) {
+        *self.mutation_guard.write().unwrap() = Some(Arc::new(guard));
+    }
+
+    /// Remove the hook installed by [Db::set_mutation_guard].
+    pub fn clear_mutation_guard(&self) {
+        *self.mutation_guard.write().unwrap() = None;
+    }
+
+    pub(crate) fn check_mutation(&self, name: &str, kind: MutationKind) -> Result<()> {
+        if name.starts_with('_') {
+            return Ok(());
+        }
+        let guard = self.mutation_guard.read().unwrap().clone();
+        match guard {
+            None => Ok(()),
+            Some(guard) => guard(name, kind),
+        }
+    }
+
+    fn check_sys_op_mutations(&self, op: &SysOp) -> Result<()> {
+        match op {
+            SysOp::RemoveRelation(names) => {
+                for name in names {
+                    self.check_mutation(name, MutationKind::Remove)?;
+                }
+            }
+            SysOp::RenameRelation(renames) => {
+                for (old, new) in renames {
+                    self.check_mutation(old, MutationKind::Rename)?;
+                    self.check_mutation(new, MutationKind::Create)?;
+                }
+            }
+            SysOp::SetAccessLevel(names, _) => {
+                for name in names {
+                    self.check_mutation(name, MutationKind::AlterSchema)?;
+                }
+            }
+            SysOp::PurgeHistory(name, _)
+            | SysOp::Truncate(name)
+            | SysOp::DeleteWhere(name, _) => self.check_mutation(name, MutationKind::Rm)?,
+            SysOp::SetTriggers(name, ..)
+            | SysOp::SetRetention(name, _)
+            | SysOp::SetHistoryRetention(name, _)
+            | SysOp::SetPartitions(name, _)
+            | SysOp::TrackTxTime(name)
+            | SysOp::UntrackTxTime(name)
+            | SysOp::CreateIndex(name, ..)
+            | SysOp::RemoveIndex(name, _)
+            | SysOp::AddGeneratedColumn(name, ..)
+            | SysOp::RemoveGeneratedColumn(name, _)
+            | SysOp::DescribeRelation(name, _) => {
+                self.check_mutation(name, MutationKind::AlterSchema)?
+            }
+            SysOp::CreateVectorIndex(config) => {
+                self.check_mutation(&config.base_relation, MutationKind::AlterSchema)?
+            }
+            SysOp::CreateFtsIndex(config) => {
+                self.check_mutation(&config.base_relation, MutationKind::AlterSchema)?
+            }
+            SysOp::CreateMinHashLshIndex(config) => {
+                self.check_mutation(&config.base_relation, MutationKind::AlterSchema)?
+            }
+            SysOp::CreateGeoIndex(config) => {
+                self.check_mutation(&config.base_relation, MutationKind::AlterSchema)?
+            }
+            SysOp::CreateIdGen(spec, _) => {
+                self.check_mutation(&spec.name, MutationKind::Catalog)?
+            }
+            SysOp::AddSchedule(script) => {
+                self.check_mutation(&script.name, MutationKind::Catalog)?
+            }
+            SysOp::SaveModule(module) => {
+                self.check_mutation(&module.name, MutationKind::Catalog)?
+            }
+            SysOp::SaveProcedure(procedure) => {
+                self.check_mutation(&procedure.name, MutationKind::Catalog)?
+            }
+            SysOp::Call(name, _) => self.check_mutation(name, MutationKind::Call)?,
+            SysOp::AddCdcSink(spec, _) => self.check_mutation(&spec.name, MutationKind::Catalog)?,
+            SysOp::RemoveIdGen(name)
+            | SysOp::RemoveSchedule(name)
+            | SysOp::RemoveModule(name)
+            | SysOp::RemoveProcedure(name)
+            | SysOp::RemoveCdcSink(name) => self.check_mutation(name, MutationKind::Catalog)?,
+            SysOp::Compact
+            | SysOp::ListColumns(_)
+            | SysOp::ListIndices(_)
+            | SysOp::ListRelations
+            | SysOp::ListRunning
+            | SysOp::ShowScheduler
+            | SysOp::ListFixedRules
+            | SysOp::KillRunning(_)
+            | SysOp::Explain(_)
+            | SysOp::Check(_)
+            | SysOp::Format(_)
+            | SysOp::ShowTrigger(_)
+            | SysOp::ShowRetention
+            | SysOp::RunRetention
+            | SysOp::ListPartitions(_)
+            | SysOp::ListIdGens
+            | SysOp::ListSchedules
+            | SysOp::ListModules
+            | SysOp::ListProcedures
+            | SysOp::ListCdcSinks
+            | SysOp::VerifyRelation(_)
+            | SysOp::IndexAdvisor(_) => {}
+        }
+        Ok(())
+    }
+
     pub(crate) fn obtain_relation_locks<'a, T: Iterator<Item = &'a SmartString<LazyCompact>>>(
         &'s self,
         rels: T,
@@ -858,7 +1626,14 @@ impl<'s, S: Storage<'s>> Db<S> {
     fn compact_relation(&'s self) -> Result<()> {
         let l = Tuple::default().encode_as_key(RelationId(0));
         let u = vec![DataValue::Bot].encode_as_key(RelationId(u64::MAX));
+        #[cfg(not(target_arch = "wasm32"))]
+        let start = Instant::now();
         self.db.range_compact(&l, &u)?;
+        #[cfg(not(target_arch = "wasm32"))]
+        self.metrics
+            .record_compaction(start.elapsed().as_secs_f64());
+        #[cfg(target_arch = "wasm32")]
+        self.metrics.record_compaction(0.);
         Ok(())
     }
 
@@ -876,16 +1651,61 @@ impl<'s, S: Storage<'s>> Db<S> {
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            id_generators: self.id_generators.clone(),
+            id_gen_changes: vec![],
+            metrics: self.metrics.clone(),
+            memory_budget: self.scheduler.memory_budget(),
+            sandbox: self.sandbox.read().unwrap().clone(),
+            virtual_relations: self.virtual_relations.clone(),
+            fixed_rules: self.fixed_rules.clone(),
+        };
+        Ok(ret)
+    }
+    /// A read transaction that collects the relations it reads into `reads`.
+    fn transact_recording_reads(&'s self, reads: Arc<Mutex<ReadSet>>) -> Result<SessionTx<'s>> {
+        let ret = SessionTx {
+            store_tx: Box::new(TrackedTx::new(
+                Box::new(self.db.transact(false)?),
+                self.query_cache.clone(),
+                Some(reads),
+            )),
+            temp_store_tx: self.temp_db.transact(true)?,
+            relation_store_id: self.relation_store_id.clone(),
+            temp_store_id: Default::default(),
+            tokenizers: self.tokenizers.clone(),
+            id_generators: self.id_generators.clone(),
+            id_gen_changes: vec![],
+            metrics: self.metrics.clone(),
+            memory_budget: self.scheduler.memory_budget(),
+            sandbox: self.sandbox.read().unwrap().clone(),
+            virtual_relations: self.virtual_relations.clone(),
+            fixed_rules: self.fixed_rules.clone(),
         };
         Ok(ret)
     }
     pub(crate) fn transact_write(&'s self) -> Result<SessionTx<'_>> {
+        let store_tx: Box<dyn StoreTx<'_>> = if self.query_cache.is_enabled() {
+            Box::new(TrackedTx::new(
+                Box::new(self.db.transact(true)?),
+                self.query_cache.clone(),
+                None,
+            ))
+        } else {
+            Box::new(self.db.transact(true)?)
+        };
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(true)?),
+            store_tx,
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            id_generators: self.id_generators.clone(),
+            id_gen_changes: vec![],
+            metrics: self.metrics.clone(),
+            memory_budget: self.scheduler.memory_budget(),
+            sandbox: self.sandbox.read().unwrap().clone(),
+            virtual_relations: self.virtual_relations.clone(),
+            fixed_rules: self.fixed_rules.clone(),
         };
         Ok(ret)
     }
@@ -898,11 +1718,19 @@ impl<'s, S: Storage<'s>> Db<S> {
         cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
+        poison: &Poison,
     ) -> Result<NamedRows> {
         #[allow(unused_variables)]
         let sleep_opt = p.out_opts.sleep;
-        let (q_res, q_cleanups) =
-            self.run_query(tx, p, cur_vld, callback_targets, callback_collector, true)?;
+        let (q_res, q_cleanups) = self.run_query(
+            tx,
+            p,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            poison,
+            true,
+        )?;
         cleanups.extend(q_cleanups);
         #[cfg(not(target_arch = "wasm32"))]
         if let Some(secs) = sleep_opt {
@@ -916,6 +1744,8 @@ impl<'s, S: Storage<'s>> Db<S> {
         cur_vld: ValidityTs,
         p: InputProgram,
         read_only: bool,
+        poison: &Poison,
+        reads: Option<Arc<Mutex<ReadSet>>>,
     ) -> Result<NamedRows, Report> {
         let mut callback_collector = BTreeMap::new();
         let write_lock_names = p.needs_write_lock();
@@ -923,6 +1753,15 @@ impl<'s, S: Storage<'s>> Db<S> {
         if read_only && is_write {
             bail!("write lock required for read-only query");
         }
+        let _permit = self.scheduler.acquire(
+            if is_write {
+                QueueKind::Write
+            } else {
+                QueueKind::Read
+            },
+            poison,
+        )?;
+        let poison = &poison.with_memory_gauge(_permit.gauge());
         let write_lock = self.obtain_relation_locks(write_lock_names.iter());
         let _write_lock_guards = if is_write {
             Some(write_lock[0].read().unwrap())
@@ -937,10 +1776,10 @@ impl<'s, S: Storage<'s>> Db<S> {
         let mut cleanups = vec![];
         let res;
         {
-            let mut tx = if is_write {
-                self.transact_write()?
-            } else {
-                self.transact()?
+            let mut tx = match reads {
+                _ if is_write => self.transact_write()?,
+                Some(reads) => self.transact_recording_reads(reads)?,
+                None => self.transact()?,
             };
 
             res = self.execute_single_program(
@@ -950,6 +1789,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 cur_vld,
                 &callback_targets,
                 &mut callback_collector,
+                poison,
             )?;
 
             for (lower, upper) in cleanups {
@@ -958,7 +1798,6 @@ impl<'s, S: Storage<'s>> Db<S> {
 
             tx.commit_tx()?;
         }
-        #[cfg(not(target_arch = "wasm32"))]
         if !callback_collector.is_empty() {
             self.send_callbacks(callback_collector)
         }
@@ -1039,10 +1878,19 @@ impl<'s, S: Storage<'s>> Db<S> {
                                         json!(filters.iter().map(|f| f.to_string()).collect_vec()),
                                     ),
                                     RelAlgebra::Stored(StoredRA {
-                                        storage, filters, ..
+                                        storage,
+                                        filters,
+                                        expr_index,
+                                        ..
                                     }) => (
                                         "load_stored",
-                                        json!(format!(":{}", storage.name)),
+                                        // scans by an expression index refer to the index
+                                        json!(format!(
+                                            ":{}",
+                                            expr_index
+                                                .as_ref()
+                                                .map_or(&storage.name, |scan| &scan.index.name)
+                                        )),
                                         json!(null),
                                         json!(filters.iter().map(|f| f.to_string()).collect_vec()),
                                     ),
@@ -1056,6 +1904,12 @@ impl<'s, S: Storage<'s>> Db<S> {
                                         json!(null),
                                         json!(filters.iter().map(|f| f.to_string()).collect_vec()),
                                     ),
+                                    RelAlgebra::Virtual(VirtualRA { name, filters, .. }) => (
+                                        "load_virtual",
+                                        json!(format!(":{}", name)),
+                                        json!(null),
+                                        json!(filters.iter().map(|f| f.to_string()).collect_vec()),
+                                    ),
                                     RelAlgebra::Join(inner) => {
                                         if inner.left.is_unit() {
                                             rel_stack.push(&inner.right);
@@ -1148,6 +2002,16 @@ impl<'s, S: Storage<'s>> Db<S> {
                                             .map(|f| f.to_string())
                                             .collect_vec()),
                                     ),
+                                    RelAlgebra::GeoSearch(GeoSearchRA { geo_search, .. }) => (
+                                        "geo_index",
+                                        json!(format!(":{}", geo_search.query.name)),
+                                        json!(geo_search.query.name),
+                                        json!(geo_search
+                                            .filter
+                                            .iter()
+                                            .map(|f| f.to_string())
+                                            .collect_vec()),
+                                    ),
                                 };
                                 ret_for_relation.push(json!({
                                     STRATUM: stratum,
@@ -1189,21 +2053,134 @@ impl<'s, S: Storage<'s>> Db<S> {
 
         Ok(NamedRows::new(headers, rows))
     }
+    /// Lint the program, then compile it without evaluation.
+    /// Compilation errors are reported as rows instead of failing the script.
+    fn check_program(&self, tx: &mut SessionTx<'_>, prog: &InputProgram) -> NamedRows {
+        let span_val = |span: SourceSpan| {
+            DataValue::List(vec![
+                DataValue::from(span.0 as i64),
+                DataValue::from((span.0 + span.1) as i64),
+            ])
+        };
+        let mut rows = prog
+            .lint()
+            .into_iter()
+            .map(|w| {
+                vec![
+                    DataValue::from("warning"),
+                    DataValue::from(&w.rule.name as &str),
+                    DataValue::from(w.code),
+                    DataValue::from(w.message),
+                    span_val(w.span),
+                ]
+            })
+            .collect_vec();
+        let compiled = prog
+            .clone()
+            .into_normalized_program(tx)
+            .and_then(|(normalized, _)| normalized.into_stratified_program())
+            .and_then(|(stratified, _)| stratified.magic_sets_rewrite(tx))
+            .and_then(|magic| {
+                tx.stratified_magic_compile(magic, prog.experimental, &prog.hints, prog.as_of_tx)
+            });
+        if let Err(err) = compiled {
+            let span = err
+                .labels()
+                .and_then(|mut labels| labels.next())
+                .map(|l| span_val(SourceSpan(l.offset(), l.len())))
+                .unwrap_or(DataValue::Null);
+            rows.push(vec![
+                DataValue::from("error"),
+                DataValue::Null,
+                match err.code() {
+                    None => DataValue::Null,
+                    Some(code) => DataValue::from(code.to_string()),
+                },
+                DataValue::from(err.to_string()),
+                span,
+            ]);
+        }
+        NamedRows::new(
+            vec![
+                "severity".to_string(),
+                "rule".to_string(),
+                "code".to_string(),
+                "message".to_string(),
+                "span".to_string(),
+            ],
+            rows,
+        )
+    }
+    /// Run a system op within `tx`. The key ranges of the data of removed relations and
+    /// indices are added to `cleanups` to be deleted just before the transaction commits,
+    /// so that the op is rolled back with the rest of the transaction if it fails.
     pub(crate) fn run_sys_op_with_tx(
         &'s self,
         tx: &mut SessionTx<'_>,
         op: &SysOp,
         read_only: bool,
         skip_locking: bool,
+        cleanups: &mut Vec<(Vec<u8>, Vec<u8>)>,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        poison: &Poison,
     ) -> Result<NamedRows> {
+        self.check_sys_op_mutations(op)?;
         match op {
             SysOp::Explain(prog) => {
                 let (normalized_program, _) = prog.clone().into_normalized_program(tx)?;
                 let (stratified_program, _) = normalized_program.into_stratified_program()?;
                 let program = stratified_program.magic_sets_rewrite(tx)?;
-                let compiled = tx.stratified_magic_compile(program)?;
+                let compiled = tx.stratified_magic_compile(
+                    program,
+                    prog.experimental,
+                    &prog.hints,
+                    prog.as_of_tx,
+                )?;
                 self.explain_compiled(&compiled)
             }
+            SysOp::Check(prog) => Ok(self.check_program(tx, prog)),
+            SysOp::Format(formatted) => Ok(NamedRows::new(
+                vec!["formatted".to_string()],
+                vec![vec![DataValue::from(formatted as &str)]],
+            )),
+            SysOp::IndexAdvisor(prog) => {
+                let (normalized_program, _) = prog.clone().into_normalized_program(tx)?;
+                let (stratified_program, _) = normalized_program.into_stratified_program()?;
+                let program = stratified_program.magic_sets_rewrite(tx)?;
+                let compiled = tx.stratified_magic_compile(
+                    program,
+                    prog.experimental,
+                    &prog.hints,
+                    prog.as_of_tx,
+                )?;
+                let rows = advise_indices(&compiled)
+                    .into_iter()
+                    .map(|advice| {
+                        vec![
+                            DataValue::from(advice.rule),
+                            DataValue::from(advice.relation),
+                            DataValue::from(advice.kind),
+                            DataValue::List(
+                                advice.columns.into_iter().map(DataValue::from).collect(),
+                            ),
+                            DataValue::from(advice.reason),
+                            DataValue::from(advice.suggestion),
+                        ]
+                    })
+                    .collect_vec();
+                Ok(NamedRows::new(
+                    vec![
+                        "rule".to_string(),
+                        "relation".to_string(),
+                        "kind".to_string(),
+                        "columns".to_string(),
+                        "reason".to_string(),
+                        "suggestion".to_string(),
+                    ],
+                    rows,
+                ))
+            }
             SysOp::Compact => {
                 if read_only {
                     bail!("Cannot compact in read-only mode");
@@ -1236,16 +2213,12 @@ impl<'s, S: Storage<'s>> Db<S> {
                     self.obtain_relation_locks(rel_name_strs)
                 };
                 let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
-                let mut bounds = vec![];
                 for rs in rel_names {
                     let bound = tx.destroy_relation(rs)?;
                     if !rs.is_temp_store_name() {
-                        bounds.extend(bound);
+                        cleanups.extend(bound);
                     }
                 }
-                for (lower, upper) in bounds {
-                    tx.store_tx.del_range_from_persisted(&lower, &upper)?;
-                }
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
@@ -1258,19 +2231,19 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
-            SysOp::CreateIndex(rel_name, idx_name, cols) => {
+            SysOp::CreateIndex(rel_name, idx_name, cols, payload, unique) => {
                 if read_only {
                     bail!("Cannot create index in read-only mode");
                 }
                 if skip_locking {
-                    tx.create_index(rel_name, idx_name, cols)?;
+                    tx.create_index(rel_name, idx_name, cols, payload, *unique)?;
                 } else {
                     let lock = self
                         .obtain_relation_locks(iter::once(&rel_name.name))
                         .pop()
                         .unwrap();
                     let _guard = lock.write().unwrap();
-                    tx.create_index(rel_name, idx_name, cols)?;
+                    tx.create_index(rel_name, idx_name, cols, payload, *unique)?;
                 }
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
@@ -1317,42 +2290,100 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
             SysOp::CreateMinHashLshIndex(config) => {
                 if read_only {
-                    bail!("Cannot create minhash lsh index in read-only mode");
+                    bail!("Cannot create minhash lsh index in read-only mode");
+                }
+                if skip_locking {
+                    tx.create_minhash_lsh_index(config)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&config.base_relation))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.create_minhash_lsh_index(config)?;
+                }
+
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::CreateGeoIndex(config) => {
+                if read_only {
+                    bail!("Cannot create geo index in read-only mode");
+                }
+                if skip_locking {
+                    tx.create_geo_index(config)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&config.base_relation))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.create_geo_index(config)?;
+                }
+
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RemoveIndex(rel_name, idx_name) => {
+                if read_only {
+                    bail!("Cannot remove index in read-only mode");
+                }
+                let bounds = if skip_locking {
+                    tx.remove_index(rel_name, idx_name)?
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&rel_name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.read().unwrap();
+                    tx.remove_index(rel_name, idx_name)?
+                };
+
+                cleanups.extend(bounds);
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::AddGeneratedColumn(rel_name, col_name, expr, index) => {
+                if read_only {
+                    bail!("Cannot add generated column in read-only mode");
                 }
                 if skip_locking {
-                    tx.create_minhash_lsh_index(config)?;
+                    tx.add_generated_column(rel_name, col_name, expr, *index)?;
                 } else {
                     let lock = self
-                        .obtain_relation_locks(iter::once(&config.base_relation))
+                        .obtain_relation_locks(iter::once(&rel_name.name))
                         .pop()
                         .unwrap();
                     let _guard = lock.write().unwrap();
-                    tx.create_minhash_lsh_index(config)?;
+                    tx.add_generated_column(rel_name, col_name, expr, *index)?;
                 }
-
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
-            SysOp::RemoveIndex(rel_name, idx_name) => {
+            SysOp::RemoveGeneratedColumn(rel_name, col_name) => {
                 if read_only {
-                    bail!("Cannot remove index in read-only mode");
+                    bail!("Cannot remove generated column in read-only mode");
                 }
                 let bounds = if skip_locking {
-                    tx.remove_index(rel_name, idx_name)?
+                    tx.remove_generated_column(rel_name, col_name)?
                 } else {
                     let lock = self
                         .obtain_relation_locks(iter::once(&rel_name.name))
                         .pop()
                         .unwrap();
-                    let _guard = lock.read().unwrap();
-                    tx.remove_index(rel_name, idx_name)?
+                    let _guard = lock.write().unwrap();
+                    tx.remove_generated_column(rel_name, col_name)?
                 };
 
-                for (lower, upper) in bounds {
-                    tx.store_tx.del_range_from_persisted(&lower, &upper)?;
-                }
+                cleanups.extend(bounds);
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
@@ -1380,6 +2411,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::ListRunning => self.list_running(),
+            SysOp::ShowScheduler => Ok(self.scheduler.metrics()),
             SysOp::KillRunning(id) => {
                 let queries = self.running_queries.lock().unwrap();
                 Ok(match queries.get(id) {
@@ -1388,7 +2420,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         vec![vec![DataValue::from("NOT_FOUND")]],
                     ),
                     Some(handle) => {
-                        handle.poison.0.store(true, Ordering::Relaxed);
+                        handle.poison.cancel();
                         NamedRows::new(
                             vec![STATUS_STR.to_string()],
                             vec![vec![DataValue::from("KILLING")]],
@@ -1427,6 +2459,229 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::SetRetention(name, policy) => {
+                if read_only {
+                    bail!("Cannot set retention policy in read-only mode");
+                }
+                tx.set_retention(name, policy.clone())?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ShowRetention => self.retention_status(tx),
+            SysOp::SetPartitions(name, bounds) => {
+                if read_only {
+                    bail!("Cannot set partitions in read-only mode");
+                }
+                tx.set_partitions(name, bounds.clone())?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListPartitions(name) => tx.list_partitions(name),
+            SysOp::Truncate(name) | SysOp::DeleteWhere(name, _) => {
+                if read_only {
+                    bail!("Cannot delete rows in read-only mode");
+                }
+                let locks = if skip_locking {
+                    vec![]
+                } else {
+                    self.obtain_relation_locks(iter::once(&name.name))
+                };
+                let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+                let cur_vld = current_validity();
+                let deleted = match op {
+                    SysOp::DeleteWhere(_, pred) => tx.delete_where(
+                        self,
+                        name,
+                        Some(pred),
+                        cur_vld,
+                        callback_targets,
+                        callback_collector,
+                        poison,
+                    )?,
+                    _ => tx.truncate_relation(
+                        self,
+                        name,
+                        cur_vld,
+                        callback_targets,
+                        callback_collector,
+                        poison,
+                    )?,
+                };
+                Ok(NamedRows::new(
+                    vec!["deleted".to_string()],
+                    vec![vec![DataValue::from(deleted as i64)]],
+                ))
+            }
+            SysOp::SetHistoryRetention(name, keep) => {
+                if read_only {
+                    bail!("Cannot set history retention in read-only mode");
+                }
+                tx.set_history_retention(name, *keep)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::TrackTxTime(rel_name) => {
+                if read_only {
+                    bail!("Cannot track transaction time in read-only mode");
+                }
+                if skip_locking {
+                    tx.track_tx_time(rel_name, current_validity())?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&rel_name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.track_tx_time(rel_name, current_validity())?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::UntrackTxTime(rel_name) => {
+                if read_only {
+                    bail!("Cannot untrack transaction time in read-only mode");
+                }
+                let bounds = if skip_locking {
+                    tx.untrack_tx_time(rel_name)?
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&rel_name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.untrack_tx_time(rel_name)?
+                };
+                cleanups.extend(bounds);
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::CreateIdGen(spec, span) => {
+                if read_only {
+                    bail!("Cannot create ID generator in read-only mode");
+                }
+                tx.create_id_gen(spec, *span)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RemoveIdGen(name) => {
+                if read_only {
+                    bail!("Cannot remove ID generator in read-only mode");
+                }
+                tx.remove_id_gen(name)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListIdGens => list_id_gens(tx),
+            SysOp::AddSchedule(schedule) => {
+                if read_only {
+                    bail!("Cannot add scheduled script in read-only mode");
+                }
+                tx.add_schedule(schedule)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RemoveSchedule(name) => {
+                if read_only {
+                    bail!("Cannot remove scheduled script in read-only mode");
+                }
+                tx.remove_schedule(name)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListSchedules => list_schedules(tx),
+            SysOp::SaveModule(module) => {
+                if read_only {
+                    bail!("Cannot save module in read-only mode");
+                }
+                tx.save_module(module)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RemoveModule(name) => {
+                if read_only {
+                    bail!("Cannot remove module in read-only mode");
+                }
+                tx.remove_module(name)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListModules => list_modules(tx),
+            SysOp::SaveProcedure(procedure) => {
+                if read_only {
+                    bail!("Cannot save procedure in read-only mode");
+                }
+                tx.save_procedure(procedure)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RemoveProcedure(name) => {
+                if read_only {
+                    bail!("Cannot remove procedure in read-only mode");
+                }
+                tx.remove_procedure(name)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListProcedures => list_procedures(tx),
+            SysOp::Call(name, _) => bail!(NestedProcedureCall(name.span)),
+            SysOp::AddCdcSink(spec, span) => {
+                if read_only {
+                    bail!("Cannot add change-data-capture sink in read-only mode");
+                }
+                self.ensure_io_allowed(&spec.target)?;
+                tx.add_cdc_sink(spec, *span)?;
+                let key_len = tx.get_relation(&spec.relation, false)?.metadata.keys.len();
+                self.start_cdc_sink(spec, key_len)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RemoveCdcSink(name) => {
+                if read_only {
+                    bail!("Cannot remove change-data-capture sink in read-only mode");
+                }
+                tx.remove_cdc_sink(name)?;
+                self.stop_cdc_sink(&name.name);
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListCdcSinks => list_cdc_sinks(self, tx),
+            SysOp::VerifyRelation(rel) => tx.verify_relation(rel),
+            SysOp::RunRetention => {
+                bail!("Retention policies cannot be enforced inside a transaction")
+            }
+            SysOp::PurgeHistory(..) => {
+                bail!("History cannot be purged inside a transaction")
+            }
             SysOp::SetAccessLevel(names, level) => {
                 if read_only {
                     bail!("Cannot set access level in read-only mode");
@@ -1441,14 +2696,55 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         }
     }
-    fn run_sys_op(&'s self, op: SysOp, read_only: bool) -> Result<NamedRows> {
+    fn run_sys_op(&'s self, op: SysOp, read_only: bool, poison: &Poison) -> Result<NamedRows> {
+        match &op {
+            // each batch of deletions is a transaction of its own
+            SysOp::RunRetention => return self.run_retention(read_only),
+            SysOp::PurgeHistory(rel, before) => {
+                if read_only {
+                    bail!("Cannot purge history in read-only mode");
+                }
+                let deleted = self.purge_history(rel, *before)?;
+                return Ok(NamedRows::new(
+                    vec!["deleted".to_string()],
+                    vec![vec![DataValue::from(deleted as i64)]],
+                ));
+            }
+            SysOp::Compact if !read_only => {
+                self.run_retention(false)?;
+                self.enforce_history_retention()?
+            }
+            _ => {}
+        }
         let mut tx = if read_only {
             self.transact()?
         } else {
             self.transact_write()?
         };
-        let res = self.run_sys_op_with_tx(&mut tx, &op, read_only, false)?;
+        let callback_targets = if read_only {
+            Default::default()
+        } else {
+            self.current_callback_targets()
+        };
+        let mut callback_collector = BTreeMap::new();
+        let mut cleanups = vec![];
+        let res = self.run_sys_op_with_tx(
+            &mut tx,
+            &op,
+            read_only,
+            false,
+            &mut cleanups,
+            &callback_targets,
+            &mut callback_collector,
+            poison,
+        )?;
+        for (lower, upper) in cleanups {
+            tx.store_tx.del_range_from_persisted(&lower, &upper)?;
+        }
         tx.commit_tx()?;
+        if !callback_collector.is_empty() {
+            self.send_callbacks(callback_collector)
+        }
         Ok(res)
     }
     /// This is the entry to query evaluation
@@ -1459,10 +2755,12 @@ impl<'s, S: Storage<'s>> Db<S> {
         cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
+        poison: &Poison,
         top_level: bool,
     ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
         // cleanups contain stored relations that should be deleted at the end of query
         let mut clean_ups = vec![];
+        let _id_gens = self.id_generators.install();
 
         // Some checks in case the query specifies mutation
         if let Some((meta, op, _)) = &input_program.out_opts.store_relation {
@@ -1491,21 +2789,40 @@ impl<'s, S: Storage<'s>> Db<S> {
 
                 existing.ensure_compatible(
                     meta,
-                    *op == RelationOp::Rm || *op == RelationOp::Delete || *op == RelationOp::Update,
+                    *op == RelationOp::Rm
+                        || *op == RelationOp::Delete
+                        || *op == RelationOp::Update
+                        || *op == RelationOp::Upsert,
                 )?;
             }
         };
 
+        self.generate_system_relations(tx, &input_program)?;
+
         // query compilation
         let entry_head_or_default = input_program.get_entry_out_head_or_default()?;
-        let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
-        let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
-        let program = stratified_program.magic_sets_rewrite(tx)?;
-        let compiled = tx.stratified_magic_compile(program)?;
+        let experimental = input_program.experimental;
+        let hints = input_program.hints.clone();
+        let as_of_tx = input_program.as_of_tx;
+        let (compiled, out_opts, store_lifetimes) =
+            span!("cozo.compile").in_scope(|| -> Result<_> {
+                let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
+                let (stratified_program, store_lifetimes) =
+                    normalized_program.into_stratified_program()?;
+                let program = stratified_program.magic_sets_rewrite(tx)?;
+                let compiled =
+                    tx.stratified_magic_compile(program, experimental, &hints, as_of_tx)?;
+                Ok((compiled, out_opts, store_lifetimes))
+            })?;
 
         // poison is used to terminate queries early
-        let poison = Poison::default();
-        if let Some(secs) = out_opts.timeout {
+        let poison = poison.child();
+        let timeout = if top_level {
+            out_opts.timeout.or(*self.default_timeout.read().unwrap())
+        } else {
+            out_opts.timeout
+        };
+        if let Some(secs) = timeout {
             poison.set_timeout(secs)?;
         }
         // give the query an ID and store it so that it can be queried and cancelled
@@ -1526,17 +2843,19 @@ impl<'s, S: Storage<'s>> Db<S> {
             running_queries: self.running_queries.clone(),
         };
 
-        let total_num_to_take = if out_opts.sorters.is_empty() {
+        // sorting and window functions need all the rows
+        let takes_all = !out_opts.sorters.is_empty() || out_opts.window.is_some();
+
+        let total_num_to_take = if !takes_all {
             out_opts.num_to_take()
         } else {
             None
         };
 
-        let num_to_skip = if out_opts.sorters.is_empty() {
-            out_opts.offset
-        } else {
-            None
-        };
+        let num_to_skip = if !takes_all { out_opts.offset } else { None };
+
+        // random functions evaluated outside of rules, e.g. in default values, are seeded too
+        let _rng = seed_rng(out_opts.seed.map(|seed| derive_seed(seed, "query")));
 
         // the real evaluation
         let (result_store, early_return) = tx.stratified_magic_evaluate(
@@ -1544,7 +2863,8 @@ impl<'s, S: Storage<'s>> Db<S> {
             store_lifetimes,
             total_num_to_take,
             num_to_skip,
-            poison,
+            out_opts.seed,
+            poison.clone(),
         )?;
 
         // deal with assertions
@@ -1573,6 +2893,26 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         }
 
+        if let Some(window) = &out_opts.window {
+            // mutations and the other reshaping options are rejected by the parser
+            let rows = if out_opts.sorters.is_empty() {
+                result_store
+                    .all_iter()
+                    .map(|t| t.into_tuple())
+                    .collect_vec()
+            } else {
+                tx.sort_and_collect(result_store, &out_opts.sorters, &entry_head_or_default)?
+            };
+            let mut res = window.apply(&entry_head_or_default, rows)?;
+            res.rows = res
+                .rows
+                .into_iter()
+                .skip(out_opts.offset.unwrap_or(0))
+                .take(out_opts.limit.unwrap_or(usize::MAX))
+                .collect_vec();
+            return Ok((res, clean_ups));
+        }
+
         if !out_opts.sorters.is_empty() {
             // sort outputs if required
             let sorted_result =
@@ -1588,41 +2928,70 @@ impl<'s, S: Storage<'s>> Db<S> {
                 Right(sorted_iter)
             };
             if let Some((meta, relation_op, returning)) = &out_opts.store_relation {
+                let (rows, guarded) = match &out_opts.guard {
+                    None => (Left(sorted_iter), None),
+                    Some(guard) => {
+                        let kept = tx.filter_by_guard(
+                            sorted_iter,
+                            meta,
+                            &entry_head_or_default,
+                            guard,
+                            cur_vld,
+                        )?;
+                        let n = kept.len();
+                        (Right(kept.into_iter()), Some(n))
+                    }
+                };
+                let mut counts = MutationCounts::default();
                 let to_clear = tx
                     .execute_relation(
                         self,
-                        sorted_iter,
+                        rows,
                         *relation_op,
                         meta,
                         &entry_head_or_default,
                         cur_vld,
                         callback_targets,
                         callback_collector,
+                        &poison,
                         top_level,
-                        if *returning == ReturnMutation::Returning {
+                        if returning.returns_rows() {
                             &meta.name.name
                         } else {
                             ""
                         },
+                        &mut counts,
                     )
                     .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
                 clean_ups.extend(to_clear);
-                let returned_rows =
-                    tx.get_returning_rows(callback_collector, &meta.name, returning)?;
+                if out_opts.track_tx_time {
+                    // the rows just put are recorded at the time of this transaction
+                    tx.track_tx_time(&meta.name, cur_vld)?;
+                }
+                let returned_rows = tx.get_returning_rows(
+                    callback_collector,
+                    &meta.name,
+                    returning,
+                    counts,
+                    guarded,
+                )?;
                 Ok((returned_rows, clean_ups))
             } else {
                 // not sorting outputs
                 let rows: Vec<Tuple> = sorted_iter.collect_vec();
-                Ok((
-                    NamedRows::new(
-                        entry_head_or_default
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect_vec(),
-                        rows,
-                    ),
-                    clean_ups,
-                ))
+                let headers = entry_head_or_default
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect_vec();
+                if let Some(topic) = &out_opts.outbox {
+                    Ok((tx.append_outbox(topic, &headers, rows)?, clean_ups))
+                } else if let Some(transform) = &out_opts.transform {
+                    Ok((transform.apply(rows)?, clean_ups))
+                } else if let Some(nest) = &out_opts.nest {
+                    Ok((nest.apply(rows), clean_ups))
+                } else {
+                    Ok((NamedRows::new(headers, rows), clean_ups))
+                }
             }
         } else {
             let scan = if early_return {
@@ -1644,42 +3013,64 @@ impl<'s, S: Storage<'s>> Db<S> {
             };
 
             if let Some((meta, relation_op, returning)) = &out_opts.store_relation {
+                let (rows, guarded) = match &out_opts.guard {
+                    None => (Left(scan), None),
+                    Some(guard) => {
+                        let kept =
+                            tx.filter_by_guard(scan, meta, &entry_head_or_default, guard, cur_vld)?;
+                        let n = kept.len();
+                        (Right(kept.into_iter()), Some(n))
+                    }
+                };
+                let mut counts = MutationCounts::default();
                 let to_clear = tx
                     .execute_relation(
                         self,
-                        scan,
+                        rows,
                         *relation_op,
                         meta,
                         &entry_head_or_default,
                         cur_vld,
                         callback_targets,
                         callback_collector,
+                        &poison,
                         top_level,
-                        if *returning == ReturnMutation::Returning {
+                        if returning.returns_rows() {
                             &meta.name.name
                         } else {
                             ""
                         },
+                        &mut counts,
                     )
                     .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
                 clean_ups.extend(to_clear);
-                let returned_rows =
-                    tx.get_returning_rows(callback_collector, &meta.name, returning)?;
-
+                if out_opts.track_tx_time {
+                    // the rows just put are recorded at the time of this transaction
+                    tx.track_tx_time(&meta.name, cur_vld)?;
+                }
+                let returned_rows = tx.get_returning_rows(
+                    callback_collector,
+                    &meta.name,
+                    returning,
+                    counts,
+                    guarded,
+                )?;
                 Ok((returned_rows, clean_ups))
             } else {
                 let rows: Vec<Tuple> = scan.collect_vec();
-
-                Ok((
-                    NamedRows::new(
-                        entry_head_or_default
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect_vec(),
-                        rows,
-                    ),
-                    clean_ups,
-                ))
+                let headers = entry_head_or_default
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect_vec();
+                if let Some(topic) = &out_opts.outbox {
+                    Ok((tx.append_outbox(topic, &headers, rows)?, clean_ups))
+                } else if let Some(transform) = &out_opts.transform {
+                    Ok((transform.apply(rows)?, clean_ups))
+                } else if let Some(nest) = &out_opts.nest {
+                    Ok((nest.apply(rows), clean_ups))
+                } else {
+                    Ok((NamedRows::new(headers, rows), clean_ups))
+                }
             }
         }
     }
@@ -1701,15 +3092,21 @@ impl<'s, S: Storage<'s>> Db<S> {
             rows,
         ))
     }
-    fn list_indices(&'s self, tx: &SessionTx<'_>, name: &str) -> Result<NamedRows> {
+    pub(crate) fn list_indices(&self, tx: &SessionTx<'_>, name: &str) -> Result<NamedRows> {
         let handle = tx.get_relation(name, false)?;
         let mut rows = vec![];
         for (name, (rel, cols)) in &handle.indices {
+            let (keys, payload) = cols.split_at(rel.metadata.keys.len());
+            let kind = if handle.unique_indices.contains(name) {
+                "unique"
+            } else {
+                "normal"
+            };
             rows.push(vec![
                 json!(name),
-                json!("normal"),
+                json!(kind),
                 json!([rel.name]),
-                json!({ "indices": cols }),
+                json!({ "indices": keys, "payload": payload }),
             ]);
         }
         for (name, (rel, manifest)) in &handle.hnsw_indices {
@@ -1729,6 +3126,13 @@ impl<'s, S: Storage<'s>> Db<S> {
                     "level_multiplier": manifest.level_multiplier,
                     "extend_candidates": manifest.extend_candidates,
                     "keep_pruned_connections": manifest.keep_pruned_connections,
+                    "quantization": match &manifest.quantizer {
+                        None => json!(null),
+                        Some(VectorQuantizer::Scalar) => json!("SQ"),
+                        Some(VectorQuantizer::Product { centroids, .. }) => {
+                            json!({"PQ": {"subspaces": centroids.len()}})
+                        }
+                    },
                 }),
             ]);
         }
@@ -1754,6 +3158,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                     "tokenizer": manifest.tokenizer,
                     "tokenizer_filters": manifest.filters,
                     "n_gram": manifest.n_gram,
+                    "shingling": manifest.shingling,
                     "num_perm": manifest.num_perm,
                     "n_bands": manifest.n_bands,
                     "n_rows_in_band": manifest.n_rows_in_band,
@@ -1761,6 +3166,17 @@ impl<'s, S: Storage<'s>> Db<S> {
                 }),
             ]);
         }
+        for (name, (rel, manifest)) in &handle.geo_indices {
+            rows.push(vec![
+                json!(name),
+                json!("geo"),
+                json!([rel.name]),
+                json!({
+                    "extractor": manifest.extractor,
+                    "precision": manifest.precision,
+                }),
+            ]);
+        }
         let rows = rows
             .into_iter()
             .map(|row| row.into_iter().map(DataValue::from).collect_vec())
@@ -1775,7 +3191,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             rows,
         ))
     }
-    fn list_columns(&'s self, tx: &SessionTx<'_>, name: &str) -> Result<NamedRows> {
+    pub(crate) fn list_columns(&self, tx: &SessionTx<'_>, name: &str) -> Result<NamedRows> {
         let handle = tx.get_relation(name, false)?;
         let mut rows = vec![];
         let mut idx = 0;
@@ -1821,7 +3237,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             rows,
         ))
     }
-    fn list_relations(&'s self, tx: &SessionTx<'_>) -> Result<NamedRows> {
+    pub(crate) fn list_relations(&self, tx: &SessionTx<'_>) -> Result<NamedRows> {
         let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
         let upper =
             vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
@@ -1889,6 +3305,22 @@ pub fn evaluate_expressions(
     })
 }
 
+/// Evaluate a string expression once for each set of variables in `vars_batch`,
+/// parsing it only once. The results are in the same order as the sets of variables.
+pub fn evaluate_expressions_batch(
+    src: &str,
+    params: &BTreeMap<String, DataValue>,
+    vars_batch: &[BTreeMap<String, DataValue>],
+) -> Result<Vec<DataValue>> {
+    _evaluate_expressions_batch(src, params, vars_batch).map_err(|err| {
+        if err.source().is_none() {
+            err.with_source_code(format!("{src} "))
+        } else {
+            err
+        }
+    })
+}
+
 /// Get the variables referenced in a string expression
 pub fn get_variables(src: &str, params: &BTreeMap<String, DataValue>) -> Result<BTreeSet<String>> {
     _get_variables(src, params).map_err(|err| {
@@ -1916,14 +3348,53 @@ fn _evaluate_expressions(
     expr.eval(&ctx)
 }
 
+fn _evaluate_expressions_batch(
+    src: &str,
+    params: &BTreeMap<String, DataValue>,
+    vars_batch: &[BTreeMap<String, DataValue>],
+) -> Result<Vec<DataValue>> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Variable '{0}' is not bound in the binding set at position {1}")]
+    #[diagnostic(code(eval::unbound_variable_in_batch))]
+    struct UnboundVariableInBatch(String, usize);
+
+    let mut expr = parse_expressions(src, params)?;
+    let names = expr.get_variables()?.into_iter().collect_vec();
+    let binding_map = names
+        .iter()
+        .enumerate()
+        .map(|(i, k)| (Symbol::new(k, Default::default()), i))
+        .collect();
+    expr.fill_binding_indices(&binding_map)?;
+    let mut ret = Vec::with_capacity(vars_batch.len());
+    for (i, vars) in vars_batch.iter().enumerate() {
+        let ctx = names
+            .iter()
+            .map(|k| match vars.get(k) {
+                Some(v) => Ok(v.clone()),
+                None => Err(UnboundVariableInBatch(k.clone(), i)),
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        ret.push(expr.eval(&ctx)?);
+    }
+    Ok(ret)
+}
+
 fn _get_variables(src: &str, params: &BTreeMap<String, DataValue>) -> Result<BTreeSet<String>> {
     let expr = parse_expressions(src, params)?;
     expr.get_variables()
 }
 
-/// Used for user-initiated termination of running queries
+/// Used for user-initiated termination of running queries.
+///
+/// A poison created by the user can be passed to [Db::run_script_with_handle] and used as
+/// a cancellation token: calling [Poison::cancel] from another thread terminates the script.
 #[derive(Clone, Default)]
-pub struct Poison(pub(crate) Arc<AtomicBool>);
+pub struct Poison(
+    pub(crate) Arc<AtomicBool>,
+    Option<Arc<Poison>>,
+    Option<Arc<MemoryGauge>>,
+);
 
 impl Poison {
     /// Will return `Err` if user has initiated termination.
@@ -1935,11 +3406,49 @@ impl Poison {
         #[diagnostic(help("A query may be killed by timeout, or explicit command"))]
         struct ProcessKilled;
 
-        if self.0.load(Ordering::Relaxed) {
+        if self.is_cancelled() {
             bail!(ProcessKilled)
         }
         Ok(())
     }
+    /// Request termination of all queries watching this poison.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    /// Whether termination has been requested, either on this poison or on its parent.
+    #[inline(always)]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed) || self.1.as_ref().is_some_and(|p| p.is_cancelled())
+    }
+    /// A new poison that is cancelled when either itself or `self` is cancelled.
+    /// Cancelling the child does not affect `self`.
+    pub fn child(&self) -> Self {
+        Self(
+            Default::default(),
+            Some(Arc::new(self.clone())),
+            self.2.clone(),
+        )
+    }
+    /// A child that records the memory used by the script in `gauge`, if there is one.
+    /// Without a gauge, the poison itself is returned, so that checking it stays cheap.
+    pub(crate) fn with_memory_gauge(&self, gauge: Option<Arc<MemoryGauge>>) -> Self {
+        match gauge {
+            None => self.clone(),
+            Some(gauge) => Self(
+                Default::default(),
+                Some(Arc::new(self.clone())),
+                Some(gauge),
+            ),
+        }
+    }
+    /// Record that the script uses `bytes()` more memory. Without a memory budget,
+    /// `bytes` is never called. Returns `Err` if the memory budget is exceeded.
+    pub(crate) fn track_memory(&self, bytes: impl FnOnce() -> usize) -> Result<()> {
+        match &self.2 {
+            None => Ok(()),
+            Some(gauge) => gauge.add(bytes()),
+        }
+    }
     #[cfg(target_arch = "wasm32")]
     pub(crate) fn set_timeout(&self, _secs: f64) -> Result<()> {
         bail!("Cannot set timeout when threading is disallowed");
@@ -1949,7 +3458,7 @@ impl Poison {
         let pill = self.clone();
         thread::spawn(move || {
             thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
-            pill.0.store(true, Ordering::Relaxed);
+            pill.cancel();
         });
         Ok(())
     }
@@ -0,0 +1,137 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Generated columns of stored relations, declared with `::alter rel add generated`.
+//!
+//! A generated column is computed from the other columns of a row whenever the row is read,
+//! so that relations keeping their data in a loosely structured column such as a `Json`
+//! payload can gain columns later without rewriting their rows. Generated columns are read
+//! by name, as in `*rel{kind}`. Indexing one creates an expression index of the same name,
+//! which is kept up to date as rows are written and used by filters on the column.
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::parse::SourceSpan;
+use crate::runtime::relation::{ensure_index_expr_round_trips, parse_index_expr, RelationHandle};
+use crate::runtime::transact::SessionTx;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation {0} already has a column named {1}")]
+#[diagnostic(code(tx::generated_column_exists))]
+struct GeneratedColumnExists(String, String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation {0} has no generated column named {1}")]
+#[diagnostic(code(tx::generated_column_not_found))]
+struct GeneratedColumnNotFound(String, String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot generate column {0} of relation {1}")]
+#[diagnostic(code(tx::bad_generated_column))]
+#[diagnostic(help("{2}"))]
+struct BadGeneratedColumn(String, String, String, #[label] SourceSpan);
+
+impl RelationHandle {
+    /// The expression computing a generated column, with the columns of the relation
+    /// as variables, or `None` if there is no generated column of that name.
+    pub(crate) fn generated_column(&self, name: &str) -> Result<Option<Expr>> {
+        self.generated_columns
+            .get(name)
+            .map(|text| parse_index_expr(text))
+            .transpose()
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Add a generated column computed by `expr` from the columns of a stored relation,
+    /// and if `index` is set an index of the same name on its values.
+    pub(crate) fn add_generated_column(
+        &mut self,
+        rel: &Symbol,
+        name: &Symbol,
+        expr: &Expr,
+        index: bool,
+    ) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        let bad = |reason: &str| {
+            BadGeneratedColumn(
+                name.name.to_string(),
+                rel.name.to_string(),
+                reason.to_string(),
+                expr.span(),
+            )
+        };
+        ensure!(
+            !meta.name.contains(':') && !meta.is_temp,
+            bad("only stored relations can have generated columns")
+        );
+        let columns = meta
+            .metadata
+            .keys
+            .iter()
+            .chain(meta.metadata.non_keys.iter())
+            .map(|col| &col.name)
+            .collect_vec();
+        ensure!(
+            !columns.contains(&&name.name) && !meta.generated_columns.contains_key(&name.name),
+            GeneratedColumnExists(rel.name.to_string(), name.name.to_string(), name.span)
+        );
+        for var in expr.bindings()? {
+            if !columns.contains(&&var.name) {
+                bail!(bad(&format!(
+                    "'{}' is not a column of the relation",
+                    var.name
+                )))
+            }
+        }
+        let text = expr.to_string();
+        ensure_index_expr_round_trips(&text, expr.span())?;
+        meta.generated_columns.insert(name.name.clone(), text);
+
+        self.save_relation_meta(&meta)?;
+
+        if index {
+            self.create_index(rel, name, std::slice::from_ref(expr), &[], false)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a generated column, along with the index of the same name on its values.
+    /// Returns the ranges of keys to clean up, as [Self::remove_index] does.
+    pub(crate) fn remove_generated_column(
+        &mut self,
+        rel: &Symbol,
+        name: &Symbol,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut meta = self.get_relation(rel, true)?;
+        let Some(text) = meta.generated_columns.remove(&name.name) else {
+            bail!(GeneratedColumnNotFound(
+                rel.name.to_string(),
+                name.name.to_string(),
+                name.span
+            ))
+        };
+        let arity = meta.arity();
+        let has_index = matches!(
+            meta.indices.get(&name.name),
+            Some((_, mapper)) if mapper[0] >= arity && meta.index_exprs[mapper[0] - arity] == text
+        );
+
+        self.save_relation_meta(&meta)?;
+
+        if has_index {
+            self.remove_index(rel, name)
+        } else {
+            Ok(vec![])
+        }
+    }
+}
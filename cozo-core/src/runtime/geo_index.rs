@@ -0,0 +1,178 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Geo indices: the rows of a relation are indexed by the geohash of a point extracted from them,
+//! so that the rows within a radius of a point are found by scanning the few geohash cells
+//! covering the circle.
+
+use miette::{bail, miette, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::{eval_bytecode, eval_bytecode_pred, Bytecode};
+use crate::data::geo::{covering_cells, geohash_encode, get_point, haversine_km};
+use crate::data::tuple::Tuple;
+use crate::runtime::relation::RelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::{DataValue, Expr, SourceSpan, Symbol};
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct GeoIndexManifest {
+    pub(crate) base_relation: SmartString<LazyCompact>,
+    pub(crate) index_name: SmartString<LazyCompact>,
+    pub(crate) extractor: String,
+    /// Length of the geohashes the rows are indexed by
+    pub(crate) precision: usize,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct GeoSearch {
+    pub(crate) base_handle: RelationHandle,
+    pub(crate) idx_handle: RelationHandle,
+    pub(crate) manifest: GeoIndexManifest,
+    pub(crate) bindings: Vec<Symbol>,
+    pub(crate) k: Option<usize>,
+    pub(crate) query: Symbol,
+    pub(crate) radius: f64,
+    pub(crate) bind_distance: Option<Symbol>,
+    pub(crate) filter: Option<Expr>,
+    pub(crate) span: SourceSpan,
+}
+
+impl GeoSearch {
+    pub(crate) fn all_bindings(&self) -> impl Iterator<Item = &Symbol> {
+        self.bindings.iter().chain(self.bind_distance.iter())
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    fn geo_index_key(
+        tuple: &[DataValue],
+        extractor: &[Bytecode],
+        stack: &mut Vec<DataValue>,
+        rel_handle: &RelationHandle,
+        manifest: &GeoIndexManifest,
+    ) -> Result<Option<(Vec<DataValue>, (f64, f64))>> {
+        let to_index = eval_bytecode(extractor, tuple, stack)?;
+        if to_index == DataValue::Null {
+            return Ok(None);
+        }
+        let (lat, lon) = match get_point(&to_index) {
+            Some(p) => p,
+            None => bail!(
+                "Cannot put value {:?} into a geo index, points are lists [lat, lon]",
+                to_index
+            ),
+        };
+        let mut key = Vec::with_capacity(rel_handle.metadata.keys.len() + 1);
+        key.push(DataValue::from(geohash_encode(
+            lat,
+            lon,
+            manifest.precision,
+        )));
+        key.extend_from_slice(&tuple[..rel_handle.metadata.keys.len()]);
+        Ok(Some((key, (lat, lon))))
+    }
+    pub(crate) fn put_geo_index_item(
+        &mut self,
+        tuple: &[DataValue],
+        extractor: &[Bytecode],
+        stack: &mut Vec<DataValue>,
+        rel_handle: &RelationHandle,
+        idx_handle: &RelationHandle,
+        manifest: &GeoIndexManifest,
+    ) -> Result<()> {
+        if let Some((mut key, (lat, lon))) =
+            Self::geo_index_key(tuple, extractor, stack, rel_handle, manifest)?
+        {
+            key.push(DataValue::from(lat));
+            key.push(DataValue::from(lon));
+            let key_bytes = idx_handle.encode_key_for_store(&key, Default::default())?;
+            let val_bytes = idx_handle.encode_val_for_store(&key, Default::default())?;
+            self.store_tx.put(&key_bytes, &val_bytes)?;
+        }
+        Ok(())
+    }
+    pub(crate) fn del_geo_index_item(
+        &mut self,
+        tuple: &[DataValue],
+        extractor: &[Bytecode],
+        stack: &mut Vec<DataValue>,
+        rel_handle: &RelationHandle,
+        idx_handle: &RelationHandle,
+        manifest: &GeoIndexManifest,
+    ) -> Result<()> {
+        if let Some((key, _)) = Self::geo_index_key(tuple, extractor, stack, rel_handle, manifest)?
+        {
+            let key_bytes = idx_handle.encode_key_for_store(&key, Default::default())?;
+            self.store_tx.del(&key_bytes)?;
+        }
+        Ok(())
+    }
+    /// The rows within the radius of the point `q`, nearest first,
+    /// followed by their distance if it is bound.
+    pub(crate) fn geo_search(
+        &self,
+        q: &DataValue,
+        config: &GeoSearch,
+        stack: &mut Vec<DataValue>,
+        filter_code: &Option<(Vec<Bytecode>, SourceSpan)>,
+    ) -> Result<Vec<Tuple>> {
+        let (lat, lon) = match q {
+            DataValue::Null => return Ok(vec![]),
+            q => get_point(q).ok_or_else(|| {
+                miette!(
+                    "Cannot search for value {:?} in a geo index, points are lists [lat, lon]",
+                    q
+                )
+            })?,
+        };
+        let n_keys = config.base_handle.metadata.keys.len();
+        let mut found = vec![];
+        for cell in covering_cells(lat, lon, config.radius, config.manifest.precision) {
+            // '{' comes after all the characters of geohashes
+            let upper = DataValue::from(format!("{}{{", cell));
+            for tuple in
+                config
+                    .idx_handle
+                    .scan_bounded_prefix(self, &[], &[DataValue::from(cell)], &[upper])
+            {
+                let tuple = tuple?;
+                let (p_lat, p_lon) =
+                    match (tuple[n_keys + 1].get_float(), tuple[n_keys + 2].get_float()) {
+                        (Some(p_lat), Some(p_lon)) => (p_lat, p_lon),
+                        _ => bail!("Corrupted geo index {}", config.idx_handle.name),
+                    };
+                let distance = haversine_km(lat, lon, p_lat, p_lon);
+                if distance <= config.radius {
+                    found.push((distance, tuple[1..n_keys + 1].to_vec()));
+                }
+            }
+        }
+        found.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        let mut ret = vec![];
+        for (distance, key) in found {
+            let mut tuple = config
+                .base_handle
+                .get(self, &key)?
+                .ok_or_else(|| miette!("Tuple not found in base geo relation"))?;
+            if config.bind_distance.is_some() {
+                tuple.push(DataValue::from(distance));
+            }
+            if let Some((filter_code, span)) = filter_code {
+                if !eval_bytecode_pred(filter_code, &tuple, stack, *span)? {
+                    continue;
+                }
+            }
+            ret.push(tuple);
+            if config.k == Some(ret.len()) {
+                break;
+            }
+        }
+        Ok(ret)
+    }
+}
@@ -12,6 +12,7 @@ use crate::data::relation::VecElementType;
 use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN};
 use crate::data::value::Vector;
 use crate::parse::sys::HnswDistance;
+use crate::runtime::quantization::VectorQuantizer;
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, SourceSpan};
@@ -40,6 +41,10 @@ pub(crate) struct HnswIndexManifest {
     pub(crate) index_filter: Option<String>,
     pub(crate) extend_candidates: bool,
     pub(crate) keep_pruned_connections: bool,
+    /// If set, the graph is traversed using the quantized vectors stored in the codes
+    /// relation of the index, and search results are re-ranked with the full vectors
+    #[serde(default)]
+    pub(crate) quantizer: Option<VectorQuantizer>,
 }
 
 impl HnswIndexManifest {
@@ -50,16 +55,49 @@ impl HnswIndexManifest {
         // the level is the largest integer smaller than r
         -(r.floor() as i64)
     }
+    fn extract_vectors<'t>(&self, tuple: &'t [DataValue]) -> Vec<(&'t Vector, usize, i32)> {
+        extract_vectors(&self.vec_fields, tuple)
+    }
+}
+
+/// The vectors to index in a tuple, with their field and position within the field
+pub(crate) fn extract_vectors<'t>(
+    vec_fields: &[usize],
+    tuple: &'t [DataValue],
+) -> Vec<(&'t Vector, usize, i32)> {
+    let mut extracted_vectors = vec![];
+    for idx in vec_fields {
+        let val = tuple.get(*idx).unwrap();
+        if let DataValue::Vec(v) = val {
+            extracted_vectors.push((v, *idx, -1));
+        } else if let DataValue::List(l) = val {
+            for (sidx, v) in l.iter().enumerate() {
+                if let DataValue::Vec(v) = v {
+                    extracted_vectors.push((v, *idx, sidx as i32));
+                }
+            }
+        }
+    }
+    extracted_vectors
 }
 
 type CompoundKey = (Tuple, usize, i32);
 
-struct VectorCache {
+fn compound_key_tuple(key: &CompoundKey) -> Tuple {
+    let mut tuple = key.0.clone();
+    tuple.push(DataValue::from(key.1 as i64));
+    tuple.push(DataValue::from(key.2 as i64));
+    tuple
+}
+
+struct VectorCache<'c> {
     cache: FxHashMap<CompoundKey, Vector>,
     distance: HnswDistance,
+    /// For quantized indices, the codes relation and the quantizer
+    codes: Option<(RelationHandle, &'c VectorQuantizer, VecElementType)>,
 }
 
-impl VectorCache {
+impl VectorCache<'_> {
     fn insert(&mut self, k: CompoundKey, v: Vector) {
         self.cache.insert(k, v);
     }
@@ -126,6 +164,19 @@ impl VectorCache {
         tx: &SessionTx<'_>,
     ) -> Result<()> {
         if !self.cache.contains_key(key) {
+            if let Some((codes, quantizer, dtype)) = &self.codes {
+                match codes.get(tx, &compound_key_tuple(key))? {
+                    Some(tuple) => match tuple.last() {
+                        Some(DataValue::Bytes(code)) => {
+                            let v = quantizer.decode(code, *dtype);
+                            self.cache.insert(key.clone(), v);
+                        }
+                        _ => bail!("Cannot interpret {:?} as quantized vector", tuple),
+                    },
+                    None => bail!("Cannot find quantized vector for HNSW: {:?}", key),
+                }
+                return Ok(());
+            }
             match handle.get(tx, &key.0)? {
                 Some(tuple) => {
                     let mut field = &tuple[key.1];
@@ -152,6 +203,31 @@ impl VectorCache {
 }
 
 impl<'a> SessionTx<'a> {
+    fn hnsw_codes_handle(
+        &self,
+        idx_table: &RelationHandle,
+        manifest: &HnswIndexManifest,
+    ) -> Result<Option<RelationHandle>> {
+        Ok(match manifest.quantizer {
+            None => None,
+            Some(_) => Some(self.get_relation(&format!("{}:codes", idx_table.name), false)?),
+        })
+    }
+    fn hnsw_vector_cache<'c>(
+        &self,
+        idx_table: &RelationHandle,
+        manifest: &'c HnswIndexManifest,
+    ) -> Result<VectorCache<'c>> {
+        let codes = match (self.hnsw_codes_handle(idx_table, manifest)?, &manifest.quantizer) {
+            (Some(codes), Some(quantizer)) => Some((codes, quantizer, manifest.dtype)),
+            _ => None,
+        };
+        Ok(VectorCache {
+            cache: FxHashMap::default(),
+            distance: manifest.distance,
+            codes,
+        })
+    }
     fn hnsw_put_vector(
         &mut self,
         tuple: &[DataValue],
@@ -161,10 +237,9 @@ impl<'a> SessionTx<'a> {
         manifest: &HnswIndexManifest,
         orig_table: &RelationHandle,
         idx_table: &RelationHandle,
-        vec_cache: &mut VectorCache,
+        vec_cache: &mut VectorCache<'_>,
     ) -> Result<()> {
         let tuple_key = &tuple[..orig_table.metadata.keys.len()];
-        vec_cache.insert((tuple_key.to_vec(), idx, subidx), q.clone());
         let hash = q.get_hash();
         let mut canary_tuple = vec![DataValue::from(0)];
         for _ in 0..2 {
@@ -178,8 +253,25 @@ impl<'a> SessionTx<'a> {
                     return Ok(());
                 }
             }
-            self.hnsw_remove_vec(tuple_key, idx, subidx, orig_table, idx_table)?;
+            let codes = vec_cache.codes.as_ref().map(|(codes, _, _)| codes);
+            self.hnsw_remove_vec(tuple_key, idx, subidx, orig_table, idx_table, codes)?;
         }
+        let compound_key = (tuple_key.to_vec(), idx, subidx);
+        match &vec_cache.codes {
+            None => vec_cache.insert(compound_key, q.clone()),
+            Some((codes, quantizer, dtype)) => {
+                // the inserted vector is compared with the others in the same way as the others
+                let code = quantizer.encode(q);
+                let mut code_tuple = compound_key_tuple(&compound_key);
+                code_tuple.push(DataValue::Bytes(code.clone()));
+                let (key, val) = codes.encode_index_entry(&code_tuple)?;
+                self.store_tx.put(&key, &val)?;
+                let decoded = quantizer.decode(&code, *dtype);
+                vec_cache.insert(compound_key, decoded);
+            }
+        }
+        let q = vec_cache.get_key(&(tuple_key.to_vec(), idx, subidx)).clone();
+        let q = &q;
 
         let ep_res = idx_table
             .scan_bounded_prefix(
@@ -381,7 +473,7 @@ impl<'a> SessionTx<'a> {
         manifest: &HnswIndexManifest,
         idx_table: &RelationHandle,
         orig_table: &RelationHandle,
-        vec_cache: &mut VectorCache,
+        vec_cache: &mut VectorCache<'_>,
     ) -> Result<usize> {
         vec_cache.ensure_key(target_key, orig_table, self)?;
         let vec = vec_cache.get_key(target_key).clone();
@@ -476,7 +568,7 @@ impl<'a> SessionTx<'a> {
         manifest: &HnswIndexManifest,
         idx_table: &RelationHandle,
         orig_table: &RelationHandle,
-        vec_cache: &mut VectorCache,
+        vec_cache: &mut VectorCache<'_>,
     ) -> Result<PriorityQueue<CompoundKey, Reverse<OrderedFloat<f64>>>> {
         let mut candidates = PriorityQueue::new();
         // Simple non-heuristic selection
@@ -544,7 +636,7 @@ impl<'a> SessionTx<'a> {
         orig_table: &RelationHandle,
         idx_table: &RelationHandle,
         found_nn: &mut PriorityQueue<CompoundKey, OrderedFloat<f64>>,
-        vec_cache: &mut VectorCache,
+        vec_cache: &mut VectorCache<'_>,
     ) -> Result<()> {
         let mut visited: FxHashSet<CompoundKey> = FxHashSet::default();
         // min queue
@@ -687,30 +779,15 @@ impl<'a> SessionTx<'a> {
     ) -> Result<bool> {
         if let Some(code) = filter {
             if !eval_bytecode_pred(code, tuple, stack, Default::default())? {
-                self.hnsw_remove(orig_table, idx_table, tuple)?;
+                self.hnsw_remove(manifest, orig_table, idx_table, tuple)?;
                 return Ok(false);
             }
         }
-        let mut extracted_vectors = vec![];
-        for idx in &manifest.vec_fields {
-            let val = tuple.get(*idx).unwrap();
-            if let DataValue::Vec(v) = val {
-                extracted_vectors.push((v, *idx, -1));
-            } else if let DataValue::List(l) = val {
-                for (sidx, v) in l.iter().enumerate() {
-                    if let DataValue::Vec(v) = v {
-                        extracted_vectors.push((v, *idx, sidx as i32));
-                    }
-                }
-            }
-        }
+        let extracted_vectors = manifest.extract_vectors(tuple);
         if extracted_vectors.is_empty() {
             return Ok(false);
         }
-        let mut vec_cache = VectorCache {
-            cache: FxHashMap::default(),
-            distance: manifest.distance,
-        };
+        let mut vec_cache = self.hnsw_vector_cache(idx_table, manifest)?;
         for (vec, idx, sub) in extracted_vectors {
             self.hnsw_put_vector(
                 tuple,
@@ -727,10 +804,12 @@ impl<'a> SessionTx<'a> {
     }
     pub(crate) fn hnsw_remove(
         &mut self,
+        manifest: &HnswIndexManifest,
         orig_table: &RelationHandle,
         idx_table: &RelationHandle,
         tuple: &[DataValue],
     ) -> Result<()> {
+        let codes = self.hnsw_codes_handle(idx_table, manifest)?;
         let mut prefix = vec![DataValue::from(0)];
         prefix.extend_from_slice(&tuple[0..orig_table.metadata.keys.len()]);
         let candidates: FxHashSet<_> = idx_table
@@ -747,7 +826,7 @@ impl<'a> SessionTx<'a> {
             })
             .collect();
         for (tuple_key, idx, subidx) in candidates {
-            self.hnsw_remove_vec(&tuple_key, idx, subidx, orig_table, idx_table)?;
+            self.hnsw_remove_vec(&tuple_key, idx, subidx, orig_table, idx_table, codes.as_ref())?;
         }
         Ok(())
     }
@@ -758,8 +837,16 @@ impl<'a> SessionTx<'a> {
         subidx: i32,
         orig_table: &RelationHandle,
         idx_table: &RelationHandle,
+        codes: Option<&RelationHandle>,
     ) -> Result<()> {
         let compound_key = (tuple_key.to_vec(), idx, subidx);
+        if let Some(codes) = codes {
+            let code_key = codes.encode_key_for_store(
+                &compound_key_tuple(&compound_key),
+                Default::default(),
+            )?;
+            self.store_tx.del(&code_key)?;
+        }
         // Go down the layers and remove all the links
         let mut encountered_singletons = false;
         for neg_layer in 0i64.. {
@@ -883,10 +970,7 @@ impl<'a> SessionTx<'a> {
             (Vector::F64(v), VecElementType::F32) => Vector::F32(v.mapv(|x| x as f32)),
         };
 
-        let mut vec_cache = VectorCache {
-            cache: Default::default(),
-            distance: config.manifest.distance,
-        };
+        let mut vec_cache = self.hnsw_vector_cache(&config.idx_handle, &config.manifest)?;
 
         let ep_res = config
             .idx_handle
@@ -940,25 +1024,50 @@ impl<'a> SessionTx<'a> {
                 return Ok(vec![]);
             }
 
-            if config.filter.is_none() {
-                while found_nn.len() > config.k {
-                    found_nn.pop();
+            let mut candidates = vec![];
+            if config.manifest.quantizer.is_some() {
+                // re-rank with the distances to the full vectors
+                for (cand_key, _) in found_nn {
+                    let cand_tuple = config
+                        .base_handle
+                        .get(self, &cand_key.0)?
+                        .ok_or_else(|| miette!("corrupted index"))?;
+                    let vec = match config.manifest.extract_vectors(&cand_tuple).into_iter().find(
+                        |(_, idx, subidx)| *idx == cand_key.1 && *subidx == cand_key.2,
+                    ) {
+                        Some((vec, _, _)) => vec.clone(),
+                        None => bail!("corrupted index"),
+                    };
+                    let distance = vec_cache.dist(&q, &vec);
+                    candidates.push((cand_key, distance, Some(cand_tuple)));
+                }
+            } else {
+                for (cand_key, OrderedFloat(distance)) in found_nn {
+                    candidates.push((cand_key, distance, None));
                 }
             }
+            candidates.sort_by(|(_, a, _), (_, b, _)| a.total_cmp(b));
+
+            if config.filter.is_none() {
+                candidates.truncate(config.k);
+            }
 
             let mut ret = vec![];
 
-            while let Some((cand_key, OrderedFloat(distance))) = found_nn.pop() {
+            for (cand_key, distance, cand_tuple) in candidates {
                 if let Some(r) = config.radius {
                     if distance > r {
                         continue;
                     }
                 }
 
-                let mut cand_tuple = config
-                    .base_handle
-                    .get(self, &cand_key.0)?
-                    .ok_or_else(|| miette!("corrupted index"))?;
+                let mut cand_tuple = match cand_tuple {
+                    Some(t) => t,
+                    None => config
+                        .base_handle
+                        .get(self, &cand_key.0)?
+                        .ok_or_else(|| miette!("corrupted index"))?,
+                };
 
                 // make sure the order is the same as in all_bindings()!!!
                 if config.bind_field.is_some() {
@@ -1002,7 +1111,6 @@ impl<'a> SessionTx<'a> {
 
                 ret.push(cand_tuple);
             }
-            ret.reverse();
             ret.truncate(config.k);
 
             Ok(ret)
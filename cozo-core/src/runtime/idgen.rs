@@ -0,0 +1,288 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Named ID generators: they are defined in the catalog with `::idgen create`, and
+//! `gen_id(name)` returns the next ID of one, in queries as well as in column defaults.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crossbeam::sync::ShardedLock;
+use itertools::Itertools;
+use miette::{bail, miette, Diagnostic, Result};
+use rand::Rng;
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::functions::{current_validity, with_rng};
+use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::parse::SourceSpan;
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+/// Default epoch of snowflake IDs, 2020-01-01T00:00:00Z in milliseconds.
+pub(crate) const SNOWFLAKE_EPOCH: i64 = 1_577_836_800_000;
+/// Snowflake node IDs have 10 bits.
+pub(crate) const MAX_SNOWFLAKE_NODE_ID: u16 = 1023;
+/// The alphabet of nanoid: URL-safe, 64 characters.
+pub(crate) const NANOID_ALPHABET: &str =
+    "_-0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+pub(crate) const NANOID_SIZE: usize = 21;
+
+const SNOWFLAKE_SEQUENCE_BITS: u32 = 12;
+const SNOWFLAKE_NODE_BITS: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub enum IdGenKind {
+    /// Time-ordered UUIDs, with 48 bits of milliseconds since the Unix epoch.
+    UuidV7,
+    /// 64-bit integers made of 41 bits of milliseconds since `epoch`,
+    /// 10 bits of node ID and a 12-bit sequence.
+    Snowflake { node_id: u16, epoch: i64 },
+    /// Random strings of `size` characters drawn from `alphabet`.
+    NanoId { alphabet: String, size: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct IdGenSpec {
+    pub(crate) name: SmartString<LazyCompact>,
+    pub(crate) kind: IdGenKind,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("ID generator {0} not found")]
+#[diagnostic(code(eval::id_gen_not_found))]
+struct IdGenNotFound(String);
+
+fn id_gen_key(name: &str) -> Vec<u8> {
+    let tuple = vec![
+        DataValue::Null,
+        DataValue::from("IDGEN"),
+        DataValue::from(name),
+    ];
+    tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+impl<'a> SessionTx<'a> {
+    pub(crate) fn create_id_gen(&mut self, spec: &IdGenSpec, span: SourceSpan) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("ID generator {0} already exists")]
+        #[diagnostic(code(tx::id_gen_exists))]
+        struct IdGenExists(String, #[label] SourceSpan);
+
+        let key = id_gen_key(&spec.name);
+        if self.store_tx.exists(&key, false)? {
+            bail!(IdGenExists(spec.name.to_string(), span))
+        }
+        let mut val = vec![];
+        spec.serialize(&mut Serializer::new(&mut val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&key, &val)?;
+        self.id_gen_changes.push(IdGenChange::Create(spec.clone()));
+        Ok(())
+    }
+    pub(crate) fn remove_id_gen(&mut self, name: &Symbol) -> Result<()> {
+        let key = id_gen_key(&name.name);
+        if !self.store_tx.exists(&key, false)? {
+            bail!(IdGenNotFound(name.name.to_string()))
+        }
+        self.store_tx.del(&key)?;
+        self.id_gen_changes
+            .push(IdGenChange::Remove(name.name.clone()));
+        Ok(())
+    }
+    pub(crate) fn id_gens(&self) -> Result<Vec<IdGenSpec>> {
+        let lower = id_gen_key("");
+        let upper = id_gen_key(&String::from(LARGEST_UTF_CHAR));
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            ret.push(
+                rmp_serde::from_slice(&v_slice)
+                    .map_err(|e| miette!("Cannot decode ID generator: {e}"))?,
+            );
+        }
+        Ok(ret)
+    }
+}
+
+/// A generator, with the state of snowflake sequences.
+struct IdGenerator {
+    kind: IdGenKind,
+    /// The millisecond and the sequence number of the last snowflake ID.
+    last: Mutex<(i64, i64)>,
+}
+
+impl IdGenerator {
+    fn next(&self) -> DataValue {
+        match &self.kind {
+            IdGenKind::UuidV7 => {
+                let mut bytes = [0u8; 10];
+                with_rng(|rng| rng.fill(&mut bytes));
+                let millis = current_validity().0 .0 / 1000;
+                let id =
+                    uuid::Builder::from_unix_timestamp_millis(millis as u64, &bytes).into_uuid();
+                DataValue::uuid(id)
+            }
+            IdGenKind::Snowflake { node_id, epoch } => {
+                let now = current_validity().0 .0 / 1000 - epoch;
+                let mut last = self.last.lock().unwrap();
+                // the IDs keep increasing even if the clock goes backwards,
+                // or if the sequence of a millisecond is exhausted
+                let (millis, seq) = if now > last.0 {
+                    (now, 0)
+                } else if last.1 + 1 < 1 << SNOWFLAKE_SEQUENCE_BITS {
+                    (last.0, last.1 + 1)
+                } else {
+                    (last.0 + 1, 0)
+                };
+                *last = (millis, seq);
+                DataValue::from(
+                    millis << (SNOWFLAKE_NODE_BITS + SNOWFLAKE_SEQUENCE_BITS)
+                        | (*node_id as i64) << SNOWFLAKE_SEQUENCE_BITS
+                        | seq,
+                )
+            }
+            IdGenKind::NanoId { alphabet, size } => {
+                let chars = alphabet.chars().collect_vec();
+                let id: String = with_rng(|rng| {
+                    (0..*size)
+                        .map(|_| chars[rng.gen_range(0..chars.len())])
+                        .collect()
+                });
+                DataValue::from(id)
+            }
+        }
+    }
+}
+
+/// A change to the ID generators in the catalog.
+pub(crate) enum IdGenChange {
+    Create(IdGenSpec),
+    Remove(SmartString<LazyCompact>),
+}
+
+/// The ID generators of a database, cached from the catalog.
+#[derive(Default)]
+pub(crate) struct IdGenerators {
+    generators: ShardedLock<BTreeMap<SmartString<LazyCompact>, Arc<IdGenerator>>>,
+}
+
+impl IdGenerators {
+    pub(crate) fn load(&self, specs: Vec<IdGenSpec>) {
+        self.generators.write().unwrap().clear();
+        for spec in specs {
+            self.insert(spec);
+        }
+    }
+    pub(crate) fn insert(&self, spec: IdGenSpec) {
+        self.generators.write().unwrap().insert(
+            spec.name,
+            Arc::new(IdGenerator {
+                kind: spec.kind,
+                last: Mutex::new((i64::MIN, 0)),
+            }),
+        );
+    }
+    pub(crate) fn remove(&self, name: &str) {
+        self.generators.write().unwrap().remove(name);
+    }
+    /// Apply a change made by a committed transaction.
+    pub(crate) fn apply(&self, change: IdGenChange) {
+        match change {
+            IdGenChange::Create(spec) => self.insert(spec),
+            IdGenChange::Remove(name) => self.remove(&name),
+        }
+    }
+    /// Make `gen_id` use these generators on this thread until the guard is dropped.
+    pub(crate) fn install(self: &Arc<Self>) -> IdGeneratorsGuard {
+        let prev = ID_GENERATORS.with(|gens| gens.borrow_mut().replace(self.clone()));
+        IdGeneratorsGuard(prev)
+    }
+}
+
+thread_local! {
+    static ID_GENERATORS: RefCell<Option<Arc<IdGenerators>>> = const { RefCell::new(None) };
+}
+
+/// Restores the previous ID generators of the thread when dropped.
+pub(crate) struct IdGeneratorsGuard(Option<Arc<IdGenerators>>);
+
+impl Drop for IdGeneratorsGuard {
+    fn drop(&mut self) {
+        let prev = self.0.take();
+        ID_GENERATORS.with(|gens| *gens.borrow_mut() = prev);
+    }
+}
+
+/// The next ID of the generator `name` of the database running on this thread.
+pub(crate) fn next_id(name: &str) -> Result<DataValue> {
+    let generator = ID_GENERATORS.with(|gens| {
+        gens.borrow()
+            .as_ref()
+            .and_then(|gens| gens.generators.read().unwrap().get(name).cloned())
+    });
+    match generator {
+        Some(generator) => Ok(generator.next()),
+        None => bail!(IdGenNotFound(name.to_string())),
+    }
+}
+
+pub(crate) fn list_id_gens(tx: &SessionTx<'_>) -> Result<NamedRows> {
+    let rows = tx
+        .id_gens()?
+        .into_iter()
+        .map(|spec| {
+            let mut row = vec![DataValue::Str(spec.name)];
+            row.extend(match spec.kind {
+                IdGenKind::UuidV7 => [
+                    DataValue::from("uuid_v7"),
+                    DataValue::Null,
+                    DataValue::Null,
+                    DataValue::Null,
+                    DataValue::Null,
+                ],
+                IdGenKind::Snowflake { node_id, epoch } => [
+                    DataValue::from("snowflake"),
+                    DataValue::from(node_id as i64),
+                    DataValue::from(epoch),
+                    DataValue::Null,
+                    DataValue::Null,
+                ],
+                IdGenKind::NanoId { alphabet, size } => [
+                    DataValue::from("nanoid"),
+                    DataValue::Null,
+                    DataValue::Null,
+                    DataValue::from(alphabet),
+                    DataValue::from(size as i64),
+                ],
+            });
+            row
+        })
+        .collect_vec();
+    Ok(NamedRows::new(
+        vec![
+            "name".to_string(),
+            "kind".to_string(),
+            "node_id".to_string(),
+            "epoch".to_string(),
+            "alphabet".to_string(),
+            "size".to_string(),
+        ],
+        rows,
+    ))
+}
@@ -22,6 +22,7 @@ use crate::parse::{ImperativeCondition, ImperativeProgram, ImperativeStmt, Sourc
 use crate::runtime::callback::CallbackCollector;
 use crate::runtime::db::{seconds_since_the_epoch, RunningQueryCleanup, RunningQueryHandle};
 use crate::runtime::relation::InputRelationHandle;
+use crate::runtime::scheduler::QueueKind;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, Db, NamedRows, Poison, Storage, ValidityTs};
 
@@ -40,6 +41,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         cur_vld: ValidityTs,
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
+        poison: &Poison,
     ) -> Result<bool> {
         let res = match p {
             Left(rel) => {
@@ -53,11 +55,12 @@ impl<'s, S: Storage<'s>> Db<S> {
                 cur_vld,
                 callback_targets,
                 callback_collector,
+                poison,
             )?,
         };
         if let Right(pg) = &p {
             if let Some(store_as) = &pg.store_as {
-                tx.script_store_as_relation(self, store_as, &res, cur_vld)?;
+                tx.script_store_as_relation(self, store_as, &res, cur_vld, poison)?;
             }
         }
         Ok(!res.rows.is_empty())
@@ -98,6 +101,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                                 cur_vld,
                                 callback_targets,
                                 callback_collector,
+                                poison,
                             )?,
                             Right(rel) => {
                                 let relation = tx.get_relation(rel, false)?;
@@ -106,7 +110,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         };
                         if let Left(pg) = nxt {
                             if let Some(store_as) = &pg.store_as {
-                                tx.script_store_as_relation(self, store_as, &nr, cur_vld)?;
+                                tx.script_store_as_relation(self, store_as, &nr, cur_vld, poison)?;
                             }
                         }
                         nr.next = current;
@@ -120,9 +124,18 @@ impl<'s, S: Storage<'s>> Db<S> {
                     ret = NamedRows::default();
                 }
                 ImperativeStmt::SysOp { sysop, .. } => {
-                    ret = self.run_sys_op_with_tx(tx, &sysop.sysop, readonly, true)?;
+                    ret = self.run_sys_op_with_tx(
+                        tx,
+                        &sysop.sysop,
+                        readonly,
+                        true,
+                        cleanups,
+                        callback_targets,
+                        callback_collector,
+                        poison,
+                    )?;
                     if let Some(store_as) = &sysop.store_as {
-                        tx.script_store_as_relation(self, store_as, &ret, cur_vld)?;
+                        tx.script_store_as_relation(self, store_as, &ret, cur_vld, poison)?;
                     }
                 }
                 ImperativeStmt::Program { prog, .. } => {
@@ -133,9 +146,10 @@ impl<'s, S: Storage<'s>> Db<S> {
                         cur_vld,
                         callback_targets,
                         callback_collector,
+                        poison,
                     )?;
                     if let Some(store_as) = &prog.store_as {
-                        tx.script_store_as_relation(self, store_as, &ret, cur_vld)?;
+                        tx.script_store_as_relation(self, store_as, &ret, cur_vld, poison)?;
                     }
                 }
                 ImperativeStmt::IgnoreErrorProgram { prog, .. } => {
@@ -146,10 +160,11 @@ impl<'s, S: Storage<'s>> Db<S> {
                         cur_vld,
                         callback_targets,
                         callback_collector,
+                        poison,
                     ) {
                         Ok(res) => {
                             if let Some(store_as) = &prog.store_as {
-                                tx.script_store_as_relation(self, store_as, &res, cur_vld)?;
+                                tx.script_store_as_relation(self, store_as, &res, cur_vld, poison)?;
                             }
                             ret = res
                         }
@@ -175,6 +190,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         cur_vld,
                         callback_targets,
                         callback_collector,
+                        poison,
                     )?;
                     let cond_val = if *negated { !cond_val } else { cond_val };
                     let to_execute = if cond_val { then_branch } else { else_branch };
@@ -257,6 +273,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         cur_vld: ValidityTs,
         ps: &ImperativeProgram,
         readonly: bool,
+        poison: &Poison,
     ) -> Result<NamedRows, Report> {
         let mut callback_collector = BTreeMap::new();
         let mut write_lock_names = BTreeSet::new();
@@ -267,6 +284,15 @@ impl<'s, S: Storage<'s>> Db<S> {
             bail!("Read-only imperative program attempted to acquire write locks");
         }
         let is_write = !write_lock_names.is_empty();
+        let _permit = self.scheduler.acquire(
+            if is_write {
+                QueueKind::Write
+            } else {
+                QueueKind::Read
+            },
+            poison,
+        )?;
+        let poison = &poison.with_memory_gauge(_permit.gauge());
         let write_lock = self.obtain_relation_locks(write_lock_names.iter());
         let _write_lock_guards = write_lock.iter().map(|l| l.read().unwrap()).collect_vec();
 
@@ -284,7 +310,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 self.transact()?
             };
 
-            let poison = Poison::default();
+            let poison = poison.child();
             let qid = self.queries_count.fetch_add(1, Ordering::AcqRel);
             let since_the_epoch = seconds_since_the_epoch()?;
 
@@ -330,7 +356,6 @@ impl<'s, S: Storage<'s>> Db<S> {
 
             tx.commit_tx()?;
         }
-        #[cfg(not(target_arch = "wasm32"))]
         if !callback_collector.is_empty() {
             self.send_callbacks(callback_collector)
         }
@@ -346,6 +371,7 @@ impl SessionTx<'_> {
         name: &str,
         rels: &NamedRows,
         cur_vld: ValidityTs,
+        poison: &Poison,
     ) -> Result<()> {
         let mut key_bindings = vec![];
         for k in rels.headers.iter() {
@@ -391,8 +417,10 @@ impl SessionTx<'_> {
             cur_vld,
             &Default::default(),
             &mut Default::default(),
+            poison,
             true,
             "",
+            &mut Default::default(),
         )?;
         Ok(())
     }
@@ -0,0 +1,240 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Schema metadata exposed as relations that can be joined with data in queries.
+//!
+//! The relations `*__relations`, `*__columns`, `*__indices` and `*__triggers` are not kept
+//! in storage. When a query refers to one of them, it is generated from the metadata of the
+//! stored relations into the temporary store of the transaction, so that it always reflects
+//! the schema as seen by the query.
+
+use std::collections::BTreeSet;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::program::{FixedRuleArg, InputAtom, InputInlineRulesOrFixed, InputProgram};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::db::Db;
+use crate::runtime::relation::{InputRelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::storage::{Storage, StoreTx};
+use crate::NamedRows;
+
+/// The names of the system relations, which are reserved.
+pub(crate) const SYSTEM_RELATIONS: [&str; 4] =
+    ["__relations", "__columns", "__indices", "__triggers"];
+
+// the columns of `::columns`, `::indices` and `::show_triggers`
+const COLUMNS_HEADERS: [&str; 6] = [
+    "column",
+    "is_key",
+    "index",
+    "type",
+    "has_default",
+    "default_expr",
+];
+const INDICES_HEADERS: [&str; 4] = ["name", "type", "relations", "config"];
+const TRIGGERS_HEADERS: [&str; 3] = ["type", "idx", "trigger"];
+
+impl InputAtom {
+    fn collect_relations<'a>(&'a self, coll: &mut BTreeSet<&'a str>) {
+        let name = match self {
+            InputAtom::Relation { inner } => &inner.name,
+            InputAtom::NamedFieldRelation { inner } => &inner.name,
+            InputAtom::Negation { inner, .. } => return inner.collect_relations(coll),
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                for atom in inner {
+                    atom.collect_relations(coll);
+                }
+                return;
+            }
+            InputAtom::Path { inner } => {
+                let mut relations = vec![];
+                inner.pattern.relations(&mut relations);
+                coll.extend(relations.into_iter().map(|r| r.name.as_str()));
+                return;
+            }
+            _ => return,
+        };
+        coll.insert(&name.name);
+    }
+}
+
+impl InputProgram {
+    /// The names of the relations the program reads.
+    pub(crate) fn relations_read(&self) -> BTreeSet<&str> {
+        let mut coll = BTreeSet::new();
+        for rules_or_fixed in self.prog.values() {
+            match rules_or_fixed {
+                InputInlineRulesOrFixed::Rules { rules } => {
+                    for atom in rules.iter().flat_map(|rule| rule.body.iter()) {
+                        atom.collect_relations(&mut coll);
+                    }
+                }
+                InputInlineRulesOrFixed::Fixed { fixed } => {
+                    for arg in &fixed.rule_args {
+                        if let FixedRuleArg::Stored { name, .. }
+                        | FixedRuleArg::NamedStored { name, .. } = arg
+                        {
+                            coll.insert(&name.name);
+                        }
+                    }
+                }
+            }
+        }
+        coll
+    }
+    /// The system relations the program reads.
+    pub(crate) fn system_relations(&self) -> BTreeSet<&'static str> {
+        let read = self.relations_read();
+        SYSTEM_RELATIONS
+            .into_iter()
+            .filter(|rel| read.contains(rel))
+            .collect()
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Generate the system relations read by `prog` in the temporary store of `tx`,
+    /// replacing the rows generated for earlier queries of the same transaction.
+    pub(crate) fn generate_system_relations(
+        &self,
+        tx: &mut SessionTx<'_>,
+        prog: &InputProgram,
+    ) -> Result<()> {
+        for name in prog.system_relations() {
+            let (n_keys, rows) = match name {
+                "__relations" => (1, self.list_relations(tx)?),
+                "__columns" => (
+                    2,
+                    self.per_relation(tx, &COLUMNS_HEADERS, |tx, rel| self.list_columns(tx, rel))?,
+                ),
+                "__indices" => (
+                    2,
+                    self.per_relation(tx, &INDICES_HEADERS, |tx, rel| self.list_indices(tx, rel))?,
+                ),
+                "__triggers" => (3, self.per_relation(tx, &TRIGGERS_HEADERS, list_triggers)?),
+                _ => unreachable!(),
+            };
+            tx.put_system_relation(name, n_keys, rows)?;
+        }
+        Ok(())
+    }
+
+    /// Concatenate the rows returned by `f` for every stored relation, prefixed with the
+    /// name of the relation.
+    fn per_relation(
+        &self,
+        tx: &SessionTx<'_>,
+        headers: &[&str],
+        f: impl Fn(&SessionTx<'_>, &str) -> Result<NamedRows>,
+    ) -> Result<NamedRows> {
+        let relations = self.list_relations(tx)?;
+        let mut rows = vec![];
+        for rel in relations.rows {
+            let DataValue::Str(rel) = &rel[0] else {
+                continue;
+            };
+            for row in f(tx, rel)?.rows {
+                let mut prefixed = vec![DataValue::Str(rel.clone())];
+                prefixed.extend(row);
+                rows.push(prefixed);
+            }
+        }
+        let headers = ["relation"]
+            .iter()
+            .chain(headers)
+            .map(|h| h.to_string())
+            .collect_vec();
+        Ok(NamedRows::new(headers, rows))
+    }
+}
+
+fn list_triggers(tx: &SessionTx<'_>, name: &str) -> Result<NamedRows> {
+    let rel = tx.get_relation(name, false)?;
+    let rows = [
+        ("put", &rel.put_triggers),
+        ("rm", &rel.rm_triggers),
+        ("replace", &rel.replace_triggers),
+    ]
+    .into_iter()
+    .flat_map(|(kind, triggers)| {
+        triggers.iter().enumerate().map(move |(i, trigger)| {
+            vec![
+                DataValue::from(kind),
+                DataValue::from(i as i64),
+                DataValue::from(trigger as &str),
+            ]
+        })
+    })
+    .collect_vec();
+    Ok(NamedRows::new(
+        TRIGGERS_HEADERS.iter().map(|h| h.to_string()).collect(),
+        rows,
+    ))
+}
+
+impl<'a> SessionTx<'a> {
+    /// Store `rows` as the temporary relation `name`, whose first `n_keys` columns are keys.
+    fn put_system_relation(&mut self, name: &str, n_keys: usize, rows: NamedRows) -> Result<()> {
+        let name_key = vec![DataValue::from(name)].encode_as_key(RelationId::SYSTEM);
+        let handle = if self.temp_store_tx.exists(&name_key, false)? {
+            let handle = self.get_relation(name, false)?;
+            let lower = Tuple::default().encode_as_key(handle.id);
+            let upper = Tuple::default().encode_as_key(handle.id.next());
+            let keys: Vec<_> = self
+                .temp_store_tx
+                .range_scan(&lower, &upper)
+                .map_ok(|(k, _)| k)
+                .try_collect()?;
+            for k in keys {
+                self.temp_store_tx.del(&k)?;
+            }
+            handle
+        } else {
+            let mut columns = rows
+                .headers
+                .iter()
+                .map(|h| ColumnDef {
+                    name: h.into(),
+                    typing: NullableColType {
+                        coltype: ColType::Any,
+                        nullable: true,
+                    },
+                    default_gen: None,
+                })
+                .collect_vec();
+            let non_keys = columns.split_off(n_keys);
+            let symbols = |cols: &[ColumnDef]| {
+                cols.iter()
+                    .map(|c| Symbol::new(c.name.clone(), Default::default()))
+                    .collect_vec()
+            };
+            self.create_relation(InputRelationHandle {
+                name: Symbol::new(name, Default::default()),
+                key_bindings: symbols(&columns),
+                dep_bindings: symbols(&non_keys),
+                metadata: StoredRelationMetadata {
+                    keys: columns,
+                    non_keys,
+                },
+                span: Default::default(),
+            })?
+        };
+        for row in rows.rows {
+            let key = handle.encode_key_for_store(&row, Default::default())?;
+            let val = handle.encode_val_for_store(&row, Default::default())?;
+            self.temp_store_tx.put(&key, &val)?;
+        }
+        Ok(())
+    }
+}
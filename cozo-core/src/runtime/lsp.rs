@@ -0,0 +1,520 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Support for editors and language servers: tokens for highlighting, diagnostics with spans
+//! and completion candidates, so that editor integrations need not reimplement the grammar.
+//!
+//! The tokenizer is deliberately more lenient than the parser: it never fails, so that scripts
+//! being edited can still be highlighted.
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{Report, Result};
+
+use crate::data::aggr::aggr_names;
+use crate::data::expr::op_names;
+use crate::data::functions::current_validity;
+use crate::data::value::DataValue;
+use crate::parse::{parse_script, MultipleErrors, SourceSpan};
+use crate::runtime::db::Db;
+use crate::storage::Storage;
+
+/// The kind of a [Token].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub enum TokenKind {
+    /// Line and block comments.
+    Comment,
+    /// String literals, including raw strings.
+    String,
+    /// Number literals.
+    Number,
+    /// Query options like `:put`, system ops like `::relations`, imperative keywords like `%if`,
+    /// `not`, `or`, `in`, `true`, `false` and `null`.
+    Keyword,
+    /// Variables, and names of columns and options.
+    Variable,
+    /// Parameters like `$name`.
+    Parameter,
+    /// Functions and aggregations applied to arguments.
+    Function,
+    /// Inline and fixed rules, including the entry `?`.
+    Rule,
+    /// Stored relations like `*name`, and indices searched like `~name:index`.
+    Relation,
+    /// Operators, including `:=`, `<-` and `<~`.
+    Operator,
+    /// Brackets, commas, semicolons and colons.
+    Punctuation,
+    /// Characters not allowed in scripts.
+    Unknown,
+}
+
+/// A token of a script, see [tokenize].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct Token {
+    /// The kind of the token.
+    pub kind: TokenKind,
+    /// The position of the token in the script, in bytes.
+    pub span: SourceSpan,
+}
+
+const QUERY_OPTIONS: &[&str] = &[
+    "as_of_tx",
+    "assert",
+    "create",
+    "delete",
+    "disable_magic_rewrite",
+    "ensure",
+    "ensure_not",
+    "experimental",
+    "hints",
+    "insert",
+    "limit",
+    "nest",
+    "offset",
+    "order",
+    "outbox",
+    "put",
+    "replace",
+    "returning",
+    "rm",
+    "seed",
+    "sleep",
+    "sort",
+    "timeout",
+    "transform",
+    "update",
+    "upsert",
+    "window",
+];
+
+const KEYWORDS: &[&str] = &["false", "in", "not", "null", "or", "true"];
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The length in bytes of the longest prefix of `s` whose characters satisfy `pred`.
+fn prefix_len(s: &str, pred: impl Fn(char) -> bool) -> usize {
+    s.find(|c| !pred(c)).unwrap_or(s.len())
+}
+
+/// Split the script into tokens for highlighting. Whitespace is skipped.
+///
+/// Invalid scripts are tokenized as well as possible: unterminated strings and comments
+/// extend to the end of the script, and characters not allowed are [TokenKind::Unknown].
+pub fn tokenize(src: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut pos = 0;
+    while let Some(c) = src[pos..].chars().next() {
+        let rest = &src[pos..];
+        let prev_is_operand = tokens.last().is_some_and(|t| match t.kind {
+            TokenKind::Variable | TokenKind::Number | TokenKind::String | TokenKind::Parameter => {
+                true
+            }
+            TokenKind::Punctuation => matches!(token_text(src, t), ")" | "]"),
+            _ => false,
+        });
+        let after = |n: usize| rest[n..].chars().next();
+        // the length of a sigil followed by a name, as in `:put` or `%if`
+        let word_len = c.len_utf8() + prefix_len(&rest[c.len_utf8()..], is_ident_continue);
+        let (kind, len) = if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        } else if c == '#' {
+            (TokenKind::Comment, rest.find('\n').unwrap_or(rest.len()))
+        } else if rest.starts_with("/*") {
+            (TokenKind::Comment, block_comment_len(rest))
+        } else if c == '"' || c == '\'' {
+            (TokenKind::String, quoted_string_len(rest, c))
+        } else if c == '_' && rest[prefix_len(rest, |c| c == '_')..].starts_with('"') {
+            (TokenKind::String, raw_string_len(rest))
+        } else if c.is_ascii_digit() {
+            (TokenKind::Number, number_len(rest))
+        } else if c == '$' {
+            (
+                TokenKind::Parameter,
+                1 + prefix_len(&rest[1..], |c| is_ident_continue(c) || c == '.'),
+            )
+        } else if is_ident_start(c) {
+            let mut len = prefix_len(rest, is_ident_continue);
+            // field access in variables, as in `a.b`
+            while rest[len..].starts_with('.') && after(len + 1).is_some_and(is_ident_start) {
+                len += 1 + prefix_len(&rest[len + 1..], is_ident_continue);
+            }
+            let kind = if KEYWORDS.contains(&&rest[..len]) {
+                TokenKind::Keyword
+            } else {
+                match rest[len..].trim_start().chars().next() {
+                    Some('(') => TokenKind::Function,
+                    Some('[') => TokenKind::Rule,
+                    _ => TokenKind::Variable,
+                }
+            };
+            (kind, len)
+        } else if (c == '*' || c == '~') && !prev_is_operand && after(1).is_some_and(is_ident_start)
+        {
+            (
+                TokenKind::Relation,
+                1 + prefix_len(&rest[1..], |c| is_ident_continue(c) || c == '.' || c == ':'),
+            )
+        } else if rest.starts_with("::") && after(2).is_some_and(is_ident_start) {
+            (
+                TokenKind::Keyword,
+                2 + prefix_len(&rest[2..], is_ident_continue),
+            )
+        } else if (c == ':'
+            && !src[..pos].ends_with(is_ident_continue)
+            && QUERY_OPTIONS.contains(&&rest[1..word_len]))
+            || (c == '%' && !prev_is_operand && after(1).is_some_and(is_ident_start))
+        {
+            (TokenKind::Keyword, word_len)
+        } else if c == '?' {
+            (TokenKind::Rule, 1)
+        } else if [
+            ":=", "<-", "<~", "->", "=>", "||", "&&", "++", "==", "!=", ">=", "<=",
+        ]
+        .iter()
+        .any(|op| rest.starts_with(op))
+        {
+            (TokenKind::Operator, 2)
+        } else if "+-*/%^~<>!=@|".contains(c) {
+            (TokenKind::Operator, 1)
+        } else if "()[]{},;:.".contains(c) {
+            (TokenKind::Punctuation, 1)
+        } else {
+            (TokenKind::Unknown, c.len_utf8())
+        };
+        tokens.push(Token {
+            kind,
+            span: SourceSpan(pos, len),
+        });
+        pos += len;
+    }
+    tokens
+}
+
+fn block_comment_len(s: &str) -> usize {
+    let mut depth = 0;
+    let mut pos = 0;
+    while pos < s.len() {
+        if s[pos..].starts_with("/*") {
+            depth += 1;
+            pos += 2;
+        } else if s[pos..].starts_with("*/") {
+            depth -= 1;
+            pos += 2;
+            if depth == 0 {
+                return pos;
+            }
+        } else {
+            pos += s[pos..].chars().next().unwrap().len_utf8();
+        }
+    }
+    s.len()
+}
+
+fn quoted_string_len(s: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return i + 1;
+        }
+    }
+    s.len()
+}
+
+fn raw_string_len(s: &str) -> usize {
+    let n_underscores = prefix_len(s, |c| c == '_');
+    let closing = format!("\"{}", &s[..n_underscores]);
+    match s[n_underscores + 1..].find(&closing) {
+        Some(i) => n_underscores + 1 + i + closing.len(),
+        None => s.len(),
+    }
+}
+
+fn number_len(s: &str) -> usize {
+    let is_hex = s.starts_with("0x");
+    let mut prev = '0';
+    for (i, c) in s.char_indices() {
+        let in_number = is_ident_continue(c)
+            || (c == '.' && !s[i + 1..].starts_with('.'))
+            || ((c == '+' || c == '-') && (prev == 'e' || prev == 'E') && !is_hex);
+        if !in_number {
+            return i;
+        }
+        prev = c;
+    }
+    s.len()
+}
+
+/// A part of the script that a [ScriptDiagnostic] points to.
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct DiagnosticLabel {
+    /// The position in the script, in bytes.
+    pub span: SourceSpan,
+    /// What is wrong there, if more specific than the message of the diagnostic.
+    pub message: Option<String>,
+}
+
+/// A problem found in a script, see [crate::Db::diagnostics].
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct ScriptDiagnostic {
+    /// The code identifying the kind of the problem, e.g. `parser::pest`.
+    pub code: Option<String>,
+    /// The description of the problem.
+    pub message: String,
+    /// How to fix the problem, if known.
+    pub help: Option<String>,
+    /// The parts of the script involved.
+    pub labels: Vec<DiagnosticLabel>,
+}
+
+impl From<&Report> for ScriptDiagnostic {
+    fn from(err: &Report) -> Self {
+        Self {
+            code: err.code().map(|c| c.to_string()),
+            message: err.to_string(),
+            help: err.help().map(|h| h.to_string()),
+            labels: err
+                .labels()
+                .map(|labels| {
+                    labels
+                        .map(|l| DiagnosticLabel {
+                            span: SourceSpan(l.offset(), l.len()),
+                            message: l.label().map(|s| s.to_string()),
+                        })
+                        .collect_vec()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// The kind of a [Completion].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub enum CompletionKind {
+    /// A stored relation, an index or a virtual relation.
+    Relation,
+    /// A column of the relation being applied.
+    Column,
+    /// A function for expressions.
+    Function,
+    /// An aggregation for rule heads.
+    Aggregation,
+    /// A fixed rule.
+    FixedRule,
+    /// A query option, without the leading `:`.
+    QueryOption,
+}
+
+/// A candidate for completing the script being edited, see [crate::Db::completions].
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct Completion {
+    /// The text to insert, replacing the partial name before the cursor.
+    pub label: String,
+    /// What the text names.
+    pub kind: CompletionKind,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// The problems found when parsing the script passed in, without running it.
+    /// Parameters used by the script must be given, as they are substituted during parsing.
+    ///
+    /// For scripts made of several blocks `{...}`, the problems of each block are reported.
+    pub fn diagnostics(
+        &'s self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+    ) -> Vec<ScriptDiagnostic> {
+        match parse_script(payload, params, &self.get_fixed_rules(), current_validity()) {
+            Ok(_) => vec![],
+            Err(err) => match err.downcast_ref::<MultipleErrors>() {
+                Some(MultipleErrors(errs)) => errs.iter().map(ScriptDiagnostic::from).collect_vec(),
+                None => vec![ScriptDiagnostic::from(&err)],
+            },
+        }
+    }
+
+    /// The names that can be inserted at `offset` (in bytes) in the script being edited,
+    /// which starts with the partial name before `offset`, if any.
+    ///
+    /// Relations are proposed after `*`, columns inside `*relation{...}`, fixed rules after `<~`,
+    /// query options after `:`, and functions and aggregations elsewhere.
+    pub fn completions(&'s self, payload: &str, offset: usize) -> Result<Vec<Completion>> {
+        let mut offset = offset.min(payload.len());
+        while !payload.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let before = &payload[..offset];
+        let tokens = tokenize(before);
+        let text = |t: &Token| token_text(before, t);
+
+        let mut context = tokens.as_slice();
+        let mut prefix = "";
+        if let Some(last) = tokens.last() {
+            if last.span.0 + last.span.1 == offset {
+                match last.kind {
+                    TokenKind::Relation => {
+                        return self.relation_completions(&text(last)[1..]);
+                    }
+                    TokenKind::Keyword if text(last).starts_with("::") => return Ok(vec![]),
+                    TokenKind::Keyword if text(last).starts_with(':') => {
+                        return Ok(option_completions(&text(last)[1..]));
+                    }
+                    TokenKind::Variable
+                    | TokenKind::Function
+                    | TokenKind::Rule
+                    | TokenKind::Keyword => {
+                        prefix = text(last);
+                        context = &tokens[..tokens.len() - 1];
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(prev) = context.last() {
+            if prev.kind == TokenKind::Punctuation
+                && text(prev) == ":"
+                && prev.span.0 + prev.span.1 == offset - prefix.len()
+                && (prev.span.0 == 0 || before[..prev.span.0].ends_with(char::is_whitespace))
+            {
+                return Ok(option_completions(prefix));
+            }
+            if prev.kind == TokenKind::Operator && text(prev) == "<~" {
+                return Ok(self
+                    .get_fixed_rules()
+                    .into_keys()
+                    .filter(|name| name.starts_with(prefix))
+                    .map(|label| Completion {
+                        label,
+                        kind: CompletionKind::FixedRule,
+                    })
+                    .collect_vec());
+            }
+            if prev.kind == TokenKind::Punctuation && matches!(text(prev), "{" | ",") {
+                if let Some(relation) = enclosing_relation(before, context) {
+                    return self.column_completions(&relation[1..], prefix);
+                }
+            }
+        }
+
+        Ok(op_names()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Completion {
+                label: name.to_string(),
+                kind: CompletionKind::Function,
+            })
+            .chain(
+                aggr_names()
+                    .filter(|name| name.starts_with(prefix))
+                    .map(|name| Completion {
+                        label: name.to_string(),
+                        kind: CompletionKind::Aggregation,
+                    }),
+            )
+            .collect_vec())
+    }
+
+    fn relation_completions(&'s self, prefix: &str) -> Result<Vec<Completion>> {
+        let tx = self.transact()?;
+        let listed = self.list_relations(&tx)?;
+        let mut names = listed
+            .rows
+            .iter()
+            .filter(|row| row[2].get_str() != Some("hidden"))
+            .filter_map(|row| row[0].get_str().map(|s| s.to_string()))
+            .collect_vec();
+        names.extend(self.virtual_relations.read().unwrap().keys().cloned());
+        Ok(names
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|label| Completion {
+                label,
+                kind: CompletionKind::Relation,
+            })
+            .collect_vec())
+    }
+
+    fn column_completions(&'s self, relation: &str, prefix: &str) -> Result<Vec<Completion>> {
+        let columns = match self.virtual_relations.read().unwrap().get(relation) {
+            Some(virtual_relation) => virtual_relation.schema(),
+            None => {
+                let tx = self.transact()?;
+                if !tx.relation_exists(relation)? {
+                    return Ok(vec![]);
+                }
+                let handle = tx.get_relation(relation, false)?;
+                handle
+                    .metadata
+                    .keys
+                    .iter()
+                    .chain(handle.metadata.non_keys.iter())
+                    .map(|col| col.name.to_string())
+                    .collect_vec()
+            }
+        };
+        Ok(columns
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|label| Completion {
+                label,
+                kind: CompletionKind::Column,
+            })
+            .collect_vec())
+    }
+}
+
+fn option_completions(prefix: &str) -> Vec<Completion> {
+    QUERY_OPTIONS
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| Completion {
+            label: name.to_string(),
+            kind: CompletionKind::QueryOption,
+        })
+        .collect_vec()
+}
+
+fn token_text<'a>(src: &'a str, token: &Token) -> &'a str {
+    &src[token.span.0..token.span.0 + token.span.1]
+}
+
+/// The relation token applied with the innermost unclosed `{` at the end of `tokens`, if any.
+fn enclosing_relation<'a>(src: &'a str, tokens: &[Token]) -> Option<&'a str> {
+    let text = |t: &Token| token_text(src, t);
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().rev() {
+        if token.kind != TokenKind::Punctuation {
+            continue;
+        }
+        match text(token) {
+            "}" | "]" | ")" => depth += 1,
+            "{" | "[" | "(" if depth > 0 => depth -= 1,
+            "{" => {
+                return match i.checked_sub(1).map(|j| &tokens[j]) {
+                    Some(t) if t.kind == TokenKind::Relation => Some(text(t)),
+                    _ => None,
+                }
+            }
+            "[" | "(" => return None,
+            _ => {}
+        }
+    }
+    None
+}
@@ -0,0 +1,119 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Counters collected by a database instance, rendered in the Prometheus text format.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Upper bounds of the buckets of the query duration histogram, in seconds.
+const DURATION_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1., 5., 10.];
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    pub(crate) queries: AtomicU64,
+    pub(crate) query_errors: AtomicU64,
+    pub(crate) rows_returned: AtomicU64,
+    pub(crate) rows_scanned: AtomicU64,
+    pub(crate) compactions: AtomicU64,
+    pub(crate) hash_join_spills: AtomicU64,
+    compaction_micros: AtomicU64,
+    duration_micros: AtomicU64,
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()],
+}
+
+impl Metrics {
+    pub(crate) fn record_query(&self, secs: f64, rows: Option<usize>) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        match rows {
+            None => {
+                self.query_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(n) => {
+                self.rows_returned.fetch_add(n as u64, Ordering::Relaxed);
+            }
+        }
+        self.duration_micros
+            .fetch_add((secs * 1e6) as u64, Ordering::Relaxed);
+        if let Some(i) = DURATION_BUCKETS.iter().position(|b| secs <= *b) {
+            self.duration_buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    pub(crate) fn record_compaction(&self, secs: f64) {
+        self.compactions.fetch_add(1, Ordering::Relaxed);
+        self.compaction_micros
+            .fetch_add((secs * 1e6) as u64, Ordering::Relaxed);
+    }
+    /// Render the metrics in the Prometheus text exposition format.
+    /// `extra` contains additional counters as `(name, help, value)`.
+    pub(crate) fn render(&self, extra: &[(&str, &str, u64)]) -> String {
+        let mut ret = String::new();
+        let mut counter = |name: &str, help: &str, val: String| {
+            let _ = writeln!(ret, "# HELP {name} {help}");
+            let _ = writeln!(ret, "# TYPE {name} counter");
+            let _ = writeln!(ret, "{name} {val}");
+        };
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        counter(
+            "cozo_queries_total",
+            "Number of scripts run.",
+            get(&self.queries).to_string(),
+        );
+        counter(
+            "cozo_query_errors_total",
+            "Number of scripts that returned an error.",
+            get(&self.query_errors).to_string(),
+        );
+        counter(
+            "cozo_rows_returned_total",
+            "Number of rows returned by scripts.",
+            get(&self.rows_returned).to_string(),
+        );
+        counter(
+            "cozo_rows_scanned_total",
+            "Number of rows read from stored relations.",
+            get(&self.rows_scanned).to_string(),
+        );
+        counter(
+            "cozo_compactions_total",
+            "Number of compactions.",
+            get(&self.compactions).to_string(),
+        );
+        counter(
+            "cozo_compaction_seconds_total",
+            "Time spent in compactions.",
+            (get(&self.compaction_micros) as f64 / 1e6).to_string(),
+        );
+        counter(
+            "cozo_hash_join_spills_total",
+            "Number of hash joins that spilled to disk.",
+            get(&self.hash_join_spills).to_string(),
+        );
+        for (name, help, val) in extra {
+            counter(name, help, val.to_string());
+        }
+
+        let name = "cozo_query_duration_seconds";
+        let _ = writeln!(ret, "# HELP {name} Time taken by scripts.");
+        let _ = writeln!(ret, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS.iter().zip(self.duration_buckets.iter()) {
+            cumulative += get(count);
+            let _ = writeln!(ret, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let total = get(&self.queries);
+        let _ = writeln!(ret, "{name}_bucket{{le=\"+Inf\"}} {total}");
+        let _ = writeln!(
+            ret,
+            "{name}_sum {}",
+            get(&self.duration_micros) as f64 / 1e6
+        );
+        let _ = writeln!(ret, "{name}_count {total}");
+        ret
+    }
+}
@@ -0,0 +1,220 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Schema migrations for embedding applications: each migration is a named script that is
+//! applied at most once, and recorded with a checksum of its text in a stored relation.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::{seconds_since_the_epoch, Db};
+use crate::runtime::relation::{AccessLevel, InputRelationHandle, RelationHandle};
+use crate::runtime::transact::SessionTx;
+use crate::{NamedRows, ScriptMutability, Storage};
+
+/// Name of the stored relation recording the applied migrations.
+pub(crate) const MIGRATIONS_RELATION: &str = "migrations";
+
+/// A named script to be applied once by [Db::migrate].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    /// Identifies the migration among those already applied, must never change
+    pub name: String,
+    /// The CozoScript to run. It must not change after the migration has been applied,
+    /// as its checksum is verified on each call to [Db::migrate].
+    pub script: String,
+}
+
+impl Migration {
+    /// Create a migration from its name and script.
+    pub fn new(name: impl Into<String>, script: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            script: script.into(),
+        }
+    }
+    fn checksum(&self) -> String {
+        format!("{:x}", Sha256::digest(self.script.as_bytes()))
+    }
+}
+
+fn migrations_metadata() -> StoredRelationMetadata {
+    let col = |name: &str, coltype| ColumnDef {
+        name: name.into(),
+        typing: NullableColType {
+            coltype,
+            nullable: false,
+        },
+        default_gen: None,
+    };
+    StoredRelationMetadata {
+        keys: vec![col("name", ColType::String)],
+        non_keys: vec![
+            col("checksum", ColType::String),
+            col("applied", ColType::Float),
+        ],
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' exists but does not record migrations")]
+#[diagnostic(code(tx::not_migrations))]
+#[diagnostic(help("The relation '{0}' is reserved for migrations, rename the existing one"))]
+struct NotMigrationsError(&'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Migration '{0}' has changed since it was applied")]
+#[diagnostic(code(tx::migration_checksum_mismatch))]
+#[diagnostic(help(
+    "Applied migrations must not be edited, add a new migration with the changes instead"
+))]
+struct MigrationChecksumMismatch(String);
+
+impl<'a> SessionTx<'a> {
+    fn migrations_relation(&self) -> Result<Option<RelationHandle>> {
+        if !self.relation_exists(MIGRATIONS_RELATION)? {
+            return Ok(None);
+        }
+        let handle = self.get_relation(MIGRATIONS_RELATION, false)?;
+        ensure!(
+            handle.metadata == migrations_metadata(),
+            NotMigrationsError(MIGRATIONS_RELATION)
+        );
+        Ok(Some(handle))
+    }
+    /// Checksums of the applied migrations, by name.
+    fn applied_migrations(&self) -> Result<BTreeMap<String, String>> {
+        let Some(handle) = self.migrations_relation()? else {
+            return Ok(BTreeMap::new());
+        };
+        handle
+            .scan_all(self)
+            .map_ok(|tuple| {
+                (
+                    tuple[0].get_str().unwrap().to_string(),
+                    tuple[1].get_str().unwrap().to_string(),
+                )
+            })
+            .try_collect()
+    }
+    fn record_migration(&mut self, migration: &Migration) -> Result<()> {
+        let span = SourceSpan(0, 0);
+        let handle = match self.migrations_relation()? {
+            Some(handle) => handle,
+            None => {
+                let name = Symbol::new(MIGRATIONS_RELATION, span);
+                let handle = self.create_relation(InputRelationHandle {
+                    name: name.clone(),
+                    metadata: migrations_metadata(),
+                    key_bindings: vec![],
+                    dep_bindings: vec![],
+                    span,
+                })?;
+                // migrations are only recorded through `Db::migrate`
+                self.set_access_level(&name, AccessLevel::ReadOnly)?;
+                handle
+            }
+        };
+        let tuple = vec![
+            DataValue::from(migration.name.as_str()),
+            DataValue::from(migration.checksum()),
+            DataValue::from(seconds_since_the_epoch()?),
+        ];
+        let key = handle.encode_key_for_store(&tuple, span)?;
+        let val = handle.encode_val_for_store(&tuple, span)?;
+        self.store_tx.put(&key, &val)?;
+        Ok(())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Apply the migrations that have not been applied yet, in order.
+    ///
+    /// Each migration runs as one script, and is recorded in the stored relation `migrations`
+    /// once it has succeeded. Before anything is run, the checksums of the migrations already
+    /// applied are verified against their scripts. Returns the name and status of each
+    /// migration, `applied` or `skipped`.
+    ///
+    /// A migration that fails is not recorded, and the migrations after it are not run.
+    pub fn migrate(&'s self, migrations: &[Migration]) -> Result<NamedRows> {
+        let statuses = self.migration_statuses(migrations)?;
+        let mut rows = Vec::with_capacity(migrations.len());
+        for (migration, applied) in migrations.iter().zip(statuses) {
+            if !applied {
+                self.run_script(
+                    &migration.script,
+                    Default::default(),
+                    ScriptMutability::Mutable,
+                )
+                .map_err(|err| err.wrap_err(format!("Migration '{}' failed", migration.name)))?;
+                let mut tx = self.transact_write()?;
+                tx.record_migration(migration)?;
+                tx.commit_tx()?;
+            }
+            let status = if applied { "skipped" } else { "applied" };
+            rows.push(vec![
+                DataValue::from(migration.name.as_str()),
+                DataValue::from(status),
+            ]);
+        }
+        Ok(NamedRows::new(
+            vec!["name".to_string(), "status".to_string()],
+            rows,
+        ))
+    }
+    /// Verify the migrations as [Db::migrate] does, without running any of them.
+    /// Returns the name and status of each migration, `applied` or `pending`.
+    pub fn migrate_dry_run(&'s self, migrations: &[Migration]) -> Result<NamedRows> {
+        let statuses = self.migration_statuses(migrations)?;
+        let rows = migrations
+            .iter()
+            .zip(statuses)
+            .map(|(migration, applied)| {
+                vec![
+                    DataValue::from(migration.name.as_str()),
+                    DataValue::from(if applied { "applied" } else { "pending" }),
+                ]
+            })
+            .collect_vec();
+        Ok(NamedRows::new(
+            vec!["name".to_string(), "status".to_string()],
+            rows,
+        ))
+    }
+    /// Whether each migration has been applied, after verifying their checksums.
+    fn migration_statuses(&'s self, migrations: &[Migration]) -> Result<Vec<bool>> {
+        let mut seen = BTreeSet::new();
+        for migration in migrations {
+            if !seen.insert(&migration.name) {
+                bail!("Migration '{}' is given more than once", migration.name)
+            }
+        }
+        let applied = self.transact()?.applied_migrations()?;
+        migrations
+            .iter()
+            .map(|migration| match applied.get(&migration.name) {
+                None => Ok(false),
+                Some(checksum) => {
+                    ensure!(
+                        *checksum == migration.checksum(),
+                        MigrationChecksumMismatch(migration.name.clone())
+                    );
+                    Ok(true)
+                }
+            })
+            .try_collect()
+    }
+}
@@ -12,6 +12,7 @@ use crate::data::expr::{eval_bytecode, eval_bytecode_pred, Bytecode};
 use crate::data::tuple::Tuple;
 use crate::fts::tokenizer::TextAnalyzer;
 use crate::fts::TokenizerConfig;
+use crate::parse::sys::LshShingling;
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, Expr, SourceSpan, Symbol};
@@ -96,10 +97,7 @@ impl<'a> SessionTx<'a> {
         let min_hash = match to_index {
             DataValue::Null => return Ok(()),
             DataValue::List(l) => HashValues::new(l.iter(), hash_perms),
-            DataValue::Str(s) => {
-                let n_grams = tokenizer.unique_ngrams(&s, manifest.n_gram);
-                HashValues::new(n_grams.iter(), hash_perms)
-            }
+            DataValue::Str(s) => manifest.hash_text(&s, tokenizer, hash_perms),
             _ => bail!("Cannot put value {:?} into a LSH index", to_index),
         };
         let bytes = min_hash.get_bytes();
@@ -149,16 +147,18 @@ impl<'a> SessionTx<'a> {
                 return Ok(vec![]);
             }
             DataValue::List(l) => HashValues::new(l.iter(), perms).get_bytes().to_vec(),
-            DataValue::Str(s) => {
-                let n_grams = tokenizer.unique_ngrams(s, config.manifest.n_gram);
-                HashValues::new(n_grams.iter(), perms).get_bytes().to_vec()
-            }
+            DataValue::Str(s) => config
+                .manifest
+                .hash_text(s, tokenizer, perms)
+                .get_bytes()
+                .to_vec(),
             _ => bail!("Cannot search for value {:?} in a LSH index", q),
         };
         let chunk_size = config.manifest.n_rows_in_band * std::mem::size_of::<u32>();
         let mut key_prefix = Vec::with_capacity(1);
         let mut found_tuples: FxHashSet<_> = FxHashSet::default();
-        let early_stopper = if filter_code.is_some() {
+        // the most similar candidates are only known after looking at all of them
+        let early_stopper = if filter_code.is_some() || config.bind_similarity.is_some() {
             None
         } else {
             config.k
@@ -179,12 +179,22 @@ impl<'a> SessionTx<'a> {
                 }
             }
         }
+        let mut candidates = found_tuples.into_iter().map(|key| (key, None)).collect_vec();
+        if config.bind_similarity.is_some() {
+            for (key, similarity) in candidates.iter_mut() {
+                *similarity = Some(self.lsh_similarity(&bytes, key, config)?);
+            }
+            candidates.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+        }
         let mut ret = vec![];
-        for key in found_tuples {
-            let orig_tuple = config
+        for (key, similarity) in candidates {
+            let mut orig_tuple = config
                 .base_handle
                 .get(self, &key)?
                 .ok_or_else(|| miette!("Tuple not found in base LSH relation"))?;
+            if let Some(similarity) = similarity {
+                orig_tuple.push(DataValue::from(similarity));
+            }
             if let Some((filter_code, span)) = filter_code {
                 if !eval_bytecode_pred(filter_code, &orig_tuple, stack, *span)? {
                     continue;
@@ -199,6 +209,28 @@ impl<'a> SessionTx<'a> {
         }
         Ok(ret)
     }
+    /// The Jaccard similarity estimated from the hash values of the query and of a stored
+    /// item, whose hash values are kept in the inverse index as the chunks of the bands.
+    fn lsh_similarity(&self, query: &[u8], key: &[DataValue], config: &LshSearch) -> Result<f64> {
+        let inv_name = format!("{}:inv", config.idx_handle.name);
+        let inv_handle = self.get_relation(&inv_name, false)?;
+        let chunks = match inv_handle.get_val_only(self, key)?.and_then(|mut v| v.pop()) {
+            Some(DataValue::List(l)) => l,
+            _ => bail!("Tuple not found in inverse LSH index"),
+        };
+        let chunk_size = config.manifest.n_rows_in_band * std::mem::size_of::<u32>();
+        let mut matches = 0;
+        for (chunk, query_chunk) in chunks.iter().zip(query.chunks_exact(chunk_size)) {
+            if let DataValue::Bytes(chunk) = chunk {
+                matches += chunk[..chunk_size]
+                    .chunks_exact(std::mem::size_of::<u32>())
+                    .zip(query_chunk.chunks_exact(std::mem::size_of::<u32>()))
+                    .filter(|(a, b)| a == b)
+                    .count();
+            }
+        }
+        Ok(matches as f64 / config.manifest.num_perm as f64)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -210,12 +242,14 @@ pub(crate) struct LshSearch {
     pub(crate) k: Option<usize>,
     pub(crate) query: Symbol,
     pub(crate) filter: Option<Expr>,
+    /// Bound to the estimated Jaccard similarity of the found item with the query
+    pub(crate) bind_similarity: Option<Symbol>,
     pub(crate) span: SourceSpan,
 }
 
 impl LshSearch {
     pub(crate) fn all_bindings(&self) -> impl Iterator<Item = &Symbol> {
-        self.bindings.iter()
+        self.bindings.iter().chain(self.bind_similarity.iter())
     }
 }
 
@@ -228,6 +262,8 @@ pub(crate) struct MinHashLshIndexManifest {
     pub(crate) index_name: SmartString<LazyCompact>,
     pub(crate) extractor: String,
     pub(crate) n_gram: usize,
+    #[serde(default)]
+    pub(crate) shingling: LshShingling,
     pub(crate) tokenizer: TokenizerConfig,
     pub(crate) filters: Vec<TokenizerConfig>,
 
@@ -242,6 +278,21 @@ impl MinHashLshIndexManifest {
     pub(crate) fn get_hash_perms(&self) -> HashPermutations {
         HashPermutations::from_bytes(&self.perms)
     }
+    fn hash_text(
+        &self,
+        text: &str,
+        tokenizer: &TextAnalyzer,
+        perms: &HashPermutations,
+    ) -> HashValues {
+        match self.shingling {
+            LshShingling::Word => {
+                HashValues::new(tokenizer.unique_ngrams(text, self.n_gram).iter(), perms)
+            }
+            LshShingling::Char => {
+                HashValues::new(tokenizer.unique_char_ngrams(text, self.n_gram).iter(), perms)
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
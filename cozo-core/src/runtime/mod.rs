@@ -6,13 +6,43 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+#[cfg(feature = "arrow")]
+pub(crate) mod arrow;
+#[cfg(feature = "compressed-backup")]
+pub(crate) mod backup;
 pub(crate) mod callback;
+pub(crate) mod cdc;
+pub(crate) mod cron;
+pub(crate) mod datomic;
 pub(crate) mod db;
+pub(crate) mod generated;
+pub(crate) mod idgen;
 pub(crate) mod imperative;
+pub(crate) mod introspection;
+pub(crate) mod lsp;
+pub(crate) mod metrics;
+pub(crate) mod migration;
+pub(crate) mod module;
+pub(crate) mod procedure;
+pub(crate) mod outbox;
+pub(crate) mod partition;
+pub(crate) mod plan;
+pub(crate) mod pool;
+pub(crate) mod quantization;
+pub(crate) mod query_cache;
 pub(crate) mod relation;
+pub(crate) mod retention;
+pub(crate) mod sandbox;
+pub(crate) mod scheduler;
+pub(crate) mod signature;
+pub(crate) mod spans;
 pub(crate) mod temp_store;
 pub(crate) mod transact;
+pub(crate) mod tx_time;
+pub(crate) mod verify;
+pub(crate) mod virtual_relation;
 pub(crate) mod hnsw;
+pub(crate) mod geo_index;
 pub(crate) mod minhash_lsh;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,174 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Modules: named sets of rules kept in the catalog with `::module save`, and brought
+//! into the scope of queries with `use`, so that common rules need not be repeated.
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use itertools::Itertools;
+use miette::{bail, miette, Diagnostic, Result};
+use pest::Parser;
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::program::InputInlineRulesOrFixed;
+use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::parse::query::parse_query;
+use crate::parse::{CozoScriptParser, Pair, Rule, SourceSpan};
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+use crate::{FixedRule, NamedRows};
+
+/// Rules saved under a name, to be used by queries.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct StoredModule {
+    pub(crate) name: SmartString<LazyCompact>,
+    /// The source of the rules, as given to `::module save`.
+    pub(crate) rules: String,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Module {0} not found")]
+#[diagnostic(code(tx::module_not_found))]
+struct ModuleNotFound(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Rule {0} of module {1} conflicts with a rule of the same name")]
+#[diagnostic(code(eval::module_rule_conflict))]
+#[diagnostic(help("Rename the rule defined in the query, or use only one of the modules"))]
+struct ModuleRuleConflict(String, String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Modules cannot have an entry rule")]
+#[diagnostic(code(parser::entry_in_module))]
+#[diagnostic(help("Modules define rules for queries to use, the queries define their own entry"))]
+struct EntryInModule(#[label] SourceSpan);
+
+fn module_key(name: &str) -> Vec<u8> {
+    let tuple = vec![
+        DataValue::Null,
+        DataValue::from("MODULE"),
+        DataValue::from(name),
+    ];
+    tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+/// Parse the rules of a module, given as the `module_body` rule of the grammar.
+/// Parameters are not available to modules.
+pub(crate) fn parse_module_body(
+    body: Pair<'_>,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+) -> Result<BTreeMap<Symbol, InputInlineRulesOrFixed>> {
+    let prog = parse_query(
+        body.into_inner(),
+        &Default::default(),
+        fixed_rules,
+        current_validity(),
+    )?;
+    if let Some((_, rule)) = prog.prog.iter().find(|(name, _)| name.is_prog_entry()) {
+        let span = match rule {
+            InputInlineRulesOrFixed::Rules { rules } => rules[0].span,
+            InputInlineRulesOrFixed::Fixed { fixed } => fixed.span,
+        };
+        bail!(EntryInModule(span))
+    }
+    Ok(prog.prog)
+}
+
+impl<'a> SessionTx<'a> {
+    pub(crate) fn save_module(&mut self, module: &StoredModule) -> Result<()> {
+        let mut val = vec![];
+        module
+            .serialize(&mut Serializer::new(&mut val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&module_key(&module.name), &val)?;
+        Ok(())
+    }
+    pub(crate) fn remove_module(&mut self, name: &Symbol) -> Result<()> {
+        let key = module_key(&name.name);
+        if !self.store_tx.exists(&key, false)? {
+            bail!(ModuleNotFound(name.name.to_string(), name.span))
+        }
+        self.store_tx.del(&key)?;
+        Ok(())
+    }
+    fn module(&self, name: &Symbol) -> Result<StoredModule> {
+        match self.store_tx.get(&module_key(&name.name), false)? {
+            None => bail!(ModuleNotFound(name.name.to_string(), name.span)),
+            Some(v_slice) => {
+                rmp_serde::from_slice(&v_slice).map_err(|e| miette!("Cannot decode module: {e}"))
+            }
+        }
+    }
+    pub(crate) fn modules(&self) -> Result<Vec<StoredModule>> {
+        let lower = module_key("");
+        let upper = module_key(&String::from(LARGEST_UTF_CHAR));
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            ret.push(
+                rmp_serde::from_slice(&v_slice)
+                    .map_err(|e| miette!("Cannot decode module: {e}"))?,
+            );
+        }
+        Ok(ret)
+    }
+    /// Add the rules of the modules used to those of a program.
+    /// A rule of a module cannot have the name of a rule of the program or of another module.
+    pub(crate) fn import_modules(
+        &self,
+        prog: &mut BTreeMap<Symbol, InputInlineRulesOrFixed>,
+        uses: &[Symbol],
+    ) -> Result<()> {
+        let fixed_rules = self.fixed_rules.read().unwrap().clone();
+        for module_name in uses {
+            let module = self.module(module_name)?;
+            let body = CozoScriptParser::parse(Rule::module_body, &module.rules)
+                .map_err(|e| miette!("Cannot parse module {}: {e}", module.name))?
+                .next()
+                .unwrap();
+            for (name, rules) in parse_module_body(body, &fixed_rules)? {
+                match prog.entry(name) {
+                    Entry::Vacant(e) => {
+                        e.insert(rules);
+                    }
+                    Entry::Occupied(e) => bail!(ModuleRuleConflict(
+                        e.key().name.to_string(),
+                        module_name.name.to_string(),
+                        module_name.span
+                    )),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The saved modules, with the source of their rules.
+pub(crate) fn list_modules(tx: &SessionTx<'_>) -> Result<NamedRows> {
+    let rows = tx
+        .modules()?
+        .into_iter()
+        .map(|module| vec![DataValue::Str(module.name), DataValue::from(module.rules)])
+        .collect_vec();
+    Ok(NamedRows::new(
+        vec!["name".to_string(), "rules".to_string()],
+        rows,
+    ))
+}
@@ -0,0 +1,147 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The transactional outbox: queries with the `:outbox` option append their rows as events
+//! in the same transaction as the rest of the script, and consumers poll the events
+//! and acknowledge them, with their positions stored durably.
+
+use miette::{bail, miette, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use serde_json::Map;
+
+use crate::data::json::JsonValue;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::{DataValue, JsonData};
+use crate::runtime::db::{seconds_since_the_epoch, Db};
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+use crate::{NamedRows, Storage};
+
+/// Name of the lock taken by queries appending to the outbox. It is not a valid relation name,
+/// so it is never the lock of a relation.
+pub(crate) const OUTBOX_LOCK: &str = "::outbox";
+
+fn outbox_seq_key() -> Vec<u8> {
+    let tuple = vec![DataValue::Null, DataValue::from("OUTBOX_SEQ")];
+    tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+fn outbox_offset_key(consumer: &str) -> Vec<u8> {
+    let tuple = vec![
+        DataValue::Null,
+        DataValue::from("OUTBOX_OFFSET"),
+        DataValue::from(consumer),
+    ];
+    tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+/// Events are kept in the system key range, so that they never clash with user relations.
+fn outbox_event_key(seq: DataValue) -> Vec<u8> {
+    let tuple = vec![DataValue::Null, DataValue::from("OUTBOX_EVENT"), seq];
+    tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+fn decode_seq(found: Option<Vec<u8>>) -> i64 {
+    match found {
+        None => 0,
+        Some(bytes) => i64::from_be_bytes(bytes[..8].try_into().unwrap()),
+    }
+}
+
+fn outbox_headers() -> Vec<String> {
+    vec![
+        "seq".to_string(),
+        "topic".to_string(),
+        "payload".to_string(),
+        "created".to_string(),
+    ]
+}
+
+impl<'a> SessionTx<'a> {
+    /// Append each row as an event of `topic`, with the row as a JSON object keyed by `headers`.
+    /// Returns the sequence numbers assigned to the events.
+    pub(crate) fn append_outbox(
+        &mut self,
+        topic: &str,
+        headers: &[String],
+        rows: Vec<Tuple>,
+    ) -> Result<NamedRows> {
+        let seq_key = outbox_seq_key();
+        let mut seq = decode_seq(self.store_tx.get(&seq_key, true)?);
+        let created = seconds_since_the_epoch()?;
+        let mut ret = Vec::with_capacity(rows.len());
+        for row in rows {
+            seq += 1;
+            let payload: Map<String, JsonValue> = headers
+                .iter()
+                .cloned()
+                .zip(row.into_iter().map(JsonValue::from))
+                .collect();
+            let tuple = vec![
+                DataValue::from(seq),
+                DataValue::from(topic),
+                DataValue::Json(JsonData(JsonValue::Object(payload))),
+                DataValue::from(created),
+            ];
+            let mut val = vec![];
+            tuple.serialize(&mut Serializer::new(&mut val)).unwrap();
+            let key = outbox_event_key(DataValue::from(seq));
+            self.store_tx.put(&key, &val)?;
+            ret.push(vec![DataValue::from(seq)]);
+        }
+        self.store_tx.put(&seq_key, &seq.to_be_bytes())?;
+        Ok(NamedRows::new(vec!["seq".to_string()], ret))
+    }
+    /// Up to `n` events after the last one acknowledged by `consumer`.
+    pub(crate) fn poll_outbox(&self, consumer: &str, n: usize) -> Result<NamedRows> {
+        let offset = decode_seq(self.store_tx.get(&outbox_offset_key(consumer), false)?);
+        let lower = outbox_event_key(DataValue::from(offset + 1));
+        let upper = outbox_event_key(DataValue::Bot);
+        let mut rows = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper).take(n) {
+            let (_, v_slice) = kv_res?;
+            let tuple: Tuple = rmp_serde::from_slice(&v_slice)
+                .map_err(|e| miette!("Cannot decode outbox event: {e}"))?;
+            rows.push(tuple);
+        }
+        Ok(NamedRows::new(outbox_headers(), rows))
+    }
+    /// Record that `consumer` has processed every event up to and including `seq`.
+    /// Acknowledging an event that is already acknowledged does nothing.
+    pub(crate) fn ack_outbox(&mut self, consumer: &str, seq: i64) -> Result<()> {
+        let last = decode_seq(self.store_tx.get(&outbox_seq_key(), false)?);
+        if seq > last {
+            bail!("Cannot acknowledge outbox event {seq}, the last event is {last}")
+        }
+        let key = outbox_offset_key(consumer);
+        if seq > decode_seq(self.store_tx.get(&key, true)?) {
+            self.store_tx.put(&key, &seq.to_be_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Fetch up to `n` events of the outbox that `consumer_id` has not acknowledged yet,
+    /// with columns `seq`, `topic`, `payload` and `created`.
+    ///
+    /// Events are appended by queries with the `:outbox <topic>` option, atomically with
+    /// the rest of the transaction. The same events are returned until they are acknowledged
+    /// with [Db::ack_outbox], so a consumer that publishes and then acknowledges never loses
+    /// an event, and can use `seq` to discard the ones it has already published.
+    pub fn poll_outbox(&'s self, consumer_id: &str, n: usize) -> Result<NamedRows> {
+        self.transact()?.poll_outbox(consumer_id, n)
+    }
+    /// Acknowledge that `consumer_id` has processed all events up to and including `seq`.
+    pub fn ack_outbox(&'s self, consumer_id: &str, seq: i64) -> Result<()> {
+        let mut tx = self.transact_write()?;
+        tx.ack_outbox(consumer_id, seq)?;
+        tx.commit_tx()
+    }
+}
@@ -0,0 +1,142 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Range partitioning of stored relations on their first key column.
+//!
+//! The partitions of a relation are the ranges of its keys between consecutive bounds
+//! declared with `::partition set`. As keys are stored in order, each partition occupies
+//! a distinct range of the key space of the relation. Scans bounded by filters on the
+//! first key column only visit the partitions overlapping the bounds, and scans spanning
+//! several partitions visit them in parallel.
+
+use itertools::Itertools;
+use miette::{ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::relation::RelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot partition relation {0}")]
+#[diagnostic(code(tx::bad_partition))]
+#[diagnostic(help("{1}"))]
+struct BadPartition(String, String, #[label] SourceSpan);
+
+impl RelationHandle {
+    /// The ranges of encoded keys of the partitions that may hold keys within `bounds`,
+    /// in order. `bounds` are the inclusive bounds on the key columns, as computed from
+    /// the filters of a scan.
+    pub(crate) fn partition_ranges(
+        &self,
+        bounds: Option<&(Vec<DataValue>, Vec<DataValue>)>,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let (lower, upper) = match bounds {
+            None => (
+                Tuple::default().encode_as_key(self.id),
+                Tuple::default().encode_as_key(self.id.next()),
+            ),
+            Some((l, u)) => {
+                let mut u = u.clone();
+                u.push(DataValue::Bot);
+                (l.encode_as_key(self.id), u.encode_as_key(self.id))
+            }
+        };
+        let splits = self
+            .partition_bounds
+            .iter()
+            .map(|b| vec![b.clone()].encode_as_key(self.id))
+            .collect_vec();
+        let mut ranges = vec![];
+        let mut start = lower;
+        for split in splits {
+            if split >= upper {
+                break;
+            }
+            if split > start {
+                ranges.push((start, split.clone()));
+                start = split;
+            }
+        }
+        if start < upper {
+            ranges.push((start, upper));
+        }
+        ranges
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// Set the bounds splitting a stored relation into partitions, or remove the partitioning
+    /// if there are none. The bounds are values of the first key column.
+    pub(crate) fn set_partitions(&mut self, rel: &Symbol, bounds: Vec<DataValue>) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        ensure!(
+            !meta.name.contains(':') && !meta.is_temp,
+            BadPartition(
+                meta.name.to_string(),
+                "only stored relations can be partitioned".to_string(),
+                rel.span
+            )
+        );
+        let Some(first_key) = meta.metadata.keys.first() else {
+            return Err(BadPartition(
+                meta.name.to_string(),
+                "relations without keys cannot be partitioned".to_string(),
+                rel.span,
+            )
+            .into());
+        };
+        let cur_vld = current_validity();
+        let mut bounds: Vec<_> = bounds
+            .into_iter()
+            .map(|b| first_key.typing.coerce(b, cur_vld))
+            .try_collect()?;
+        bounds.sort();
+        bounds.dedup();
+        meta.partition_bounds = bounds;
+
+        self.save_relation_meta(&meta)
+    }
+    /// The partitions of a relation, with their bounds and the number of rows in each.
+    pub(crate) fn list_partitions(&self, rel: &Symbol) -> Result<NamedRows> {
+        let meta = self.get_relation(rel, false)?;
+        let ranges = meta.partition_ranges(None);
+        let lowers = [DataValue::Null]
+            .into_iter()
+            .chain(meta.partition_bounds.iter().cloned());
+        let uppers = meta
+            .partition_bounds
+            .iter()
+            .cloned()
+            .chain([DataValue::Null]);
+        let mut rows = vec![];
+        for (i, ((lower, upper), (l_key, u_key))) in lowers.zip(uppers).zip(ranges).enumerate() {
+            let count = self.store_tx.range_count(&l_key, &u_key)?;
+            rows.push(vec![
+                DataValue::from(i as i64),
+                lower,
+                upper,
+                DataValue::from(count as i64),
+            ]);
+        }
+        Ok(NamedRows::new(
+            vec![
+                "partition".to_string(),
+                "lower".to_string(),
+                "upper".to_string(),
+                "rows".to_string(),
+            ],
+            rows,
+        ))
+    }
+}
@@ -0,0 +1,383 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Query plans as data for tools, see [crate::Db::explain].
+//! The rows returned by `::explain` are meant for humans instead.
+
+use itertools::Itertools;
+
+use crate::data::expr::Expr;
+use crate::data::program::MagicFixedRuleRuleArg;
+use crate::data::symb::Symbol;
+use crate::query::compile::{CompiledProgram, CompiledRuleSet};
+use crate::query::ra::{key_bounds, RelAlgebra};
+
+/// The plan of a query, as compiled for evaluation.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct QueryPlan {
+    /// The strata of the query, evaluated in order. The entry rule is in the last one.
+    pub strata: Vec<PlanStratum>,
+}
+
+/// The rules evaluated together, until none of them derives new rows.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct PlanStratum {
+    /// The rules of the stratum, one for each clause of the inline rules.
+    pub rules: Vec<PlanRule>,
+}
+
+/// A clause of an inline rule, or the application of a fixed rule.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct PlanRule {
+    /// The name of the rule, including the adornments added by the magic sets rewriting.
+    pub name: String,
+    /// How the rows of the rule are derived.
+    pub body: PlanRuleBody,
+}
+
+/// How the rows of a rule are derived.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub enum PlanRuleBody {
+    /// A clause of an inline rule.
+    Clause {
+        /// The position of the clause among those of the rule.
+        clause_idx: usize,
+        /// The aggregation applied to each column of the head, if any.
+        aggregations: Vec<Option<String>>,
+        /// The evaluation of the body of the clause.
+        root: PlanNode,
+    },
+    /// The application of a fixed rule.
+    Fixed {
+        /// The name of the fixed rule.
+        rule: String,
+        /// The input relations, rules or stored relations prefixed with `*`.
+        inputs: Vec<String>,
+    },
+}
+
+/// An operation evaluating the body of a clause.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct PlanNode {
+    /// The operation.
+    pub op: PlanOp,
+    /// The variables of the rows produced by the operation, in order.
+    pub bindings: Vec<String>,
+    /// The operations producing the input rows, the left side first for joins.
+    pub children: Vec<PlanNode>,
+}
+
+/// The kind of a [PlanNode].
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub enum PlanOp {
+    /// Constant rows.
+    Constant,
+    /// Reading the rows of a rule.
+    LoadRule {
+        /// The name of the rule.
+        rule: String,
+        /// The filters applied to the rows.
+        filters: Vec<String>,
+    },
+    /// Reading the rows of a stored relation or of one of its indices.
+    LoadStored {
+        /// The name of the relation, or `relation:index` for an index.
+        relation: String,
+        /// The filters applied to the rows.
+        filters: Vec<String>,
+        /// Whether all the rows are read, instead of those with given keys or in a range.
+        full_scan: bool,
+        /// Whether the relation is read as of a validity or transaction time.
+        time_travel: bool,
+    },
+    /// Reading the rows of a virtual relation.
+    LoadVirtual {
+        /// The name of the virtual relation.
+        relation: String,
+        /// The filters applied to the rows.
+        filters: Vec<String>,
+        /// Whether all the rows are read, instead of those with given leading columns.
+        full_scan: bool,
+    },
+    /// Joining the rows of the two children.
+    Join {
+        /// How the join is performed, e.g. `stored_prefix_join` or `hash_join`.
+        join_type: String,
+        /// The variables of the left side joined on.
+        left_keys: Vec<String>,
+        /// The variables of the right side joined on.
+        right_keys: Vec<String>,
+    },
+    /// Keeping the rows of the left child without matching rows in the right child.
+    NegJoin {
+        /// How the join is performed.
+        join_type: String,
+        /// The variables of the left side joined on.
+        left_keys: Vec<String>,
+        /// The variables of the right side joined on.
+        right_keys: Vec<String>,
+    },
+    /// Filtering the rows of the child.
+    Filter {
+        /// The filters.
+        filters: Vec<String>,
+    },
+    /// Binding a variable to the value of an expression for each row of the child.
+    Unify {
+        /// The variable bound.
+        binding: String,
+        /// The expression.
+        expr: String,
+        /// Whether the variable is bound to each element of the value instead.
+        multi: bool,
+    },
+    /// Changing the order of the columns of the rows of the child.
+    Reorder,
+    /// Searching an index for each row of the child.
+    IndexSearch {
+        /// The kind of the index: `hnsw`, `fts`, `lsh` or `geo`.
+        kind: String,
+        /// The name of the index, as `relation:index`.
+        index: String,
+        /// The filter applied to the results, if any.
+        filter: Option<String>,
+    },
+}
+
+impl QueryPlan {
+    /// All the operations of the plan, depth first.
+    pub fn nodes(&self) -> Vec<&PlanNode> {
+        let mut ret = vec![];
+        for rule in self.strata.iter().flat_map(|s| s.rules.iter()) {
+            if let PlanRuleBody::Clause { root, .. } = &rule.body {
+                root.collect_nodes(&mut ret);
+            }
+        }
+        ret
+    }
+    /// The stored and virtual relations whose rows are all read.
+    pub fn full_scans(&self) -> Vec<&str> {
+        self.nodes()
+            .into_iter()
+            .filter_map(|node| match &node.op {
+                PlanOp::LoadStored {
+                    relation,
+                    full_scan: true,
+                    ..
+                }
+                | PlanOp::LoadVirtual {
+                    relation,
+                    full_scan: true,
+                    ..
+                } => Some(relation as &str),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl PlanNode {
+    fn collect_nodes<'a>(&'a self, coll: &mut Vec<&'a PlanNode>) {
+        coll.push(self);
+        for child in &self.children {
+            child.collect_nodes(coll);
+        }
+    }
+}
+
+pub(crate) fn query_plan(strata: &[CompiledProgram]) -> QueryPlan {
+    let strata = strata
+        .iter()
+        .map(|prog| {
+            let mut rules = vec![];
+            for (name, rule_set) in prog {
+                match rule_set {
+                    CompiledRuleSet::Rules(clauses) => {
+                        for (clause_idx, clause) in clauses.iter().enumerate() {
+                            rules.push(PlanRule {
+                                name: name.to_string(),
+                                body: PlanRuleBody::Clause {
+                                    clause_idx,
+                                    aggregations: clause
+                                        .aggr
+                                        .iter()
+                                        .map(|a| a.as_ref().map(|(aggr, _)| aggr.name.to_string()))
+                                        .collect(),
+                                    root: plan_node(&clause.relation, false),
+                                },
+                            })
+                        }
+                    }
+                    CompiledRuleSet::Fixed(fixed) => rules.push(PlanRule {
+                        name: name.to_string(),
+                        body: PlanRuleBody::Fixed {
+                            rule: fixed.fixed_handle.name.to_string(),
+                            inputs: fixed
+                                .rule_args
+                                .iter()
+                                .map(|arg| match arg {
+                                    MagicFixedRuleRuleArg::InMem { name, .. } => name.to_string(),
+                                    MagicFixedRuleRuleArg::Stored { name, .. } => {
+                                        format!("*{name}")
+                                    }
+                                })
+                                .collect(),
+                        },
+                    }),
+                }
+            }
+            PlanStratum { rules }
+        })
+        .collect();
+    QueryPlan { strata }
+}
+
+fn names(symbols: &[Symbol]) -> Vec<String> {
+    symbols.iter().map(|s| s.to_string()).collect()
+}
+
+fn exprs(filters: &[Expr]) -> Vec<String> {
+    filters.iter().map(|f| f.to_string()).collect()
+}
+
+/// The plan of `rel`, which is `looked_up` if it is the right side of a prefix join,
+/// in which case only the rows matching those of the left side are read.
+fn plan_node(rel: &RelAlgebra, looked_up: bool) -> PlanNode {
+    let (op, children) = match rel {
+        RelAlgebra::Fixed(_) => (PlanOp::Constant, vec![]),
+        RelAlgebra::TempStore(r) => (
+            PlanOp::LoadRule {
+                rule: r.storage_key.to_string(),
+                filters: exprs(&r.filters),
+            },
+            vec![],
+        ),
+        RelAlgebra::Stored(r) => {
+            let key_bindings = &r.bindings[..r.storage.metadata.keys.len()];
+            (
+                PlanOp::LoadStored {
+                    relation: match &r.expr_index {
+                        Some(scan) => scan.index.name.to_string(),
+                        None => r.storage.name.to_string(),
+                    },
+                    filters: exprs(&r.filters),
+                    full_scan: !looked_up
+                        && r.expr_index.is_none()
+                        && key_bounds(&r.filters, key_bindings).is_none(),
+                    time_travel: false,
+                },
+                vec![],
+            )
+        }
+        RelAlgebra::StoredWithValidity(r) => {
+            let key_bindings = &r.bindings[..r.storage.metadata.keys.len()];
+            (
+                PlanOp::LoadStored {
+                    relation: r.storage.name.to_string(),
+                    filters: exprs(&r.filters),
+                    full_scan: !looked_up
+                        && (r.history_valid_at.is_some()
+                            || key_bounds(&r.filters, key_bindings).is_none()),
+                    time_travel: true,
+                },
+                vec![],
+            )
+        }
+        RelAlgebra::Virtual(r) => (
+            PlanOp::LoadVirtual {
+                relation: r.name.to_string(),
+                filters: exprs(&r.filters),
+                full_scan: !looked_up,
+            },
+            vec![],
+        ),
+        RelAlgebra::Join(inner) => {
+            if inner.left.is_unit() {
+                return plan_node(&inner.right, looked_up);
+            }
+            let join_type = inner.join_type();
+            (
+                PlanOp::Join {
+                    join_type: join_type.to_string(),
+                    left_keys: names(&inner.joiner.left_keys),
+                    right_keys: names(&inner.joiner.right_keys),
+                },
+                vec![
+                    plan_node(&inner.left, false),
+                    plan_node(&inner.right, join_type.ends_with("prefix_join")),
+                ],
+            )
+        }
+        RelAlgebra::NegJoin(inner) => {
+            let join_type = inner.join_type();
+            (
+                PlanOp::NegJoin {
+                    join_type: join_type.to_string(),
+                    left_keys: names(&inner.joiner.left_keys),
+                    right_keys: names(&inner.joiner.right_keys),
+                },
+                vec![
+                    plan_node(&inner.left, false),
+                    plan_node(&inner.right, join_type.ends_with("prefix_join")),
+                ],
+            )
+        }
+        RelAlgebra::Reorder(r) => (PlanOp::Reorder, vec![plan_node(&r.relation, false)]),
+        RelAlgebra::Filter(r) => (
+            PlanOp::Filter {
+                filters: exprs(&r.filters),
+            },
+            vec![plan_node(&r.parent, false)],
+        ),
+        RelAlgebra::Unification(r) => (
+            PlanOp::Unify {
+                binding: r.binding.to_string(),
+                expr: r.expr.to_string(),
+                multi: r.is_multi,
+            },
+            vec![plan_node(&r.parent, false)],
+        ),
+        RelAlgebra::HnswSearch(r) => (
+            index_search(
+                "hnsw",
+                &r.hnsw_search.idx_handle.name,
+                &r.hnsw_search.filter,
+            ),
+            vec![plan_node(&r.parent, false)],
+        ),
+        RelAlgebra::FtsSearch(r) => (
+            index_search("fts", &r.fts_search.idx_handle.name, &r.fts_search.filter),
+            vec![plan_node(&r.parent, false)],
+        ),
+        RelAlgebra::LshSearch(r) => (
+            index_search("lsh", &r.lsh_search.idx_handle.name, &r.lsh_search.filter),
+            vec![plan_node(&r.parent, false)],
+        ),
+        RelAlgebra::GeoSearch(r) => (
+            index_search("geo", &r.geo_search.idx_handle.name, &r.geo_search.filter),
+            vec![plan_node(&r.parent, false)],
+        ),
+    };
+    PlanNode {
+        op,
+        bindings: rel
+            .bindings_after_eliminate()
+            .iter()
+            .map(|s| s.to_string())
+            .collect_vec(),
+        children,
+    }
+}
+
+fn index_search(kind: &str, index: &str, filter: &Option<Expr>) -> PlanOp {
+    PlanOp::IndexSearch {
+        kind: kind.to_string(),
+        index: index.to_string(),
+        filter: filter.as_ref().map(|f| f.to_string()),
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+use crate::{DataValue, DbInstance, NamedRows, ScriptMutability};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid tenant name '{0}'")]
+#[diagnostic(code(pool::invalid_tenant))]
+#[diagnostic(help(
+    "Tenant names may only contain letters, digits, '_', '-' and '.', and cannot be '.' or '..'"
+))]
+struct InvalidTenantError(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot open database for tenant '{0}': all {1} open databases are in use")]
+#[diagnostic(code(pool::exhausted))]
+#[diagnostic(help("Drop the handles returned by `DbPool::get` or raise the cap"))]
+struct PoolExhaustedError(String, usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database of tenant '{0}' was closed while it was being opened")]
+#[diagnostic(code(pool::closed))]
+struct TenantClosedError(String);
+
+struct OpenDb {
+    db: Arc<DbInstance>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct PoolState {
+    open: BTreeMap<String, OpenDb>,
+    /// Tenants whose databases are being opened, outside the lock. They count towards the cap.
+    opening: BTreeSet<String>,
+    /// Tenants closed while they were being opened: the databases are dropped once opened.
+    closed_while_opening: BTreeSet<String>,
+    /// Tenants whose evicted databases are being dropped, outside the lock.
+    closing: BTreeSet<String>,
+    clock: u64,
+}
+
+/// A pool of databases keyed by tenant, all using the same storage engine and options.
+///
+/// The database of a tenant is opened on first use at `<root>/<tenant>`,
+/// and at most `max_open` databases are kept open: when the cap is reached, the least
+/// recently used database that is not in use is closed to make room. With the `mem`
+/// engine, closing a database discards its data.
+pub struct DbPool {
+    engine: String,
+    root: PathBuf,
+    options: String,
+    max_open: usize,
+    state: Mutex<PoolState>,
+    /// Notified when a database has been opened or dropped outside the lock
+    opened: Condvar,
+}
+
+impl DbPool {
+    /// Create a pool. The arguments `engine` and `options` are as in [DbInstance::new],
+    /// and the root directory is created if it does not exist.
+    pub fn new(
+        engine: &str,
+        root: impl AsRef<Path>,
+        options: &str,
+        max_open: usize,
+    ) -> Result<Self> {
+        ensure!(
+            max_open > 0,
+            "The pool must allow at least one open database"
+        );
+        let root = root.as_ref().to_path_buf();
+        if engine != "mem" {
+            std::fs::create_dir_all(&root).into_diagnostic()?;
+        }
+        Ok(Self {
+            engine: engine.to_string(),
+            root,
+            options: options.to_string(),
+            max_open,
+            state: Default::default(),
+            opened: Condvar::new(),
+        })
+    }
+    /// Get the database of a tenant, opening it if necessary.
+    /// The database is not closed by the pool while the returned handle is alive.
+    pub fn get(&self, tenant: &str) -> Result<Arc<DbInstance>> {
+        let valid = !tenant.is_empty()
+            && tenant != "."
+            && tenant != ".."
+            && tenant
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+        ensure!(valid, InvalidTenantError(tenant.to_string()));
+
+        let mut state = self.state.lock().unwrap();
+        // another caller opening or dropping the same database holds its files, so wait for it
+        while state.opening.contains(tenant) || state.closing.contains(tenant) {
+            state = self.opened.wait(state).unwrap();
+        }
+        state.clock += 1;
+        let now = state.clock;
+        if let Some(found) = state.open.get_mut(tenant) {
+            found.last_used = now;
+            return Ok(found.db.clone());
+        }
+        let mut evicted = None;
+        if state.open.len() + state.opening.len() >= self.max_open {
+            let victim = state
+                .open
+                .iter()
+                .filter(|(_, v)| Arc::strong_count(&v.db) == 1)
+                .min_by_key(|(_, v)| v.last_used)
+                .map(|(k, _)| k.clone());
+            match victim {
+                Some(victim) => {
+                    let db = state.open.remove(&victim);
+                    state.closing.insert(victim.clone());
+                    evicted = Some((victim, db));
+                }
+                None => bail!(PoolExhaustedError(tenant.to_string(), self.max_open)),
+            }
+        }
+        state.opening.insert(tenant.to_string());
+        drop(state);
+        // opening and closing databases can be slow, so it is done without holding the lock
+        if let Some((victim, db)) = evicted {
+            drop(db);
+            self.state.lock().unwrap().closing.remove(&victim);
+            self.opened.notify_all();
+        }
+        let opened = DbInstance::new(&self.engine, self.root.join(tenant), &self.options);
+
+        let mut state = self.state.lock().unwrap();
+        state.opening.remove(tenant);
+        let closed = state.closed_while_opening.remove(tenant);
+        self.opened.notify_all();
+        if closed {
+            drop(state);
+            drop(opened);
+            bail!(TenantClosedError(tenant.to_string()))
+        }
+        let db = Arc::new(opened?);
+        state.open.insert(
+            tenant.to_string(),
+            OpenDb {
+                db: db.clone(),
+                last_used: now,
+            },
+        );
+        Ok(db)
+    }
+    /// Run a script against the database of a tenant. See [DbInstance::run_script].
+    pub fn run_script(
+        &self,
+        tenant: &str,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        self.get(tenant)?.run_script(payload, params, mutability)
+    }
+    /// Close the database of a tenant, returning whether it was open.
+    /// Handles still held by callers keep the database usable until they are dropped.
+    /// If the database is being opened, the pending [DbPool::get] fails instead.
+    pub fn close(&self, tenant: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.opening.contains(tenant) {
+            state.closed_while_opening.insert(tenant.to_string());
+            return true;
+        }
+        let closed = state.open.remove(tenant);
+        drop(state);
+        closed.is_some()
+    }
+    /// The tenants whose databases are currently open, in alphabetical order.
+    pub fn open_tenants(&self) -> Vec<String> {
+        self.state.lock().unwrap().open.keys().cloned().collect()
+    }
+}
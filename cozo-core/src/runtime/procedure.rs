@@ -0,0 +1,216 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Stored procedures: named scripts with parameters kept in the catalog with
+//! `::procedure save`, and run with `::call`, so that clients need not send them each time.
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, ensure, miette, Diagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
+use crate::parse::{parse_script, Pair, Rule, SourceSpan};
+use crate::runtime::db::Db;
+use crate::runtime::relation::{MutationKind, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{NamedRows, Poison, ScriptMutability, Storage};
+
+/// A script saved under a name, run with the values of its parameters given by `::call`.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct StoredProcedure {
+    pub(crate) name: SmartString<LazyCompact>,
+    /// The names of the parameters, without the leading `$`.
+    pub(crate) params: Vec<String>,
+    /// The source of the script, as given to `::procedure save`.
+    pub(crate) body: String,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Procedure {0} not found")]
+#[diagnostic(code(tx::procedure_not_found))]
+struct ProcedureNotFound(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Procedure {0} takes {1} argument(s), but {2} are given")]
+#[diagnostic(code(eval::procedure_arity_mismatch))]
+struct ProcedureArityMismatch(String, usize, usize, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Parameter {0} is not declared by the procedure")]
+#[diagnostic(code(parser::undeclared_procedure_param))]
+#[diagnostic(help("Add the parameter to the list after the name of the procedure"))]
+struct UndeclaredProcedureParam(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Parameter {0} is declared more than once")]
+#[diagnostic(code(parser::duplicate_procedure_param))]
+pub(crate) struct DuplicateProcedureParam(pub(crate) String, #[label] pub(crate) SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Procedures cannot call procedures")]
+#[diagnostic(code(parser::call_in_procedure))]
+struct CallInProcedure(#[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Procedures can only be called at the top level of a script")]
+#[diagnostic(code(eval::nested_procedure_call))]
+#[diagnostic(help("Run `::call` on its own instead of inside imperative scripts or transactions"))]
+pub(crate) struct NestedProcedureCall(#[label] pub(crate) SourceSpan);
+
+fn procedure_key(name: &str) -> Vec<u8> {
+    let tuple = vec![
+        DataValue::Null,
+        DataValue::from("PROCEDURE"),
+        DataValue::from(name),
+    ];
+    tuple.encode_as_key(RelationId::SYSTEM)
+}
+
+/// Check the body of a procedure, given as the `procedure_body` rule of the grammar:
+/// it can only use the parameters declared, and cannot call procedures.
+pub(crate) fn check_procedure_body(body: Pair<'_>, params: &[String]) -> Result<()> {
+    let mut pending = vec![body];
+    while let Some(pair) = pending.pop() {
+        match pair.as_rule() {
+            Rule::param => {
+                let name = pair.as_str().strip_prefix('$').unwrap();
+                if !params.iter().any(|p| p == name) {
+                    let span = pair.as_span();
+                    bail!(UndeclaredProcedureParam(
+                        name.to_string(),
+                        SourceSpan(span.start(), span.end() - span.start())
+                    ))
+                }
+            }
+            Rule::call_op => {
+                let span = pair.as_span();
+                bail!(CallInProcedure(SourceSpan(
+                    span.start(),
+                    span.end() - span.start()
+                )))
+            }
+            _ => pending.extend(pair.into_inner()),
+        }
+    }
+    Ok(())
+}
+
+impl<'a> SessionTx<'a> {
+    pub(crate) fn save_procedure(&mut self, procedure: &StoredProcedure) -> Result<()> {
+        let mut val = vec![];
+        procedure
+            .serialize(&mut Serializer::new(&mut val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&procedure_key(&procedure.name), &val)?;
+        Ok(())
+    }
+    pub(crate) fn remove_procedure(&mut self, name: &Symbol) -> Result<()> {
+        let key = procedure_key(&name.name);
+        if !self.store_tx.exists(&key, false)? {
+            bail!(ProcedureNotFound(name.name.to_string(), name.span))
+        }
+        self.store_tx.del(&key)?;
+        Ok(())
+    }
+    fn procedure(&self, name: &Symbol) -> Result<StoredProcedure> {
+        match self.store_tx.get(&procedure_key(&name.name), false)? {
+            None => bail!(ProcedureNotFound(name.name.to_string(), name.span)),
+            Some(v_slice) => {
+                rmp_serde::from_slice(&v_slice).map_err(|e| miette!("Cannot decode procedure: {e}"))
+            }
+        }
+    }
+    pub(crate) fn procedures(&self) -> Result<Vec<StoredProcedure>> {
+        let lower = procedure_key("");
+        let upper = procedure_key(&String::from(LARGEST_UTF_CHAR));
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            ret.push(
+                rmp_serde::from_slice(&v_slice)
+                    .map_err(|e| miette!("Cannot decode procedure: {e}"))?,
+            );
+        }
+        Ok(ret)
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Run the procedure with the arguments given, as `::call` does.
+    ///
+    /// The procedure runs with the mutability of the calling script, and calling it
+    /// is subject to the mutation guard with [MutationKind::Call].
+    pub(crate) fn call_procedure(
+        &'s self,
+        name: &Symbol,
+        args: Vec<DataValue>,
+        cur_vld: ValidityTs,
+        mutability: ScriptMutability,
+        handle: &Poison,
+    ) -> Result<NamedRows> {
+        self.check_mutation(&name.name, MutationKind::Call)?;
+        let procedure = self.transact()?.procedure(name)?;
+        ensure!(
+            procedure.params.len() == args.len(),
+            ProcedureArityMismatch(
+                name.name.to_string(),
+                procedure.params.len(),
+                args.len(),
+                name.span
+            )
+        );
+        let params: BTreeMap<_, _> = procedure.params.iter().cloned().zip(args).collect();
+        // the errors of the body point to the body, not to the calling script
+        let with_body = |err: miette::Report| {
+            if err.source_code().is_none() {
+                err.with_source_code(format!("{} ", procedure.body))
+            } else {
+                err
+            }
+        };
+        let script = parse_script(&procedure.body, &params, &self.get_fixed_rules(), cur_vld)
+            .map_err(with_body)?;
+        self.run_script_ast_with_handle(script, cur_vld, mutability, handle)
+            .map_err(with_body)
+    }
+}
+
+/// The saved procedures, with their parameters and the source of their bodies.
+pub(crate) fn list_procedures(tx: &SessionTx<'_>) -> Result<NamedRows> {
+    let rows = tx
+        .procedures()?
+        .into_iter()
+        .map(|procedure| {
+            vec![
+                DataValue::Str(procedure.name),
+                DataValue::List(
+                    procedure
+                        .params
+                        .into_iter()
+                        .map(DataValue::from)
+                        .collect_vec(),
+                ),
+                DataValue::from(procedure.body),
+            ]
+        })
+        .collect_vec();
+    Ok(NamedRows::new(
+        vec!["name".to_string(), "params".to_string(), "body".to_string()],
+        rows,
+    ))
+}
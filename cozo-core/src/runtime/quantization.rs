@@ -0,0 +1,167 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Quantization of the vectors of HNSW indices. The graph is traversed using the
+//! compact codes of the vectors, and the results are re-ranked with the full vectors.
+
+use miette::{bail, Result};
+use ndarray::Array1;
+
+use crate::data::relation::VecElementType;
+use crate::data::value::Vector;
+
+/// At most this many centroids are trained for each subspace, so that codes fit in bytes
+const MAX_CENTROIDS: usize = 256;
+/// Training uses at most this many vectors
+pub(crate) const MAX_TRAINING_VECTORS: usize = 10000;
+const TRAINING_ROUNDS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum VectorQuantizer {
+    /// Each component becomes a byte, within the range of the components of its vector
+    Scalar,
+    /// Each subvector becomes the byte numbering the nearest centroid of its subspace
+    Product {
+        sub_dim: usize,
+        n_centroids: usize,
+        /// For each subspace, `n_centroids` subvectors laid out one after the other
+        centroids: Vec<Vec<f32>>,
+    },
+}
+
+fn to_f32(v: &Vector) -> Vec<f32> {
+    match v {
+        Vector::F32(a) => a.to_vec(),
+        Vector::F64(a) => a.iter().map(|x| *x as f32).collect(),
+    }
+}
+
+fn sq_dist(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+impl VectorQuantizer {
+    /// Trains the centroids of product quantization with `n_subspaces` subspaces.
+    pub(crate) fn train_product(
+        vectors: &[Vector],
+        dim: usize,
+        n_subspaces: usize,
+    ) -> Result<Self> {
+        if n_subspaces == 0 || !dim.is_multiple_of(n_subspaces) {
+            bail!(
+                "The number of subspaces {} must divide the dimension {}",
+                n_subspaces,
+                dim
+            );
+        }
+        if vectors.is_empty() {
+            bail!("Product quantization requires existing vectors to train on");
+        }
+        let sub_dim = dim / n_subspaces;
+        let samples: Vec<Vec<f32>> = vectors.iter().map(to_f32).collect();
+        let n_centroids = samples.len().min(MAX_CENTROIDS);
+
+        let mut centroids = Vec::with_capacity(n_subspaces);
+        for s in 0..n_subspaces {
+            let range = s * sub_dim..(s + 1) * sub_dim;
+            let subs: Vec<&[f32]> = samples.iter().map(|v| &v[range.clone()]).collect();
+            centroids.push(k_means(&subs, n_centroids, sub_dim));
+        }
+        Ok(Self::Product {
+            sub_dim,
+            n_centroids,
+            centroids,
+        })
+    }
+    pub(crate) fn encode(&self, v: &Vector) -> Vec<u8> {
+        let v = to_f32(v);
+        match self {
+            VectorQuantizer::Scalar => {
+                let min = v.iter().copied().fold(f32::INFINITY, f32::min);
+                let max = v.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let scale = if max > min { (max - min) / 255. } else { 1. };
+                let mut code = Vec::with_capacity(v.len() + 8);
+                code.extend_from_slice(&min.to_le_bytes());
+                code.extend_from_slice(&scale.to_le_bytes());
+                code.extend(v.iter().map(|x| ((x - min) / scale).round() as u8));
+                code
+            }
+            VectorQuantizer::Product {
+                sub_dim,
+                n_centroids,
+                centroids,
+            } => v
+                .chunks_exact(*sub_dim)
+                .zip(centroids)
+                .map(|(sub, cs)| nearest(sub, cs, *n_centroids, *sub_dim) as u8)
+                .collect(),
+        }
+    }
+    /// The approximation of the vector represented by the code
+    pub(crate) fn decode(&self, code: &[u8], dtype: VecElementType) -> Vector {
+        let v: Vec<f32> = match self {
+            VectorQuantizer::Scalar => {
+                let min = f32::from_le_bytes(code[0..4].try_into().unwrap());
+                let scale = f32::from_le_bytes(code[4..8].try_into().unwrap());
+                code[8..].iter().map(|c| min + *c as f32 * scale).collect()
+            }
+            VectorQuantizer::Product {
+                sub_dim,
+                centroids,
+                ..
+            } => code
+                .iter()
+                .zip(centroids)
+                .flat_map(|(c, cs)| {
+                    let start = *c as usize * sub_dim;
+                    cs[start..start + sub_dim].iter().copied()
+                })
+                .collect(),
+        };
+        match dtype {
+            VecElementType::F32 => Vector::F32(Array1::from(v)),
+            VecElementType::F64 => Vector::F64(v.into_iter().map(|x| x as f64).collect()),
+        }
+    }
+}
+
+fn nearest(sub: &[f32], centroids: &[f32], n_centroids: usize, sub_dim: usize) -> usize {
+    (0..n_centroids)
+        .map(|i| (i, sq_dist(sub, &centroids[i * sub_dim..(i + 1) * sub_dim])))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap()
+        .0
+}
+
+/// Lloyd's algorithm starting from evenly spaced samples
+fn k_means(samples: &[&[f32]], k: usize, sub_dim: usize) -> Vec<f32> {
+    let step = samples.len() / k;
+    let mut centroids: Vec<f32> = (0..k)
+        .flat_map(|i| samples[i * step].iter().copied())
+        .collect();
+    for _ in 0..TRAINING_ROUNDS {
+        let mut sums = vec![0f32; k * sub_dim];
+        let mut counts = vec![0usize; k];
+        for sample in samples {
+            let i = nearest(sample, &centroids, k, sub_dim);
+            counts[i] += 1;
+            for (s, x) in sums[i * sub_dim..(i + 1) * sub_dim].iter_mut().zip(*sample) {
+                *s += x;
+            }
+        }
+        for (i, count) in counts.into_iter().enumerate() {
+            // empty clusters keep their centroids
+            if count > 0 {
+                for j in i * sub_dim..(i + 1) * sub_dim {
+                    centroids[j] = sums[j] / count as f32;
+                }
+            }
+        }
+    }
+    centroids
+}
@@ -0,0 +1,546 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Caching of the results of read-only queries.
+//!
+//! Every committed write transaction bumps the versions of the relations it touched.
+//! A cache entry remembers the relations its query read and the version counter at
+//! the time the query started, and is served only as long as none of those relations
+//! has been written since.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use miette::Result;
+use pest::Parser;
+
+use crate::data::expr::Expr;
+use crate::data::program::{FixedRuleArg, InputAtom, InputInlineRulesOrFixed, InputProgram};
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::fixed_rule::DEFAULT_FIXED_RULES;
+use crate::parse::{CozoScript, CozoScriptParser, Pair, Rule};
+use crate::runtime::virtual_relation::VirtualRelationRegistry;
+use crate::storage::StoreTx;
+use crate::NamedRows;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct CacheKey {
+    tokens: Vec<String>,
+    params: BTreeMap<String, DataValue>,
+}
+
+struct CacheEntry {
+    /// Value of the version counter when the query started
+    seq: u64,
+    /// Prefixes of the relations read by the query
+    reads: BTreeSet<u64>,
+    rows: NamedRows,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    seq: u64,
+    cleared_at: u64,
+    versions: BTreeMap<u64, u64>,
+    entries: BTreeMap<CacheKey, CacheEntry>,
+    ticks: u64,
+}
+
+impl CacheState {
+    fn is_fresh(&self, entry: &CacheEntry) -> bool {
+        self.cleared_at <= entry.seq
+            && entry
+                .reads
+                .iter()
+                .all(|id| !matches!(self.versions.get(id), Some(v) if *v > entry.seq))
+    }
+}
+
+/// Results of read-only queries, kept until a relation they read is written to.
+/// Disabled unless a capacity is set.
+#[derive(Default)]
+pub(crate) struct QueryCache {
+    capacity: AtomicUsize,
+    state: Mutex<CacheState>,
+    pub(crate) hits: AtomicU64,
+    pub(crate) misses: AtomicU64,
+}
+
+/// The relations read by a query, collected while it runs.
+#[derive(Default)]
+pub(crate) struct ReadSet {
+    relations: BTreeSet<u64>,
+    /// Set when the query read something that cannot be attributed to single relations
+    unbounded: bool,
+}
+
+impl QueryCache {
+    /// Writes are only tracked while the cache is enabled, so the entries are dropped
+    /// whenever the capacity changes.
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        if self.capacity.swap(capacity, Ordering::AcqRel) != capacity {
+            self.clear();
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity.load(Ordering::Acquire) > 0
+    }
+
+    /// The key under which the result of the script is cached, or `None` if
+    /// the cache is disabled or the result cannot be cached.
+    pub(crate) fn key_for(
+        &self,
+        payload: &str,
+        params: &BTreeMap<String, DataValue>,
+        script: &CozoScript,
+        virtual_relations: &VirtualRelationRegistry,
+        cur_vld: ValidityTs,
+    ) -> Option<CacheKey> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let CozoScript::Single(p) = script else {
+            return None;
+        };
+        if p.out_opts.store_relation.is_some() || p.out_opts.outbox.is_some() {
+            return None;
+        }
+        // the rules of modules may change without the script changing
+        if !p.uses.is_empty() {
+            return None;
+        }
+        if !p.is_deterministic(cur_vld) {
+            return None;
+        }
+        // the data of virtual relations changes without the database knowing
+        let virtual_relations = virtual_relations.read().unwrap();
+        if p.relations_read()
+            .iter()
+            .any(|rel| virtual_relations.contains_key(*rel))
+        {
+            return None;
+        }
+        Some(CacheKey {
+            tokens: script_tokens(payload)?,
+            params: params.clone(),
+        })
+    }
+
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<NamedRows> {
+        let mut state = self.state.lock().unwrap();
+        let fresh = match state.entries.get(key) {
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            Some(entry) => state.is_fresh(entry),
+        };
+        if !fresh {
+            state.entries.remove(key);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        state.ticks += 1;
+        let tick = state.ticks;
+        let entry = state.entries.get_mut(key).unwrap();
+        entry.last_used = tick;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(entry.rows.clone())
+    }
+
+    /// The version counter, to be taken before the transaction of a query begins.
+    pub(crate) fn current_seq(&self) -> u64 {
+        self.state.lock().unwrap().seq
+    }
+
+    pub(crate) fn insert(&self, key: CacheKey, seq: u64, reads: ReadSet, rows: NamedRows) {
+        let capacity = self.capacity.load(Ordering::Acquire);
+        if capacity == 0 || reads.unbounded {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let entry = CacheEntry {
+            seq,
+            reads: reads.relations,
+            rows,
+            last_used: 0,
+        };
+        if !state.is_fresh(&entry) {
+            return;
+        }
+        if !state.entries.contains_key(&key) {
+            while state.entries.len() >= capacity {
+                evict_one(&mut state);
+            }
+        }
+        state.ticks += 1;
+        let entry = CacheEntry {
+            last_used: state.ticks,
+            ..entry
+        };
+        state.entries.insert(key, entry);
+    }
+
+    /// Invalidate every entry, for writes that bypass transactions.
+    pub(crate) fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.seq += 1;
+        state.cleared_at = state.seq;
+        state.entries.clear();
+    }
+
+    fn record_commit(&self, written: &BTreeSet<u64>, unbounded: bool) {
+        if !unbounded && written.is_empty() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.seq += 1;
+        let seq = state.seq;
+        if unbounded {
+            state.cleared_at = seq;
+            state.entries.clear();
+        } else {
+            for id in written {
+                state.versions.insert(*id, seq);
+            }
+        }
+    }
+}
+
+impl InputProgram {
+    /// Whether running the program again gives the same rows as long as the stored
+    /// relations it reads are unchanged. `cur_vld` is the time `'NOW'` was resolved to.
+    fn is_deterministic(&self, cur_vld: ValidityTs) -> bool {
+        if self.as_of_tx == Some(cur_vld) {
+            return false;
+        }
+        self.prog
+            .values()
+            .all(|rules_or_fixed| match rules_or_fixed {
+                InputInlineRulesOrFixed::Rules { rules } => rules.iter().all(|rule| {
+                    rule.aggr
+                        .iter()
+                        .flatten()
+                        .all(|(aggr, _)| aggr.is_deterministic)
+                        && rule.body.iter().all(|atom| atom.is_deterministic(cur_vld))
+                }),
+                InputInlineRulesOrFixed::Fixed { fixed } => {
+                    // user-defined rules may be replaced without the script changing
+                    DEFAULT_FIXED_RULES.contains_key(&fixed.fixed_handle.name.name as &str)
+                        && fixed.fixed_impl.is_deterministic()
+                        && fixed.options.values().all(Expr::is_deterministic)
+                        && fixed.rule_args.iter().all(|arg| match arg {
+                            FixedRuleArg::InMem { .. } => true,
+                            FixedRuleArg::Stored { valid_at, .. }
+                            | FixedRuleArg::NamedStored { valid_at, .. } => {
+                                *valid_at != Some(cur_vld)
+                            }
+                        })
+                }
+            })
+    }
+}
+
+impl InputAtom {
+    fn is_deterministic(&self, cur_vld: ValidityTs) -> bool {
+        match self {
+            InputAtom::Rule { inner } => inner.args.iter().all(Expr::is_deterministic),
+            InputAtom::NamedFieldRelation { inner } => {
+                inner.valid_at != Some(cur_vld) && inner.args.values().all(Expr::is_deterministic)
+            }
+            InputAtom::Relation { inner } => {
+                inner.valid_at != Some(cur_vld) && inner.args.iter().all(Expr::is_deterministic)
+            }
+            InputAtom::Predicate { inner } => inner.is_deterministic(),
+            InputAtom::Negation { inner, .. } => inner.is_deterministic(cur_vld),
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                inner.iter().all(|atom| atom.is_deterministic(cur_vld))
+            }
+            InputAtom::Unification { inner } => inner.expr.is_deterministic(),
+            InputAtom::Search { inner } => inner
+                .bindings
+                .values()
+                .chain(inner.parameters.values())
+                .all(Expr::is_deterministic),
+            InputAtom::Path { .. } => true,
+        }
+    }
+}
+
+impl Expr {
+    fn is_deterministic(&self) -> bool {
+        match self {
+            Expr::Binding { .. } => true,
+            // resolved to the current time where a validity is expected
+            Expr::Const { val, .. } => !matches!(val, DataValue::Str(s) if s == "NOW"),
+            Expr::Apply { op, args, .. } => {
+                op.deterministic && args.iter().all(Expr::is_deterministic)
+            }
+            // custom functions may be replaced without the script changing
+            Expr::UnboundApply { .. } => false,
+            Expr::Cond { clauses, .. } => clauses
+                .iter()
+                .all(|(cond, val)| cond.is_deterministic() && val.is_deterministic()),
+        }
+    }
+}
+
+fn evict_one(state: &mut CacheState) {
+    let oldest = state
+        .entries
+        .iter()
+        .min_by_key(|(_, entry)| entry.last_used)
+        .map(|(key, _)| key.clone());
+    if let Some(key) = oldest {
+        state.entries.remove(&key);
+    }
+}
+
+/// The syntax tree of the script, flattened into the rules of its nodes and the
+/// literals between them, without whitespace and comments, so that scripts differing
+/// only in layout share their cache entries.
+fn script_tokens(payload: &str) -> Option<Vec<String>> {
+    let pairs = CozoScriptParser::parse(Rule::script, payload).ok()?;
+    let mut tokens = vec![];
+    for pair in pairs {
+        push_tokens(payload, pair, &mut tokens);
+    }
+    Some(tokens)
+}
+
+fn push_tokens(payload: &str, pair: Pair<'_>, tokens: &mut Vec<String>) {
+    tokens.push(format!("{:?}", pair.as_rule()));
+    if matches!(
+        pair.as_rule(),
+        Rule::quoted_string | Rule::s_quoted_string | Rule::raw_string
+    ) {
+        tokens.push(pair.as_str().to_string());
+        return;
+    }
+    let span = pair.as_span();
+    let mut pos = span.start();
+    for inner in pair.into_inner() {
+        push_literals(&payload[pos..inner.as_span().start()], tokens);
+        pos = inner.as_span().end();
+        push_tokens(payload, inner, tokens);
+    }
+    push_literals(&payload[pos..span.end()], tokens);
+    tokens.push(")".to_string());
+}
+
+/// Push the text outside the children of a node, which can only hold literals,
+/// whitespace and comments.
+fn push_literals(text: &str, tokens: &mut Vec<String>) {
+    let mut literals = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c == '#' {
+            rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+        } else if rest.starts_with("/*") {
+            let mut depth = 0;
+            while !rest.is_empty() {
+                if rest.starts_with("/*") {
+                    depth += 1;
+                    rest = &rest[2..];
+                } else if rest.starts_with("*/") {
+                    depth -= 1;
+                    rest = &rest[2..];
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    rest = &rest[rest.chars().next().unwrap().len_utf8()..];
+                }
+            }
+        } else {
+            literals.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    if !literals.is_empty() {
+        tokens.push(literals);
+    }
+}
+
+fn relation_prefix(key: &[u8]) -> Option<u64> {
+    key.get(..8)
+        .map(|prefix| u64::from_be_bytes(prefix.try_into().unwrap()))
+}
+
+/// Wraps a storage transaction to record the relations it reads and writes.
+/// Written relations are reported to the cache on commit; read relations,
+/// if requested, are collected into a [ReadSet].
+pub(crate) struct TrackedTx<'s> {
+    inner: Box<dyn StoreTx<'s> + 's>,
+    cache: Arc<QueryCache>,
+    written: Mutex<(BTreeSet<u64>, bool)>,
+    reads: Option<Arc<Mutex<ReadSet>>>,
+}
+
+impl<'s> TrackedTx<'s> {
+    pub(crate) fn new(
+        inner: Box<dyn StoreTx<'s> + 's>,
+        cache: Arc<QueryCache>,
+        reads: Option<Arc<Mutex<ReadSet>>>,
+    ) -> Self {
+        Self {
+            inner,
+            cache,
+            written: Default::default(),
+            reads,
+        }
+    }
+
+    fn record_write(&self, key: &[u8]) {
+        let mut written = self.written.lock().unwrap();
+        match relation_prefix(key) {
+            Some(id) => {
+                written.0.insert(id);
+            }
+            None => written.1 = true,
+        }
+    }
+
+    fn record_read(&self, lower: &[u8], upper: Option<&[u8]>) {
+        if let Some(reads) = &self.reads {
+            let mut reads = reads.lock().unwrap();
+            let lower_id = relation_prefix(lower);
+            let upper_id = match upper {
+                None => lower_id,
+                Some(upper) => relation_prefix(upper),
+            };
+            match (lower_id, upper_id) {
+                (Some(l), Some(u)) if l == u => {
+                    reads.relations.insert(l);
+                }
+                _ => reads.unbounded = true,
+            }
+        }
+    }
+}
+
+impl<'s> StoreTx<'s> for TrackedTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.record_read(key, None);
+        self.inner.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        for key in keys {
+            self.record_read(key, None);
+        }
+        self.inner.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record_write(key);
+        self.inner.put(key, val)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record_write(key);
+        self.inner.par_put(key, val)
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.record_write(key);
+        self.inner.del(key)
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.record_write(key);
+        self.inner.par_del(key)
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        {
+            let mut written = self.written.lock().unwrap();
+            match (relation_prefix(lower), relation_prefix(upper)) {
+                (Some(l), Some(u)) if l == u => {
+                    written.0.insert(l);
+                }
+                _ => written.1 = true,
+            }
+        }
+        self.inner.del_range_from_persisted(lower, upper)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.record_read(key, None);
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()?;
+        let written = self.written.lock().unwrap();
+        self.cache.record_commit(&written.0, written.1);
+        Ok(())
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.record_read(lower, Some(upper));
+        self.inner.range_scan_tuple(lower, upper)
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.record_read(lower, Some(upper));
+        self.inner.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.record_read(lower, Some(upper));
+        self.inner.range_scan(lower, upper)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.record_read(lower, Some(upper));
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        if let Some(reads) = &self.reads {
+            reads.lock().unwrap().unbounded = true;
+        }
+        self.inner.total_scan()
+    }
+}
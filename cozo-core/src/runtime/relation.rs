@@ -6,31 +6,37 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::atomic::Ordering;
 
 use itertools::Itertools;
 use log::error;
-use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
+use miette::{bail, ensure, miette, Diagnostic, IntoDiagnostic, Result};
 use pest::Parser;
 use rmp_serde::Serializer;
 use serde::Serialize;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::expr::{eval_bytecode, Bytecode, Expr};
 use crate::data::memcmp::MemCmpEncoder;
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
-use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT, ENCODED_KEY_MIN_LEN};
+use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT, TupleView, ENCODED_KEY_MIN_LEN};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fts::FtsIndexManifest;
 use crate::parse::expr::build_expr;
-use crate::parse::sys::{FtsIndexConfig, HnswIndexConfig, MinHashLshConfig};
+use crate::parse::sys::{
+    FtsIndexConfig, GeoIndexConfig, HnswIndexConfig, HnswQuantization, MinHashLshConfig,
+};
 use crate::parse::{CozoScriptParser, Rule, SourceSpan};
 use crate::query::compile::IndexPositionUse;
-use crate::runtime::hnsw::HnswIndexManifest;
+use crate::runtime::geo_index::GeoIndexManifest;
+use crate::runtime::hnsw::{extract_vectors, HnswIndexManifest};
 use crate::runtime::minhash_lsh::{HashPermutations, LshParams, MinHashLshIndexManifest, Weights};
+use crate::runtime::quantization::{VectorQuantizer, MAX_TRAINING_VECTORS};
+use crate::runtime::retention::RetentionPolicy;
 use crate::runtime::transact::SessionTx;
 use crate::utils::TempCollector;
 use crate::{NamedRows, StoreTx};
@@ -90,6 +96,64 @@ pub(crate) struct RelationHandle {
         (RelationHandle, RelationHandle, MinHashLshIndexManifest),
     >,
     pub(crate) description: SmartString<LazyCompact>,
+    #[serde(default)]
+    pub(crate) retention: Option<RetentionPolicy>,
+    /// The name of the history relation recording the changes, if transaction time is tracked
+    #[serde(default)]
+    pub(crate) tx_history: Option<SmartString<LazyCompact>>,
+    #[serde(default)]
+    pub(crate) geo_indices: BTreeMap<SmartString<LazyCompact>, (RelationHandle, GeoIndexManifest)>,
+    /// The regular indices whose indexed columns must be unique across the relation
+    #[serde(default)]
+    pub(crate) unique_indices: BTreeSet<SmartString<LazyCompact>>,
+    /// The computed columns of expression indices, in canonical form. Index mappers refer
+    /// to them by positions after the columns of the relation.
+    #[serde(default)]
+    pub(crate) index_exprs: Vec<String>,
+    /// For relations with validity, the seconds of history kept when compacting,
+    /// see `::history set`
+    #[serde(default)]
+    pub(crate) history_keep: Option<f64>,
+    /// The values of the first key column splitting the relation into partitions,
+    /// in ascending order, see `::partition set`
+    #[serde(default)]
+    pub(crate) partition_bounds: Vec<DataValue>,
+    /// The expressions computing the generated columns from the columns of the relation,
+    /// in canonical form, see `::alter rel add generated`
+    #[serde(default)]
+    pub(crate) generated_columns: BTreeMap<SmartString<LazyCompact>, String>,
+}
+
+pub(crate) fn parse_index_expr(text: &str) -> Result<Expr> {
+    let parsed = CozoScriptParser::parse(Rule::expr, text)
+        .into_diagnostic()?
+        .next()
+        .unwrap();
+    build_expr(parsed, &Default::default())
+}
+
+/// Computed columns are stored as text, which must parse back to the same expression.
+pub(crate) fn ensure_index_expr_round_trips(text: &str, span: SourceSpan) -> Result<()> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("expression {0} cannot be indexed")]
+    #[diagnostic(code(tx::bad_index_expr))]
+    struct BadIndexExpr(String, #[label] SourceSpan);
+
+    match parse_index_expr(text) {
+        Ok(expr) if expr.to_string() == text => Ok(()),
+        _ => bail!(BadIndexExpr(text.to_string(), span)),
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unique index {index} of {relation} violated by {values:?}")]
+#[diagnostic(code(transact::unique_violation))]
+#[diagnostic(help("The row with keys {existing:?} already has these values"))]
+pub(crate) struct UniqueIndexViolation {
+    relation: String,
+    index: String,
+    values: Vec<DataValue>,
+    existing: Vec<DataValue>,
 }
 
 impl RelationHandle {
@@ -98,12 +162,25 @@ impl RelationHandle {
             || self.hnsw_indices.contains_key(index_name)
             || self.fts_indices.contains_key(index_name)
             || self.lsh_indices.contains_key(index_name)
+            || self.geo_indices.contains_key(index_name)
     }
     pub(crate) fn has_no_index(&self) -> bool {
         self.indices.is_empty()
             && self.hnsw_indices.is_empty()
             && self.fts_indices.is_empty()
             && self.lsh_indices.is_empty()
+            && self.geo_indices.is_empty()
+    }
+    /// Forget all indices of the relation, leaving the relation itself as it is.
+    #[cfg(feature = "compressed-backup")]
+    pub(crate) fn strip_indices(&mut self) {
+        self.indices.clear();
+        self.unique_indices.clear();
+        self.index_exprs.clear();
+        self.hnsw_indices.clear();
+        self.fts_indices.clear();
+        self.lsh_indices.clear();
+        self.geo_indices.clear();
     }
 }
 
@@ -138,6 +215,37 @@ impl Display for AccessLevel {
     }
 }
 
+/// The kind of a change submitted to the mutation guard, see [crate::Db::set_mutation_guard].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MutationKind {
+    /// `:create`, and the new name of `::rename`
+    Create,
+    /// `:replace`
+    Replace,
+    /// `:put`, and rows put by data imports
+    Put,
+    /// `:insert`
+    Insert,
+    /// `:update`
+    Update,
+    /// `:rm`, and rows removed by data imports
+    Rm,
+    /// `:delete`
+    Delete,
+    /// `::remove`
+    Remove,
+    /// `::rename`, for the old name
+    Rename,
+    /// Changes to the indices, triggers, access level, retention policy,
+    /// transaction time tracking or description of a relation
+    AlterSchema,
+    /// Changes to ID generators, scheduled scripts, modules, stored procedures and CDC sinks,
+    /// with the name of the object instead of a relation
+    Catalog,
+    /// `::call`, with the name of the stored procedure instead of a relation
+    Call,
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Arity mismatch for stored relation {name}: expect {expect_arity}, got {actual_arity}")]
 #[diagnostic(code(eval::stored_rel_arity_mismatch))]
@@ -188,7 +296,32 @@ impl RelationHandle {
         );
         Ok(NamedRows::new(headers, rows))
     }
-    #[allow(dead_code)]
+    /// Whether data can be copied into the relation without going through queries,
+    /// as is done when importing from backups.
+    #[cfg(any(feature = "storage-sqlite", feature = "compressed-backup"))]
+    pub(crate) fn ensure_bare_import_allowed(&self) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot import data into relation {0} from backup as the relation has indices")]
+        #[diagnostic(code(tx::bare_import_with_indices))]
+        #[diagnostic(help("Use `import_relations()` instead"))]
+        struct RestoreIntoRelWithIndices(String);
+
+        if self.name.contains(':') {
+            bail!(crate::runtime::db::ImportIntoIndex(self.name.to_string()))
+        }
+        if !self.indices.is_empty() {
+            bail!(RestoreIntoRelWithIndices(self.name.to_string()))
+        }
+        if self.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                self.name.to_string(),
+                "data import".to_string(),
+                self.access_level
+            ));
+        }
+        Ok(())
+    }
+    #[cfg(any(feature = "storage-sqlite", feature = "compressed-backup"))]
     pub(crate) fn amend_key_prefix(&self, data: &mut [u8]) {
         let prefix_bytes = self.id.0.to_be_bytes();
         data[0..8].copy_from_slice(&prefix_bytes);
@@ -218,32 +351,74 @@ impl RelationHandle {
             .collect_vec();
         let mut chosen = None;
         for (manifest, mapper) in self.indices.values() {
-            if validity_query && *mapper.last().unwrap() != self.metadata.keys.len() - 1 {
+            // expression indices are only used for filters on their expressions
+            if mapper.iter().any(|i| *i >= self.arity()) {
+                continue;
+            }
+            // the mapper also contains the payload columns of covering indices
+            let key_mapper = &mapper[..manifest.metadata.keys.len()];
+            if validity_query && *key_mapper.last().unwrap() != self.metadata.keys.len() - 1 {
                 continue;
             }
 
             let mut cur_prefix_len = 0;
-            for i in mapper {
+            for i in key_mapper {
                 if arg_uses[*i] == IndexPositionUse::Join {
                     cur_prefix_len += 1;
                 } else {
                     break;
                 }
             }
-            if cur_prefix_len > max_prefix_len {
-                max_prefix_len = cur_prefix_len;
-                let mut need_join = false;
-                for need_pos in required_positions.iter() {
-                    if !mapper.contains(need_pos) {
-                        need_join = true;
-                        break;
-                    }
+            let need_join = required_positions
+                .iter()
+                .any(|need_pos| !mapper.contains(need_pos));
+            // among indices with the same prefix, prefer one that covers the query
+            let better = match &chosen {
+                None => cur_prefix_len > 0,
+                Some((_, _, chosen_need_join)) => {
+                    cur_prefix_len > max_prefix_len
+                        || (cur_prefix_len == max_prefix_len && *chosen_need_join && !need_join)
                 }
+            };
+            if better {
+                max_prefix_len = cur_prefix_len;
                 chosen = Some((manifest.clone(), mapper.clone(), need_join))
             }
         }
         chosen
     }
+    /// The index given by a `use_index` hint, as returned by [Self::choose_index],
+    /// even if it cannot be joined on.
+    pub(crate) fn hinted_index(
+        &self,
+        index: &Symbol,
+        arg_uses: &[IndexPositionUse],
+        validity_query: bool,
+    ) -> Result<(RelationHandle, Vec<usize>, bool)> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Index {1} of stored relation {0} cannot be used for time travel")]
+        #[diagnostic(code(eval::hinted_index_unusable))]
+        #[diagnostic(help("The keys of the index must end with the validity of the relation"))]
+        struct HintedIndexUnusable(String, String, #[label] SourceSpan);
+
+        let (manifest, mapper) = self.indices.get(&index.name).ok_or_else(|| {
+            miette!(
+                "Index {} of stored relation {} not found",
+                index.name,
+                self.name
+            )
+        })?;
+        let key_mapper = &mapper[..manifest.metadata.keys.len()];
+        ensure!(
+            !validity_query || *key_mapper.last().unwrap() == self.metadata.keys.len() - 1,
+            HintedIndexUnusable(self.name.to_string(), index.name.to_string(), index.span)
+        );
+        let need_join = arg_uses
+            .iter()
+            .enumerate()
+            .any(|(i, pos_use)| *pos_use != IndexPositionUse::Ignored && !mapper.contains(&i));
+        Ok((manifest.clone(), mapper.clone(), need_join))
+    }
     pub(crate) fn encode_key_for_store(
         &self,
         tuple: &[DataValue],
@@ -285,6 +460,50 @@ impl RelationHandle {
             .unwrap();
         Ok(ret)
     }
+    /// Compiles the computed columns of expression indices, to be passed to
+    /// [`Self::index_tuple`].
+    pub(crate) fn compile_index_exprs(&self) -> Result<Vec<Vec<Bytecode>>> {
+        let binding_map = self.raw_binding_map();
+        self.index_exprs
+            .iter()
+            .map(|text| {
+                let mut expr = parse_index_expr(text)?;
+                expr.fill_binding_indices(&binding_map)?;
+                expr.compile()
+            })
+            .collect()
+    }
+    /// The tuple stored by the index with the given mapper for a row of the relation.
+    /// Positions after the columns of the relation are computed columns.
+    pub(crate) fn index_tuple(
+        &self,
+        mapper: &[usize],
+        tuple: &[DataValue],
+        index_exprs: &[Vec<Bytecode>],
+        stack: &mut Vec<DataValue>,
+    ) -> Result<Tuple> {
+        let arity = self.arity();
+        mapper
+            .iter()
+            .map(|i| {
+                if *i < arity {
+                    Ok(tuple[*i].clone())
+                } else {
+                    eval_bytecode(&index_exprs[*i - arity], tuple, stack)
+                }
+            })
+            .collect()
+    }
+    /// Encodes an entry of an index relation. Only covering indices have values.
+    pub(crate) fn encode_index_entry(&self, tuple: &[DataValue]) -> Result<(Vec<u8>, Vec<u8>)> {
+        let key = self.encode_key_for_store(tuple, Default::default())?;
+        let val = if self.metadata.non_keys.is_empty() {
+            vec![]
+        } else {
+            self.encode_val_for_store(tuple, Default::default())?
+        };
+        Ok((key, val))
+    }
     pub(crate) fn encode_val_only_for_store(
         &self,
         tuple: &[DataValue],
@@ -360,11 +579,29 @@ impl RelationHandle {
     ) -> impl Iterator<Item = Result<Tuple>> + 'a {
         let lower = Tuple::default().encode_as_key(self.id);
         let upper = Tuple::default().encode_as_key(self.id.next());
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx.range_scan_tuple(&lower, &upper)
         } else {
             tx.store_tx.range_scan_tuple(&lower, &upper)
-        }
+        };
+        count_scanned(tx, it)
+    }
+
+    /// Like [Self::scan_all], but only decoding the columns marked in `needed`.
+    pub(crate) fn scan_all_columns<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        needed: &[bool],
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let lower = Tuple::default().encode_as_key(self.id);
+        let upper = Tuple::default().encode_as_key(self.id.next());
+        let it = if self.is_temp {
+            tx.temp_store_tx
+                .range_scan_tuple_columns(&lower, &upper, needed)
+        } else {
+            tx.store_tx.range_scan_tuple_columns(&lower, &upper, needed)
+        };
+        count_scanned(tx, it)
     }
 
     pub(crate) fn skip_scan_all<'a>(
@@ -374,12 +611,13 @@ impl RelationHandle {
     ) -> impl Iterator<Item = Result<Tuple>> + 'a {
         let lower = Tuple::default().encode_as_key(self.id);
         let upper = Tuple::default().encode_as_key(self.id.next());
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&lower, &upper, valid_at)
         } else {
             tx.store_tx.range_skip_scan_tuple(&lower, &upper, valid_at)
-        }
+        };
+        count_scanned(tx, it)
     }
 
     pub(crate) fn get(&self, tx: &SessionTx<'_>, key: &[DataValue]) -> Result<Option<Tuple>> {
@@ -397,6 +635,22 @@ impl RelationHandle {
         }
     }
 
+    /// Like [Self::get], but only decoding the columns marked in `needed`.
+    pub(crate) fn get_columns(
+        &self,
+        tx: &SessionTx<'_>,
+        key: &[DataValue],
+        needed: &[bool],
+    ) -> Result<Option<Tuple>> {
+        let key_data = key.encode_as_key(self.id);
+        let found = if self.is_temp {
+            tx.temp_store_tx.get(&key_data, false)?
+        } else {
+            tx.store_tx.get(&key_data, false)?
+        };
+        Ok(found.map(|val_data| decode_tuple_columns_from_kv(&key_data, &val_data, needed)))
+    }
+
     pub(crate) fn get_val_only(
         &self,
         tx: &SessionTx<'_>,
@@ -436,13 +690,37 @@ impl RelationHandle {
         upper.push(DataValue::Bot);
         let prefix_encoded = lower.encode_as_key(self.id);
         let upper_encoded = upper.encode_as_key(self.id);
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_scan_tuple(&prefix_encoded, &upper_encoded)
         } else {
             tx.store_tx
                 .range_scan_tuple(&prefix_encoded, &upper_encoded)
-        }
+        };
+        count_scanned(tx, it)
+    }
+
+    /// Like [Self::scan_prefix], but only decoding the columns marked in `needed`.
+    pub(crate) fn scan_prefix_columns<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        prefix: &Tuple,
+        needed: &[bool],
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let mut lower = prefix.clone();
+        lower.truncate(self.metadata.keys.len());
+        let mut upper = lower.clone();
+        upper.push(DataValue::Bot);
+        let prefix_encoded = lower.encode_as_key(self.id);
+        let upper_encoded = upper.encode_as_key(self.id);
+        let it = if self.is_temp {
+            tx.temp_store_tx
+                .range_scan_tuple_columns(&prefix_encoded, &upper_encoded, needed)
+        } else {
+            tx.store_tx
+                .range_scan_tuple_columns(&prefix_encoded, &upper_encoded, needed)
+        };
+        count_scanned(tx, it)
     }
 
     pub(crate) fn skip_scan_prefix<'a>(
@@ -457,13 +735,14 @@ impl RelationHandle {
         upper.push(DataValue::Bot);
         let prefix_encoded = lower.encode_as_key(self.id);
         let upper_encoded = upper.encode_as_key(self.id);
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
         } else {
             tx.store_tx
                 .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
-        }
+        };
+        count_scanned(tx, it)
     }
 
     pub(crate) fn scan_bounded_prefix<'a>(
@@ -480,12 +759,54 @@ impl RelationHandle {
         upper_t.push(DataValue::Bot);
         let lower_encoded = lower_t.encode_as_key(self.id);
         let upper_encoded = upper_t.encode_as_key(self.id);
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_scan_tuple(&lower_encoded, &upper_encoded)
         } else {
             tx.store_tx.range_scan_tuple(&lower_encoded, &upper_encoded)
-        }
+        };
+        count_scanned(tx, it)
+    }
+    /// Like [Self::scan_bounded_prefix], but only decoding the columns marked in `needed`.
+    pub(crate) fn scan_bounded_prefix_columns<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        prefix: &[DataValue],
+        lower: &[DataValue],
+        upper: &[DataValue],
+        needed: &[bool],
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let mut lower_t = prefix.to_vec();
+        lower_t.extend_from_slice(lower);
+        let mut upper_t = prefix.to_vec();
+        upper_t.extend_from_slice(upper);
+        upper_t.push(DataValue::Bot);
+        let lower_encoded = lower_t.encode_as_key(self.id);
+        let upper_encoded = upper_t.encode_as_key(self.id);
+        let it = if self.is_temp {
+            tx.temp_store_tx
+                .range_scan_tuple_columns(&lower_encoded, &upper_encoded, needed)
+        } else {
+            tx.store_tx
+                .range_scan_tuple_columns(&lower_encoded, &upper_encoded, needed)
+        };
+        count_scanned(tx, it)
+    }
+    /// Scan the rows whose encoded keys are in `[lower, upper)`, only decoding the columns
+    /// marked in `needed`.
+    pub(crate) fn scan_key_range_columns<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        lower: &[u8],
+        upper: &[u8],
+        needed: &[bool],
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let it = if needed.iter().all(|n| *n) {
+            tx.store_tx.range_scan_tuple(lower, upper)
+        } else {
+            tx.store_tx.range_scan_tuple_columns(lower, upper, needed)
+        };
+        count_scanned(tx, it)
     }
     pub(crate) fn skip_scan_bounded_prefix<'a>(
         &self,
@@ -502,16 +823,27 @@ impl RelationHandle {
         upper_t.push(DataValue::Bot);
         let lower_encoded = lower_t.encode_as_key(self.id);
         let upper_encoded = upper_t.encode_as_key(self.id);
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
         } else {
             tx.store_tx
                 .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
-        }
+        };
+        count_scanned(tx, it)
     }
 }
 
+fn count_scanned<'a>(
+    tx: &'a SessionTx<'_>,
+    it: impl Iterator<Item = Result<Tuple>> + 'a,
+) -> impl Iterator<Item = Result<Tuple>> + 'a {
+    let metrics = &tx.metrics;
+    it.inspect(move |_| {
+        metrics.rows_scanned.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
 const DEFAULT_SIZE_HINT: usize = 16;
 
 /// Decode tuple from key-value pairs. Used for customizing storage
@@ -523,6 +855,13 @@ pub fn decode_tuple_from_kv(key: &[u8], val: &[u8], size_hint: Option<usize>) ->
     tup
 }
 
+/// Decode only the columns marked in `needed` from key-value pairs, putting `Null` in the
+/// place of the others. Used for customizing storage in trait [`StoreTx`](crate::StoreTx).
+#[inline]
+pub fn decode_tuple_columns_from_kv(key: &[u8], val: &[u8], needed: &[bool]) -> Tuple {
+    TupleView::new(key, val).decode_columns(needed)
+}
+
 pub fn extend_tuple_from_v(key: &mut Tuple, val: &[u8]) {
     if !val.is_empty() {
         let vals: Vec<DataValue> = rmp_serde::from_slice(&val[ENCODED_KEY_MIN_LEN..]).unwrap();
@@ -596,7 +935,9 @@ impl<'a> SessionTx<'a> {
             if self.store_tx.exists(&encoded, true)? {
                 bail!(RelNameConflictError(input_meta.name.to_string()))
             };
-        } else if self.temp_store_tx.exists(&encoded, true)? {
+        } else if self.temp_store_tx.exists(&encoded, true)?
+            || self.get_virtual_relation(&input_meta.name).is_some()
+        {
             bail!(RelNameConflictError(input_meta.name.to_string()))
         }
 
@@ -620,6 +961,14 @@ impl<'a> SessionTx<'a> {
             fts_indices: Default::default(),
             lsh_indices: Default::default(),
             description: Default::default(),
+            retention: None,
+            tx_history: None,
+            geo_indices: Default::default(),
+            unique_indices: Default::default(),
+            index_exprs: Default::default(),
+            history_keep: None,
+            partition_bounds: vec![],
+            generated_columns: Default::default(),
         };
 
         let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
@@ -662,6 +1011,15 @@ impl<'a> SessionTx<'a> {
         let metadata = RelationHandle::decode(&found)?;
         Ok(metadata)
     }
+    /// Write back the metadata of a stored relation after changing it.
+    pub(crate) fn save_relation_meta(&mut self, meta: &RelationHandle) -> Result<()> {
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+        Ok(())
+    }
     pub(crate) fn describe_relation(&mut self, name: &str, description: &str) -> Result<()> {
         let mut meta = self.get_relation(name, true)?;
 
@@ -705,9 +1063,18 @@ impl<'a> SessionTx<'a> {
             to_clean.extend(more_to_clean);
         }
 
-        for k in store.hnsw_indices.keys() {
+        for (k, (_, manifest)) in store.hnsw_indices.iter() {
             let more_to_clean = self.destroy_relation(&format!("{name}:{k}"))?;
             to_clean.extend(more_to_clean);
+            if manifest.quantizer.is_some() {
+                let more_to_clean = self.destroy_relation(&format!("{name}:{k}:codes"))?;
+                to_clean.extend(more_to_clean);
+            }
+        }
+
+        if let Some(history) = &store.tx_history {
+            let more_to_clean = self.destroy_relation(history)?;
+            to_clean.extend(more_to_clean);
         }
 
         let key = DataValue::from(name);
@@ -805,6 +1172,7 @@ impl<'a> SessionTx<'a> {
             index_name: config.index_name.clone(),
             extractor: config.extractor.clone(),
             n_gram: config.n_gram,
+            shingling: config.shingling,
             tokenizer: config.tokenizer.clone(),
             filters: config.filters.clone(),
             num_perm,
@@ -866,6 +1234,101 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
+    pub(crate) fn create_geo_index(&mut self, config: &GeoIndexConfig) -> Result<()> {
+        // Get relation handle
+        let mut rel_handle = self.get_relation(&config.base_relation, true)?;
+
+        // Check if index already exists
+        if rel_handle.has_index(&config.index_name) {
+            bail!(IndexAlreadyExists(
+                config.index_name.to_string(),
+                config.index_name.to_string()
+            ));
+        }
+
+        let mut idx_keys = vec![ColumnDef {
+            name: SmartString::from("geohash"),
+            typing: NullableColType {
+                coltype: ColType::String,
+                nullable: false,
+            },
+            default_gen: None,
+        }];
+        for k in rel_handle.metadata.keys.iter() {
+            idx_keys.push(ColumnDef {
+                name: format!("src_{}", k.name).into(),
+                typing: k.typing.clone(),
+                default_gen: None,
+            });
+        }
+        let idx_vals = ["lat", "lon"]
+            .into_iter()
+            .map(|name| ColumnDef {
+                name: SmartString::from(name),
+                typing: NullableColType {
+                    coltype: ColType::Float,
+                    nullable: false,
+                },
+                default_gen: None,
+            })
+            .collect_vec();
+
+        let idx_handle = self.write_idx_relation(
+            &config.base_relation,
+            &config.index_name,
+            idx_keys,
+            idx_vals,
+        )?;
+
+        let manifest = GeoIndexManifest {
+            base_relation: config.base_relation.clone(),
+            index_name: config.index_name.clone(),
+            extractor: config.extractor.clone(),
+            precision: config.precision,
+        };
+
+        // populate index
+        let parsed = CozoScriptParser::parse(Rule::expr, &manifest.extractor)
+            .into_diagnostic()?
+            .next()
+            .unwrap();
+        let mut code_expr = build_expr(parsed, &Default::default())?;
+        let binding_map = rel_handle.raw_binding_map();
+        code_expr.fill_binding_indices(&binding_map)?;
+        let extractor = code_expr.compile()?;
+
+        let mut stack = vec![];
+        let mut existing = TempCollector::default();
+        for tuple in rel_handle.scan_all(self) {
+            existing.push(tuple?);
+        }
+        for tuple in existing.into_iter() {
+            self.put_geo_index_item(
+                &tuple,
+                &extractor,
+                &mut stack,
+                &rel_handle,
+                &idx_handle,
+                &manifest,
+            )?;
+        }
+
+        rel_handle
+            .geo_indices
+            .insert(manifest.index_name.clone(), (idx_handle, manifest));
+
+        // update relation metadata
+        let new_encoded =
+            vec![DataValue::from(&rel_handle.name as &str)].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        rel_handle
+            .serialize(&mut Serializer::new(&mut meta_val))
+            .unwrap();
+        self.store_tx.put(&new_encoded, &meta_val)?;
+
+        Ok(())
+    }
+
     pub(crate) fn create_fts_index(&mut self, config: &FtsIndexConfig) -> Result<()> {
         // Get relation handle
         let mut rel_handle = self.get_relation(&config.base_relation, true)?;
@@ -1132,6 +1595,56 @@ impl<'a> SessionTx<'a> {
             non_idx_keys,
         )?;
 
+        // quantized vectors are stored in a relation of their own
+        let quantizer = match config.quantization {
+            None => None,
+            Some(HnswQuantization::Scalar) => Some(VectorQuantizer::Scalar),
+            Some(HnswQuantization::Product { subspaces }) => {
+                let mut samples = vec![];
+                'outer: for tuple in rel_handle.scan_all(self) {
+                    let tuple = tuple?;
+                    for (v, _, _) in extract_vectors(&vec_field_indices, &tuple) {
+                        if samples.len() >= MAX_TRAINING_VECTORS {
+                            break 'outer;
+                        }
+                        samples.push(v.clone());
+                    }
+                }
+                Some(VectorQuantizer::train_product(
+                    &samples,
+                    config.vec_dim,
+                    subspaces,
+                )?)
+            }
+        };
+        if quantizer.is_some() {
+            let mut code_keys = rel_handle.metadata.keys.clone();
+            for name in ["field", "sub_idx"] {
+                code_keys.push(ColumnDef {
+                    name: SmartString::from(format!("_{}", name)),
+                    typing: NullableColType {
+                        coltype: ColType::Int,
+                        nullable: false,
+                    },
+                    default_gen: None,
+                });
+            }
+            let code_vals = vec![ColumnDef {
+                name: SmartString::from("code"),
+                typing: NullableColType {
+                    coltype: ColType::Bytes,
+                    nullable: false,
+                },
+                default_gen: None,
+            }];
+            self.write_idx_relation(
+                &config.base_relation,
+                &format!("{}:codes", config.index_name),
+                code_keys,
+                code_vals,
+            )?;
+        }
+
         // add index to relation
         let manifest = HnswIndexManifest {
             base_relation: config.base_relation.clone(),
@@ -1148,6 +1661,7 @@ impl<'a> SessionTx<'a> {
             index_filter: config.index_filter.clone(),
             extend_candidates: config.extend_candidates,
             keep_pruned_connections: config.keep_pruned_connections,
+            quantizer,
         };
 
         // populate index
@@ -1233,11 +1747,30 @@ impl<'a> SessionTx<'a> {
         &mut self,
         rel_name: &Symbol,
         idx_name: &Symbol,
-        cols: &[Symbol],
+        cols: &[Expr],
+        payload: &[Symbol],
+        unique: bool,
     ) -> Result<()> {
         // Get relation handle
         let mut rel_handle = self.get_relation(rel_name, true)?;
 
+        let has_validity = matches!(
+            rel_handle.metadata.keys.last(),
+            Some(col) if col.typing.coltype == ColType::Validity
+        );
+        if unique && has_validity {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("cannot create unique index {0} for relation {1} with validity")]
+            #[diagnostic(code(tx::unique_idx_with_validity))]
+            #[diagnostic(help("Each version of a row would be counted as a duplicate"))]
+            pub(crate) struct UniqueIndexWithValidity(String, String);
+
+            bail!(UniqueIndexWithValidity(
+                idx_name.name.to_string(),
+                rel_name.name.to_string()
+            ));
+        }
+
         // Check if index already exists
         if rel_handle.has_index(&idx_name.name) {
             bail!(IndexAlreadyExists(
@@ -1246,49 +1779,132 @@ impl<'a> SessionTx<'a> {
             ));
         }
 
-        // Build column definitions
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("column {0} in index {1} for relation {2} not found")]
+        #[diagnostic(code(tx::col_in_idx_not_found))]
+        pub(crate) struct ColInIndexNotFound(String, String, String);
+
+        // Build column definitions, along with their positions in the rows of the relation
+        let arity = rel_handle.arity();
+        let all_cols = rel_handle
+            .metadata
+            .keys
+            .iter()
+            .chain(rel_handle.metadata.non_keys.iter())
+            .cloned()
+            .collect_vec();
         let mut col_defs = vec![];
-        'outer: for col in cols.iter() {
-            for orig_col in rel_handle
-                .metadata
-                .keys
-                .iter()
-                .chain(rel_handle.metadata.non_keys.iter())
-            {
-                if orig_col.name == col.name {
-                    col_defs.push(orig_col.clone());
-                    continue 'outer;
+        let mut extraction_indices = vec![];
+        for col in cols.iter() {
+            match col {
+                Expr::Binding { var, .. } => {
+                    match all_cols.iter().position(|c| c.name == var.name) {
+                        Some(i) => {
+                            col_defs.push(all_cols[i].clone());
+                            extraction_indices.push(i);
+                        }
+                        None => bail!(ColInIndexNotFound(
+                            var.name.to_string(),
+                            idx_name.name.to_string(),
+                            rel_name.name.to_string()
+                        )),
+                    }
+                }
+                expr => {
+                    for var in expr.bindings()? {
+                        if !all_cols.iter().any(|c| c.name == var.name) {
+                            bail!(ColInIndexNotFound(
+                                var.name.to_string(),
+                                idx_name.name.to_string(),
+                                rel_name.name.to_string()
+                            ));
+                        }
+                    }
+                    let text = expr.to_string();
+                    ensure_index_expr_round_trips(&text, expr.span())?;
+                    // computed columns come after the columns of the relation
+                    let pos = match rel_handle.index_exprs.iter().position(|e| *e == text) {
+                        Some(pos) => pos,
+                        None => {
+                            rel_handle.index_exprs.push(text.clone());
+                            rel_handle.index_exprs.len() - 1
+                        }
+                    };
+                    col_defs.push(ColumnDef {
+                        name: SmartString::from(text),
+                        typing: NullableColType {
+                            coltype: ColType::Any,
+                            nullable: true,
+                        },
+                        default_gen: None,
+                    });
+                    extraction_indices.push(arity + pos);
                 }
             }
+        }
 
-            #[derive(Debug, Error, Diagnostic)]
-            #[error("column {0} in index {1} for relation {2} not found")]
-            #[diagnostic(code(tx::col_in_idx_not_found))]
-            pub(crate) struct ColInIndexNotFound(String, String, String);
-
-            bail!(ColInIndexNotFound(
-                col.name.to_string(),
-                idx_name.name.to_string(),
-                rel_name.name.to_string()
-            ));
+        for (i, key) in rel_handle.metadata.keys.iter().enumerate() {
+            if !extraction_indices.contains(&i) {
+                col_defs.push(key.clone());
+                extraction_indices.push(i);
+            }
         }
 
-        'outer: for key in rel_handle.metadata.keys.iter() {
-            for col in cols.iter() {
-                if col.name == key.name {
-                    continue 'outer;
+        // Payload columns are stored as the values of a covering index
+        let mut payload_defs = vec![];
+        for col in payload.iter() {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("payload column {0} of index {1} is already stored in the index")]
+            #[diagnostic(code(tx::dup_payload_col_in_idx))]
+            #[diagnostic(help("Keys of the relation are always stored in its indices"))]
+            pub(crate) struct PayloadColInIndexKeys(String, String, #[label] SourceSpan);
+
+            if col_defs.iter().any(|c| c.name == col.name)
+                || payload_defs.iter().any(|c: &ColumnDef| c.name == col.name)
+            {
+                bail!(PayloadColInIndexKeys(
+                    col.name.to_string(),
+                    idx_name.name.to_string(),
+                    col.span
+                ));
+            }
+            match rel_handle
+                .metadata
+                .non_keys
+                .iter()
+                .position(|orig_col| orig_col.name == col.name)
+            {
+                Some(i) => {
+                    payload_defs.push(rel_handle.metadata.non_keys[i].clone());
+                    extraction_indices.push(i + rel_handle.metadata.keys.len());
                 }
+                None => bail!(ColInIndexNotFound(
+                    col.name.to_string(),
+                    idx_name.name.to_string(),
+                    rel_name.name.to_string()
+                )),
             }
-            col_defs.push(key.clone());
+        }
+
+        // Unique indices are keyed by the unique columns alone, so that rows with the
+        // same values collide. The keys of the relation are stored before the payload.
+        if unique {
+            let mut non_keys = col_defs.split_off(cols.len());
+            non_keys.extend(payload_defs);
+            payload_defs = non_keys;
         }
 
         let key_bindings = col_defs
             .iter()
             .map(|col| Symbol::new(col.name.clone(), Default::default()))
             .collect_vec();
+        let dep_bindings = payload_defs
+            .iter()
+            .map(|col| Symbol::new(col.name.clone(), Default::default()))
+            .collect_vec();
         let idx_meta = StoredRelationMetadata {
             keys: col_defs,
-            non_keys: vec![],
+            non_keys: payload_defs,
         };
 
         // create index relation
@@ -1299,41 +1915,27 @@ impl<'a> SessionTx<'a> {
             ),
             metadata: idx_meta,
             key_bindings,
-            dep_bindings: vec![],
+            dep_bindings,
             span: Default::default(),
         };
 
         let idx_handle = self.create_relation(idx_handle)?;
 
         // populate index
-        let extraction_indices = idx_handle
-            .metadata
-            .keys
-            .iter()
-            .map(|col| {
-                for (i, kc) in rel_handle.metadata.keys.iter().enumerate() {
-                    if kc.name == col.name {
-                        return i;
-                    }
-                }
-                for (i, kc) in rel_handle.metadata.non_keys.iter().enumerate() {
-                    if kc.name == col.name {
-                        return i + rel_handle.metadata.keys.len();
-                    }
-                }
-                unreachable!()
-            })
-            .collect_vec();
-
-        if self.store_tx.supports_par_put() {
+        let index_exprs = rel_handle.compile_index_exprs()?;
+        let mut stack = vec![];
+        // unique indices need to see what has been written so far
+        if self.store_tx.supports_par_put() && !unique {
             for tuple in rel_handle.scan_all(self) {
                 let tuple = tuple?;
-                let extracted = extraction_indices
-                    .iter()
-                    .map(|idx| tuple[*idx].clone())
-                    .collect_vec();
-                let key = idx_handle.encode_key_for_store(&extracted, Default::default())?;
-                self.store_tx.par_put(&key, &[])?;
+                let extracted = rel_handle.index_tuple(
+                    &extraction_indices,
+                    &tuple,
+                    &index_exprs,
+                    &mut stack,
+                )?;
+                let (key, val) = idx_handle.encode_index_entry(&extracted)?;
+                self.store_tx.par_put(&key, &val)?;
             }
         } else {
             let mut existing = TempCollector::default();
@@ -1341,12 +1943,24 @@ impl<'a> SessionTx<'a> {
                 existing.push(tuple?);
             }
             for tuple in existing.into_iter() {
-                let extracted = extraction_indices
-                    .iter()
-                    .map(|idx| tuple[*idx].clone())
-                    .collect_vec();
-                let key = idx_handle.encode_key_for_store(&extracted, Default::default())?;
-                self.store_tx.put(&key, &[])?;
+                let extracted = rel_handle.index_tuple(
+                    &extraction_indices,
+                    &tuple,
+                    &index_exprs,
+                    &mut stack,
+                )?;
+                if unique {
+                    self.ensure_unique_in_index(
+                        &rel_handle,
+                        &idx_name.name,
+                        &idx_handle,
+                        &extraction_indices,
+                        &extracted,
+                        &tuple,
+                    )?;
+                }
+                let (key, val) = idx_handle.encode_index_entry(&extracted)?;
+                self.store_tx.put(&key, &val)?;
             }
         }
 
@@ -1354,6 +1968,9 @@ impl<'a> SessionTx<'a> {
         rel_handle
             .indices
             .insert(idx_name.name.clone(), (idx_handle, extraction_indices));
+        if unique {
+            rel_handle.unique_indices.insert(idx_name.name.clone());
+        }
 
         // update relation metadata
         let new_encoded =
@@ -1367,6 +1984,62 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
+    /// Checks the unique indices of the relation before `new_kv` is written. Rows only
+    /// conflict with other rows, not with their own previous versions.
+    pub(crate) fn ensure_unique_in_indices(
+        &mut self,
+        relation_store: &RelationHandle,
+        new_kv: &[DataValue],
+        index_exprs: &[Vec<Bytecode>],
+        stack: &mut Vec<DataValue>,
+    ) -> Result<()> {
+        for idx_name in relation_store.unique_indices.iter() {
+            let (idx_handle, extractor) = &relation_store.indices[idx_name];
+            let idx_tuple = relation_store.index_tuple(extractor, new_kv, index_exprs, stack)?;
+            self.ensure_unique_in_index(
+                relation_store,
+                idx_name,
+                idx_handle,
+                extractor,
+                &idx_tuple,
+                new_kv,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn ensure_unique_in_index(
+        &mut self,
+        relation_store: &RelationHandle,
+        idx_name: &str,
+        idx_handle: &RelationHandle,
+        extractor: &[usize],
+        idx_tuple: &[DataValue],
+        new_kv: &[DataValue],
+    ) -> Result<()> {
+        let key = idx_handle.encode_key_for_store(idx_tuple, Default::default())?;
+        // reading for update makes concurrent writers of the same values conflict
+        if let Some(val) = self.store_tx.get(&key, true)? {
+            let existing = decode_tuple_from_kv(&key, &val, Some(idx_handle.arity()));
+            let key_len = relation_store.metadata.keys.len();
+            let existing_keys = (0..key_len)
+                .map(|k| {
+                    let pos = extractor.iter().position(|i| *i == k).unwrap();
+                    existing[pos].clone()
+                })
+                .collect_vec();
+            if existing_keys[..] != new_kv[..key_len] {
+                bail!(UniqueIndexViolation {
+                    relation: relation_store.name.to_string(),
+                    index: idx_name.to_string(),
+                    values: idx_tuple[..idx_handle.metadata.keys.len()].to_vec(),
+                    existing: existing_keys,
+                });
+            }
+        }
+        Ok(())
+    }
+
     pub(crate) fn remove_index(
         &mut self,
         rel_name: &Symbol,
@@ -1375,14 +2048,20 @@ impl<'a> SessionTx<'a> {
         let mut rel = self.get_relation(rel_name, true)?;
         let is_lsh = rel.lsh_indices.contains_key(&idx_name.name);
         let is_fts = rel.fts_indices.contains_key(&idx_name.name);
+        let is_quantized = matches!(
+            rel.hnsw_indices.get(&idx_name.name),
+            Some((_, manifest)) if manifest.quantizer.is_some()
+        );
         if is_lsh || is_fts {
             self.tokenizers.named_cache.write().unwrap().clear();
             self.tokenizers.hashed_cache.write().unwrap().clear();
         }
+        rel.unique_indices.remove(&idx_name.name);
         if rel.indices.remove(&idx_name.name).is_none()
             && rel.hnsw_indices.remove(&idx_name.name).is_none()
             && rel.lsh_indices.remove(&idx_name.name).is_none()
             && rel.fts_indices.remove(&idx_name.name).is_none()
+            && rel.geo_indices.remove(&idx_name.name).is_none()
         {
             #[derive(Debug, Error, Diagnostic)]
             #[error("index {0} for relation {1} not found")]
@@ -1391,6 +2070,14 @@ impl<'a> SessionTx<'a> {
 
             bail!(IndexNotFound(idx_name.to_string(), rel_name.to_string()));
         }
+        let arity = rel.arity();
+        if rel
+            .indices
+            .values()
+            .all(|(_, mapper)| mapper.iter().all(|i| *i < arity))
+        {
+            rel.index_exprs.clear();
+        }
 
         let mut to_clean =
             self.destroy_relation(&format!("{}:{}", rel_name.name, idx_name.name))?;
@@ -1399,6 +2086,11 @@ impl<'a> SessionTx<'a> {
                 self.destroy_relation(&format!("{}:{}:inv", rel_name.name, idx_name.name))?,
             );
         }
+        if is_quantized {
+            to_clean.extend(
+                self.destroy_relation(&format!("{}:{}:codes", rel_name.name, idx_name.name))?,
+            );
+        }
 
         let new_encoded =
             vec![DataValue::from(&rel_name.name as &str)].encode_as_key(RelationId::SYSTEM);
@@ -0,0 +1,287 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Declarative data retention: rows of a stored relation whose timestamp column is older
+//! than the period kept by the policy of the relation are deleted in batches, when the host
+//! runs the scheduled scripts, on `::compact` and on `::retention run`.
+//!
+//! For relations with validity, the history that can no longer be seen by time travel
+//! queries after a cutoff can also be purged, on demand or when compacting.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use log::error;
+use miette::{bail, ensure, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::relation::ColType;
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
+use crate::parse::SourceSpan;
+use crate::runtime::db::{seconds_since_the_epoch, Db};
+use crate::runtime::relation::{RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{NamedRows, ScriptMutability, Storage};
+
+/// Number of rows deleted per transaction when enforcing a policy.
+const RETENTION_BATCH: usize = 1000;
+
+/// Rows are kept for `keep` seconds after the time in `column`,
+/// which holds seconds since the epoch, as returned by `now()`.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct RetentionPolicy {
+    pub(crate) column: SmartString<LazyCompact>,
+    pub(crate) keep: f64,
+}
+
+impl<'a> SessionTx<'a> {
+    /// Set or remove the retention policy of a stored relation.
+    pub(crate) fn set_retention(
+        &mut self,
+        rel: &Symbol,
+        policy: Option<RetentionPolicy>,
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Column {0} not found in relation {1}")]
+        #[diagnostic(code(tx::retention_column_not_found))]
+        struct RetentionColumnNotFound(String, String, #[label] SourceSpan);
+
+        let mut meta = self.get_relation(rel, true)?;
+        ensure!(
+            !meta.name.contains(':') && !meta.is_temp,
+            "Retention policies can only be set on stored relations, {} is not one",
+            meta.name
+        );
+        if let Some(policy) = &policy {
+            ensure!(
+                meta.metadata
+                    .keys
+                    .iter()
+                    .chain(meta.metadata.non_keys.iter())
+                    .any(|col| col.name == policy.column),
+                RetentionColumnNotFound(policy.column.to_string(), meta.name.to_string(), rel.span)
+            );
+        }
+        meta.retention = policy;
+        self.save_relation_meta(&meta)
+    }
+    /// Set or remove the period for which the history of a relation with validity is kept
+    /// when compacting.
+    pub(crate) fn set_history_retention(&mut self, rel: &Symbol, keep: Option<f64>) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        ensure_validity_key(&meta, rel.span)?;
+        meta.history_keep = keep;
+        self.save_relation_meta(&meta)
+    }
+    /// The stored relations that have a retention policy.
+    pub(crate) fn retention_policies(&self) -> Result<Vec<RelationHandle>> {
+        self.relations_where(|meta| meta.retention.is_some())
+    }
+    fn relations_where(
+        &self,
+        pred: impl Fn(&RelationHandle) -> bool,
+    ) -> Result<Vec<RelationHandle>> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            let meta = RelationHandle::decode(&v_slice)?;
+            if pred(&meta) {
+                ret.push(meta);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Only relations whose last key is of type `Validity` have history.
+fn ensure_validity_key(meta: &RelationHandle, span: SourceSpan) -> Result<()> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Relation {0} has no validity key, so it has no history")]
+    #[diagnostic(code(tx::no_validity_key))]
+    #[diagnostic(help("History is kept for relations whose last key is of type `Validity`"))]
+    struct NoValidityKey(String, #[label] SourceSpan);
+
+    let has_validity = matches!(
+        meta.metadata.keys.last(),
+        Some(col) if col.typing.coltype == ColType::Validity
+    );
+    ensure!(
+        has_validity && !meta.is_temp,
+        NoValidityKey(meta.name.to_string(), span)
+    );
+    Ok(())
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Enforce the retention policies if the scheduler says they are due.
+    /// Failures are logged, since there is no script to report them to.
+    pub(crate) fn maybe_enforce_retention(&'s self) {
+        if self.scheduler.claim_retention_run(false) {
+            if let Err(err) = self.enforce_retention() {
+                error!("failed to enforce retention policies: {err:?}");
+            }
+        }
+    }
+    /// Enforce the retention policies now, returning the number of rows deleted
+    /// from each relation.
+    pub(crate) fn run_retention(&'s self, read_only: bool) -> Result<NamedRows> {
+        if read_only {
+            bail!("Cannot enforce retention policies in read-only mode");
+        }
+        self.scheduler.claim_retention_run(true);
+        self.enforce_retention()
+    }
+    fn enforce_retention(&'s self) -> Result<NamedRows> {
+        let policies = self.transact()?.retention_policies()?;
+        let now = seconds_since_the_epoch()?;
+        let mut rows = vec![];
+        for handle in policies {
+            let policy = handle.retention.as_ref().unwrap();
+            let name = &handle.name;
+            let col = &policy.column;
+            let keys = handle.metadata.keys.iter().map(|c| &c.name).join(", ");
+            let bindings = if handle.metadata.keys.iter().any(|c| c.name == *col) {
+                keys.clone()
+            } else {
+                format!("{keys}, {col}")
+            };
+            // deletions go through `:rm` so that indices and triggers are maintained
+            let script = format!(
+                "?[{keys}] := *{name}{{{bindings}}}, if(is_num({col}), {col} < $cutoff, false) \
+                 :limit {RETENTION_BATCH} :returning :rm {name} {{{keys}}}"
+            );
+            let params =
+                BTreeMap::from([("cutoff".to_string(), DataValue::from(now - policy.keep))]);
+            let mut deleted = 0;
+            loop {
+                let res = self.run_script(&script, params.clone(), ScriptMutability::Mutable)?;
+                let mut requested = 0;
+                for row in &res.rows {
+                    match row[0].get_str() {
+                        Some("requested") => requested += 1,
+                        Some("deleted") => deleted += 1,
+                        _ => {}
+                    }
+                }
+                if requested < RETENTION_BATCH {
+                    break;
+                }
+            }
+            self.scheduler.record_retention(name, now, deleted);
+            rows.push(vec![
+                DataValue::from(name as &str),
+                DataValue::from(deleted as i64),
+            ]);
+        }
+        Ok(NamedRows::new(
+            vec!["relation".to_string(), "deleted".to_string()],
+            rows,
+        ))
+    }
+    /// The retention policies, with statistics of their enforcement.
+    pub(crate) fn retention_status(&'s self, tx: &SessionTx<'_>) -> Result<NamedRows> {
+        let rows = tx
+            .retention_policies()?
+            .into_iter()
+            .map(|handle| {
+                let policy = handle.retention.unwrap();
+                let (last_run, deleted) = match self.scheduler.retention_stats(&handle.name) {
+                    None => (DataValue::Null, DataValue::from(0)),
+                    Some((at, n)) => (DataValue::from(at), DataValue::from(n as i64)),
+                };
+                vec![
+                    DataValue::Str(handle.name),
+                    DataValue::Str(policy.column),
+                    DataValue::from(policy.keep),
+                    last_run,
+                    deleted,
+                ]
+            })
+            .collect_vec();
+        Ok(NamedRows::new(
+            vec![
+                "relation".to_string(),
+                "column".to_string(),
+                "keep".to_string(),
+                "last_run".to_string(),
+                "deleted".to_string(),
+            ],
+            rows,
+        ))
+    }
+    /// Physically delete the facts of a relation with validity that cannot be seen
+    /// by queries as of `before` or later: the facts superseded by a later fact that is
+    /// not later than `before`, and the retractions that are not later than `before`.
+    /// Returns the number of facts deleted.
+    pub(crate) fn purge_history(&'s self, rel: &Symbol, before: ValidityTs) -> Result<usize> {
+        let (handle, doomed) = {
+            let tx = self.transact()?;
+            let handle = tx.get_relation(rel, false)?;
+            ensure_validity_key(&handle, rel.span)?;
+            let n_keys = handle.metadata.keys.len();
+            let mut doomed = vec![];
+            let mut entity: Option<Tuple> = None;
+            let mut seen_visible = false;
+            // the facts of each entity come from the newest to the oldest
+            for tuple in handle.scan_all(&tx) {
+                let mut key = tuple?;
+                key.truncate(n_keys);
+                let vld = match key.last() {
+                    Some(DataValue::Validity(vld)) => *vld,
+                    _ => continue,
+                };
+                if entity.as_deref() != Some(&key[..n_keys - 1]) {
+                    entity = Some(key[..n_keys - 1].to_vec());
+                    seen_visible = false;
+                }
+                if vld.timestamp.0 .0 > before.0 .0 {
+                    continue;
+                }
+                // only the newest fact not later than `before` can still be seen
+                if seen_visible || !vld.is_assert.0 {
+                    doomed.push(DataValue::List(key));
+                }
+                seen_visible = true;
+            }
+            (handle, doomed)
+        };
+        // deletions go through `:rm` so that indices and triggers are maintained
+        let keys = handle.metadata.keys.iter().map(|c| &c.name).join(", ");
+        let script = format!("?[{keys}] <- $rows :rm {} {{{keys}}}", handle.name);
+        for batch in doomed.chunks(RETENTION_BATCH) {
+            let params = BTreeMap::from([("rows".to_string(), DataValue::List(batch.to_vec()))]);
+            self.run_script(&script, params, ScriptMutability::Mutable)?;
+        }
+        Ok(doomed.len())
+    }
+    /// Purge the history of the relations that keep it for a limited period.
+    pub(crate) fn enforce_history_retention(&'s self) -> Result<()> {
+        let relations = self
+            .transact()?
+            .relations_where(|meta| meta.history_keep.is_some())?;
+        let now = current_validity().0 .0;
+        for handle in relations {
+            let keep_micros = (handle.history_keep.unwrap() * 1_000_000.) as i64;
+            let before = ValidityTs(Reverse(now.saturating_sub(keep_micros)));
+            let name = Symbol::new(handle.name.clone(), SourceSpan(0, 0));
+            self.purge_history(&name, before)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The sandbox restricts which files and URLs scripts may reach through fixed rules
+//! such as `CsvReader`, `JsonReader` and `SqlReader`, and through CDC sinks.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+/// Schemes of URLs naming local files, which are checked against [Sandbox::allowed_paths].
+const LOCAL_SCHEMES: [&str; 2] = ["file", "sqlite"];
+
+/// Restrictions on the IO performed by scripts, see [crate::Db::set_sandbox].
+/// The default sandbox forbids all IO.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    /// Schemes of the remote URLs that may be accessed, e.g. `https` or `postgres`.
+    pub allowed_schemes: BTreeSet<String>,
+    /// Directories whose files may be read through `file://` and `sqlite://` URLs.
+    pub allowed_paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Access to '{0}' is forbidden by the sandbox")]
+#[diagnostic(code(eval::sandbox_violation))]
+#[diagnostic(help("{1}"))]
+struct SandboxViolation(String, String);
+
+impl Sandbox {
+    /// Allow remote URLs with the given scheme.
+    pub fn allow_scheme(mut self, scheme: impl Into<String>) -> Self {
+        self.allowed_schemes.insert(scheme.into());
+        self
+    }
+    /// Allow reading the files under the given directory.
+    pub fn allow_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.allowed_paths.push(path.into());
+        self
+    }
+    /// Fails if `url` may not be accessed. URLs of local files must name existing files,
+    /// as their paths are resolved before being checked.
+    pub(crate) fn check_url(&self, url: &str) -> Result<()> {
+        let Some((scheme, rest)) = url.split_once("://") else {
+            bail!(SandboxViolation(
+                url.to_string(),
+                "URLs without a scheme are not allowed".to_string()
+            ))
+        };
+        if LOCAL_SCHEMES.contains(&scheme) {
+            self.check_path(url, Path::new(rest))
+        } else if self.allowed_schemes.contains(scheme) {
+            Ok(())
+        } else {
+            bail!(SandboxViolation(
+                url.to_string(),
+                format!("the scheme '{scheme}' is not allowed")
+            ))
+        }
+    }
+    fn check_path(&self, url: &str, path: &Path) -> Result<()> {
+        // resolving symlinks and `..` prevents escaping the allowed directories
+        let Ok(path) = path.canonicalize() else {
+            bail!(SandboxViolation(
+                url.to_string(),
+                "the file cannot be resolved".to_string()
+            ))
+        };
+        let allowed = self
+            .allowed_paths
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| path.starts_with(root));
+        if !allowed {
+            bail!(SandboxViolation(
+                url.to_string(),
+                "the file is not under an allowed path".to_string()
+            ))
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Admission control for scripts, so that heavy read-only queries
+//! cannot starve writes of the same database instance, and vice versa,
+//! and so that running scripts together stay within a memory budget.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::value::DataValue;
+use crate::runtime::db::Poison;
+use crate::NamedRows;
+
+/// The queue a script is admitted through.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum QueueKind {
+    Read,
+    Write,
+}
+
+#[derive(Default)]
+struct QueueState {
+    limit: Option<usize>,
+    running: usize,
+    waiting: usize,
+    admitted: u64,
+    total_wait: f64,
+    max_wait: f64,
+}
+
+#[derive(Default)]
+struct Queue {
+    state: Mutex<QueueState>,
+    cond: Condvar,
+}
+
+/// How often retention policies are enforced, in seconds.
+#[cfg(not(target_arch = "wasm32"))]
+const RETENTION_INTERVAL: f64 = 60.;
+
+#[derive(Default)]
+struct RetentionRuns {
+    #[cfg(not(target_arch = "wasm32"))]
+    claimed_at: Option<Instant>,
+    /// For each relation, the time of the last run and the number of rows deleted so far.
+    stats: BTreeMap<SmartString<LazyCompact>, (f64, u64)>,
+}
+
+/// New scripts are only admitted while running scripts use less than this fraction
+/// of the memory budget.
+const MEMORY_ADMISSION_RATIO: f64 = 0.9;
+/// Scripts that have waited this many seconds for memory to be freed are rejected.
+#[cfg(not(target_arch = "wasm32"))]
+const MEMORY_MAX_WAIT: f64 = 10.;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Running scripts use {0} bytes of the memory budget of {1} bytes")]
+#[diagnostic(code(eval::memory_budget_exhausted))]
+#[diagnostic(help("Try again later, or raise the budget with `set_memory_budget`"))]
+struct MemoryBudgetExhausted(usize, usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Script exceeded the memory budget of {0} bytes")]
+#[diagnostic(code(eval::memory_budget_exceeded))]
+#[diagnostic(help(
+    "The budget is shared by all running scripts, and the memory used is an estimate"
+))]
+struct MemoryBudgetExceeded(usize);
+
+#[derive(Default)]
+struct MemoryState {
+    budget: Option<usize>,
+    used: usize,
+}
+
+/// Approximate memory used by the temporary data of all running scripts.
+#[derive(Default)]
+struct MemoryBudget {
+    state: Mutex<MemoryState>,
+    cond: Condvar,
+}
+
+/// The memory used by a single running script, which is also counted towards the budget.
+pub(crate) struct MemoryGauge {
+    budget: Arc<MemoryBudget>,
+    used: AtomicUsize,
+}
+
+impl MemoryGauge {
+    /// Record that the script uses `bytes` more memory,
+    /// returning an error if the budget is exceeded as a result.
+    pub(crate) fn add(&self, bytes: usize) -> Result<()> {
+        let mut state = self.budget.state.lock().unwrap();
+        state.used += bytes;
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(budget) = state.budget {
+            if state.used > budget {
+                bail!(MemoryBudgetExceeded(budget))
+            }
+        }
+        Ok(())
+    }
+    fn release(&self) {
+        let freed = self.used.swap(0, Ordering::Relaxed);
+        let mut state = self.budget.state.lock().unwrap();
+        state.used -= freed;
+        self.budget.cond.notify_all();
+    }
+}
+
+/// Per-instance scheduler with separate concurrency limits for read and write scripts,
+/// and a memory budget shared by all running scripts.
+/// Without limits (the default), scripts are admitted immediately.
+/// The scheduler also decides when retention policies are due.
+#[derive(Default)]
+pub(crate) struct QueryScheduler {
+    read: Queue,
+    write: Queue,
+    memory: Arc<MemoryBudget>,
+    retention: Mutex<RetentionRuns>,
+}
+
+/// Releases the slot taken in the queue, and the memory used by the script, when dropped.
+pub(crate) struct SchedulerPermit<'a> {
+    queue: &'a Queue,
+    gauge: Option<Arc<MemoryGauge>>,
+}
+
+impl SchedulerPermit<'_> {
+    /// The gauge to record the memory used by the script in,
+    /// if there was a memory budget when the script was admitted.
+    pub(crate) fn gauge(&self) -> Option<Arc<MemoryGauge>> {
+        self.gauge.clone()
+    }
+}
+
+impl Drop for SchedulerPermit<'_> {
+    fn drop(&mut self) {
+        if let Some(gauge) = &self.gauge {
+            gauge.release();
+        }
+        let mut state = self.queue.state.lock().unwrap();
+        state.running -= 1;
+        self.queue.cond.notify_one();
+    }
+}
+
+impl QueryScheduler {
+    fn queue(&self, kind: QueueKind) -> &Queue {
+        match kind {
+            QueueKind::Read => &self.read,
+            QueueKind::Write => &self.write,
+        }
+    }
+    /// Set the maximum number of concurrently running scripts in each queue.
+    /// `None` means unlimited.
+    pub(crate) fn set_limits(&self, read: Option<usize>, write: Option<usize>) {
+        for (queue, limit) in [(&self.read, read), (&self.write, write)] {
+            queue.state.lock().unwrap().limit = limit;
+            queue.cond.notify_all();
+        }
+    }
+    /// Set the approximate number of bytes that running scripts may use together.
+    /// `None` means unlimited.
+    pub(crate) fn set_memory_budget(&self, budget: Option<usize>) {
+        self.memory.state.lock().unwrap().budget = budget;
+        self.memory.cond.notify_all();
+    }
+    pub(crate) fn memory_budget(&self) -> Option<usize> {
+        self.memory.state.lock().unwrap().budget
+    }
+    /// Wait until the memory used by running scripts is below the admission threshold.
+    /// Scripts are rejected if memory is not freed in time.
+    fn wait_for_memory(&self, poison: &Poison) -> Result<()> {
+        let mut state = self.memory.state.lock().unwrap();
+        let over = |state: &MemoryState| {
+            state
+                .budget
+                .is_some_and(|b| state.used as f64 >= b as f64 * MEMORY_ADMISSION_RATIO)
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let started = Instant::now();
+            while over(&state) && started.elapsed().as_secs_f64() < MEMORY_MAX_WAIT {
+                poison.check()?;
+                state = self
+                    .memory
+                    .cond
+                    .wait_timeout(state, Duration::from_millis(100))
+                    .unwrap()
+                    .0;
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = poison;
+        if over(&state) {
+            bail!(MemoryBudgetExhausted(state.used, state.budget.unwrap()))
+        }
+        Ok(())
+    }
+    /// Wait until the script is allowed to run. The wait is abandoned if `poison` is cancelled.
+    pub(crate) fn acquire(&self, kind: QueueKind, poison: &Poison) -> Result<SchedulerPermit<'_>> {
+        self.wait_for_memory(poison)?;
+        let queue = self.queue(kind);
+        let mut state = queue.state.lock().unwrap();
+        #[cfg(not(target_arch = "wasm32"))]
+        let started = Instant::now();
+        state.waiting += 1;
+        #[cfg(not(target_arch = "wasm32"))]
+        while state.limit.is_some_and(|l| state.running >= l) {
+            if let Err(err) = poison.check() {
+                state.waiting -= 1;
+                // the slot we may have been woken up for should go to someone else
+                queue.cond.notify_one();
+                return Err(err);
+            }
+            state = queue
+                .cond
+                .wait_timeout(state, Duration::from_millis(100))
+                .unwrap()
+                .0;
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = poison;
+        state.waiting -= 1;
+        state.running += 1;
+        state.admitted += 1;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let waited = started.elapsed().as_secs_f64();
+            state.total_wait += waited;
+            if waited > state.max_wait {
+                state.max_wait = waited;
+            }
+        }
+        let gauge = self.memory_budget().map(|_| {
+            Arc::new(MemoryGauge {
+                budget: self.memory.clone(),
+                used: AtomicUsize::new(0),
+            })
+        });
+        Ok(SchedulerPermit { queue, gauge })
+    }
+    /// Metrics of the queues, returned by the `::scheduler` system op.
+    pub(crate) fn metrics(&self) -> NamedRows {
+        let rows = [("read", &self.read), ("write", &self.write)]
+            .into_iter()
+            .map(|(name, queue)| {
+                let state = queue.state.lock().unwrap();
+                vec![
+                    DataValue::from(name),
+                    match state.limit {
+                        None => DataValue::Null,
+                        Some(l) => DataValue::from(l as i64),
+                    },
+                    DataValue::from(state.running as i64),
+                    DataValue::from(state.waiting as i64),
+                    DataValue::from(state.admitted as i64),
+                    DataValue::from(state.total_wait),
+                    DataValue::from(state.max_wait),
+                ]
+            })
+            .collect();
+        NamedRows::new(
+            vec![
+                "queue".to_string(),
+                "limit".to_string(),
+                "running".to_string(),
+                "waiting".to_string(),
+                "admitted".to_string(),
+                "total_wait".to_string(),
+                "max_wait".to_string(),
+            ],
+            rows,
+        )
+    }
+    /// Returns true if the caller should enforce the retention policies now.
+    /// At most one caller is told so per interval, unless `force` is given.
+    pub(crate) fn claim_retention_run(&self, force: bool) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut runs = self.retention.lock().unwrap();
+            let due = force
+                || runs
+                    .claimed_at
+                    .is_none_or(|t| t.elapsed().as_secs_f64() >= RETENTION_INTERVAL);
+            if due {
+                runs.claimed_at = Some(Instant::now());
+            }
+            due
+        }
+        #[cfg(target_arch = "wasm32")]
+        force
+    }
+    pub(crate) fn record_retention(&self, relation: &str, at: f64, deleted: u64) {
+        let mut runs = self.retention.lock().unwrap();
+        let entry = runs.stats.entry(relation.into()).or_default();
+        entry.0 = at;
+        entry.1 += deleted;
+    }
+    /// The time of the last run and the number of rows deleted since the instance was opened.
+    pub(crate) fn retention_stats(&self, relation: &str) -> Option<(f64, u64)> {
+        self.retention.lock().unwrap().stats.get(relation).copied()
+    }
+}
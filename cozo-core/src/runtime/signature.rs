@@ -0,0 +1,390 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The parameters required by scripts, see [crate::Db::script_signature].
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Report, Result};
+use pest::error::InputLocation;
+use pest::Parser;
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::relation::{ColType, NullableColType};
+use crate::data::value::DataValue;
+use crate::parse::schema::parse_nullable_type;
+use crate::parse::{CozoScriptParser, Pair, ParseError, Rule, SourceSpan};
+use crate::runtime::db::Db;
+use crate::runtime::transact::SessionTx;
+use crate::storage::Storage;
+
+/// The parameters required by a script, see [crate::Db::script_signature].
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize)]
+pub struct ScriptSignature {
+    /// The parameters, in the order of their first use in the script.
+    pub params: Vec<ParamSignature>,
+}
+
+/// A parameter required by a script.
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize)]
+pub struct ParamSignature {
+    /// The name of the parameter, without the leading `$`.
+    pub name: String,
+    /// The type inferred from the uses of the parameter, written as in schemas,
+    /// e.g. `Int` or `String?`. It is `Any?` if nothing is known.
+    pub typing: String,
+    /// The positions of the uses of the parameter in the script, in bytes.
+    pub spans: Vec<SourceSpan>,
+    #[serde(skip)]
+    coltype: NullableColType,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Required parameter {0} not given")]
+#[diagnostic(code(eval::param_not_given))]
+struct ParamNotGivenError(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Parameter {0} is expected to be of type {1}")]
+#[diagnostic(code(eval::param_type_mismatch))]
+struct ParamTypeMismatchError(String, String, #[label] SourceSpan, #[related] [Report; 1]);
+
+impl ScriptSignature {
+    /// Check that all the parameters are given and that their values have the inferred types,
+    /// so that bad parameters are reported before the script is run.
+    pub fn validate(&self, params: &BTreeMap<String, DataValue>) -> Result<()> {
+        let cur_vld = current_validity();
+        for param in &self.params {
+            let span = param.spans.first().cloned().unwrap_or_default();
+            match params.get(&param.name) {
+                None => bail!(ParamNotGivenError(param.name.clone(), span)),
+                Some(val) => {
+                    if let Err(err) = param.coltype.coerce(val.clone(), cur_vld) {
+                        bail!(ParamTypeMismatchError(
+                            param.name.clone(),
+                            param.typing.clone(),
+                            span,
+                            [err]
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// The parameters required by the script passed in, without running it.
+    ///
+    /// The type of a parameter is inferred from where it is used: as a column of a stored
+    /// relation, in the data of the entry rule stored with `:put` and the like,
+    /// or as the value of a query option such as `:limit`.
+    pub fn script_signature(&'s self, payload: &str) -> Result<ScriptSignature> {
+        let parsed = CozoScriptParser::parse(Rule::script, payload)
+            .map_err(|err| {
+                let span = match err.location {
+                    InputLocation::Pos(p) => SourceSpan(p, 0),
+                    InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+                };
+                ParseError { span }
+            })?
+            .next()
+            .unwrap();
+        let tx = self.transact()?;
+        let mut inference = Inference {
+            tx: &tx,
+            params: vec![],
+        };
+        inference.visit(parsed)?;
+        Ok(ScriptSignature {
+            params: inference
+                .params
+                .into_iter()
+                .map(|(name, spans, coltype)| {
+                    let coltype = coltype.unwrap_or(NullableColType {
+                        coltype: ColType::Any,
+                        nullable: true,
+                    });
+                    ParamSignature {
+                        name,
+                        typing: coltype.to_string(),
+                        spans,
+                        coltype,
+                    }
+                })
+                .collect_vec(),
+        })
+    }
+}
+
+struct Inference<'a, 't> {
+    tx: &'a SessionTx<'t>,
+    params: Vec<(String, Vec<SourceSpan>, Option<NullableColType>)>,
+}
+
+fn span_of(pair: &Pair<'_>) -> SourceSpan {
+    let span = pair.as_span();
+    SourceSpan(span.start(), span.end() - span.start())
+}
+
+/// The parameter making up the whole of the expression, if any.
+fn lone_param(pair: Pair<'_>) -> Option<Pair<'_>> {
+    if pair.as_rule() != Rule::expr {
+        return None;
+    }
+    let mut inner = pair.into_inner();
+    match (inner.next(), inner.next()) {
+        (Some(p), None) if p.as_rule() == Rule::param => Some(p),
+        _ => None,
+    }
+}
+
+fn simple_type(coltype: ColType) -> NullableColType {
+    NullableColType {
+        coltype,
+        nullable: false,
+    }
+}
+
+impl Inference<'_, '_> {
+    /// Record a use of a parameter, with the type required there if known.
+    /// Different types required at different places are reconciled as `Any`.
+    fn record(&mut self, pair: Pair<'_>, typing: Option<NullableColType>) {
+        let name = pair.as_str().strip_prefix('$').unwrap();
+        let span = span_of(&pair);
+        match self.params.iter_mut().find(|(n, _, _)| n == name) {
+            None => self.params.push((name.to_string(), vec![span], typing)),
+            Some((_, spans, existing)) => {
+                spans.push(span);
+                *existing = match (existing.take(), typing) {
+                    (None, t) | (t, None) => t,
+                    (Some(a), Some(b)) if a.coltype == b.coltype => Some(NullableColType {
+                        coltype: a.coltype,
+                        nullable: a.nullable && b.nullable,
+                    }),
+                    _ => Some(simple_type(ColType::Any)),
+                }
+            }
+        }
+    }
+
+    /// Record the parameter if the expression is just a parameter, otherwise visit it.
+    fn visit_typed(&mut self, expr: Pair<'_>, typing: Option<NullableColType>) -> Result<()> {
+        match lone_param(expr.clone()) {
+            Some(param) => self.record(param, typing),
+            None => self.visit(expr)?,
+        }
+        Ok(())
+    }
+
+    fn visit(&mut self, pair: Pair<'_>) -> Result<()> {
+        match pair.as_rule() {
+            Rule::param => self.record(pair, None),
+            Rule::query_script | Rule::query_script_inner | Rule::query_script_inner_no_bracket => {
+                self.visit_query(pair)?
+            }
+            Rule::relation_apply => {
+                let mut src = pair.into_inner();
+                let columns = self.columns(&src.next().unwrap().as_str()[1..])?;
+                for (i, arg) in src.next().unwrap().into_inner().enumerate() {
+                    let typing = columns.get(i).map(|(_, t)| t.clone());
+                    self.visit_typed(arg, typing)?;
+                }
+                for rest in src {
+                    self.visit(rest)?;
+                }
+            }
+            Rule::relation_column_apply => {
+                let mut src = pair.into_inner();
+                let (name, column) = src.next().unwrap().as_str()[1..].rsplit_once('.').unwrap();
+                let typing = self
+                    .columns(name)?
+                    .into_iter()
+                    .find(|(col, _)| col == column)
+                    .map(|(_, t)| t);
+                self.visit_typed(src.next().unwrap(), typing)?;
+                for rest in src {
+                    self.visit(rest)?;
+                }
+            }
+            Rule::relation_named_apply => {
+                let mut src = pair.into_inner();
+                let columns = self.columns(&src.next().unwrap().as_str()[1..])?;
+                for arg in src.next().unwrap().into_inner() {
+                    let mut arg = arg.into_inner();
+                    let name = arg.next().unwrap().as_str();
+                    if let Some(expr) = arg.next() {
+                        let typing = columns
+                            .iter()
+                            .find(|(col, _)| col == name)
+                            .map(|(_, t)| t.clone());
+                        self.visit_typed(expr, typing)?;
+                    }
+                }
+                for rest in src {
+                    self.visit(rest)?;
+                }
+            }
+            _ => {
+                for inner in pair.into_inner() {
+                    self.visit(inner)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_query(&mut self, pair: Pair<'_>) -> Result<()> {
+        let stored = match pair
+            .clone()
+            .into_inner()
+            .find(|p| p.as_rule() == Rule::relation_option)
+        {
+            Some(opt) => self.stored_columns(opt)?,
+            None => BTreeMap::new(),
+        };
+        for item in pair.into_inner() {
+            match item.as_rule() {
+                Rule::limit_option | Rule::offset_option => {
+                    let expr = item.into_inner().next().unwrap();
+                    self.visit_typed(expr, Some(simple_type(ColType::Int)))?;
+                }
+                Rule::timeout_option | Rule::sleep_option => {
+                    let expr = item.into_inner().next().unwrap();
+                    self.visit_typed(expr, Some(simple_type(ColType::Float)))?;
+                }
+                Rule::const_rule => {
+                    let mut src = item.into_inner();
+                    let head = src.next().unwrap();
+                    let data = src.next().unwrap();
+                    let mut head = head.into_inner();
+                    let is_entry = head.next().unwrap().as_rule() == Rule::prog_entry;
+                    let head_types = head
+                        .map(|arg| {
+                            if is_entry {
+                                stored.get(arg.as_str()).cloned()
+                            } else {
+                                None
+                            }
+                        })
+                        .collect_vec();
+                    self.visit_rows(data, &head_types)?;
+                }
+                _ => self.visit(item)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Visit the data of a constant rule, the columns of which have the types given if known.
+    fn visit_rows(&mut self, data: Pair<'_>, head_types: &[Option<NullableColType>]) -> Result<()> {
+        if let Some(param) = lone_param(data.clone()) {
+            let row = simple_type(ColType::List {
+                eltype: Box::new(NullableColType {
+                    coltype: ColType::Any,
+                    nullable: true,
+                }),
+                len: None,
+            });
+            self.record(
+                param,
+                Some(simple_type(ColType::List {
+                    eltype: Box::new(row),
+                    len: None,
+                })),
+            );
+            return Ok(());
+        }
+        let rows = {
+            let mut inner = data.clone().into_inner();
+            match (inner.next(), inner.next()) {
+                (Some(list), None) if list.as_rule() == Rule::list => Some(list),
+                _ => None,
+            }
+        };
+        let rows = match rows {
+            Some(rows) => rows,
+            None => return self.visit(data),
+        };
+        for row in rows.into_inner() {
+            let cells = {
+                let mut inner = row.clone().into_inner();
+                match (inner.next(), inner.next()) {
+                    (Some(list), None) if list.as_rule() == Rule::list => Some(list),
+                    _ => None,
+                }
+            };
+            match cells {
+                Some(cells) => {
+                    for (i, cell) in cells.into_inner().enumerate() {
+                        let typing = head_types.get(i).cloned().flatten();
+                        self.visit_typed(cell, typing)?;
+                    }
+                }
+                None => self.visit(row)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// The columns of a stored relation with their types, empty if the relation does not exist.
+    fn columns(&self, name: &str) -> Result<Vec<(String, NullableColType)>> {
+        if !self.tx.relation_exists(name)? {
+            return Ok(vec![]);
+        }
+        let handle = self.tx.get_relation(name, false)?;
+        Ok(handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .map(|col| (col.name.to_string(), col.typing.clone()))
+            .collect_vec())
+    }
+
+    /// The types of the columns of the relation the results are stored into,
+    /// by the names of the variables of the entry rule providing them.
+    fn stored_columns(&self, opt: Pair<'_>) -> Result<BTreeMap<String, NullableColType>> {
+        let mut src = opt.into_inner();
+        src.next().unwrap();
+        let existing = self.columns(src.next().unwrap().as_str())?;
+        let mut ret = BTreeMap::new();
+        match src.next() {
+            Some(schema) if schema.as_rule() == Rule::table_schema => {
+                for col in schema.into_inner().flat_map(|cols| cols.into_inner()) {
+                    let mut col = col.into_inner();
+                    let name = col.next().unwrap().as_str();
+                    let mut binding = name;
+                    let mut typing = existing
+                        .iter()
+                        .find(|(n, _)| n == name)
+                        .map(|(_, t)| t.clone());
+                    for rest in col {
+                        match rest.as_rule() {
+                            Rule::col_type => typing = Some(parse_nullable_type(rest)?),
+                            Rule::out_arg => binding = rest.as_str(),
+                            _ => {}
+                        }
+                    }
+                    if let Some(typing) = typing {
+                        ret.insert(binding.to_string(), typing);
+                    }
+                }
+            }
+            _ => {
+                for (name, typing) in existing {
+                    ret.insert(name, typing);
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
@@ -0,0 +1,49 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Spans marking the stages of query execution. With the `tracing` feature they are
+//! [tracing](https://docs.rs/tracing) spans, otherwise they compile to nothing.
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+/// Stand-in for `tracing::Span` when the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn current() -> Self {
+        Span
+    }
+    pub(crate) fn entered(self) -> Self {
+        self
+    }
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+}
+
+/// Create a span at the info level, taking the same arguments as `tracing::info_span!`.
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($($args:tt)*) => {
+        tracing::info_span!($($args)*)
+    };
+}
+
+/// Create a span at the info level, taking the same arguments as `tracing::info_span!`.
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($args:tt)*) => {
+        $crate::runtime::spans::Span
+    };
+}
+
+pub(crate) use span;
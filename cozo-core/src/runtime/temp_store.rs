@@ -19,7 +19,7 @@ use miette::Result;
 
 use crate::data::aggr::Aggregation;
 use crate::data::tuple::Tuple;
-use crate::data::value::DataValue;
+use crate::data::value::{DataValue, Vector};
 
 /// A store holding temp data during evaluation of queries.
 /// The public interface is used in custom implementations of algorithms/utilities.
@@ -243,6 +243,38 @@ impl TempStore {
             TempStore::MeetAggr(m) => m.inner.is_empty(),
         }
     }
+    /// A rough estimate of the memory taken by the tuples of the store.
+    pub(crate) fn approx_bytes(&self) -> usize {
+        match self {
+            TempStore::Normal(n) => n.inner.keys().map(approx_tuple_bytes).sum(),
+            TempStore::MeetAggr(m) => m
+                .inner
+                .iter()
+                .map(|(k, v)| approx_tuple_bytes(k) + approx_tuple_bytes(v))
+                .sum(),
+        }
+    }
+}
+
+/// Overhead of a tuple in a temp store: the node of the map and the vector itself.
+const TUPLE_OVERHEAD: usize = 64;
+
+pub(crate) fn approx_tuple_bytes(tuple: &Tuple) -> usize {
+    TUPLE_OVERHEAD + tuple.iter().map(approx_value_bytes).sum::<usize>()
+}
+
+fn approx_value_bytes(val: &DataValue) -> usize {
+    let heap = match val {
+        DataValue::Str(s) => s.len(),
+        DataValue::Bytes(b) => b.len(),
+        DataValue::List(l) => l.iter().map(approx_value_bytes).sum(),
+        DataValue::Set(s) => s.iter().map(|v| approx_value_bytes(v) + 32).sum(),
+        DataValue::Vec(Vector::F32(v)) => v.len() * 4,
+        DataValue::Vec(Vector::F64(v)) => v.len() * 8,
+        DataValue::Json(j) => j.0.to_string().len() * 2,
+        _ => 0,
+    };
+    mem::size_of::<DataValue>() + heap
 }
 
 #[derive(Debug)]
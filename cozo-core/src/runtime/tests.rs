@@ -7,7 +7,9 @@
  *
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use itertools::Itertools;
@@ -16,14 +18,21 @@ use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
+use crate::data::json::JsonValue;
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
 use crate::fixed_rule::FixedRulePayload;
 use crate::fts::{TokenizerCache, TokenizerConfig};
 use crate::parse::SourceSpan;
 use crate::runtime::callback::CallbackOp;
+use crate::runtime::cron::CronExpr;
 use crate::runtime::db::Poison;
-use crate::{DbInstance, FixedRule, RegularTempStore, ScriptMutability};
+use crate::runtime::scheduler::QueueKind;
+use crate::{
+    format_script, new_cozo_mem, tokenize, CompletionKind, DbInstance, DbPool, FixedRule, Migration, MutationKind,
+    NamedRows, PlanOp, RegularTempStore, RestoreOptions, Sandbox, ScriptMutability, TokenKind,
+    TransactionConflict,
+};
 
 #[test]
 fn test_limit_offset() {
@@ -667,6 +676,303 @@ fn test_multi_tx() {
     assert!(db.run_default("?[a] := *a[a]").is_err());
 }
 
+#[test]
+fn test_transact_with_retries() {
+    let db = DbInstance::default();
+    db.run_default(":create a {a}").unwrap();
+    let mut attempts = 0;
+    let res = db
+        .transact_with_retries(true, 3, |tx| {
+            attempts += 1;
+            tx.run_script(
+                "?[a] <- [[$n]] :put a {a}",
+                BTreeMap::from([("n".to_string(), DataValue::from(attempts))]),
+            )?;
+            if attempts < 3 {
+                miette::bail!(TransactionConflict("simulated".to_string()))
+            }
+            Ok(attempts)
+        })
+        .unwrap();
+    assert_eq!(res, 3);
+    // only the last attempt is committed
+    assert_eq!(
+        db.run_default("?[a] := *a[a]").unwrap().into_json()["rows"],
+        json!([[3]])
+    );
+
+    let mut attempts = 0;
+    let err = db
+        .transact_with_retries(true, 2, |_| -> miette::Result<()> {
+            attempts += 1;
+            miette::bail!(TransactionConflict("simulated".to_string()))
+        })
+        .unwrap_err();
+    assert!(TransactionConflict::is_conflict(&err));
+    assert_eq!(attempts, 3);
+
+    let mut attempts = 0;
+    let err = db
+        .transact_with_retries(true, 2, |tx| {
+            attempts += 1;
+            tx.run_script(":create a {a}", Default::default())
+        })
+        .unwrap_err();
+    assert!(!TransactionConflict::is_conflict(&err));
+    assert_eq!(attempts, 1);
+}
+
+#[test]
+fn test_snapshot() {
+    let db = new_cozo_mem().unwrap();
+    let run = |q: &str| db.run_script(q, Default::default(), ScriptMutability::Mutable);
+    run(":create a {a}").unwrap();
+    run("?[a] <- [[1]] :put a {a}").unwrap();
+    let mut snapshot = db.snapshot().unwrap();
+    // writes proceed while the snapshot is alive
+    run("?[a] <- [[2]] :put a {a}").unwrap();
+    let count = |res: NamedRows| res.into_json()["rows"][0][0].clone();
+    assert_eq!(
+        count(
+            snapshot
+                .run_script("?[count(a)] := *a[a]", Default::default())
+                .unwrap()
+        ),
+        json!(1)
+    );
+    assert_eq!(count(run("?[count(a)] := *a[a]").unwrap()), json!(2));
+    assert!(snapshot
+        .run_script("?[a] <- [[3]] :put a {a}", Default::default())
+        .is_err());
+    assert!(snapshot
+        .run_script("::relations", Default::default())
+        .is_err());
+    drop(snapshot);
+
+    let db = DbInstance::default();
+    db.run_default(":create a {a}").unwrap();
+    db.run_default("?[a] <- [[1]] :put a {a}").unwrap();
+    let snapshot = db.snapshot();
+    assert_eq!(
+        count(
+            snapshot
+                .run_script("?[count(a)] := *a[a]", Default::default())
+                .unwrap()
+        ),
+        json!(1)
+    );
+    db.run_default("?[a] <- [[2]] :rm a {a}").unwrap();
+    db.run_default("?[a] <- [[1]] :rm a {a}").unwrap();
+    assert_eq!(
+        count(
+            snapshot
+                .run_script("?[count(a)] := *a[a]", Default::default())
+                .unwrap()
+        ),
+        json!(1)
+    );
+    snapshot.commit().unwrap();
+    assert!(snapshot
+        .run_script("?[count(a)] := *a[a]", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_mutation_guard() {
+    let db = DbInstance::default();
+    let seen = Arc::new(Mutex::new(vec![]));
+    let recorded = seen.clone();
+    db.set_mutation_guard(move |rel, kind| {
+        recorded.lock().unwrap().push((rel.to_string(), kind));
+        if rel.starts_with("locked") && kind != MutationKind::Create {
+            miette::bail!("relation {rel} is locked")
+        }
+        Ok(())
+    });
+    db.run_default(":create a {k => v}").unwrap();
+    db.run_default(":create locked {k => v}").unwrap();
+    db.run_default("?[k, v] <- [[1, 2]] :put a {k => v}")
+        .unwrap();
+    db.run_default("?[k, v] <- [[1, 2]] :ensure a {k => v}")
+        .unwrap();
+    db.run_default("?[k, v] <- [[1, 2]] :replace _tmp {k => v}")
+        .unwrap();
+    let err = db
+        .run_default("?[k, v] <- [[1, 2]] :put locked {k => v}")
+        .unwrap_err();
+    assert!(err.to_string().contains("locked"));
+    assert!(db.run_default("::index create locked:idx {v}").is_err());
+    assert!(db.run_default("::remove locked").is_err());
+    assert!(db.run_default("::rename a -> locked_a").is_ok());
+    assert!(db.run_default("::rename locked -> b").is_err());
+    assert!(db
+        .import_relations(BTreeMap::from([(
+            "locked".to_string(),
+            NamedRows::new(
+                vec!["k".to_string(), "v".to_string()],
+                vec![vec![DataValue::from(1), DataValue::from(2)]]
+            )
+        )]))
+        .is_err());
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            ("a".to_string(), MutationKind::Create),
+            ("locked".to_string(), MutationKind::Create),
+            ("a".to_string(), MutationKind::Put),
+            ("locked".to_string(), MutationKind::Put),
+            ("locked".to_string(), MutationKind::AlterSchema),
+            ("locked".to_string(), MutationKind::Remove),
+            ("a".to_string(), MutationKind::Rename),
+            ("locked_a".to_string(), MutationKind::Create),
+            ("locked".to_string(), MutationKind::Rename),
+            ("locked".to_string(), MutationKind::Put),
+        ]
+    );
+
+    db.clear_mutation_guard();
+    db.run_default("::remove locked").unwrap();
+}
+
+#[test]
+fn test_query_observer() {
+    let db = DbInstance::default();
+    let events = Arc::new(Mutex::new(vec![]));
+    let recorded = events.clone();
+    db.set_query_observer(move |event| recorded.lock().unwrap().push(event));
+    db.run_default("?[a] <- [[1], [2]]").unwrap();
+    db.run_script(
+        "?[a] := a = $x",
+        BTreeMap::from([("x".to_string(), DataValue::from(1))]),
+        ScriptMutability::Immutable,
+    )
+    .unwrap();
+    db.run_script(
+        "?[a] := a = $x",
+        BTreeMap::from([("x".to_string(), DataValue::from(2))]),
+        ScriptMutability::Immutable,
+    )
+    .unwrap();
+    assert!(db.run_default("?[a] := a = ").is_err());
+    assert!(db
+        .run_script(
+            ":create a {k}",
+            Default::default(),
+            ScriptMutability::Immutable
+        )
+        .is_err());
+    db.clear_query_observer();
+    db.run_default("?[a] <- [[1]]").unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 5);
+    assert_eq!(events[0].script, "?[a] <- [[1], [2]]");
+    assert_eq!(events[0].mutability, ScriptMutability::Mutable);
+    assert_eq!(events[0].rows, Some(2));
+    assert_eq!(events[0].error, None);
+    assert_eq!(events[1].mutability, ScriptMutability::Immutable);
+    assert_eq!(events[1].rows, Some(1));
+    assert_ne!(events[1].params_hash, events[2].params_hash);
+    assert_eq!(events[3].rows, None);
+    assert!(events[3].error.is_some());
+    assert!(events[4].error.is_some());
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing_spans() {
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Records the names of the spans created, with the name of their parent.
+    #[derive(Default)]
+    struct Recorder {
+        spans: Mutex<Vec<(&'static str, Option<&'static str>)>>,
+        stack: Mutex<Vec<u64>>,
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.spans.lock().unwrap();
+            let parent = match span.parent() {
+                Some(id) => Some(id.into_u64()),
+                None if span.is_contextual() => self.stack.lock().unwrap().last().copied(),
+                None => None,
+            };
+            let parent = parent.map(|id| spans[id as usize - 1].0);
+            spans.push((span.metadata().name(), parent));
+            Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+        fn event(&self, _event: &Event<'_>) {}
+        fn enter(&self, span: &Id) {
+            self.stack.lock().unwrap().push(span.into_u64());
+        }
+        fn exit(&self, _span: &Id) {
+            self.stack.lock().unwrap().pop();
+        }
+    }
+
+    let recorder = Arc::new(Recorder::default());
+    let db = DbInstance::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let request = tracing::info_span!("request");
+        db.run_script_in_span(
+            "?[a, b] <~ Constant(data: [[1, 2]]) :create r {a => b}",
+            Default::default(),
+            ScriptMutability::Mutable,
+            &request,
+        )
+        .unwrap();
+    });
+    let spans = recorder.spans.lock().unwrap();
+    for expected in [
+        ("cozo.script", Some("request")),
+        ("cozo.parse", Some("cozo.script")),
+        ("cozo.compile", Some("cozo.script")),
+        ("cozo.stratum", Some("cozo.script")),
+        ("cozo.fixed_rule", Some("cozo.stratum")),
+        ("cozo.commit", Some("cozo.script")),
+    ] {
+        assert!(spans.contains(&expected), "{expected:?} not in {spans:?}");
+    }
+}
+
+#[test]
+fn test_inline_tx() {
+    let db = new_cozo_mem().unwrap();
+    let (_id, receiver) = db.register_callback("a", None);
+    let mut tx = db.inline_transaction(true).unwrap();
+    tx.run_script(":create a {a}", Default::default()).unwrap();
+    tx.run_script("?[a] <- [[1], [2]] :put a {a}", Default::default())
+        .unwrap();
+    assert!(tx.run_script(":create a {a}", Default::default()).is_err());
+    let res = tx
+        .run_script("?[count(a)] := *a[a]", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2]]));
+    assert!(receiver.try_recv().is_err());
+    tx.commit().unwrap();
+    let (op, new, _) = receiver.try_recv().unwrap();
+    assert_eq!(op, CallbackOp::Put);
+    assert_eq!(new.into_json()["rows"], json!([[1], [2]]));
+
+    let mut tx = db.inline_transaction(true).unwrap();
+    tx.run_script("?[a] <- [[3]] :put a {a}", Default::default())
+        .unwrap();
+    tx.abort();
+    assert_eq!(
+        db.run_script("?[a] := *a[a]", Default::default(), ScriptMutability::Immutable)
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1], [2]])
+    );
+}
+
 #[test]
 fn test_vec_types() {
     let db = DbInstance::new("mem", "", "").unwrap();
@@ -808,6 +1114,64 @@ fn test_vec_index() {
     }
 }
 
+#[test]
+fn test_vec_index_quantization() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let rows = |q: &str| db.run_default(q).unwrap().into_json()["rows"].clone();
+    db.run_default(r":create a {k: Int => v: <F32; 4>}").unwrap();
+    assert!(db
+        .run_default(r"::hnsw create a:pq {dim: 4, m: 16, dtype: F32, distance: L2, fields: [v], quantization: PQ}")
+        .is_err());
+    db.run_default(
+        r"?[k, v] := k in int_range(100), v = vec([k, k * 2, 100 - k, k % 7]) :put a {k => v}",
+    )
+    .unwrap();
+    db.run_default(
+        r"::hnsw create a:sq {dim: 4, m: 16, dtype: F32, distance: L2, fields: [v], ef_construction: 20, quantization: SQ}",
+    )
+    .unwrap();
+    db.run_default(
+        r"::hnsw create a:pq {dim: 4, m: 16, dtype: F32, distance: L2, fields: [v], ef_construction: 20, quantization: PQ, pq_subspaces: 2}",
+    )
+    .unwrap();
+    assert!(db
+        .run_default(r"::hnsw create a:bad {dim: 4, m: 16, dtype: F32, distance: L2, fields: [v], pq_subspaces: 2}")
+        .is_err());
+    assert!(db
+        .run_default(r"::hnsw create a:bad {dim: 4, m: 16, dtype: F32, distance: L2, fields: [v], quantization: PQ, pq_subspaces: 3}")
+        .is_err());
+    let indices = rows("::indices a");
+    let quantization = |name: &str| {
+        indices
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r[0] == json!(name))
+            .unwrap()[3]["quantization"]
+            .clone()
+    };
+    assert_eq!(quantization("sq"), json!("SQ"));
+    assert_eq!(quantization("pq"), json!({"PQ": {"subspaces": 2}}));
+
+    // rows inserted after creation are encoded too, and distances are exact after re-ranking
+    db.run_default(r"?[k, v] <- [[1000, [40.5, 81, 59.5, 5]]] :put a {k => v}")
+        .unwrap();
+    for idx in ["sq", "pq"] {
+        let res = rows(&format!(
+            "?[d, k] := ~a:{idx}{{k | query: q, k: 3, ef: 50, bind_distance: d}}, q = vec([40.5, 81, 59.5, 5]) :order d"
+        ));
+        assert_eq!(res[0], json!([0.0, 1000]));
+        assert_eq!(res.as_array().unwrap().len(), 3);
+    }
+    db.run_default(r"?[k] <- [[1000]] :rm a {k}").unwrap();
+    let res = rows("?[k] := ~a:sq{k | query: q, k: 1, ef: 50}, q = vec([40.5, 81, 59.5, 5])");
+    assert_ne!(res[0][0], json!(1000));
+
+    assert!(db.run_default("?[k] := *a:pq:codes{k}").is_ok());
+    db.run_default("::hnsw drop a:pq").unwrap();
+    assert!(db.run_default("?[k] := *a:pq:codes{k}").is_err());
+}
+
 #[test]
 fn test_fts_indexing() {
     let db = DbInstance::new("mem", "", "").unwrap();
@@ -1036,6 +1400,42 @@ fn test_lsh_indexing() {
     db.run_default(r"::lsh drop a:lsh").unwrap();
 }
 
+#[test]
+fn test_lsh_similarity_and_shingling() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let rows = |q: &str| db.run_default(q).unwrap().into_json()["rows"].clone();
+    db.run_default(r":create a {k: String => v: String}")
+        .unwrap();
+    db.run_default(
+        r"?[k, v] <- [['a', 'Hello World'], ['b', 'hello  world!'], ['c', 'goodbye moon']] :put a {k => v}",
+    )
+    .unwrap();
+    db.run_default(
+        r"::lsh create a:lsh {extractor: v, tokenizer: Simple, filters: [Lowercase], shingling: Char, n_gram: 3, target_threshold: 0.5 }",
+    )
+    .unwrap();
+    assert!(db
+        .run_default(r"::lsh create a:bad {extractor: v, shingling: Letter}")
+        .is_err());
+    assert_eq!(rows("::indices a")[0][3]["shingling"], json!("Char"));
+
+    // char shingles see through case and spacing
+    let res = rows(r"?[k, s] := ~a:lsh{k | query: 'hello world', bind_similarity: s}");
+    let res = res.as_array().unwrap();
+    assert_eq!(res.len(), 2);
+    for row in res {
+        assert_eq!(row[1], json!(1.0));
+    }
+    let res = rows(r"?[k, s] := ~a:lsh{k | query: 'hello world!!', k: 1, bind_similarity: s}");
+    assert_eq!(res.as_array().unwrap().len(), 1);
+    let s = res[0][1].as_f64().unwrap();
+    assert!(s > 0.5 && s <= 1.0);
+    assert_eq!(
+        rows(r"?[k] := ~a:lsh{k | query: 'hello world', bind_similarity: s, filter: s < 0.5}"),
+        json!([])
+    );
+}
+
 #[test]
 fn test_insertions() {
     let db = DbInstance::new("mem", "", "").unwrap();
@@ -1612,3 +2012,4343 @@ fn fts_drop() {
     )
     .unwrap();
 }
+
+#[test]
+fn cancel_with_handle() {
+    let db = DbInstance::default();
+    let handle = Poison::default();
+    let h = handle.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        h.cancel();
+    });
+    let res = db.run_script_with_handle(
+        r#"
+        r[n] := n = 0
+        r[n] := r[m], n = m + 1
+        ?[n] := r[n]
+        "#,
+        Default::default(),
+        ScriptMutability::Immutable,
+        &handle,
+    );
+    assert!(res.is_err());
+    assert!(handle.is_cancelled());
+
+    // a cancelled handle terminates subsequent scripts immediately
+    let res = db.run_script_with_handle(
+        r#"{?[a] <- [[1]]}"#,
+        Default::default(),
+        ScriptMutability::Immutable,
+        &handle,
+    );
+    assert!(res.is_err());
+    // but not those run without it
+    db.run_default(r#"?[a] <- [[1]]"#).unwrap();
+}
+
+#[test]
+fn default_timeout() {
+    let db = DbInstance::default();
+    assert!(db.set_default_timeout(Some(0.)).is_err());
+    db.set_default_timeout(Some(0.1)).unwrap();
+    let res = db.run_default(
+        r#"
+        r[n] := n = 0
+        r[n] := r[m], n = m + 1
+        ?[n] := r[n]
+        "#,
+    );
+    assert!(res.is_err());
+    db.set_default_timeout(None).unwrap();
+    db.run_default(r#"?[a] <- [[1]]"#).unwrap();
+}
+
+#[test]
+fn scheduler_limits() {
+    let db = DbInstance::default();
+    assert!(db.set_max_concurrency(Some(0), None).is_err());
+    db.set_max_concurrency(Some(1), Some(1)).unwrap();
+    db.run_default(":create a {x}").unwrap();
+
+    let handles = (0..4)
+        .map(|i| {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                db.run_script(
+                    "?[x] <- [[$x]] :put a {x}",
+                    BTreeMap::from([("x".to_string(), DataValue::from(i))]),
+                    ScriptMutability::Mutable,
+                )
+                .unwrap();
+                db.run_script(
+                    "?[x] := *a{x}",
+                    Default::default(),
+                    ScriptMutability::Immutable,
+                )
+                .unwrap();
+            })
+        })
+        .collect_vec();
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    let res = db.run_default("::scheduler").unwrap().into_json();
+    assert_eq!(
+        res["headers"],
+        json!([
+            "queue",
+            "limit",
+            "running",
+            "waiting",
+            "admitted",
+            "total_wait",
+            "max_wait"
+        ])
+    );
+    assert_eq!(res["rows"][0][0], json!("read"));
+    assert_eq!(res["rows"][0][1], json!(1));
+    assert_eq!(res["rows"][0][2], json!(0));
+    assert_eq!(res["rows"][0][4], json!(4));
+    assert_eq!(res["rows"][1][0], json!("write"));
+    assert_eq!(res["rows"][1][4], json!(5));
+
+    // a cancelled script does not wait in the queue forever
+    let db = DbInstance::default();
+    db.set_max_concurrency(Some(1), None).unwrap();
+    let handle = Poison::default();
+    let blocker = {
+        let db = db.clone();
+        let handle = handle.clone();
+        std::thread::spawn(move || {
+            db.run_script_with_handle(
+                r#"
+                r[n] := n = 0
+                r[n] := r[m], n = m + 1
+                ?[n] := r[n]
+                "#,
+                Default::default(),
+                ScriptMutability::Immutable,
+                &handle,
+            )
+        })
+    };
+    std::thread::sleep(Duration::from_millis(50));
+    let waiter = Poison::default();
+    {
+        let waiter = waiter.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            waiter.cancel();
+        });
+    }
+    assert!(db
+        .run_script_with_handle(
+            "?[a] <- [[1]]",
+            Default::default(),
+            ScriptMutability::Immutable,
+            &waiter
+        )
+        .is_err());
+    handle.cancel();
+    assert!(blocker.join().unwrap().is_err());
+    db.run_default("?[a] <- [[1]]").unwrap();
+}
+
+#[test]
+fn band_join() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r#"
+        a[id, t] <- [['a1', 1.0], ['a2', 5.0], ['a3', 10.0], ['a4', 10.5]]
+        b[id, t] <- [['b1', 0.0], ['b2', 2.0], ['b3', 9.0], ['b4', 20]]
+        ?[a, ta, b, tb] <~ BandJoin(a[a, ta], b[b, tb], left_key: ta, right_key: tb, window: 1.5)
+        :order a, b
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["a1", 1.0, "b1", 0.0],
+            ["a1", 1.0, "b2", 2.0],
+            ["a3", 10.0, "b3", 9.0]
+        ])
+    );
+    let res = db
+        .run_default(
+            r#"
+        a[id, t] <- [['a1', 1.0], ['a2', 5.0], ['a3', 10.0], ['a4', 10.5]]
+        b[id, t] <- [['b1', 0.0], ['b2', 2.0], ['b3', 9.0], ['b4', 20]]
+        ?[a, ta, b, tb] <~ BandJoin(a[a, ta], b[b, tb], left_key: ta, right_key: tb, window: 1.5, inclusive: true)
+        :order a, b
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["a1", 1.0, "b1", 0.0],
+            ["a1", 1.0, "b2", 2.0],
+            ["a3", 10.0, "b3", 9.0],
+            ["a4", 10.5, "b3", 9.0]
+        ])
+    );
+    assert!(db
+        .run_default(
+            r#"
+        a[id, t] <- [['a1', 1.0]]
+        ?[a, ta, b] <~ BandJoin(a[a, ta], a[b, tb], left_key: ta, right_key: tb, window: 1)
+        "#,
+        )
+        .is_err());
+}
+
+#[test]
+fn resample_and_fill_gaps() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r#"
+        data[t, v] <- [[0, 1], [3, 3], [4, 5], [11, 10], [1, 2]]
+        ?[bucket, v] <~ Resample(data[t, v], time: t, value: v, interval: 5)
+        "#,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[0, 2.75], [10, 10.0]]));
+    let res = db
+        .run_default(
+            r#"
+        data[s, t, v] <- [['a', 0, 1], ['a', 3, 3], ['b', 12, 4]]
+        ?[s, bucket, v] <~ Resample(data[s, t, v], time: t, value: v, by: [s],
+                                    interval: 5, aggregation: 'count')
+        "#,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a", 0, 2], ["b", 10, 1]]));
+    let res = db
+        .run_default(
+            r#"
+        data[t, v] <- [[0, 1.0], [15, 4.0]]
+        ?[bucket, v] <~ FillGaps(data[t, v], time: t, value: v, interval: 5)
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[0, 1.0], [5, 1.0], [10, 1.0], [15, 4.0]])
+    );
+    let res = db
+        .run_default(
+            r#"
+        data[t, v] <- [[0, 1.0], [15, 4.0]]
+        ?[bucket, v] <~ FillGaps(data[t, v], time: t, value: v, interval: 5,
+                                 method: 'linear', end: 20)
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[0, 1.0], [5, 2.0], [10, 3.0], [15, 4.0], [20, null]])
+    );
+    assert!(db
+        .run_default(
+            r#"
+        data[t, v] <- [[0, 1.0]]
+        ?[bucket, v] <~ FillGaps(data[t, v], time: t, value: v, interval: 0)
+        "#,
+        )
+        .is_err());
+    assert!(db
+        .run_default(
+            r#"
+        data[t, v] <- [[0, 1.0], [5000000000, 2.0]]
+        ?[bucket, v] <~ FillGaps(data[t, v], time: t, value: v, interval: 1)
+        "#,
+        )
+        .is_err());
+    assert!(db
+        .run_default(
+            r#"
+        data[t, v] <- [[9223372036854775807, 1.0]]
+        ?[bucket, v] <~ Resample(data[t, v], time: t, value: v, interval: 10, origin: -10)
+        "#,
+        )
+        .is_err());
+}
+
+#[test]
+fn rollup() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        {:create metrics {s: String, t: Int => v: Float}}
+        {?[s, t, v] <- [['a', 0, 1], ['a', 5, 3], ['a', 12, 4], ['b', 3, 2]]
+         :put metrics {s, t => v}}
+        {:create rollups {s: String, bucket: Int => n: Int, mean: Float, hi: Float}}
+        ",
+    )
+    .unwrap();
+    let query = r"
+        ?[s, bucket, n, mean, hi] <~ Rollup(*metrics[s, t, v], *rollups[s0, b, n, m, h],
+                                            time: t, by: [s], interval: 10,
+                                            aggregations: [['count', v], ['mean', v], ['max', v]])
+    ";
+    let rows = |q: &str| db.run_default(q).unwrap().into_json()["rows"].clone();
+    assert_eq!(
+        rows(query),
+        json!([
+            ["a", 0, 2, 2.0, 3.0],
+            ["a", 10, 1, 4.0, 4.0],
+            ["b", 0, 1, 2.0, 2.0]
+        ])
+    );
+    db.run_default(&format!(
+        "{query} :put rollups {{s, bucket => n, mean, hi}}"
+    ))
+    .unwrap();
+
+    // only the latest bucket of each group is computed again
+    db.run_default("?[s, t, v] <- [['a', 15, 6], ['a', 2, 100]] :put metrics {s, t => v}")
+        .unwrap();
+    assert_eq!(
+        rows(query),
+        json!([["a", 10, 2, 5.0, 6.0], ["b", 0, 1, 2.0, 2.0]])
+    );
+    db.run_default(&format!(
+        "{query} :put rollups {{s, bucket => n, mean, hi}}"
+    ))
+    .unwrap();
+    assert_eq!(
+        rows("?[s, bucket, n, mean, hi] := *rollups[s, bucket, n, mean, hi]"),
+        json!([
+            ["a", 0, 2, 2.0, 3.0],
+            ["a", 10, 2, 5.0, 6.0],
+            ["b", 0, 1, 2.0, 2.0]
+        ])
+    );
+
+    assert!(db
+        .run_default(
+            r"
+        data[t, v] <- [[0, 1.0]]
+        ?[bucket, v] <~ Rollup(data[t, v], time: t, interval: 5, aggregations: [['nope', v]])
+        ",
+        )
+        .is_err());
+}
+
+#[test]
+fn sample() {
+    let db = DbInstance::default();
+    db.run_default(":create nums {x: Int => y: Int}").unwrap();
+    db.run_default("?[x, y] := x in int_range(1000), y = x * 2 :put nums {x => y}")
+        .unwrap();
+    let rows = |q: &str| {
+        db.run_default(q).unwrap().into_json()["rows"]
+            .as_array()
+            .unwrap()
+            .clone()
+    };
+
+    let sampled = rows("?[x, y] <~ Sample(*nums[x, y], n: 10)");
+    assert_eq!(sampled.len(), 10);
+    assert!(sampled
+        .iter()
+        .all(|r| r[1] == json!(r[0].as_i64().unwrap() * 2)));
+    assert_eq!(rows("?[x, y] <~ Sample(*nums[x, y], n: 2000)").len(), 1000);
+    let script = "?[x, y] <~ Sample(*nums[x, y], n: 5) :seed 7";
+    assert_eq!(rows(script), rows(script));
+
+    let sampled = rows("?[x, y] <~ Sample(*nums[x, y], p: 0.5)");
+    assert!(sampled.len() > 350 && sampled.len() < 650);
+    assert_eq!(rows("?[x, y] <~ Sample(*nums[x, y], p: 0)").len(), 0);
+    assert_eq!(rows("?[x, y] <~ Sample(*nums[x, y], p: 1)").len(), 1000);
+
+    assert!(db
+        .run_default("?[x, y] <~ Sample(*nums[x, y], n: 1, p: 0.5)")
+        .is_err());
+    assert!(db.run_default("?[x] <~ Sample(*nums[x, y], n: 1)").is_err());
+}
+
+#[test]
+fn sessionize_and_funnel() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r#"
+        events[u, t] <- [['a', 0], ['a', 5], ['a', 40], ['b', 3], ['a', 12]]
+        ?[u, session, start, end, n] <~ Sessionize(events[u, t], gap: 10)
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["a", 0, 0, 12, 3], ["a", 1, 40, 40, 1], ["b", 0, 3, 3, 1]])
+    );
+    let res = db
+        .run_default(
+            r#"
+        events[u, t, e] <- [['a', 0, 'view'], ['a', 5, 'cart'], ['a', 50, 'buy'],
+                            ['b', 1, 'cart'], ['b', 2, 'view'], ['b', 3, 'cart'], ['b', 4, 'buy'],
+                            ['c', 7, 'buy']]
+        ?[u, reached, start, end] <~ Funnel(events[u, t, e], steps: ['view', 'cart', 'buy'],
+                                            window: 20)
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["a", 2, 0, 5], ["b", 3, 2, 4]])
+    );
+    assert!(db
+        .run_default(
+            r#"
+        events[u, t, e] <- [['a', 0, 'view']]
+        ?[u, reached, start, end] <~ Funnel(events[u, t, e], steps: [])
+        "#,
+        )
+        .is_err());
+}
+
+#[test]
+fn check_program() {
+    let db = DbInstance::default();
+    let codes = |script: &str| -> Vec<(String, String)> {
+        let res = db.run_default(script).unwrap();
+        res.rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row[0].get_str().unwrap().to_string(),
+                    row[2].get_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect()
+    };
+    let ok = |sev: &str, code: &str| vec![(sev.to_string(), code.to_string())];
+    assert!(codes("::check { e[a, b] <- [[1, 2]] ?[a] := e[a, _] }").is_empty());
+    assert_eq!(
+        codes("::check { a[x] := x = 1 b[x] := x = 2 ?[x] := a[x] }"),
+        ok("warning", "unused_rule")
+    );
+    assert_eq!(
+        codes("::check { ?[x] := x = 1, x = 2 }"),
+        ok("warning", "always_empty")
+    );
+    assert_eq!(
+        codes("::check { e[x] <- [] ?[x] := e[x], x > 1 }"),
+        ok("warning", "always_empty")
+    );
+    assert_eq!(
+        codes(
+            "::check { e[a, b] <- [[1, 2]] r[a, b] := e[a, b] r[a, c] := r[a, b], r[b, c] ?[a, b] := r[a, b] }"
+        ),
+        ok("warning", "non_tail_recursion")
+    );
+    let found = codes("::check { ?[x, y] := x = 1 }");
+    assert_eq!(
+        found[0],
+        ("warning".to_string(), "unbound_head_variable".to_string())
+    );
+    assert_eq!(found[1].0, "error");
+    // nothing is executed
+    db.run_default("::check { ?[x] <- [[1]] :create a {x} }")
+        .unwrap();
+    assert!(db.run_default("?[x] := *a[x]").is_err());
+}
+
+#[test]
+fn retention_policies() {
+    let db = DbInstance::default();
+    db.run_default(":create events {id: Int => ts: Float?}")
+        .unwrap();
+    db.run_default("::index create events:by_ts {ts}").unwrap();
+    db.run_default(":create removed {id: Int}").unwrap();
+    db.run_default(r#"::set_triggers events on rm { ?[id] := _old[id, _] :put removed {id} }"#)
+        .unwrap();
+    db.run_default(
+        r#"
+        ?[id, ts] := id = 1, ts = now() - 100 * 86400
+        ?[id, ts] := id = 2, ts = now() - 10 * 86400
+        ?[id, ts] := id = 3, ts = null
+        :put events {id => ts}
+        "#,
+    )
+    .unwrap();
+    assert!(db
+        .run_default("::retention set events keep 90d on nonexistent")
+        .is_err());
+    db.run_default("::retention set events keep 90d on ts")
+        .unwrap();
+    let res = db.run_default("::retention status").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["events", "ts", 7776000.0, null, 0]])
+    );
+    let res = db.run_default("::retention run").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["events", 1]]));
+    let res = db.run_default("?[id] := *events{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [3]]));
+    let res = db.run_default("?[id] := *events:by_ts{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [3]]));
+    let res = db.run_default("?[id] := *removed{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+    let res = db.run_default("::retention status").unwrap();
+    assert_eq!(res.rows[0][4], DataValue::from(1));
+    db.run_default("?[id, ts] := id = 4, ts = now() - 100 * 86400 :put events {id => ts}")
+        .unwrap();
+    let res = db.run_default("?[id] := *events{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [3], [4]]));
+    db.run_default("::compact").unwrap();
+    let res = db.run_default("?[id] := *events{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [3]]));
+    db.run_default("::retention remove events").unwrap();
+    let res = db.run_default("::retention status").unwrap();
+    assert!(res.rows.is_empty());
+}
+
+#[test]
+fn history_purge() {
+    let db = DbInstance::default();
+    db.run_default(":create hist {k: Int, vld: Validity => v: Int}")
+        .unwrap();
+    db.run_default("::index create hist:by_v {v}").unwrap();
+    db.run_default(
+        r#"
+        ?[k, vld, v] <- [[1, [10, true], 1], [1, [20, true], 2], [1, [30, true], 3],
+                         [2, [10, true], 1], [2, [20, false], 1],
+                         [3, [40, true], 4]]
+        :put hist {k, vld => v}
+        "#,
+    )
+    .unwrap();
+    let as_of_25 = "?[k, v] := *hist{k, v @ 25}";
+    let before = db.run_default(as_of_25).unwrap();
+
+    let res = db.run_default("::history purge hist before 25").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3]]));
+    assert_eq!(db.run_default(as_of_25).unwrap().rows, before.rows);
+    let res = db
+        .run_default("?[k, t] := *hist{k, vld}, t = to_int(vld)")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 20], [1, 30], [3, 40]]));
+    let res = db.run_default("?[v] := *hist:by_v{v}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [3], [4]]));
+
+    let res = db
+        .run_default("::history purge hist before '2000-01-01T00:00:00Z'")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+
+    db.run_default(":create plain {k: Int => v: Int}").unwrap();
+    assert!(db.run_default("::history purge plain before 25").is_err());
+    assert!(db.run_default("::history set plain keep 1d").is_err());
+
+    db.run_default(
+        "?[k, vld, v] <- [[5, [10, true], 1], [5, [20, true], 2]] :put hist {k, vld => v}",
+    )
+    .unwrap();
+    db.run_default("::history set hist keep 1d").unwrap();
+    db.run_default("::compact").unwrap();
+    let res = db
+        .run_default("?[k, t] := *hist{k, vld}, t = to_int(vld)")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 30], [3, 40], [5, 20]]));
+    db.run_default("::history remove hist").unwrap();
+}
+
+#[test]
+fn cron_expressions() {
+    // 2024-01-01T00:00:00Z, a Monday
+    let t0 = 1704067200.;
+    let next = |expr: &str, after: f64| CronExpr::parse(expr).unwrap().next_after(after);
+    assert_eq!(next("30 2 * * *", t0), Some(t0 + 9000.));
+    assert_eq!(next("*/15 * * * *", t0), Some(t0 + 900.));
+    assert_eq!(next("*/15 * * * *", t0 + 1.), Some(t0 + 900.));
+    assert_eq!(next("@weekly", t0), Some(t0 + 6. * 86400.));
+    assert_eq!(next("0 0 * * 7", t0), Some(t0 + 6. * 86400.));
+    // the 13th or a Friday
+    assert_eq!(next("0 0 13 * 5", t0), Some(t0 + 4. * 86400.));
+    assert_eq!(next("0 0 1 3 *", t0), Some(t0 + 60. * 86400.));
+    assert_eq!(next("0 0 30 2 *", t0), None);
+    assert!(CronExpr::parse("61 * * * *").is_err());
+    assert!(CronExpr::parse("* * *").is_err());
+    assert!(CronExpr::parse("5-1 * * * *").is_err());
+}
+
+#[test]
+fn scheduled_scripts() {
+    let t0 = 1704067200.;
+    let db = DbInstance::default();
+    db.run_default(":create ticks {x: Int}").unwrap();
+    assert!(db
+        .run_default("::schedule add tick '* * * 13 *' '?[x] <- [[1]]'")
+        .is_err());
+    db.run_default("::schedule add tick '* * * * *' '?[x] <- [[1]] :put ticks {x}'")
+        .unwrap();
+    db.run_default("::schedule add broken '@hourly' '?[x] := y'")
+        .unwrap();
+    let res = db.run_default("::schedule list").unwrap();
+    assert_eq!(res.rows.len(), 2);
+    assert_eq!(res.rows[1][1], DataValue::from("* * * * *"));
+
+    let res = db.run_scheduled_scripts(t0, t0 + 60.).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["tick", true]]));
+    let res = db.run_scheduled_scripts(t0 + 60., t0 + 3600.).unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["broken", false], ["tick", true]])
+    );
+    let res = db.run_scheduled_scripts(t0 + 3600., t0 + 3600.).unwrap();
+    assert!(res.rows.is_empty());
+
+    let res = db.run_default("?[x] := *ticks{x}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+    let res = db
+        .run_default("?[name, count(at)] := *schedule_log{name, at, ok}, ok")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["tick", 2]]));
+    let res = db
+        .run_default("?[result] := *schedule_log{name: 'broken', result}")
+        .unwrap();
+    assert!(res.rows[0][0].to_string().contains("unbound"));
+    assert!(db.run_default("?[name, at] <- [] :rm schedule_log {name, at}").is_err());
+
+    db.run_default("::schedule remove broken").unwrap();
+    assert!(db.run_default("::schedule remove broken").is_err());
+    let res = db.run_default("::schedule list").unwrap();
+    assert_eq!(res.rows.len(), 1);
+}
+
+#[test]
+fn verify_relation() {
+    let db = new_cozo_mem().unwrap();
+    let run = |script: &str| {
+        db.run_script(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap()
+    };
+    run(":create users {id: Int => name: String, age: Int}");
+    run("::index create users:by_age {age}");
+    run("?[id, name, age] <- [[1, 'alice', 30], [2, 'bob', 40]] :put users {id => name, age}");
+    let res = run("::verify users").into_json();
+    assert_eq!(res["rows"][0][0], json!("users"));
+    assert_eq!(res["rows"][1][0], json!("users:by_age"));
+    assert_eq!(res["rows"][1][2], json!(2));
+    assert_eq!(res["rows"][1][4], json!(0));
+    assert_eq!(res["rows"][1][7], json!(true));
+
+    // the checksum only depends on the contents
+    let other = new_cozo_mem().unwrap();
+    let run_other = |script: &str| {
+        other
+            .run_script(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap()
+    };
+    run_other(":create padding {x}");
+    run_other(":create users {id: Int => name: String, age: Int}");
+    run_other(
+        "?[id, name, age] <- [[2, 'bob', 40], [1, 'alice', 30]] :put users {id => name, age}",
+    );
+    let res_other = run_other("::verify users").into_json();
+    assert_eq!(res_other["rows"][0][3], res["rows"][0][3]);
+
+    // break the index behind the back of the relation
+    {
+        let mut tx = db.transact_write().unwrap();
+        let handle = tx.get_relation("users", false).unwrap();
+        let (idx, _) = handle.indices.get("by_age").unwrap();
+        let key = idx
+            .encode_key_for_store(
+                &[DataValue::from(30), DataValue::from(1)],
+                Default::default(),
+            )
+            .unwrap();
+        tx.store_tx.del(&key).unwrap();
+        let key = idx
+            .encode_key_for_store(
+                &[DataValue::from(50), DataValue::from(3)],
+                Default::default(),
+            )
+            .unwrap();
+        tx.store_tx.put(&key, &[]).unwrap();
+        tx.commit_tx().unwrap();
+    }
+    let res = run("::verify users").into_json();
+    assert_eq!(res["rows"][1][4], json!(1));
+    assert_eq!(res["rows"][1][5], json!(1));
+    assert_eq!(
+        res["rows"][1][6],
+        json!([{"kind": "missing", "tuple": [30, 1]}, {"kind": "dangling", "tuple": [50, 3]}])
+    );
+    assert_eq!(res["rows"][1][7], json!(false));
+    assert!(db
+        .run_script(
+            "::verify nonexistent",
+            Default::default(),
+            ScriptMutability::Immutable
+        )
+        .is_err());
+}
+
+#[test]
+fn query_seed() {
+    let db = DbInstance::default();
+    let run = |script: &str| db.run_default(script).unwrap().rows;
+    let script = r#"
+        ?[i, r, f, u] := i in int_range(5), r = rand_int(0, 1000000 + i), f = rand_float(),
+                         u = rand_uuid_v4()
+        :seed 42
+    "#;
+    assert_eq!(run(script), run(script));
+    assert_ne!(run(script), run(&script.replace("42", "43")));
+    assert_ne!(run(&script.replace(":seed 42", "")), run(script));
+
+    let walk = r#"
+        edges[] <- [[1, 2], [1, 3], [1, 4], [2, 1], [3, 1], [4, 1]]
+        nodes[n] := edges[n, _]
+        start[] <- [[1]]
+        ?[] <~ RandomWalk(edges[], nodes[], start[], steps: 20, iterations: 5)
+        :seed 1234
+    "#;
+    assert_eq!(run(walk), run(walk));
+}
+
+#[test]
+fn outbox() {
+    let db = DbInstance::default();
+    db.run_default(":create users {id: Int => name: String}")
+        .unwrap();
+    let res = db
+        .run_default(
+            r#"
+            {
+                ?[id, name] <- [[1, 'alice'], [2, 'bob']]
+                :put users {id => name}
+            }
+            {
+                ?[id, name] <- [[1, 'alice'], [2, 'bob']]
+                :outbox 'user_created'
+            }
+            "#,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2]]));
+
+    // events of failed transactions are never delivered
+    assert!(db
+        .run_default(
+            r#"
+            {
+                ?[id] <- [[3]]
+                :outbox 'user_created'
+            }
+            {
+                ?[id] <- [[3]]
+                :assert none
+            }
+            "#,
+        )
+        .is_err());
+    // the outbox does not take the name of a user relation
+    db.run_default(":create outbox {seq: Int}").unwrap();
+    db.run_default("?[seq] <- [[9]] :put outbox {seq}").unwrap();
+    assert!(db
+        .run_default("?[id] <- [[3]] :outbox 'x' :put users {id}")
+        .is_err());
+
+    let res = db.poll_outbox("mailer", 10).unwrap();
+    assert_eq!(res.headers, ["seq", "topic", "payload", "created"]);
+    assert_eq!(
+        res.rows
+            .iter()
+            .map(|row| (
+                row[0].clone(),
+                row[1].clone(),
+                JsonValue::from(row[2].clone())
+            ))
+            .collect_vec(),
+        vec![
+            (
+                DataValue::from(1),
+                DataValue::from("user_created"),
+                json!({"id": 1, "name": "alice"})
+            ),
+            (
+                DataValue::from(2),
+                DataValue::from("user_created"),
+                json!({"id": 2, "name": "bob"})
+            ),
+        ]
+    );
+    assert_eq!(db.poll_outbox("mailer", 1).unwrap().rows.len(), 1);
+    db.ack_outbox("mailer", 1).unwrap();
+    let res = db.poll_outbox("mailer", 10).unwrap();
+    assert_eq!(res.rows.len(), 1);
+    assert_eq!(res.rows[0][0], DataValue::from(2));
+    // consumers are independent, and acknowledgements are idempotent
+    assert_eq!(db.poll_outbox("audit", 10).unwrap().rows.len(), 2);
+    db.ack_outbox("mailer", 2).unwrap();
+    db.ack_outbox("mailer", 1).unwrap();
+    assert!(db.poll_outbox("mailer", 10).unwrap().rows.is_empty());
+    assert!(db.ack_outbox("mailer", 3).is_err());
+}
+
+#[test]
+fn test_migrations() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let statuses = |res: NamedRows| res.into_json()["rows"].clone();
+    let mut migrations = vec![
+        Migration::new("001_users", ":create users {id: Int => name: String}"),
+        Migration::new(
+            "002_seed",
+            "?[id, name] <- [[1, 'alice']] :put users {id => name}",
+        ),
+    ];
+    assert_eq!(
+        statuses(db.migrate_dry_run(&migrations).unwrap()),
+        json!([["001_users", "pending"], ["002_seed", "pending"]])
+    );
+    assert!(db.run_default("?[id] := *users{id}").is_err());
+    assert_eq!(
+        statuses(db.migrate(&migrations).unwrap()),
+        json!([["001_users", "applied"], ["002_seed", "applied"]])
+    );
+
+    migrations.push(Migration::new(
+        "003_more",
+        "?[id, name] <- [[2, 'bob']] :put users {id => name}",
+    ));
+    assert_eq!(
+        statuses(db.migrate(&migrations).unwrap()),
+        json!([
+            ["001_users", "skipped"],
+            ["002_seed", "skipped"],
+            ["003_more", "applied"]
+        ])
+    );
+    let res = db.run_default("?[id] := *users{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2]]));
+    assert!(db
+        .run_default("?[name, checksum, applied] <- [['x', '', 0.]] :put migrations {name => checksum, applied}")
+        .is_err());
+
+    // failing migrations are not recorded, nor are the ones after them run
+    let failing = [
+        Migration::new("004_bad", "?[x] := *nowhere{x}"),
+        Migration::new("005_never", ":create never {x}"),
+    ];
+    let err = db.migrate(&failing).unwrap_err();
+    assert!(err.to_string().contains("004_bad"));
+    assert_eq!(
+        statuses(db.migrate_dry_run(&failing).unwrap()),
+        json!([["004_bad", "pending"], ["005_never", "pending"]])
+    );
+
+    // applied migrations must not be edited
+    migrations[1].script = "?[id, name] <- [[1, 'carol']] :put users {id => name}".to_string();
+    assert!(db.migrate_dry_run(&migrations).is_err());
+    assert!(db.migrate(&migrations).is_err());
+    let dup = [migrations[0].clone(), migrations[0].clone()];
+    assert!(db.migrate(&dup).is_err());
+}
+
+#[test]
+#[cfg(feature = "storage-sqlite")]
+fn db_pool() {
+    let dir = tempfile::tempdir().unwrap();
+    let pool = DbPool::new("sqlite", dir.path(), "", 2).unwrap();
+    let run = |tenant: &str, script: &str| {
+        pool.run_script(
+            tenant,
+            script,
+            Default::default(),
+            ScriptMutability::Mutable,
+        )
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    for tenant in ["a", "b", "c"] {
+        run(tenant, ":create t {v: String}");
+        run(tenant, &format!("?[v] <- [['{tenant}']] :put t {{v}}"));
+    }
+    assert_eq!(pool.open_tenants(), ["b", "c"]);
+    // evicted databases are reopened with their data
+    assert_eq!(run("a", "?[v] := *t{v}"), json!([["a"]]));
+    assert_eq!(pool.open_tenants(), ["a", "c"]);
+
+    // databases in use are never evicted
+    let a = pool.get("a").unwrap();
+    let c = pool.get("c").unwrap();
+    assert!(pool.get("b").is_err());
+    drop(c);
+    assert_eq!(run("b", "?[v] := *t{v}"), json!([["b"]]));
+    assert_eq!(pool.open_tenants(), ["a", "b"]);
+    drop(a);
+
+    assert!(pool.get("../escape").is_err());
+    assert!(pool.close("a"));
+    assert!(!pool.close("a"));
+
+    // concurrent callers share the database opened by one of them
+    let handles = std::thread::scope(|s| {
+        let getters = (0..4)
+            .map(|_| s.spawn(|| pool.get("d").unwrap()))
+            .collect_vec();
+        getters.into_iter().map(|g| g.join().unwrap()).collect_vec()
+    });
+    assert!(handles.iter().all(|h| Arc::ptr_eq(h, &handles[0])));
+}
+
+#[test]
+fn memory_budget() {
+    let db = crate::new_cozo_mem().unwrap();
+    assert!(db.set_memory_budget(Some(0)).is_err());
+    db.set_memory_budget(Some(100_000)).unwrap();
+    let big = "?[x] := x in int_range(100000)";
+    let err = db
+        .run_script(big, Default::default(), ScriptMutability::Immutable)
+        .unwrap_err();
+    assert!(err.to_string().contains("memory budget"));
+    // memory of failed scripts is given back
+    db.run_script(
+        "?[x] := x in int_range(10)",
+        Default::default(),
+        ScriptMutability::Immutable,
+    )
+    .unwrap();
+
+    // new scripts wait while running scripts are close to the budget
+    let permit = db
+        .scheduler
+        .acquire(QueueKind::Read, &Poison::default())
+        .unwrap();
+    permit.gauge().unwrap().add(95_000).unwrap();
+    let (tx, rx) = crossbeam::channel::bounded(1);
+    let waiting = {
+        let db = db.clone();
+        std::thread::spawn(move || {
+            let res = db.run_script(
+                "?[x] <- [[1]]",
+                Default::default(),
+                ScriptMutability::Immutable,
+            );
+            tx.send(()).unwrap();
+            res
+        })
+    };
+    assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+    drop(permit);
+    rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(waiting.join().unwrap().is_ok());
+
+    db.set_memory_budget(None).unwrap();
+    db.run_script(big, Default::default(), ScriptMutability::Immutable)
+        .unwrap();
+}
+
+#[test]
+fn wcc_union_find() {
+    let db = DbInstance::default();
+    let script = r#"
+        edges[] <- [[1, 2], [3, 2], [4, 5], [6, 6], [7, 4]]
+        nodes[] <- [[1], [8]]
+        ?[node, c] <~ RULE(edges[], nodes[])
+    "#;
+    let res = db
+        .run_default(&script.replace("RULE", "WCCUnionFind"))
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            [1, 0],
+            [2, 0],
+            [3, 0],
+            [4, 1],
+            [5, 1],
+            [6, 2],
+            [7, 1],
+            [8, 3]
+        ])
+    );
+    let groups = |rule: &str| {
+        let rows = db.run_default(&script.replace("RULE", rule)).unwrap().rows;
+        rows.into_iter()
+            .into_group_map_by(|row| row[1].clone())
+            .into_values()
+            .map(|rows| rows.into_iter().map(|row| row[0].clone()).collect_vec())
+            .sorted()
+            .collect_vec()
+    };
+    assert_eq!(groups("WCCUnionFind"), groups("ConnectedComponents"));
+}
+
+#[test]
+fn bidirectional_and_landmark_shortest_paths() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[fr, to, cost] <- [['a', 'b', 1], ['b', 'c', 2], ['a', 'c', 4], ['c', 'd', 1],
+                            ['b', 'd', 5], ['d', 'e', 3], ['e', 'f', 1], ['c', 'f', 7],
+                            ['g', 'a', 2]]
+        :create road {fr, to => cost}
+    "#,
+    )
+    .unwrap();
+    let pairs = r#"
+        starting[] <- [['a'], ['g'], ['f']]
+        goals[] <- [['f'], ['d'], ['a'], ['x']]
+    "#;
+    let dijkstra = |options: &str| {
+        db.run_default(&format!(
+            "{pairs} ?[s, g, cost, path] <~ ShortestPathDijkstra(*road[], starting[], goals[]{options})"
+        ))
+        .unwrap()
+        .rows
+    };
+    let expected = dijkstra("");
+    assert_eq!(dijkstra(", bidirectional: true"), expected);
+
+    db.run_default(
+        r#"
+        ?[landmark, node, from, to] <~ LandmarkDistances(*road[], num_landmarks: 2)
+        :create landmarks {landmark, node => from, to}
+    "#,
+    )
+    .unwrap();
+    let alt = db
+        .run_default(&format!(
+            "{pairs} ?[s, g, cost, path] <~ ShortestPathALT(*road[], *landmarks[], starting[], goals[])"
+        ))
+        .unwrap()
+        .rows;
+    assert_eq!(alt, expected);
+
+    let chosen = db
+        .run_default(
+            r#"
+        l[] <- [['c']]
+        ?[landmark, node, from, to] <~ LandmarkDistances(*road[], l[])
+    "#,
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        chosen["rows"],
+        json!([
+            ["c", "a", null, 3.0],
+            ["c", "b", null, 2.0],
+            ["c", "c", 0.0, 0.0],
+            ["c", "d", 1.0, null],
+            ["c", "e", 4.0, null],
+            ["c", "f", 5.0, null],
+            ["c", "g", null, 5.0]
+        ])
+    );
+
+    assert!(db
+        .run_default(
+            "s[] <- [['a']] ?[s, g, cost, path] <~ ShortestPathDijkstra(*road[], s[], bidirectional: true)"
+        )
+        .is_err());
+}
+
+#[test]
+fn query_nest() {
+    let db = DbInstance::default();
+    let data = r#"
+        ?[user, name, order, item, qty] <- [
+            [1, 'bob', 10, 'pen', 2],
+            [1, 'bob', 10, 'ink', 1],
+            [1, 'bob', 11, 'cup', 1],
+            [2, 'alice', null, null, null],
+        ]
+        :order user, order, item
+    "#;
+    let res = db
+        .run_default(&format!(
+            "{data} :nest {{parent: [user, name], children: {{orders: {{parent: [order], \
+             children: {{lines: [item, qty]}}}}, items: [item]}}}}"
+        ))
+        .unwrap()
+        .into_json();
+    assert_eq!(res["headers"], json!(["user", "name", "orders", "items"]));
+    assert_eq!(
+        res["rows"],
+        json!([
+            [
+                1,
+                "bob",
+                [
+                    {"order": 10, "lines": [{"item": "ink", "qty": 1}, {"item": "pen", "qty": 2}]},
+                    {"order": 11, "lines": [{"item": "cup", "qty": 1}]}
+                ],
+                [{"item": "ink"}, {"item": "pen"}, {"item": "cup"}]
+            ],
+            [2, "alice", [], []]
+        ])
+    );
+
+    assert!(db
+        .run_default(&format!("{data} :nest {{parent: [nope]}}"))
+        .is_err());
+    assert!(db
+        .run_default(&format!(
+            "{data} :nest {{parent: [user], children: {{user: [item]}}}}"
+        ))
+        .is_err());
+    assert!(db
+        .run_default(&format!(
+            "{data} :nest {{parent: [user]}} :transform x = item"
+        ))
+        .is_err());
+}
+
+#[test]
+fn max_flow() {
+    let db = DbInstance::default();
+    let edges = r#"
+        edges[] <- [['s', 'v1', 16], ['s', 'v2', 13], ['v1', 'v3', 12], ['v2', 'v1', 4],
+                    ['v2', 'v4', 14], ['v3', 'v2', 9], ['v3', 't', 20], ['v4', 'v3', 7],
+                    ['v4', 't', 4]]
+    "#;
+    let run = |options: &str| {
+        db.run_default(&format!(
+            "{edges} ?[a, b, c] <~ MaxFlow(edges[], source: 's', sink: 't'{options})"
+        ))
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(run(", output: 'value'"), json!([["s", "t", 23.0]]));
+    assert_eq!(
+        run(", output: 'cut'"),
+        json!([["v1", "v3", 12.0], ["v4", "t", 4.0], ["v4", "v3", 7.0]])
+    );
+    // flow is conserved at every inner node and no edge is over its capacity
+    let mut balance: BTreeMap<String, f64> = BTreeMap::new();
+    for row in run("").as_array().unwrap() {
+        let flow = row[2].as_f64().unwrap();
+        *balance
+            .entry(row[0].as_str().unwrap().to_string())
+            .or_default() -= flow;
+        *balance
+            .entry(row[1].as_str().unwrap().to_string())
+            .or_default() += flow;
+    }
+    assert_eq!(balance.remove("s"), Some(-23.));
+    assert_eq!(balance.remove("t"), Some(23.));
+    assert!(balance.values().all(|b| *b == 0.));
+
+    let res = db
+        .run_default(
+            r#"
+        edges[] <- [['a', 'b', 3], ['b', 'c', 2], ['c', 'b', 5]]
+        ?[a, b, c] <~ MaxFlow(edges[], source: 'c', sink: 'a', undirected: true)
+    "#,
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([["b", "a", 3.0], ["c", "b", 3.0]]));
+
+    assert!(db
+        .run_default(&format!(
+            "{edges} ?[a, b, c] <~ MaxFlow(edges[], source: 's', sink: 's')"
+        ))
+        .is_err());
+    assert!(db
+        .run_default(&format!(
+            "{edges} ?[a, b, c] <~ MaxFlow(edges[], source: 's', sink: 't', output: 'nope')"
+        ))
+        .is_err());
+}
+
+#[test]
+fn edn_discard() {
+    use crate::data::edn::{parse_edn, Edn};
+
+    assert_eq!(
+        parse_edn("[1 #_2] #_ #_3 4 [#_5]").unwrap(),
+        vec![Edn::Vector(vec![Edn::Int(1)]), Edn::Vector(vec![])]
+    );
+    assert!(parse_edn("[#_1").is_err());
+    assert!(parse_edn("[1 #_]").is_err());
+    assert!(parse_edn("#_").is_err());
+}
+
+#[test]
+fn datomic_edn_transactions() {
+    let db = DbInstance::default();
+    let log = r#"
+        ; schema and data in the Datascript style
+        [{:db/id "ann", :person/name "Ann", :person/tags #{:admin :staff},
+          :person/address {:address/city "Oslo"}}
+         [:db/add -1 :person/name "Bob"]
+         [:db/add -1 :person/friend #db/id [:db.part/user "ann"]]]
+        [[:db/retract [:person/name "Bob"] :person/name "Bob"]
+         [:db/add [:person/name "Ann"] :person/born #inst "1990-01-02T00:00:00Z"]]
+        {:t 1000 :data [#datom [0 :person/name "Annie" 13194139534313 true]
+                        #datom [13194139534313 :db/txInstant #inst "2020-01-01T00:00:00.000-00:00"]]}
+        [[:db/retractEntity 2]]
+    "#;
+    assert_eq!(db.import_edn_transactions("datoms", log).unwrap(), 12);
+
+    let current = db
+        .run_default(
+            "?[e, a, v] := *datoms{entity: e, attribute: a, value: v @ 'NOW'} :order e, a, v",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        current["rows"],
+        json!([
+            [0, "person/address", 1],
+            [0, "person/born", 631238400.0],
+            [0, "person/name", "Ann"],
+            [0, "person/name", "Annie"],
+            [0, "person/tags", ":admin"],
+            [0, "person/tags", ":staff"],
+            [1, "address/city", "Oslo"],
+            [13194139534313i64, "db/txInstant", 1577836800.0]
+        ])
+    );
+
+    // the export imports to the same datoms
+    let exported = db.export_edn_transactions("datoms").unwrap();
+    assert!(exported.contains(r#"#datom [1 :address/city "Oslo" 1 true]"#));
+    let copy = DbInstance::default();
+    copy.import_edn_transactions("datoms", &exported).unwrap();
+    assert_eq!(
+        copy.export_relations(["datoms"].into_iter()).unwrap()["datoms"].rows,
+        db.export_relations(["datoms"].into_iter()).unwrap()["datoms"].rows
+    );
+
+    assert!(db
+        .import_edn_transactions("datoms", "[[:db/add [:person/name \"Zed\"] :a 1]]")
+        .is_err());
+    assert!(db
+        .import_edn_transactions("datoms", "[[:db/add 1 :a")
+        .is_err());
+    db.run_default(":create other {a}").unwrap();
+    assert!(db.import_edn_transactions("other", "[]").is_err());
+}
+
+#[test]
+fn bipartite_matching_and_assignment() {
+    let db = DbInstance::default();
+    // `x` only fits `a`, so a greedy choice of `a-y` would leave a node unmatched
+    let res = db
+        .run_default(
+            r#"
+        edges[] <- [['a', 'y'], ['a', 'x'], ['b', 'y'], ['b', 'z'], ['c', 'y'], ['d', 'x']]
+        ?[l, r] <~ MaximumBipartiteMatching(edges[])
+    "#,
+        )
+        .unwrap()
+        .into_json();
+    let rows = res["rows"].as_array().unwrap();
+    assert_eq!(rows.len(), 3);
+    let mut right: Vec<_> = rows.iter().map(|r| r[1].as_str().unwrap()).collect();
+    right.sort();
+    assert_eq!(right, ["x", "y", "z"]);
+
+    let costs = r#"
+        costs[] <- [['a', 'x', 4], ['a', 'y', 1], ['a', 'z', 3],
+                    ['b', 'x', 2], ['b', 'y', 0], ['b', 'z', 5],
+                    ['c', 'x', 3], ['c', 'y', 2], ['c', 'z', 2]]
+    "#;
+    let run = |options: &str| {
+        db.run_default(&format!(
+            "{costs} ?[l, r, w] <~ MinimumCostAssignment(costs[]{options})"
+        ))
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(
+        run(""),
+        json!([["a", "y", 1.0], ["b", "x", 2.0], ["c", "z", 2.0]])
+    );
+    assert_eq!(
+        run(", maximize: true"),
+        json!([["a", "x", 4.0], ["b", "z", 5.0], ["c", "y", 2.0]])
+    );
+
+    // missing edges are never used, and more nodes on the right than on the left
+    let res = db
+        .run_default(
+            r#"
+        costs[] <- [['a', 'x', 1], ['b', 'x', 1], ['b', 'y', 10], ['b', 'z', 20]]
+        ?[l, r, w] <~ MinimumCostAssignment(costs[])
+    "#,
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([["a", "x", 1.0], ["b", "y", 10.0]]));
+    assert!(db
+        .run_default(
+            r#"
+        costs[] <- [['a', 'x', 1e308], ['b', 'y', -1e308]]
+        ?[l, r, w] <~ MinimumCostAssignment(costs[])
+    "#,
+        )
+        .is_err());
+}
+
+#[test]
+fn export_query_result() {
+    let db = DbInstance::default();
+    db.run_default(":create sales {item: String, month: Int => amount: Float}")
+        .unwrap();
+    db.run_default(
+        r#"
+        ?[item, month, amount] <- [['a', 1, 10.], ['a', 2, 5.], ['b', 1, 7.]]
+        :put sales {item, month => amount}
+    "#,
+    )
+    .unwrap();
+    let exported = db
+        .export_query(
+            "totals",
+            r#"
+            totals[item, sum(amount)] := *sales{item, amount}
+            ?[item, amount] := totals[item, amount]
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(exported.keys().collect_vec(), ["totals"]);
+
+    // the export has the same shape as that of a stored relation
+    db.run_default(":create totals {item: String => amount: Float}")
+        .unwrap();
+    db.import_relations(exported).unwrap();
+    let res = db
+        .run_default("?[item, amount] := *totals{item, amount}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a", 15.0], ["b", 7.0]]));
+
+    assert!(db
+        .export_query("x", "?[a] <- [[1]] :create x {a}", Default::default())
+        .is_err());
+}
+
+#[test]
+fn temporal_shortest_path() {
+    let db = DbInstance::default();
+    db.run_default(":create road {fr: String, to: String, vld: Validity => cost: Float}")
+        .unwrap();
+    db.run_default(
+        r#"
+        ?[fr, to, vld, cost] <- [['a', 'b', [10, true], 1.], ['b', 'c', [10, true], 1.],
+                                 ['a', 'c', [10, true], 5.], ['b', 'c', [20, false], 1.]]
+        :put road {fr, to, vld => cost}
+    "#,
+    )
+    .unwrap();
+    let run = |edges: &str, at: i64| {
+        db.run_default(&format!(
+            r#"
+            start[] <- [['a']]
+            goal[] <- [['c']]
+            ?[s, g, cost, path] <~ ShortestPathTemporal({edges}, start[], goal[], at: {at})
+            "#
+        ))
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(
+        run("*road[]", 15),
+        json!([["a", "c", 2.0, ["a", "b", "c"]]])
+    );
+    // retracted at 20
+    assert_eq!(run("*road[]", 25), json!([["a", "c", 5.0, ["a", "c"]]]));
+    // nothing exists yet
+    assert_eq!(run("*road[]", 5), json!([]));
+
+    let intervals = "edges[fr, to, vld, cost] <- [['a', 'b', [null, 100], 1], \
+                     ['b', 'c', [50, null], 1], ['a', 'c', [null, null], 10]]";
+    let run_intervals = |at: i64| {
+        db.run_default(&format!(
+            r#"
+            {intervals}
+            start[] <- [['a']]
+            goal[] <- [['c']]
+            ?[s, g, cost] := r[s, g, cost, _]
+            r[s, g, cost, path] <~ ShortestPathTemporal(edges[], start[], goal[], at: {at})
+            "#
+        ))
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(run_intervals(10), json!([["a", "c", 10.0]]));
+    assert_eq!(run_intervals(60), json!([["a", "c", 2.0]]));
+    assert_eq!(run_intervals(100), json!([["a", "c", 10.0]]));
+}
+
+#[test]
+fn transitive_closure_paths() {
+    let db = DbInstance::default();
+    let edges = "edges[fr, to] <- [['a', 'b'], ['b', 'c'], ['c', 'a'], ['a', 'c'], ['c', 'd']]";
+    let run = |args: &str| {
+        db.run_default(&format!(
+            r#"
+            {edges}
+            start[] <- [['a']]
+            ?[s, n, hops, path] <~ TransitiveClosure({args})
+            "#
+        ))
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(
+        run("edges[], start[]"),
+        json!([
+            ["a", "b", 1, ["a", "b"]],
+            ["a", "c", 1, ["a", "c"]],
+            ["a", "d", 2, ["a", "c", "d"]]
+        ])
+    );
+    assert_eq!(
+        run("edges[], start[], max_depth: 1"),
+        json!([["a", "b", 1, ["a", "b"]], ["a", "c", 1, ["a", "c"]]])
+    );
+    // the cycle back to `a` is not followed
+    assert_eq!(
+        run("edges[], start[], all_paths: true"),
+        json!([
+            ["a", "b", 1, ["a", "b"]],
+            ["a", "c", 1, ["a", "c"]],
+            ["a", "c", 2, ["a", "b", "c"]],
+            ["a", "d", 2, ["a", "c", "d"]],
+            ["a", "d", 3, ["a", "b", "c", "d"]]
+        ])
+    );
+    assert_eq!(
+        run("edges[], start[], all_paths: true, max_depth: 2")
+            .as_array()
+            .unwrap()
+            .len(),
+        4
+    );
+    // without starting nodes, from every node with outgoing edges
+    let res = run("edges[]");
+    assert_eq!(res.as_array().unwrap().len(), 9);
+    assert!(res
+        .as_array()
+        .unwrap()
+        .contains(&json!(["c", "b", 2, ["c", "a", "b"]])));
+    assert!(db
+        .run_default(&format!(
+            "{edges} ?[s, n, hops, path] <~ TransitiveClosure(edges[], max_depth: -1)"
+        ))
+        .is_err());
+}
+
+#[test]
+fn regular_path_queries() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        {:create knows {fr: String, to: String}}
+        {:create works_at {person: String, company: String => since: Int}}
+        {?[fr, to] <- [['a', 'b'], ['b', 'c'], ['c', 'a'], ['c', 'd']] :put knows {fr, to}}
+        {?[person, company, since] <- [['c', 'acme', 2020], ['d', 'initech', 2021]]
+         :put works_at {person, company => since}}
+        ",
+    )
+    .unwrap();
+    let run = |script: &str| db.run_default(script).unwrap().into_json()["rows"].clone();
+    assert_eq!(
+        run("?[b] := a = 'a', a -[:knows+ / :works_at]-> b"),
+        json!([["acme"], ["initech"]])
+    );
+    assert_eq!(
+        run("path[a, b] := a -[:knows / :knows]-> b ?[a, b] := path[a, b]"),
+        json!([["a", "c"], ["b", "a"], ["b", "d"], ["c", "b"]])
+    );
+    // inverse edges, alternatives and optional steps
+    assert_eq!(
+        run("?[b] := a = 'acme', a -[^:works_at / ^:knows?]-> b"),
+        json!([["b"], ["c"]])
+    );
+    assert_eq!(
+        run("?[b] := a = 'd', a -[(^:knows | :works_at)+]-> b"),
+        json!([["a"], ["acme"], ["b"], ["c"], ["initech"]])
+    );
+    assert_eq!(
+        run("?[a] := *works_at{person: a}, b = 'acme', not a -[:knows* / :works_at]-> b"),
+        json!([["d"]])
+    );
+    assert_eq!(
+        run("?[a] := *knows{fr: a}, not a -[:knows / :works_at]-> _"),
+        json!([["a"]])
+    );
+    let err = db.run_default("?[a, b] := a -[:knows*]-> b").unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::nullable_path_pattern"
+    );
+    let err = db
+        .run_default("?[a, b] := a -[:knows / :nowhere]-> b")
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "query::relation_not_found");
+    assert_eq!(
+        format_script("?[b]:=a-[ :knows + /^:works_at ]->b").unwrap(),
+        "?[b] := a -[:knows+ / ^:works_at]-> b\n"
+    );
+}
+
+#[test]
+fn named_field_access() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        {:create person {id: Int => name: String, age: Int}}
+        {?[id, name, age] <- [[1, 'alice', 30], [2, 'bob', 41]] :put person {id => name, age}}
+        ",
+    )
+    .unwrap();
+    let run = |script: &str| db.run_default(script).unwrap().into_json()["rows"].clone();
+    assert_eq!(
+        run("?[id, name, age] := *person{id, ..}"),
+        json!([[1, "alice", 30], [2, "bob", 41]])
+    );
+    assert_eq!(
+        run("?[name] := *person{name, ..}, age > 40"),
+        json!([["bob"]])
+    );
+    assert_eq!(run("?[a] := *person.age(a), a < 40"), json!([[30]]));
+    assert_eq!(
+        run("?[n] := *person.name(n), *person{name: n, age: 41}"),
+        json!([["bob"]])
+    );
+    let err = db.run_default("?[h] := *person.height(h)").unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::named_field_not_found"
+    );
+    assert_eq!(
+        err.help().unwrap().to_string(),
+        "The columns of 'person' are 'id', 'name', 'age'"
+    );
+    let sig = db
+        .script_signature("?[n] := *person.age($age), *person{name: n}")
+        .unwrap();
+    assert_eq!(sig.params[0].typing, "Int");
+    assert_eq!(
+        format_script("?[a]:=*person{id,..},*person.age( a )").unwrap(),
+        "?[a] := *person{id, ..}, *person.age(a)\n"
+    );
+}
+
+#[test]
+fn expressions_batch() {
+    let params = BTreeMap::from([("limit".to_string(), DataValue::from(10))]);
+    let batch = [
+        BTreeMap::from([("x".to_string(), DataValue::from(3))]),
+        BTreeMap::from([
+            ("x".to_string(), DataValue::from(12)),
+            ("unused".to_string(), DataValue::Null),
+        ]),
+    ];
+    let res = crate::evaluate_expressions_batch("x * 2 < $limit", &params, &batch).unwrap();
+    assert_eq!(res, vec![DataValue::from(true), DataValue::from(false)]);
+
+    let missing = [BTreeMap::from([("y".to_string(), DataValue::from(1))])];
+    assert!(crate::evaluate_expressions_batch("x + 1", &params, &missing).is_err());
+    assert!(crate::evaluate_expressions_batch("x + 1", &params, &[])
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn node2vec_embeddings() {
+    let db = DbInstance::default();
+    db.run_default(":create emb {node: String => v: <F32; 8>}")
+        .unwrap();
+    db.run_default(
+        r"
+        ::hnsw create emb:vec {dim: 8, m: 16, dtype: F32, fields: [v], distance: Cosine, ef_construction: 20}
+    ",
+    )
+    .unwrap();
+    // two cliques joined by a single edge
+    db.run_default(
+        r#"
+        nodes[n] <- [['a1'], ['a2'], ['a3'], ['a4'], ['b1'], ['b2'], ['b3'], ['b4']]
+        edges[x, y] := nodes[x], nodes[y], x < y, starts_with(x, 'a'), starts_with(y, 'a')
+        edges[x, y] := nodes[x], nodes[y], x < y, starts_with(x, 'b'), starts_with(y, 'b')
+        edges[x, y] := x = 'a1', y = 'b1'
+        ?[node, v] <~ Node2Vec(edges[], undirected: true, dimensions: 8, walk_length: 10,
+                               walks_per_node: 20, epochs: 5)
+        :put emb {node => v}
+        :seed 42
+    "#,
+    )
+    .unwrap();
+    let res = db
+        .run_default(
+            r"
+        ?[node] := *emb{node: 'a2', v: q},
+                   ~emb:vec{node | query: q, k: 4, ef: 20}
+    ",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([["a1"], ["a2"], ["a3"], ["a4"]]));
+
+    let run = || {
+        db.run_default(
+            "edges[] <- [[1, 2], [2, 3]] ?[n, v] <~ Node2Vec(edges[], dimensions: 4) :seed 7",
+        )
+        .unwrap()
+        .rows
+    };
+    assert_eq!(run(), run());
+    assert!(db
+        .run_default("edges[] <- [[1, 2]] ?[n, v] <~ Node2Vec(edges[], p: 0)")
+        .is_err());
+}
+
+#[test]
+fn vector_clustering() {
+    let db = DbInstance::default();
+    let points = r#"
+        points[id, v] <- [['a', [0., 0.]], ['b', [0.1, 0.2]], ['c', [0.2, 0.]],
+                          ['d', [10., 10.]], ['e', [10.1, 9.9]], ['f', [9.8, 10.2]],
+                          ['g', [50., -50.]]]
+    "#;
+    let run = |rule: &str| {
+        db.run_default(&format!("{points} ?[id, c] <~ {rule} :seed 3"))
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(
+        run("KMeans(points[], k: 3)"),
+        json!([
+            ["a", 0],
+            ["b", 0],
+            ["c", 0],
+            ["d", 1],
+            ["e", 1],
+            ["f", 1],
+            ["g", 2]
+        ])
+    );
+    assert_eq!(
+        run("DBSCAN(points[], eps: 1, min_points: 2)"),
+        json!([
+            ["a", 0],
+            ["b", 0],
+            ["c", 0],
+            ["d", 1],
+            ["e", 1],
+            ["f", 1],
+            ["g", null]
+        ])
+    );
+
+    db.run_default(":create emb {id: String => v: <F32; 2>}")
+        .unwrap();
+    db.run_default(&format!(
+        "{points} ?[id, v] := points[id, l], v = vec(l) :put emb {{id => v}}"
+    ))
+    .unwrap();
+    let res = db
+        .run_default("?[id, c] <~ DBSCAN(*emb[], eps: 0.01, min_points: 2, distance: 'Cosine')")
+        .unwrap()
+        .into_json();
+    // only 'd', 'e' and 'f' point in nearly the same direction, and 'a' has none
+    assert_eq!(
+        res["rows"],
+        json!([
+            ["a", null],
+            ["b", null],
+            ["c", null],
+            ["d", 0],
+            ["e", 0],
+            ["f", 0],
+            ["g", null]
+        ])
+    );
+
+    assert!(db
+        .run_default(r#"p[id, v] <- [[1, [1, 2]], [2, [1]]] ?[id, c] <~ KMeans(p[], k: 1)"#)
+        .is_err());
+}
+
+#[test]
+fn id_generators() {
+    let db = DbInstance::default();
+    db.run_default("::idgen create orders {kind: 'snowflake', node_id: 7}")
+        .unwrap();
+    db.run_default("::idgen create tokens {kind: 'nanoid', alphabet: 'ab', size: 8}")
+        .unwrap();
+    db.run_default("::idgen create events {kind: 'uuid_v7'}")
+        .unwrap();
+    assert!(db
+        .run_default("::idgen create orders {kind: 'uuid_v7'}")
+        .is_err());
+    assert!(db
+        .run_default("::idgen create bad {kind: 'snowflake', node_id: 1024}")
+        .is_err());
+    assert!(db
+        .run_default("::idgen create bad {kind: 'uuid_v7', size: 3}")
+        .is_err());
+    let res = db.run_default("::idgen list").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["events", "uuid_v7", null, null, null, null],
+            ["orders", "snowflake", 7, 1577836800000i64, null, null],
+            ["tokens", "nanoid", null, null, "ab", 8]
+        ])
+    );
+
+    let res = db
+        .run_default("?[i, id] := i in int_range(100), id = gen_id('orders')")
+        .unwrap();
+    let ids: BTreeSet<_> = res.rows.iter().map(|r| r[1].get_int().unwrap()).collect();
+    assert_eq!(ids.len(), 100);
+    assert!(ids.iter().all(|id| (id >> 12) & 1023 == 7));
+
+    let res = db.run_default("?[t] := t = gen_id('tokens')").unwrap();
+    let token = res.rows[0][0].get_str().unwrap();
+    assert_eq!(token.len(), 8);
+    assert!(token.chars().all(|c| c == 'a' || c == 'b'));
+
+    let res = db.run_default("?[u] := u = gen_id('events')").unwrap();
+    match &res.rows[0][0] {
+        DataValue::Uuid(u) => assert_eq!(u.0.get_version_num(), 7),
+        v => panic!("not a uuid: {v:?}"),
+    }
+
+    db.run_default(":create orders {id: Int default gen_id('orders') => item: String}")
+        .unwrap();
+    db.run_default("?[item] <- [['apple'], ['pear']] :put orders {item}")
+        .unwrap();
+    let res = db.run_default("?[id] := *orders{id}").unwrap();
+    assert_eq!(res.rows.len(), 2);
+
+    // the generators only change when the transaction commits
+    let aborted = |op: &str| {
+        assert!(db
+            .run_default(&format!(
+                "{{:create scratch {{x}}}} {{{op}}} {{?[x] <- [[1]] :assert none}}"
+            ))
+            .is_err());
+    };
+    aborted("::idgen create temp {kind: 'uuid_v7'}");
+    assert!(db.run_default("?[u] := u = gen_id('temp')").is_err());
+    aborted("::idgen drop events");
+    assert!(db.run_default("?[u] := u = gen_id('events')").is_ok());
+
+    db.run_default("::idgen drop orders").unwrap();
+    assert!(db.run_default("::idgen drop orders").is_err());
+    assert!(db.run_default("?[id] := id = gen_id('orders')").is_err());
+}
+
+#[test]
+fn experimental_features() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x: Int => y: Int}").unwrap();
+    db.run_default(":create b {y: Int => z: Int}").unwrap();
+    db.run_default(":create c {z: Int => w: Int}").unwrap();
+    db.run_default("?[x, y] <- [[1, 10], [2, 20], [3, 10]] :put a {x => y}")
+        .unwrap();
+    db.run_default("?[y, z] <- [[10, 100], [20, 200]] :put b {y => z}")
+        .unwrap();
+    db.run_default("?[z, w] <- [[100, 1000], [200, 2000], [300, 3000]] :put c {z => w}")
+        .unwrap();
+
+    let query = "?[x, w] := *c{z, w}, *a{x, y}, *b{y, z}";
+    let expected = json!([[1, 1000], [2, 2000], [3, 1000]]);
+    for opts in [
+        "",
+        ":experimental join_reorder",
+        ":experimental hash_join",
+        ":experimental join_reorder, hash_join",
+    ] {
+        let res = db.run_default(&format!("{query} {opts}")).unwrap();
+        assert_eq!(res.into_json()["rows"], expected, "{opts}");
+    }
+
+    let explain = |opts: &str| {
+        db.run_default(&format!("::explain {{ {query} {opts} }}"))
+            .unwrap()
+            .rows
+    };
+    let loaded = |opts: &str| {
+        explain(opts)
+            .iter()
+            .filter_map(|row| row[5].get_str().map(|s| s.to_string()))
+            .collect_vec()
+    };
+    // as written, `c` and `a` share no variable
+    assert_eq!(loaded(""), vec![":c", ":a", ":b"]);
+    assert_eq!(loaded(":experimental join_reorder"), vec![":c", ":b", ":a"]);
+    let hash_joins = explain(":experimental join_reorder, hash_join")
+        .iter()
+        .filter(|row| row[4] == DataValue::from("hash_join"))
+        .count();
+    assert_eq!(hash_joins, 2);
+
+    assert!(db
+        .run_default(&format!("{query} :experimental warp_drive"))
+        .is_err());
+}
+
+#[test]
+fn statistical_aggregations() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r#"
+        data[g, x, y] <- [['a', 1, 2], ['a', 2, 4], ['a', 3, 7], ['b', 10, 1], ['b', 20, 3]]
+        ?[g, median(x), percentile(x, 100), covariance(xy), correlation(xy), std_dev(y)] :=
+            data[g, x, y], xy = [x, y]
+        "#,
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(res.len(), 2);
+    assert_eq!(res[0][0], DataValue::from("a"));
+    assert_eq!(res[0][1].get_float(), Some(2.));
+    assert_eq!(res[0][2].get_float(), Some(3.));
+    assert_eq!(res[0][3].get_float(), Some(2.5));
+    assert_eq!(res[1][1].get_float(), Some(15.));
+    assert_eq!(res[1][4].get_float(), Some(1.));
+    assert_eq!(res[1][5].get_float(), Some(2f64.sqrt()));
+}
+
+#[test]
+fn window_functions() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r#"
+        ?[k, t, x] <- [['a', 1, 10], ['b', 1, 5], ['a', 2, 15], ['a', 3, 15], ['b', 2, 3]]
+        :order -k, t
+        :window row_number(), prev = lag(x, 1, 0), cumsum(x), rank(), diff(x),
+            first_value(x), lead(x)
+            over (partition by k order by t)
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.headers,
+        vec![
+            "k",
+            "t",
+            "x",
+            "row_number()",
+            "prev",
+            "cumsum(x)",
+            "rank()",
+            "diff(x)",
+            "first_value(x)",
+            "lead(x)"
+        ]
+    );
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["b", 1, 5, 1, 0, 5, 1, null, 5, 3],
+            ["b", 2, 3, 2, 5, 8, 2, -2, 5, null],
+            ["a", 1, 10, 1, 0, 10, 1, null, 10, 15],
+            ["a", 2, 15, 2, 10, 25, 2, 5, 10, 15],
+            ["a", 3, 15, 3, 15, 40, 3, 0, 10, null]
+        ])
+    );
+
+    // ranks of ties, and limits applied after the window functions
+    let res = db
+        .run_default(
+            r#"
+        ?[i, x] <- [[1, 3], [2, 1], [3, 3], [4, 2]]
+        :window rank(), dense_rank(), cummax(x) over (order by -x)
+        :limit 3
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, 3, 1, 1, 3], [2, 1, 4, 3, 3], [3, 3, 1, 1, 3]])
+    );
+
+    assert!(db.run_default("?[x] <- [[1]] :window nope(x)").is_err());
+    assert!(db.run_default("?[x] <- [[1]] :window lag(x, x)").is_err());
+    assert!(db
+        .run_default("?[x] <- [[1]] :window row_number() over (partition by y)")
+        .is_err());
+    assert!(db
+        .run_default("?[x] <- [[1]] :window row_number() :create w {x}")
+        .is_err());
+}
+
+#[test]
+fn top_k_per_group() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r#"
+        events[user, id, ts] <- [['u1', 'e1', 10], ['u1', 'e2', 30], ['u1', 'e3', 20],
+                                 ['u2', 'e4', 5], ['u2', 'e5', 7]]
+        ?[user, top_k(e, 2), bottom_k(e, 1)] := events[user, id, ts], e = [id, ts]
+        "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["u1", ["e2", "e3"], ["e1"]], ["u2", ["e5", "e4"], ["e4"]]])
+    );
+}
+
+#[test]
+fn latest_aggregation() {
+    let db = DbInstance::default();
+    db.run_default(":create status {k: String, vld: Validity => v: String}")
+        .unwrap();
+    db.run_default(
+        r#"
+        ?[k, vld, v] <- [['a', [10, true], 'a1'], ['a', [20, true], 'a2'],
+                         ['b', [10, true], 'b1'], ['b', [30, false], 'b1'],
+                         ['c', [5, true], 'c1']]
+        :put status {k, vld => v}
+    "#,
+    )
+    .unwrap();
+    let res = db
+        .run_default("?[k, latest(x)] := *status{k, vld, v}, x = [v, vld]")
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["a", "a2"], ["b", null], ["c", "c1"]])
+    );
+    // the same as the skip scan at the end of time, except for retracted keys
+    let res = db.run_default("?[k, v] := *status{k, v @ 'END'}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a", "a2"], ["c", "c1"]]));
+}
+
+#[test]
+fn transaction_time() {
+    let db = DbInstance::default();
+    let pause = || std::thread::sleep(Duration::from_millis(2));
+    db.run_default(":create tracked {k: Int => v: String}")
+        .unwrap();
+    db.run_default("?[k, v] <- [[1, 'a'], [2, 'b']] :put tracked {k => v}")
+        .unwrap();
+    pause();
+    db.run_default("::tx_time track tracked").unwrap();
+    assert!(db.run_default("::tx_time track tracked").is_err());
+    pause();
+    db.run_default("?[k, v] <- [[1, 'a2']] :put tracked {k => v}")
+        .unwrap();
+    pause();
+    db.run_default("?[k] <- [[2], [4]] :rm tracked {k}")
+        .unwrap();
+    pause();
+    db.run_default("?[k, v] <- [[3, 'c']] :put tracked {k => v}")
+        .unwrap();
+    pause();
+    db.run_default("?[k, v] <- [[3, 'c2']] :update tracked {k => v}")
+        .unwrap();
+
+    // the key 4 was not there, so its removal is not recorded
+    let history = db
+        .run_default(
+            "?[t, k, v] := *tracked:tx_history{k, v, tx_time}, t = to_int(tx_time) :order t, k",
+        )
+        .unwrap()
+        .into_json()["rows"]
+        .clone();
+    let history = history.as_array().unwrap();
+    assert_eq!(history.len(), 6);
+    let at = |i: usize| history[i][0].as_i64().unwrap();
+    assert_eq!(at(0), at(1));
+    assert_eq!(
+        history.iter().map(|r| r[2].clone()).collect_vec(),
+        vec![
+            json!("a"),
+            json!("b"),
+            json!("a2"),
+            json!(null),
+            json!("c"),
+            json!("c2")
+        ]
+    );
+
+    let as_of = |t: i64| {
+        db.run_default(&format!("?[k, v] := *tracked{{k, v}} :as_of_tx {t}"))
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(as_of(at(0) - 1), json!([]));
+    assert_eq!(as_of(at(0)), json!([[1, "a"], [2, "b"]]));
+    assert_eq!(as_of(at(2)), json!([[1, "a2"], [2, "b"]]));
+    assert_eq!(as_of(at(3)), json!([[1, "a2"]]));
+    assert_eq!(as_of(at(4)), json!([[1, "a2"], [3, "c"]]));
+    assert_eq!(as_of(at(5)), json!([[1, "a2"], [3, "c2"]]));
+    // joins on the keys, and relations that are not tracked are read as they are now
+    db.run_default(":create plain {k: Int => w: String}")
+        .unwrap();
+    db.run_default("?[k, w] <- [[1, 'x'], [2, 'y']] :put plain {k => w}")
+        .unwrap();
+    let res = db
+        .run_default(&format!(
+            "?[k, v, w] := *plain{{k, w}}, *tracked{{k, v}} :as_of_tx {}",
+            at(2)
+        ))
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, "a2", "x"], [2, "b", "y"]]));
+
+    assert!(db
+        .run_default("?[k] := *plain{k}, not *tracked{k} :as_of_tx 'NOW'")
+        .is_err());
+    assert!(db
+        .run_default("?[k, v] <- [[5, 'e']] :replace tracked {k => v}")
+        .is_err());
+
+    // valid time and transaction time together
+    db.run_default(":create status {k: Int, at: Validity => v: String} {track_tx_time: true}")
+        .unwrap();
+    db.run_default(
+        "?[k, at, v] <- [[1, [10, true], 'x'], [1, [20, true], 'y']] :put status {k, at => v}",
+    )
+    .unwrap();
+    pause();
+    db.run_default("?[k, at] <- [[1, [20, true]]] :rm status {k, at}")
+        .unwrap();
+    let times = db
+        .run_default("?[t] := *status:tx_history{tx_time}, t = to_int(tx_time) :order t")
+        .unwrap()
+        .into_json()["rows"]
+        .clone();
+    let tracked_at = times[0][0].as_i64().unwrap();
+    let removed_at = times[1][0].as_i64().unwrap();
+    let status_at = |t: i64, v: i64| {
+        db.run_default(&format!("?[v] := *status{{k: 1, v @ {v}}} :as_of_tx {t}"))
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(status_at(tracked_at, 25), json!([["y"]]));
+    assert_eq!(status_at(tracked_at, 15), json!([["x"]]));
+    assert_eq!(status_at(tracked_at, 5), json!([]));
+    assert_eq!(status_at(removed_at, 25), json!([["x"]]));
+    assert!(db
+        .run_default(&format!(
+            "?[v] := *tracked{{k: 1, v @ 25}} :as_of_tx {tracked_at}"
+        ))
+        .is_err());
+
+    // the rows put when creating, and imports, are recorded too
+    db.run_default("?[k, v] <- [[3, 'c']] :create imported {k => v} {track_tx_time: true}")
+        .unwrap();
+    pause();
+    db.import_relations(BTreeMap::from([(
+        "imported".to_string(),
+        NamedRows::new(
+            vec!["k".to_string(), "v".to_string()],
+            vec![vec![DataValue::from(1), DataValue::from("x")]],
+        ),
+    )]))
+    .unwrap();
+    pause();
+    db.import_relations(BTreeMap::from([(
+        "-imported".to_string(),
+        NamedRows::new(
+            vec!["k".to_string()],
+            vec![vec![DataValue::from(1)], vec![DataValue::from(2)]],
+        ),
+    )]))
+    .unwrap();
+    assert_eq!(
+        db.run_default("?[k, v] := *imported:tx_history{k, v}")
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1, null], [1, "x"], [3, "c"]])
+    );
+    assert!(db
+        .run_default("?[k, v] <- [[1, 'a']] :put tracked {k => v} {track_tx_time: true}")
+        .is_err());
+    assert!(db
+        .run_default(":create other {k => v} {track_time: true}")
+        .is_err());
+
+    db.run_default("::tx_time untrack tracked").unwrap();
+    assert!(db.run_default("::tx_time untrack tracked").is_err());
+    assert!(db.run_default("?[k] := *tracked:tx_history{k}").is_err());
+    assert_eq!(
+        db.run_default("?[k, v] := *tracked{k, v} :as_of_tx 0")
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1, "a2"], [3, "c2"]])
+    );
+    db.run_default("::remove status").unwrap();
+    assert!(db.run_default("?[k] := *status:tx_history{k}").is_err());
+}
+
+#[test]
+fn geo_index() {
+    let db = DbInstance::default();
+    db.run_default(":create place {name: String => lat: Float, lon: Float}")
+        .unwrap();
+    db.run_default(
+        r"?[name, lat, lon] <- [
+            ['paris', 48.8566, 2.3522],
+            ['versailles', 48.8049, 2.1204],
+            ['london', 51.5074, -0.1278],
+            ['suva', -18.1416, 178.4419],
+            ['east', -17.0, 179.95],
+            ['west', -17.0, -179.95],
+        ]
+        :put place {name => lat, lon}",
+    )
+    .unwrap();
+    db.run_default("::geo create place:geo {extractor: [lat, lon], precision: 6}")
+        .unwrap();
+    let names = |query: &str| -> Vec<String> {
+        db.run_default(query)
+            .unwrap()
+            .rows
+            .into_iter()
+            .map(|row| row[0].get_str().unwrap().to_string())
+            .collect()
+    };
+
+    let res = db
+        .run_default(
+            "?[name, d] := ~place:geo{name | query: [48.8566, 2.3522], radius: 20, bind_distance: d}",
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+    let versailles = res
+        .rows
+        .iter()
+        .find(|row| row[0] == DataValue::from("versailles"));
+    let d = versailles.unwrap()[1].get_float().unwrap();
+    assert!((d - 17.9).abs() < 0.1, "{}", d);
+
+    assert_eq!(
+        names("?[name] := ~place:geo{name | query: [49.0, 2.0], radius: 1000, k: 1}"),
+        vec!["versailles"]
+    );
+    assert_eq!(
+        names("?[name] := ~place:geo{name | query: [48.8566, 2.3522], radius: 400, filter: name != 'paris'}"),
+        vec!["london", "versailles"]
+    );
+    // the circle crosses the antimeridian
+    assert_eq!(
+        names("?[name] := ~place:geo{name | query: [-17.0, 179.99], radius: 20}"),
+        vec!["east", "west"]
+    );
+    assert_eq!(
+        names("?[name] := ~place:geo{name | query: [-17.0, 179.99], radius: 300}"),
+        vec!["east", "suva", "west"]
+    );
+
+    // the index follows the changes of the relation
+    db.run_default("?[name, lat, lon] <- [['versailles', 0., 0.]] :put place {name => lat, lon}")
+        .unwrap();
+    db.run_default("?[name, lon] <- [['london', 2.35]] :update place {name => lon}")
+        .unwrap();
+    db.run_default("?[name] <- [['paris']] :rm place {name}")
+        .unwrap();
+    assert_eq!(
+        names("?[name] := ~place:geo{name | query: [48.8566, 2.3522], radius: 400}"),
+        vec!["london"]
+    );
+    assert_eq!(
+        names("?[name] := ~place:geo{name | query: [0., 0.], radius: 1}"),
+        vec!["versailles"]
+    );
+
+    assert!(db
+        .run_default("?[name] := ~place:geo{name | query: [0., 0.]}")
+        .is_err());
+    assert!(db
+        .run_default("?[name, lat, lon] <- [['nowhere', 100., 0.]] :put place {name => lat, lon}")
+        .is_err());
+    let indices = db.run_default("::indices place").unwrap();
+    assert_eq!(indices.rows[0][1], DataValue::from("geo"));
+    db.run_default("::geo drop place:geo").unwrap();
+    db.run_default("::remove place").unwrap();
+}
+
+#[test]
+fn decimal_columns() {
+    let db = DbInstance::default();
+    db.run_default(":create account {id: BigInt => balance: Decimal(2), rate: Decimal?}")
+        .unwrap();
+    db.run_default(
+        r"?[id, balance, rate] <- [
+            ['123456789012345678901234567890', 10.005, null],
+            [2, '0.1', '0.035'],
+            [1, to_decimal('-3'), 1],
+        ]
+        :put account {id => balance, rate}",
+    )
+    .unwrap();
+    assert!(db
+        .run_default(r"?[id, balance] <- [[1.5, 0]] :put account {id => balance}")
+        .is_err());
+    let res = db
+        .run_default("?[id, balance, rate] := *account{id, balance, rate}")
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            ["1", "-3.00", "1"],
+            ["2", "0.10", "0.035"],
+            ["123456789012345678901234567890", "10.01", null]
+        ])
+    );
+    let res = db
+        .run_default("?[id] := *account{id}, id > 1, id < to_decimal('1e40')")
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([["2"], ["123456789012345678901234567890"]])
+    );
+    let res = db
+        .run_default(
+            "?[s] := *account{id: to_bigint(2), balance, rate}, s = to_string(balance * rate)",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"][0][0], json!("0.00350"));
+}
+
+#[test]
+fn duration_columns() {
+    let db = DbInstance::default();
+    db.run_default(":create job {name: String => every: Duration, last: Validity}")
+        .unwrap();
+    db.run_default(
+        r"?[name, every, last] <- [
+            ['backup', '1d', [1000000000, true]],
+            ['cleanup', to_duration(90), [2000000000, true]],
+            ['sync', '15m', [3000000000, true]],
+        ]
+        :put job {name => every, last}",
+    )
+    .unwrap();
+    assert!(db
+        .run_default(
+            r"?[name, every, last] <- [['x', 10, [0, true]]] :put job {name => every, last}"
+        )
+        .is_err());
+    let res = db
+        .run_default(
+            "?[name, every, next] := *job{name, every, last}, next = to_int(last + every) :order every",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            ["cleanup", "1m30s", 2090000000],
+            ["sync", "15m", 3900000000i64],
+            ["backup", "1d", 87400000000i64]
+        ])
+    );
+    let res = db
+        .run_default(
+            "?[name] := *job{name, every}, every >= to_duration('15m'), every < to_duration('2d')",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([["backup"], ["sync"]]));
+    let res = db
+        .run_default(
+            "?[gap] := *job{name: 'backup', last: a}, *job{name: 'sync', last: b}, gap = b - a",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"][0][0], json!("33m20s"));
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn arrow_conversion() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{DurationMicrosecondType, Float64Type, Int64Type};
+    use arrow_schema::{DataType, TimeUnit};
+
+    use crate::NamedRows;
+
+    let db = DbInstance::default();
+    let batch = db
+        .run_script_arrow(
+            r"?[i, f, s, b, d, v, n, mixed] <- [
+                [1, 1, 'a', true, to_duration('1h'), vec([1, 2]), null, 1],
+                [2, 2.5, 'b', false, to_duration('1s'), vec([3, 4]), null, 'x'],
+                [3, null, null, null, null, null, null, [1, 2]],
+            ]",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(batch.num_rows(), 3);
+    let types = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| f.data_type().clone())
+        .collect_vec();
+    assert_eq!(types[0], DataType::Int64);
+    assert_eq!(types[1], DataType::Float64);
+    assert_eq!(types[2], DataType::Utf8);
+    assert_eq!(types[3], DataType::Boolean);
+    assert_eq!(types[4], DataType::Duration(TimeUnit::Microsecond));
+    assert!(matches!(types[5], DataType::List(_)));
+    assert_eq!(types[6], DataType::Null);
+    assert_eq!(types[7], DataType::Utf8);
+    assert_eq!(batch.column(0).as_primitive::<Int64Type>().value(2), 3);
+    assert_eq!(batch.column(1).as_primitive::<Float64Type>().value(1), 2.5);
+    assert!(batch.column(1).is_null(2));
+    assert_eq!(
+        batch
+            .column(4)
+            .as_primitive::<DurationMicrosecondType>()
+            .value(0),
+        3_600_000_000
+    );
+    let mixed = batch.column(7).as_string::<i32>();
+    assert_eq!(mixed.value(0), "1");
+    assert_eq!(mixed.value(1), "x");
+    assert_eq!(mixed.value(2), "[1,2]");
+
+    let rows = NamedRows::from_arrow(&batch).unwrap();
+    assert_eq!(
+        rows.headers,
+        vec!["i", "f", "s", "b", "d", "v", "n", "mixed"]
+    );
+    assert_eq!(
+        rows.rows[1][..5],
+        [
+            DataValue::from(2),
+            DataValue::from(2.5),
+            DataValue::from("b"),
+            DataValue::from(false),
+            DataValue::Duration(1_000_000),
+        ]
+    );
+    assert_eq!(
+        rows.rows[0][5],
+        DataValue::List(vec![DataValue::from(1.0), DataValue::from(2.0)])
+    );
+    assert_eq!(rows.rows[2][1], DataValue::Null);
+}
+
+#[test]
+fn query_transform() {
+    let db = DbInstance::default();
+    let data = r#"
+        ?[user, item, qty] <- [['bob', 'pen', 2], ['alice', 'ink', 1], ['bob', 'cup', 1]]
+    "#;
+    let res = db
+        .run_default(&format!(
+            "{data} :order -qty, item :transform line = {{'user': user, 'qty': qty}}"
+        ))
+        .unwrap()
+        .into_json();
+    assert_eq!(res["headers"], json!(["line"]));
+    assert_eq!(
+        res["rows"],
+        json!([
+            [{"user": "bob", "qty": 2}],
+            [{"user": "bob", "qty": 1}],
+            [{"user": "alice", "qty": 1}]
+        ])
+    );
+
+    let res = db
+        .run_default(&format!(
+            "{data} :order item :transform items = [item, qty] by user"
+        ))
+        .unwrap()
+        .into_json();
+    assert_eq!(res["headers"], json!(["user", "items"]));
+    assert_eq!(
+        res["rows"],
+        json!([["bob", [["cup", 1], ["pen", 2]]], ["alice", [["ink", 1]]]])
+    );
+
+    assert!(db
+        .run_default(&format!("{data} :transform x = nope"))
+        .is_err());
+    assert!(db
+        .run_default(&format!("{data} :transform x = item by nope"))
+        .is_err());
+    assert!(db
+        .run_default(&format!(
+            "{data} :transform x = item :create t {{user, item, qty}}"
+        ))
+        .is_err());
+}
+
+#[test]
+fn metrics() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x}").unwrap();
+    db.run_default("?[x] <- [[1], [2]] :put a {x}").unwrap();
+    db.run_default("?[x] := *a{x}").unwrap();
+    assert!(db.run_default("?[x] := *b{x}").is_err());
+    let text = db.metrics_text();
+    assert!(text.contains("cozo_queries_total 4\n"));
+    assert!(text.contains("cozo_query_errors_total 1\n"));
+    assert!(text.contains("cozo_rows_scanned_total 2\n"));
+    assert!(text.contains("cozo_query_duration_seconds_count 4\n"));
+    assert!(text.contains("cozo_query_duration_seconds_bucket{le=\"+Inf\"} 4\n"));
+}
+
+#[test]
+fn bytes_backup() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x => y}").unwrap();
+    db.run_default("?[x, y] <- [[1, 'a'], [2, 'b']] :put a {x => y}")
+        .unwrap();
+    let data = db.backup_db_to_bytes().unwrap();
+
+    let restored = DbInstance::default();
+    restored.restore_backup_from_bytes(&data).unwrap();
+    assert_eq!(
+        restored
+            .run_default("?[x, y] := *a{x, y}")
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1, "a"], [2, "b"]])
+    );
+    restored.run_default(":create b {x}").unwrap();
+    restored.run_default("?[x] <- [[1]] :put b {x}").unwrap();
+    assert_eq!(
+        restored.run_default("?[x] := *a{x}").unwrap().rows.len(),
+        2
+    );
+
+    assert!(restored.restore_backup_from_bytes(&data).is_err());
+    assert!(DbInstance::default()
+        .restore_backup_from_bytes(&data[..data.len() - 1])
+        .is_err());
+    assert!(DbInstance::default()
+        .restore_backup_from_bytes(b"garbage")
+        .is_err());
+}
+
+#[test]
+#[cfg(feature = "compressed-backup")]
+fn compressed_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = DbInstance::default();
+    db.run_default(":create a {x => y}").unwrap();
+    db.run_default("::index create a:y {y} unique").unwrap();
+    db.run_default("?[x, y] <- [[1, 'a'], [2, 'b']] :put a {x => y}")
+        .unwrap();
+    db.run_default(":create b {x}").unwrap();
+    db.run_default("?[x] <- [[10], [20], [30]] :put b {x}")
+        .unwrap();
+    let path = dir.path().join("backup.cozo");
+    db.backup_db(&path).unwrap();
+    assert!(db.backup_db(&path).is_err());
+
+    let restored = DbInstance::default();
+    restored.restore_backup(&path).unwrap();
+    assert_eq!(
+        restored
+            .run_default("?[x, y] := *a{x, y}")
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1, "a"], [2, "b"]])
+    );
+    assert_eq!(
+        restored
+            .run_default("?[y, x] := *a:y{y, x}")
+            .unwrap()
+            .rows
+            .len(),
+        2
+    );
+    restored.run_default(":create c {x}").unwrap();
+    assert!(restored.restore_backup(&path).is_err());
+
+    let options = RestoreOptions {
+        relations: Some(vec!["a".to_string()]),
+        skip_indices: true,
+    };
+    let partial = DbInstance::default();
+    partial
+        .restore_backup_with_options(&path, &options)
+        .unwrap();
+    assert_eq!(partial.run_default("?[x] := *a{x}").unwrap().rows.len(), 2);
+    assert!(partial.run_default("?[x] := *b{x}").is_err());
+    assert!(partial.run_default("::indices a").unwrap().rows.is_empty());
+    // nothing is left of the unique index
+    partial.run_default("::index create a:y {y}").unwrap();
+    assert_eq!(
+        partial.run_default("::indices a").unwrap().rows[0][1],
+        DataValue::from("normal")
+    );
+
+    let target = DbInstance::default();
+    target.run_default(":create b {x}").unwrap();
+    target
+        .import_from_backup(&path, &["b".to_string()])
+        .unwrap();
+    assert_eq!(target.run_default("?[x] := *b{x}").unwrap().rows.len(), 3);
+    target.run_default(":create a {x => y}").unwrap();
+    target.run_default("::index create a:y {y}").unwrap();
+    assert!(target
+        .import_from_backup(&path, &["a".to_string()])
+        .is_err());
+
+    let garbage = dir.path().join("garbage");
+    std::fs::write(&garbage, b"COZOBAK2 is not enough").unwrap();
+    assert!(DbInstance::default().restore_backup(&garbage).is_err());
+}
+
+#[test]
+#[cfg(all(feature = "compressed-backup", feature = "storage-sqlite"))]
+fn restore_v1_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = new_cozo_mem().unwrap();
+    let run = |script: &str| {
+        db.run_script(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap()
+    };
+    run(":create a {x => y}");
+    run("?[x, y] <- [[1, 'a'], [2, 'b']] :put a {x => y}");
+    let path = dir.path().join("backup.db");
+    db.backup_db_v1(&path).unwrap();
+
+    let restored = DbInstance::default();
+    restored.restore_backup(&path).unwrap();
+    assert_eq!(restored.run_default("?[x] := *a{x}").unwrap().rows.len(), 2);
+    let options = RestoreOptions {
+        relations: Some(vec!["a".to_string()]),
+        skip_indices: false,
+    };
+    assert!(DbInstance::default()
+        .restore_backup_with_options(&path, &options)
+        .is_err());
+}
+
+#[test]
+#[cfg(feature = "sql-reader")]
+fn sql_reader() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("source.db");
+    let conn = sqlite::Connection::open(&path).unwrap();
+    conn.execute(
+        "create table person (id integer primary key, name text, score real, photo blob);
+         insert into person values (1, 'alice', 1.5, x'0102'), (2, 'bob', null, null);",
+    )
+    .unwrap();
+    drop(conn);
+
+    let db = DbInstance::default();
+    let connection = format!("sqlite://{}", path.display());
+    let params = BTreeMap::from([("conn".to_string(), DataValue::from(connection))]);
+    let res = db
+        .run_script(
+            "?[id, name, score, photo] <~ SqlReader(connection: $conn,
+                query: 'select id, name, score, photo from person order by id',
+                columns: ['id', 'name', 'score', 'photo'])",
+            params.clone(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![
+            vec![
+                DataValue::from(1),
+                DataValue::from("alice"),
+                DataValue::from(1.5),
+                DataValue::Bytes(vec![1, 2])
+            ],
+            vec![
+                DataValue::from(2),
+                DataValue::from("bob"),
+                DataValue::Null,
+                DataValue::Null
+            ],
+        ]
+    );
+    assert!(db
+        .run_script(
+            "?[id] <~ SqlReader(connection: $conn, query: 'select id, name from person',
+                columns: ['id'])",
+            params.clone(),
+            ScriptMutability::Immutable,
+        )
+        .is_err());
+    assert!(db
+        .run_script(
+            "?[id] <~ SqlReader(connection: 'oracle://x', query: 'select 1', columns: ['id'])",
+            params,
+            ScriptMutability::Immutable,
+        )
+        .is_err());
+}
+
+#[test]
+fn sandbox() {
+    let dir = tempfile::tempdir().unwrap();
+    let allowed = dir.path().join("allowed");
+    std::fs::create_dir(&allowed).unwrap();
+    std::fs::write(allowed.join("a.csv"), "1,x\n2,y\n").unwrap();
+    std::fs::write(dir.path().join("b.csv"), "3,z\n").unwrap();
+    let read = |db: &DbInstance, path: &std::path::Path| {
+        let url = format!("file://{}", path.display());
+        db.run_script(
+            "?[a, b] <~ CsvReader(url: $url, types: ['Int', 'String'], has_headers: false)",
+            BTreeMap::from([("url".to_string(), DataValue::from(url))]),
+            ScriptMutability::Immutable,
+        )
+    };
+
+    let db = DbInstance::default();
+    assert_eq!(read(&db, &allowed.join("a.csv")).unwrap().rows.len(), 2);
+    db.set_sandbox(Some(Sandbox::default()));
+    let err = read(&db, &allowed.join("a.csv")).unwrap_err();
+    assert!(err.to_string().contains("sandbox"));
+
+    db.set_sandbox(Some(Sandbox::default().allow_path(&allowed)));
+    assert_eq!(read(&db, &allowed.join("a.csv")).unwrap().rows.len(), 2);
+    assert!(read(&db, &dir.path().join("b.csv")).is_err());
+    assert!(read(&db, &allowed.join("../b.csv")).is_err());
+    assert!(read(&db, &allowed.join("missing.csv")).is_err());
+    assert!(db
+        .run_default("?[a] <~ JsonReader(url: 'http://localhost:1/x.json', fields: ['a'])")
+        .unwrap_err()
+        .to_string()
+        .contains("sandbox"));
+
+    db.run_default(":create a {k => v}").unwrap();
+    assert!(db
+        .run_default("::cdc add sink a 'nats://127.0.0.1:1/changes'")
+        .unwrap_err()
+        .to_string()
+        .contains("sandbox"));
+
+    db.set_sandbox(None);
+    assert_eq!(read(&db, &dir.path().join("b.csv")).unwrap().rows.len(), 1);
+}
+
+#[test]
+fn cdc_sink_config() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k => v}").unwrap();
+    assert!(db
+        .run_default("::cdc add sink a 'redis://localhost/x'")
+        .is_err());
+    assert!(db
+        .run_default("::cdc add sink a 'nats://localhost'")
+        .is_err());
+    assert!(db.run_default("::cdc remove sink").is_err());
+    assert!(db.run_default("::cdc list").unwrap().rows.is_empty());
+}
+
+#[test]
+#[cfg(feature = "cdc")]
+fn cdc_sinks() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k => v}").unwrap();
+    assert!(db
+        .run_default("::cdc add sink missing 'nats://127.0.0.1:1/changes'")
+        .is_err());
+    db.run_default("::cdc add sink a 'nats://127.0.0.1:1/changes' avro")
+        .unwrap();
+    assert!(db
+        .run_default("::cdc add sink a 'nats://127.0.0.1:1/changes'")
+        .is_err());
+    assert_eq!(
+        db.run_default("::cdc list").unwrap().into_json()["rows"],
+        json!([["sink", "a", "nats://127.0.0.1:1/changes", "avro", true]])
+    );
+    // the broker is unreachable, the events are dropped
+    db.run_default("?[k, v] <- [[1, 'x']] :put a {k => v}")
+        .unwrap();
+    db.run_default("::cdc remove sink").unwrap();
+    assert!(db.run_default("::cdc list").unwrap().rows.is_empty());
+}
+
+#[test]
+#[cfg(feature = "cdc")]
+fn cdc_events() {
+    use crate::runtime::cdc::{change_events, encode_event, CdcFormat, CDC_AVRO_SCHEMA};
+    use crate::NamedRows;
+
+    let headers = vec!["k".to_string(), "v".to_string()];
+    let new = NamedRows::new(
+        headers.clone(),
+        vec![
+            vec![DataValue::from(1), DataValue::from("new")],
+            vec![DataValue::from(2), DataValue::from("other")],
+        ],
+    );
+    let old = NamedRows::new(
+        headers.clone(),
+        vec![vec![DataValue::from(1), DataValue::from("old")]],
+    );
+    let events = change_events(CallbackOp::Put, 1, new, old);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].message_key(), b"[1]");
+    assert_eq!(
+        events[0].to_json("a", &headers),
+        json!({"relation": "a", "op": "put", "keys": {"k": 1},
+               "new": {"k": 1, "v": "new"}, "old": {"k": 1, "v": "old"}})
+    );
+    assert_eq!(events[1].to_json("a", &headers)["old"], JsonValue::Null);
+
+    let keys = NamedRows::new(vec!["k".to_string()], vec![vec![DataValue::from(1)]]);
+    let old = NamedRows::new(
+        headers.clone(),
+        vec![vec![DataValue::from(1), DataValue::from("old")]],
+    );
+    let events = change_events(CallbackOp::Rm, 1, keys, old);
+    assert_eq!(
+        events[0].to_json("a", &headers),
+        json!({"relation": "a", "op": "rm", "keys": {"k": 1},
+               "new": null, "old": {"k": 1, "v": "old"}})
+    );
+
+    let encoded = encode_event(&events[0], CdcFormat::Avro, "a", &headers).unwrap();
+    let schema = apache_avro::Schema::parse_str(CDC_AVRO_SCHEMA).unwrap();
+    let decoded = apache_avro::from_avro_datum(&schema, &mut encoded.as_slice(), None).unwrap();
+    let decoded: JsonValue = decoded.try_into().unwrap();
+    assert_eq!(decoded["op"], json!("rm"));
+    assert_eq!(decoded["keys"]["k"], json!(1));
+    assert_eq!(decoded["new"], JsonValue::Null);
+    assert_eq!(decoded["old"]["v"], json!("old"));
+}
+
+#[test]
+fn query_cache() {
+    let db = DbInstance::default();
+    let DbInstance::Mem(inner) = &db else {
+        unreachable!()
+    };
+    let hits = || inner.query_cache.hits.load(Ordering::Relaxed);
+    db.run_default(":create a {k => v}").unwrap();
+    db.run_default(":create b {k => v}").unwrap();
+    db.run_default("?[k, v] <- [[1, 'x']] :put a {k => v}")
+        .unwrap();
+
+    // disabled by default
+    db.run_default("?[v] := *a{v}").unwrap();
+    db.run_default("?[v] := *a{v}").unwrap();
+    assert_eq!(hits(), 0);
+
+    db.set_query_cache_capacity(2);
+    assert_eq!(
+        db.run_default("?[v] := *a{v}").unwrap().into_json()["rows"],
+        json!([["x"]])
+    );
+    assert_eq!(
+        db.run_default("?[v]   :=\n  *a{v}").unwrap().into_json()["rows"],
+        json!([["x"]])
+    );
+    assert_eq!(hits(), 1);
+
+    // writing to another relation keeps the entry
+    db.run_default("?[k, v] <- [[1, 'y']] :put b {k => v}")
+        .unwrap();
+    db.run_default("?[v] := *a{v}").unwrap();
+    assert_eq!(hits(), 2);
+
+    // writing to the relation invalidates it
+    db.run_default("?[k, v] <- [[2, 'z']] :put a {k => v}")
+        .unwrap();
+    assert_eq!(
+        db.run_default("?[v] := *a{v}").unwrap().into_json()["rows"],
+        json!([["x"], ["z"]])
+    );
+    assert_eq!(hits(), 2);
+
+    // parameters are part of the key
+    let q = "?[v] := *a{k: $k, v}";
+    let params = |k: i64| BTreeMap::from([("k".to_string(), DataValue::from(k))]);
+    let run = |k| {
+        db.run_script(q, params(k), ScriptMutability::Immutable)
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(run(1), json!([["x"]]));
+    assert_eq!(run(2), json!([["z"]]));
+    assert_eq!(run(1), json!([["x"]]));
+    assert_eq!(hits(), 3);
+
+    // impure scripts are never cached
+    for q in [
+        "?[v, t] := *a{v}, t = now()",
+        "?[choice_rand(v)] := *a{v}",
+        "r[k, v] := *a{k, v} ?[n, v] <~ Node2Vec(r[], dimensions: 2)",
+        "r[k, v] := *a{k, v} ?[k, v] <~ Sample(r[], n: 1)",
+        "?[v] := *a{v}, v != 'NOW'",
+    ] {
+        db.run_default(q).unwrap();
+        db.run_default(q).unwrap();
+    }
+    assert_eq!(hits(), 3);
+
+    // layout only counts outside comments and strings
+    assert_eq!(
+        db.run_default("?[k] := *a{k} # note\n, k > 1")
+            .unwrap()
+            .into_json()["rows"],
+        json!([[2]])
+    );
+    assert_eq!(
+        db.run_default("?[k] := *a{k} # note , k > 1")
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1], [2]])
+    );
+    assert_eq!(
+        db.run_default(r#"?[s] <- [[_"a"  b"_]]"#)
+            .unwrap()
+            .into_json()["rows"],
+        json!([["a\"  b"]])
+    );
+    assert_eq!(
+        db.run_default(r#"?[s] <- [[_"a" b"_]]"#)
+            .unwrap()
+            .into_json()["rows"],
+        json!([["a\" b"]])
+    );
+
+    // dropping the relation invalidates it too
+    db.run_default("?[v] := *a{v}").unwrap();
+    db.run_default("::remove a").unwrap();
+    assert!(db.run_default("?[v] := *a{v}").is_err());
+}
+
+#[test]
+fn large_hash_joins() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k => v}").unwrap();
+    db.run_default(":create b {k => v}").unwrap();
+    db.run_default("?[k, v] <- [[1, 5], [2, 7], [3, 1000]] :put a {k => v}")
+        .unwrap();
+    db.run_default("?[k, v] := k in int_range(30000), v = k % 100 :put b {k => v}")
+        .unwrap();
+    // `b` is joined on its non-key column, with more rows than the threshold
+    let query = "?[x, count(k)] := *a{k: x, v}, *b{k, v}";
+    let expected = json!([[1, 300], [2, 300]]);
+    let spills = || {
+        db.metrics_text()
+            .lines()
+            .find_map(|l| l.strip_prefix("cozo_hash_join_spills_total "))
+            .unwrap()
+            .to_string()
+    };
+
+    assert_eq!(db.run_default(query).unwrap().into_json()["rows"], expected);
+    assert_eq!(spills(), "0");
+
+    db.set_memory_budget(Some(500_000)).unwrap();
+    assert_eq!(db.run_default(query).unwrap().into_json()["rows"], expected);
+    assert_eq!(
+        db.run_default(&format!("{query} :experimental hash_join"))
+            .unwrap()
+            .into_json()["rows"],
+        expected
+    );
+    assert_eq!(spills(), "2");
+}
+
+#[test]
+fn independent_strata() {
+    let db = DbInstance::default();
+    db.run_default(":create r {x}").unwrap();
+    db.run_default(":create s {y}").unwrap();
+    db.run_default("?[x] <- [[1], [2], [3]] :put r {x}")
+        .unwrap();
+    db.run_default("?[y] <- [[1], [2]] :put s {y}").unwrap();
+    let query = r#"
+        c0[x] := *r{x}, x > 1
+        c1[count(x)] := c0[x]
+        c2[n, count(n)] := c1[n]
+        b1[y] := *s{y}
+        b2[count(y)] := b1[y]
+        ?[p, q] := c2[p, _], b2[q]
+    "#;
+    assert_eq!(
+        db.run_default(query).unwrap().into_json()["rows"],
+        json!([[2, 2]])
+    );
+    let explained = db.run_default(&format!("::explain {{ {query} }}")).unwrap();
+    let stratum_of = |rule: &str| {
+        explained
+            .rows
+            .iter()
+            .find(|row| row[2] == DataValue::from(rule))
+            .map(|row| row[0].clone())
+            .unwrap()
+    };
+    assert_eq!(stratum_of("c0"), DataValue::from(0));
+    assert_eq!(stratum_of("c1"), DataValue::from(1));
+    // `b1` would be evaluated with `c1`, but does not depend on anything
+    assert_eq!(stratum_of("b1"), DataValue::from(0));
+    assert_eq!(stratum_of("?"), DataValue::from(2));
+}
+
+#[test]
+fn joins_decode_needed_columns() {
+    let db = DbInstance::default();
+    db.run_default(":create people {id, tag => name, bio, score}")
+        .unwrap();
+    db.run_default(":create pairs {a, b}").unwrap();
+    db.run_default(
+        r#"?[id, tag, name, bio, score] := id in int_range(100), tag = id % 3,
+                name = concat('p', to_string(id)), bio = [id, 'a long biography'], score = id * 2
+           :put people {id, tag => name, bio, score}"#,
+    )
+    .unwrap();
+    db.run_default("?[a, b] := a in int_range(50), b = a % 3 :put pairs {a, b}")
+        .unwrap();
+
+    // point lookups, using one value column in the output and another in a filter
+    let res = db
+        .run_default("?[a, name] := *pairs{a, b}, *people{id: a, tag: b, name, score}, score > 90")
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([[46, "p46"], [47, "p47"], [48, "p48"], [49, "p49"]])
+    );
+
+    // prefix scans, with some columns only used for the join or not at all
+    let res = db
+        .run_default("?[a, count(tag)] := *pairs{a, b: 0}, *people{id: a, tag, bio}, a < 10")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[0, 1], [3, 1], [6, 1], [9, 1]]));
+    let res = db
+        .run_default("?[a, bio] := *pairs{a, b: 1}, *people{id: a, bio}, a < 5")
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([[1, [1, "a long biography"]], [4, [4, "a long biography"]]])
+    );
+
+    // bounded prefix scans on a filtered key
+    let res = db
+        .run_default("?[a, name] := *pairs{a}, *people{id: a, tag, name}, tag > 1, a < 6")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[2, "p2"], [5, "p5"]]));
+}
+
+#[test]
+fn projection_pushdown() {
+    let db = DbInstance::default();
+    db.run_default(":create wide {k => c1, c2, c3, c4, c5, c6, c7, c8, c9}")
+        .unwrap();
+    db.run_default(
+        r#"?[k, c1, c2, c3, c4, c5, c6, c7, c8, c9] := k in int_range(20),
+                c1 = k * 10, c2 = to_string(k), c3 = [k], c4 = k % 2, c5 = null,
+                c6 = 'six', c7 = k + 0.5, c8 = {}, c9 = k % 5
+           :put wide {k => c1, c2, c3, c4, c5, c6, c7, c8, c9}"#,
+    )
+    .unwrap();
+    let rows = |q: &str| db.run_default(q).unwrap().into_json()["rows"].clone();
+
+    // full scan with a filter on a column not in the output
+    assert_eq!(
+        rows("?[k, c2] := *wide{k, c2, c9}, c9 == 3"),
+        json!([[3, "3"], [8, "8"], [13, "13"], [18, "18"]])
+    );
+    // positional access with ignored columns
+    assert_eq!(
+        rows("?[c1] := *wide[k, c1, _, _, _, _, _, _, _, _], k > 17"),
+        json!([[180], [190]])
+    );
+    // materialized join on a non-key column, and unification on a projected column
+    assert_eq!(
+        rows("?[k, x] := *wide{k: j, c4: 1}, *wide{k, c9: j}, x = k + j, k < 10"),
+        json!([[1, 2], [3, 6], [6, 7], [8, 11]])
+    );
+    // rows equal on the projected columns are still counted separately
+    assert_eq!(
+        rows("?[c4, count(c4)] := *wide{c9: 3, c4}"),
+        json!([[0, 2], [1, 2]])
+    );
+    // negation only reads the join columns
+    assert_eq!(
+        rows("?[k] := *wide{k, c4: 0}, not *wide{k, c9: 0}, k < 10"),
+        json!([[2], [4], [6], [8]])
+    );
+    // every column is still returned when asked for
+    assert_eq!(
+        rows("?[k, c1, c2, c3, c4, c5, c6, c7, c8, c9] := *wide{k, c1, c2, c3, c4, c5, c6, c7, c8, c9}, k == 1"),
+        json!([[1, 10, "1", [1], 1, null, "six", 1.5, {}, 1]])
+    );
+}
+
+#[test]
+fn key_range_scans() {
+    let db = DbInstance::default();
+    db.run_default(":create nums {x: Int, y: Int => z}")
+        .unwrap();
+    db.run_default(
+        "?[x, y, z] := x in int_range(1000), y = x % 7, z = x * 2 :put nums {x, y => z}",
+    )
+    .unwrap();
+    let scanned = || {
+        db.metrics_text()
+            .lines()
+            .find_map(|l| l.strip_prefix("cozo_rows_scanned_total "))
+            .unwrap()
+            .parse::<u64>()
+            .unwrap()
+    };
+    let rows_and_scanned = |q: &str| {
+        let before = scanned();
+        let rows = db.run_default(q).unwrap().into_json()["rows"].clone();
+        (rows, scanned() - before)
+    };
+
+    let (rows, n) = rows_and_scanned("?[x] := *nums{x}, x > 10, x < 14");
+    assert_eq!(rows, json!([[11], [12], [13]]));
+    assert!(n <= 5, "{n}");
+    let (rows, n) = rows_and_scanned("?[x, y] := *nums{x, y}, x >= 995, y < 3");
+    assert_eq!(rows, json!([[995, 1], [996, 2]]));
+    assert!(n <= 5, "{n}");
+    let (rows, n) = rows_and_scanned("?[x, z] := *nums{x, y: 3, z}, x <= 10.5");
+    assert_eq!(rows, json!([[3, 6], [10, 20]]));
+    assert!(n <= 11, "{n}");
+    let (rows, n) = rows_and_scanned("?[x] := *nums{x}, x > 10, x < 5");
+    assert_eq!(rows, json!([]));
+    assert_eq!(n, 0);
+    // bounds only on a later key column cannot narrow the scan
+    let (rows, n) = rows_and_scanned("?[count(x)] := *nums{x, y}, y > 5");
+    assert_eq!(rows, json!([[142]]));
+    assert_eq!(n, 1000);
+
+    db.run_default(":create events {id: Int, at: Validity => v: String}")
+        .unwrap();
+    db.run_default(
+        r#"?[id, at, v] <- [[1, [1, true], 'a'], [1, [5, true], 'b'], [2, [1, true], 'c'],
+                            [3, [1, true], 'd'], [3, [2, false], 'e']]
+           :put events {id, at => v}"#,
+    )
+    .unwrap();
+    // the same conditions, written so that no bounds can be extracted from them
+    for cond in ["id > 1", "id <= 1", "id == 2", "id > 2"] {
+        let (rows, _) = rows_and_scanned(&format!("?[id, v] := *events{{id, v @ 3}}, {cond}"));
+        let (expected, _) =
+            rows_and_scanned(&format!("?[id, v] := *events{{id, v @ 3}}, !!({cond})"));
+        assert_eq!(rows, expected, "{cond}");
+    }
+    let (rows, n) = rows_and_scanned("?[id, v] := *events{id, v @ 3}, id >= 2");
+    assert_eq!(rows, json!([[2, "c"]]));
+    assert!(n < 5, "{n}");
+}
+
+#[test]
+fn index_advisor() {
+    let db = DbInstance::default();
+    db.run_default(":create follows {fr: Int, to: Int => weight: Float}")
+        .unwrap();
+    db.run_default(":create docs {id: Int => text: String, emb: <F32; 2>}")
+        .unwrap();
+    let advice = |q: &str| {
+        db.run_default(&format!("::index advisor {{ {q} }}"))
+            .unwrap()
+            .rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row[2].get_str().unwrap().to_string(),
+                    row[5].get_str().unwrap().to_string(),
+                )
+            })
+            .collect_vec()
+    };
+
+    // joining on the second key scans everything
+    let join_query = "?[fr, w] := *follows{fr, to: 3, weight: w}";
+    assert_eq!(
+        advice(join_query),
+        vec![(
+            "index".to_string(),
+            "::index create follows:idx_to {to}".to_string()
+        )]
+    );
+    // joining on the first key is already a prefix scan
+    assert!(advice("?[to] := *follows{fr: 3, to}").is_empty());
+    // negations are covered by the same index
+    assert_eq!(advice("?[x] := x in [1, 2], not *follows{to: x}").len(), 1);
+    assert_eq!(
+        advice("?[id] := *docs{id, text}, str_includes(text, 'cozo')"),
+        vec![(
+            "fts".to_string(),
+            "::fts create docs:fts_text {extractor: text, tokenizer: Simple, filters: [Lowercase]}"
+                .to_string()
+        )]
+    );
+    assert_eq!(
+        advice("?[id] := *docs{id, emb}, cos_dist(emb, vec([1, 0])) < 0.1"),
+        vec![(
+            "hnsw".to_string(),
+            "::hnsw create docs:hnsw_emb {dim: 2, dtype: F32, fields: [emb], distance: Cosine, m: 50, ef_construction: 20}"
+                .to_string()
+        )]
+    );
+
+    // the suggestion is picked up by the planner
+    db.run_default("::index create follows:idx_to {to}")
+        .unwrap();
+    assert!(advice(join_query).is_empty());
+    let plan = db
+        .run_default(&format!("::explain {{ {join_query} }}"))
+        .unwrap()
+        .into_json();
+    assert!(!plan["rows"].to_string().contains("stored_mat_join"));
+    assert!(advice("?[fr] := *follows{fr, to: 3, weight: 1.0}").is_empty());
+}
+
+#[test]
+fn covering_index() {
+    let db = DbInstance::default();
+    let rows = |q: &str| db.run_default(q).unwrap().into_json()["rows"].clone();
+    db.run_default(":create users {id: Int => name: String, age: Int, city: String}")
+        .unwrap();
+    db.run_default(
+        r"?[id, name, age, city] <- [[1, 'alice', 30, 'paris'], [2, 'bob', 40, 'rome'],
+                                     [3, 'carol', 30, 'oslo']]
+          :put users {id => name, age, city}",
+    )
+    .unwrap();
+    // an index on the same column without payload is considered first
+    db.run_default("::index create users:a_age {age}").unwrap();
+    db.run_default("::index create users:by_age {age => name}")
+        .unwrap();
+    let loaded = |q: &str| {
+        let plan = db
+            .run_default(&format!("::explain {{ {q} }}"))
+            .unwrap()
+            .into_json();
+        plan["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|row| row[4].as_str().unwrap().starts_with("load_"))
+            .map(|row| row[5].as_str().unwrap().to_string())
+            .collect_vec()
+    };
+
+    let by_age = "?[id, name] := *users{id, age: 30, name}";
+    assert_eq!(loaded(by_age), vec![":users:by_age".to_string()]);
+    assert_eq!(rows(by_age), json!([[1, "alice"], [3, "carol"]]));
+    // columns outside of the index still need the relation
+    let with_city = "?[name, city] := *users{age: 30, name, city}";
+    assert!(loaded(with_city).contains(&":users".to_string()));
+    assert_eq!(
+        rows(with_city),
+        json!([["alice", "paris"], ["carol", "oslo"]])
+    );
+
+    // the payload is kept up to date
+    db.run_default(
+        "?[id, name, age, city] <- [[1, 'alicia', 30, 'paris']] :put users {id => name, age, city}",
+    )
+    .unwrap();
+    db.run_default("?[id, name] <- [[3, 'caroline']] :update users {id => name}")
+        .unwrap();
+    db.run_default("?[id] <- [[2]] :rm users {id}").unwrap();
+    assert_eq!(rows(by_age), json!([[1, "alicia"], [3, "caroline"]]));
+    assert_eq!(rows("?[name] := *users{age: 40, name}"), json!([]));
+    assert_eq!(rows("::verify users")[2][7], json!(true));
+
+    let indices = rows("::indices users");
+    assert_eq!(indices[1][3], json!({"indices": [2, 0], "payload": [1]}));
+    assert!(db
+        .run_default("::index create users:bad {age => id}")
+        .is_err());
+    assert!(db
+        .run_default("::index create users:bad {age => nope}")
+        .is_err());
+}
+
+#[test]
+fn unique_index() {
+    let db = DbInstance::default();
+    let rows = |q: &str| db.run_default(q).unwrap().into_json()["rows"].clone();
+    let put = |data: &str| {
+        db.run_default(&format!(
+            "?[id, email, name] <- {data} :put users {{id => email, name}}"
+        ))
+    };
+    db.run_default(":create users {id: Int => email: String, name: String}")
+        .unwrap();
+    put("[[1, 'a@x', 'alice'], [2, 'b@x', 'bob']]").unwrap();
+    db.run_default("::index create users:by_email {email} unique")
+        .unwrap();
+
+    let err = put("[[3, 'a@x', 'carol']]").unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "transact::unique_violation"
+    );
+    assert!(err.chain().any(|e| e.to_string().contains("\"a@x\"")));
+    assert!(err.help().unwrap().to_string().contains("[1]"));
+
+    // a row does not conflict with itself, and values are freed when they change
+    put("[[1, 'a@x', 'alicia']]").unwrap();
+    put("[[1, 'c@x', 'alicia']]").unwrap();
+    put("[[3, 'a@x', 'carol']]").unwrap();
+    assert!(db
+        .run_default("?[id, email] <- [[2, 'c@x']] :update users {id => email}")
+        .is_err());
+    // rows of the same batch are checked against each other
+    assert!(put("[[4, 'd@x', 'dan'], [5, 'd@x', 'dave']]").is_err());
+    assert_eq!(rows("?[id] := *users{id, email: 'd@x'}"), json!([]));
+    db.run_default("?[id] <- [[3]] :rm users {id}").unwrap();
+    put("[[4, 'a@x', 'dan']]").unwrap();
+
+    assert_eq!(
+        rows("?[id, name] := *users{id, email: 'c@x', name}"),
+        json!([[1, "alicia"]])
+    );
+    assert_eq!(rows("::indices users")[0][1], json!("unique"));
+    assert_eq!(rows("::verify users")[1][7], json!(true));
+
+    // existing duplicates prevent the creation of the index
+    put("[[5, 'e@x', 'dan']]").unwrap();
+    assert!(db
+        .run_default("::index create users:by_name {name} unique")
+        .is_err());
+    assert_eq!(rows("::indices users").as_array().unwrap().len(), 1);
+    db.run_default(":create hist {id: Int, at: Validity => email: String}")
+        .unwrap();
+    assert!(db
+        .run_default("::index create hist:by_email {email} unique")
+        .is_err());
+}
+
+#[test]
+fn expression_index() {
+    let db = DbInstance::default();
+    let rows = |q: &str| db.run_default(q).unwrap().into_json()["rows"].clone();
+    db.run_default(":create users {id: Int => email: String}")
+        .unwrap();
+    db.run_default("?[id, email] <- [[1, 'A@x'], [2, 'b@X']] :put users {id => email}")
+        .unwrap();
+    db.run_default("::index create users:by_email {lowercase(email)} unique")
+        .unwrap();
+    assert!(db
+        .run_default("::index create users:bad {lowercase(nope)}")
+        .is_err());
+
+    // the expression is evaluated on writes
+    db.run_default("?[id, email] <- [[3, 'C@x']] :put users {id => email}")
+        .unwrap();
+    db.run_default("?[id, email] <- [[2, 'd@x']] :put users {id => email}")
+        .unwrap();
+    assert!(db
+        .run_default("?[id, email] <- [[4, 'a@X']] :put users {id => email}")
+        .is_err());
+    db.run_default("?[id] <- [[3]] :rm users {id}").unwrap();
+    assert_eq!(rows("::verify users")[1][7], json!(true));
+
+    // filters on the same expression scan the index
+    let q = "?[id, e] := *users{id, email: e}, lowercase(e) == 'd@x'";
+    assert_eq!(rows(q), json!([[2, "d@x"]]));
+    let plan = rows(&format!("::explain {{ {q} }}"));
+    assert!(plan
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|r| r[5] == json!(":users:by_email")));
+    assert_eq!(
+        rows("?[id] := *users{id, email}, 'a@x' == lowercase(email)"),
+        json!([[1]])
+    );
+    assert_eq!(
+        rows("?[id] := *users{id, email}, lowercase(email) == 'c@x'"),
+        json!([])
+    );
+
+    db.run_default("::index drop users:by_email").unwrap();
+    assert_eq!(
+        rows("?[id] := *users{id, email}, lowercase(email) == 'a@x'"),
+        json!([[1]])
+    );
+}
+
+#[test]
+fn generated_columns() {
+    let db = DbInstance::default();
+    let rows = |q: &str| db.run_default(q).unwrap().into_json()["rows"].clone();
+    db.run_default(":create events {id: Int => payload: Json}")
+        .unwrap();
+    db.run_default(
+        r#"?[id, payload] <- [[1, parse_json('{"kind": "a", "n": 1}')],
+                              [2, parse_json('{"kind": "b"}')],
+                              [3, parse_json('{}')]]
+           :put events {id => payload}"#,
+    )
+    .unwrap();
+    db.run_default("::alter events add generated kind = json_get(payload, 'kind') index")
+        .unwrap();
+    db.run_default("::alter events add generated n = json_get(payload, ['n'])")
+        .unwrap();
+
+    assert_eq!(
+        rows("?[id, kind, n] := *events{id, kind, n}"),
+        json!([[1, "a", 1], [2, "b", null], [3, null, null]])
+    );
+    assert_eq!(rows("?[id] := *events{id, kind: 'b'}"), json!([[2]]));
+    assert_eq!(
+        rows("?[k] := *events.kind(k), k != null"),
+        json!([["a"], ["b"]])
+    );
+
+    // rows written later are indexed too, and filters on the column scan the index
+    db.run_default(
+        r#"?[id, payload] <- [[4, parse_json('{"kind": "b"}')]] :put events {id => payload}"#,
+    )
+    .unwrap();
+    let q = "?[id] := *events{id, kind: 'b'}";
+    assert_eq!(rows(q), json!([[2], [4]]));
+    let plan = rows(&format!("::explain {{ {q} }}"));
+    assert!(plan
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|r| r[5] == json!(":events:kind")));
+
+    let err = db
+        .run_default("::alter events add generated kind = 1")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "tx::generated_column_exists"
+    );
+    let err = db
+        .run_default("::alter events add generated x = json_get(body, 'x')")
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::bad_generated_column");
+    let err = db
+        .run_default("?[id] := *events{id}, not *events{id, kind: 'a'}")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::negated_generated_column"
+    );
+
+    db.run_default("::alter events drop generated kind")
+        .unwrap();
+    assert_eq!(rows("::indices events"), json!([]));
+    let err = db.run_default("?[k] := *events{kind: k}").unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::named_field_not_found"
+    );
+    let err = db
+        .run_default("::alter events drop generated kind")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "tx::generated_column_not_found"
+    );
+}
+
+#[test]
+fn partitioned_scans() {
+    let db = DbInstance::default();
+    db.run_default(":create nums {x: Int => y: Int}").unwrap();
+    db.run_default("?[x, y] := x in int_range(100), y = x % 10 :put nums {x => y}")
+        .unwrap();
+    assert!(db.run_default("::partition set nums at 25").is_err());
+    assert!(db.run_default("::partition set nums at ['a']").is_err());
+    db.run_default("::partition set nums at [75, 25, 50, 50]")
+        .unwrap();
+    let res = db.run_default("::partition list nums").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            [0, null, 25, 25],
+            [1, 25, 50, 25],
+            [2, 50, 75, 25],
+            [3, 75, null, 25]
+        ])
+    );
+
+    let scanned = || {
+        db.metrics_text()
+            .lines()
+            .find_map(|l| l.strip_prefix("cozo_rows_scanned_total "))
+            .unwrap()
+            .parse::<u64>()
+            .unwrap()
+    };
+    let rows_and_scanned = |q: &str| {
+        let before = scanned();
+        let rows = db.run_default(q).unwrap().into_json()["rows"].clone();
+        (rows, scanned() - before)
+    };
+    let (rows, n) = rows_and_scanned("?[x] := *nums{x, y}, y == 0");
+    assert_eq!(
+        rows,
+        json!([[0], [10], [20], [30], [40], [50], [60], [70], [80], [90]])
+    );
+    assert_eq!(n, 100);
+    let (rows, n) = rows_and_scanned("?[x] := *nums{x}, x >= 48, x < 53");
+    assert_eq!(rows, json!([[48], [49], [50], [51], [52]]));
+    assert!(n <= 7, "{n}");
+    let (rows, _) = rows_and_scanned("?[x, y] := *nums{x, y} :order x :limit 3");
+    assert_eq!(rows, json!([[0, 0], [1, 1], [2, 2]]));
+
+    db.run_default("::partition remove nums").unwrap();
+    let res = db.run_default("::partition list nums").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[0, null, null, 100]]));
+}
+
+#[test]
+fn bulk_deletes() {
+    let db = DbInstance::default();
+    db.run_default(":create logs {id: Int => level: String}")
+        .unwrap();
+    db.run_default("::index create logs:by_level {level}")
+        .unwrap();
+    db.run_default(
+        "?[id, level] := id in int_range(3000), level = if(id % 3 == 0, 'debug', 'info') \
+         :put logs {id => level}",
+    )
+    .unwrap();
+    let count = |q: &str| db.run_default(q).unwrap().rows[0][0].clone();
+
+    let res = db
+        .run_script(
+            "::delete_where logs level == 'debug' && id < $max",
+            BTreeMap::from([("max".to_string(), DataValue::from(2400))]),
+            ScriptMutability::Mutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[800]]));
+    assert_eq!(count("?[count(id)] := *logs{id}"), DataValue::from(2200));
+    assert_eq!(
+        count("?[count(id)] := *logs:by_level{level: 'debug', id}"),
+        DataValue::from(200)
+    );
+    assert!(db
+        .run_default("::delete_where logs nonexistent > 1")
+        .is_err());
+    assert!(db
+        .run_script(
+            "::delete_where logs true",
+            Default::default(),
+            ScriptMutability::Immutable
+        )
+        .is_err());
+
+    let res = db.run_default("::truncate logs").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2200]]));
+    assert_eq!(
+        count("?[count(id)] := *logs:by_level{id}"),
+        DataValue::from(0)
+    );
+
+    db.run_default(":create plain {k: Int => v: Int}").unwrap();
+    db.run_default("?[k, v] := k in int_range(2500), v = k :put plain {k => v}")
+        .unwrap();
+    let res = db.run_default("::truncate plain").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2500]]));
+    assert!(db.run_default("?[k] := *plain{k}").unwrap().rows.is_empty());
+
+    // callbacks see the deleted rows
+    db.run_default(":create watched {k: Int => v: Int}")
+        .unwrap();
+    db.run_default("?[k, v] := k in int_range(10), v = k :put watched {k => v}")
+        .unwrap();
+    let (_id, receiver) = db.register_callback("watched", None);
+    db.run_default("::delete_where watched k < 3").unwrap();
+    db.run_default("::truncate watched").unwrap();
+    let mut deleted = vec![];
+    while let Ok((op, new, old)) = receiver.recv_timeout(Duration::from_secs(1)) {
+        assert_eq!(op, CallbackOp::Rm);
+        deleted.push((new.rows.len(), old.rows.len()));
+        if deleted.len() == 2 {
+            break;
+        }
+    }
+    assert_eq!(deleted, vec![(3, 3), (7, 7)]);
+}
+
+#[test]
+fn upsert_partial_columns() {
+    let db = DbInstance::default();
+    db.run_default(":create users {id: Int => name: String, email: String, visits: Int default 0}")
+        .unwrap();
+    db.run_default("::index create users:by_email {email}")
+        .unwrap();
+    db.run_default(
+        "?[id, name, email, visits] <- [[1, 'a', 'a@x', 5]] :put users {id => name, email, visits}",
+    )
+    .unwrap();
+
+    db.run_default("?[id, visits] <- [[1, 6]] :upsert users {id => visits}")
+        .unwrap();
+    let res = db
+        .run_default("?[id, name, email, visits] := *users{id, name, email, visits}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "a", "a@x", 6]]));
+
+    assert!(db
+        .run_default("?[id, visits] <- [[2, 1]] :update users {id => visits}")
+        .is_err());
+    assert!(db
+        .run_default("?[id, visits] <- [[2, 1]] :upsert users {id => visits}")
+        .is_err());
+
+    db.run_default("?[id, name, email] <- [[2, 'b', 'b@x']] :upsert users {id => name, email}")
+        .unwrap();
+    let res = db
+        .run_default("?[id, name, email, visits] := *users{id, name, email, visits}")
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "a", "a@x", 6], [2, "b", "b@x", 0]])
+    );
+    let res = db
+        .run_default("?[id] := *users:by_email{email: 'b@x', id}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2]]));
+}
+
+#[test]
+fn guarded_mutations() {
+    let db = DbInstance::default();
+    db.run_default(":create accounts {id: Int => balance: Int, version: Int}")
+        .unwrap();
+    db.run_default("?[id, balance, version] <- [[1, 100, 1], [2, 50, 1]] :put accounts {id => balance, version}")
+        .unwrap();
+
+    let cas = |id: i64, balance: i64, v: i64| {
+        db.run_script(
+            "?[id, balance, version] <- [[$id, $balance, $v + 1]] \
+             :put accounts {id => balance, version} if old.version == $v",
+            BTreeMap::from([
+                ("id".to_string(), DataValue::from(id)),
+                ("balance".to_string(), DataValue::from(balance)),
+                ("v".to_string(), DataValue::from(v)),
+            ]),
+            ScriptMutability::Mutable,
+        )
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(cas(1, 80, 1), json!([["OK", 1]]));
+    assert_eq!(cas(1, 60, 1), json!([["OK", 0]]));
+    assert_eq!(cas(3, 10, 0), json!([["OK", 0]]));
+    let res = db
+        .run_default("?[id, balance, version] := *accounts{id, balance, version}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 80, 2], [2, 50, 1]]));
+
+    let res = db
+        .run_default("?[id] <- [[1], [2]] :rm accounts {id} if old.balance < 60")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["OK", 1]]));
+    let res = db.run_default("?[id] := *accounts{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+
+    assert!(db
+        .run_default("?[id, balance, version] <- [[5, 1, 1]] :insert accounts {id => balance, version} if true")
+        .is_err());
+}
+
+#[test]
+fn mutation_counts() {
+    let db = DbInstance::default();
+    db.run_default(":create kv {k: Int => v: String}").unwrap();
+    let res = db
+        .run_default("?[k, v] <- [[0, 'z']] :put kv {k => v}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["OK"]]));
+    let res = db
+        .run_default("?[k, v] <- [[1, 'a'], [2, 'b']] :put kv {k => v} :returning counts")
+        .unwrap();
+    assert_eq!(res.headers, ["status", "inserted", "updated", "deleted"]);
+    assert_eq!(res.into_json()["rows"], json!([["OK", 2, 0, 0]]));
+    let res = db
+        .run_default("?[k, v] <- [[2, 'c'], [3, 'd']] :put kv {k => v} :returning counts")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["OK", 1, 1, 0]]));
+    let res = db
+        .run_default("?[k] <- [[1], [5]] :rm kv {k} :returning counts")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["OK", 0, 0, 1]]));
+
+    let res = db
+        .run_default("?[k, v] <- [[3, 'e'], [4, 'f']] :put kv {k => v} :returning keys")
+        .unwrap();
+    assert_eq!(res.headers, ["_kind", "k"]);
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["inserted", 3], ["inserted", 4], ["replaced", 3]])
+    );
+}
+
+#[test]
+fn atomic_migration_block() {
+    let db = DbInstance::default();
+    db.run_default(":create old_users {id: Int => name: String}")
+        .unwrap();
+    db.run_default("?[id, name] <- [[1, 'a'], [2, 'b']] :put old_users {id => name}")
+        .unwrap();
+    let migration = |last: &str| {
+        format!(
+            r#"
+            {{::rename old_users -> legacy_users}}
+            {{:create users {{id: Int => name: String}}}}
+            {{?[id, name] := *legacy_users{{id, name}} :put users {{id => name}}}}
+            {{::remove legacy_users}}
+            {{{last}}}
+            "#
+        )
+    };
+
+    assert!(db
+        .run_default(&migration(
+            "?[id, name] <- [[1, 'c']] :insert users {id => name}"
+        ))
+        .is_err());
+    let res = db
+        .run_default("?[id, name] := *old_users{id, name}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "a"], [2, "b"]]));
+    assert!(db.run_default("?[id] := *users{id}").is_err());
+    assert!(db.run_default("?[id] := *legacy_users{id}").is_err());
+
+    db.run_default(&migration(
+        "?[id, name] <- [[3, 'c']] :insert users {id => name}",
+    ))
+    .unwrap();
+    let res = db.run_default("?[id, name] := *users{id, name}").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "a"], [2, "b"], [3, "c"]])
+    );
+    assert!(db.run_default("?[id] := *old_users{id}").is_err());
+}
+
+#[test]
+fn system_relations() {
+    let db = DbInstance::default();
+    db.run_default(":create users {id: Int => name: String default ''}")
+        .unwrap();
+    db.run_default(":create posts {id: Int => author: Int, title: String}")
+        .unwrap();
+    db.run_default("::index create posts:by_author {author}")
+        .unwrap();
+    db.run_default(
+        r#"::set_triggers users on put {
+            ?[id, author, title] := _new[id, _], author = id, title = ''
+            :put posts {id => author, title}
+        }"#,
+    )
+    .unwrap();
+    db.run_default("?[id, name] <- [[1, 'a']] :put users {id => name}")
+        .unwrap();
+
+    let res = db
+        .run_default("?[name, arity] := *__relations{name, arity}, not starts_with(name, '_')")
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["posts", 3], ["posts:by_author", 2], ["users", 2]])
+    );
+    let res = db
+        .run_default("?[column, type] := *__columns{relation: 'users', column, type}")
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["id", "Int"], ["name", "String"]])
+    );
+    let res = db
+        .run_default("?[relation, name, type] := *__indices{relation, name, type}")
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["posts", "by_author", "normal"]])
+    );
+    let res = db
+        .run_default("?[relation, type, idx] := *__triggers{relation, type, idx}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["users", "put", 0]]));
+
+    // metadata joined with data
+    let res = db
+        .run_default(
+            "?[rel, count(id)] := *__relations{name: rel, n_put_triggers}, n_put_triggers > 0, \
+             *users{id}",
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["users", 1]]));
+
+    // generated anew for each query of an imperative script
+    let res = db
+        .run_default(
+            r#"
+            {?[name] := *__relations{name}, name == 'tags'}
+            {:create tags {tag: String}}
+            {?[name] := *__relations{name}, name == 'tags'}
+            "#,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["tags"]]));
+}
+
+#[test]
+fn virtual_relations() {
+    use crate::{VirtualRelation, VirtualRows};
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Default)]
+    struct Scores {
+        rows: Mutex<Vec<Vec<DataValue>>>,
+        prefix_scans: AtomicUsize,
+    }
+
+    impl VirtualRelation for Arc<Scores> {
+        fn schema(&self) -> Vec<String> {
+            vec!["id".to_string(), "score".to_string()]
+        }
+        fn scan(&self) -> miette::Result<VirtualRows<'_>> {
+            let rows = self.rows.lock().unwrap().clone();
+            Ok(Box::new(rows.into_iter().map(Ok)))
+        }
+        fn prefix_scan(&self, prefix: &[DataValue]) -> miette::Result<VirtualRows<'_>> {
+            self.prefix_scans.fetch_add(1, Ordering::SeqCst);
+            let rows = self
+                .rows
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|row| row.starts_with(prefix))
+                .cloned()
+                .collect_vec();
+            Ok(Box::new(rows.into_iter().map(Ok)))
+        }
+    }
+
+    let db = DbInstance::default();
+    db.run_default(":create users {id: Int => name: String}")
+        .unwrap();
+    db.run_default("?[id, name] <- [[1, 'a'], [2, 'b'], [3, 'c']] :put users {id => name}")
+        .unwrap();
+    let scores = Arc::new(Scores::default());
+    *scores.rows.lock().unwrap() = vec![
+        vec![DataValue::from(1), DataValue::from(10)],
+        vec![DataValue::from(2), DataValue::from(20)],
+    ];
+    db.register_virtual_relation("scores".to_string(), scores.clone())
+        .unwrap();
+
+    let res = db
+        .run_default("?[id, score] := *scores[id, score], score > 15")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, 20]]));
+    assert_eq!(scores.prefix_scans.load(Ordering::SeqCst), 0);
+
+    // joined on the leading column by prefix scans
+    let res = db
+        .run_default("?[name, score] := *users{id, name}, *scores{id, score}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a", 10], ["b", 20]]));
+    assert_eq!(scores.prefix_scans.load(Ordering::SeqCst), 3);
+    let res = db
+        .run_default("?[name] := *users{id, name}, not *scores{id}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["c"]]));
+    let res = db
+        .run_default("?[name] := *users{id, name}, s = id * 10, not *scores{score: s}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["c"]]));
+
+    // the rows are read anew by each query
+    scores
+        .rows
+        .lock()
+        .unwrap()
+        .push(vec![DataValue::from(3), DataValue::from(30)]);
+    let res = db.run_default("?[count(id)] := *scores{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3]]));
+
+    assert!(db.run_default("?[id] := *scores[id]").is_err());
+    assert!(db.run_default("?[id] := *scores{id @ 'NOW'}").is_err());
+    assert!(db
+        .register_virtual_relation("scores".to_string(), scores.clone())
+        .is_err());
+    assert!(db
+        .register_virtual_relation("users".to_string(), scores.clone())
+        .is_err());
+    assert!(db.run_default(":create scores {id: Int}").is_err());
+
+    assert!(db.unregister_virtual_relation("scores"));
+    assert!(db.run_default("?[id] := *scores{id}").is_err());
+}
+
+#[test]
+fn streaming_fixed_rule() {
+    use crate::StreamingFixedRule;
+
+    let db = DbInstance::default();
+    db.run_default(":create nums {n: Int}").unwrap();
+    db.run_default("?[n] := n in int_range(10) :put nums {n}")
+        .unwrap();
+    // emits the size and the sum of each batch
+    let rule = StreamingFixedRule::new(2, 4, |inputs, _options, out| {
+        for input in inputs {
+            assert_eq!(input.headers(), ["n"]);
+            for batch in input {
+                let batch = batch?;
+                let sum: i64 = batch.iter().map(|row| row[0].get_int().unwrap()).sum();
+                out.emit([vec![
+                    DataValue::from(batch.len() as i64),
+                    DataValue::from(sum),
+                ]])?;
+            }
+        }
+        Ok(())
+    });
+    db.register_fixed_rule("BatchSums".to_string(), rule)
+        .unwrap();
+    let res = db
+        .run_default("?[size, sum] <~ BatchSums(*nums[n])")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, 17], [4, 6], [4, 22]]));
+
+    let rule = StreamingFixedRule::new(2, 4, |_inputs, _options, out| {
+        out.emit([vec![DataValue::from(1)]])
+    });
+    db.register_fixed_rule("BadArity".to_string(), rule)
+        .unwrap();
+    assert!(db.run_default("?[a, b] <~ BadArity(*nums[n])").is_err());
+}
+
+#[test]
+fn query_plan() {
+    let db = DbInstance::default();
+    db.run_default(":create posts {id: Int => author: String, title: String}")
+        .unwrap();
+    db.run_default("::index create posts:by_author {author}")
+        .unwrap();
+
+    let plan = db
+        .explain("?[t] := *posts{title: t}", Default::default())
+        .unwrap();
+    assert_eq!(plan.strata.len(), 1);
+    assert_eq!(plan.full_scans(), vec!["posts"]);
+
+    let plan = db
+        .explain("?[t] := *posts{id, title: t}, id > 10", Default::default())
+        .unwrap();
+    assert!(plan.full_scans().is_empty());
+
+    let plan = db
+        .explain("?[t] := *posts{author: 'a', title: t}", Default::default())
+        .unwrap();
+    assert!(plan.full_scans().is_empty());
+    assert!(plan.nodes().iter().any(|n| matches!(
+        &n.op,
+        PlanOp::LoadStored { relation, .. } if relation == "posts:by_author"
+    )));
+
+    let plan = db
+        .explain(
+            "a[x] <- [[1], [2]] ?[x, t] := a[x], *posts{id: x, title: t}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(plan.strata.len(), 2);
+    assert!(plan.full_scans().is_empty());
+    assert!(plan.nodes().iter().any(|n| matches!(
+        &n.op,
+        PlanOp::Join { join_type, .. } if join_type == "stored_prefix_join"
+    )));
+
+    assert!(db.explain("::relations", Default::default()).is_err());
+}
+
+#[test]
+fn lsp_support() {
+    let db = DbInstance::default();
+    db.run_default(":create posts {id: Int => author: String, title: String}")
+        .unwrap();
+
+    let script = "?[t, count(a)] := *posts{author: a, title: t}, a != 'x' # all\n:limit $n";
+    let tokens = tokenize(script)
+        .into_iter()
+        .map(|t| (t.kind, &script[t.span.0..t.span.0 + t.span.1]))
+        .collect_vec();
+    assert_eq!(tokens[0], (TokenKind::Rule, "?"));
+    assert!(tokens.contains(&(TokenKind::Function, "count")));
+    assert!(tokens.contains(&(TokenKind::Relation, "*posts")));
+    assert!(tokens.contains(&(TokenKind::String, "'x'")));
+    assert!(tokens.contains(&(TokenKind::Comment, "# all")));
+    assert!(tokens.contains(&(TokenKind::Keyword, ":limit")));
+    assert!(tokens.contains(&(TokenKind::Parameter, "$n")));
+    assert_eq!(tokenize("::relations")[0].span, SourceSpan(0, 11));
+    assert!(tokenize("?[x] := x = 'unterminated").last().unwrap().kind == TokenKind::String);
+
+    let params = BTreeMap::from([("n".to_string(), DataValue::from(1))]);
+    assert!(db.diagnostics(script, &params).is_empty());
+    let diagnostics = db.diagnostics("?[x] := x = 1 +", &params);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code.as_deref(), Some("parser::pest"));
+    assert_eq!(diagnostics[0].labels[0].span.0, 15);
+    let diagnostics = db.diagnostics("?[x] := x = abs(1, 2)", &params);
+    assert_eq!(diagnostics[0].labels[0].span, SourceSpan(12, 9));
+    assert_eq!(
+        diagnostics[0].help.as_deref(),
+        Some("Need exactly 1 argument(s)")
+    );
+
+    let labels = |script: &str| {
+        db.completions(script, script.len())
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.kind, c.label))
+            .collect_vec()
+    };
+    assert_eq!(
+        labels("?[x] := *po"),
+        vec![(CompletionKind::Relation, "posts".to_string())]
+    );
+    assert_eq!(
+        labels("?[x] := *posts{id: x, ti"),
+        vec![(CompletionKind::Column, "title".to_string())]
+    );
+    assert_eq!(labels("?[x] := *posts{").len(), 3);
+    assert!(labels("?[x] := x = str_in")
+        .contains(&(CompletionKind::Function, "str_includes".to_string())));
+    assert!(labels("?[x, cou").contains(&(CompletionKind::Aggregation, "count".to_string())));
+    assert!(labels("?[x] <~ Page").contains(&(CompletionKind::FixedRule, "PageRank".to_string())));
+    assert_eq!(
+        labels("?[x] := x = 1\n:li"),
+        vec![(CompletionKind::QueryOption, "limit".to_string())]
+    );
+}
+
+#[test]
+fn format_scripts() {
+    assert_eq!(
+        format_script("?[a,b]:=*rel{a,b:x},x>-1 ; :limit 10 :sort -a").unwrap(),
+        "?[a, b] := *rel{a, b: x}, x > -1\n:sort -a\n:limit 10\n"
+    );
+    assert_eq!(
+        format_script(
+            "# count\n?[a, count(b)] := f[a, b] # why\n, not *blocked{id: a}, a = -b + 2 * b, \
+            long_function_name(a, b, 'padding to go over the maximum width') :order +a"
+        )
+        .unwrap(),
+        "# count\n\
+        ?[a, count(b)] :=\n    \
+            f[a, b],\n    \
+            # why\n    \
+            not *blocked{id: a},\n    \
+            a = -b + 2 * b,\n    \
+            long_function_name(a, b, 'padding to go over the maximum width')\n\
+        :order +a\n"
+    );
+    assert_eq!(
+        format_script("{?[a] <- [[1]] :replace a {a}} %if _a {::relations} %end").unwrap(),
+        "{\n    ?[a] <- [[1]]\n    :replace a {a}\n}\n%if _a %then\n    {::relations}\n%end\n"
+    );
+    assert_eq!(
+        format_script("::index create a:y {y}").unwrap(),
+        "::index create a:y {y}\n"
+    );
+    for script in [
+        "{?[a] <- [[1]]} %if {?[x] := *a{a: x}} %then {::relations} %else %return _x %end %loop %break %end",
+        "::explain { ?[a] := *rel{a} }",
+        "?[x] <~ PageRank(*rel[a, b], theta: 0.5)",
+        "?[x] := x = ___\"raw \"string\"___, y = $p",
+    ] {
+        let formatted = format_script(script).unwrap();
+        assert_eq!(format_script(&formatted).unwrap(), formatted);
+    }
+    assert!(format_script("?[x] := x +").is_err());
+
+    let db = DbInstance::default();
+    let res = db.run_default("::format { ?[x]:=x=1 }").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("?[x] := x = 1\n"));
+}
+
+#[test]
+fn script_signatures() {
+    let db = DbInstance::default();
+    db.run_default(":create users {id: Int => name: String, age: Int?}")
+        .unwrap();
+    let typings = |script: &str| {
+        db.script_signature(script)
+            .unwrap()
+            .params
+            .into_iter()
+            .map(|p| (p.name, p.typing))
+            .collect_vec()
+    };
+    let pairs = |items: &[(&str, &str)]| {
+        items
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect_vec()
+    };
+    assert_eq!(
+        typings("?[id, name, age] <- [[$id, $name, $age]] :put users {id => name, age}"),
+        pairs(&[("id", "Int"), ("name", "String"), ("age", "Int?")])
+    );
+    assert_eq!(
+        typings("?[a] <- [[$a]] :create other {a: Float}"),
+        pairs(&[("a", "Float")])
+    );
+    assert_eq!(
+        typings("?[n] := *users{id: $uid, name: n}, n != $other :limit $lim"),
+        pairs(&[("uid", "Int"), ("other", "Any?"), ("lim", "Int")])
+    );
+    assert_eq!(
+        typings("?[n] := x = $p + 1, *users[x, $p, n]"),
+        pairs(&[("p", "String")])
+    );
+    assert_eq!(
+        typings("?[n] := *users{id: $p, name: n}, *users{name: $p}"),
+        pairs(&[("p", "Any")])
+    );
+    assert_eq!(typings("?[a, b] <- $rows"), pairs(&[("rows", "[[Any?]]")]));
+
+    let signature = db
+        .script_signature("?[n] := *users{id: $uid, name: n} :limit $lim")
+        .unwrap();
+    assert_eq!(signature.params[0].spans, vec![SourceSpan(19, 4)]);
+    let params = |uid: DataValue| {
+        BTreeMap::from([
+            ("uid".to_string(), uid),
+            ("lim".to_string(), DataValue::from(10)),
+        ])
+    };
+    assert!(signature.validate(&params(DataValue::from(1))).is_ok());
+    let err = signature
+        .validate(&params(DataValue::from("one")))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::param_type_mismatch");
+    let err = signature
+        .validate(&BTreeMap::from([("uid".to_string(), DataValue::from(1))]))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::param_not_given");
+    assert!(db.script_signature("?[x] := x +").is_err());
+}
+
+#[test]
+fn multiple_diagnostics() {
+    let db = DbInstance::default();
+    let params = BTreeMap::new();
+    let script = "{?[x] := x = 1 +}\n{?[y] <- [[1]]}\n{?[z] := z = abs(1, 2)}";
+    let diagnostics = db.diagnostics(script, &params);
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].code.as_deref(), Some("parser::pest"));
+    assert_eq!(diagnostics[0].labels[0].span.0, 16);
+    assert_eq!(diagnostics[1].labels[0].span, SourceSpan(47, 9));
+
+    let err = db.run_default(script).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::multiple_errors");
+    assert_eq!(err.related().unwrap().count(), 2);
+    let json = crate::format_error_as_json(err, Some(script));
+    assert_eq!(json["related"].as_array().unwrap().len(), 2);
+
+    let diagnostics = db.diagnostics("{?[x] := x = 1 +}\n{?[y] <- [[1]]}", &params);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code.as_deref(), Some("parser::pest"));
+    assert!(db
+        .diagnostics("{?[y] <- [[1]]} %if _y %then {?[z] <- [[2]]} %end", &params)
+        .is_empty());
+}
+
+#[test]
+fn modules() {
+    let db = DbInstance::default();
+    db.run_default(":create edge {a: Int, b: Int}").unwrap();
+    db.run_default("?[a, b] <- [[1, 2], [2, 3], [3, 4]] :put edge {a, b}")
+        .unwrap();
+    db.run_default(
+        "::module save graph_utils {
+            reachable[a, b] := *edge[a, b]
+            reachable[a, b] := reachable[a, c], *edge[c, b]
+            degree[a, count(b)] := *edge[a, b]
+        }",
+    )
+    .unwrap();
+    let res = db
+        .run_default("use graph_utils; ?[b] := reachable[1, b]")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [3], [4]]));
+    let res = db
+        .run_default("{use graph_utils ?[a, n] := degree[a, n] :limit 1}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 1]]));
+
+    let res = db.run_default("::module list").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("graph_utils"));
+
+    let err = db
+        .run_default("use graph_utils; reachable[a] := a = 1; ?[a] := reachable[a]")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::module_rule_conflict"
+    );
+    let err = db
+        .run_default("::module save bad { ?[a] := *edge[a, _] }")
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::entry_in_module");
+
+    db.run_default("::module remove graph_utils").unwrap();
+    let err = db
+        .run_default("use graph_utils; ?[b] := reachable[1, b]")
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::module_not_found");
+    assert!(db.run_default("::module remove graph_utils").is_err());
+
+    assert_eq!(
+        format_script("use m ?[a]:=r[a]").unwrap(),
+        "use m\n?[a] := r[a]\n"
+    );
+}
+
+#[test]
+fn procedures() {
+    let db = DbInstance::default();
+    db.run_default(":create logs {id: Int => age: Int}")
+        .unwrap();
+    db.run_default("?[id, age] <- [[1, 10], [2, 40], [3, 50]] :put logs {id => age}")
+        .unwrap();
+    db.run_default(
+        "::procedure save cleanup($days) {
+            ?[id] := *logs{id, age}, age > $days
+            :rm logs {id}
+        }",
+    )
+    .unwrap();
+    db.run_default("::procedure save count_logs() { ?[count(id)] := *logs{id} }")
+        .unwrap();
+
+    let res = db.run_default("::procedure list").unwrap();
+    assert_eq!(
+        res.into_json()["rows"][0],
+        json!([
+            "cleanup",
+            ["days"],
+            "?[id] := *logs{id, age}, age > $days\n            :rm logs {id}"
+        ])
+    );
+
+    let err = db
+        .run_script(
+            "::call cleanup(30)",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("read-only"));
+    db.run_default("::call cleanup(20 + 10)").unwrap();
+    let res = db
+        .run_script(
+            "::call count_logs()",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+
+    let err = db.run_default("::call cleanup()").unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::procedure_arity_mismatch"
+    );
+    let err = db
+        .run_default("::procedure save bad($a) { ?[x] := x = $b }")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::undeclared_procedure_param"
+    );
+    let err = db.run_default("{::call count_logs()}").unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::nested_procedure_call"
+    );
+
+    db.set_mutation_guard(|name, kind| {
+        if kind == MutationKind::Call && name == "cleanup" {
+            miette::bail!("procedure {name} is not allowed")
+        }
+        Ok(())
+    });
+    assert!(db.run_default("::call cleanup(0)").is_err());
+    db.run_default("::call count_logs()").unwrap();
+    db.clear_mutation_guard();
+
+    db.run_default("::procedure remove cleanup").unwrap();
+    let err = db.run_default("::call cleanup(30)").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::procedure_not_found");
+
+    assert_eq!(
+        format_script("::procedure save p($a) { {?[x] := x = $a} %return _ }").unwrap(),
+        "::procedure save p($a) {\n    {\n        ?[x] := x = $a\n    }\n    %return _\n}\n"
+    );
+}
+
+#[test]
+fn query_hints() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x: Int => y: Int}").unwrap();
+    db.run_default(":create b {y: Int => z: Int}").unwrap();
+    db.run_default(":create c {z: Int => w: Int}").unwrap();
+    db.run_default("?[x, y] <- [[1, 10], [2, 20], [3, 10]] :put a {x => y}")
+        .unwrap();
+    db.run_default("?[y, z] <- [[10, 100], [20, 200]] :put b {y => z}")
+        .unwrap();
+    db.run_default("?[z, w] <- [[100, 1000], [200, 2000], [300, 3000]] :put c {z => w}")
+        .unwrap();
+    db.run_default("::index create a:by_y {y}").unwrap();
+
+    let query = "?[x, w] := *c{z, w}, *a{x, y}, *b{y, z}";
+    let expected = json!([[1, 1000], [2, 2000], [3, 1000]]);
+    let loaded = |opts: &str| {
+        let res = db.run_default(&format!("{query} {opts}")).unwrap();
+        assert_eq!(res.into_json()["rows"], expected, "{opts}");
+        db.run_default(&format!("::explain {{ {query} {opts} }}"))
+            .unwrap()
+            .rows
+            .iter()
+            .filter_map(|row| row[5].get_str().map(|s| s.to_string()))
+            .collect_vec()
+    };
+    assert_eq!(loaded(""), vec![":c", ":a", ":b"]);
+    assert_eq!(loaded(":hints join_order(a, b, c)"), vec![":a", ":b", ":c"]);
+    assert_eq!(
+        loaded(":hints use_index a:by_y"),
+        vec![":c", ":a:by_y", ":b"]
+    );
+    assert_eq!(
+        loaded(":hints join_order(b, c), use_index a:by_y"),
+        vec![":b", ":a:by_y", ":c"]
+    );
+
+    let reach = "reach[a, b] := *b{y: a, z: b}
+        reach[a, b] := reach[a, c], *b{y: c, z: b}
+        ?[b] := reach[10, b]";
+    let rules = |opts: &str| {
+        db.run_default(&format!("::explain {{ {reach} {opts} }}"))
+            .unwrap()
+            .rows
+            .iter()
+            .map(|row| row[2].get_str().unwrap().to_string())
+            .unique()
+            .collect_vec()
+    };
+    assert!(rules("").contains(&"reach|Mbf".to_string()));
+    assert_eq!(rules(":hints no_magic_set reach"), vec!["?", "reach"]);
+    let res = db
+        .run_default(&format!("{reach} :hints no_magic_set reach"))
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[100]]));
+
+    for (opts, code) in [
+        (":hints no_magic_set nowhere", "eval::hint_target_not_found"),
+        (":hints join_order(a, d)", "eval::hint_target_not_found"),
+        (":hints use_index d:by_y", "eval::hint_target_not_found"),
+        (":hints use_index a:by_z", "eval::hinted_index_not_found"),
+    ] {
+        let err = db.run_default(&format!("{query} {opts}")).unwrap_err();
+        assert_eq!(err.code().unwrap().to_string(), code, "{opts}");
+    }
+}
+
+#[test]
+fn unstratifiable_cycles() {
+    let db = DbInstance::default();
+    let labels = |err: &miette::Report| {
+        err.labels()
+            .unwrap()
+            .map(|l| (l.label().unwrap().to_string(), l.offset()))
+            .collect_vec()
+    };
+    let script = "r[x] <- [[1], [2]]
+        p[x] := r[x], not q[x]
+        q[x] := p[x]
+        ?[x] := p[x]";
+    let err = db.run_default(script).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::unstratifiable");
+    assert!(err
+        .to_string()
+        .contains("'p' depends on itself through negation"));
+    assert!(err
+        .help()
+        .unwrap()
+        .to_string()
+        .starts_with("The dependency cycle is 'p' -> 'q' -> 'p'."));
+    assert_eq!(
+        labels(&err),
+        vec![
+            (
+                "'p' negates 'q' here".to_string(),
+                script.find("not q").unwrap() + 4
+            ),
+            (
+                "'q' depends on 'p' here".to_string(),
+                script.find("p[x]\n").unwrap()
+            ),
+        ]
+    );
+
+    let err = db
+        .run_default(
+            "r[x, y] <- [[1, 2]]
+            a[x, count(y)] := b[x, y]
+            b[x, y] := r[x, y]
+            b[x, y] := c[x, y]
+            c[x, y] := a[x, y]
+            ?[x] := a[x, _]",
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("through aggregation"));
+    assert!(err
+        .help()
+        .unwrap()
+        .to_string()
+        .starts_with("The dependency cycle is 'a' -> 'b' -> 'c' -> 'a'."));
+    assert_eq!(labels(&err)[0].0, "'a' aggregates over 'b' here");
+    assert_eq!(labels(&err).len(), 3);
+
+    // recursion through meet aggregations is allowed
+    db.run_default(
+        "r[x, y] <- [[1, 2], [2, 3]]
+        m[x, min(y)] := r[x, y]
+        m[x, min(y)] := m[z, y], r[x, z]
+        ?[x, y] := m[x, y]",
+    )
+    .unwrap();
+}
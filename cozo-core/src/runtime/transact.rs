@@ -6,18 +6,26 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicU32, AtomicU64};
 use std::sync::Arc;
 
+use crossbeam::sync::ShardedLock;
 use miette::{bail, Result};
 use crate::data::program::ReturnMutation;
 
 use crate::data::tuple::TupleT;
 use crate::data::value::DataValue;
 use crate::fts::TokenizerCache;
-use crate::{CallbackOp, NamedRows};
+use crate::query::stored::MutationCounts;
+use crate::{CallbackOp, FixedRule, NamedRows};
 use crate::runtime::callback::CallbackCollector;
+use crate::runtime::idgen::{IdGenChange, IdGenerators};
+use crate::runtime::metrics::Metrics;
 use crate::runtime::relation::RelationId;
+use crate::runtime::sandbox::Sandbox;
+use crate::runtime::spans::span;
+use crate::runtime::virtual_relation::VirtualRelationRegistry;
 use crate::storage::temp::TempTx;
 use crate::storage::StoreTx;
 
@@ -27,6 +35,19 @@ pub struct SessionTx<'a> {
     pub(crate) relation_store_id: Arc<AtomicU64>,
     pub(crate) temp_store_id: AtomicU32,
     pub(crate) tokenizers: Arc<TokenizerCache>,
+    pub(crate) id_generators: Arc<IdGenerators>,
+    /// Changes to the ID generators, applied to `id_generators` when the transaction commits.
+    pub(crate) id_gen_changes: Vec<IdGenChange>,
+    pub(crate) metrics: Arc<Metrics>,
+    /// The memory budget when the transaction started.
+    /// Hash joins whose build side exceeds it spill to disk.
+    pub(crate) memory_budget: Option<usize>,
+    /// The sandbox when the transaction started, `None` if IO is unrestricted.
+    pub(crate) sandbox: Option<Arc<Sandbox>>,
+    /// The virtual relations registered on the database.
+    pub(crate) virtual_relations: VirtualRelationRegistry,
+    /// The fixed rules registered on the database, for parsing the rules of modules.
+    pub(crate) fixed_rules: Arc<ShardedLock<BTreeMap<String, Arc<Box<dyn FixedRule>>>>>,
 }
 
 pub const CURRENT_STORAGE_VERSION: [u8; 1] = [0x00];
@@ -40,18 +61,44 @@ const STATUS_STR: &str = "status";
 const OK_STR: &str = "OK";
 
 impl<'a> SessionTx<'a> {
-    pub(crate) fn get_returning_rows(&self, callback_collector: &mut CallbackCollector, rel: &str, returning: &ReturnMutation) -> Result<NamedRows> {
+    /// The rows returned by a mutation. `guarded` is the number of rows that passed
+    /// the guard of the mutation, if it has one.
+    pub(crate) fn get_returning_rows(&self, callback_collector: &mut CallbackCollector, rel: &str, returning: &ReturnMutation, counts: MutationCounts, guarded: Option<usize>) -> Result<NamedRows> {
         let returned_rows = {
             match returning {
-                ReturnMutation::NotReturning => {
-                    NamedRows::new(
+                ReturnMutation::NotReturning => match guarded {
+                    None => NamedRows::new(
                         vec![STATUS_STR.to_string()],
                         vec![vec![DataValue::from(OK_STR)]],
+                    ),
+                    Some(n) => NamedRows::new(
+                        vec![STATUS_STR.to_string(), "mutated".to_string()],
+                        vec![vec![DataValue::from(OK_STR), DataValue::from(n as i64)]],
+                    ),
+                },
+                ReturnMutation::ReturningCounts => {
+                    NamedRows::new(
+                        vec![
+                            STATUS_STR.to_string(),
+                            "inserted".to_string(),
+                            "updated".to_string(),
+                            "deleted".to_string(),
+                        ],
+                        vec![vec![
+                            DataValue::from(OK_STR),
+                            DataValue::from(counts.inserted as i64),
+                            DataValue::from(counts.updated as i64),
+                            DataValue::from(counts.deleted as i64),
+                        ]],
                     )
                 }
-                ReturnMutation::Returning => {
+                ReturnMutation::Returning | ReturnMutation::ReturningKeys => {
                     let meta = self.get_relation(rel, false)?;
-                    let target_len = meta.metadata.keys.len() + meta.metadata.non_keys.len();
+                    let target_len = if *returning == ReturnMutation::ReturningKeys {
+                        meta.metadata.keys.len()
+                    } else {
+                        meta.metadata.keys.len() + meta.metadata.non_keys.len()
+                    };
                     let mut returned_rows = Vec::new();
                     if let Some(collected) = callback_collector.get(&meta.name) {
                         for (kind, insertions, deletions) in collected {
@@ -62,7 +109,7 @@ impl<'a> SessionTx<'a> {
                             for row in &insertions.rows {
                                 let mut v = Vec::with_capacity(target_len + 1);
                                 v.push(DataValue::from(pos_key));
-                                v.extend_from_slice(row);
+                                v.extend_from_slice(&row[..target_len.min(row.len())]);
                                 while v.len() <= target_len {
                                     v.push(DataValue::Null);
                                 }
@@ -71,7 +118,7 @@ impl<'a> SessionTx<'a> {
                             for row in &deletions.rows {
                                 let mut v = Vec::with_capacity(target_len + 1);
                                 v.push(DataValue::from(neg_key));
-                                v.extend_from_slice(row);
+                                v.extend_from_slice(&row[..target_len.min(row.len())]);
                                 while v.len() <= target_len {
                                     v.push(DataValue::Null);
                                 }
@@ -83,6 +130,7 @@ impl<'a> SessionTx<'a> {
                     header.extend(meta.metadata.keys
                         .iter()
                         .chain(meta.metadata.non_keys.iter())
+                        .take(target_len)
                         .map(|s| s.name.to_string()));
                     NamedRows::new(
                         header,
@@ -130,7 +178,11 @@ impl<'a> SessionTx<'a> {
     }
 
     pub fn commit_tx(&mut self) -> Result<()> {
+        let _span = span!("cozo.commit").entered();
         self.store_tx.commit()?;
+        for change in self.id_gen_changes.drain(..) {
+            self.id_generators.apply(change);
+        }
         Ok(())
     }
 }
@@ -0,0 +1,205 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Transaction time: every change to a tracked stored relation is also recorded
+//! in its history relation `<name>:tx_history`, keyed by the keys of the relation
+//! followed by the time of the change as a validity, so that scanning the history
+//! at a transaction time gives the rows the relation had then.
+//! Tracking is switched on with `{track_tx_time: true}` after the schema in `:create`,
+//! or for an existing relation with `::tx_time track`.
+//! The query option `:as_of_tx` makes queries read tracked relations that way.
+
+use std::cmp::Reverse;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Validity, ValidityTs};
+use crate::runtime::relation::{
+    AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
+};
+use crate::runtime::transact::SessionTx;
+
+/// Name of the history relation of a tracked relation, after the `:`.
+pub(crate) const TX_HISTORY_SUFFIX: &str = "tx_history";
+/// Name of the transaction time column of history relations.
+pub(crate) const TX_TIME_COLUMN: &str = "tx_time";
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation {0} does not track transaction time")]
+#[diagnostic(code(tx::tx_time_not_tracked))]
+struct TxTimeNotTracked(String);
+
+impl<'a> SessionTx<'a> {
+    /// Start recording the changes to a stored relation, with its current rows
+    /// as the state at `now`.
+    pub(crate) fn track_tx_time(&mut self, rel: &Symbol, now: ValidityTs) -> Result<()> {
+        let mut handle = self.get_relation(rel, true)?;
+        ensure!(
+            !handle.name.contains(':') && !handle.is_temp,
+            "Transaction time can only be tracked for stored relations, {} is not one",
+            handle.name
+        );
+        ensure!(
+            handle.tx_history.is_none(),
+            "Relation {} already tracks transaction time",
+            handle.name
+        );
+        if handle.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "tracking transaction time".to_string(),
+                handle.access_level
+            ))
+        }
+        ensure!(
+            handle
+                .metadata
+                .keys
+                .iter()
+                .chain(handle.metadata.non_keys.iter())
+                .all(|col| col.name != TX_TIME_COLUMN),
+            "Relation {} has a column named {}, which is reserved for transaction time",
+            handle.name,
+            TX_TIME_COLUMN
+        );
+
+        let mut keys = handle.metadata.keys.clone();
+        keys.push(ColumnDef {
+            name: SmartString::from(TX_TIME_COLUMN),
+            typing: NullableColType {
+                coltype: ColType::Validity,
+                nullable: false,
+            },
+            default_gen: None,
+        });
+        // retractions have no values
+        let non_keys = handle
+            .metadata
+            .non_keys
+            .iter()
+            .map(|col| ColumnDef {
+                name: col.name.clone(),
+                typing: NullableColType {
+                    coltype: col.typing.coltype.clone(),
+                    nullable: true,
+                },
+                default_gen: None,
+            })
+            .collect_vec();
+        let history = self.create_relation(InputRelationHandle {
+            name: Symbol::new(
+                format!("{}:{}", handle.name, TX_HISTORY_SUFFIX),
+                Default::default(),
+            ),
+            metadata: StoredRelationMetadata { keys, non_keys },
+            key_bindings: vec![],
+            dep_bindings: vec![],
+            span: Default::default(),
+        })?;
+
+        let existing: Vec<Tuple> = handle.scan_all(self).try_collect()?;
+        for tuple in existing {
+            self.record_tx_time(&history, &tuple, true, now)?;
+        }
+
+        handle.tx_history = Some(history.name.clone());
+        self.save_relation_meta(&handle)
+    }
+    /// Stop recording the changes to a stored relation, deleting its history.
+    pub(crate) fn untrack_tx_time(&mut self, rel: &Symbol) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut handle = self.get_relation(rel, true)?;
+        let history = match handle.tx_history.take() {
+            Some(history) => history,
+            None => bail!(TxTimeNotTracked(handle.name.to_string())),
+        };
+        if handle.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "untracking transaction time".to_string(),
+                handle.access_level
+            ))
+        }
+        let to_clean = self.destroy_relation(&history)?;
+        self.save_relation_meta(&handle)?;
+        Ok(to_clean)
+    }
+    /// The history relation of a relation, if it tracks transaction time.
+    pub(crate) fn tx_history(&self, handle: &RelationHandle) -> Result<Option<RelationHandle>> {
+        match &handle.tx_history {
+            None => Ok(None),
+            Some(name) => Ok(Some(self.get_relation(name, false)?)),
+        }
+    }
+    /// Record in the history relation of a tracked relation that the row `tuple`
+    /// was put, or that the row with the keys in `tuple` was removed, at `tx_time`.
+    pub(crate) fn record_tx_time(
+        &mut self,
+        history: &RelationHandle,
+        tuple: &[DataValue],
+        is_assert: bool,
+        tx_time: ValidityTs,
+    ) -> Result<()> {
+        let n_keys = history.metadata.keys.len() - 1;
+        let mut entry = tuple[..n_keys].to_vec();
+        entry.push(DataValue::Validity(Validity {
+            timestamp: tx_time,
+            is_assert: Reverse(is_assert),
+        }));
+        if is_assert {
+            entry.extend_from_slice(&tuple[n_keys..]);
+        } else {
+            entry.extend((0..history.metadata.non_keys.len()).map(|_| DataValue::Null));
+        }
+        let key = history.encode_key_for_store(&entry, Default::default())?;
+        let val = history.encode_val_for_store(&entry, Default::default())?;
+        self.store_tx.put(&key, &val)?;
+        Ok(())
+    }
+}
+
+/// For the history of a relation whose last key is a validity, scanned at a transaction time:
+/// keep for each key only the version that is valid at `valid_at`, as a time travel scan
+/// of the relation would. The versions of a key come one after the other, newest first.
+pub(crate) fn valid_versions<'a>(
+    it: impl Iterator<Item = Result<Tuple>> + 'a,
+    n_keys: usize,
+    valid_at: ValidityTs,
+) -> impl Iterator<Item = Result<Tuple>> + 'a {
+    // the keys of the relation, without the validity and the transaction time
+    let n_prefix = n_keys - 2;
+    let mut done_with: Option<Tuple> = None;
+    it.filter_map(move |tuple| {
+        let tuple = match tuple {
+            Ok(t) => t,
+            Err(e) => return Some(Err(e)),
+        };
+        if matches!(&done_with, Some(prefix) if prefix[..] == tuple[..n_prefix]) {
+            return None;
+        }
+        let vld = match &tuple[n_prefix] {
+            DataValue::Validity(vld) => *vld,
+            _ => return None,
+        };
+        if vld.timestamp < valid_at {
+            // later than `valid_at`
+            return None;
+        }
+        done_with = Some(tuple[..n_prefix].to_vec());
+        if vld.is_assert.0 {
+            Some(Ok(tuple))
+        } else {
+            None
+        }
+    })
+}
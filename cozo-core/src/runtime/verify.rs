@@ -0,0 +1,177 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Integrity verification of stored relations with `::verify`: checksums of the stored
+//! tuples, and a comparison of the contents of the indices with the relation.
+
+use itertools::Itertools;
+use miette::{ensure, Result};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::data::json::JsonValue;
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::{DataValue, JsonData};
+use crate::runtime::relation::{RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+/// At most this many tuples are reported for each kind of discrepancy.
+const MAX_REPORTED: usize = 10;
+
+/// Number of tuples and a checksum of them, independent of the storage engine
+/// and of the ID of the relation.
+fn checksum(tx: &SessionTx<'_>, handle: &RelationHandle) -> Result<(usize, String)> {
+    let mut hasher = Sha256::new();
+    let mut count = 0;
+    for tuple in handle.scan_all(tx) {
+        let encoded = tuple?.encode_as_key(RelationId::SYSTEM);
+        hasher.update((encoded.len() as u64).to_be_bytes());
+        hasher.update(&encoded);
+        count += 1;
+    }
+    Ok((count, format!("{:x}", hasher.finalize())))
+}
+
+/// Discrepancies between a relation and one of its regular indices.
+#[derive(Default)]
+struct IndexReport {
+    /// Rows of the relation without their index entry
+    missing: usize,
+    /// Index entries without the row they point to
+    dangling: usize,
+    examples: Vec<JsonValue>,
+}
+
+impl IndexReport {
+    fn record(&mut self, kind: &str, tuple: &Tuple) {
+        let count = if kind == "missing" {
+            &mut self.missing
+        } else {
+            &mut self.dangling
+        };
+        *count += 1;
+        if *count <= MAX_REPORTED {
+            let tuple = tuple.iter().cloned().map(JsonValue::from).collect_vec();
+            self.examples.push(json!({"kind": kind, "tuple": tuple}));
+        }
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    fn compare_index(
+        &self,
+        handle: &RelationHandle,
+        idx_handle: &RelationHandle,
+        mapping: &[usize],
+    ) -> Result<IndexReport> {
+        let mut report = IndexReport::default();
+        let index_exprs = handle.compile_index_exprs()?;
+        let mut stack = vec![];
+        for tuple in handle.scan_all(self) {
+            let tuple = tuple?;
+            let idx_tuple = handle.index_tuple(mapping, &tuple, &index_exprs, &mut stack)?;
+            let idx_key = &idx_tuple[..idx_handle.metadata.keys.len()];
+            if idx_handle.get(self, idx_key)?.as_ref() != Some(&idx_tuple) {
+                report.record("missing", &idx_tuple);
+            }
+        }
+        // every key column of the relation is part of the index
+        let key_positions = (0..handle.metadata.keys.len())
+            .map(|k| mapping.iter().position(|i| *i == k).unwrap())
+            .collect_vec();
+        for idx_tuple in idx_handle.scan_all(self) {
+            let idx_tuple = idx_tuple?;
+            let key = key_positions
+                .iter()
+                .map(|p| idx_tuple[*p].clone())
+                .collect_vec();
+            let matches = match handle.get(self, &key)? {
+                None => false,
+                Some(tuple) => {
+                    handle.index_tuple(mapping, &tuple, &index_exprs, &mut stack)? == idx_tuple
+                }
+            };
+            if !matches {
+                report.record("dangling", &idx_tuple);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Checksums of a stored relation and of its indices, with the discrepancies
+    /// between the relation and its regular indices.
+    pub(crate) fn verify_relation(&self, rel: &Symbol) -> Result<NamedRows> {
+        let handle = self.get_relation(rel, false)?;
+        ensure!(
+            !handle.is_temp,
+            "Only stored relations can be verified, {} is not one",
+            handle.name
+        );
+        let mut rows = vec![];
+        let mut push_row = |handle: &RelationHandle, kind: &str, report: Option<IndexReport>| {
+            let (count, sum) = checksum(self, handle)?;
+            let (missing, dangling, examples, ok) = match report {
+                None => (DataValue::Null, DataValue::Null, json!([]), true),
+                Some(r) => (
+                    DataValue::from(r.missing as i64),
+                    DataValue::from(r.dangling as i64),
+                    JsonValue::Array(r.examples),
+                    r.missing == 0 && r.dangling == 0,
+                ),
+            };
+            rows.push(vec![
+                DataValue::Str(handle.name.clone()),
+                DataValue::from(kind),
+                DataValue::from(count as i64),
+                DataValue::from(sum),
+                missing,
+                dangling,
+                DataValue::Json(JsonData(examples)),
+                DataValue::from(ok),
+            ]);
+            Ok::<(), miette::Report>(())
+        };
+
+        push_row(&handle, "base", None)?;
+        for (idx_handle, mapping) in handle.indices.values() {
+            let report = self.compare_index(&handle, idx_handle, mapping)?;
+            push_row(idx_handle, "normal", Some(report))?;
+        }
+        for (idx_handle, _) in handle.hnsw_indices.values() {
+            push_row(idx_handle, "hnsw", None)?;
+        }
+        for (idx_handle, _) in handle.fts_indices.values() {
+            push_row(idx_handle, "fts", None)?;
+        }
+        for (idx_handle, inv_handle, _) in handle.lsh_indices.values() {
+            push_row(idx_handle, "lsh", None)?;
+            push_row(inv_handle, "lsh", None)?;
+        }
+        for (idx_handle, _) in handle.geo_indices.values() {
+            push_row(idx_handle, "geo", None)?;
+        }
+        if let Some(history) = self.tx_history(&handle)? {
+            push_row(&history, "history", None)?;
+        }
+        Ok(NamedRows::new(
+            vec![
+                "relation".to_string(),
+                "kind".to_string(),
+                "rows".to_string(),
+                "checksum".to_string(),
+                "missing".to_string(),
+                "dangling".to_string(),
+                "examples".to_string(),
+                "ok".to_string(),
+            ],
+            rows,
+        ))
+    }
+}
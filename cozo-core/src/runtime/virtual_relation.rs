@@ -0,0 +1,60 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Virtual relations expose data kept outside of the database to queries.
+//!
+//! A virtual relation registered with [crate::Db::register_virtual_relation] is read in rule
+//! bodies as `*name[...]` or `*name{...}`, just like a stored relation. Its rows are pulled
+//! from the implementation while the query runs, instead of being copied into the database
+//! or materialized as the input of a fixed rule first. Virtual relations are read-only
+//! and do not support time travel.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crossbeam::sync::ShardedLock;
+use miette::Result;
+
+use crate::data::value::DataValue;
+use crate::runtime::transact::SessionTx;
+
+/// The rows returned by a [VirtualRelation].
+pub type VirtualRows<'a> = Box<dyn Iterator<Item = Result<Vec<DataValue>>> + 'a>;
+
+/// Data source readable in queries as a relation, see [crate::Db::register_virtual_relation].
+pub trait VirtualRelation: Send + Sync {
+    /// The names of the columns, in the order of the values of the rows.
+    fn schema(&self) -> Vec<String>;
+    /// The number of columns.
+    fn arity(&self) -> usize {
+        self.schema().len()
+    }
+    /// All the rows of the relation.
+    fn scan(&self) -> Result<VirtualRows<'_>>;
+    /// The rows whose leading columns are equal to `prefix`, used when the query joins on them.
+    /// The default implementation filters the rows of [VirtualRelation::scan]: override it
+    /// when the data source can look up rows directly.
+    fn prefix_scan(&self, prefix: &[DataValue]) -> Result<VirtualRows<'_>> {
+        let prefix = prefix.to_vec();
+        Ok(Box::new(self.scan()?.filter(move |row| match row {
+            Ok(row) => row.starts_with(&prefix),
+            Err(_) => true,
+        })))
+    }
+}
+
+/// The virtual relations registered on a database, shared with its transactions.
+pub(crate) type VirtualRelationRegistry =
+    Arc<ShardedLock<BTreeMap<String, Arc<dyn VirtualRelation>>>>;
+
+impl<'a> SessionTx<'a> {
+    /// The virtual relation registered as `name`, if any.
+    pub(crate) fn get_virtual_relation(&self, name: &str) -> Option<Arc<dyn VirtualRelation>> {
+        self.virtual_relations.read().unwrap().get(name).cloned()
+    }
+}
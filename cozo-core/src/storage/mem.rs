@@ -13,7 +13,7 @@ use std::collections::BTreeMap;
 use std::default::Default;
 use std::iter::Fuse;
 use std::mem;
-use std::ops::Bound;
+use std::ops::{Bound, Deref};
 use std::sync::Arc;
 
 use itertools::Itertools;
@@ -21,7 +21,9 @@ use miette::{bail, Result};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
-use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::runtime::relation::{
+    decode_tuple_columns_from_kv, decode_tuple_from_kv, extend_tuple_from_v,
+};
 use crate::storage::{Storage, StoreTx};
 use crate::utils::swap_option_result;
 
@@ -54,10 +56,16 @@ impl<'s> Storage<'s> for MemStorage {
             MemTx::Writer(wtr, Default::default())
         } else {
             let rdr = self.store.read().unwrap();
-            MemTx::Reader(rdr)
+            MemTx::Reader(MemReader::Locked(rdr))
         })
     }
 
+    /// Copies the data, so that writes are not blocked while the snapshot lives.
+    fn snapshot(&'s self) -> Result<Self::Tx> {
+        let copied = self.store.read().unwrap().clone();
+        Ok(MemTx::Reader(MemReader::Copied(copied)))
+    }
+
     fn range_compact(&'s self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
         Ok(())
     }
@@ -76,13 +84,32 @@ impl<'s> Storage<'s> for MemStorage {
 }
 
 pub enum MemTx<'s> {
-    Reader(ShardedLockReadGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>),
+    Reader(MemReader<'s>),
     Writer(
         ShardedLockWriteGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>,
         BTreeMap<Vec<u8>, Option<Vec<u8>>>,
     ),
 }
 
+/// The data seen by a read transaction
+pub enum MemReader<'s> {
+    /// Writes wait until the transaction ends
+    Locked(ShardedLockReadGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>),
+    /// A copy taken for a snapshot
+    Copied(BTreeMap<Vec<u8>, Vec<u8>>),
+}
+
+impl Deref for MemReader<'_> {
+    type Target = BTreeMap<Vec<u8>, Vec<u8>>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            MemReader::Locked(guard) => guard,
+            MemReader::Copied(data) => data,
+        }
+    }
+}
+
 impl<'s> StoreTx<'s> for MemTx<'s> {
     fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
         Ok(match self {
@@ -198,6 +225,28 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
         }
     }
 
+    fn range_scan_tuple_columns<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        needed: &[bool],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        let needed = needed.to_vec();
+        match self {
+            MemTx::Reader(rdr) => Box::new(
+                rdr.range(lower.to_vec()..upper.to_vec())
+                    .map(move |(k, v)| Ok(decode_tuple_columns_from_kv(k, v, &needed))),
+            ),
+            MemTx::Writer(_, _) => Box::new(
+                self.range_scan(lower, upper)
+                    .map_ok(move |(k, v)| decode_tuple_columns_from_kv(&k, &v, &needed)),
+            ),
+        }
+    }
+
     fn range_skip_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
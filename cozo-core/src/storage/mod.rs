@@ -7,13 +7,16 @@
  */
 
 use itertools::Itertools;
-use miette::Result;
+use miette::{Diagnostic, Report, Result};
+use thiserror::Error;
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
-use crate::decode_tuple_from_kv;
+use crate::{decode_tuple_columns_from_kv, decode_tuple_from_kv};
 
 pub(crate) mod mem;
+#[cfg(feature = "storage-opfs")]
+pub(crate) mod opfs;
 #[cfg(feature = "storage-rocksdb")]
 pub(crate) mod rocks;
 #[cfg(feature = "storage-sled")]
@@ -27,6 +30,22 @@ pub(crate) mod tikv;
 pub mod newrocks;
 // pub(crate) mod re;
 
+/// Raised when committing a write transaction that raced with another one writing the same keys.
+/// Nothing of the transaction has been written, and running it again may succeed.
+/// See [crate::DbInstance::transact_with_retries].
+#[derive(Debug, Error, Diagnostic)]
+#[error("Transaction conflicts with a concurrent write: {0}")]
+#[diagnostic(code(storage::tx_conflict))]
+#[diagnostic(help("Retry the transaction"))]
+pub struct TransactionConflict(pub(crate) String);
+
+impl TransactionConflict {
+    /// Whether the error is caused by a transaction conflict.
+    pub fn is_conflict(err: &Report) -> bool {
+        err.chain().any(|e| e.downcast_ref::<Self>().is_some())
+    }
+}
+
 /// Swappable storage trait for Cozo's storage engine
 pub trait Storage<'s>: Send + Sync + Clone {
     /// The associated transaction type used by this engine
@@ -38,6 +57,12 @@ pub trait Storage<'s>: Send + Sync + Clone {
     /// Create a transaction object. Write ops will only be called when `write == true`.
     fn transact(&'s self, write: bool) -> Result<Self::Tx>;
 
+    /// Create a read transaction for a snapshot, which may live long and serve many queries.
+    /// Engines whose read transactions block writes should override this.
+    fn snapshot(&'s self) -> Result<Self::Tx> {
+        self.transact(false)
+    }
+
     /// Compact the key range. Can be a no-op if the storage engine does not
     /// have the concept of compaction.
     fn range_compact(&'s self, lower: &[u8], upper: &[u8]) -> Result<()>;
@@ -120,6 +145,27 @@ pub trait StoreTx<'s>: Sync {
         Box::new(it.map_ok(|(k, v)| decode_tuple_from_kv(&k, &v, None)))
     }
 
+    /// Scan on a range like [`range_scan_tuple`](Self::range_scan_tuple), but only decode
+    /// the columns marked in `needed`. The other columns are returned as `Null`.
+    /// The default implementation calls [`range_scan`](Self::range_scan) and converts the results.
+    ///
+    /// The implementation must call
+    /// [`decode_tuple_columns_from_kv`](crate::decode_tuple_columns_from_kv) to obtain
+    /// a decoded tuple in the loop of the iterator.
+    fn range_scan_tuple_columns<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        needed: &[bool],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        let needed = needed.to_vec();
+        let it = self.range_scan(lower, upper);
+        Box::new(it.map_ok(move |(k, v)| decode_tuple_columns_from_kv(&k, &v, &needed)))
+    }
+
     /// Scan on a range with a certain validity.
     ///
     /// `lower` is inclusive whereas `upper` is exclusive.
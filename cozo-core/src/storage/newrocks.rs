@@ -5,13 +5,16 @@ use std::sync::Arc;
 use log::info;
 use miette::{miette, IntoDiagnostic, Result, WrapErr};
 
-use rocksdb::{OptimisticTransactionDB, Options, WriteBatchWithTransaction, DB};
+use rocksdb::{
+    ErrorKind, OptimisticTransactionDB, OptimisticTransactionOptions, Options, ReadOptions,
+    WriteBatchWithTransaction, WriteOptions, DB,
+};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::db::{BadDbInit, DbManifest};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
-use crate::storage::{Storage, StoreTx};
+use crate::storage::{Storage, StoreTx, TransactionConflict};
 use crate::Db;
 
 const KEY_PREFIX_LEN: usize = 9;
@@ -95,9 +98,16 @@ impl<'s> Storage<'s> for NewRocksDbStorage {
         "rocksdb"
     }
 
-    fn transact(&'s self, _write: bool) -> Result<Self::Tx> {
+    fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+        // read-only transactions see the data as of their start
+        let mut tx_options = OptimisticTransactionOptions::default();
+        tx_options.set_snapshot(!write);
         Ok(NewRocksDbTx {
-            db_tx: Some(self.db.transaction()),
+            db_tx: Some(
+                self.db
+                    .transaction_opt(&WriteOptions::default(), &tx_options),
+            ),
+            read_only: !write,
         })
     }
 
@@ -124,10 +134,24 @@ impl<'s> Storage<'s> for NewRocksDbStorage {
 
 pub struct NewRocksDbTx<'a> {
     db_tx: Option<rocksdb::Transaction<'a, OptimisticTransactionDB>>,
+    /// Only read-only transactions have a snapshot.
+    read_only: bool,
 }
 
 unsafe impl<'a> Sync for NewRocksDbTx<'a> {}
 
+/// Read options using the snapshot of the transaction if it is read-only.
+fn read_options(
+    db_tx: &rocksdb::Transaction<'_, OptimisticTransactionDB>,
+    read_only: bool,
+) -> ReadOptions {
+    let mut options = ReadOptions::default();
+    if read_only {
+        options.set_snapshot(&db_tx.snapshot());
+    }
+    options
+}
+
 impl<'s> StoreTx<'s> for NewRocksDbTx<'s> {
     fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
         let db_tx = self
@@ -136,7 +160,7 @@ impl<'s> StoreTx<'s> for NewRocksDbTx<'s> {
             .ok_or_else(|| miette!("Transaction already committed"))?;
 
         db_tx
-            .get(key)
+            .get_opt(key, &read_options(db_tx, self.read_only))
             .into_diagnostic()
             .wrap_err("failed to get value")
     }
@@ -193,10 +217,10 @@ impl<'s> StoreTx<'s> for NewRocksDbTx<'s> {
     fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
         match self.db_tx {
             Some(ref mut db_tx) => {
-                let iter = db_tx.iterator(rocksdb::IteratorMode::From(
-                    lower,
-                    rocksdb::Direction::Forward,
-                ));
+                let iter = db_tx.iterator_opt(
+                    rocksdb::IteratorMode::From(lower, rocksdb::Direction::Forward),
+                    read_options(db_tx, self.read_only),
+                );
                 for item in iter {
                     let (k, _) = item
                         .into_diagnostic()
@@ -222,7 +246,7 @@ impl<'s> StoreTx<'s> for NewRocksDbTx<'s> {
             .as_ref()
             .ok_or(miette!("Transaction already committed"))?;
         db_tx
-            .get(key)
+            .get_opt(key, &read_options(db_tx, self.read_only))
             .into_diagnostic()
             .wrap_err("Error during exists check")
             .map(|opt| opt.is_some())
@@ -230,10 +254,14 @@ impl<'s> StoreTx<'s> for NewRocksDbTx<'s> {
 
     fn commit(&mut self) -> Result<()> {
         let db_tx = self.db_tx.take().expect("Transaction already committed");
-        db_tx
-            .commit()
-            .into_diagnostic()
-            .wrap_err_with(|| "Commit failed")
+        match db_tx.commit() {
+            Ok(()) => Ok(()),
+            // optimistic transactions report conflicts with concurrent writes at commit time
+            Err(err) if matches!(err.kind(), ErrorKind::Busy | ErrorKind::TryAgain) => {
+                Err(TransactionConflict(err.into_string()).into())
+            }
+            Err(err) => Err(err).into_diagnostic().wrap_err("Commit failed"),
+        }
     }
 
     fn range_scan_tuple<'a>(
@@ -246,10 +274,10 @@ impl<'s> StoreTx<'s> for NewRocksDbTx<'s> {
     {
         match &self.db_tx {
             Some(db_tx) => Box::new(NewRocksDbIterator {
-                inner: db_tx.iterator(rocksdb::IteratorMode::From(
-                    lower,
-                    rocksdb::Direction::Forward,
-                )),
+                inner: db_tx.iterator_opt(
+                    rocksdb::IteratorMode::From(lower, rocksdb::Direction::Forward),
+                    read_options(db_tx, self.read_only),
+                ),
                 upper_bound: upper.to_vec(),
             }),
             None => Box::new(std::iter::once(Err(miette!(
@@ -266,10 +294,10 @@ impl<'s> StoreTx<'s> for NewRocksDbTx<'s> {
     ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
         match self.db_tx {
             Some(ref db_tx) => Box::new(NewRocksDbSkipIterator {
-                inner: db_tx.iterator(rocksdb::IteratorMode::From(
-                    lower,
-                    rocksdb::Direction::Forward,
-                )),
+                inner: db_tx.iterator_opt(
+                    rocksdb::IteratorMode::From(lower, rocksdb::Direction::Forward),
+                    read_options(db_tx, self.read_only),
+                ),
                 upper_bound: upper.to_vec(),
                 valid_at,
                 next_bound: lower.to_vec(),
@@ -290,10 +318,10 @@ impl<'s> StoreTx<'s> for NewRocksDbTx<'s> {
     {
         match self.db_tx {
             Some(ref db_tx) => {
-                let iter = db_tx.iterator(rocksdb::IteratorMode::From(
-                    lower,
-                    rocksdb::Direction::Forward,
-                ));
+                let iter = db_tx.iterator_opt(
+                    rocksdb::IteratorMode::From(lower, rocksdb::Direction::Forward),
+                    read_options(db_tx, self.read_only),
+                );
                 Box::new(NewRocksDbIteratorRaw {
                     inner: iter,
                     upper_bound: upper.to_vec(),
@@ -313,10 +341,10 @@ impl<'s> StoreTx<'s> for NewRocksDbTx<'s> {
             .db_tx
             .as_ref()
             .ok_or(miette!("Transaction already committed"))?;
-        let iter = db_tx.iterator(rocksdb::IteratorMode::From(
-            lower,
-            rocksdb::Direction::Forward,
-        ));
+        let iter = db_tx.iterator_opt(
+            rocksdb::IteratorMode::From(lower, rocksdb::Direction::Forward),
+            read_options(db_tx, self.read_only),
+        );
         let count = iter
             .take_while(|item| match item {
                 Ok((k, _)) => k.as_ref() < upper,
@@ -331,11 +359,18 @@ impl<'s> StoreTx<'s> for NewRocksDbTx<'s> {
         's: 'a,
     {
         match self.db_tx {
-            Some(ref db_tx) => Box::new(db_tx.iterator(rocksdb::IteratorMode::Start).map(|item| {
-                item.map(|(k, v)| (k.to_vec(), v.to_vec()))
-                    .into_diagnostic()
-                    .wrap_err_with(|| "Error during total scan")
-            })),
+            Some(ref db_tx) => Box::new(
+                db_tx
+                    .iterator_opt(
+                        rocksdb::IteratorMode::Start,
+                        read_options(db_tx, self.read_only),
+                    )
+                    .map(|item| {
+                        item.map(|(k, v)| (k.to_vec(), v.to_vec()))
+                            .into_diagnostic()
+                            .wrap_err_with(|| "Error during total scan")
+                    }),
+            ),
             None => Box::new(std::iter::once(Err(miette!(
                 "Transaction already committed"
             )))),
@@ -557,4 +592,20 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_snapshots() -> Result<()> {
+        let (_temp_dir, db) = setup_test_db()?;
+        let reader = db.db.transact(false)?;
+        let writer = db.db.transact(true)?;
+        let mut other = db.db.transact(true)?;
+        other.put(b"\xffk", b"v")?;
+        other.commit()?;
+
+        // only read-only transactions read from a snapshot
+        assert_eq!(reader.get(b"\xffk", false)?, None);
+        assert_eq!(writer.get(b"\xffk", false)?, Some(b"v".to_vec()));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,813 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use itertools::Itertools;
+use miette::{bail, miette, Result};
+use web_sys::{FileSystemReadWriteOptions, FileSystemSyncAccessHandle};
+
+use crate::data::tuple::{check_key_for_validity, Tuple};
+use crate::data::value::ValidityTs;
+use crate::runtime::relation::extend_tuple_from_v;
+use crate::storage::{Storage, StoreTx};
+
+/// Creates a database backed by a file in the browser's
+/// [Origin Private File System](https://developer.mozilla.org/en-US/docs/Web/API/File_System_API/Origin_private_file_system).
+///
+/// Sync access handles can only be created inside a dedicated worker, so the database
+/// must live in a worker as well. The handle is held exclusively for as long as the
+/// database is alive.
+///
+/// Keys are kept in memory, values are read from the file on demand.
+pub fn new_cozo_opfs(handle: FileSystemSyncAccessHandle) -> Result<crate::Db<OpfsStorage>> {
+    let storage = OpfsStorage::open(Box::new(SyncAccessHandle(handle)))?;
+    let ret = crate::Db::new(storage)?;
+
+    ret.initialize()?;
+    Ok(ret)
+}
+
+/// Random access file the log is stored in.
+pub(crate) trait LogFile: Send + Sync {
+    fn size(&self) -> Result<u64>;
+    fn read_at(&self, buf: &mut [u8], at: u64) -> Result<()>;
+    fn write_at(&self, buf: &[u8], at: u64) -> Result<()>;
+    fn truncate(&self, size: u64) -> Result<()>;
+    fn flush(&self) -> Result<()>;
+}
+
+struct SyncAccessHandle(FileSystemSyncAccessHandle);
+
+// JS objects cannot cross threads, but WASM builds of Cozo are single-threaded.
+unsafe impl Send for SyncAccessHandle {}
+unsafe impl Sync for SyncAccessHandle {}
+
+impl LogFile for SyncAccessHandle {
+    fn size(&self) -> Result<u64> {
+        let size = self
+            .0
+            .get_size()
+            .map_err(|e| miette!("OPFS error: {:?}", e))?;
+        Ok(size as u64)
+    }
+
+    fn read_at(&self, mut buf: &mut [u8], mut at: u64) -> Result<()> {
+        while !buf.is_empty() {
+            let mut opts = FileSystemReadWriteOptions::new();
+            opts.at(at as f64);
+            let n = self
+                .0
+                .read_with_u8_array_and_options(buf, &opts)
+                .map_err(|e| miette!("OPFS error: {:?}", e))? as usize;
+            if n == 0 {
+                bail!("OPFS error: unexpected end of file at {}", at);
+            }
+            buf = &mut buf[n..];
+            at += n as u64;
+        }
+        Ok(())
+    }
+
+    fn write_at(&self, mut buf: &[u8], mut at: u64) -> Result<()> {
+        while !buf.is_empty() {
+            let mut opts = FileSystemReadWriteOptions::new();
+            opts.at(at as f64);
+            let n = self
+                .0
+                .write_with_u8_array_and_options(buf, &opts)
+                .map_err(|e| miette!("OPFS error: {:?}", e))? as usize;
+            if n == 0 {
+                bail!("OPFS error: failed to write at {}", at);
+            }
+            buf = &buf[n..];
+            at += n as u64;
+        }
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        self.0
+            .truncate_with_f64(size as f64)
+            .map_err(|e| miette!("OPFS error: {:?}", e))
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.0.flush().map_err(|e| miette!("OPFS error: {:?}", e))
+    }
+}
+
+// The file starts with a header holding the offset where the log begins,
+// followed by records of the form `tag | key len (u32) | value len (u32) | key | value`.
+// Changes only take effect once a commit record is seen. Commit records carry
+// a strictly increasing sequence number so that stale bytes left behind by a
+// compaction are never replayed.
+const MAGIC: &[u8; 8] = b"COZOLOG1";
+const HEADER_LEN: u64 = 16;
+const RECORD_HEADER_LEN: u64 = 9;
+const TAG_PUT: u8 = 1;
+const TAG_DEL: u8 = 2;
+const TAG_COMMIT: u8 = 3;
+const WRITE_CHUNK: usize = 1 << 20;
+const COMPACT_MIN_GARBAGE: u64 = 4 << 20;
+
+#[derive(Clone, Copy)]
+struct ValueLoc {
+    offset: u64,
+    len: u32,
+    record_len: u64,
+}
+
+/// In-memory index over the log file
+pub struct LogStore {
+    file: Box<dyn LogFile>,
+    index: BTreeMap<Vec<u8>, ValueLoc>,
+    start: u64,
+    end: u64,
+    live_bytes: u64,
+    seq: u64,
+}
+
+/// Storage engine keeping data in an append-only log inside the
+/// Origin Private File System
+#[derive(Clone)]
+pub struct OpfsStorage {
+    store: Arc<RwLock<LogStore>>,
+}
+
+impl OpfsStorage {
+    pub(crate) fn open(file: Box<dyn LogFile>) -> Result<Self> {
+        let store = LogStore::open(file)?;
+        Ok(Self {
+            store: Arc::new(RwLock::new(store)),
+        })
+    }
+}
+
+fn encode_record(buf: &mut Vec<u8>, tag: u8, key: &[u8], val: &[u8]) {
+    buf.push(tag);
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(val.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(val);
+}
+
+impl LogStore {
+    fn open(file: Box<dyn LogFile>) -> Result<Self> {
+        let size = file.size()?;
+        let mut store = LogStore {
+            file,
+            index: Default::default(),
+            start: HEADER_LEN,
+            end: HEADER_LEN,
+            live_bytes: 0,
+            seq: 0,
+        };
+        if size == 0 {
+            store.write_header(HEADER_LEN)?;
+            store.file.flush()?;
+            return Ok(store);
+        }
+        if size < HEADER_LEN {
+            bail!("OPFS file is not a Cozo database");
+        }
+        let mut header = [0u8; HEADER_LEN as usize];
+        store.file.read_at(&mut header, 0)?;
+        if &header[..8] != MAGIC {
+            bail!("OPFS file is not a Cozo database");
+        }
+        store.start = u64::from_le_bytes(header[8..].try_into().unwrap());
+        store.end = store.start;
+        store.replay(size)?;
+        if store.end < size {
+            store.file.truncate(store.end)?;
+            store.file.flush()?;
+        }
+        Ok(store)
+    }
+
+    fn write_header(&self, start: u64) -> Result<()> {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&start.to_le_bytes());
+        self.file.write_at(&header, 0)
+    }
+
+    fn replay(&mut self, size: u64) -> Result<()> {
+        let mut pos = self.start;
+        let mut pending = vec![];
+        let mut record_header = [0u8; RECORD_HEADER_LEN as usize];
+        while pos + RECORD_HEADER_LEN <= size {
+            self.file.read_at(&mut record_header, pos)?;
+            let tag = record_header[0];
+            let key_len = u32::from_le_bytes(record_header[1..5].try_into().unwrap());
+            let val_len = u32::from_le_bytes(record_header[5..].try_into().unwrap());
+            let record_len = RECORD_HEADER_LEN + key_len as u64 + val_len as u64;
+            if pos + record_len > size {
+                break;
+            }
+            match tag {
+                TAG_PUT | TAG_DEL => {
+                    let mut key = vec![0; key_len as usize];
+                    self.file.read_at(&mut key, pos + RECORD_HEADER_LEN)?;
+                    let loc = (tag == TAG_PUT).then_some(ValueLoc {
+                        offset: pos + RECORD_HEADER_LEN + key_len as u64,
+                        len: val_len,
+                        record_len,
+                    });
+                    pending.push((key, loc));
+                }
+                TAG_COMMIT if key_len == 0 && val_len == 8 => {
+                    let mut seq = [0u8; 8];
+                    self.file.read_at(&mut seq, pos + RECORD_HEADER_LEN)?;
+                    let seq = u64::from_le_bytes(seq);
+                    if seq <= self.seq {
+                        break;
+                    }
+                    self.seq = seq;
+                    self.apply(pending.drain(..));
+                    self.end = pos + record_len;
+                }
+                _ => break,
+            }
+            pos += record_len;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, changes: impl Iterator<Item = (Vec<u8>, Option<ValueLoc>)>) {
+        for (key, loc) in changes {
+            let old = match loc {
+                Some(loc) => {
+                    self.live_bytes += loc.record_len;
+                    self.index.insert(key, loc)
+                }
+                None => self.index.remove(&key),
+            };
+            if let Some(old) = old {
+                self.live_bytes -= old.record_len;
+            }
+        }
+    }
+
+    fn load(&self, loc: ValueLoc) -> Result<Vec<u8>> {
+        let mut val = vec![0; loc.len as usize];
+        self.file.read_at(&mut val, loc.offset)?;
+        Ok(val)
+    }
+
+    /// Appends the changes followed by a commit record, and only then updates the index.
+    fn append(
+        &mut self,
+        changes: impl Iterator<Item = Result<(Vec<u8>, Option<Vec<u8>>)>>,
+    ) -> Result<()> {
+        let res = self.append_inner(changes);
+        if res.is_err() {
+            // Get rid of a partially written transaction, best effort
+            let _ = self.file.truncate(self.end);
+        }
+        res
+    }
+
+    fn append_inner(
+        &mut self,
+        changes: impl Iterator<Item = Result<(Vec<u8>, Option<Vec<u8>>)>>,
+    ) -> Result<()> {
+        let mut pos = self.end;
+        let mut buf = vec![];
+        let mut pending = vec![];
+        for change in changes {
+            let (key, val) = change?;
+            let record_start = pos + buf.len() as u64;
+            let loc = match &val {
+                Some(val) => {
+                    encode_record(&mut buf, TAG_PUT, &key, val);
+                    Some(ValueLoc {
+                        offset: record_start + RECORD_HEADER_LEN + key.len() as u64,
+                        len: val.len() as u32,
+                        record_len: RECORD_HEADER_LEN + (key.len() + val.len()) as u64,
+                    })
+                }
+                None => {
+                    encode_record(&mut buf, TAG_DEL, &key, &[]);
+                    None
+                }
+            };
+            pending.push((key, loc));
+            if buf.len() >= WRITE_CHUNK {
+                self.file.write_at(&buf, pos)?;
+                pos += buf.len() as u64;
+                buf.clear();
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+        self.seq += 1;
+        encode_record(&mut buf, TAG_COMMIT, &[], &self.seq.to_le_bytes());
+        self.file.write_at(&buf, pos)?;
+        self.file.flush()?;
+        self.end = pos + buf.len() as u64;
+        self.apply(pending.into_iter());
+        Ok(())
+    }
+
+    fn maybe_compact(&mut self) -> Result<()> {
+        let garbage = self.end - self.start - self.live_bytes;
+        if garbage > COMPACT_MIN_GARBAGE && garbage > self.live_bytes {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites all live records after the end of the log, then moves them
+    /// to the front of the file if they fit into the space freed.
+    fn compact(&mut self) -> Result<()> {
+        let snapshot_start = self.end;
+        let mut pos = snapshot_start;
+        let mut buf = vec![];
+        let mut offsets = Vec::with_capacity(self.index.len());
+        for (key, loc) in self.index.iter() {
+            let val = self.load(*loc)?;
+            offsets.push(pos + buf.len() as u64 - snapshot_start);
+            encode_record(&mut buf, TAG_PUT, key, &val);
+            if buf.len() >= WRITE_CHUNK {
+                self.file.write_at(&buf, pos)?;
+                pos += buf.len() as u64;
+                buf.clear();
+            }
+        }
+        let seq = self.seq + 1;
+        encode_record(&mut buf, TAG_COMMIT, &[], &seq.to_le_bytes());
+        self.file.write_at(&buf, pos)?;
+        self.file.flush()?;
+        self.write_header(snapshot_start)?;
+        self.file.flush()?;
+        self.seq = seq;
+        let snapshot_len = pos + buf.len() as u64 - snapshot_start;
+        self.rebase(snapshot_start, snapshot_len, &offsets);
+
+        if snapshot_len > snapshot_start - HEADER_LEN {
+            return Ok(());
+        }
+        let mut copied = 0;
+        while copied < snapshot_len {
+            let n = (snapshot_len - copied).min(WRITE_CHUNK as u64);
+            let mut chunk = vec![0; n as usize];
+            self.file.read_at(&mut chunk, snapshot_start + copied)?;
+            self.file.write_at(&chunk, HEADER_LEN + copied)?;
+            copied += n;
+        }
+        self.file.flush()?;
+        self.write_header(HEADER_LEN)?;
+        self.file.flush()?;
+        self.rebase(HEADER_LEN, snapshot_len, &offsets);
+        self.file.truncate(self.end)?;
+        self.file.flush()
+    }
+
+    fn rebase(&mut self, start: u64, len: u64, offsets: &[u64]) {
+        let mut live_bytes = 0;
+        for ((key, loc), offset) in self.index.iter_mut().zip(offsets) {
+            loc.offset = start + offset + RECORD_HEADER_LEN + key.len() as u64;
+            live_bytes += loc.record_len;
+        }
+        self.start = start;
+        self.end = start + len;
+        self.live_bytes = live_bytes;
+    }
+}
+
+impl<'s> Storage<'s> for OpfsStorage {
+    type Tx = OpfsTx<'s>;
+
+    fn storage_kind(&self) -> &'static str {
+        "opfs"
+    }
+
+    fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+        Ok(if write {
+            let wtr = self.store.write().unwrap();
+            OpfsTx::Writer(wtr, Default::default())
+        } else {
+            let rdr = self.store.read().unwrap();
+            OpfsTx::Reader(rdr)
+        })
+    }
+
+    fn range_compact(&'s self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+        self.store.write().unwrap().compact()
+    }
+
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        let mut store = self.store.write().unwrap();
+        store.append(data.map_ok(|(k, v)| (k, Some(v))))?;
+        store.maybe_compact()
+    }
+}
+
+pub enum OpfsTx<'s> {
+    Reader(RwLockReadGuard<'s, LogStore>),
+    Writer(
+        RwLockWriteGuard<'s, LogStore>,
+        BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    ),
+}
+
+enum Found<'a> {
+    Stored(ValueLoc),
+    Changed(&'a [u8]),
+}
+
+fn successor(key: &[u8]) -> Vec<u8> {
+    let mut ret = key.to_vec();
+    ret.push(0);
+    ret
+}
+
+impl<'s> OpfsTx<'s> {
+    fn store(&self) -> &LogStore {
+        match self {
+            OpfsTx::Reader(rdr) => rdr,
+            OpfsTx::Writer(wtr, _) => wtr,
+        }
+    }
+
+    fn load(&self, found: Found<'_>) -> Result<Vec<u8>> {
+        match found {
+            Found::Stored(loc) => self.store().load(loc),
+            Found::Changed(val) => Ok(val.to_vec()),
+        }
+    }
+
+    /// Finds the first live key within the bounds, taking uncommitted changes into account.
+    fn seek(&self, lower: &[u8], upper: Option<&[u8]>) -> Option<(Vec<u8>, Found<'_>)> {
+        let mut lower = lower.to_vec();
+        loop {
+            if let Some(upper) = upper {
+                if lower.as_slice() >= upper {
+                    return None;
+                }
+            }
+            let bounds = (
+                Bound::Included(lower.as_slice()),
+                upper.map_or(Bound::Unbounded, Bound::Excluded),
+            );
+            let stored = self.store().index.range::<[u8], _>(bounds).next();
+            let changed = match self {
+                OpfsTx::Reader(_) => None,
+                OpfsTx::Writer(_, changes) => changes.range::<[u8], _>(bounds).next(),
+            };
+            return match (stored, changed) {
+                (None, None) => None,
+                (Some((k, loc)), None) => Some((k.clone(), Found::Stored(*loc))),
+                (Some((sk, loc)), Some((ck, _))) if sk < ck => {
+                    Some((sk.clone(), Found::Stored(*loc)))
+                }
+                (_, Some((ck, cv))) => match cv {
+                    Some(v) => Some((ck.clone(), Found::Changed(v))),
+                    None => {
+                        lower = successor(ck);
+                        continue;
+                    }
+                },
+            };
+        }
+    }
+}
+
+impl<'s> StoreTx<'s> for OpfsTx<'s> {
+    fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
+        if let OpfsTx::Writer(_, changes) = self {
+            if let Some(r) = changes.get(key) {
+                return Ok(r.clone());
+            }
+        }
+        match self.store().index.get(key) {
+            None => Ok(None),
+            Some(loc) => Ok(Some(self.store().load(*loc)?)),
+        }
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        match self {
+            OpfsTx::Reader(_) => {
+                bail!("write in read transaction")
+            }
+            OpfsTx::Writer(_, changes) => {
+                changes.insert(key.to_vec(), Some(val.to_vec()));
+                Ok(())
+            }
+        }
+    }
+
+    fn supports_par_put(&self) -> bool {
+        false
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        match self {
+            OpfsTx::Reader(_) => {
+                bail!("write in read transaction")
+            }
+            OpfsTx::Writer(_, changes) => {
+                changes.insert(key.to_vec(), None);
+                Ok(())
+            }
+        }
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        match self {
+            OpfsTx::Reader(_) => {
+                bail!("write in read transaction")
+            }
+            OpfsTx::Writer(wtr, changes) => {
+                for k in wtr
+                    .index
+                    .range(lower.to_vec()..upper.to_vec())
+                    .map(|kv| kv.0)
+                {
+                    changes.entry(k.clone()).or_insert(None);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn exists(&self, key: &[u8], _for_update: bool) -> Result<bool> {
+        if let OpfsTx::Writer(_, changes) = self {
+            if let Some(r) = changes.get(key) {
+                return Ok(r.is_some());
+            }
+        }
+        Ok(self.store().index.contains_key(key))
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        match self {
+            OpfsTx::Reader(_) => Ok(()),
+            OpfsTx::Writer(wtr, changes) => {
+                let changes = std::mem::take(changes);
+                wtr.append(changes.into_iter().map(Ok))?;
+                wtr.maybe_compact()
+            }
+        }
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        Box::new(OpfsSkipIter {
+            tx: self,
+            upper: upper.to_vec(),
+            valid_at,
+            next_bound: lower.to_vec(),
+        })
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(OpfsIter {
+            tx: self,
+            upper: Some(upper.to_vec()),
+            next_bound: Some(lower.to_vec()),
+        })
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        let mut count = 0;
+        let mut bound = lower.to_vec();
+        while let Some((k, _)) = self.seek(&bound, Some(upper)) {
+            count += 1;
+            bound = successor(&k);
+        }
+        Ok(count)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(OpfsIter {
+            tx: self,
+            upper: None,
+            next_bound: Some(vec![]),
+        })
+    }
+}
+
+struct OpfsIter<'a, 's> {
+    tx: &'a OpfsTx<'s>,
+    upper: Option<Vec<u8>>,
+    next_bound: Option<Vec<u8>>,
+}
+
+impl Iterator for OpfsIter<'_, '_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bound = self.next_bound.take()?;
+        let (k, found) = self.tx.seek(&bound, self.upper.as_deref())?;
+        match self.tx.load(found) {
+            Ok(v) => {
+                self.next_bound = Some(successor(&k));
+                Some(Ok((k, v)))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+struct OpfsSkipIter<'a, 's> {
+    tx: &'a OpfsTx<'s>,
+    upper: Vec<u8>,
+    valid_at: ValidityTs,
+    next_bound: Vec<u8>,
+}
+
+impl Iterator for OpfsSkipIter<'_, '_> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (candidate_key, found) = self.tx.seek(&self.next_bound, Some(&self.upper))?;
+            let (ret, nxt_bound) = check_key_for_validity(&candidate_key, self.valid_at, None);
+            self.next_bound = nxt_bound;
+            if let Some(mut nk) = ret {
+                return Some(match self.tx.load(found) {
+                    Ok(v) => {
+                        extend_tuple_from_v(&mut nk, &v);
+                        Ok(nk)
+                    }
+                    Err(e) => {
+                        self.next_bound = self.upper.clone();
+                        Err(e)
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::value::DataValue;
+    use crate::runtime::db::ScriptMutability;
+    use crate::Db;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct MemFile(Arc<Mutex<Vec<u8>>>);
+
+    impl LogFile for MemFile {
+        fn size(&self) -> Result<u64> {
+            Ok(self.0.lock().unwrap().len() as u64)
+        }
+
+        fn read_at(&self, buf: &mut [u8], at: u64) -> Result<()> {
+            let data = self.0.lock().unwrap();
+            let at = at as usize;
+            if at + buf.len() > data.len() {
+                bail!("unexpected end of file");
+            }
+            buf.copy_from_slice(&data[at..at + buf.len()]);
+            Ok(())
+        }
+
+        fn write_at(&self, buf: &[u8], at: u64) -> Result<()> {
+            let mut data = self.0.lock().unwrap();
+            let at = at as usize;
+            if data.len() < at + buf.len() {
+                data.resize(at + buf.len(), 0);
+            }
+            data[at..at + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn truncate(&self, size: u64) -> Result<()> {
+            self.0.lock().unwrap().resize(size as usize, 0);
+            Ok(())
+        }
+
+        fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn open_db(file: &MemFile) -> Result<Db<OpfsStorage>> {
+        let db = Db::new(OpfsStorage::open(Box::new(file.clone()))?)?;
+        db.initialize()?;
+        Ok(db)
+    }
+
+    fn run(db: &Db<OpfsStorage>, script: &str) -> Result<Vec<Vec<DataValue>>> {
+        Ok(db
+            .run_script(script, Default::default(), ScriptMutability::Mutable)?
+            .rows)
+    }
+
+    #[test]
+    fn test_persistence() -> Result<()> {
+        let file = MemFile::default();
+        {
+            let db = open_db(&file)?;
+            run(&db, ":create plain {k: Int => v}")?;
+            run(
+                &db,
+                "?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']] :put plain {k => v}",
+            )?;
+            run(&db, "?[k] <- [[2]] :rm plain {k}")?;
+            run(&db, "?[k, v] <- [[3, 'z']] :put plain {k => v}")?;
+        }
+        let db = open_db(&file)?;
+        let rows = run(&db, "?[k, v] := *plain{k, v}")?;
+        assert_eq!(
+            rows,
+            vec![
+                vec![DataValue::from(1), DataValue::from("a")],
+                vec![DataValue::from(3), DataValue::from("z")],
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_torn_tail_is_discarded() -> Result<()> {
+        let file = MemFile::default();
+        {
+            let db = open_db(&file)?;
+            run(&db, ":create plain {k: Int => v}")?;
+            run(&db, "?[k, v] <- [[1, 'a']] :put plain {k => v}")?;
+        }
+        let committed = file.size()?;
+        {
+            let db = open_db(&file)?;
+            run(&db, "?[k, v] <- [[2, 'b']] :put plain {k => v}")?;
+        }
+        // Simulate a crash in the middle of writing the second transaction
+        file.truncate(file.size()? - 3)?;
+        let db = open_db(&file)?;
+        assert_eq!(file.size()?, committed);
+        let rows = run(&db, "?[k] := *plain{k}")?;
+        assert_eq!(rows, vec![vec![DataValue::from(1)]]);
+        run(&db, "?[k, v] <- [[3, 'c']] :put plain {k => v}")?;
+        let db = open_db(&file)?;
+        let rows = run(&db, "?[k] := *plain{k}")?;
+        assert_eq!(
+            rows,
+            vec![vec![DataValue::from(1)], vec![DataValue::from(3)]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction() -> Result<()> {
+        let file = MemFile::default();
+        let db = open_db(&file)?;
+        run(&db, ":create plain {k: Int => v}")?;
+        for i in 0..20 {
+            run(
+                &db,
+                &format!("?[k, v] := k in int_range(100), v = {i} :put plain {{k => v}}"),
+            )?;
+        }
+        let before = file.size()?;
+        db.db.range_compact(&[], &[])?;
+        assert!(file.size()? < before);
+        run(&db, "?[k, v] <- [[0, 'x']] :put plain {k => v}")?;
+
+        for db in [db, open_db(&file)?] {
+            let rows = run(&db, "?[count(k), sum(v)] := *plain{k, v}, k > 0")?;
+            assert_eq!(
+                rows,
+                vec![vec![DataValue::from(99), DataValue::from(19.0 * 99.)]]
+            );
+            let rows = run(&db, "?[v] := *plain{k: 0, v}")?;
+            assert_eq!(rows, vec![vec![DataValue::from("x")]]);
+        }
+        Ok(())
+    }
+}
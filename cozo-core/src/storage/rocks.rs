@@ -12,13 +12,13 @@ use std::path::{Path, PathBuf};
 use log::info;
 use miette::{miette, IntoDiagnostic, Result, WrapErr};
 
-use cozorocks::{DbBuilder, DbIter, RocksDb, Tx};
+use cozorocks::{DbBuilder, DbIter, RocksDb, StatusCode, Tx};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::db::{BadDbInit, DbManifest};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
-use crate::storage::{Storage, StoreTx};
+use crate::storage::{Storage, StoreTx, TransactionConflict};
 use crate::utils::swap_option_result;
 use crate::Db;
 
@@ -205,7 +205,14 @@ impl<'s> StoreTx<'s> for RocksDbTx {
     }
 
     fn commit(&mut self) -> Result<()> {
-        Ok(self.db_tx.commit()?)
+        match self.db_tx.commit() {
+            Ok(()) => Ok(()),
+            // optimistic transactions report conflicts with concurrent writes at commit time
+            Err(status) if matches!(status.code, StatusCode::kBusy | StatusCode::kTryAgain) => {
+                Err(TransactionConflict(status.message).into())
+            }
+            Err(status) => Err(status.into()),
+        }
     }
 
     fn range_scan_tuple<'a>(
@@ -22,6 +22,16 @@ pub(crate) struct TempCollector<T: serde::Serialize + for<'a> serde::Deserialize
 }
 
 impl<T: serde::Serialize + for<'a> serde::Deserialize<'a>> TempCollector<T> {
+    /// A collector that starts writing to disk once it holds `swap_after` elements.
+    pub(crate) fn with_swap_after(swap_after: usize) -> Self {
+        Self {
+            inner: swapvec::SwapVec::with_config(swapvec::SwapVecConfig {
+                swap_after,
+                batch_size: swap_after,
+                compression: None,
+            }),
+        }
+    }
     pub(crate) fn push(&mut self, val: T) {
         self.inner.push(val).unwrap();
     }
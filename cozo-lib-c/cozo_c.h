@@ -63,6 +63,63 @@ char *cozo_run_query(int32_t db_id,
                      const char *params_raw,
                      bool immutable_query);
 
+/**
+ * Run query against a database, which can be cancelled with `cozo_cancel`.
+ *
+ * `db_id`:           the ID representing the database to run the query.
+ * `script_raw`:      a UTF-8 encoded C-string for the CozoScript to execute.
+ * `params_raw`:      a UTF-8 encoded C-string for the params of the query,
+ *                    in JSON format. You must always pass in a valid JSON map,
+ *                    even if you do not use params in your query
+ *                    (pass "{}" in this case).
+ * `immutable_query`: whether the query is read-only.
+ * `handle_id`:       the ID of a cancel handle obtained from `cozo_new_cancel_handle`.
+ *
+ * Returns a UTF-8-encoded C-string that **must** be freed with `cozo_free_str`.
+ * The string contains the JSON return value of the query.
+ */
+char *cozo_run_query_with_handle(int32_t db_id,
+                                 const char *script_raw,
+                                 const char *params_raw,
+                                 bool immutable_query,
+                                 int32_t handle_id);
+
+/**
+ * Create a handle that can be used to cancel running queries.
+ *
+ * Returns the ID of the handle, which must be freed with `cozo_free_cancel_handle`.
+ */
+int32_t cozo_new_cancel_handle(void);
+
+/**
+ * Cancel all queries running with the handle, as well as all future ones.
+ *
+ * `handle_id`: the ID of the cancel handle.
+ *
+ * Returns `false` if the handle does not exist.
+ */
+bool cozo_cancel(int32_t handle_id);
+
+/**
+ * Free a cancel handle. Queries already running with the handle are not affected.
+ *
+ * `handle_id`: the ID of the cancel handle.
+ *
+ * Returns `false` if the handle has already been freed, or does not exist.
+ */
+bool cozo_free_cancel_handle(int32_t handle_id);
+
+/**
+ * Set the timeout in seconds for queries that do not specify `:timeout` themselves.
+ *
+ * `db_id`: the ID representing the database.
+ * `secs`:  the timeout in seconds, pass a non-positive number to remove the default timeout.
+ *
+ * Returns a UTF-8-encoded C-string indicating the result that **must** be freed with `cozo_free_str`.
+ */
+char *cozo_set_default_timeout(int32_t db_id,
+                               double secs);
+
 /**
  * Import data into relations
  *
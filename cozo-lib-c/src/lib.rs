@@ -21,12 +21,14 @@ use cozo::*;
 struct Handles {
     current: AtomicI32,
     dbs: Mutex<BTreeMap<i32, DbInstance>>,
+    cancel_handles: Mutex<BTreeMap<i32, Poison>>,
 }
 
 lazy_static! {
     static ref HANDLES: Handles = Handles {
         current: Default::default(),
-        dbs: Mutex::new(Default::default())
+        dbs: Mutex::new(Default::default()),
+        cancel_handles: Mutex::new(Default::default())
     };
 }
 
@@ -107,6 +109,116 @@ pub unsafe extern "C" fn cozo_run_query(
     script_raw: *const c_char,
     params_raw: *const c_char,
     immutable_query: bool,
+) -> *mut c_char {
+    run_query(
+        db_id,
+        script_raw,
+        params_raw,
+        immutable_query,
+        &Poison::default(),
+    )
+}
+
+/// Run query against a database, which can be cancelled with `cozo_cancel`.
+///
+/// `db_id`:           the ID representing the database to run the query.
+/// `script_raw`:      a UTF-8 encoded C-string for the CozoScript to execute.
+/// `params_raw`:      a UTF-8 encoded C-string for the params of the query,
+///                    in JSON format. You must always pass in a valid JSON map,
+///                    even if you do not use params in your query
+///                    (pass "{}" in this case).
+/// `immutable_query`: whether the query is read-only.
+/// `handle_id`:       the ID of a cancel handle obtained from `cozo_new_cancel_handle`.
+///
+/// Returns a UTF-8-encoded C-string that **must** be freed with `cozo_free_str`.
+/// The string contains the JSON return value of the query.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_run_query_with_handle(
+    db_id: i32,
+    script_raw: *const c_char,
+    params_raw: *const c_char,
+    immutable_query: bool,
+    handle_id: i32,
+) -> *mut c_char {
+    let handle = {
+        let handles = HANDLES.cancel_handles.lock().unwrap();
+        handles.get(&handle_id).cloned()
+    };
+    match handle {
+        None => CString::new(r##"{"ok":false,"message":"cancel handle does not exist"}"##)
+            .unwrap()
+            .into_raw(),
+        Some(handle) => run_query(db_id, script_raw, params_raw, immutable_query, &handle),
+    }
+}
+
+/// Create a handle that can be used to cancel running queries.
+///
+/// Returns the ID of the handle, which must be freed with `cozo_free_cancel_handle`.
+#[no_mangle]
+pub extern "C" fn cozo_new_cancel_handle() -> i32 {
+    let id = HANDLES.current.fetch_add(1, Ordering::AcqRel);
+    let mut handles = HANDLES.cancel_handles.lock().unwrap();
+    handles.insert(id, Poison::default());
+    id
+}
+
+/// Cancel all queries running with the handle, as well as all future ones.
+///
+/// `handle_id`: the ID of the cancel handle.
+///
+/// Returns `false` if the handle does not exist.
+#[no_mangle]
+pub extern "C" fn cozo_cancel(handle_id: i32) -> bool {
+    let handles = HANDLES.cancel_handles.lock().unwrap();
+    match handles.get(&handle_id) {
+        None => false,
+        Some(handle) => {
+            handle.cancel();
+            true
+        }
+    }
+}
+
+/// Free a cancel handle. Queries already running with the handle are not affected.
+///
+/// `handle_id`: the ID of the cancel handle.
+///
+/// Returns `false` if the handle has already been freed, or does not exist.
+#[no_mangle]
+pub extern "C" fn cozo_free_cancel_handle(handle_id: i32) -> bool {
+    let mut handles = HANDLES.cancel_handles.lock().unwrap();
+    handles.remove(&handle_id).is_some()
+}
+
+/// Set the timeout in seconds for queries that do not specify `:timeout` themselves.
+///
+/// `db_id`: the ID representing the database.
+/// `secs`:  the timeout in seconds, pass a non-positive number to remove the default timeout.
+///
+/// Returns a UTF-8-encoded C-string indicating the result that **must** be freed with `cozo_free_str`.
+#[no_mangle]
+pub extern "C" fn cozo_set_default_timeout(db_id: i32, secs: f64) -> *mut c_char {
+    let db = {
+        let dbs = HANDLES.dbs.lock().unwrap();
+        dbs.get(&db_id).cloned()
+    };
+    let result = match db {
+        None => r##"{"ok":false,"message":"database closed"}"##.to_string(),
+        Some(db) => match db.set_default_timeout(if secs > 0. { Some(secs) } else { None }) {
+            Ok(()) => r##"{"ok":true}"##.to_string(),
+            Err(err) => format_error_as_json(err, None).to_string(),
+        },
+    };
+    CString::new(result).unwrap().into_raw()
+}
+
+unsafe fn run_query(
+    db_id: i32,
+    script_raw: *const c_char,
+    params_raw: *const c_char,
+    immutable_query: bool,
+    handle: &Poison,
 ) -> *mut c_char {
     let script = match CStr::from_ptr(script_raw).to_str() {
         Ok(p) => p,
@@ -141,7 +253,7 @@ pub unsafe extern "C" fn cozo_run_query(
         }
     };
 
-    let result = db.run_script_str(script, params_str, immutable_query);
+    let result = db.run_script_str_with_handle(script, params_str, immutable_query, handle);
     CString::new(result).unwrap().into_raw()
 }
 
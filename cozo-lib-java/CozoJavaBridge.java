@@ -4,9 +4,50 @@ public class CozoJavaBridge {
     private static native int openDb(String engine, String path, String options);
     private static native boolean closeDb(int id);
     private static native String runQuery(int id, String script, String params);
+    private static native String runQueryWithHandle(int id, String script, String params, int handle);
+    private static native int newCancelHandle();
+    private static native boolean cancel(int handle);
+    private static native boolean closeCancelHandle(int handle);
+    private static native String setDefaultTimeout(int id, double secs);
     private static native String exportRelations(int id, String rel);
     private static native String importRelations(int id, String data);
     private static native String backup(int id, String file);
     private static native String restore(int id, String file);
     private static native String importFromBackup(int id, String data);
+
+    // Typed API, errors are thrown as CozoException
+
+    private static native NamedRows runQueryTyped(int id, String script, java.util.Map<String, Object> params, boolean immutable);
+    private static native int multiTransact(int id, boolean write);
+    private static native NamedRows runTxQuery(int txId, String script, java.util.Map<String, Object> params);
+    private static native void commitTx(int txId);
+    private static native void abortTx(int txId);
+    private static native int registerCallback(int id, String relation, MutationCallback callback);
+    private static native boolean unregisterCallback(int id, int callbackId);
+    private static native void registerFixedRule(int id, String name, int arity, FixedRule rule);
+    private static native boolean unregisterFixedRule(int id, String name);
+
+    public static final class NamedRows {
+        public final String[] headers;
+        public final Object[][] rows;
+
+        public NamedRows(String[] headers, Object[][] rows) {
+            this.headers = headers;
+            this.rows = rows;
+        }
+    }
+
+    public static class CozoException extends RuntimeException {
+        public CozoException(String message) {
+            super(message);
+        }
+    }
+
+    public interface MutationCallback {
+        void onMutation(String op, Object[][] newRows, Object[][] oldRows);
+    }
+
+    public interface FixedRule {
+        Object[][] run(Object[][][] inputs, java.util.Map<String, Object> options);
+    }
 }
\ No newline at end of file
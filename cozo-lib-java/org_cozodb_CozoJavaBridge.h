@@ -31,6 +31,46 @@ JNIEXPORT jboolean JNICALL Java_org_cozodb_CozoJavaBridge_closeDb
 JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_runQuery
   (JNIEnv *, jclass, jint, jstring, jstring);
 
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    runQueryWithHandle
+ * Signature: (ILjava/lang/String;Ljava/lang/String;I)Ljava/lang/String;
+ */
+JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_runQueryWithHandle
+  (JNIEnv *, jclass, jint, jstring, jstring, jint);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    newCancelHandle
+ * Signature: ()I
+ */
+JNIEXPORT jint JNICALL Java_org_cozodb_CozoJavaBridge_newCancelHandle
+  (JNIEnv *, jclass);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    cancel
+ * Signature: (I)Z
+ */
+JNIEXPORT jboolean JNICALL Java_org_cozodb_CozoJavaBridge_cancel
+  (JNIEnv *, jclass, jint);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    closeCancelHandle
+ * Signature: (I)Z
+ */
+JNIEXPORT jboolean JNICALL Java_org_cozodb_CozoJavaBridge_closeCancelHandle
+  (JNIEnv *, jclass, jint);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    setDefaultTimeout
+ * Signature: (ID)Ljava/lang/String;
+ */
+JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_setDefaultTimeout
+  (JNIEnv *, jclass, jint, jdouble);
+
 /*
  * Class:     org_cozodb_CozoJavaBridge
  * Method:    exportRelations
@@ -71,6 +111,78 @@ JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_restore
 JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_importFromBackup
   (JNIEnv *, jclass, jint, jstring);
 
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    runQueryTyped
+ * Signature: (ILjava/lang/String;Ljava/util/Map;Z)Lorg/cozodb/CozoJavaBridge/NamedRows;
+ */
+JNIEXPORT jobject JNICALL Java_org_cozodb_CozoJavaBridge_runQueryTyped
+  (JNIEnv *, jclass, jint, jstring, jobject, jboolean);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    multiTransact
+ * Signature: (IZ)I
+ */
+JNIEXPORT jint JNICALL Java_org_cozodb_CozoJavaBridge_multiTransact
+  (JNIEnv *, jclass, jint, jboolean);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    runTxQuery
+ * Signature: (ILjava/lang/String;Ljava/util/Map;)Lorg/cozodb/CozoJavaBridge/NamedRows;
+ */
+JNIEXPORT jobject JNICALL Java_org_cozodb_CozoJavaBridge_runTxQuery
+  (JNIEnv *, jclass, jint, jstring, jobject);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    commitTx
+ * Signature: (I)V
+ */
+JNIEXPORT void JNICALL Java_org_cozodb_CozoJavaBridge_commitTx
+  (JNIEnv *, jclass, jint);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    abortTx
+ * Signature: (I)V
+ */
+JNIEXPORT void JNICALL Java_org_cozodb_CozoJavaBridge_abortTx
+  (JNIEnv *, jclass, jint);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    registerCallback
+ * Signature: (ILjava/lang/String;Lorg/cozodb/CozoJavaBridge/MutationCallback;)I
+ */
+JNIEXPORT jint JNICALL Java_org_cozodb_CozoJavaBridge_registerCallback
+  (JNIEnv *, jclass, jint, jstring, jobject);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    unregisterCallback
+ * Signature: (II)Z
+ */
+JNIEXPORT jboolean JNICALL Java_org_cozodb_CozoJavaBridge_unregisterCallback
+  (JNIEnv *, jclass, jint, jint);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    registerFixedRule
+ * Signature: (ILjava/lang/String;ILorg/cozodb/CozoJavaBridge/FixedRule;)V
+ */
+JNIEXPORT void JNICALL Java_org_cozodb_CozoJavaBridge_registerFixedRule
+  (JNIEnv *, jclass, jint, jstring, jint, jobject);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    unregisterFixedRule
+ * Signature: (ILjava/lang/String;)Z
+ */
+JNIEXPORT jboolean JNICALL Java_org_cozodb_CozoJavaBridge_unregisterFixedRule
+  (JNIEnv *, jclass, jint, jstring);
+
 #ifdef __cplusplus
 }
 #endif
@@ -6,12 +6,16 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 use std::collections::BTreeMap;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicI32, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
-use jni::objects::{JClass, JString};
-use jni::sys::{jboolean, jint, jstring};
-use jni::JNIEnv;
+use bigdecimal::BigDecimal;
+use jni::objects::{
+    JByteArray, JClass, JDoubleArray, JFloatArray, JObject, JObjectArray, JString, JValue,
+};
+use jni::sys::{jboolean, jdouble, jint, jobject, jstring};
+use jni::{JNIEnv, JavaVM};
 use lazy_static::lazy_static;
 
 use cozo::*;
@@ -20,12 +24,16 @@ use cozo::*;
 struct Handles {
     current: AtomicI32,
     dbs: Mutex<BTreeMap<i32, DbInstance>>,
+    cancel_handles: Mutex<BTreeMap<i32, Poison>>,
+    txs: Mutex<BTreeMap<i32, Arc<MultiTransaction>>>,
 }
 
 lazy_static! {
     static ref HANDLES: Handles = Handles {
         current: Default::default(),
-        dbs: Mutex::new(Default::default())
+        dbs: Mutex::new(Default::default()),
+        cancel_handles: Mutex::new(Default::default()),
+        txs: Mutex::new(Default::default()),
     };
 }
 
@@ -34,6 +42,11 @@ fn get_db(id: i32) -> Option<DbInstance> {
     dbs.get(&id).cloned()
 }
 
+fn get_tx(id: i32) -> Option<Arc<MultiTransaction>> {
+    let txs = HANDLES.txs.lock().unwrap();
+    txs.get(&id).cloned()
+}
+
 #[no_mangle]
 pub extern "system" fn Java_org_cozodb_CozoJavaBridge_openDb(
     mut env: JNIEnv,
@@ -74,6 +87,8 @@ pub extern "system" fn Java_org_cozodb_CozoJavaBridge_closeDb(
 }
 
 const DB_NOT_FOUND: &str = r#"{"ok":false,"message":"database not found"}"#;
+const HANDLE_NOT_FOUND: &str = r#"{"ok":false,"message":"cancel handle not found"}"#;
+const TX_NOT_FOUND: &str = r#"{"ok":false,"message":"transaction not found"}"#;
 
 #[no_mangle]
 pub extern "system" fn Java_org_cozodb_CozoJavaBridge_runQuery(
@@ -94,6 +109,87 @@ pub extern "system" fn Java_org_cozodb_CozoJavaBridge_runQuery(
     }
 }
 
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_runQueryWithHandle(
+    mut env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    script: JString,
+    params_str: JString,
+    handle_id: jint,
+) -> jstring {
+    let script: String = env.get_string(&script).unwrap().into();
+    let params_str: String = env.get_string(&params_str).unwrap().into();
+    let handle = {
+        let handles = HANDLES.cancel_handles.lock().unwrap();
+        handles.get(&handle_id).cloned()
+    };
+    match (get_db(id), handle) {
+        (None, _) => env.new_string(DB_NOT_FOUND).unwrap().into_raw(),
+        (_, None) => env.new_string(HANDLE_NOT_FOUND).unwrap().into_raw(),
+        (Some(db), Some(handle)) => {
+            let res = db.run_script_str_with_handle(&script, &params_str, false, &handle);
+            env.new_string(res).unwrap().into_raw()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_newCancelHandle(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jint {
+    let id = HANDLES.current.fetch_add(1, Ordering::AcqRel);
+    let mut handles = HANDLES.cancel_handles.lock().unwrap();
+    handles.insert(id, Poison::default());
+    id
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_cancel(
+    _env: JNIEnv,
+    _class: JClass,
+    handle_id: jint,
+) -> jboolean {
+    let handles = HANDLES.cancel_handles.lock().unwrap();
+    match handles.get(&handle_id) {
+        None => false.into(),
+        Some(handle) => {
+            handle.cancel();
+            true.into()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_closeCancelHandle(
+    _env: JNIEnv,
+    _class: JClass,
+    handle_id: jint,
+) -> jboolean {
+    let mut handles = HANDLES.cancel_handles.lock().unwrap();
+    handles.remove(&handle_id).is_some().into()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_setDefaultTimeout(
+    env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    secs: jdouble,
+) -> jstring {
+    match get_db(id) {
+        None => env.new_string(DB_NOT_FOUND).unwrap().into_raw(),
+        Some(db) => {
+            let res = match db.set_default_timeout(if secs > 0. { Some(secs) } else { None }) {
+                Ok(()) => r#"{"ok":true}"#.to_string(),
+                Err(err) => format_error_as_json(err, None).to_string(),
+            };
+            env.new_string(res).unwrap().into_raw()
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "system" fn Java_org_cozodb_CozoJavaBridge_exportRelations(
     mut env: JNIEnv,
@@ -178,3 +274,564 @@ pub extern "system" fn Java_org_cozodb_CozoJavaBridge_importFromBackup(
         }
     }
 }
+
+// Typed API: instead of JSON strings, values are exchanged as Java objects.
+//
+// null <-> Null, Boolean <-> Bool, Long (or any integral Number) <-> Int,
+// Double (or Float) <-> Float, String <-> Str, byte[] <-> Bytes,
+// float[] and double[] <-> Vec, Object[] (or java.util.List) <-> List,
+// BigDecimal <-> Decimal, java.time.Duration <-> Duration, and java.util.Map -> Json.
+// Other values are returned as their JSON representations in strings.
+// Errors are thrown as `CozoJavaBridge.CozoException`, with the error as JSON as the message.
+
+type JniResult<T> = jni::errors::Result<T>;
+
+const COZO_EXCEPTION: &str = "org/cozodb/CozoJavaBridge$CozoException";
+const NAMED_ROWS_CLASS: &str = "org/cozodb/CozoJavaBridge$NamedRows";
+
+/// Throws a `CozoException`, returning the error signalling a pending exception.
+fn throw_cozo(env: &mut JNIEnv, msg: String) -> jni::errors::Error {
+    match env.throw_new(COZO_EXCEPTION, msg) {
+        Ok(()) => jni::errors::Error::JavaException,
+        Err(err) => err,
+    }
+}
+
+fn throw_report(env: &mut JNIEnv, err: Error, script: Option<&str>) -> jni::errors::Error {
+    let msg = format_error_as_json(err, script).to_string();
+    throw_cozo(env, msg)
+}
+
+/// Makes sure an exception is pending for errors, and returns the raw object.
+fn into_raw_or_throw(env: &mut JNIEnv, res: JniResult<JObject>) -> jobject {
+    match res {
+        Ok(obj) => obj.into_raw(),
+        Err(err) => {
+            if !matches!(err, jni::errors::Error::JavaException) {
+                let _ = env.throw_new("java/lang/RuntimeException", err.to_string());
+            }
+            JObject::null().into_raw()
+        }
+    }
+}
+
+fn value2java<'local>(env: &mut JNIEnv<'local>, val: &DataValue) -> JniResult<JObject<'local>> {
+    Ok(match val {
+        DataValue::Null => JObject::null(),
+        DataValue::Bool(b) => {
+            env.new_object("java/lang/Boolean", "(Z)V", &[JValue::Bool(*b as u8)])?
+        }
+        DataValue::Num(Num::Int(i)) => {
+            env.new_object("java/lang/Long", "(J)V", &[JValue::Long(*i)])?
+        }
+        DataValue::Num(Num::Float(f)) => {
+            env.new_object("java/lang/Double", "(D)V", &[JValue::Double(*f)])?
+        }
+        DataValue::Str(s) => env.new_string(s as &str)?.into(),
+        DataValue::Bytes(b) => env.byte_array_from_slice(b)?.into(),
+        DataValue::Vec(Vector::F32(a)) => {
+            let arr = env.new_float_array(a.len() as i32)?;
+            env.set_float_array_region(&arr, 0, &a.to_vec())?;
+            arr.into()
+        }
+        DataValue::Vec(Vector::F64(a)) => {
+            let arr = env.new_double_array(a.len() as i32)?;
+            env.set_double_array_region(&arr, 0, &a.to_vec())?;
+            arr.into()
+        }
+        DataValue::List(l) => values2java(env, l)?.into(),
+        DataValue::Decimal(d) => {
+            let s = env.new_string(d.to_string())?;
+            env.new_object(
+                "java/math/BigDecimal",
+                "(Ljava/lang/String;)V",
+                &[(&s).into()],
+            )?
+        }
+        DataValue::Duration(micros) => env
+            .call_static_method(
+                "java/time/Duration",
+                "ofSeconds",
+                "(JJ)Ljava/time/Duration;",
+                &[
+                    JValue::Long(micros.div_euclid(1_000_000)),
+                    JValue::Long(micros.rem_euclid(1_000_000) * 1000),
+                ],
+            )?
+            .l()?,
+        v => match serde_json::Value::from(v.clone()) {
+            serde_json::Value::String(s) => env.new_string(s)?.into(),
+            j => env.new_string(j.to_string())?.into(),
+        },
+    })
+}
+
+fn values2java<'local>(
+    env: &mut JNIEnv<'local>,
+    vals: &[DataValue],
+) -> JniResult<JObjectArray<'local>> {
+    let arr = env.new_object_array(vals.len() as i32, "java/lang/Object", JObject::null())?;
+    for (i, v) in vals.iter().enumerate() {
+        let el = env.with_local_frame_returning_local(8, |env| value2java(env, v))?;
+        env.set_object_array_element(&arr, i as i32, &el)?;
+        env.delete_local_ref(el)?;
+    }
+    Ok(arr)
+}
+
+fn rows2java<'local>(
+    env: &mut JNIEnv<'local>,
+    rows: &[Vec<DataValue>],
+) -> JniResult<JObjectArray<'local>> {
+    let arr = env.new_object_array(rows.len() as i32, "[Ljava/lang/Object;", JObject::null())?;
+    for (i, row) in rows.iter().enumerate() {
+        let el = values2java(env, row)?;
+        env.set_object_array_element(&arr, i as i32, &el)?;
+        env.delete_local_ref(el)?;
+    }
+    Ok(arr)
+}
+
+fn named_rows2java<'local>(env: &mut JNIEnv<'local>, nr: &NamedRows) -> JniResult<JObject<'local>> {
+    let headers =
+        env.new_object_array(nr.headers.len() as i32, "java/lang/String", JObject::null())?;
+    for (i, h) in nr.headers.iter().enumerate() {
+        let h = env.new_string(h)?;
+        env.set_object_array_element(&headers, i as i32, &h)?;
+        env.delete_local_ref(h)?;
+    }
+    let rows = rows2java(env, &nr.rows)?;
+    env.new_object(
+        NAMED_ROWS_CLASS,
+        "([Ljava/lang/String;[[Ljava/lang/Object;)V",
+        &[(&headers).into(), (&rows).into()],
+    )
+}
+
+fn java2string(env: &mut JNIEnv, obj: &JObject) -> JniResult<String> {
+    let s = env
+        .call_method(obj, "toString", "()Ljava/lang/String;", &[])?
+        .l()?;
+    let s = JString::from(s);
+    let ret = env.get_string(&s)?.into();
+    env.delete_local_ref(s)?;
+    Ok(ret)
+}
+
+fn java2values(env: &mut JNIEnv, arr: &JObjectArray) -> JniResult<Vec<DataValue>> {
+    let len = env.get_array_length(arr)?;
+    let mut ret = Vec::with_capacity(len as usize);
+    for i in 0..len {
+        ret.push(env.with_local_frame(8, |env| {
+            let el = env.get_object_array_element(arr, i)?;
+            java2value(env, &el)
+        })?);
+    }
+    Ok(ret)
+}
+
+fn java2value(env: &mut JNIEnv, obj: &JObject) -> JniResult<DataValue> {
+    if obj.is_null() {
+        return Ok(DataValue::Null);
+    }
+    Ok(if env.is_instance_of(obj, "java/lang/Boolean")? {
+        DataValue::from(env.call_method(obj, "booleanValue", "()Z", &[])?.z()?)
+    } else if env.is_instance_of(obj, "java/lang/Double")?
+        || env.is_instance_of(obj, "java/lang/Float")?
+    {
+        DataValue::from(env.call_method(obj, "doubleValue", "()D", &[])?.d()?)
+    } else if env.is_instance_of(obj, "java/math/BigDecimal")?
+        || env.is_instance_of(obj, "java/math/BigInteger")?
+    {
+        let s = java2string(env, obj)?;
+        match BigDecimal::from_str(&s) {
+            Ok(d) => DataValue::Decimal(d.normalized()),
+            Err(err) => return Err(throw_cozo(env, err.to_string())),
+        }
+    } else if env.is_instance_of(obj, "java/lang/Number")? {
+        DataValue::from(env.call_method(obj, "longValue", "()J", &[])?.j()?)
+    } else if env.is_instance_of(obj, "java/lang/String")? {
+        let s = env.get_string(<&JString>::from(obj))?;
+        DataValue::from(String::from(s))
+    } else if env.is_instance_of(obj, "[B")? {
+        DataValue::Bytes(env.convert_byte_array(<&JByteArray>::from(obj))?)
+    } else if env.is_instance_of(obj, "[F")? {
+        let arr = <&JFloatArray>::from(obj);
+        let mut buf = vec![0.; env.get_array_length(arr)? as usize];
+        env.get_float_array_region(arr, 0, &mut buf)?;
+        DataValue::Vec(Vector::F32(ndarray::Array1::from(buf)))
+    } else if env.is_instance_of(obj, "[D")? {
+        let arr = <&JDoubleArray>::from(obj);
+        let mut buf = vec![0.; env.get_array_length(arr)? as usize];
+        env.get_double_array_region(arr, 0, &mut buf)?;
+        DataValue::Vec(Vector::F64(ndarray::Array1::from(buf)))
+    } else if env.is_instance_of(obj, "[Ljava/lang/Object;")? {
+        DataValue::List(java2values(env, <&JObjectArray>::from(obj))?)
+    } else if env.is_instance_of(obj, "java/util/List")? {
+        let arr = env
+            .call_method(obj, "toArray", "()[Ljava/lang/Object;", &[])?
+            .l()?;
+        let arr = JObjectArray::from(arr);
+        let ret = java2values(env, &arr)?;
+        env.delete_local_ref(arr)?;
+        DataValue::List(ret)
+    } else if env.is_instance_of(obj, "java/time/Duration")? {
+        let secs = env.call_method(obj, "getSeconds", "()J", &[])?.j()?;
+        let nanos = env.call_method(obj, "getNano", "()I", &[])?.i()?;
+        match secs
+            .checked_mul(1_000_000)
+            .and_then(|us| us.checked_add(nanos as i64 / 1000))
+        {
+            Some(micros) => DataValue::Duration(micros),
+            None => return Err(throw_cozo(env, "duration out of range".to_string())),
+        }
+    } else if env.is_instance_of(obj, "java/util/Map")? {
+        let mut coll = serde_json::Map::default();
+        for (k, v) in java2params(env, obj)? {
+            coll.insert(k, serde_json::Value::from(v));
+        }
+        DataValue::Json(JsonData(serde_json::Value::Object(coll)))
+    } else {
+        let desc = java2string(env, obj)?;
+        return Err(throw_cozo(
+            env,
+            format!("Java value cannot be converted: {desc}"),
+        ));
+    })
+}
+
+fn java2params(env: &mut JNIEnv, map: &JObject) -> JniResult<BTreeMap<String, DataValue>> {
+    let mut ret = BTreeMap::new();
+    if map.is_null() {
+        return Ok(ret);
+    }
+    let entries = env
+        .call_method(map, "entrySet", "()Ljava/util/Set;", &[])?
+        .l()?;
+    let entries = env
+        .call_method(&entries, "toArray", "()[Ljava/lang/Object;", &[])?
+        .l()?;
+    let entries = JObjectArray::from(entries);
+    for i in 0..env.get_array_length(&entries)? {
+        let (k, v) = env.with_local_frame(8, |env| -> JniResult<_> {
+            let entry = env.get_object_array_element(&entries, i)?;
+            let k = env
+                .call_method(&entry, "getKey", "()Ljava/lang/Object;", &[])?
+                .l()?;
+            let v = env
+                .call_method(&entry, "getValue", "()Ljava/lang/Object;", &[])?
+                .l()?;
+            Ok((java2string(env, &k)?, java2value(env, &v)?))
+        })?;
+        ret.insert(k, v);
+    }
+    Ok(ret)
+}
+
+fn java2rows(env: &mut JNIEnv, arr: &JObject) -> JniResult<Vec<Vec<DataValue>>> {
+    if arr.is_null() {
+        return Ok(vec![]);
+    }
+    let arr = <&JObjectArray>::from(arr);
+    let len = env.get_array_length(arr)?;
+    let mut ret = Vec::with_capacity(len as usize);
+    for i in 0..len {
+        ret.push(env.with_local_frame(8, |env| {
+            let row = env.get_object_array_element(arr, i)?;
+            java2values(env, <&JObjectArray>::from(&row))
+        })?);
+    }
+    Ok(ret)
+}
+
+fn run_query_typed<'local>(
+    env: &mut JNIEnv<'local>,
+    id: jint,
+    script: &JString,
+    params: &JObject,
+    immutable: bool,
+) -> JniResult<JObject<'local>> {
+    let script: String = env.get_string(script)?.into();
+    let params = java2params(env, params)?;
+    let db = match get_db(id) {
+        None => return Err(throw_cozo(env, DB_NOT_FOUND.to_string())),
+        Some(db) => db,
+    };
+    let mutability = if immutable {
+        ScriptMutability::Immutable
+    } else {
+        ScriptMutability::Mutable
+    };
+    match db.run_script(&script, params, mutability) {
+        Ok(nr) => named_rows2java(env, &nr),
+        Err(err) => Err(throw_report(env, err, Some(&script))),
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_runQueryTyped(
+    mut env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    script: JString,
+    params: JObject,
+    immutable: jboolean,
+) -> jobject {
+    let res = run_query_typed(&mut env, id, &script, &params, immutable != 0);
+    into_raw_or_throw(&mut env, res)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_multiTransact(
+    _env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    write: jboolean,
+) -> jint {
+    match get_db(id) {
+        None => -1,
+        Some(db) => {
+            let tx = db.multi_transaction(write != 0);
+            let tx_id = HANDLES.current.fetch_add(1, Ordering::AcqRel);
+            HANDLES.txs.lock().unwrap().insert(tx_id, Arc::new(tx));
+            tx_id
+        }
+    }
+}
+
+fn run_tx_query<'local>(
+    env: &mut JNIEnv<'local>,
+    tx_id: jint,
+    script: &JString,
+    params: &JObject,
+) -> JniResult<JObject<'local>> {
+    let script: String = env.get_string(script)?.into();
+    let params = java2params(env, params)?;
+    let tx = match get_tx(tx_id) {
+        None => return Err(throw_cozo(env, TX_NOT_FOUND.to_string())),
+        Some(tx) => tx,
+    };
+    match tx.run_script(&script, params) {
+        Ok(nr) => named_rows2java(env, &nr),
+        Err(err) => Err(throw_report(env, err, Some(&script))),
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_runTxQuery(
+    mut env: JNIEnv,
+    _class: JClass,
+    tx_id: jint,
+    script: JString,
+    params: JObject,
+) -> jobject {
+    let res = run_tx_query(&mut env, tx_id, &script, &params);
+    into_raw_or_throw(&mut env, res)
+}
+
+fn finish_tx(env: &mut JNIEnv, tx_id: jint, commit: bool) {
+    let tx = HANDLES.txs.lock().unwrap().remove(&tx_id);
+    let res = match tx {
+        None => Err(TX_NOT_FOUND.to_string()),
+        Some(tx) => {
+            let res = if commit { tx.commit() } else { tx.abort() };
+            res.map_err(|err| format_error_as_json(err, None).to_string())
+        }
+    };
+    if let Err(msg) = res {
+        throw_cozo(env, msg);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_commitTx(
+    mut env: JNIEnv,
+    _class: JClass,
+    tx_id: jint,
+) {
+    finish_tx(&mut env, tx_id, true)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_abortTx(
+    mut env: JNIEnv,
+    _class: JClass,
+    tx_id: jint,
+) {
+    finish_tx(&mut env, tx_id, false)
+}
+
+/// Prints and clears the exception thrown by a Java callback, returning its description.
+fn take_exception(env: &mut JNIEnv) -> String {
+    let desc = env.exception_occurred().ok().and_then(|exc| {
+        env.exception_clear().ok()?;
+        java2string(env, &exc).ok()
+    });
+    desc.unwrap_or_else(|| "exception in Java callback".to_string())
+}
+
+fn call_mutation_callback(
+    env: &mut JNIEnv,
+    callback: &JObject,
+    op: CallbackOp,
+    new: &NamedRows,
+    old: &NamedRows,
+) -> JniResult<()> {
+    env.with_local_frame(8, |env| {
+        let op = env.new_string(op.as_str())?;
+        let new = rows2java(env, &new.rows)?;
+        let old = rows2java(env, &old.rows)?;
+        env.call_method(
+            callback,
+            "onMutation",
+            "(Ljava/lang/String;[[Ljava/lang/Object;[[Ljava/lang/Object;)V",
+            &[(&op).into(), (&new).into(), (&old).into()],
+        )?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_registerCallback(
+    mut env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    relation: JString,
+    callback: JObject,
+) -> jint {
+    let relation: String = env.get_string(&relation).unwrap().into();
+    let db = match get_db(id) {
+        None => return -1,
+        Some(db) => db,
+    };
+    let (vm, callback) = match (env.get_java_vm(), env.new_global_ref(callback)) {
+        (Ok(vm), Ok(cb)) => (vm, cb),
+        _ => return -1,
+    };
+    let (cb_id, recv) = db.register_callback(&relation, None);
+    std::thread::spawn(move || {
+        let mut env = match vm.attach_current_thread() {
+            Ok(env) => env,
+            Err(err) => {
+                eprintln!("{err:?}");
+                return;
+            }
+        };
+        for (op, new, old) in recv {
+            if call_mutation_callback(&mut env, callback.as_obj(), op, &new, &old).is_err() {
+                eprintln!("{}", take_exception(&mut env));
+            }
+        }
+    });
+    cb_id as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_unregisterCallback(
+    _env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    cb_id: jint,
+) -> jboolean {
+    match get_db(id) {
+        None => false.into(),
+        Some(db) => db.unregister_callback(cb_id as u32).into(),
+    }
+}
+
+fn call_fixed_rule(
+    vm: &JavaVM,
+    rule: &JObject,
+    inputs: Vec<NamedRows>,
+    options: BTreeMap<String, DataValue>,
+) -> Result<NamedRows, Error> {
+    // the threads of the query pool stay attached, as rules are usually called repeatedly
+    let mut env = vm
+        .attach_current_thread_as_daemon()
+        .map_err(|err| Error::msg(err.to_string()))?;
+    let res = env.with_local_frame(16, |env| -> JniResult<Vec<Vec<DataValue>>> {
+        let js_inputs =
+            env.new_object_array(inputs.len() as i32, "[[Ljava/lang/Object;", JObject::null())?;
+        for (i, input) in inputs.iter().enumerate() {
+            let rows = rows2java(env, &input.rows)?;
+            env.set_object_array_element(&js_inputs, i as i32, &rows)?;
+            env.delete_local_ref(rows)?;
+        }
+        let js_options = env.new_object("java/util/HashMap", "()V", &[])?;
+        for (k, v) in &options {
+            let k = env.new_string(k)?;
+            let v = value2java(env, v)?;
+            env.call_method(
+                &js_options,
+                "put",
+                "(Ljava/lang/Object;Ljava/lang/Object;)Ljava/lang/Object;",
+                &[(&k).into(), (&v).into()],
+            )?;
+            env.delete_local_ref(k)?;
+            env.delete_local_ref(v)?;
+        }
+        let res = env
+            .call_method(
+                rule,
+                "run",
+                "([[[Ljava/lang/Object;Ljava/util/Map;)[[Ljava/lang/Object;",
+                &[(&js_inputs).into(), (&js_options).into()],
+            )?
+            .l()?;
+        java2rows(env, &res)
+    });
+    match res {
+        Ok(rows) => Ok(NamedRows::new(vec![], rows)),
+        Err(jni::errors::Error::JavaException) => Err(Error::msg(take_exception(&mut env))),
+        Err(err) => Err(Error::msg(err.to_string())),
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_registerFixedRule(
+    mut env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    name: JString,
+    arity: jint,
+    rule: JObject,
+) {
+    let name: String = env.get_string(&name).unwrap().into();
+    let db = match get_db(id) {
+        None => {
+            throw_cozo(&mut env, DB_NOT_FOUND.to_string());
+            return;
+        }
+        Some(db) => db,
+    };
+    let (vm, rule) = match (env.get_java_vm(), env.new_global_ref(rule)) {
+        (Ok(vm), Ok(rule)) => (vm, rule),
+        _ => return,
+    };
+    let rule_impl = SimpleFixedRule::new(arity as usize, move |inputs, options| {
+        call_fixed_rule(&vm, rule.as_obj(), inputs, options)
+    });
+    if let Err(err) = db.register_fixed_rule(name, rule_impl) {
+        throw_report(&mut env, err, None);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_unregisterFixedRule(
+    mut env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    name: JString,
+) -> jboolean {
+    let name: String = env.get_string(&name).unwrap().into();
+    match get_db(id) {
+        None => false.into(),
+        Some(db) => match db.unregister_fixed_rule(&name) {
+            Ok(removed) => removed.into(),
+            Err(err) => {
+                throw_report(&mut env, err, None);
+                false.into()
+            }
+        },
+    }
+}
@@ -22,10 +22,32 @@ declare module "cozo-node" {
     /**
      * Runs a query
      *
-     * @param script: the query
-     * @param params: the parameters as key-value pairs, defaults to {}
+     * @param script:    the query
+     * @param params:    the parameters as key-value pairs, defaults to {}
+     * @param immutable: whether the query is read-only, defaults to false
+     * @param signal:    aborting the signal cancels the running query
      */
-    run(script: string, params?: Record<string, any>): Promise<any>;
+    run(script: string, params?: Record<string, any>, immutable?: boolean, signal?: AbortSignal): Promise<any>;
+
+    /**
+     * Runs a query, yielding the rows of the result in chunks, each of the form `{headers, rows}`.
+     * Rows are converted only as the chunks are requested:
+     * `for await (const batch of db.stream(query)) { ... }`.
+     *
+     * @param script:    the query
+     * @param params:    the parameters as key-value pairs, defaults to {}
+     * @param immutable: whether the query is read-only, defaults to false
+     * @param chunkSize: the maximal number of rows in each chunk, defaults to 1024
+     * @param signal:    aborting the signal cancels the running query, or stops the iteration
+     */
+    stream(script: string, params?: Record<string, any>, immutable?: boolean, chunkSize?: number, signal?: AbortSignal): AsyncGenerator<{ headers: string[], rows: any[][] }>;
+
+    /**
+     * Set the timeout in seconds for queries that do not specify `:timeout` themselves
+     *
+     * @param secs: the timeout, pass `null` to remove the default timeout
+     */
+    setDefaultTimeout(secs: number | null): void;
 
     /**
      * Export several relations
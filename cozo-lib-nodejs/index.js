@@ -52,19 +52,77 @@ class CozoDb {
         return new CozoTx(native.multi_transact(this.db_id, !!write))
     }
 
-    run(script, params, immutable) {
+    // Read-only, all scripts see the data as of this call. Release it with `commit` or `abort`.
+    snapshot() {
+        return new CozoTx(native.snapshot(this.db_id))
+    }
+
+    run(script, params, immutable, signal) {
         return new Promise((resolve, reject) => {
             params = params || {};
-            native.query_db(this.db_id, script, params, (err, result) => {
+            if (signal && signal.aborted) {
+                reject(signal.reason);
+                return;
+            }
+            const onAbort = () => native.cancel_query(query_id);
+            const query_id = native.query_db(this.db_id, script, params, (err, result) => {
+                if (signal) {
+                    signal.removeEventListener('abort', onAbort)
+                }
                 if (err) {
                     reject(JSON.parse(err))
                 } else {
                     resolve(result)
                 }
-            }, !!immutable)
+            }, !!immutable);
+            if (signal) {
+                signal.addEventListener('abort', onAbort, {once: true})
+            }
         })
     }
 
+    async* stream(script, params, immutable, chunkSize = 1024, signal) {
+        params = params || {};
+        if (signal && signal.aborted) {
+            throw signal.reason;
+        }
+        let query_id;
+        const onAbort = () => native.cancel_query(query_id);
+        const headers = await new Promise((resolve, reject) => {
+            query_id = native.query_db_stream(this.db_id, script, params, (err, headers) => {
+                if (signal) {
+                    signal.removeEventListener('abort', onAbort)
+                }
+                if (err) {
+                    reject(JSON.parse(err))
+                } else {
+                    resolve(headers)
+                }
+            }, !!immutable);
+            if (signal) {
+                signal.addEventListener('abort', onAbort, {once: true})
+            }
+        });
+        try {
+            while (true) {
+                if (signal && signal.aborted) {
+                    throw signal.reason;
+                }
+                const rows = native.next_chunk(query_id, chunkSize);
+                if (rows === null) {
+                    return;
+                }
+                yield {headers, rows};
+            }
+        } finally {
+            native.close_stream(query_id)
+        }
+    }
+
+    setDefaultTimeout(secs) {
+        native.set_default_timeout(this.db_id, secs)
+    }
+
     exportRelations(relations, as_objects) {
         return new Promise((resolve, reject) => {
             native.export_relations(this.db_id, relations, (err, data) => {
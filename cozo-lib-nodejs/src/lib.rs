@@ -139,6 +139,8 @@ fn value2js<'a>(cx: &mut impl Context<'a>, val: &DataValue) -> JsResult<'a, JsVa
             Num::Int(i) => cx.number(*i as f64).as_value(cx),
             Num::Float(f) => cx.number(*f).as_value(cx),
         },
+        DataValue::Decimal(d) => cx.string(d.to_string()).as_value(cx),
+        d @ DataValue::Duration(_) => json2js(cx, &serde_json::Value::from(d.clone()))?,
         DataValue::Str(s) => cx.string(s).as_value(cx),
         DataValue::Bytes(b) => {
             let b = b.clone();
@@ -273,6 +275,9 @@ struct Handles {
     current_cbs: Mutex<BTreeMap<u32, Sender<Result<NamedRows>>>>,
     nxt_tx_id: AtomicU32,
     txs: Mutex<BTreeMap<u32, Arc<MultiTransaction>>>,
+    nxt_query_id: AtomicU32,
+    queries: Mutex<BTreeMap<u32, Poison>>,
+    streams: Mutex<BTreeMap<u32, std::vec::IntoIter<Vec<DataValue>>>>,
 }
 
 lazy_static! {
@@ -375,6 +380,14 @@ fn multi_transact(mut cx: FunctionContext) -> JsResult<JsNumber> {
     Ok(cx.number(id))
 }
 
+fn snapshot(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let db = get_db!(cx);
+    let tx = db.snapshot();
+    let id = HANDLES.nxt_tx_id.fetch_add(1, Ordering::AcqRel);
+    HANDLES.txs.lock().unwrap().insert(id, Arc::new(tx));
+    Ok(cx.number(id))
+}
+
 fn abort_tx(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let tx = remove_tx!(cx);
     match tx.abort() {
@@ -397,7 +410,7 @@ fn commit_tx(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     }
 }
 
-fn query_db(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+fn query_db(mut cx: FunctionContext) -> JsResult<JsNumber> {
     let db = get_db!(cx);
     let query = cx.argument::<JsString>(1)?.value(&mut cx);
     let params_js = cx.argument::<JsObject>(2)?;
@@ -409,8 +422,16 @@ fn query_db(mut cx: FunctionContext) -> JsResult<JsUndefined> {
 
     let channel = cx.channel();
 
+    let query_id = HANDLES.nxt_query_id.fetch_add(1, Ordering::AcqRel);
+    let poison = Poison::default();
+    HANDLES
+        .queries
+        .lock()
+        .unwrap()
+        .insert(query_id, poison.clone());
+
     rayon::spawn(move || {
-        let result = db.run_script(
+        let result = db.run_script_with_handle(
             &query,
             params,
             if immutable {
@@ -418,7 +439,9 @@ fn query_db(mut cx: FunctionContext) -> JsResult<JsUndefined> {
             } else {
                 ScriptMutability::Mutable
             },
+            &poison,
         );
+        HANDLES.queries.lock().unwrap().remove(&query_id);
         channel.send(move |mut cx| {
             let callback = callback.into_inner(&mut cx);
             let this = cx.undefined();
@@ -438,7 +461,135 @@ fn query_db(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         });
     });
 
-    Ok(cx.undefined())
+    Ok(cx.number(query_id))
+}
+
+fn query_db_stream(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let db = get_db!(cx);
+    let query = cx.argument::<JsString>(1)?.value(&mut cx);
+    let params_js = cx.argument::<JsObject>(2)?;
+    let mut params = BTreeMap::new();
+    js2params(&mut cx, params_js, &mut params)?;
+
+    let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
+    let immutable = cx.argument::<JsBoolean>(4)?.value(&mut cx);
+
+    let channel = cx.channel();
+
+    let query_id = HANDLES.nxt_query_id.fetch_add(1, Ordering::AcqRel);
+    let poison = Poison::default();
+    HANDLES
+        .queries
+        .lock()
+        .unwrap()
+        .insert(query_id, poison.clone());
+
+    rayon::spawn(move || {
+        let result = db.run_script_with_handle(
+            &query,
+            params,
+            if immutable {
+                ScriptMutability::Immutable
+            } else {
+                ScriptMutability::Mutable
+            },
+            &poison,
+        );
+        HANDLES.queries.lock().unwrap().remove(&query_id);
+        // the rows are kept here and converted chunk by chunk as they are pulled by `next_chunk`
+        let result = result.map(|nr| {
+            HANDLES
+                .streams
+                .lock()
+                .unwrap()
+                .insert(query_id, nr.rows.into_iter());
+            nr.headers
+        });
+        channel.send(move |mut cx| {
+            let callback = callback.into_inner(&mut cx);
+            let this = cx.undefined();
+            match result {
+                Ok(headers) => {
+                    let js_headers = cx.empty_array();
+                    for (i, header) in headers.iter().enumerate() {
+                        let converted = cx.string(header);
+                        js_headers.set(&mut cx, i as u32, converted)?;
+                    }
+                    let js_headers = js_headers.as_value(&mut cx);
+                    let err = cx.undefined().as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err, js_headers])?;
+                }
+                Err(err) => {
+                    let reports = format_error_as_json(err, Some(&query)).to_string();
+                    let err = cx.string(&reports).as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err])?;
+                }
+            }
+            Ok(())
+        });
+    });
+
+    Ok(cx.number(query_id))
+}
+
+fn next_chunk(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let size = cx.argument::<JsNumber>(1)?.value(&mut cx).max(1.) as usize;
+    let chunk: Vec<_> = {
+        let mut streams = HANDLES.streams.lock().unwrap();
+        match streams.get_mut(&id) {
+            None => vec![],
+            Some(rows) => {
+                let chunk: Vec<_> = rows.take(size).collect();
+                if chunk.is_empty() {
+                    streams.remove(&id);
+                }
+                chunk
+            }
+        }
+    };
+    if chunk.is_empty() {
+        Ok(cx.null().upcast())
+    } else {
+        Ok(rows2js(&mut cx, &chunk)?.upcast())
+    }
+}
+
+fn close_stream(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let removed = HANDLES.streams.lock().unwrap().remove(&id);
+    Ok(cx.boolean(removed.is_some()))
+}
+
+fn cancel_query(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let queries = HANDLES.queries.lock().unwrap();
+    let found = match queries.get(&id) {
+        None => false,
+        Some(poison) => {
+            poison.cancel();
+            true
+        }
+    };
+    Ok(cx.boolean(found))
+}
+
+fn set_default_timeout(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let db = get_db!(cx);
+    let secs = cx.argument_opt(1);
+    let secs = match secs {
+        Some(v) if !v.is_a::<JsUndefined, _>(&mut cx) && !v.is_a::<JsNull, _>(&mut cx) => {
+            Some(v.downcast_or_throw::<JsNumber, _>(&mut cx)?.value(&mut cx))
+        }
+        _ => None,
+    };
+    match db.set_default_timeout(secs) {
+        Ok(_) => Ok(cx.undefined()),
+        Err(err) => {
+            let msg = cx.string(err.to_string());
+            cx.throw(msg)
+        }
+    }
 }
 
 fn query_tx(mut cx: FunctionContext) -> JsResult<JsUndefined> {
@@ -781,6 +932,11 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("open_db", open_db)?;
     cx.export_function("close_db", close_db)?;
     cx.export_function("query_db", query_db)?;
+    cx.export_function("cancel_query", cancel_query)?;
+    cx.export_function("query_db_stream", query_db_stream)?;
+    cx.export_function("next_chunk", next_chunk)?;
+    cx.export_function("close_stream", close_stream)?;
+    cx.export_function("set_default_timeout", set_default_timeout)?;
     cx.export_function("backup_db", backup_db)?;
     cx.export_function("restore_db", restore_db)?;
     cx.export_function("export_relations", export_relations)?;
@@ -797,6 +953,7 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("abort_tx", abort_tx)?;
     cx.export_function("commit_tx", commit_tx)?;
     cx.export_function("multi_transact", multi_transact)?;
+    cx.export_function("snapshot", snapshot)?;
     cx.export_function("query_tx", query_tx)?;
     Ok(())
 }
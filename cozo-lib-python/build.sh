@@ -1,3 +1,3 @@
 #!/usr/bin/env bash
 
-PYO3_NO_PYTHON=1 maturin build -F compact -F storage-rocksdb --release --strip
\ No newline at end of file
+PYO3_NO_PYTHON=1 maturin build -F compact -F storage-rocksdb -F arrow --release --strip
\ No newline at end of file
@@ -7,6 +7,9 @@
  */
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use miette::{IntoDiagnostic, Report, Result};
 use pyo3::exceptions::PyException;
@@ -146,6 +149,8 @@ fn value_to_py(val: DataValue, py: Python<'_>) -> PyObject {
             Num::Int(i) => i.into_py(py),
             Num::Float(f) => f.into_py(py),
         },
+        DataValue::Decimal(d) => d.to_string().into_py(py),
+        d @ DataValue::Duration(_) => json_to_py(serde_json::Value::from(d), py),
         DataValue::Str(s) => s.as_str().into_py(py),
         DataValue::Bytes(b) => PyBytes::new(py, &b).into(),
         DataValue::Uuid(uuid) => uuid.0.to_string().into_py(py),
@@ -205,11 +210,133 @@ struct CozoDbPy {
 
 #[pyclass]
 struct CozoDbMulTx {
-    tx: MultiTransaction,
+    tx: Arc<Mutex<MultiTransaction>>,
+}
+
+/// Passed to `run_script` to cancel the running script from another thread.
+#[pyclass]
+#[derive(Default)]
+struct CancelHandle {
+    poison: Poison,
+}
+
+#[pymethods]
+impl CancelHandle {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.poison.cancel()
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.poison.is_cancelled()
+    }
+}
+
+/// Result of `run_script_arrow`, exported through the Arrow C stream interface.
+#[cfg(feature = "arrow")]
+#[pyclass]
+struct ArrowResult {
+    batch: arrow_array::RecordBatch,
+}
+
+#[cfg(feature = "arrow")]
+#[pymethods]
+impl ArrowResult {
+    #[getter]
+    fn headers(&self) -> Vec<String> {
+        self.batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().to_string())
+            .collect()
+    }
+    #[getter]
+    fn num_rows(&self) -> usize {
+        self.batch.num_rows()
+    }
+    /// Part of the Arrow PyCapsule interface. The schema cannot be negotiated,
+    /// `requested_schema` is ignored as the protocol allows.
+    #[pyo3(signature = (requested_schema=None))]
+    fn __arrow_c_stream__(
+        &self,
+        py: Python<'_>,
+        requested_schema: Option<PyObject>,
+    ) -> PyResult<PyObject> {
+        let _ = requested_schema;
+        let reader =
+            arrow_array::RecordBatchIterator::new([Ok(self.batch.clone())], self.batch.schema());
+        let stream = arrow_array::ffi_stream::FFI_ArrowArrayStream::new(Box::new(reader));
+        let name = std::ffi::CString::new("arrow_array_stream").unwrap();
+        Ok(pyo3::types::PyCapsule::new_bound(py, stream, Some(name))?.into_py(py))
+    }
 }
 
 const DB_CLOSED_MSG: &str = r##"{"ok":false,"message":"database closed"}"##;
 
+impl CozoDbPy {
+    fn run_script_rows(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: &PyDict,
+        immutable: bool,
+        handle: Option<PyRef<'_, CancelHandle>>,
+    ) -> PyResult<NamedRows> {
+        if let Some(db) = &self.db {
+            let params = convert_params(params)?;
+            let mutability = if immutable {
+                ScriptMutability::Immutable
+            } else {
+                ScriptMutability::Mutable
+            };
+            // the script runs in the shared worker pool so that we can react to
+            // KeyboardInterrupt by cancelling it
+            let poison = match &handle {
+                None => Poison::default(),
+                Some(h) => h.poison.child(),
+            };
+            let (sender, receiver) = channel();
+            {
+                let db = db.clone();
+                let query = query.to_string();
+                let poison = poison.clone();
+                rayon::spawn(move || {
+                    let res = db.run_script_with_handle(&query, params, mutability, &poison);
+                    let _ = sender.send(res);
+                });
+            }
+            // a receiver cannot be shared with the thread released from the GIL
+            let receiver = Mutex::new(receiver);
+            let res = loop {
+                match py.allow_threads(|| {
+                    receiver
+                        .lock()
+                        .unwrap()
+                        .recv_timeout(Duration::from_millis(100))
+                }) {
+                    Ok(res) => break res,
+                    Err(RecvTimeoutError::Timeout) => {
+                        if let Err(err) = py.check_signals() {
+                            poison.cancel();
+                            let _ = py.allow_threads(|| receiver.lock().unwrap().recv());
+                            return Err(err);
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(PyException::new_err("script execution panicked"))
+                    }
+                }
+            };
+            res.map_err(|err| script_error_to_py(py, err, query))
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG))
+        }
+    }
+}
+
 #[pymethods]
 impl CozoDbPy {
     #[new]
@@ -219,39 +346,79 @@ impl CozoDbPy {
             Err(err) => Err(PyException::new_err(format!("{err:?}"))),
         }
     }
+    #[pyo3(signature = (query, params, immutable, handle=None))]
     pub fn run_script(
         &self,
         py: Python<'_>,
         query: &str,
         params: &PyDict,
         immutable: bool,
+        handle: Option<PyRef<'_, CancelHandle>>,
     ) -> PyResult<PyObject> {
-        if let Some(db) = &self.db {
-            let params = convert_params(params)?;
-            match py.allow_threads(|| {
-                db.run_script(
-                    query,
-                    params,
-                    if immutable {
-                        ScriptMutability::Immutable
-                    } else {
-                        ScriptMutability::Mutable
-                    },
-                )
-            }) {
-                Ok(rows) => Ok(named_rows_to_py(rows, py)),
-                Err(err) => {
-                    let reports = format_error_as_json(err, Some(query)).to_string();
-                    let json_mod = py.import("json")?;
-                    let loads_fn = json_mod.getattr("loads")?;
-                    let args = PyTuple::new(py, [PyString::new(py, &reports)]);
-                    let msg = loads_fn.call1(args)?;
-                    Err(PyException::new_err(PyObject::from(msg)))
-                }
-            }
+        let rows = self.run_script_rows(py, query, params, immutable, handle)?;
+        Ok(named_rows_to_py(rows, py))
+    }
+    /// Like `run_script`, but returns an awaitable and runs the script without blocking
+    /// the event loop. Cancelling the awaitable cancels the script.
+    #[pyo3(signature = (query, params, immutable, handle=None))]
+    pub fn run_script_async(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: &PyDict,
+        immutable: bool,
+        handle: Option<PyRef<'_, CancelHandle>>,
+    ) -> PyResult<PyObject> {
+        let Some(db) = &self.db else {
+            return Err(PyException::new_err(DB_CLOSED_MSG));
+        };
+        let params = convert_params(params)?;
+        let mutability = if immutable {
+            ScriptMutability::Immutable
         } else {
-            Err(PyException::new_err(DB_CLOSED_MSG))
+            ScriptMutability::Mutable
+        };
+        let poison = match &handle {
+            None => Poison::default(),
+            Some(h) => h.poison.child(),
+        };
+        let (sender, receiver) = channel();
+        {
+            let db = db.clone();
+            let query = query.to_string();
+            let poison = poison.clone();
+            rayon::spawn(move || {
+                let res = db.run_script_with_handle(&query, params, mutability, &poison);
+                let _ = sender.send(res);
+            });
         }
+        let query = query.to_string();
+        run_in_executor(py, Some(poison), move |py| {
+            match py.allow_threads(move || receiver.recv()) {
+                Ok(Ok(rows)) => Ok(named_rows_to_py(rows, py)),
+                Ok(Err(err)) => Err(script_error_to_py(py, err, &query)),
+                Err(_) => Err(PyException::new_err("script execution panicked")),
+            }
+        })
+    }
+    /// Like `run_script`, but the result is an object implementing the Arrow PyCapsule
+    /// interface, so that e.g. `pyarrow.table(res)` or `polars.from_arrow(res)` take it
+    /// without creating Python objects for the values.
+    #[cfg(feature = "arrow")]
+    #[pyo3(signature = (query, params, immutable, handle=None))]
+    pub fn run_script_arrow(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: &PyDict,
+        immutable: bool,
+        handle: Option<PyRef<'_, CancelHandle>>,
+    ) -> PyResult<ArrowResult> {
+        let rows = self.run_script_rows(py, query, params, immutable, handle)?;
+        let batch = rows
+            .to_arrow()
+            .map_err(|err| PyException::new_err(format!("{err:?}")))?;
+        Ok(ArrowResult { batch })
     }
     pub fn register_callback(&self, rel: &str, callback: &PyAny) -> PyResult<u32> {
         if let Some(db) = &self.db {
@@ -375,13 +542,31 @@ impl CozoDbPy {
             Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
         }
     }
+    pub fn set_default_timeout(&self, secs: Option<f64>) -> PyResult<()> {
+        if let Some(db) = &self.db {
+            db.set_default_timeout(secs).map_err(report2py)
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
+        }
+    }
     pub fn close(&mut self) -> bool {
         self.db.take().is_some()
     }
     pub fn multi_transact(&self, write: bool) -> PyResult<CozoDbMulTx> {
         if let Some(db) = &self.db {
             Ok(CozoDbMulTx {
-                tx: db.multi_transaction(write),
+                tx: Arc::new(Mutex::new(db.multi_transaction(write))),
+            })
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
+        }
+    }
+    /// A read-only snapshot: all scripts run on it see the data as of this call.
+    /// Committing or aborting it releases the snapshot.
+    pub fn snapshot(&self) -> PyResult<CozoDbMulTx> {
+        if let Some(db) = &self.db {
+            Ok(CozoDbMulTx {
+                tx: Arc::new(Mutex::new(db.snapshot())),
             })
         } else {
             Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
@@ -391,29 +576,135 @@ impl CozoDbPy {
 
 #[pymethods]
 impl CozoDbMulTx {
-    pub fn abort(&self) -> PyResult<()> {
-        self.tx
-            .abort()
+    pub fn abort(&self, py: Python<'_>) -> PyResult<()> {
+        py.allow_threads(|| self.tx.lock().unwrap().abort())
             .map_err(|err| PyException::new_err(err.to_string()))
     }
-    pub fn commit(&self) -> PyResult<()> {
-        self.tx
-            .commit()
+    pub fn commit(&self, py: Python<'_>) -> PyResult<()> {
+        py.allow_threads(|| self.tx.lock().unwrap().commit())
             .map_err(|err| PyException::new_err(err.to_string()))
     }
     pub fn run_script(&self, py: Python<'_>, query: &str, params: &PyDict) -> PyResult<PyObject> {
         let params = convert_params(params)?;
-        match py.allow_threads(|| self.tx.run_script(query, params)) {
+        match py.allow_threads(|| self.tx.lock().unwrap().run_script(query, params)) {
             Ok(rows) => Ok(named_rows_to_py(rows, py)),
-            Err(err) => {
-                let reports = format_error_as_json(err, Some(query)).to_string();
-                let json_mod = py.import("json")?;
-                let loads_fn = json_mod.getattr("loads")?;
-                let args = PyTuple::new(py, [PyString::new(py, &reports)]);
-                let msg = loads_fn.call1(args)?;
-                Err(PyException::new_err(PyObject::from(msg)))
+            Err(err) => Err(script_error_to_py(py, err, query)),
+        }
+    }
+    /// Like `run_script`, but returns an awaitable and does not block the event loop.
+    pub fn run_script_async(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: &PyDict,
+    ) -> PyResult<PyObject> {
+        let params = convert_params(params)?;
+        let tx = self.tx.clone();
+        let query = query.to_string();
+        run_in_executor(py, None, move |py| {
+            match py.allow_threads(|| tx.lock().unwrap().run_script(&query, params)) {
+                Ok(rows) => Ok(named_rows_to_py(rows, py)),
+                Err(err) => Err(script_error_to_py(py, err, &query)),
             }
+        })
+    }
+    fn __aenter__(slf: Py<Self>, py: Python<'_>) -> PyResult<PyObject> {
+        let fut = py
+            .import("asyncio")?
+            .call_method0("get_running_loop")?
+            .call_method0("create_future")?;
+        fut.call_method1("set_result", (slf,))?;
+        Ok(fut.into())
+    }
+    /// Commits the transaction if the block exited normally, aborts it otherwise.
+    fn __aexit__(
+        &self,
+        py: Python<'_>,
+        exc_type: &PyAny,
+        _exc: &PyAny,
+        _tb: &PyAny,
+    ) -> PyResult<PyObject> {
+        let tx = self.tx.clone();
+        let failed = !exc_type.is_none();
+        run_in_executor(py, None, move |py| {
+            py.allow_threads(|| {
+                let tx = tx.lock().unwrap();
+                if failed {
+                    tx.abort()
+                } else {
+                    tx.commit()
+                }
+            })
+            .map_err(|err| PyException::new_err(err.to_string()))?;
+            // returning false lets the exception, if any, propagate
+            Ok(false.into_py(py))
+        })
+    }
+}
+
+/// Done callback of the futures returned by the async methods: cancelling the
+/// future cancels the script.
+#[pyclass]
+struct FutureCanceller {
+    poison: Poison,
+}
+
+#[pymethods]
+impl FutureCanceller {
+    fn __call__(&self, fut: &PyAny) -> PyResult<()> {
+        if fut.call_method0("cancelled")?.is_true()? {
+            self.poison.cancel();
         }
+        Ok(())
+    }
+}
+
+type BlockingFn = Box<dyn FnOnce(Python<'_>) -> PyResult<PyObject> + Send>;
+
+/// A blocking function handed to the executor of the event loop, run at most once.
+#[pyclass]
+struct BlockingJob {
+    job: Mutex<Option<BlockingFn>>,
+}
+
+#[pymethods]
+impl BlockingJob {
+    fn __call__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        match self.job.lock().unwrap().take() {
+            Some(job) => job(py),
+            None => Err(PyException::new_err("job already run")),
+        }
+    }
+}
+
+/// Runs `job` in the default executor of the running event loop, returning the future.
+/// Results are converted to Python objects there and not in rayon threads, which may
+/// outlive the interpreter. If `poison` is given, cancelling the future cancels it.
+fn run_in_executor<F>(py: Python<'_>, poison: Option<Poison>, job: F) -> PyResult<PyObject>
+where
+    F: FnOnce(Python<'_>) -> PyResult<PyObject> + Send + 'static,
+{
+    let job = BlockingJob {
+        job: Mutex::new(Some(Box::new(job))),
+    };
+    let fut = py
+        .import("asyncio")?
+        .call_method0("get_running_loop")?
+        .call_method1("run_in_executor", (py.None(), job))?;
+    if let Some(poison) = poison {
+        fut.call_method1("add_done_callback", (FutureCanceller { poison },))?;
+    }
+    Ok(fut.into())
+}
+
+fn script_error_to_py(py: Python<'_>, err: Report, query: &str) -> PyErr {
+    let reports = format_error_as_json(err, Some(query)).to_string();
+    let msg = py
+        .import("json")
+        .and_then(|json_mod| json_mod.getattr("loads")?.call1((reports,)));
+    match msg {
+        Ok(msg) => PyException::new_err(PyObject::from(msg)),
+        Err(err) => err,
     }
 }
 
@@ -439,6 +730,31 @@ fn eval_expressions(
     }
 }
 
+#[pyfunction]
+fn eval_expressions_batch(
+    py: Python<'_>,
+    query: &str,
+    params: &PyDict,
+    bindings: Vec<&PyDict>,
+) -> PyResult<PyObject> {
+    let params = convert_params(params)?;
+    let bindings = bindings
+        .into_iter()
+        .map(convert_params)
+        .collect::<PyResult<Vec<_>>>()?;
+    match py.allow_threads(|| evaluate_expressions_batch(query, &params, &bindings)) {
+        Ok(vs) => Ok(PyList::new(py, vs.into_iter().map(|v| value_to_py(v, py))).into()),
+        Err(err) => {
+            let reports = format_error_as_json(err, Some(query)).to_string();
+            let json_mod = py.import("json")?;
+            let loads_fn = json_mod.getattr("loads")?;
+            let args = PyTuple::new(py, [PyString::new(py, &reports)]);
+            let msg = loads_fn.call1(args)?;
+            Err(PyException::new_err(PyObject::from(msg)))
+        }
+    }
+}
+
 #[pyfunction]
 fn variables(py: Python<'_>, query: &str, params: &PyDict) -> PyResult<BTreeSet<String>> {
     let params = convert_params(params).unwrap();
@@ -458,8 +774,12 @@ fn variables(py: Python<'_>, query: &str, params: &PyDict) -> PyResult<BTreeSet<
 #[pymodule]
 fn cozo_embedded(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<CozoDbPy>()?;
+    m.add_class::<CancelHandle>()?;
     m.add_class::<CozoDbMulTx>()?;
+    #[cfg(feature = "arrow")]
+    m.add_class::<ArrowResult>()?;
     m.add_function(wrap_pyfunction!(eval_expressions, m)?)?;
+    m.add_function(wrap_pyfunction!(eval_expressions_batch, m)?)?;
     m.add_function(wrap_pyfunction!(variables, m)?)?;
     Ok(())
 }
@@ -77,6 +77,92 @@ extension NamedRow {
     }
 }
 
+/// A value in the result of a query
+public indirect enum DataValue {
+    case null
+    case bool(Bool)
+    case int(Int64)
+    case float(Double)
+    case string(String)
+    case bytes([UInt8])
+    case list([DataValue])
+    case vector([Double])
+    /// Any other value, e.g. a UUID or JSON, in its JSON representation
+    case other(String)
+}
+
+extension DataValue {
+    init(_ v: CozoValueRef) {
+        switch v.kind() {
+        case .Null:
+            self = .null
+        case .Bool:
+            self = .bool(v.as_bool())
+        case .Int:
+            self = .int(v.as_int())
+        case .Float:
+            self = .float(v.as_float())
+        case .Str:
+            self = .string(v.as_string().toString())
+        case .Bytes:
+            self = .bytes(Array(v.as_bytes()))
+        case .List:
+            self = .list((0..<v.list_len()).map { DataValue(v.list_get($0)) })
+        case .Vector:
+            self = .vector(Array(v.as_vector()))
+        case .Other:
+            self = .other(v.as_string().toString())
+        }
+    }
+}
+
+/// Typed result of a query
+public struct Rows {
+    public let headers: [String]
+    public let rows: [[DataValue]]
+
+    init(_ res: QueryRowsRef) throws {
+        if !res.is_ok() {
+            let dataFromString = res.error_json().toString().data(using: .utf8, allowLossyConversion: false)!
+            throw CozoError.query(JSON(dataFromString))
+        }
+        self.headers = (0..<res.num_cols()).map { res.header($0).toString() }
+        self.rows = (0..<res.num_rows()).map { i in
+            (0..<res.num_cols()).map { j in DataValue(res.get(i, j)) }
+        }
+    }
+}
+
+func checkStatus(_ resStr: String) throws {
+    let dataFromString = resStr.data(using: .utf8, allowLossyConversion: false)!
+    let json = JSON(dataFromString);
+    if !json["ok"].boolValue {
+        throw CozoError.query(json)
+    }
+}
+
+/// A multi-statement transaction, which must be ended by `commit` or `abort`
+public class CozoTx {
+    let tx: CozoTransaction
+
+    init(tx: CozoTransaction) {
+        self.tx = tx
+    }
+    public func run(_ query: String, params: JSON) throws -> Rows {
+        let payload = params.rawString(.utf8, options: .init(rawValue: 0))!
+        return try Rows(self.tx.run_script(query, payload))
+    }
+    public func run(_ query: String) throws -> Rows {
+        return try Rows(self.tx.run_script(query, ""))
+    }
+    public func commit() throws {
+        try checkStatus(self.tx.commit().toString())
+    }
+    public func abort() throws {
+        try checkStatus(self.tx.abort().toString())
+    }
+}
+
 public class CozoDB {
     public let db: DbInstance
     
@@ -115,6 +201,33 @@ public class CozoDB {
             throw CozoError.query(json)
         }
     }
+    public func runTyped(_ query: String, params: JSON, immutable: Bool = false) throws -> Rows {
+        let payload = params.rawString(.utf8, options: .init(rawValue: 0))!
+        return try Rows(run_script_rows(self.db, query, payload, immutable))
+    }
+    public func runTyped(_ query: String, immutable: Bool = false) throws -> Rows {
+        return try Rows(run_script_rows(self.db, query, "", immutable))
+    }
+    public func multiTransact(write: Bool) -> CozoTx {
+        return CozoTx(tx: new_transaction(self.db, write))
+    }
+    /// Calls `callback` with the operation, the new rows and the old rows whenever the relation changes.
+    /// The callback runs on a background thread. Returns the id for `unregisterCallback`.
+    public func registerCallback(relation: String, callback: @escaping (String, Rows, Rows) -> Void) -> UInt32 {
+        let receiver = register_relation_callback(self.db, relation)
+        let id = receiver.id()
+        Thread {
+            while let change = receiver.recv() {
+                if let new = try? Rows(change.new_rows()), let old = try? Rows(change.old_rows()) {
+                    callback(change.op().toString(), new, old)
+                }
+            }
+        }.start()
+        return id
+    }
+    public func unregisterCallback(id: UInt32) -> Bool {
+        return unregister_relation_callback(self.db, id)
+    }
     public func exportRelations(relations: [String]) throws -> JSON {
         let payload = JSON(["relations": relations]).rawString(.utf8, options: .init(rawValue: 0))!
         let resStr = self.db.export_relations_str(payload).toString()
@@ -6,10 +6,33 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+// the code generated for returning opaque types trips this lint
+#![allow(clippy::unnecessary_cast)]
+
+use std::collections::BTreeMap;
+
+use crossbeam::channel::Receiver;
+use serde_json::json;
+
 use cozo::*;
 
+use crate::ffi::ValueKind;
+
 #[swift_bridge::bridge]
 mod ffi {
+    enum ValueKind {
+        Null,
+        Bool,
+        Int,
+        Float,
+        Str,
+        Bytes,
+        List,
+        Vector,
+        /// Any other value, available as its JSON representation from `as_string`
+        Other,
+    }
+
     extern "Rust" {
         type DbInstance;
 
@@ -21,6 +44,64 @@ mod ffi {
         fn backup_db_str(&self, out_file: &str) -> String;
         fn restore_backup_str(&self, in_file: &str) -> String;
         fn import_from_backup_str(&self, data: &str) -> String;
+
+        fn run_script_rows(
+            db: &DbInstance,
+            payload: &str,
+            params: &str,
+            immutable: bool,
+        ) -> QueryRows;
+        fn new_transaction(db: &DbInstance, write: bool) -> CozoTransaction;
+        fn register_relation_callback(db: &DbInstance, relation: &str) -> CallbackReceiver;
+        fn unregister_relation_callback(db: &DbInstance, id: u32) -> bool;
+    }
+
+    extern "Rust" {
+        type QueryRows;
+
+        fn is_ok(&self) -> bool;
+        fn error_json(&self) -> String;
+        fn num_cols(&self) -> usize;
+        fn header(&self, col: usize) -> String;
+        fn num_rows(&self) -> usize;
+        fn get(&self, row: usize, col: usize) -> CozoValue;
+    }
+
+    extern "Rust" {
+        type CozoValue;
+
+        fn kind(&self) -> ValueKind;
+        fn as_bool(&self) -> bool;
+        fn as_int(&self) -> i64;
+        fn as_float(&self) -> f64;
+        fn as_string(&self) -> String;
+        fn as_bytes(&self) -> Vec<u8>;
+        fn as_vector(&self) -> Vec<f64>;
+        fn list_len(&self) -> usize;
+        fn list_get(&self, idx: usize) -> CozoValue;
+    }
+
+    extern "Rust" {
+        type CozoTransaction;
+
+        fn run_script(&self, payload: &str, params: &str) -> QueryRows;
+        fn commit(&self) -> String;
+        fn abort(&self) -> String;
+    }
+
+    extern "Rust" {
+        type CallbackReceiver;
+
+        fn id(&self) -> u32;
+        fn recv(&self) -> Option<RelationChange>;
+    }
+
+    extern "Rust" {
+        type RelationChange;
+
+        fn op(&self) -> String;
+        fn new_rows(&self) -> QueryRows;
+        fn old_rows(&self) -> QueryRows;
     }
 }
 
@@ -34,3 +115,208 @@ fn new_cozo_db(engine: &str, path: &str, options: &str) -> Option<DbInstance> {
         }
     }
 }
+
+fn parse_params(params: &str) -> Result<BTreeMap<String, DataValue>, String> {
+    if params.is_empty() {
+        return Ok(BTreeMap::default());
+    }
+    match serde_json::from_str::<BTreeMap<String, serde_json::Value>>(params) {
+        Ok(map) => Ok(map
+            .into_iter()
+            .map(|(k, v)| (k, DataValue::from(v)))
+            .collect()),
+        Err(_) => {
+            Err(json!({"ok": false, "message": "params argument is not a JSON map"}).to_string())
+        }
+    }
+}
+
+/// Rows of a result, or the error as JSON
+pub struct QueryRows(Result<NamedRows, String>);
+
+impl QueryRows {
+    fn from_result(res: Result<NamedRows, Error>, payload: &str) -> Self {
+        Self(res.map_err(|err| format_error_as_json(err, Some(payload)).to_string()))
+    }
+    fn is_ok(&self) -> bool {
+        self.0.is_ok()
+    }
+    fn error_json(&self) -> String {
+        match &self.0 {
+            Ok(_) => String::new(),
+            Err(err) => err.clone(),
+        }
+    }
+    fn num_cols(&self) -> usize {
+        match &self.0 {
+            Ok(nr) => nr.headers.len(),
+            Err(_) => 0,
+        }
+    }
+    fn header(&self, col: usize) -> String {
+        match &self.0 {
+            Ok(nr) => nr.headers.get(col).cloned().unwrap_or_default(),
+            Err(_) => String::new(),
+        }
+    }
+    fn num_rows(&self) -> usize {
+        match &self.0 {
+            Ok(nr) => nr.rows.len(),
+            Err(_) => 0,
+        }
+    }
+    fn get(&self, row: usize, col: usize) -> CozoValue {
+        let val = match &self.0 {
+            Ok(nr) => nr.rows.get(row).and_then(|r| r.get(col)).cloned(),
+            Err(_) => None,
+        };
+        CozoValue(val.unwrap_or(DataValue::Null))
+    }
+}
+
+/// A single value, inspected according to its kind
+pub struct CozoValue(DataValue);
+
+impl CozoValue {
+    fn kind(&self) -> ValueKind {
+        match &self.0 {
+            DataValue::Null => ValueKind::Null,
+            DataValue::Bool(_) => ValueKind::Bool,
+            DataValue::Num(Num::Int(_)) => ValueKind::Int,
+            DataValue::Num(Num::Float(_)) => ValueKind::Float,
+            DataValue::Str(_) => ValueKind::Str,
+            DataValue::Bytes(_) => ValueKind::Bytes,
+            DataValue::List(_) => ValueKind::List,
+            DataValue::Vec(_) => ValueKind::Vector,
+            _ => ValueKind::Other,
+        }
+    }
+    fn as_bool(&self) -> bool {
+        self.0.get_bool().unwrap_or(false)
+    }
+    fn as_int(&self) -> i64 {
+        self.0.get_int().unwrap_or(0)
+    }
+    fn as_float(&self) -> f64 {
+        self.0.get_float().unwrap_or(0.)
+    }
+    fn as_string(&self) -> String {
+        match serde_json::Value::from(self.0.clone()) {
+            serde_json::Value::String(s) => s,
+            j => j.to_string(),
+        }
+    }
+    fn as_bytes(&self) -> Vec<u8> {
+        match &self.0 {
+            DataValue::Bytes(b) => b.clone(),
+            _ => vec![],
+        }
+    }
+    fn as_vector(&self) -> Vec<f64> {
+        match &self.0 {
+            DataValue::Vec(Vector::F32(a)) => a.iter().map(|f| *f as f64).collect(),
+            DataValue::Vec(Vector::F64(a)) => a.to_vec(),
+            _ => vec![],
+        }
+    }
+    fn list_len(&self) -> usize {
+        match &self.0 {
+            DataValue::List(l) => l.len(),
+            _ => 0,
+        }
+    }
+    fn list_get(&self, idx: usize) -> CozoValue {
+        match &self.0 {
+            DataValue::List(l) => CozoValue(l.get(idx).cloned().unwrap_or(DataValue::Null)),
+            _ => CozoValue(DataValue::Null),
+        }
+    }
+}
+
+fn run_script_rows(db: &DbInstance, payload: &str, params: &str, immutable: bool) -> QueryRows {
+    let params = match parse_params(params) {
+        Ok(params) => params,
+        Err(err) => return QueryRows(Err(err)),
+    };
+    let mutability = if immutable {
+        ScriptMutability::Immutable
+    } else {
+        ScriptMutability::Mutable
+    };
+    QueryRows::from_result(db.run_script(payload, params, mutability), payload)
+}
+
+/// A multi-statement transaction, committed or aborted explicitly
+pub struct CozoTransaction(MultiTransaction);
+
+fn new_transaction(db: &DbInstance, write: bool) -> CozoTransaction {
+    CozoTransaction(db.multi_transaction(write))
+}
+
+fn status_json(res: Result<(), Error>) -> String {
+    match res {
+        Ok(()) => json!({"ok": true}).to_string(),
+        Err(err) => format_error_as_json(err, None).to_string(),
+    }
+}
+
+impl CozoTransaction {
+    fn run_script(&self, payload: &str, params: &str) -> QueryRows {
+        match parse_params(params) {
+            Ok(params) => QueryRows::from_result(self.0.run_script(payload, params), payload),
+            Err(err) => QueryRows(Err(err)),
+        }
+    }
+    fn commit(&self) -> String {
+        status_json(self.0.commit())
+    }
+    fn abort(&self) -> String {
+        status_json(self.0.abort())
+    }
+}
+
+/// Changes to a relation, received by blocking calls to `recv`
+pub struct CallbackReceiver {
+    id: u32,
+    receiver: Receiver<(CallbackOp, NamedRows, NamedRows)>,
+}
+
+fn register_relation_callback(db: &DbInstance, relation: &str) -> CallbackReceiver {
+    let (id, receiver) = db.register_callback(relation, None);
+    CallbackReceiver { id, receiver }
+}
+
+fn unregister_relation_callback(db: &DbInstance, id: u32) -> bool {
+    db.unregister_callback(id)
+}
+
+impl CallbackReceiver {
+    fn id(&self) -> u32 {
+        self.id
+    }
+    /// Returns `None` once the callback is unregistered.
+    fn recv(&self) -> Option<RelationChange> {
+        self.receiver
+            .recv()
+            .ok()
+            .map(|(op, new, old)| RelationChange { op, new, old })
+    }
+}
+
+pub struct RelationChange {
+    op: CallbackOp,
+    new: NamedRows,
+    old: NamedRows,
+}
+
+impl RelationChange {
+    fn op(&self) -> String {
+        self.op.as_str().to_string()
+    }
+    fn new_rows(&self) -> QueryRows {
+        QueryRows(Ok(self.new.clone()))
+    }
+    fn old_rows(&self) -> QueryRows {
+        QueryRows(Ok(self.old.clone()))
+    }
+}
@@ -6,7 +6,16 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crossbeam::channel::Receiver;
+use js_sys::Function;
+use miette::{IntoDiagnostic, Result};
+use serde_json::json;
 use wasm_bindgen::prelude::*;
+use web_sys::FileSystemSyncAccessHandle;
 
 use cozo::*;
 
@@ -23,9 +32,18 @@ extern "C" {
     fn alert(s: &str);
 }
 
+type Callbacks =
+    Rc<RefCell<BTreeMap<u32, (Receiver<(CallbackOp, NamedRows, NamedRows)>, Function)>>>;
+
+const TX_IN_PROGRESS: &str = "a transaction is in progress, commit or abort it first";
+
 #[wasm_bindgen]
 pub struct CozoDb {
     db: DbInstance,
+    callbacks: Callbacks,
+    // Only one transaction may be open at a time: without threads, waiting
+    // for a lock held by an open transaction would never end.
+    in_tx: Rc<Cell<bool>>,
 }
 
 #[wasm_bindgen]
@@ -34,15 +52,246 @@ impl CozoDb {
     pub fn new() -> Self {
         utils::set_panic_hook();
         let db = DbInstance::new("mem", "", "").unwrap();
-        Self { db }
+        Self::wrap(db)
+    }
+    /// Open a persistent database stored in a file of the Origin Private File System.
+    /// Sync access handles are only available in dedicated workers.
+    pub fn open_opfs(handle: FileSystemSyncAccessHandle) -> Result<CozoDb, JsError> {
+        utils::set_panic_hook();
+        let db = new_cozo_opfs(handle).map_err(|e| JsError::new(&format!("{e:?}")))?;
+        Ok(Self::wrap(DbInstance::Opfs(db)))
     }
     pub fn run(&self, script: &str, params: &str, immutable: bool) -> String {
-        self.db.run_script_str(script, params, immutable)
+        if self.in_tx.get() {
+            return error_json(TX_IN_PROGRESS);
+        }
+        let ret = self.db.run_script_str(script, params, immutable);
+        dispatch_callbacks(&self.callbacks);
+        ret
     }
     pub fn export_relations(&self, data: &str) -> String {
+        if self.in_tx.get() {
+            return error_json(TX_IN_PROGRESS);
+        }
         self.db.export_relations_str(data)
     }
     pub fn import_relations(&self, data: &str) -> String {
+        if self.in_tx.get() {
+            return error_json(TX_IN_PROGRESS);
+        }
         self.db.import_relations_str(data)
     }
+    /// Start a multi-statement transaction. No other operation can be performed on the
+    /// database until the transaction is committed or aborted.
+    pub fn multi_transact(&self, write: bool) -> Result<CozoTx, JsError> {
+        if self.in_tx.get() {
+            return Err(JsError::new(TX_IN_PROGRESS));
+        }
+        let inner = match &self.db {
+            DbInstance::Mem(db) => OwnedTx::new(db, write).map(TxInner::Mem),
+            DbInstance::Opfs(db) => OwnedTx::new(db, write).map(TxInner::Opfs),
+        }
+        .map_err(|e| JsError::new(&e.to_string()))?;
+        self.in_tx.set(true);
+        Ok(CozoTx {
+            inner: Some(inner),
+            callbacks: self.callbacks.clone(),
+            in_tx: self.in_tx.clone(),
+        })
+    }
+    /// Call `callback(op, new_rows, old_rows)` whenever a committed query changes the relation.
+    pub fn register_callback(&self, relation: &str, callback: Function) -> u32 {
+        let (id, receiver) = self.db.register_callback(relation, None);
+        self.callbacks.borrow_mut().insert(id, (receiver, callback));
+        id
+    }
+    pub fn unregister_callback(&self, id: u32) -> bool {
+        self.callbacks.borrow_mut().remove(&id);
+        self.db.unregister_callback(id)
+    }
+    /// Export all data in the database, to be restored with `restore`.
+    pub fn backup(&self) -> Result<Vec<u8>, JsError> {
+        if self.in_tx.get() {
+            return Err(JsError::new(TX_IN_PROGRESS));
+        }
+        self.db
+            .backup_db_to_bytes()
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+    /// Restore from the output of `backup`. The database must be empty.
+    pub fn restore(&self, data: &[u8]) -> String {
+        if self.in_tx.get() {
+            return error_json(TX_IN_PROGRESS);
+        }
+        match self.db.restore_backup_from_bytes(data) {
+            Ok(()) => json!({"ok": true}).to_string(),
+            Err(err) => error_json(&err.to_string()),
+        }
+    }
+}
+
+impl CozoDb {
+    fn wrap(db: DbInstance) -> Self {
+        Self {
+            db,
+            callbacks: Default::default(),
+            in_tx: Default::default(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct CozoTx {
+    inner: Option<TxInner>,
+    callbacks: Callbacks,
+    in_tx: Rc<Cell<bool>>,
+}
+
+#[wasm_bindgen]
+impl CozoTx {
+    pub fn run(&mut self, script: &str, params: &str) -> String {
+        let Some(inner) = &mut self.inner else {
+            return error_json("transaction is already finished");
+        };
+        let res = parse_params(params).and_then(|params| inner.run_script(script, params));
+        match res {
+            Ok(rows) => {
+                let mut ret = rows.into_json();
+                ret.as_object_mut()
+                    .unwrap()
+                    .insert("ok".to_string(), json!(true));
+                ret.to_string()
+            }
+            Err(err) => format_error_as_json(err, Some(script)).to_string(),
+        }
+    }
+    pub fn commit(&mut self) -> String {
+        let Some(inner) = self.inner.take() else {
+            return error_json("transaction is already finished");
+        };
+        let res = inner.commit();
+        self.in_tx.set(false);
+        dispatch_callbacks(&self.callbacks);
+        match res {
+            Ok(()) => json!({"ok": true}).to_string(),
+            Err(err) => error_json(&err.to_string()),
+        }
+    }
+    pub fn abort(&mut self) -> String {
+        if self.inner.take().is_none() {
+            return error_json("transaction is already finished");
+        }
+        self.in_tx.set(false);
+        json!({"ok": true}).to_string()
+    }
+}
+
+impl Drop for CozoTx {
+    fn drop(&mut self) {
+        if self.inner.take().is_some() {
+            self.in_tx.set(false);
+        }
+    }
+}
+
+enum TxInner {
+    Mem(OwnedTx<MemStorage>),
+    Opfs(OwnedTx<OpfsStorage>),
+}
+
+impl TxInner {
+    fn run_script(
+        &mut self,
+        script: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        match self {
+            TxInner::Mem(tx) => tx.run_script(script, params),
+            TxInner::Opfs(tx) => tx.run_script(script, params),
+        }
+    }
+    fn commit(self) -> Result<()> {
+        match self {
+            TxInner::Mem(tx) => tx.commit(),
+            TxInner::Opfs(tx) => tx.commit(),
+        }
+    }
+}
+
+/// An [InlineTransaction] together with the database handle it borrows from.
+struct OwnedTx<S: Storage<'static> + 'static> {
+    tx: Option<InlineTransaction<'static, S>>,
+    db: *mut Db<S>,
+}
+
+impl<S: Storage<'static> + 'static> OwnedTx<S> {
+    fn new(db: &Db<S>, write: bool) -> Result<Self> {
+        let db = Box::into_raw(Box::new(db.clone()));
+        // SAFETY: the box is only freed when `Self` is dropped, after the transaction.
+        let tx = unsafe { &*db }.inline_transaction(write);
+        let tx = match tx {
+            Ok(tx) => tx,
+            Err(err) => {
+                drop(unsafe { Box::from_raw(db) });
+                return Err(err);
+            }
+        };
+        Ok(Self { tx: Some(tx), db })
+    }
+    fn run_script(
+        &mut self,
+        script: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        self.tx.as_mut().unwrap().run_script(script, params)
+    }
+    fn commit(mut self) -> Result<()> {
+        self.tx.take().unwrap().commit()
+    }
+}
+
+impl<S: Storage<'static> + 'static> Drop for OwnedTx<S> {
+    fn drop(&mut self) {
+        self.tx.take();
+        drop(unsafe { Box::from_raw(self.db) });
+    }
+}
+
+fn parse_params(params: &str) -> Result<BTreeMap<String, DataValue>> {
+    if params.is_empty() {
+        return Ok(Default::default());
+    }
+    let map: BTreeMap<String, serde_json::Value> =
+        serde_json::from_str(params).into_diagnostic()?;
+    Ok(map
+        .into_iter()
+        .map(|(k, v)| (k, DataValue::from(v)))
+        .collect())
+}
+
+fn error_json(message: &str) -> String {
+    json!({"ok": false, "message": message}).to_string()
+}
+
+fn rows_to_js(rows: NamedRows) -> JsValue {
+    js_sys::JSON::parse(&rows.into_json()["rows"].to_string()).unwrap_or(JsValue::NULL)
+}
+
+/// Callbacks are delivered once the operation that triggered them has finished,
+/// so that they are free to use the database.
+fn dispatch_callbacks(callbacks: &Callbacks) {
+    let mut events = vec![];
+    for (receiver, callback) in callbacks.borrow().values() {
+        for (op, new, old) in receiver.try_iter() {
+            events.push((callback.clone(), op, new, old));
+        }
+    }
+    for (callback, op, new, old) in events {
+        let _ = callback.call3(
+            &JsValue::NULL,
+            &JsValue::from_str(op.as_str()),
+            &rows_to_js(new),
+            &rows_to_js(old),
+        );
+    }
 }